@@ -1,7 +1,14 @@
 use std::{
     fs,
-    io::{Read, Write},
+    io::{BufRead, BufReader, Read, Write},
+    net::UdpSocket,
     path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use clap::{Parser, ValueEnum};
@@ -57,6 +64,35 @@ struct Args {
 
     #[arg(short, long, default_value_t = false)]
     assemble_smp_trampoline: bool,
+
+    /// poison freed kernel heap memory and check frees (kernel feature heap-debug)
+    #[arg(long, default_value_t = false)]
+    heap_debug: bool,
+
+    /// track hold times, contention and owners of the central kernel locks and report deadlocks (kernel feature lock-debug)
+    #[arg(long, default_value_t = false)]
+    lock_debug: bool,
+
+    /// kernel command line (e.g. "loglevel=debug graphics=off test=mem")
+    #[arg(short, long, default_value_t = String::new())]
+    cmdline: String,
+
+    /// write the ram disk as a ustar archive instead of the region format (the kernel reads both)
+    #[arg(long, default_value_t = false)]
+    tar_ram_disk: bool,
+
+    /// frame buffer resolution the bootloader selects (e.g. 1920x1080, the smallest mode at least this large),
+    /// without it the kernel's BOOTLOADER_CONFIG decides
+    #[arg(long, value_parser = parse_resolution)]
+    resolution: Option<(u64, u64)>,
+
+    /// serve the kernel gdb stub (COM2) on this tcp port and stop the boot until gdb is attached (adds gdb=on to the command line)
+    #[arg(long)]
+    gdb: Option<u16>,
+
+    /// forward this host tcp port to the kernel remote shell (tcp port 2323 of the guest, connect with `nc 127.0.0.1 <port>`)
+    #[arg(long)]
+    remote_shell: Option<u16>,
 }
 
 fn main() {
@@ -85,8 +121,20 @@ fn main() {
     cmd.arg("--target");
     cmd.arg("x86_64-unknown-none");
     let kernel_profile = add_profile_args(&mut cmd, args.kernel_profile);
+    // backtraces (panics, exceptions and heap-debug) are found by walking frame pointers
+    cmd.env("RUSTFLAGS", "-C force-frame-pointers=yes");
+    let mut features = Vec::new();
     if test_mode {
-        cmd.args(["--features", "testing"]);
+        features.push("testing");
+    }
+    if args.heap_debug {
+        features.push("heap-debug");
+    }
+    if args.lock_debug {
+        features.push("lock-debug");
+    }
+    if !features.is_empty() {
+        cmd.args(["--features", &features.join(",")]);
     }
     if !cmd.spawn().unwrap().wait().unwrap().success() {
         panic!("Failed to build kernel");
@@ -108,17 +156,34 @@ fn main() {
 
     let _ = std::fs::create_dir("bootimage/out");
 
-    let ram_disk_path: tempfile::TempPath = create_ram_disk(user_profile).into_temp_path();
+    let mut cmdline = match args.gdb {
+        Some(_) => format!("{} gdb=on", args.cmdline),
+        None => args.cmdline.clone(),
+    };
+    // the test results are read from the json log (later options override earlier ones)
+    if test_mode {
+        cmdline = format!("logformat=json {cmdline}");
+    }
+    let ram_disk_path: tempfile::TempPath =
+        create_ram_disk(user_profile, &cmdline, &kernel, args.tar_ram_disk).into_temp_path();
+
+    let mut boot_config = bootloader::BootConfig::default();
+    if let Some((width, height)) = args.resolution {
+        boot_config.frame_buffer.minimum_framebuffer_width = Some(width);
+        boot_config.frame_buffer.minimum_framebuffer_height = Some(height);
+    }
 
     let uefi_path = "bootimage/out/uefi.img";
     bootloader::UefiBoot::new(&kernel)
         .set_ramdisk(Path::new(ram_disk_path.to_str().unwrap()))
+        .set_boot_config(&boot_config)
         .create_disk_image(&std::path::PathBuf::from(&uefi_path))
         .unwrap();
 
     let bios_path = "bootimage/out/bios.img";
     bootloader::BiosBoot::new(&kernel)
         .set_ramdisk(Path::new(ram_disk_path.to_str().unwrap()))
+        .set_boot_config(&boot_config)
         .create_disk_image(&std::path::PathBuf::from(&bios_path))
         .unwrap();
 
@@ -169,19 +234,194 @@ fn main() {
             cmd.arg("stdio");
         }
     }
+    if let Some(port) = args.gdb {
+        // COM2 is the second serial port
+        if args.redirect_serial == RedirectSerial::None {
+            cmd.args(["-serial", "null"]);
+        }
+        cmd.arg("-serial");
+        cmd.arg(format!("tcp::{port},server,nowait"));
+    }
+    // the socket of the runner and the host port forwarded to UDP_TEST_PORT of the kernel
+    let udp_test = test_mode.then(|| {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let forwarded = UdpSocket::bind("127.0.0.1:0").unwrap();
+        (socket, forwarded.local_addr().unwrap().port())
+    });
+    // the default network card with user networking, plus the forwardings
+    let mut forwards = Vec::new();
+    if let Some(port) = args.remote_shell {
+        forwards.push(format!("hostfwd=tcp:127.0.0.1:{port}-:2323"));
+    }
+    if let Some((_, port)) = &udp_test {
+        forwards.push(format!("hostfwd=udp:127.0.0.1:{port}-:{UDP_TEST_PORT}"));
+    }
+    if !forwards.is_empty() {
+        cmd.arg("-nic");
+        cmd.arg(format!("user,model=e1000,{}", forwards.join(",")));
+    }
 
+    let mut report = TestReport::default();
+    let piped = test_mode && args.redirect_serial == RedirectSerial::Stdout;
+    if piped {
+        cmd.stdout(Stdio::piped());
+    }
+    let qemu_exited = Arc::new(AtomicBool::new(false));
+    let udp_exchange = udp_test.map(|(socket, port)| {
+        let qemu_exited = qemu_exited.clone();
+        std::thread::spawn(move || exchange_udp(&socket, port, &qemu_exited))
+    });
     let mut child = cmd.spawn().unwrap();
+    if piped {
+        for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+            println!("{}", report.record(&line.unwrap()));
+        }
+    }
     let exit_code = child.wait().unwrap();
+    qemu_exited.store(true, Ordering::Relaxed);
+    let udp_exchange = udp_exchange.map(|thread| thread.join().unwrap());
 
     if test_mode {
+        if args.redirect_serial == RedirectSerial::File {
+            for line in fs::read_to_string(&log).unwrap_or_default().lines() {
+                report.record(line);
+            }
+        }
+        println!("{}", report.summary());
         assert!(
             exit_code.code().unwrap() == 33,
             "Wrong qemu exit code {exit_code}"
         );
+        if report.ran(UDP_TEST) {
+            let exchange = udp_exchange.unwrap_or_default();
+            assert!(
+                exchange.echoed && exchange.greeted,
+                "The kernel did not answer over udp: {exchange:?}"
+            );
+            println!("The kernel answered over udp");
+        }
     }
     let _ = exit_code;
 }
 
+// The kernel test udp_echo_with_the_host (kernel/src/tests/net_test.rs) answers on this guest port
+const UDP_TEST: &str = "udp_echo_with_the_host";
+const UDP_TEST_PORT: u16 = 5555;
+const HOST_GREETING: &[u8] = b"hello from the host";
+const KERNEL_GREETING: &[u8] = b"hello from steelmind";
+
+// what the runner got from the kernel
+#[derive(Debug, Default)]
+struct UdpExchange {
+    echoed: bool,
+    greeted: bool,
+}
+
+// Repeats the greeting until the kernel echoed it and sent its own, then says bye (so the kernel test ends)
+// until qemu exits
+fn exchange_udp(socket: &UdpSocket, forwarded: u16, qemu_exited: &AtomicBool) -> UdpExchange {
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let mut exchange = UdpExchange::default();
+    let mut buffer = [0; 64];
+    while !qemu_exited.load(Ordering::Relaxed) {
+        let message = if exchange.echoed && exchange.greeted {
+            b"bye".as_slice()
+        } else {
+            HOST_GREETING
+        };
+        let _ = socket.send_to(message, ("127.0.0.1", forwarded));
+        // times out while the kernel doesn't listen yet
+        if let Ok(len) = socket.recv(&mut buffer) {
+            match &buffer[..len] {
+                HOST_GREETING => exchange.echoed = true,
+                KERNEL_GREETING => exchange.greeted = true,
+                _ => {}
+            }
+        }
+    }
+    exchange
+}
+
+// Follows the tests in the json log of the kernel (logformat=json, the records of the test runner have
+// targets below test::, see kernel/src/tester.rs). Other serial output is passed through
+#[derive(Default)]
+struct TestReport {
+    // the names of the started tests, the last one runs
+    started: Vec<String>,
+    failure: Option<String>,
+    passed: bool,
+}
+
+impl TestReport {
+    // returns the line in the text format of the kernel log
+    fn record(&mut self, line: &str) -> String {
+        let Ok(serde_json::Value::Object(record)) = serde_json::from_str(line) else {
+            return line.to_string();
+        };
+        let text = |key: &str| {
+            record
+                .get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or("")
+        };
+        let msg = text("msg");
+        match text("target") {
+            "test::start" => {
+                let name = msg.split_whitespace().next().unwrap_or("");
+                self.started.push(name.to_string());
+            }
+            "test::failed" => self.failure = Some(msg.to_string()),
+            "test::passed" => self.passed = true,
+            _ => {}
+        }
+
+        let mut formatted = format!("[{:<5}", text("level"));
+        if let Some(ts) = record.get("ts").and_then(|ts| ts.as_f64()) {
+            formatted += &format!(" {ts:>8.3}");
+        }
+        if let Some(core) = record.get("core").and_then(|core| core.as_u64()) {
+            formatted += &format!(" {core:>2}");
+        }
+        if let Some(line) = record.get("line").and_then(|line| line.as_u64()) {
+            formatted += &format!(" {}:{line}", text("file"));
+        }
+        formatted + &format!("] {msg}")
+    }
+
+    fn ran(&self, test: &str) -> bool {
+        self.started.iter().any(|name| name == test)
+    }
+
+    fn summary(&self) -> String {
+        let current = self.started.last().map_or("<none>", String::as_str);
+        match (&self.failure, self.passed) {
+            (Some(failure), _) => format!(
+                "Test {current} failed ({} of the tests started):\n{failure}",
+                self.started.len()
+            ),
+            (None, true) => format!("All {} tests passed", self.started.len()),
+            (None, false) => format!("Test {current} did not finish (the kernel stopped or hung)"),
+        }
+    }
+}
+
+// WIDTHxHEIGHT
+fn parse_resolution(resolution: &str) -> Result<(u64, u64), String> {
+    let (width, height) = resolution
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {resolution:?}"))?;
+    let parse = |value: &str| {
+        value
+            .parse::<u64>()
+            .ok()
+            .filter(|&value| value > 0)
+            .ok_or_else(|| format!("invalid resolution {resolution:?}"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
 fn add_profile_args(cmd: &mut std::process::Command, profile: Profile) -> &'static str {
     match profile {
         Profile::Dev => "debug",
@@ -196,16 +436,26 @@ fn add_profile_args(cmd: &mut std::process::Command, profile: Profile) -> &'stat
     }
 }
 
-fn create_ram_disk(profile_name: &str) -> NamedTempFile {
+fn create_ram_disk(profile_name: &str, cmdline: &str, kernel: &Path, tar: bool) -> NamedTempFile {
+    // the kernel relies on this order and these names (see kernel/src/ram_disk.rs)
     let mut img = Image::new();
-    img.add_user_app("main", profile_name)
+    img.add_string("cmdline", cmdline)
+        .add_user_app("main", profile_name)
         .add_user_app("test", profile_name)
-        .add_file(&"bootimage/test.jpg".into());
+        .add_file("logo", &"bootimage/test.jpg".into())
+        .add_user_app("pipe", profile_name)
+        .add_user_app("fault", profile_name)
+        .add_kernel_symbols(kernel);
 
-    img.build()
+    if tar {
+        img.build_tar()
+    } else {
+        img.build()
+    }
 }
 
 struct Image {
+    region_names: Vec<String>,
     region_ends: Vec<u64>,
     buffer: Vec<u8>,
 }
@@ -214,18 +464,24 @@ struct Image {
 impl Image {
     pub fn new() -> Self {
         Self {
+            region_names: Vec::new(),
             region_ends: Vec::new(),
             buffer: Vec::new(),
         }
     }
 
-    pub fn add_file(&mut self, file: &std::path::PathBuf) -> &mut Self {
+    fn end_region(&mut self, name: &str) -> &mut Self {
+        self.region_names.push(name.to_string());
+        self.region_ends.push(self.buffer.len() as u64);
+        self
+    }
+
+    pub fn add_file(&mut self, name: &str, file: &std::path::PathBuf) -> &mut Self {
         fs::File::open(file)
             .unwrap()
             .read_to_end(&mut self.buffer)
             .unwrap();
-        self.region_ends.push(self.buffer.len() as u64);
-        self
+        self.end_region(name)
     }
 
     pub fn add_user_app(&mut self, name: &str, profile_name: &str) -> &mut Self {
@@ -235,14 +491,49 @@ impl Image {
             profile_name,
             name
         ));
-        self.add_file(&user_app);
-        self
+        self.add_file(name, &user_app)
+    }
+
+    // Function symbols of the kernel for symbolized backtraces (see kernel/src/symbols.rs):
+    // entry count (u64), entries sorted by address (address u64, size u32, name start u32), demangled names
+    pub fn add_kernel_symbols(&mut self, kernel: &Path) -> &mut Self {
+        let data = fs::read(kernel).unwrap();
+        let file = elf::ElfBytes::<elf::endian::AnyEndian>::minimal_parse(&data).unwrap();
+        let (symbols, strings) = file
+            .symbol_table()
+            .unwrap()
+            .expect("the kernel has no symbol table");
+        let mut functions: Vec<(u64, u64, String)> = symbols
+            .iter()
+            .filter(|symbol| symbol.st_symtype() == elf::abi::STT_FUNC && symbol.st_value != 0)
+            .map(|symbol| {
+                let name = strings.get(symbol.st_name as usize).unwrap();
+                (
+                    symbol.st_value,
+                    symbol.st_size,
+                    format!("{:#}", rustc_demangle::demangle(name)),
+                )
+            })
+            .collect();
+        functions.sort();
+        functions.dedup_by_key(|(address, _, _)| *address);
+
+        let mut names = String::new();
+        self.buffer
+            .extend_from_slice(&(functions.len() as u64).to_le_bytes());
+        for (address, size, name) in &functions {
+            self.buffer.extend_from_slice(&address.to_le_bytes());
+            self.buffer.extend_from_slice(&(*size as u32).to_le_bytes());
+            self.buffer
+                .extend_from_slice(&(names.len() as u32).to_le_bytes());
+            names.push_str(name);
+        }
+        self.add_string("symbols", &names)
     }
 
-    pub fn add_string(&mut self, string: &str) -> &mut Self {
+    pub fn add_string(&mut self, name: &str, string: &str) -> &mut Self {
         self.buffer.extend_from_slice(string.as_bytes());
-        self.region_ends.push(self.buffer.len() as u64);
-        self
+        self.end_region(name)
     }
 
     pub fn build(mut self) -> NamedTempFile {
@@ -269,6 +560,42 @@ impl Image {
         img_file.write_all(&self.buffer).unwrap();
         img_file
     }
+
+    // ustar archive with a file per region (kernel/src/tar.rs), can be inspected with tar -tvf
+    pub fn build_tar(self) -> NamedTempFile {
+        const BLOCK_SIZE: usize = 512;
+        let mut archive = Vec::new();
+        let mut start = 0;
+        for (name, &end) in self.region_names.iter().zip(&self.region_ends) {
+            let data = &self.buffer[start as usize..end as usize];
+            start = end;
+
+            let mut header = [0u8; BLOCK_SIZE];
+            assert!(name.len() < 100, "ram disk file name too long: {name}");
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..108].copy_from_slice(b"0000644\0");
+            header[108..116].copy_from_slice(b"0000000\0");
+            header[116..124].copy_from_slice(b"0000000\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+            header[136..148].copy_from_slice(b"00000000000\0");
+            header[156] = b'0';
+            header[257..265].copy_from_slice(b"ustar\000");
+            // the checksum is computed with the field set to spaces
+            header[148..156].fill(b' ');
+            let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+            header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(data);
+            archive.resize(archive.len().next_multiple_of(BLOCK_SIZE), 0);
+        }
+        // the end of the archive
+        archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+
+        let mut img_file = NamedTempFile::new().unwrap();
+        img_file.write_all(&archive).unwrap();
+        img_file
+    }
 }
 
 #[test]
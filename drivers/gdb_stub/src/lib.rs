@@ -0,0 +1,729 @@
+#![no_std]
+
+// Minimal gdb remote serial protocol stub for x86_64 targets: registers, memory, software breakpoints and single stepping.
+// The target calls GdbStub::stopped whenever it trapped (int3 or debug exception) and resumes with the returned registers.
+// All cores except the stopped one are left to the target, the protocol only knows one thread.
+// https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+
+pub const PACKET_SIZE: usize = 0x1000;
+pub const MAX_BREAKPOINTS: usize = 32;
+// hex encoded in replies, leaves room for the framing
+const MAX_MEMORY_ACCESS: usize = PACKET_SIZE / 2 - 8;
+
+const INT3: u8 = 0xCC;
+const TRAP_FLAG: u64 = 1 << 8;
+const STOP_REPLY: &str = "S05"; // SIGTRAP
+
+const ERROR_MALFORMED: &str = "E01";
+const ERROR_NO_BREAKPOINT_SLOT: &str = "E0c";
+const ERROR_MEMORY: &str = "E14";
+
+// blocking byte stream to the debugger
+pub trait Connection {
+    fn read(&mut self) -> u8;
+    fn write(&mut self, byte: u8);
+}
+
+// memory of the stopped target, returns false if (part of) the range is not accessible
+pub trait Memory {
+    fn read(&mut self, address: u64, data: &mut [u8]) -> bool;
+    fn write(&mut self, address: u64, data: &[u8]) -> bool;
+}
+
+// general purpose registers of gdb's x86_64 register set (the fpu and sse registers are reported as unavailable)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub cs: u64,
+    pub ss: u64,
+    pub ds: u64,
+    pub es: u64,
+    pub fs: u64,
+    pub gs: u64,
+}
+
+const REGISTER_COUNT: usize = 24;
+// eflags and the segment registers are 32 bit wide in the 'g' packet
+const REGISTERS_SIZE: usize = 17 * 8 + 7 * 4;
+
+impl Registers {
+    fn fields(&mut self) -> [(&mut u64, usize); REGISTER_COUNT] {
+        [
+            (&mut self.rax, 8),
+            (&mut self.rbx, 8),
+            (&mut self.rcx, 8),
+            (&mut self.rdx, 8),
+            (&mut self.rsi, 8),
+            (&mut self.rdi, 8),
+            (&mut self.rbp, 8),
+            (&mut self.rsp, 8),
+            (&mut self.r8, 8),
+            (&mut self.r9, 8),
+            (&mut self.r10, 8),
+            (&mut self.r11, 8),
+            (&mut self.r12, 8),
+            (&mut self.r13, 8),
+            (&mut self.r14, 8),
+            (&mut self.r15, 8),
+            (&mut self.rip, 8),
+            (&mut self.rflags, 4),
+            (&mut self.cs, 4),
+            (&mut self.ss, 4),
+            (&mut self.ds, 4),
+            (&mut self.es, 4),
+            (&mut self.fs, 4),
+            (&mut self.gs, 4),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // int3, rip points behind the instruction
+    Breakpoint,
+    // debug exception after a single step
+    Step,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    Step,
+    // the debugger is gone, all breakpoints are removed
+    Detach,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    original: u8,
+}
+
+struct Reply {
+    data: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    // replies are bounded by MAX_MEMORY_ACCESS, anything longer is truncated
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn str(&mut self, s: &str) {
+        s.bytes().for_each(|byte| self.push(byte));
+    }
+
+    fn hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(HEX_DIGITS[(byte >> 4) as usize]);
+            self.push(HEX_DIGITS[(byte & 0xF) as usize]);
+        }
+    }
+}
+
+pub struct GdbStub<C: Connection> {
+    connection: C,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    packet: [u8; PACKET_SIZE],
+    reply: Reply,
+    // the debugger waits for a stop reply (it resumed the target with c or s)
+    resumed: bool,
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub const fn new(connection: C) -> Self {
+        Self {
+            connection,
+            breakpoints: [None; MAX_BREAKPOINTS],
+            packet: [0; PACKET_SIZE],
+            reply: Reply {
+                data: [0; PACKET_SIZE],
+                len: 0,
+            },
+            resumed: false,
+        }
+    }
+
+    pub fn is_breakpoint(&self, address: u64) -> bool {
+        self.breakpoint(address).is_some()
+    }
+
+    fn breakpoint(&self, address: u64) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|bp| bp.is_some_and(|bp| bp.address == address))
+    }
+
+    // Serves the debugger until it resumes the target. The registers are the state the target continues with,
+    // the trap flag is set for single steps
+    pub fn stopped(
+        &mut self,
+        memory: &mut impl Memory,
+        registers: &mut Registers,
+        stop: Stop,
+    ) -> Resume {
+        // continue at the replaced instruction, int3 instructions which are not ours are skipped
+        if stop == Stop::Breakpoint && self.is_breakpoint(registers.rip.wrapping_sub(1)) {
+            registers.rip -= 1;
+        }
+        if self.resumed {
+            self.resumed = false;
+            self.reply.clear();
+            self.reply.str(STOP_REPLY);
+            self.send_reply();
+        }
+        let resume = loop {
+            let len = self.receive_packet();
+            self.reply.clear();
+            let resume = self.handle_packet(len, memory, registers);
+            // continue and step are answered by the next stop, kill is not answered
+            if !matches!(self.packet[..len], [b'c' | b's' | b'k', ..]) {
+                self.send_reply();
+            }
+            if let Some(resume) = resume {
+                break resume;
+            }
+        };
+        match resume {
+            Resume::Continue | Resume::Step => self.resumed = true,
+            Resume::Detach => self.remove_all_breakpoints(memory),
+        }
+        if resume == Resume::Step {
+            registers.rflags |= TRAP_FLAG;
+        } else {
+            registers.rflags &= !TRAP_FLAG;
+        }
+        resume
+    }
+
+    // returns the length of the packet data, invalid packets are rejected until the debugger sent a valid one
+    fn receive_packet(&mut self) -> usize {
+        loop {
+            while self.connection.read() != b'$' {}
+            let mut len = 0;
+            let mut checksum = 0u8;
+            let mut overflow = false;
+            loop {
+                let byte = self.connection.read();
+                if byte == b'#' {
+                    break;
+                }
+                checksum = checksum.wrapping_add(byte);
+                if len < PACKET_SIZE {
+                    self.packet[len] = byte;
+                    len += 1;
+                } else {
+                    overflow = true;
+                }
+            }
+            let high = self.connection.read();
+            let low = self.connection.read();
+            if !overflow && parse_hex(&[high, low]) == Some(checksum as u64) {
+                self.connection.write(b'+');
+                return len;
+            }
+            self.connection.write(b'-');
+        }
+    }
+
+    fn send_reply(&mut self) {
+        let data = &self.reply.data[..self.reply.len];
+        let checksum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        loop {
+            self.connection.write(b'$');
+            data.iter().for_each(|&byte| self.connection.write(byte));
+            self.connection.write(b'#');
+            self.connection.write(HEX_DIGITS[(checksum >> 4) as usize]);
+            self.connection.write(HEX_DIGITS[(checksum & 0xF) as usize]);
+            // resent on a negative acknowledgment
+            loop {
+                match self.connection.read() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // fills the reply, unsupported commands get an empty one
+    fn handle_packet(
+        &mut self,
+        len: usize,
+        memory: &mut impl Memory,
+        registers: &mut Registers,
+    ) -> Option<Resume> {
+        let packet = &self.packet[..len];
+        let (&command, args) = packet.split_first()?;
+        match command {
+            b'?' => self.reply.str(STOP_REPLY),
+            b'g' => {
+                for (value, size) in registers.fields() {
+                    self.reply.hex(&value.to_le_bytes()[..size]);
+                }
+            }
+            b'G' => {
+                if args.len() < REGISTERS_SIZE * 2 {
+                    self.reply.str(ERROR_MALFORMED);
+                    return None;
+                }
+                let mut args = args;
+                let mut new = *registers;
+                for (value, size) in new.fields() {
+                    let (field, rest) = args.split_at(size * 2);
+                    let Some(field) = parse_hex_le(field) else {
+                        self.reply.str(ERROR_MALFORMED);
+                        return None;
+                    };
+                    *value = field;
+                    args = rest;
+                }
+                *registers = new;
+                self.reply.str("OK");
+            }
+            b'm' => {
+                let Some((address, len)) = parse_address_len(args) else {
+                    self.reply.str(ERROR_MALFORMED);
+                    return None;
+                };
+                let mut data = [0; MAX_MEMORY_ACCESS];
+                let data = &mut data[..(len as usize).min(MAX_MEMORY_ACCESS)];
+                if !memory.read(address, data) {
+                    self.reply.str(ERROR_MEMORY);
+                    return None;
+                }
+                // the debugger sees the original instructions
+                for bp in self.breakpoints.iter().flatten() {
+                    if let Some(offset) = offset_in(bp.address, address, data.len()) {
+                        data[offset] = bp.original;
+                    }
+                }
+                self.reply.hex(data);
+            }
+            b'M' => {
+                let Some(colon) = args.iter().position(|&byte| byte == b':') else {
+                    self.reply.str(ERROR_MALFORMED);
+                    return None;
+                };
+                let (range, hex) = (&args[..colon], &args[colon + 1..]);
+                let mut data = [0; MAX_MEMORY_ACCESS];
+                let decoded = parse_address_len(range).and_then(|(address, len)| {
+                    let len = len as usize;
+                    (len <= MAX_MEMORY_ACCESS && hex.len() == len * 2).then_some(())?;
+                    for (byte, digits) in data.iter_mut().zip(hex.chunks_exact(2)) {
+                        *byte = parse_hex(digits)? as u8;
+                    }
+                    Some((address, len))
+                });
+                let Some((address, len)) = decoded else {
+                    self.reply.str(ERROR_MALFORMED);
+                    return None;
+                };
+                let data = &mut data[..len];
+                // writes to replaced instructions change the saved byte, the int3 stays
+                for bp in self.breakpoints.iter_mut().flatten() {
+                    if let Some(offset) = offset_in(bp.address, address, len) {
+                        bp.original = data[offset];
+                        data[offset] = INT3;
+                    }
+                }
+                if memory.write(address, data) {
+                    self.reply.str("OK");
+                } else {
+                    self.reply.str(ERROR_MEMORY);
+                }
+            }
+            b'Z' | b'z' => {
+                // only software breakpoints (type 0), the kind (instruction length) does not matter for int3
+                let address = args
+                    .strip_prefix(b"0,")
+                    .and_then(|args| args.split(|&byte| byte == b',').next())
+                    .and_then(parse_hex)?;
+                let result = if command == b'Z' {
+                    self.insert_breakpoint(memory, address)
+                } else {
+                    self.remove_breakpoint(memory, address)
+                };
+                match result {
+                    Ok(()) => self.reply.str("OK"),
+                    Err(error) => self.reply.str(error),
+                }
+            }
+            b'c' | b's' => {
+                if !args.is_empty() {
+                    let Some(address) = parse_hex(args) else {
+                        self.reply.str(ERROR_MALFORMED);
+                        return None;
+                    };
+                    registers.rip = address;
+                }
+                return Some(if command == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                });
+            }
+            b'D' => {
+                self.reply.str("OK");
+                return Some(Resume::Detach);
+            }
+            // the kernel can not be killed, the debugger does not expect a reply
+            b'k' => return Some(Resume::Detach),
+            // there is only one thread
+            b'H' => self.reply.str("OK"),
+            b'q' if args.starts_with(b"Supported") => self.reply.str("PacketSize=1000"),
+            b'q' if args == b"Attached" => self.reply.str("1"),
+            _ => {}
+        }
+        None
+    }
+
+    fn insert_breakpoint(
+        &mut self,
+        memory: &mut impl Memory,
+        address: u64,
+    ) -> Result<(), &'static str> {
+        if self.is_breakpoint(address) {
+            return Ok(());
+        }
+        let slot = self
+            .breakpoints
+            .iter()
+            .position(Option::is_none)
+            .ok_or(ERROR_NO_BREAKPOINT_SLOT)?;
+        let mut original = [0];
+        if !memory.read(address, &mut original) || !memory.write(address, &[INT3]) {
+            return Err(ERROR_MEMORY);
+        }
+        self.breakpoints[slot] = Some(Breakpoint {
+            address,
+            original: original[0],
+        });
+        Ok(())
+    }
+
+    fn remove_breakpoint(
+        &mut self,
+        memory: &mut impl Memory,
+        address: u64,
+    ) -> Result<(), &'static str> {
+        let Some(slot) = self.breakpoint(address) else {
+            return Ok(());
+        };
+        let bp = self.breakpoints[slot].take().unwrap();
+        if !memory.write(bp.address, &[bp.original]) {
+            return Err(ERROR_MEMORY);
+        }
+        Ok(())
+    }
+
+    fn remove_all_breakpoints(&mut self, memory: &mut impl Memory) {
+        for bp in self.breakpoints.iter_mut().filter_map(Option::take) {
+            memory.write(bp.address, &[bp.original]);
+        }
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+// big endian number (addresses and lengths)
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits
+        .iter()
+        .try_fold(0, |value, &byte| Some(value << 4 | hex_digit(byte)? as u64))
+}
+
+// little endian byte sequence (register contents)
+fn parse_hex_le(digits: &[u8]) -> Option<u64> {
+    digits
+        .chunks_exact(2)
+        .rev()
+        .try_fold(0, |value, byte| Some(value << 8 | parse_hex(byte)?))
+}
+
+// "address,length"
+fn parse_address_len(args: &[u8]) -> Option<(u64, u64)> {
+    let comma = args.iter().position(|&byte| byte == b',')?;
+    Some((parse_hex(&args[..comma])?, parse_hex(&args[comma + 1..])?))
+}
+
+fn offset_in(address: u64, start: u64, len: usize) -> Option<usize> {
+    let offset = address.wrapping_sub(start);
+    (offset < len as u64).then_some(offset as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{collections::VecDeque, format, string::String, vec::Vec};
+
+    use super::*;
+
+    // replays the debugger side, panics if the stub reads more than was scripted
+    struct MockConnection {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Connection for MockConnection {
+        fn read(&mut self) -> u8 {
+            self.input.pop_front().expect("stub waits for more input")
+        }
+
+        fn write(&mut self, byte: u8) {
+            self.output.push(byte);
+        }
+    }
+
+    struct MockMemory {
+        base: u64,
+        data: Vec<u8>,
+    }
+
+    impl MockMemory {
+        fn range(&self, address: u64, len: usize) -> Option<core::ops::Range<usize>> {
+            let start = address.checked_sub(self.base)? as usize;
+            (start + len <= self.data.len()).then_some(start..start + len)
+        }
+    }
+
+    impl Memory for MockMemory {
+        fn read(&mut self, address: u64, data: &mut [u8]) -> bool {
+            let Some(range) = self.range(address, data.len()) else {
+                return false;
+            };
+            data.copy_from_slice(&self.data[range]);
+            true
+        }
+
+        fn write(&mut self, address: u64, data: &[u8]) -> bool {
+            let Some(range) = self.range(address, data.len()) else {
+                return false;
+            };
+            self.data[range].copy_from_slice(data);
+            true
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        format!("${data}#{checksum:02x}")
+    }
+
+    // every packet is acknowledged by the stub and every reply by the debugger
+    fn session(packets: &[&str]) -> MockConnection {
+        let mut input = String::new();
+        for data in packets {
+            input += &packet(data);
+            if !matches!(data.as_bytes()[0], b'c' | b's' | b'k') {
+                input.push('+');
+            }
+        }
+        MockConnection {
+            input: input.into_bytes().into(),
+            output: Vec::new(),
+        }
+    }
+
+    fn replies(connection: &MockConnection) -> Vec<String> {
+        let output = String::from_utf8(connection.output.clone()).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|reply| {
+                let (data, checksum) = reply.split_once('#').unwrap();
+                assert_eq!(&checksum[..2], &packet(data)[data.len() + 2..]);
+                String::from(data)
+            })
+            .collect()
+    }
+
+    fn memory() -> MockMemory {
+        MockMemory {
+            base: 0x1000,
+            data: (0..=255).collect(),
+        }
+    }
+
+    #[test]
+    fn acknowledges_and_answers_packets() {
+        let connection = session(&[
+            "qSupported:swbreak+",
+            "?",
+            "qAttached",
+            "vMustReplyEmpty",
+            "c",
+        ]);
+        let mut stub = GdbStub::new(connection);
+        let resume = stub.stopped(&mut memory(), &mut Registers::default(), Stop::Breakpoint);
+        assert_eq!(resume, Resume::Continue);
+        assert!(stub.connection.input.is_empty());
+        assert_eq!(
+            stub.connection
+                .output
+                .iter()
+                .filter(|&&byte| byte == b'+')
+                .count(),
+            5
+        );
+        assert_eq!(
+            replies(&stub.connection),
+            ["PacketSize=1000", "S05", "1", ""]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let mut connection = session(&["c"]);
+        let input = format!("$?#00{}+", packet("?"));
+        connection.input = input.bytes().chain(connection.input).collect();
+        let mut stub = GdbStub::new(connection);
+        stub.stopped(&mut memory(), &mut Registers::default(), Stop::Step);
+        assert!(stub.connection.output.starts_with(b"-+$S05"));
+    }
+
+    #[test]
+    fn resends_replies_on_negative_acknowledgments() {
+        let mut connection = session(&["?", "c"]);
+        connection.input.insert(packet("?").len(), b'-');
+        let mut stub = GdbStub::new(connection);
+        stub.stopped(&mut memory(), &mut Registers::default(), Stop::Step);
+        assert_eq!(replies(&stub.connection), ["S05", "S05"]);
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let mut registers = Registers {
+            rax: 0x1122334455667788,
+            rip: 0xFFFF_8000_0000_1000,
+            rflags: 0x202,
+            cs: 0x8,
+            ..Default::default()
+        };
+        let connection = session(&["g", "c"]);
+        let mut stub = GdbStub::new(connection);
+        stub.stopped(&mut memory(), &mut registers, Stop::Step);
+        let g = replies(&stub.connection).remove(0);
+        assert_eq!(g.len(), REGISTERS_SIZE * 2);
+        assert!(g.starts_with("8877665544332211"));
+        assert_eq!(&g[16 * 16..17 * 16], "001000000080ffff");
+        assert_eq!(&g[17 * 16..17 * 16 + 16], "0202000008000000");
+
+        let mut changed = g.clone();
+        changed.replace_range(..16, "0100000000000000");
+        let connection = session(&[&format!("G{changed}"), "G1234", "c"]);
+        let mut stub = GdbStub::new(connection);
+        stub.stopped(&mut memory(), &mut registers, Stop::Step);
+        assert_eq!(replies(&stub.connection), ["OK", ERROR_MALFORMED]);
+        assert_eq!(registers.rax, 1);
+        assert_eq!(registers.rip, 0xFFFF_8000_0000_1000);
+        assert_eq!(registers.cs, 0x8);
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let mut memory = memory();
+        let connection = session(&[
+            "m1010,4",
+            "M1010,2:abcd",
+            "m100f,4",
+            "m2000,1",
+            "M1010,2:ab",
+            "c",
+        ]);
+        let mut stub = GdbStub::new(connection);
+        stub.stopped(&mut memory, &mut Registers::default(), Stop::Step);
+        assert_eq!(
+            replies(&stub.connection),
+            ["10111213", "OK", "0fabcd12", ERROR_MEMORY, ERROR_MALFORMED]
+        );
+    }
+
+    #[test]
+    fn breakpoints_replace_instructions() {
+        let mut memory = memory();
+        let connection = session(&["Z0,1020,1", "m1020,2", "Z1,1030,1", "c"]);
+        let mut stub = GdbStub::new(connection);
+        stub.stopped(&mut memory, &mut Registers::default(), Stop::Step);
+        assert_eq!(replies(&stub.connection), ["OK", "2021", ""]);
+        assert_eq!(memory.data[0x20], INT3);
+
+        // the int3 trapped, the stop reply answers the continue
+        let mut registers = Registers {
+            rip: 0x1021,
+            ..Default::default()
+        };
+        stub.connection = session(&["z0,1020,1", "s"]);
+        stub.connection.input.push_front(b'+');
+        let resume = stub.stopped(&mut memory, &mut registers, Stop::Breakpoint);
+        assert_eq!(resume, Resume::Step);
+        assert_eq!(replies(&stub.connection), ["S05", "OK"]);
+        assert_eq!(registers.rip, 0x1020);
+        assert_eq!(registers.rflags & TRAP_FLAG, TRAP_FLAG);
+        assert_eq!(memory.data[0x20], 0x20);
+    }
+
+    #[test]
+    fn foreign_int3_is_not_rewound() {
+        let mut registers = Registers {
+            rip: 0x1021,
+            rflags: TRAP_FLAG,
+            ..Default::default()
+        };
+        let connection = session(&["c"]);
+        let mut stub = GdbStub::new(connection);
+        stub.stopped(&mut memory(), &mut registers, Stop::Breakpoint);
+        assert_eq!(registers.rip, 0x1021);
+        assert_eq!(registers.rflags, 0);
+    }
+
+    #[test]
+    fn detach_removes_breakpoints() {
+        let mut memory = memory();
+        let connection = session(&["Z0,1000,1", "Z0,10ff,1", "Z0,2000,1", "D"]);
+        let mut stub = GdbStub::new(connection);
+        let resume = stub.stopped(&mut memory, &mut Registers::default(), Stop::Step);
+        assert_eq!(resume, Resume::Detach);
+        assert_eq!(replies(&stub.connection), ["OK", "OK", ERROR_MEMORY, "OK"]);
+        assert_eq!(memory.data, (0..=255).collect::<Vec<u8>>());
+        assert!(!stub.is_breakpoint(0x1000));
+    }
+}
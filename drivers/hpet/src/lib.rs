@@ -0,0 +1,185 @@
+#![no_std]
+
+use core::hint;
+
+use port_io::Mmio32;
+
+// 64 bit registers, accessed as two 32 bit halves
+const CAPABILITIES: usize = 0x00;
+const CAPABILITIES_PERIOD: usize = 0x04;
+const CONFIGURATION: usize = 0x10;
+const MAIN_COUNTER: usize = 0xF0;
+const MAIN_COUNTER_HIGH: usize = 0xF4;
+
+const CAPABILITY_64BIT_COUNTER: u32 = 1 << 13;
+const CONFIGURATION_ENABLE: u32 = 1 << 0;
+
+// the specification requires a period of at most 100 ns
+const MAX_PERIOD_FS: u32 = 100_000_000;
+const FS_PER_US: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    InvalidPeriod(u32),
+}
+
+// Register level driver for the main counter of a high precision event timer (the comparators are not used).
+// Only reads the counter after enable, so it can be shared by all cores
+pub struct Hpet<M: Mmio32> {
+    mmio: M,
+    period_fs: u32,
+}
+
+impl<M: Mmio32> Hpet<M> {
+    pub fn new(mmio: M) -> Result<Self, HpetError> {
+        let period_fs = mmio.read(CAPABILITIES_PERIOD);
+        if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+            return Err(HpetError::InvalidPeriod(period_fs));
+        }
+        Ok(Self { mmio, period_fs })
+    }
+
+    pub const fn period_fs(&self) -> u32 {
+        self.period_fs
+    }
+
+    pub const fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs as u64
+    }
+
+    // 32 bit counters wrap around after a few minutes
+    pub fn is_64bit(&self) -> bool {
+        self.mmio.read(CAPABILITIES) & CAPABILITY_64BIT_COUNTER != 0
+    }
+
+    // the counter runs while the hpet is enabled
+    pub fn enable(&self) {
+        let configuration = self.mmio.read(CONFIGURATION);
+        self.mmio
+            .write(CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+    }
+
+    pub fn disable(&self) {
+        let configuration = self.mmio.read(CONFIGURATION);
+        self.mmio
+            .write(CONFIGURATION, configuration & !CONFIGURATION_ENABLE);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mmio.read(CONFIGURATION) & CONFIGURATION_ENABLE != 0
+    }
+
+    // The halves are read separately, the read is repeated if the low half wrapped around in between
+    pub fn counter(&self) -> u64 {
+        if !self.is_64bit() {
+            return self.mmio.read(MAIN_COUNTER) as u64;
+        }
+        loop {
+            let high = self.mmio.read(MAIN_COUNTER_HIGH);
+            let low = self.mmio.read(MAIN_COUNTER);
+            if self.mmio.read(MAIN_COUNTER_HIGH) == high {
+                return (high as u64) << 32 | low as u64;
+            }
+        }
+    }
+
+    pub fn ticks_from_us(&self, us: u64) -> u64 {
+        (us as u128 * FS_PER_US as u128 / self.period_fs as u128) as u64
+    }
+
+    pub fn us_from_ticks(&self, ticks: u64) -> u64 {
+        (ticks as u128 * self.period_fs as u128 / FS_PER_US as u128) as u64
+    }
+
+    // busy waits, the hpet has to be enabled
+    pub fn delay_us(&self, us: u64) {
+        let ticks = self.ticks_from_us(us);
+        let start = self.counter();
+        let mask = if self.is_64bit() {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        };
+        while self.counter().wrapping_sub(start) & mask < ticks {
+            hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use port_io::mock::MockMmio;
+
+    use super::*;
+
+    // 10 MHz like the hpet of qemu
+    const PERIOD_FS: u32 = 100_000_000;
+
+    fn hpet(mmio: &MockMmio, is_64bit: bool) -> Hpet<&MockMmio> {
+        mmio.set(CAPABILITIES_PERIOD, PERIOD_FS);
+        mmio.set(
+            CAPABILITIES,
+            if is_64bit {
+                CAPABILITY_64BIT_COUNTER
+            } else {
+                0
+            },
+        );
+        Hpet::new(mmio).unwrap()
+    }
+
+    #[test]
+    fn period_is_validated() {
+        let mmio = MockMmio::new();
+        assert_eq!(Hpet::new(&mmio).err(), Some(HpetError::InvalidPeriod(0)));
+        mmio.set(CAPABILITIES_PERIOD, MAX_PERIOD_FS + 1);
+        assert_eq!(
+            Hpet::new(&mmio).err(),
+            Some(HpetError::InvalidPeriod(MAX_PERIOD_FS + 1))
+        );
+
+        let hpet = hpet(&mmio, true);
+        assert_eq!(hpet.frequency(), 10_000_000);
+        assert_eq!(hpet.ticks_from_us(1000), 10_000);
+        assert_eq!(hpet.us_from_ticks(10_000), 1000);
+    }
+
+    #[test]
+    fn enable_keeps_the_other_configuration_bits() {
+        let mmio = MockMmio::new();
+        mmio.set(CONFIGURATION, 0b10);
+        let hpet = hpet(&mmio, true);
+
+        hpet.enable();
+        assert!(hpet.is_enabled());
+        assert_eq!(mmio.value(CONFIGURATION), 0b11);
+
+        hpet.disable();
+        assert!(!hpet.is_enabled());
+        assert_eq!(mmio.value(CONFIGURATION), 0b10);
+    }
+
+    #[test]
+    fn counter_halves_are_read_consistently() {
+        let mmio = MockMmio::new();
+        let hpet = hpet(&mmio, true);
+        // the low half wraps around between the first two reads
+        mmio.queue_reads(MAIN_COUNTER_HIGH, &[1, 2, 2, 2]);
+        mmio.queue_reads(MAIN_COUNTER, &[0xFFFF_FFFF, 5]);
+
+        assert_eq!(hpet.counter(), 2 << 32 | 5);
+    }
+
+    #[test]
+    fn delay_waits_for_the_counter() {
+        let mmio = MockMmio::new();
+        let hpet = hpet(&mmio, false);
+        // 32 bit counter wrapping around during the delay of 10 ticks
+        mmio.queue_reads(MAIN_COUNTER, &[0xFFFF_FFFA, 0xFFFF_FFFF, 2, 4]);
+
+        hpet.delay_us(1);
+
+        // all queued counter values were read
+        assert_eq!(hpet.counter(), 0);
+    }
+}
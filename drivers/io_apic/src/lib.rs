@@ -0,0 +1,149 @@
+#![no_std]
+
+use bitfield::bitfield;
+use port_io::Mmio32;
+
+// the registers are accessed indirectly: the index is written to IOREGSEL, the value is read/written through IOWIN
+const REGISTER_SELECT: usize = 0x00;
+const REGISTER_WINDOW: usize = 0x10;
+
+const ID_REGISTER: u32 = 0x00;
+const VERSION_REGISTER: u32 = 0x01;
+const REDIRECTION_TABLE: u32 = 0x10;
+
+// Register level driver for an I/O APIC (one block of memory mapped registers)
+pub struct IoApic<M: Mmio32> {
+    mmio: M,
+}
+
+impl<M: Mmio32> IoApic<M> {
+    pub const fn new(mmio: M) -> Self {
+        Self { mmio }
+    }
+
+    pub fn id(&mut self) -> u8 {
+        ((self.read(ID_REGISTER) >> 24) & 0xF) as u8
+    }
+
+    // number of redirection entries (inputs) of this io apic
+    pub fn entry_count(&mut self) -> u32 {
+        ((self.read(VERSION_REGISTER) >> 16) & 0xFF) + 1
+    }
+
+    pub fn read_entry(&mut self, index: u32) -> RedirectionEntry {
+        let low = self.read(REDIRECTION_TABLE + index * 2);
+        let high = self.read(REDIRECTION_TABLE + index * 2 + 1);
+        let mut entry = RedirectionEntry(0);
+        entry.set_lower(low as u64);
+        entry.set_upper(high as u64);
+        entry
+    }
+
+    // The entry is masked while it is changed, the low half (containing the mask) is written last
+    pub fn write_entry(&mut self, index: u32, entry: RedirectionEntry) {
+        let low = REDIRECTION_TABLE + index * 2;
+        self.set_masked(index, true);
+        self.write(low + 1, entry.upper() as u32);
+        self.write(low, entry.lower() as u32);
+    }
+
+    pub fn set_masked(&mut self, index: u32, masked: bool) {
+        let low = REDIRECTION_TABLE + index * 2;
+        let value = self.read(low) & !MASKED;
+        self.write(low, if masked { value | MASKED } else { value });
+    }
+
+    pub fn mask_all(&mut self) {
+        for index in 0..self.entry_count() {
+            self.set_masked(index, true);
+        }
+    }
+
+    pub fn read(&mut self, register: u32) -> u32 {
+        self.mmio.write(REGISTER_SELECT, register);
+        self.mmio.read(REGISTER_WINDOW)
+    }
+
+    pub fn write(&mut self, register: u32, value: u32) {
+        self.mmio.write(REGISTER_SELECT, register);
+        self.mmio.write(REGISTER_WINDOW, value);
+    }
+}
+
+const MASKED: u32 = 1 << 16;
+
+bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct RedirectionEntry(u64);
+    impl Debug;
+    pub vector, set_vector: 7, 0;
+    pub delivery_mode, set_delivery_mode: 10, 8;
+    pub destination_mode_logical, set_destination_mode_logical: 11;
+    pub delivery_status, _: 12;
+    pub active_low, set_active_low: 13;
+    pub remote_irr, _: 14;
+    pub level_triggered, set_level_triggered: 15;
+    pub masked, set_masked: 16;
+    pub destination, set_destination: 63, 56;
+    pub lower, set_lower : 31, 0;
+    pub upper, set_upper : 63, 32;
+}
+
+impl RedirectionEntry {
+    // fixed delivery of vector to the local apic with the (physical) id destination
+    pub fn fixed(vector: u8, destination: u8) -> Self {
+        let mut entry = Self(0);
+        entry.set_vector(vector as u64);
+        entry.set_delivery_mode(0);
+        entry.set_destination_mode_logical(false);
+        entry.set_destination(destination as u64);
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use port_io::mock::MockMmio;
+
+    use super::*;
+
+    #[test]
+    fn registers_are_selected_before_access() {
+        let mmio = MockMmio::new();
+        mmio.set(REGISTER_WINDOW, 0x0017_0020);
+        let mut io_apic = IoApic::new(&mmio);
+
+        assert_eq!(io_apic.entry_count(), 24);
+        assert_eq!(mmio.writes_to(REGISTER_SELECT), [VERSION_REGISTER]);
+    }
+
+    #[test]
+    fn entries_are_masked_while_written() {
+        let mmio = MockMmio::new();
+        let mut io_apic = IoApic::new(&mmio);
+        let mut entry = RedirectionEntry::fixed(0x31, 2);
+        entry.set_level_triggered(true);
+        entry.set_active_low(true);
+
+        io_apic.write_entry(1, entry);
+
+        assert_eq!(
+            mmio.writes_to(REGISTER_SELECT),
+            [0x12, 0x12, 0x13, 0x12] // read, mask, high, low
+        );
+        let written = mmio.writes_to(REGISTER_WINDOW);
+        assert_eq!(written[0] & MASKED, MASKED);
+        assert_eq!(written[1], 2 << 24);
+        assert_eq!(written[2], 0x31 | 1 << 13 | 1 << 15);
+    }
+
+    #[test]
+    fn fixed_entry_targets_physical_destination() {
+        let entry = RedirectionEntry::fixed(0x40, 3);
+        assert_eq!(entry.vector(), 0x40);
+        assert_eq!(entry.delivery_mode(), 0);
+        assert!(!entry.destination_mode_logical());
+        assert!(!entry.masked());
+        assert_eq!(entry.upper(), 3 << 24);
+    }
+}
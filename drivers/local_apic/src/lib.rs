@@ -0,0 +1,546 @@
+#![no_std]
+
+use port_io::{Mmio32, Msr};
+
+const TIMER_MASKED: u32 = 1 << 16;
+// timer mode (bits 17 and 18)
+const TIMER_ONE_SHOT: u32 = 0;
+const TIMER_PERIODIC: u32 = 0x20000;
+const TIMER_TSC_DEADLINE: u32 = 0x40000;
+const TIMER_DIVIDE_BY_16: u32 = 0x3;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+// the registers are msrs 0x800 + offset / 16
+const X2APIC_MSR_BASE: u32 = 0x800;
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
+// Access to the registers of the local apic, memory mapped (xAPIC) or through msrs (x2APIC)
+pub trait ApicRegisters {
+    fn read(&self, offset: Offset) -> u32;
+    fn write(&self, offset: Offset, value: u32);
+    fn read_interrupt_command(&self) -> u64;
+    // sends the interrupt
+    fn write_interrupt_command(&self, value: u64);
+    fn id(&self) -> u32;
+    // destination field of the interrupt command addressing the apic id
+    fn destination(&self, id: u32) -> u64;
+}
+
+pub struct XApic<M: Mmio32>(pub M);
+
+impl<M: Mmio32> ApicRegisters for XApic<M> {
+    fn read(&self, offset: Offset) -> u32 {
+        self.0.read(offset as usize)
+    }
+
+    fn write(&self, offset: Offset, value: u32) {
+        self.0.write(offset as usize, value);
+    }
+
+    fn read_interrupt_command(&self) -> u64 {
+        let high = self.read(Offset::InterruptCommandHigh);
+        let low = self.read(Offset::InterruptCommandLow);
+        (high as u64) << 32 | low as u64
+    }
+
+    fn write_interrupt_command(&self, value: u64) {
+        self.write(Offset::InterruptCommandHigh, (value >> 32) as u32);
+        self.write(Offset::InterruptCommandLow, value as u32); //Order is important writing to low triggers command
+    }
+
+    // 8 bit id in the upper byte
+    fn id(&self) -> u32 {
+        self.read(Offset::Id) >> 24
+    }
+
+    fn destination(&self, id: u32) -> u64 {
+        (id as u64 & 0xFF) << 56
+    }
+}
+
+// The interrupt command is a single 64 bit msr (no separate high half), the id is 32 bits wide
+pub struct X2Apic<R: Msr>(pub R);
+
+impl<R: Msr> X2Apic<R> {
+    // switches the executing core from enabled xAPIC to x2APIC mode (the mode stays until the next reset)
+    pub fn enable_mode(&self) {
+        let base = self.0.read_msr(IA32_APIC_BASE);
+        self.0
+            .write_msr(IA32_APIC_BASE, base | APIC_BASE_ENABLE | APIC_BASE_X2APIC);
+    }
+
+    pub fn is_mode_enabled(&self) -> bool {
+        self.0.read_msr(IA32_APIC_BASE) & APIC_BASE_X2APIC != 0
+    }
+
+    const fn msr(offset: Offset) -> u32 {
+        X2APIC_MSR_BASE + (offset as u32 >> 4)
+    }
+}
+
+impl<R: Msr> ApicRegisters for X2Apic<R> {
+    fn read(&self, offset: Offset) -> u32 {
+        self.0.read_msr(Self::msr(offset)) as u32
+    }
+
+    fn write(&self, offset: Offset, value: u32) {
+        self.0.write_msr(Self::msr(offset), value as u64);
+    }
+
+    fn read_interrupt_command(&self) -> u64 {
+        self.0.read_msr(Self::msr(Offset::InterruptCommandLow))
+    }
+
+    fn write_interrupt_command(&self, value: u64) {
+        self.0
+            .write_msr(Self::msr(Offset::InterruptCommandLow), value);
+    }
+
+    fn id(&self) -> u32 {
+        self.read(Offset::Id)
+    }
+
+    fn destination(&self, id: u32) -> u64 {
+        (id as u64) << 32
+    }
+}
+
+// Register level driver for the local APIC
+pub struct LocalApic<R: ApicRegisters> {
+    registers: R,
+}
+
+impl<R: ApicRegisters> LocalApic<R> {
+    pub const fn new(registers: R) -> Self {
+        Self { registers }
+    }
+
+    pub fn enable(&mut self, spurious_vector: u8) {
+        self.write(Offset::TaskPriority, 0); // set task priority to 0 (accept all interrupts)
+        self.write(
+            Offset::SpuriousInterruptVector,
+            0x1100 | spurious_vector as u32,
+        ); // enable apic and disable focus processor checking
+    }
+
+    pub fn signal_end_of_interrupt(&mut self) {
+        self.write(Offset::EndOfInterrupt, 0);
+    }
+
+    pub fn id(&mut self) -> u32 {
+        self.registers.id()
+    }
+
+    pub fn write_interrupt_command(&mut self, cmd: ipi::InterruptCommand) {
+        self.registers.write_interrupt_command(cmd.0);
+    }
+
+    // fixed delivery of vector to the core with the apic id
+    pub fn send_ipi(&mut self, vector: u8, apic_id: u32) {
+        let cmd = ipi::create_fixed_cmd(vector).0 | self.registers.destination(apic_id);
+        self.registers.write_interrupt_command(cmd);
+    }
+
+    // resets the core with the apic id, it waits for a startup ipi afterwards
+    pub fn send_init(&mut self, apic_id: u32) {
+        let cmd = ipi::create_init_cmd().0 | self.registers.destination(apic_id);
+        self.registers.write_interrupt_command(cmd);
+    }
+
+    // starts the core with the apic id in real mode at vector * 4096
+    pub fn send_startup(&mut self, vector: u8, apic_id: u32) {
+        let cmd = ipi::create_targeted_startup_cmd(vector).0 | self.registers.destination(apic_id);
+        self.registers.write_interrupt_command(cmd);
+    }
+
+    pub fn read_interrupt_command(&mut self) -> ipi::InterruptCommand {
+        ipi::InterruptCommand(self.registers.read_interrupt_command())
+    }
+
+    pub fn read(&mut self, offset: Offset) -> u32 {
+        self.registers.read(offset)
+    }
+
+    pub fn write(&mut self, offset: Offset, value: u32) {
+        self.registers.write(offset, value);
+    }
+
+    // counts timer ticks (divider 16) elapsed while wait runs
+    pub fn measure_timer_ticks(&mut self, wait: impl FnOnce()) -> u32 {
+        self.write(Offset::TimerDivideConfiguration, TIMER_DIVIDE_BY_16);
+        self.write(Offset::TimerInitialCount, 0xFFFF_FFFF);
+
+        wait();
+
+        self.stop_timer();
+
+        0xFFFF_FFFF - self.read(Offset::TimerCurrentCount)
+    }
+
+    pub fn start_timer_ticks(&mut self, vector: u8, ticks: u32, periodic: bool) {
+        self.stop_timer();
+        self.write(
+            Offset::TimerLocalVectorTableEntry,
+            vector as u32
+                | if periodic {
+                    TIMER_PERIODIC
+                } else {
+                    TIMER_ONE_SHOT
+                },
+        );
+        self.write(Offset::TimerDivideConfiguration, TIMER_DIVIDE_BY_16);
+        self.write(Offset::TimerInitialCount, ticks);
+    }
+
+    pub fn stop_timer(&mut self) {
+        self.write(Offset::TimerLocalVectorTableEntry, TIMER_MASKED);
+    }
+
+    // The timer fires once when the time stamp counter reaches the deadline written with set_tsc_deadline.
+    // In xAPIC mode the deadline write has to be fenced after this one (mfence), since wrmsr does not serialize mmio
+    pub fn start_tsc_deadline_timer(&mut self, vector: u8) {
+        self.write(
+            Offset::TimerLocalVectorTableEntry,
+            vector as u32 | TIMER_TSC_DEADLINE,
+        );
+    }
+}
+
+// arms the tsc deadline timer of the executing core (0 disarms it), the deadline is in tsc ticks
+pub fn set_tsc_deadline(msr: &impl Msr, deadline: u64) {
+    msr.write_msr(IA32_TSC_DEADLINE, deadline);
+}
+
+pub mod ipi {
+    use bitfield::bitfield;
+
+    pub fn create_send_init_cmd() -> InterruptCommand {
+        let mut ic = InterruptCommand(0);
+        ic.set_interupt_vector(0);
+        ic.set_delivery_mode(5);
+        ic.set_destination_mode_logical(false);
+        ic.set_de_assert(false);
+        ic.set_not_de_assert(true);
+        ic.set_destination_type(3);
+        // ic.set_destination_type(0);
+        // ic.set_apic_id(apic_id as u64);
+        ic
+    }
+
+    pub fn create_startup_cmd(vector: u8) -> InterruptCommand {
+        let mut ic = InterruptCommand(0);
+        ic.set_interupt_vector(vector as u64);
+        ic.set_delivery_mode(6);
+        ic.set_destination_mode_logical(false);
+        ic.set_de_assert(false);
+        ic.set_not_de_assert(true);
+        ic.set_destination_type(3);
+        // ic.set_destination_type(0);
+        // ic.set_apic_id(apic_id as u64);
+        ic
+    }
+
+    // init of the core in the destination field
+    pub fn create_init_cmd() -> InterruptCommand {
+        let mut ic = create_send_init_cmd();
+        ic.set_destination_type(0);
+        ic
+    }
+
+    // startup of the core in the destination field
+    pub fn create_targeted_startup_cmd(vector: u8) -> InterruptCommand {
+        let mut ic = create_startup_cmd(vector);
+        ic.set_destination_type(0);
+        ic
+    }
+
+    // fixed delivery of vector to all cores except the sending one
+    pub fn create_broadcast_cmd(vector: u8) -> InterruptCommand {
+        let mut ic = InterruptCommand(0);
+        ic.set_interupt_vector(vector as u64);
+        ic.set_delivery_mode(0);
+        ic.set_destination_mode_logical(false);
+        ic.set_de_assert(false);
+        ic.set_not_de_assert(true);
+        ic.set_destination_type(3);
+        ic
+    }
+
+    // fixed delivery of vector to the core in the destination field (see ApicRegisters::destination)
+    pub fn create_fixed_cmd(vector: u8) -> InterruptCommand {
+        let mut ic = InterruptCommand(0);
+        ic.set_interupt_vector(vector as u64);
+        ic.set_delivery_mode(0);
+        ic.set_destination_mode_logical(false);
+        ic.set_de_assert(false);
+        ic.set_not_de_assert(true);
+        ic.set_destination_type(0);
+        ic
+    }
+
+    // non maskable interrupt to all cores except the sending one (the vector is ignored)
+    pub fn create_nmi_broadcast_cmd() -> InterruptCommand {
+        let mut ic = InterruptCommand(0);
+        ic.set_delivery_mode(4);
+        ic.set_destination_mode_logical(false);
+        ic.set_de_assert(false);
+        ic.set_not_de_assert(true);
+        ic.set_destination_type(3);
+        ic
+    }
+
+    // fixed delivery of vector to the sending core
+    pub fn create_self_cmd(vector: u8) -> InterruptCommand {
+        let mut ic = InterruptCommand(0);
+        ic.set_interupt_vector(vector as u64);
+        ic.set_delivery_mode(0);
+        ic.set_destination_mode_logical(false);
+        ic.set_de_assert(false);
+        ic.set_not_de_assert(true);
+        ic.set_destination_type(1);
+        ic
+    }
+
+    bitfield! {
+        #[derive(Clone, Copy)]
+        pub struct InterruptCommand(u64);
+        impl Debug;
+        pub interupt_vector, set_interupt_vector: 7, 0;
+        pub delivery_mode, set_delivery_mode: 10, 8;
+        pub destination_mode_logical, set_destination_mode_logical: 11;
+        pub delivery_status, _: 12;
+        pub not_de_assert, set_not_de_assert: 14;
+        pub de_assert, set_de_assert: 15;
+        pub destination_type, set_destination_type: 19, 18;
+        pub apic_id, set_apic_id: (32+27), (32+24);
+        pub lower, set_lower : 31, 0;
+        pub upper, set_upper : 63, 32;
+    }
+}
+
+#[repr(usize)]
+pub enum Offset {
+    Id = 0x20,
+    Version = 0x30,
+    TaskPriority = 0x80,
+    ArbitrationPriority = 0x90,
+    ProcessorPriority = 0xa0,
+    EndOfInterrupt = 0xb0,
+    RemoteRead = 0xc0,
+    LocalDestination = 0xd0,
+    DestinationFormat = 0xe0,
+    SpuriousInterruptVector = 0xf0,
+    InService = 0x100,
+    TriggerMode = 0x180,
+    InterruptRequest = 0x200,
+    ErrorStatus = 0x280,
+    InterruptCommandLow = 0x300,
+    InterruptCommandHigh = 0x310,
+    TimerLocalVectorTableEntry = 0x320,
+    ThermalLocalVectorTableEntry = 0x330,
+    PerformanceCounterLocalVectorTableEntry = 0x340,
+    LocalInterrupt0VectorTableEntry = 0x350,
+    LocalInterrupt1VectorTableEntry = 0x360,
+    ErrorVectorTableEntry = 0x370,
+    TimerInitialCount = 0x380,
+    TimerCurrentCount = 0x390,
+    TimerDivideConfiguration = 0x3e0,
+    ExtendedApicFeature = 0x400,
+    ExtendedApicControl = 0x410,
+    SpecificEndOfInterrupt = 0x420,
+    InterruptEnable = 0x480,
+    ExtendedInterruptLocalVectorTable = 0x500,
+}
+
+#[cfg(test)]
+mod tests {
+    use port_io::mock::{MockMmio, MockMsr};
+
+    use super::*;
+
+    #[test]
+    fn id_is_read_from_upper_byte() {
+        let mmio = MockMmio::new();
+        mmio.set(Offset::Id as usize, 3 << 24);
+        assert_eq!(LocalApic::new(XApic(&mmio)).id(), 3);
+    }
+
+    #[test]
+    fn interrupt_command_writes_high_before_low() {
+        let mmio = MockMmio::new();
+        let mut apic = LocalApic::new(XApic(&mmio));
+
+        apic.write_interrupt_command(ipi::create_startup_cmd(8));
+
+        assert_eq!(
+            mmio.write_order(),
+            [
+                Offset::InterruptCommandHigh as usize,
+                Offset::InterruptCommandLow as usize
+            ]
+        );
+        let low = mmio.value(Offset::InterruptCommandLow as usize);
+        assert_eq!(low & 0xff, 8);
+        assert_eq!((low >> 8) & 0b111, 6);
+        assert_eq!((low >> 18) & 0b11, 3);
+    }
+
+    #[test]
+    fn broadcast_command_uses_fixed_delivery_to_all_but_self() {
+        let cmd = ipi::create_broadcast_cmd(0xF0);
+        assert_eq!(cmd.interupt_vector(), 0xF0);
+        assert_eq!(cmd.delivery_mode(), 0);
+        assert_eq!(cmd.destination_type(), 3);
+        assert_eq!(cmd.upper(), 0);
+    }
+
+    #[test]
+    fn nmi_broadcast_uses_nmi_delivery_to_all_but_self() {
+        let cmd = ipi::create_nmi_broadcast_cmd();
+        assert_eq!(cmd.delivery_mode(), 4);
+        assert_eq!(cmd.destination_type(), 3);
+        assert_eq!(cmd.upper(), 0);
+    }
+
+    #[test]
+    fn ipis_are_sent_to_the_destination_id() {
+        let mmio = MockMmio::new();
+        let mut apic = LocalApic::new(XApic(&mmio));
+        apic.send_ipi(0xF0, 3);
+        assert_eq!(
+            mmio.write_order(),
+            [
+                Offset::InterruptCommandHigh as usize,
+                Offset::InterruptCommandLow as usize
+            ]
+        );
+        assert_eq!(mmio.value(Offset::InterruptCommandHigh as usize), 3 << 24);
+        let low = ipi::InterruptCommand(mmio.value(Offset::InterruptCommandLow as usize) as u64);
+        assert_eq!(low.interupt_vector(), 0xF0);
+        assert_eq!(low.delivery_mode(), 0);
+        assert_eq!(low.destination_type(), 0);
+
+        // x2APIC ids use the whole upper half
+        let msr = MockMsr::new();
+        let mut apic = LocalApic::new(X2Apic(&msr));
+        apic.send_ipi(0xF0, 300);
+        assert_eq!(
+            msr.writes(),
+            [(0x830, ipi::create_fixed_cmd(0xF0).0 | 300 << 32)]
+        );
+    }
+
+    #[test]
+    fn init_and_startup_are_sent_to_one_core() {
+        let msr = MockMsr::new();
+        let mut apic = LocalApic::new(X2Apic(&msr));
+        apic.send_init(5);
+        apic.send_startup(0, 5);
+        let writes = msr.writes();
+        let init = ipi::InterruptCommand(writes[0].1);
+        let startup = ipi::InterruptCommand(writes[1].1);
+        assert_eq!(init.delivery_mode(), 5);
+        assert_eq!(startup.delivery_mode(), 6);
+        for cmd in [init, startup] {
+            assert_eq!(cmd.destination_type(), 0);
+            assert_eq!(cmd.upper(), 5);
+        }
+    }
+
+    #[test]
+    fn measure_timer_ticks_returns_elapsed_count() {
+        let mmio = MockMmio::new();
+        let mut apic = LocalApic::new(XApic(&mmio));
+        let mut waited = false;
+        mmio.set(Offset::TimerCurrentCount as usize, 0xFFFF_FFFF - 1234);
+
+        let ticks = apic.measure_timer_ticks(|| waited = true);
+
+        assert!(waited);
+        assert_eq!(ticks, 1234);
+        assert_eq!(
+            mmio.value(Offset::TimerLocalVectorTableEntry as usize),
+            TIMER_MASKED
+        );
+    }
+
+    #[test]
+    fn start_timer_programs_mode_and_count() {
+        let mmio = MockMmio::new();
+        let mut apic = LocalApic::new(XApic(&mmio));
+
+        apic.start_timer_ticks(32, 1000, true);
+        assert_eq!(
+            mmio.writes_to(Offset::TimerLocalVectorTableEntry as usize),
+            [TIMER_MASKED, 32 | TIMER_PERIODIC]
+        );
+        assert_eq!(mmio.value(Offset::TimerInitialCount as usize), 1000);
+
+        apic.start_timer_ticks(33, 5, false);
+        assert_eq!(
+            mmio.value(Offset::TimerLocalVectorTableEntry as usize),
+            33 | TIMER_ONE_SHOT
+        );
+    }
+
+    #[test]
+    fn x2apic_uses_msrs() {
+        let msr = MockMsr::new();
+        msr.set(0x802, 300);
+        let mut apic = LocalApic::new(X2Apic(&msr));
+
+        assert_eq!(apic.id(), 300);
+        apic.signal_end_of_interrupt();
+        apic.start_timer_ticks(32, 1000, true);
+        assert_eq!(msr.value(0x80B), 0);
+        assert_eq!(msr.value(0x832), 32 | TIMER_PERIODIC as u64);
+        assert_eq!(msr.value(0x838), 1000);
+    }
+
+    #[test]
+    fn x2apic_interrupt_command_is_one_write() {
+        let msr = MockMsr::new();
+        let mut apic = LocalApic::new(X2Apic(&msr));
+        let mut cmd = ipi::create_startup_cmd(8);
+        cmd.set_upper(300);
+
+        apic.write_interrupt_command(cmd);
+
+        assert_eq!(msr.writes(), [(0x830, cmd.0)]);
+        assert_eq!(apic.read_interrupt_command().upper(), 300);
+    }
+
+    #[test]
+    fn x2apic_mode_is_enabled_in_apic_base() {
+        let msr = MockMsr::new();
+        msr.set(IA32_APIC_BASE, 0xFEE0_0000 | APIC_BASE_ENABLE);
+        let x2apic = X2Apic(&msr);
+        assert!(!x2apic.is_mode_enabled());
+
+        x2apic.enable_mode();
+
+        assert!(x2apic.is_mode_enabled());
+        assert_eq!(
+            msr.value(IA32_APIC_BASE),
+            0xFEE0_0000 | APIC_BASE_ENABLE | APIC_BASE_X2APIC
+        );
+    }
+
+    #[test]
+    fn tsc_deadline_timer_is_armed_through_the_msr() {
+        let mmio = MockMmio::new();
+        let msr = MockMsr::new();
+        let mut apic = LocalApic::new(XApic(&mmio));
+
+        apic.start_tsc_deadline_timer(32);
+        set_tsc_deadline(&msr, 123_456);
+
+        assert_eq!(
+            mmio.value(Offset::TimerLocalVectorTableEntry as usize),
+            32 | 0b10 << 17
+        );
+        assert_eq!(msr.writes(), [(IA32_TSC_DEADLINE, 123_456)]);
+    }
+}
@@ -0,0 +1,593 @@
+#![no_std]
+
+pub mod msi;
+
+use port_io::{Mmio32, PortIo, X86PortIo};
+
+// configuration space access mechanism #1
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+pub const CONFIG_SPACE_SIZE: u16 = 0x100;
+pub const EXTENDED_CONFIG_SPACE_SIZE: u16 = 0x1000;
+
+// Header registers (dword offsets)
+const ID: u8 = 0x00;
+const COMMAND_STATUS: u8 = 0x04;
+const CLASS: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0C;
+const BAR0: u8 = 0x10;
+const CAPABILITIES_POINTER: u8 = 0x34;
+// the pci express extended capability list starts right after the legacy configuration space
+const EXTENDED_CAPABILITIES: u16 = 0x100;
+
+const COMMAND_MEMORY_SPACE: u32 = 1 << 1;
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+const COMMAND_INTX_DISABLE: u32 = 1 << 10;
+const STATUS_CAPABILITY_LIST: u32 = 1 << (16 + 4);
+const HEADER_MULTI_FUNCTION: u32 = 1 << (16 + 7);
+const BAR_IO_SPACE: u32 = 1 << 0;
+const BAR_64BIT: u32 = 0b10 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+// Access to the configuration space of every function (offsets have to be dword aligned).
+// Offsets past size read as all ones and writes to them are ignored.
+// Implementations have to do their own locking
+pub trait ConfigSpace {
+    fn read(&self, address: PciAddress, offset: u16) -> u32;
+    fn write(&self, address: PciAddress, offset: u16, value: u32);
+
+    // 256 bytes, or 4096 bytes with access to the pci express extended configuration space
+    fn size(&self) -> u16 {
+        CONFIG_SPACE_SIZE
+    }
+}
+
+pub struct PortConfigSpace<P: PortIo = X86PortIo> {
+    io: P,
+}
+
+impl PortConfigSpace {
+    pub const fn new() -> Self {
+        Self::with_io(X86PortIo)
+    }
+}
+
+impl Default for PortConfigSpace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: PortIo> PortConfigSpace<P> {
+    pub const fn with_io(io: P) -> Self {
+        Self { io }
+    }
+
+    fn select(&self, address: PciAddress, offset: u8) {
+        let value = CONFIG_ENABLE
+            | (address.bus as u32) << 16
+            | (address.device as u32 & 0x1F) << 11
+            | (address.function as u32 & 0x7) << 8
+            | (offset & 0xFC) as u32;
+        self.io.write_u32(CONFIG_ADDRESS, value);
+    }
+}
+
+// the ports only reach the first 256 bytes
+impl<P: PortIo> ConfigSpace for PortConfigSpace<P> {
+    fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        let Ok(offset) = u8::try_from(offset) else {
+            return u32::MAX;
+        };
+        self.select(address, offset);
+        self.io.read_u32(CONFIG_DATA)
+    }
+
+    fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        let Ok(offset) = u8::try_from(offset) else {
+            return;
+        };
+        self.select(address, offset);
+        self.io.write_u32(CONFIG_DATA, value);
+    }
+}
+
+// Enhanced configuration access mechanism (pci express): the configuration space of the buses start_bus..=end_bus
+// of a segment group is memory mapped, 4 KiB per function.
+// Every access is a single dword read or write, so no locking is needed
+pub struct EcamConfigSpace<M: Mmio32> {
+    mmio: M,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+impl<M: Mmio32> EcamConfigSpace<M> {
+    // mmio has to cover (end_bus - start_bus + 1) MiB
+    pub const fn new(mmio: M, start_bus: u8, end_bus: u8) -> Self {
+        Self {
+            mmio,
+            start_bus,
+            end_bus,
+        }
+    }
+
+    pub const fn buses(&self) -> (u8, u8) {
+        (self.start_bus, self.end_bus)
+    }
+
+    // None for buses outside of the mapped range and offsets past the configuration space
+    fn mmio_offset(&self, address: PciAddress, offset: u16) -> Option<usize> {
+        if !(self.start_bus..=self.end_bus).contains(&address.bus)
+            || offset >= EXTENDED_CONFIG_SPACE_SIZE
+        {
+            return None;
+        }
+        Some(
+            ((address.bus - self.start_bus) as usize) << 20
+                | (address.device as usize & 0x1F) << 15
+                | (address.function as usize & 0x7) << 12
+                | (offset & 0xFFC) as usize,
+        )
+    }
+}
+
+impl<M: Mmio32> ConfigSpace for EcamConfigSpace<M> {
+    fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        self.mmio_offset(address, offset)
+            .map_or(u32::MAX, |offset| self.mmio.read(offset))
+    }
+
+    fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        if let Some(offset) = self.mmio_offset(address, offset) {
+            self.mmio.write(offset, value);
+        }
+    }
+
+    fn size(&self) -> u16 {
+        EXTENDED_CONFIG_SPACE_SIZE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory { address: u64, prefetchable: bool },
+    Io { port: u16 },
+}
+
+// A function on the bus
+pub struct Device<'a, C: ConfigSpace> {
+    config: &'a C,
+    pub address: PciAddress,
+}
+
+impl<'a, C: ConfigSpace> Device<'a, C> {
+    pub const fn new(config: &'a C, address: PciAddress) -> Self {
+        Self { config, address }
+    }
+
+    pub fn read(&self, offset: u8) -> u32 {
+        self.config.read(self.address, offset.into())
+    }
+
+    pub fn write(&self, offset: u8, value: u32) {
+        self.config.write(self.address, offset.into(), value);
+    }
+
+    // None if the configuration space access does not reach offset (port access or not pci express)
+    pub fn read_extended(&self, offset: u16) -> Option<u32> {
+        (offset < self.config.size()).then(|| self.config.read(self.address, offset))
+    }
+
+    // returns false if the configuration space access does not reach offset
+    pub fn write_extended(&self, offset: u16, value: u32) -> bool {
+        let reachable = offset < self.config.size();
+        if reachable {
+            self.config.write(self.address, offset, value);
+        }
+        reachable
+    }
+
+    // functions that are not present read as all ones
+    pub fn exists(&self) -> bool {
+        self.vendor_id() != 0xFFFF
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(ID) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(ID) >> 16) as u16
+    }
+
+    // (class, subclass, programming interface)
+    pub fn class(&self) -> (u8, u8, u8) {
+        let value = self.read(CLASS);
+        ((value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8)
+    }
+
+    pub fn is_multi_function(&self) -> bool {
+        self.read(HEADER_TYPE) & HEADER_MULTI_FUNCTION != 0
+    }
+
+    // the status bits are write one to clear, so only the command half is written back
+    fn update_command(&self, update: impl FnOnce(u32) -> u32) {
+        let command = self.read(COMMAND_STATUS) & 0xFFFF;
+        self.write(COMMAND_STATUS, update(command) & 0xFFFF);
+    }
+
+    // stops legacy interrupts (the function uses msi or msi-x instead)
+    pub fn disable_intx(&self) {
+        self.update_command(|command| command | COMMAND_INTX_DISABLE);
+    }
+
+    // lets the function decode its memory bars and start dma
+    pub fn enable_bus_master(&self) {
+        self.update_command(|command| command | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER);
+    }
+
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        if index > 5 {
+            return None;
+        }
+        let offset = BAR0 + index * 4;
+        let low = self.read(offset);
+        if low & BAR_IO_SPACE != 0 {
+            return Some(Bar::Io {
+                port: (low & !0b11) as u16,
+            });
+        }
+        let high = if low & BAR_64BIT != 0 && index < 5 {
+            self.read(offset + 4)
+        } else {
+            0
+        };
+        Some(Bar::Memory {
+            address: (high as u64) << 32 | (low & !0xF) as u64,
+            prefetchable: low & (1 << 3) != 0,
+        })
+    }
+
+    // (capability id, offset of the capability) of every entry in the capability list
+    pub fn capabilities(&self) -> Capabilities<'_, 'a, C> {
+        let next = if self.read(COMMAND_STATUS) & STATUS_CAPABILITY_LIST != 0 {
+            self.read(CAPABILITIES_POINTER) as u8 & 0xFC
+        } else {
+            0
+        };
+        Capabilities {
+            device: self,
+            next,
+            remaining: 48,
+        }
+    }
+
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities()
+            .find(|&(cap_id, _)| cap_id == id)
+            .map(|(_, offset)| offset)
+    }
+
+    // (extended capability id, offset of the capability) of every entry in the pci express extended capability list,
+    // empty without access to the extended configuration space
+    pub fn extended_capabilities(&self) -> ExtendedCapabilities<'_, 'a, C> {
+        ExtendedCapabilities {
+            device: self,
+            next: EXTENDED_CAPABILITIES,
+            remaining: (EXTENDED_CONFIG_SPACE_SIZE - EXTENDED_CAPABILITIES) / 4,
+        }
+    }
+
+    pub fn find_extended_capability(&self, id: u16) -> Option<u16> {
+        self.extended_capabilities()
+            .find(|&(cap_id, _)| cap_id == id)
+            .map(|(_, offset)| offset)
+    }
+}
+
+pub struct Capabilities<'d, 'a, C: ConfigSpace> {
+    device: &'d Device<'a, C>,
+    next: u8,
+    // bounds broken (circular) lists
+    remaining: u8,
+}
+
+impl<'d, 'a, C: ConfigSpace> Iterator for Capabilities<'d, 'a, C> {
+    type Item = (u8, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == 0 || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        let header = self.device.read(offset);
+        self.next = (header >> 8) as u8 & 0xFC;
+        Some((header as u8, offset))
+    }
+}
+
+pub struct ExtendedCapabilities<'d, 'a, C: ConfigSpace> {
+    device: &'d Device<'a, C>,
+    next: u16,
+    // bounds broken (circular) lists
+    remaining: u16,
+}
+
+impl<'d, 'a, C: ConfigSpace> Iterator for ExtendedCapabilities<'d, 'a, C> {
+    type Item = (u16, u16);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next < EXTENDED_CAPABILITIES || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        // an empty list has a zero header, functions without extended space read as all ones
+        let header = self.device.read_extended(offset)?;
+        if header == 0 || header == u32::MAX {
+            return None;
+        }
+        self.next = (header >> 20) as u16 & 0xFFC;
+        Some((header as u16, offset))
+    }
+}
+
+// Brute force scan of all buses, returns the address of every present function
+pub fn enumerate<C: ConfigSpace>(config: &C) -> impl Iterator<Item = PciAddress> + '_ {
+    (0..=255u8)
+        .flat_map(|bus| (0..32u8).map(move |device| (bus, device)))
+        .flat_map(move |(bus, device)| {
+            let first = PciAddress {
+                bus,
+                device,
+                function: 0,
+            };
+            let functions = match Device::new(config, first) {
+                d if !d.exists() => 0,
+                d if d.is_multi_function() => 8,
+                _ => 1,
+            };
+            (0..functions).map(move |function| PciAddress {
+                bus,
+                device,
+                function,
+            })
+        })
+        .filter(move |&address| Device::new(config, address).exists())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use core::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    use port_io::mock::{MockMmio, MockPortIo};
+
+    use super::*;
+
+    // configuration space of any number of functions, absent ones read as all ones
+    #[derive(Default)]
+    pub(crate) struct MockConfigSpace {
+        pub registers: RefCell<BTreeMap<(PciAddress, u16), u32>>,
+        // pci express extended configuration space
+        pub extended: bool,
+    }
+
+    impl MockConfigSpace {
+        pub(crate) fn set(&self, address: PciAddress, offset: u8, value: u32) {
+            self.set_extended(address, offset.into(), value);
+        }
+
+        pub(crate) fn set_extended(&self, address: PciAddress, offset: u16, value: u32) {
+            self.registers.borrow_mut().insert((address, offset), value);
+        }
+
+        pub(crate) fn get(&self, address: PciAddress, offset: u8) -> u32 {
+            self.read(address, offset.into())
+        }
+    }
+
+    impl ConfigSpace for MockConfigSpace {
+        fn read(&self, address: PciAddress, offset: u16) -> u32 {
+            let registers = self.registers.borrow();
+            if !registers.contains_key(&(address, ID.into())) || offset >= self.size() {
+                return u32::MAX;
+            }
+            registers.get(&(address, offset)).copied().unwrap_or(0)
+        }
+
+        fn write(&self, address: PciAddress, offset: u16, value: u32) {
+            if offset < self.size() {
+                self.set_extended(address, offset, value);
+            }
+        }
+
+        fn size(&self) -> u16 {
+            if self.extended {
+                EXTENDED_CONFIG_SPACE_SIZE
+            } else {
+                CONFIG_SPACE_SIZE
+            }
+        }
+    }
+
+    pub(crate) const DEVICE: PciAddress = PciAddress {
+        bus: 0,
+        device: 3,
+        function: 0,
+    };
+
+    #[test]
+    fn port_access_selects_the_register() {
+        let io = MockPortIo::new();
+        io.set(CONFIG_DATA, 0x1234_8086);
+        let config = PortConfigSpace::with_io(&io);
+        let address = PciAddress {
+            bus: 1,
+            device: 2,
+            function: 3,
+        };
+
+        assert_eq!(config.read(address, 0x3F), 0x1234_8086);
+        assert_eq!(
+            io.writes_to(CONFIG_ADDRESS),
+            [CONFIG_ENABLE | 1 << 16 | 2 << 11 | 3 << 8 | 0x3C]
+        );
+
+        // the extended configuration space is not reachable through the ports
+        io.clear_log();
+        assert_eq!(config.read(address, 0x100), u32::MAX);
+        config.write(address, 0x100, 0);
+        assert!(io.accesses().is_empty());
+    }
+
+    #[test]
+    fn ecam_access_maps_the_function() {
+        let mmio = MockMmio::new();
+        let config = EcamConfigSpace::new(&mmio, 1, 2);
+        let address = PciAddress {
+            bus: 2,
+            device: 3,
+            function: 4,
+        };
+        let register = 1 << 20 | 3 << 15 | 4 << 12 | 0x104;
+        mmio.set(register, 0x1234_5678);
+
+        assert_eq!(config.size(), 0x1000);
+        assert_eq!(config.read(address, 0x107), 0x1234_5678);
+        config.write(address, 0x104, 0xABCD);
+        assert_eq!(mmio.writes_to(register), [0xABCD]);
+
+        // buses outside of the range are not present
+        let outside = PciAddress { bus: 0, ..address };
+        assert_eq!(config.read(outside, 0), u32::MAX);
+        config.write(outside, 0, 0);
+        assert_eq!(config.read(address, 0x1000), u32::MAX);
+        assert_eq!(mmio.write_order(), [register]);
+    }
+
+    #[test]
+    fn extended_capability_list_is_followed() {
+        let config = MockConfigSpace {
+            extended: true,
+            ..Default::default()
+        };
+        config.set(DEVICE, ID, 0x1000_8086);
+        // advanced error reporting and then device serial number
+        config.set_extended(DEVICE, 0x100, 0x140 << 20 | 1 << 16 | 0x0001);
+        config.set_extended(DEVICE, 0x140, 1 << 16 | 0x0003);
+        let device = Device::new(&config, DEVICE);
+
+        assert_eq!(device.read_extended(0x140), Some(1 << 16 | 0x0003));
+        assert_eq!(
+            device.extended_capabilities().collect::<std::vec::Vec<_>>(),
+            [(0x0001, 0x100), (0x0003, 0x140)]
+        );
+        assert_eq!(device.find_extended_capability(0x0003), Some(0x140));
+
+        // a list pointing to itself ends eventually
+        config.set_extended(DEVICE, 0x140, 0x100 << 20 | 1 << 16 | 0x0003);
+        assert_eq!(device.extended_capabilities().count(), 960);
+
+        // without extended configuration space access
+        let config = MockConfigSpace::default();
+        config.set(DEVICE, ID, 0x1000_8086);
+        let device = Device::new(&config, DEVICE);
+        assert_eq!(device.read_extended(0x100), None);
+        assert!(!device.write_extended(0x100, 0));
+        assert_eq!(device.extended_capabilities().count(), 0);
+    }
+
+    #[test]
+    fn enumeration_finds_functions_of_multi_function_devices() {
+        let config = MockConfigSpace::default();
+        let second_function = PciAddress {
+            function: 2,
+            ..DEVICE
+        };
+        config.set(DEVICE, ID, 0x1000_8086);
+        config.set(DEVICE, HEADER_TYPE, HEADER_MULTI_FUNCTION);
+        config.set(second_function, ID, 0x1001_8086);
+        let single = PciAddress {
+            bus: 2,
+            device: 0,
+            function: 0,
+        };
+        config.set(single, ID, 0x1111_1AF4);
+
+        let found: std::vec::Vec<_> = enumerate(&config).collect();
+        assert_eq!(found, [DEVICE, second_function, single]);
+        assert_eq!(Device::new(&config, single).device_id(), 0x1111);
+    }
+
+    #[test]
+    fn capability_list_is_followed() {
+        let config = MockConfigSpace::default();
+        config.set(DEVICE, ID, 0x1000_8086);
+        config.set(DEVICE, COMMAND_STATUS, STATUS_CAPABILITY_LIST);
+        config.set(DEVICE, CAPABILITIES_POINTER, 0x40);
+        config.set(DEVICE, 0x40, 0x50 << 8 | 0x09);
+        config.set(DEVICE, 0x50, 0x05);
+        let device = Device::new(&config, DEVICE);
+
+        assert_eq!(
+            device.capabilities().collect::<std::vec::Vec<_>>(),
+            [(0x09, 0x40), (0x05, 0x50)]
+        );
+        assert_eq!(device.find_capability(0x05), Some(0x50));
+        assert_eq!(device.find_capability(0x11), None);
+
+        // a list pointing to itself ends eventually
+        config.set(DEVICE, 0x50, 0x40 << 8 | 0x05);
+        assert_eq!(device.capabilities().count(), 48);
+    }
+
+    #[test]
+    fn bars_are_decoded() {
+        let config = MockConfigSpace::default();
+        config.set(DEVICE, ID, 0x1000_8086);
+        config.set(DEVICE, BAR0, 0xFEB0_000C);
+        config.set(DEVICE, BAR0 + 4, 0x1);
+        config.set(DEVICE, BAR0 + 8, 0xC001);
+        let device = Device::new(&config, DEVICE);
+
+        assert_eq!(
+            device.bar(0),
+            Some(Bar::Memory {
+                address: 0x1_FEB0_0000,
+                prefetchable: true
+            })
+        );
+        assert_eq!(device.bar(2), Some(Bar::Io { port: 0xC000 }));
+        assert_eq!(device.bar(6), None);
+    }
+
+    #[test]
+    fn intx_disable_keeps_status_bits() {
+        let config = MockConfigSpace::default();
+        config.set(DEVICE, ID, 0x1000_8086);
+        config.set(DEVICE, COMMAND_STATUS, 0xFFFF_0006);
+        Device::new(&config, DEVICE).disable_intx();
+        assert_eq!(config.get(DEVICE, COMMAND_STATUS), 0x0406);
+    }
+
+    #[test]
+    fn bus_master_enable_keeps_status_bits() {
+        let config = MockConfigSpace::default();
+        config.set(DEVICE, ID, 0x1000_8086);
+        config.set(DEVICE, COMMAND_STATUS, 0xFFFF_0400);
+        Device::new(&config, DEVICE).enable_bus_master();
+        assert_eq!(config.get(DEVICE, COMMAND_STATUS), 0x0406);
+    }
+}
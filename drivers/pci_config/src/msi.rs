@@ -0,0 +1,163 @@
+use port_io::Mmio32;
+
+use crate::{ConfigSpace, Device};
+
+pub const CAPABILITY_MSI: u8 = 0x05;
+pub const CAPABILITY_MSIX: u8 = 0x11;
+
+// Message control (upper half of the first dword of both capabilities)
+const MSI_ENABLE: u32 = 1 << 16;
+const MSI_MULTIPLE_MESSAGE_ENABLE: u32 = 0b111 << (16 + 4);
+const MSI_64BIT: u32 = 1 << (16 + 7);
+const MSIX_TABLE_SIZE: u32 = 0x7FF << 16;
+const MSIX_FUNCTION_MASK: u32 = 1 << (16 + 14);
+const MSIX_ENABLE: u32 = 1 << (16 + 15);
+
+// MSI-X table entries (in the memory of a bar)
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_ENTRY_ADDRESS_LOW: usize = 0x0;
+const MSIX_ENTRY_ADDRESS_HIGH: usize = 0x4;
+const MSIX_ENTRY_DATA: usize = 0x8;
+const MSIX_ENTRY_CONTROL: usize = 0xC;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+// The device signals an interrupt by writing data to address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+// Programs the msi capability at offset with a single message and enables it
+pub fn enable_msi<C: ConfigSpace>(device: &Device<C>, offset: u8, message: MsiMessage) {
+    let header = device.read(offset);
+    // disabled while the message is changed
+    device.write(offset, header & !(MSI_ENABLE | MSI_MULTIPLE_MESSAGE_ENABLE));
+    device.write(offset + 0x4, message.address as u32);
+    if header & MSI_64BIT != 0 {
+        device.write(offset + 0x8, (message.address >> 32) as u32);
+        device.write(offset + 0xC, message.data);
+    } else {
+        device.write(offset + 0x8, message.data);
+    }
+    device.write(offset, (header & !MSI_MULTIPLE_MESSAGE_ENABLE) | MSI_ENABLE);
+}
+
+pub fn disable_msi<C: ConfigSpace>(device: &Device<C>, offset: u8) {
+    device.write(offset, device.read(offset) & !MSI_ENABLE);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsixInfo {
+    pub table_size: u16,
+    pub table_bar: u8,
+    // byte offset of the table in the bar
+    pub table_offset: u32,
+}
+
+impl MsixInfo {
+    pub fn read<C: ConfigSpace>(device: &Device<C>, offset: u8) -> Self {
+        let header = device.read(offset);
+        let table = device.read(offset + 0x4);
+        Self {
+            table_size: ((header & MSIX_TABLE_SIZE) >> 16) as u16 + 1,
+            table_bar: (table & 0b111) as u8,
+            table_offset: table & !0b111,
+        }
+    }
+}
+
+// Writes one entry of the msi-x table, table is the mapped table (not the start of the bar)
+pub fn write_msix_entry(table: &impl Mmio32, index: u16, message: MsiMessage, masked: bool) {
+    let entry = index as usize * MSIX_ENTRY_SIZE;
+    table.write(entry + MSIX_ENTRY_CONTROL, MSIX_ENTRY_MASKED);
+    table.write(entry + MSIX_ENTRY_ADDRESS_LOW, message.address as u32);
+    table.write(
+        entry + MSIX_ENTRY_ADDRESS_HIGH,
+        (message.address >> 32) as u32,
+    );
+    table.write(entry + MSIX_ENTRY_DATA, message.data);
+    if !masked {
+        table.write(entry + MSIX_ENTRY_CONTROL, 0);
+    }
+}
+
+pub fn set_msix_entry_masked(table: &impl Mmio32, index: u16, masked: bool) {
+    let control = index as usize * MSIX_ENTRY_SIZE + MSIX_ENTRY_CONTROL;
+    table.write(control, if masked { MSIX_ENTRY_MASKED } else { 0 });
+}
+
+// Enables msi-x for the function, entries are delivered once they are unmasked
+pub fn enable_msix<C: ConfigSpace>(device: &Device<C>, offset: u8) {
+    let header = device.read(offset);
+    device.write(offset, (header & !MSIX_FUNCTION_MASK) | MSIX_ENABLE);
+}
+
+pub fn disable_msix<C: ConfigSpace>(device: &Device<C>, offset: u8) {
+    device.write(offset, device.read(offset) & !MSIX_ENABLE);
+}
+
+#[cfg(test)]
+mod tests {
+    use port_io::mock::MockMmio;
+
+    use super::*;
+    use crate::tests::{MockConfigSpace, DEVICE};
+
+    const MESSAGE: MsiMessage = MsiMessage {
+        address: 0xFEE0_1000,
+        data: 0x41,
+    };
+
+    #[test]
+    fn msi_is_programmed_for_32_and_64_bit_capabilities() {
+        let config = MockConfigSpace::default();
+        config.set(DEVICE, 0x00, 0x1000_8086);
+        config.set(DEVICE, 0x50, 0x0002_0005 | MSI_MULTIPLE_MESSAGE_ENABLE);
+        let device = Device::new(&config, DEVICE);
+
+        enable_msi(&device, 0x50, MESSAGE);
+        assert_eq!(config.get(DEVICE, 0x54), 0xFEE0_1000);
+        assert_eq!(config.get(DEVICE, 0x58), 0x41);
+        assert_eq!(config.get(DEVICE, 0x50), 0x0002_0005 | MSI_ENABLE);
+
+        config.set(DEVICE, 0x50, 0x0002_0005 | MSI_64BIT);
+        enable_msi(&device, 0x50, MESSAGE);
+        assert_eq!(config.get(DEVICE, 0x58), 0);
+        assert_eq!(config.get(DEVICE, 0x5C), 0x41);
+
+        disable_msi(&device, 0x50);
+        assert_eq!(config.get(DEVICE, 0x50) & MSI_ENABLE, 0);
+    }
+
+    #[test]
+    fn msix_table_entries_are_written_masked_first() {
+        let config = MockConfigSpace::default();
+        config.set(DEVICE, 0x00, 0x1000_8086);
+        config.set(DEVICE, 0x70, 0x0002_0011 | MSIX_FUNCTION_MASK);
+        config.set(DEVICE, 0x74, 0x2000 | 4);
+        let device = Device::new(&config, DEVICE);
+
+        let info = MsixInfo::read(&device, 0x70);
+        assert_eq!(
+            info,
+            MsixInfo {
+                table_size: 3,
+                table_bar: 4,
+                table_offset: 0x2000
+            }
+        );
+
+        let table = MockMmio::new();
+        write_msix_entry(&table, 2, MESSAGE, false);
+        assert_eq!(table.write_order(), [0x2C, 0x20, 0x24, 0x28, 0x2C]);
+        assert_eq!(table.value(0x20), 0xFEE0_1000);
+        assert_eq!(table.value(0x28), 0x41);
+        assert_eq!(table.value(0x2C), 0);
+
+        enable_msix(&device, 0x70);
+        assert_eq!(config.get(DEVICE, 0x70), 0x0002_0011 | MSIX_ENABLE);
+        disable_msix(&device, 0x70);
+        assert_eq!(config.get(DEVICE, 0x70), 0x0002_0011);
+    }
+}
@@ -0,0 +1,233 @@
+#![no_std]
+#![allow(dead_code)]
+
+use port_io::{PortIo, X86PortIo};
+
+const CHANNEL_DATA_PORT: u16 = 0x40;
+const MODE_PORT: u16 = 0x43;
+const CONTROL_PORT: u16 = 0x61;
+
+bitfield::bitfield! {
+    #[derive(Clone, Copy)]
+    struct Mode(u8);
+    impl Debug;
+    _, set_bcd_format          : 0;
+    _, set_operating_mode   : 3, 1;
+    _, set_access_mode      : 5, 4;
+    _, set_channel          : 7, 6;
+}
+
+#[repr(u8)]
+enum AccessMode {
+    LatchCountValue = 0,
+    LowByteOnly = 1,
+    HighByteOnly = 2,
+    LowAndHighByte = 3,
+}
+
+#[repr(u8)]
+enum OperatingMode {
+    InterruptOnTerminalCount = 0,
+    ProgrammableOneShot = 1,
+    RateGenerator = 2,
+    SquareWaveGenerator = 3,
+    SoftwareTriggeredStrobe = 4,
+    HardwareTriggeredStrobe = 5,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TimerResult {
+    OutOfRange,
+    NotActive,
+}
+
+impl Mode {
+    fn new(access_mode: AccessMode, operating_mode: OperatingMode, bcd_format: bool) -> Self {
+        let mut m = Self(0);
+        m.set_bcd_format(bcd_format);
+        m.set_access_mode(access_mode as u8);
+        m.set_operating_mode(operating_mode as u8);
+        m
+    }
+}
+
+bitfield::bitfield! {
+    #[derive(Clone, Copy)]
+    struct Control(u8);
+    impl Debug;
+    enable_timer_counter2, set_enable_timer_counter2 : 0;
+    enable_speaker_data  , set_enable_speaker_data   : 1;
+    enable_pci_serr      , set_enable_pci_serr       : 2;
+    enable_nmi_iochk     , set_enable_nmi_iochk      : 3;
+    refresh_cycle_toggle   , _ : 4;
+    status_timer_counter2  , _ : 5;
+    status_iochk_nmi_source, _ : 6;
+    status_serr_nmi_source , _ : 7;
+}
+
+pub const BASE_FREQUENCY: u64 = 1_193_182;
+
+// Channel 2 of the programmable interval timer used as a one shot (polled) delay source
+pub struct Pit<P: PortIo = X86PortIo> {
+    io: P,
+}
+
+impl Pit {
+    pub const fn new() -> Self {
+        Self::with_io(X86PortIo)
+    }
+}
+
+impl Default for Pit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: PortIo> Pit<P> {
+    pub const fn with_io(io: P) -> Self {
+        Self { io }
+    }
+
+    fn read_control(&self) -> Control {
+        Control(self.io.read_u8(CONTROL_PORT))
+    }
+
+    fn write_control(&self, control: Control) {
+        self.io.write_u8(CONTROL_PORT, control.0);
+    }
+
+    fn write_mode(&self, mode: Mode) {
+        self.io.write_u8(MODE_PORT, mode.0);
+    }
+
+    fn set_data(&self, channel: u8, value: u8) {
+        self.io.write_u8(CHANNEL_DATA_PORT + channel as u16, value);
+    }
+
+    fn get_data(&self, channel: u8) -> u8 {
+        self.io.read_u8(CHANNEL_DATA_PORT + channel as u16)
+    }
+
+    pub fn set(&self, us: u16) -> Result<(), TimerResult> {
+        let counter = BASE_FREQUENCY * us as u64 / 1_000_000;
+        if counter > 0xffff {
+            return Err(TimerResult::OutOfRange);
+        }
+        let mut c = self.read_control();
+        c.set_enable_speaker_data(false);
+        c.set_enable_timer_counter2(true);
+        self.write_control(c);
+
+        let mut m = Mode::new(
+            AccessMode::LowAndHighByte,
+            OperatingMode::InterruptOnTerminalCount,
+            false,
+        );
+        m.set_channel(2);
+        self.write_mode(m);
+
+        self.set_data(2, (counter & 0xff) as u8);
+        self.set_data(2, ((counter >> 8) & 0xff) as u8);
+
+        Ok(())
+    }
+
+    pub fn get(&self) -> u16 {
+        let mut m = Mode(0);
+        m.set_channel(2);
+        self.write_mode(m);
+
+        self.get_data(2) as u16 | ((self.get_data(2) as u16) << 8)
+    }
+
+    pub fn is_active(&self) -> bool {
+        let c = self.read_control();
+        c.enable_timer_counter2() && !c.status_timer_counter2()
+    }
+
+    pub fn wait_for_timeout(&self) -> Result<(), TimerResult> {
+        loop {
+            let c = self.read_control();
+            if !c.enable_timer_counter2() {
+                return Err(TimerResult::NotActive);
+            } else if c.status_timer_counter2() {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn delay(&self, us: u16) -> Result<(), TimerResult> {
+        self.set(us)?;
+        self.wait_for_timeout()
+    }
+
+    pub fn disable(&self) {
+        let mut c = Control(0);
+        c.set_enable_speaker_data(false);
+        c.set_enable_timer_counter2(false);
+        self.write_control(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use port_io::mock::MockPortIo;
+
+    use super::*;
+
+    #[test]
+    fn set_programs_channel_2_one_shot() {
+        let io = MockPortIo::new();
+        io.set(CONTROL_PORT, 0b10); // speaker enabled
+        let pit = Pit::with_io(&io);
+
+        pit.set(50_000).unwrap();
+
+        assert_eq!(io.writes_to(CONTROL_PORT), [0b01]);
+        assert_eq!(io.writes_to(MODE_PORT), [0b1011_0000]);
+        let counter = BASE_FREQUENCY * 50_000 / 1_000_000;
+        assert_eq!(
+            io.writes_to(CHANNEL_DATA_PORT + 2),
+            [(counter & 0xff) as u32, (counter >> 8) as u32]
+        );
+    }
+
+    #[test]
+    fn set_rejects_too_long_delays() {
+        let io = MockPortIo::new();
+        let pit = Pit::with_io(&io);
+
+        assert_eq!(pit.set(60_000), Err(TimerResult::OutOfRange));
+        assert!(io.accesses().is_empty());
+    }
+
+    #[test]
+    fn wait_for_timeout_polls_status_bit() {
+        let io = MockPortIo::new();
+        io.queue_reads(CONTROL_PORT, &[0b01, 0b01, 0b10_0001]);
+        let pit = Pit::with_io(&io);
+
+        assert_eq!(pit.wait_for_timeout(), Ok(()));
+        assert_eq!(io.accesses().len(), 3);
+    }
+
+    #[test]
+    fn wait_for_timeout_fails_if_disabled() {
+        let io = MockPortIo::new();
+        let pit = Pit::with_io(&io);
+
+        assert_eq!(pit.wait_for_timeout(), Err(TimerResult::NotActive));
+    }
+
+    #[test]
+    fn get_reads_low_then_high_byte() {
+        let io = MockPortIo::new();
+        io.queue_reads(CHANNEL_DATA_PORT + 2, &[0x34, 0x12]);
+        let pit = Pit::with_io(&io);
+
+        assert_eq!(pit.get(), 0x1234);
+        assert_eq!(io.writes_to(MODE_PORT), [0b1000_0000]);
+    }
+}
@@ -0,0 +1,143 @@
+#![no_std]
+
+#[cfg(feature = "mock")]
+extern crate std;
+
+#[cfg(feature = "mock")]
+pub mod mock;
+
+use core::ptr;
+
+use x86_64::instructions::port::Port;
+
+// Access to the x86 io port space.
+// Takes &self since port accesses don't alias any rust memory (drivers are expected to do their own locking)
+pub trait PortIo {
+    fn read_u8(&self, port: u16) -> u8;
+    fn write_u8(&self, port: u16, value: u8);
+
+    fn read_u32(&self, port: u16) -> u32;
+    fn write_u32(&self, port: u16, value: u32);
+}
+
+// Access to a block of memory mapped 32bit registers (offsets are in bytes)
+pub trait Mmio32 {
+    fn read(&self, offset: usize) -> u32;
+    fn write(&self, offset: usize, value: u32);
+}
+
+// Access to the model specific registers of the executing core
+pub trait Msr {
+    fn read_msr(&self, msr: u32) -> u64;
+    fn write_msr(&self, msr: u32, value: u64);
+}
+
+impl<T: PortIo> PortIo for &T {
+    fn read_u8(&self, port: u16) -> u8 {
+        (**self).read_u8(port)
+    }
+
+    fn write_u8(&self, port: u16, value: u8) {
+        (**self).write_u8(port, value);
+    }
+
+    fn read_u32(&self, port: u16) -> u32 {
+        (**self).read_u32(port)
+    }
+
+    fn write_u32(&self, port: u16, value: u32) {
+        (**self).write_u32(port, value);
+    }
+}
+
+impl<T: Mmio32> Mmio32 for &T {
+    fn read(&self, offset: usize) -> u32 {
+        (**self).read(offset)
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        (**self).write(offset, value);
+    }
+}
+
+impl<T: Msr> Msr for &T {
+    fn read_msr(&self, msr: u32) -> u64 {
+        (**self).read_msr(msr)
+    }
+
+    fn write_msr(&self, msr: u32, value: u64) {
+        (**self).write_msr(msr, value);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct X86PortIo;
+
+impl PortIo for X86PortIo {
+    #[inline]
+    fn read_u8(&self, port: u16) -> u8 {
+        unsafe { Port::new(port).read() }
+    }
+
+    #[inline]
+    fn write_u8(&self, port: u16, value: u8) {
+        unsafe { Port::new(port).write(value) }
+    }
+
+    #[inline]
+    fn read_u32(&self, port: u16) -> u32 {
+        unsafe { Port::new(port).read() }
+    }
+
+    #[inline]
+    fn write_u32(&self, port: u16, value: u32) {
+        unsafe { Port::new(port).write(value) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct X86Msr;
+
+impl Msr for X86Msr {
+    #[inline]
+    fn read_msr(&self, msr: u32) -> u64 {
+        unsafe { x86_64::registers::model_specific::Msr::new(msr).read() }
+    }
+
+    #[inline]
+    fn write_msr(&self, msr: u32, value: u64) {
+        unsafe { x86_64::registers::model_specific::Msr::new(msr).write(value) }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VolatileMmio {
+    base: *mut u8,
+}
+
+impl VolatileMmio {
+    /// # Safety
+    /// base has to point to a mapped (uncached) register block which is valid for the lifetime of this struct
+    pub const unsafe fn new(base: *mut u8) -> Self {
+        Self { base }
+    }
+
+    pub const fn base(&self) -> *mut u8 {
+        self.base
+    }
+}
+
+impl Mmio32 for VolatileMmio {
+    #[inline]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.base.wrapping_add(offset).cast::<u32>()) }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.base.wrapping_add(offset).cast::<u32>(), value) }
+    }
+}
+
+unsafe impl Send for VolatileMmio {}
+unsafe impl Sync for VolatileMmio {}
@@ -0,0 +1,225 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+
+use crate::{Mmio32, Msr, PortIo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read(u16, u32),
+    Write(u16, u32),
+}
+
+// Records every port access. Reads return queued values first and fall back to the last written value
+// (or a configured default) afterwards, which is enough to emulate most status registers.
+#[derive(Debug, Default)]
+pub struct MockPortIo {
+    inner: RefCell<MockState<u16>>,
+}
+
+#[derive(Debug, Default)]
+pub struct MockMmio {
+    inner: RefCell<MockState<usize>>,
+}
+
+// Msrs keep the last written value, every write is recorded
+#[derive(Debug, Default)]
+pub struct MockMsr {
+    values: RefCell<BTreeMap<u32, u64>>,
+    writes: RefCell<Vec<(u32, u64)>>,
+}
+
+#[derive(Debug)]
+struct MockState<K> {
+    queued: BTreeMap<K, VecDeque<u32>>,
+    values: BTreeMap<K, u32>,
+    log: Vec<(bool, K, u32)>,
+}
+
+impl<K> Default for MockState<K> {
+    fn default() -> Self {
+        Self {
+            queued: BTreeMap::new(),
+            values: BTreeMap::new(),
+            log: Vec::new(),
+        }
+    }
+}
+
+impl<K: Ord + Copy> MockState<K> {
+    fn read(&mut self, key: K) -> u32 {
+        let value = self
+            .queued
+            .get_mut(&key)
+            .and_then(VecDeque::pop_front)
+            .unwrap_or_else(|| self.values.get(&key).copied().unwrap_or(0));
+        self.log.push((false, key, value));
+        value
+    }
+
+    fn write(&mut self, key: K, value: u32) {
+        self.values.insert(key, value);
+        self.log.push((true, key, value));
+    }
+}
+
+impl MockPortIo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, port: u16, value: u32) {
+        self.inner.borrow_mut().values.insert(port, value);
+    }
+
+    pub fn queue_reads(&self, port: u16, values: &[u32]) {
+        self.inner
+            .borrow_mut()
+            .queued
+            .entry(port)
+            .or_default()
+            .extend(values);
+    }
+
+    pub fn value(&self, port: u16) -> u32 {
+        self.inner.borrow().values.get(&port).copied().unwrap_or(0)
+    }
+
+    pub fn accesses(&self) -> Vec<Access> {
+        self.inner
+            .borrow()
+            .log
+            .iter()
+            .map(|&(write, port, value)| {
+                if write {
+                    Access::Write(port, value)
+                } else {
+                    Access::Read(port, value)
+                }
+            })
+            .collect()
+    }
+
+    pub fn writes_to(&self, port: u16) -> Vec<u32> {
+        self.inner
+            .borrow()
+            .log
+            .iter()
+            .filter(|&&(write, p, _)| write && p == port)
+            .map(|&(_, _, value)| value)
+            .collect()
+    }
+
+    pub fn clear_log(&self) {
+        self.inner.borrow_mut().log.clear();
+    }
+}
+
+impl PortIo for MockPortIo {
+    fn read_u8(&self, port: u16) -> u8 {
+        self.inner.borrow_mut().read(port) as u8
+    }
+
+    fn write_u8(&self, port: u16, value: u8) {
+        self.inner.borrow_mut().write(port, value as u32);
+    }
+
+    fn read_u32(&self, port: u16) -> u32 {
+        self.inner.borrow_mut().read(port)
+    }
+
+    fn write_u32(&self, port: u16, value: u32) {
+        self.inner.borrow_mut().write(port, value);
+    }
+}
+
+impl MockMmio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, offset: usize, value: u32) {
+        self.inner.borrow_mut().values.insert(offset, value);
+    }
+
+    pub fn queue_reads(&self, offset: usize, values: &[u32]) {
+        self.inner
+            .borrow_mut()
+            .queued
+            .entry(offset)
+            .or_default()
+            .extend(values);
+    }
+
+    pub fn value(&self, offset: usize) -> u32 {
+        self.inner
+            .borrow()
+            .values
+            .get(&offset)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn writes_to(&self, offset: usize) -> Vec<u32> {
+        self.inner
+            .borrow()
+            .log
+            .iter()
+            .filter(|&&(write, o, _)| write && o == offset)
+            .map(|&(_, _, value)| value)
+            .collect()
+    }
+
+    // offsets of all writes in order
+    pub fn write_order(&self) -> Vec<usize> {
+        self.inner
+            .borrow()
+            .log
+            .iter()
+            .filter(|&&(write, _, _)| write)
+            .map(|&(_, offset, _)| offset)
+            .collect()
+    }
+}
+
+impl Mmio32 for MockMmio {
+    fn read(&self, offset: usize) -> u32 {
+        self.inner.borrow_mut().read(offset)
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        self.inner.borrow_mut().write(offset, value);
+    }
+}
+
+impl MockMsr {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, msr: u32, value: u64) {
+        self.values.borrow_mut().insert(msr, value);
+    }
+
+    pub fn value(&self, msr: u32) -> u64 {
+        self.values.borrow().get(&msr).copied().unwrap_or(0)
+    }
+
+    // msrs and values of all writes in order
+    pub fn writes(&self) -> Vec<(u32, u64)> {
+        self.writes.borrow().clone()
+    }
+}
+
+impl Msr for MockMsr {
+    fn read_msr(&self, msr: u32) -> u64 {
+        self.value(msr)
+    }
+
+    fn write_msr(&self, msr: u32, value: u64) {
+        self.values.borrow_mut().insert(msr, value);
+        self.writes.borrow_mut().push((msr, value));
+    }
+}
@@ -0,0 +1,532 @@
+// Keys of a us layout keyboard (named after their position, not the character they produce)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    Escape,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Enter,
+    LeftCtrl,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Backtick,
+    LeftShift,
+    Backslash,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    KeypadMultiply,
+    LeftAlt,
+    Space,
+    CapsLock,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    NumLock,
+    ScrollLock,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+    KeypadMinus,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    KeypadPlus,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad0,
+    KeypadPeriod,
+    F11,
+    F12,
+    // extended (0xE0 prefixed) keys
+    KeypadEnter,
+    RightCtrl,
+    KeypadDivide,
+    PrintScreen,
+    RightAlt,
+    Home,
+    Up,
+    PageUp,
+    Left,
+    Right,
+    End,
+    Down,
+    PageDown,
+    Insert,
+    Delete,
+    LeftGui,
+    RightGui,
+    Menu,
+}
+
+impl KeyCode {
+    // scancode set 1 (what the controller produces with translation enabled) without the release bit
+    pub const fn from_set1(code: u8, extended: bool) -> Option<Self> {
+        use KeyCode::*;
+        let key = if extended {
+            match code {
+                0x1C => KeypadEnter,
+                0x1D => RightCtrl,
+                0x35 => KeypadDivide,
+                0x37 => PrintScreen,
+                0x38 => RightAlt,
+                0x47 => Home,
+                0x48 => Up,
+                0x49 => PageUp,
+                0x4B => Left,
+                0x4D => Right,
+                0x4F => End,
+                0x50 => Down,
+                0x51 => PageDown,
+                0x52 => Insert,
+                0x53 => Delete,
+                0x5B => LeftGui,
+                0x5C => RightGui,
+                0x5D => Menu,
+                _ => return None,
+            }
+        } else {
+            match code {
+                0x01 => Escape,
+                0x02 => Key1,
+                0x03 => Key2,
+                0x04 => Key3,
+                0x05 => Key4,
+                0x06 => Key5,
+                0x07 => Key6,
+                0x08 => Key7,
+                0x09 => Key8,
+                0x0A => Key9,
+                0x0B => Key0,
+                0x0C => Minus,
+                0x0D => Equals,
+                0x0E => Backspace,
+                0x0F => Tab,
+                0x10 => Q,
+                0x11 => W,
+                0x12 => E,
+                0x13 => R,
+                0x14 => T,
+                0x15 => Y,
+                0x16 => U,
+                0x17 => I,
+                0x18 => O,
+                0x19 => P,
+                0x1A => LeftBracket,
+                0x1B => RightBracket,
+                0x1C => Enter,
+                0x1D => LeftCtrl,
+                0x1E => A,
+                0x1F => S,
+                0x20 => D,
+                0x21 => F,
+                0x22 => G,
+                0x23 => H,
+                0x24 => J,
+                0x25 => K,
+                0x26 => L,
+                0x27 => Semicolon,
+                0x28 => Quote,
+                0x29 => Backtick,
+                0x2A => LeftShift,
+                0x2B => Backslash,
+                0x2C => Z,
+                0x2D => X,
+                0x2E => C,
+                0x2F => V,
+                0x30 => B,
+                0x31 => N,
+                0x32 => M,
+                0x33 => Comma,
+                0x34 => Period,
+                0x35 => Slash,
+                0x36 => RightShift,
+                0x37 => KeypadMultiply,
+                0x38 => LeftAlt,
+                0x39 => Space,
+                0x3A => CapsLock,
+                0x3B => F1,
+                0x3C => F2,
+                0x3D => F3,
+                0x3E => F4,
+                0x3F => F5,
+                0x40 => F6,
+                0x41 => F7,
+                0x42 => F8,
+                0x43 => F9,
+                0x44 => F10,
+                0x45 => NumLock,
+                0x46 => ScrollLock,
+                0x47 => Keypad7,
+                0x48 => Keypad8,
+                0x49 => Keypad9,
+                0x4A => KeypadMinus,
+                0x4B => Keypad4,
+                0x4C => Keypad5,
+                0x4D => Keypad6,
+                0x4E => KeypadPlus,
+                0x4F => Keypad1,
+                0x50 => Keypad2,
+                0x51 => Keypad3,
+                0x52 => Keypad0,
+                0x53 => KeypadPeriod,
+                0x57 => F11,
+                0x58 => F12,
+                _ => return None,
+            }
+        };
+        Some(key)
+    }
+
+    // (without shift, with shift)
+    const fn chars(self) -> Option<(char, char)> {
+        use KeyCode::*;
+        let chars = match self {
+            Key1 => ('1', '!'),
+            Key2 => ('2', '@'),
+            Key3 => ('3', '#'),
+            Key4 => ('4', '$'),
+            Key5 => ('5', '%'),
+            Key6 => ('6', '^'),
+            Key7 => ('7', '&'),
+            Key8 => ('8', '*'),
+            Key9 => ('9', '('),
+            Key0 => ('0', ')'),
+            Minus => ('-', '_'),
+            Equals => ('=', '+'),
+            Backspace => ('\x08', '\x08'),
+            Tab => ('\t', '\t'),
+            Q => ('q', 'Q'),
+            W => ('w', 'W'),
+            E => ('e', 'E'),
+            R => ('r', 'R'),
+            T => ('t', 'T'),
+            Y => ('y', 'Y'),
+            U => ('u', 'U'),
+            I => ('i', 'I'),
+            O => ('o', 'O'),
+            P => ('p', 'P'),
+            LeftBracket => ('[', '{'),
+            RightBracket => (']', '}'),
+            Enter | KeypadEnter => ('\n', '\n'),
+            A => ('a', 'A'),
+            S => ('s', 'S'),
+            D => ('d', 'D'),
+            F => ('f', 'F'),
+            G => ('g', 'G'),
+            H => ('h', 'H'),
+            J => ('j', 'J'),
+            K => ('k', 'K'),
+            L => ('l', 'L'),
+            Semicolon => (';', ':'),
+            Quote => ('\'', '"'),
+            Backtick => ('`', '~'),
+            Backslash => ('\\', '|'),
+            Z => ('z', 'Z'),
+            X => ('x', 'X'),
+            C => ('c', 'C'),
+            V => ('v', 'V'),
+            B => ('b', 'B'),
+            N => ('n', 'N'),
+            M => ('m', 'M'),
+            Comma => (',', '<'),
+            Period => ('.', '>'),
+            Slash => ('/', '?'),
+            Space => (' ', ' '),
+            KeypadMultiply => ('*', '*'),
+            KeypadMinus => ('-', '-'),
+            KeypadPlus => ('+', '+'),
+            KeypadDivide => ('/', '/'),
+            _ => return None,
+        };
+        Some(chars)
+    }
+
+    // the keypad keys that produce digits while num lock is on
+    const fn keypad_char(self) -> Option<char> {
+        use KeyCode::*;
+        let c = match self {
+            Keypad0 => '0',
+            Keypad1 => '1',
+            Keypad2 => '2',
+            Keypad3 => '3',
+            Keypad4 => '4',
+            Keypad5 => '5',
+            Keypad6 => '6',
+            Keypad7 => '7',
+            Keypad8 => '8',
+            Keypad9 => '9',
+            KeypadPeriod => '.',
+            _ => return None,
+        };
+        Some(c)
+    }
+
+    const fn is_letter(self) -> bool {
+        matches!(self.chars(), Some((c, _)) if c.is_ascii_lowercase())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Pressed,
+    // the key is held down (typematic repeat)
+    Repeated,
+    Released,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: KeyCode,
+    pub state: KeyState,
+    // the state after this event
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    // the character typed by a press (or repeat) of a us layout keyboard
+    pub const fn char(&self) -> Option<char> {
+        if matches!(self.state, KeyState::Released) {
+            return None;
+        }
+        if let Some(c) = self.key.keypad_char() {
+            return if self.modifiers.num_lock {
+                Some(c)
+            } else {
+                None
+            };
+        }
+        let Some((normal, shifted)) = self.key.chars() else {
+            return None;
+        };
+        // caps lock only affects letters
+        let shift = self.modifiers.shift != (self.modifiers.caps_lock && self.key.is_letter());
+        Some(if shift { shifted } else { normal })
+    }
+}
+
+const RELEASED: u8 = 0x80;
+const EXTENDED_PREFIX: u8 = 0xE0;
+// pause sends E1 1D 45 E1 9D C5 (without a release)
+const PAUSE_PREFIX: u8 = 0xE1;
+
+// Turns the scancode bytes (set 1) into key events, keeping track of the pressed keys and lock states
+#[derive(Debug, Default)]
+pub struct Decoder {
+    extended: bool,
+    // bytes of a pause sequence still to be skipped
+    skip: u8,
+    // one bit per KeyCode
+    pressed: [u64; 2],
+    caps_lock: bool,
+    num_lock: bool,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            skip: 0,
+            pressed: [0; 2],
+            caps_lock: false,
+            num_lock: false,
+        }
+    }
+
+    const fn is_pressed(&self, key: KeyCode) -> bool {
+        self.pressed[key as usize / 64] & (1 << (key as usize % 64)) != 0
+    }
+
+    fn set_pressed(&mut self, key: KeyCode, pressed: bool) {
+        let bit = 1 << (key as usize % 64);
+        if pressed {
+            self.pressed[key as usize / 64] |= bit;
+        } else {
+            self.pressed[key as usize / 64] &= !bit;
+        }
+    }
+
+    pub const fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.is_pressed(KeyCode::LeftShift) || self.is_pressed(KeyCode::RightShift),
+            ctrl: self.is_pressed(KeyCode::LeftCtrl) || self.is_pressed(KeyCode::RightCtrl),
+            alt: self.is_pressed(KeyCode::LeftAlt) || self.is_pressed(KeyCode::RightAlt),
+            caps_lock: self.caps_lock,
+            num_lock: self.num_lock,
+        }
+    }
+
+    // returns an event once a byte completes a key
+    pub fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+        match byte {
+            EXTENDED_PREFIX => {
+                self.extended = true;
+                return None;
+            }
+            PAUSE_PREFIX => {
+                self.skip = 5;
+                return None;
+            }
+            // acknowledgements, resend requests and errors of the keyboard
+            0x00 | 0xEE | 0xFA | 0xFE | 0xFF => return None,
+            _ => {}
+        }
+        let extended = core::mem::take(&mut self.extended);
+        let code = byte & !RELEASED;
+        // print screen is wrapped in fake shifts (E0 2A E0 37)
+        if extended && code == 0x2A {
+            return None;
+        }
+        let key = KeyCode::from_set1(code, extended)?;
+
+        let state = if byte & RELEASED != 0 {
+            KeyState::Released
+        } else if self.is_pressed(key) {
+            KeyState::Repeated
+        } else {
+            KeyState::Pressed
+        };
+        self.set_pressed(key, state != KeyState::Released);
+        if state == KeyState::Pressed {
+            match key {
+                KeyCode::CapsLock => self.caps_lock = !self.caps_lock,
+                KeyCode::NumLock => self.num_lock = !self.num_lock,
+                _ => {}
+            }
+        }
+        Some(KeyEvent {
+            key,
+            state,
+            modifiers: self.modifiers(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_all(decoder: &mut Decoder, bytes: &[u8]) -> Option<KeyEvent> {
+        bytes.iter().fold(None, |_, &byte| decoder.feed(byte))
+    }
+
+    #[test]
+    fn letters_are_shifted_by_shift_and_caps_lock() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.feed(0x1E).unwrap().char(), Some('a'));
+        assert_eq!(decoder.feed(0x9E).unwrap().char(), None);
+
+        decoder.feed(0x2A);
+        let event = decoder.feed(0x1E).unwrap();
+        assert!(event.modifiers.shift);
+        assert_eq!(event.char(), Some('A'));
+        assert_eq!(decoder.feed(0x02).unwrap().char(), Some('!'));
+        decoder.feed(0xAA);
+
+        feed_all(&mut decoder, &[0x3A, 0xBA]);
+        assert_eq!(decoder.feed(0x1E).unwrap().char(), Some('A'));
+        // caps lock does not shift digits
+        assert_eq!(decoder.feed(0x02).unwrap().char(), Some('1'));
+    }
+
+    #[test]
+    fn held_keys_are_reported_as_repeated() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.feed(0x39).unwrap().state, KeyState::Pressed);
+        assert_eq!(decoder.feed(0x39).unwrap().state, KeyState::Repeated);
+        assert_eq!(decoder.feed(0xB9).unwrap().state, KeyState::Released);
+        assert_eq!(decoder.feed(0x39).unwrap().state, KeyState::Pressed);
+    }
+
+    #[test]
+    fn extended_keys_are_decoded() {
+        let mut decoder = Decoder::new();
+        let event = feed_all(&mut decoder, &[0xE0, 0x1D]).unwrap();
+        assert_eq!(event.key, KeyCode::RightCtrl);
+        assert!(event.modifiers.ctrl);
+
+        assert_eq!(
+            feed_all(&mut decoder, &[0xE0, 0x48]).unwrap().key,
+            KeyCode::Up
+        );
+        let event = feed_all(&mut decoder, &[0xE0, 0x2A, 0xE0, 0x37]).unwrap();
+        assert_eq!(event.key, KeyCode::PrintScreen);
+        assert!(!event.modifiers.shift);
+
+        // pause has no key code, the following key is decoded normally
+        assert_eq!(
+            feed_all(&mut decoder, &[0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5]),
+            None
+        );
+        assert_eq!(decoder.feed(0x10).unwrap().key, KeyCode::Q);
+    }
+
+    #[test]
+    fn keypad_digits_need_num_lock() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.feed(0x4F).unwrap().char(), None);
+        feed_all(&mut decoder, &[0x45, 0xC5]);
+        assert_eq!(decoder.feed(0x4F).unwrap().char(), Some('1'));
+    }
+}
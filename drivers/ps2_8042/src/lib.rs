@@ -0,0 +1,376 @@
+#![no_std]
+
+pub mod keyboard;
+pub mod mouse;
+
+use core::hint;
+
+use port_io::{PortIo, X86PortIo};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64; // read
+const COMMAND_PORT: u16 = 0x64; // write
+
+// Status register
+const OUTPUT_FULL: u8 = 1 << 0;
+const INPUT_FULL: u8 = 1 << 1;
+const SECOND_PORT_DATA: u8 = 1 << 5;
+
+// Controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_FIRST_PORT: u8 = 0xAD;
+const ENABLE_FIRST_PORT: u8 = 0xAE;
+const ENABLE_SECOND_PORT: u8 = 0xA8;
+const WRITE_SECOND_PORT: u8 = 0xD4; // the next data byte goes to the second port
+const PULSE_RESET_LINE: u8 = 0xFE;
+
+// Configuration byte
+const FIRST_PORT_INTERRUPT: u8 = 1 << 0;
+const SECOND_PORT_INTERRUPT: u8 = 1 << 1;
+const FIRST_PORT_CLOCK_DISABLED: u8 = 1 << 4;
+const SECOND_PORT_CLOCK_DISABLED: u8 = 1 << 5;
+const TRANSLATION: u8 = 1 << 6; // the controller translates scancode set 2 to set 1
+
+// Device commands and responses
+const SET_TYPEMATIC: u8 = 0xF3;
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_SCANNING: u8 = 0xF4;
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+
+// status polls before a transfer is given up
+const TIMEOUT_POLLS: u32 = 100_000;
+const RETRIES: u32 = 3;
+
+// the keyboard is attached to the first port, the mouse to the second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    First,
+    Second,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout,
+    UnexpectedResponse(u8),
+}
+
+// Key repeat of the keyboard (sent as repeated make codes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Typematic {
+    // (delay + 1) * 250ms before the first repeat, 0..=3
+    pub delay: u8,
+    // 0 (30 repeats per second) ..= 31 (2 repeats per second)
+    pub rate: u8,
+}
+
+impl Typematic {
+    pub const DEFAULT: Self = Self { delay: 1, rate: 4 };
+
+    const fn to_byte(self) -> u8 {
+        (self.delay & 0b11) << 5 | (self.rate & 0b1_1111)
+    }
+}
+
+// Register level driver for the 8042 ps/2 controller
+pub struct Ps2Controller<P: PortIo = X86PortIo> {
+    io: P,
+}
+
+impl Ps2Controller {
+    pub const fn new() -> Self {
+        Self::with_io(X86PortIo)
+    }
+}
+
+impl Default for Ps2Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: PortIo> Ps2Controller<P> {
+    pub const fn with_io(io: P) -> Self {
+        Self { io }
+    }
+
+    fn status(&self) -> u8 {
+        self.io.read_u8(STATUS_PORT)
+    }
+
+    // returns the next byte sent by a device if there is one (used by the interrupt handlers)
+    pub fn try_read_data(&self) -> Option<u8> {
+        (self.status() & OUTPUT_FULL != 0).then(|| self.io.read_u8(DATA_PORT))
+    }
+
+    // like try_read_data, but also tells which device sent the byte
+    pub fn try_read_data_from(&self) -> Option<(Ps2Port, u8)> {
+        let status = self.status();
+        if status & OUTPUT_FULL == 0 {
+            return None;
+        }
+        let port = if status & SECOND_PORT_DATA != 0 {
+            Ps2Port::Second
+        } else {
+            Ps2Port::First
+        };
+        Some((port, self.io.read_u8(DATA_PORT)))
+    }
+
+    pub fn read_data(&self) -> Result<u8, Ps2Error> {
+        for _ in 0..TIMEOUT_POLLS {
+            if let Some(data) = self.try_read_data() {
+                return Ok(data);
+            }
+            hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn wait_for_input_empty(&self) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT_POLLS {
+            if self.status() & INPUT_FULL == 0 {
+                return Ok(());
+            }
+            hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    pub fn write_command(&self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_for_input_empty()?;
+        self.io.write_u8(COMMAND_PORT, command);
+        Ok(())
+    }
+
+    pub fn write_data(&self, data: u8) -> Result<(), Ps2Error> {
+        self.wait_for_input_empty()?;
+        self.io.write_u8(DATA_PORT, data);
+        Ok(())
+    }
+
+    // resets the cpu (the controller drives its reset line), returns if the reset did not happen
+    pub fn pulse_reset_line(&self) -> Result<(), Ps2Error> {
+        self.write_command(PULSE_RESET_LINE)
+    }
+
+    // discards bytes the devices sent before
+    pub fn flush(&self) {
+        while self.try_read_data().is_some() {}
+    }
+
+    pub fn read_config(&self) -> Result<u8, Ps2Error> {
+        self.write_command(READ_CONFIG)?;
+        self.read_data()
+    }
+
+    pub fn write_config(&self, config: u8) -> Result<(), Ps2Error> {
+        self.write_command(WRITE_CONFIG)?;
+        self.write_data(config)
+    }
+
+    // sends a byte to the keyboard and waits for its acknowledgement
+    pub fn send_to_keyboard(&self, data: u8) -> Result<(), Ps2Error> {
+        self.send_to_device(Ps2Port::First, data)
+    }
+
+    pub fn send_to_mouse(&self, data: u8) -> Result<(), Ps2Error> {
+        self.send_to_device(Ps2Port::Second, data)
+    }
+
+    fn send_to_device(&self, port: Ps2Port, data: u8) -> Result<(), Ps2Error> {
+        for _ in 0..RETRIES {
+            if port == Ps2Port::Second {
+                self.write_command(WRITE_SECOND_PORT)?;
+            }
+            self.write_data(data)?;
+            match self.read_data()? {
+                ACK => return Ok(()),
+                RESEND => {}
+                response => return Err(Ps2Error::UnexpectedResponse(response)),
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    // Enables the keyboard port with translation (scancode set 1) and its interrupt.
+    // Has to be called before the interrupt is routed, since the acknowledgements are polled
+    pub fn init_keyboard(&self, typematic: Typematic) -> Result<(), Ps2Error> {
+        self.write_command(DISABLE_FIRST_PORT)?;
+        self.flush();
+        let config = self.read_config()?;
+        self.write_config(
+            (config | FIRST_PORT_INTERRUPT | TRANSLATION) & !FIRST_PORT_CLOCK_DISABLED,
+        )?;
+        self.write_command(ENABLE_FIRST_PORT)?;
+        self.send_to_keyboard(SET_TYPEMATIC)?;
+        self.send_to_keyboard(typematic.to_byte())?;
+        self.send_to_keyboard(ENABLE_SCANNING)
+    }
+
+    // Enables the mouse port and its interrupt, the mouse sends 3 byte packets (see mouse::Decoder) from then on.
+    // Has to be called before the interrupt is routed, since the acknowledgements are polled
+    pub fn init_mouse(&self) -> Result<(), Ps2Error> {
+        self.write_command(ENABLE_SECOND_PORT)?;
+        let config = self.read_config()?;
+        self.write_config((config | SECOND_PORT_INTERRUPT) & !SECOND_PORT_CLOCK_DISABLED)?;
+        self.send_to_mouse(SET_DEFAULTS)?;
+        self.send_to_mouse(ENABLE_SCANNING)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use port_io::mock::MockPortIo;
+
+    use super::*;
+
+    #[test]
+    fn data_is_only_read_when_available() {
+        let io = MockPortIo::new();
+        let controller = Ps2Controller::with_io(&io);
+        assert_eq!(controller.try_read_data(), None);
+
+        io.set(STATUS_PORT, OUTPUT_FULL as u32);
+        io.queue_reads(DATA_PORT, &[0x1E]);
+        assert_eq!(controller.try_read_data(), Some(0x1E));
+    }
+
+    #[test]
+    fn reset_is_a_controller_command() {
+        let io = MockPortIo::new();
+        let controller = Ps2Controller::with_io(&io);
+        assert_eq!(controller.pulse_reset_line(), Ok(()));
+        assert_eq!(io.writes_to(COMMAND_PORT), [PULSE_RESET_LINE as u32]);
+
+        // the command is not sent while the controller is busy
+        io.set(STATUS_PORT, INPUT_FULL as u32);
+        assert_eq!(controller.pulse_reset_line(), Err(Ps2Error::Timeout));
+        assert_eq!(io.writes_to(COMMAND_PORT).len(), 1);
+    }
+
+    #[test]
+    fn keyboard_commands_are_retried_on_resend() {
+        let io = MockPortIo::new();
+        io.set(STATUS_PORT, OUTPUT_FULL as u32);
+        io.queue_reads(DATA_PORT, &[RESEND as u32, ACK as u32]);
+        let controller = Ps2Controller::with_io(&io);
+
+        assert_eq!(controller.send_to_keyboard(ENABLE_SCANNING), Ok(()));
+        assert_eq!(
+            io.writes_to(DATA_PORT),
+            [ENABLE_SCANNING as u32, ENABLE_SCANNING as u32]
+        );
+
+        io.queue_reads(DATA_PORT, &[0x42]);
+        assert_eq!(
+            controller.send_to_keyboard(ENABLE_SCANNING),
+            Err(Ps2Error::UnexpectedResponse(0x42))
+        );
+    }
+
+    #[test]
+    fn data_of_the_second_port_is_attributed_to_it() {
+        let io = MockPortIo::new();
+        let controller = Ps2Controller::with_io(&io);
+        io.queue_reads(
+            STATUS_PORT,
+            &[
+                OUTPUT_FULL as u32,
+                (OUTPUT_FULL | SECOND_PORT_DATA) as u32,
+                0,
+            ],
+        );
+        io.queue_reads(DATA_PORT, &[0x1E, 0x08]);
+
+        assert_eq!(
+            controller.try_read_data_from(),
+            Some((Ps2Port::First, 0x1E))
+        );
+        assert_eq!(
+            controller.try_read_data_from(),
+            Some((Ps2Port::Second, 0x08))
+        );
+        assert_eq!(controller.try_read_data_from(), None);
+    }
+
+    #[test]
+    fn mouse_bytes_are_prefixed_with_the_second_port_command() {
+        let io = MockPortIo::new();
+        io.queue_reads(STATUS_PORT, &[OUTPUT_FULL as u32; 11]);
+        io.queue_reads(
+            DATA_PORT,
+            &[SECOND_PORT_CLOCK_DISABLED as u32, ACK as u32, ACK as u32],
+        );
+        let controller = Ps2Controller::with_io(&io);
+
+        assert_eq!(controller.init_mouse(), Ok(()));
+        assert_eq!(
+            io.writes_to(COMMAND_PORT),
+            [
+                ENABLE_SECOND_PORT as u32,
+                READ_CONFIG as u32,
+                WRITE_CONFIG as u32,
+                WRITE_SECOND_PORT as u32,
+                WRITE_SECOND_PORT as u32
+            ]
+        );
+        assert_eq!(
+            io.writes_to(DATA_PORT),
+            [
+                SECOND_PORT_INTERRUPT as u32,
+                SET_DEFAULTS as u32,
+                ENABLE_SCANNING as u32
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_time_out_without_data() {
+        let io = MockPortIo::new();
+        let controller = Ps2Controller::with_io(&io);
+        assert_eq!(controller.read_data(), Err(Ps2Error::Timeout));
+    }
+
+    #[test]
+    fn init_enables_translation_interrupt_and_typematic() {
+        let io = MockPortIo::new();
+        // the status and command register share a port, so every status read is queued:
+        // empty for the first command and the flush, then always data available and input empty
+        let mut status = [OUTPUT_FULL as u32; 13];
+        status[..2].fill(0);
+        io.queue_reads(STATUS_PORT, &status);
+        // config byte, then the acks of the three keyboard bytes
+        io.queue_reads(
+            DATA_PORT,
+            &[
+                FIRST_PORT_CLOCK_DISABLED as u32,
+                ACK as u32,
+                ACK as u32,
+                ACK as u32,
+            ],
+        );
+        let controller = Ps2Controller::with_io(&io);
+
+        assert_eq!(controller.init_keyboard(Typematic::DEFAULT), Ok(()));
+        assert_eq!(
+            io.writes_to(COMMAND_PORT),
+            [
+                DISABLE_FIRST_PORT as u32,
+                READ_CONFIG as u32,
+                WRITE_CONFIG as u32,
+                ENABLE_FIRST_PORT as u32
+            ]
+        );
+        assert_eq!(
+            io.writes_to(DATA_PORT),
+            [
+                (FIRST_PORT_INTERRUPT | TRANSLATION) as u32,
+                SET_TYPEMATIC as u32,
+                0b010_0100,
+                ENABLE_SCANNING as u32
+            ]
+        );
+    }
+}
@@ -0,0 +1,124 @@
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    // movement in screen direction (positive dy is down, the mouse itself reports up)
+    pub dx: i16,
+    pub dy: i16,
+    pub buttons: MouseButtons,
+}
+
+// first byte of a packet
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+// Assembles the 3 byte packets of a standard ps/2 mouse (flags, x movement, y movement)
+#[derive(Debug, Default)]
+pub struct Decoder {
+    packet: [u8; 3],
+    received: usize,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            packet: [0; 3],
+            received: 0,
+        }
+    }
+
+    // returns an event once a byte completes a packet
+    pub fn feed(&mut self, byte: u8) -> Option<MouseEvent> {
+        // bytes that can not start a packet are dropped until the stream is in sync again
+        if self.received == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet[self.received] = byte;
+        self.received += 1;
+        if self.received < self.packet.len() {
+            return None;
+        }
+        self.received = 0;
+
+        let [flags, x, y] = self.packet;
+        // the 9 bit two's complement movement is unusable after an overflow
+        let movement = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                i16::from(value) - 0x100
+            } else {
+                i16::from(value)
+            }
+        };
+        Some(MouseEvent {
+            dx: movement(x, X_SIGN, X_OVERFLOW),
+            dy: -movement(y, Y_SIGN, Y_OVERFLOW),
+            buttons: MouseButtons {
+                left: flags & LEFT_BUTTON != 0,
+                right: flags & RIGHT_BUTTON != 0,
+                middle: flags & MIDDLE_BUTTON != 0,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_are_decoded_with_sign_extension() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.feed(ALWAYS_ONE | LEFT_BUTTON | Y_SIGN), None);
+        assert_eq!(decoder.feed(5), None);
+        let event = decoder.feed(0xFE).unwrap();
+        assert_eq!(event.dx, 5);
+        // -2 reported by the mouse is 2 down on the screen
+        assert_eq!(event.dy, 2);
+        assert!(event.buttons.left);
+        assert!(!event.buttons.right);
+
+        let event = [ALWAYS_ONE | X_SIGN | MIDDLE_BUTTON, 0xF0, 3]
+            .into_iter()
+            .find_map(|byte| decoder.feed(byte))
+            .unwrap();
+        assert_eq!((event.dx, event.dy), (-16, -3));
+        assert!(event.buttons.middle);
+    }
+
+    #[test]
+    fn overflowing_movement_is_dropped() {
+        let mut decoder = Decoder::new();
+        let event = [ALWAYS_ONE | X_OVERFLOW, 0xFF, 7]
+            .into_iter()
+            .find_map(|byte| decoder.feed(byte))
+            .unwrap();
+        assert_eq!((event.dx, event.dy), (0, -7));
+    }
+
+    #[test]
+    fn stream_resynchronizes_on_packet_start() {
+        let mut decoder = Decoder::new();
+        // a lost flags byte: the movement bytes without ALWAYS_ONE are skipped
+        assert_eq!(decoder.feed(0x00), None);
+        assert_eq!(decoder.feed(0x01), None);
+        let event = [ALWAYS_ONE | RIGHT_BUTTON, 1, 1]
+            .into_iter()
+            .find_map(|byte| decoder.feed(byte))
+            .unwrap();
+        assert!(event.buttons.right);
+        assert_eq!((event.dx, event.dy), (1, -1));
+    }
+}
@@ -0,0 +1,387 @@
+#![no_std]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+use core::{fmt, hint};
+
+use alloc::string::String;
+use port_io::{PortIo, X86PortIo};
+
+#[repr(u16)]
+#[derive(Clone, Copy)]
+pub enum ComPort {
+    COM1 = 0x3f8,
+    COM2 = 0x2f8,
+    COM3 = 0x3e8,
+    COM4 = 0x2e8,
+}
+
+#[repr(u16)]
+#[derive(Clone, Copy)]
+pub enum BaudRate {
+    BAUD_300 = 384,
+    BAUD_600 = 192,
+    BAUD_1200 = 96,
+    BAUD_2400 = 48,
+    BAUD_4800 = 24,
+    BAUD_9600 = 12,
+    BAUD_19200 = 6,
+    BAUD_38400 = 3,
+    BAUD_57600 = 2,
+    BAUD_115200 = 1,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum DataBits {
+    DATA_5BIT = 0,
+    DATA_6BIT = 1,
+    DATA_7BIT = 2,
+    DATA_8BIT = 3,
+}
+
+mod StopBits {
+    pub(super) const STOP_1BIT: u8 = 0;
+    pub(super) const STOP_1_5BIT: u8 = 4;
+    pub(super) const STOP_2BIT: u8 = 4;
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum Parity {
+    PARITY_NONE = 0,
+    PARITY_ODD = 8,
+    PARITY_EVEN = 24,
+    PARITY_MARK = 40,
+    PARITY_SPACE = 56,
+}
+
+mod ri {
+    // if Divisor Latch Access Bit [DLAB] = 0
+    pub(super) const RECEIVE_BUFFER_REGISTER: u8 = 0;
+    ///< read only
+    pub(super) const TRANSMIT_BUFFER_REGISTER: u8 = 0;
+    ///< write only
+    pub(super) const INTERRUPT_ENABLE_REGISTER: u8 = 1;
+
+    // if Divisor Latch Access Bit [DLAB] = 1
+    pub(super) const DIVISOR_LOW_REGISTER: u8 = 0;
+    pub(super) const DIVISOR_HIGH_REGISTER: u8 = 1;
+
+    // (irrespective from DLAB)
+    pub(super) const INTERRUPT_IDENT_REGISTER: u8 = 2;
+    ///< read only
+    pub(super) const FIFO_CONTROL_REGISTER: u8 = 2;
+    ///< write only -- 16550 and newer (esp. not 8250a)
+    pub(super) const LINE_CONTROL_REGISTER: u8 = 3;
+    ///< highest-order bit is DLAB (see above)
+    pub(super) const MODEM_CONTROL_REGISTER: u8 = 4;
+    pub(super) const LINE_STATUS_REGISTER: u8 = 5;
+    pub(super) const MODEM_STATUS_REGISTER: u8 = 6;
+}
+
+mod RegisterMask {
+    // Interrupt Enable Register
+    pub(super) const RECEIVED_DATA_AVAILABLE: u8 = 1 << 0;
+    pub(super) const TRANSMITTER_HOLDING_REGISTER_EMPTY: u8 = 1 << 1;
+    pub(super) const RECEIVER_LINE_STATUS: u8 = 1 << 2;
+    pub(super) const MODEM_STATUS: u8 = 1 << 3;
+
+    // Interrupt Ident Register
+    pub(super) const INTERRUPT_PENDING: u8 = 1 << 0;
+    ///< 0 means interrupt pending
+    pub(super) const INTERRUPT_ID_0: u8 = 1 << 1;
+    pub(super) const INTERRUPT_ID_1: u8 = 1 << 2;
+
+    // FIFO Control Register
+    pub(super) const ENABLE_FIFO: u8 = 1 << 0;
+    ///< 0 means disabled ^= conforming to 8250a
+    pub(super) const CLEAR_RECEIVE_FIFO: u8 = 1 << 1;
+    pub(super) const CLEAR_TRANSMIT_FIFO: u8 = 1 << 2;
+    pub(super) const DMA_MODE_SELECT: u8 = 1 << 3;
+    pub(super) const TRIGGER_RECEIVE: u8 = 1 << 6;
+
+    // Line Control Register
+    //  bits per character:  5   6   7   8
+    pub(super) const WORD_LENGTH_SELECT_0: u8 = 1 << 0; //  Setting Select0:     0   1   0   1
+    pub(super) const WORD_LENGTH_SELECT_1: u8 = 1 << 1; //  Setting Select1:     0   0   1   1
+    pub(super) const NUMBER_OF_STOP_BITS: u8 = 1 << 2; //  0 ≙ one stop bit, 1 ≙ 1.5/2 stop bits
+    pub(super) const PARITY_ENABLE: u8 = 1 << 3;
+    pub(super) const EVEN_PARITY_SELECT: u8 = 1 << 4;
+    pub(super) const STICK_PARITY: u8 = 1 << 5;
+    pub(super) const SET_BREAK: u8 = 1 << 6;
+    pub(super) const DIVISOR_LATCH_ACCESS_BIT: u8 = 1 << 7; // DLAB
+
+    // Modem Control Register
+    pub(super) const DATA_TERMINAL_READY: u8 = 1 << 0;
+    pub(super) const REQUEST_TO_SEND: u8 = 1 << 1;
+    pub(super) const OUT_1: u8 = 1 << 2;
+    pub(super) const OUT_2: u8 = 1 << 3; // must be set for interrupts!
+    pub(super) const LOOP: u8 = 1 << 4;
+
+    // Line Status Register
+    pub(super) const DATA_READY: u8 = 1 << 0; // Set when there is a value in the receive buffer
+    pub(super) const OVERRUN_ERROR: u8 = 1 << 1;
+    pub(super) const PARITY_ERROR: u8 = 1 << 2;
+    pub(super) const FRAMING_ERROR: u8 = 1 << 3;
+    pub(super) const BREAK_INTERRUPT: u8 = 1 << 4;
+    pub(super) const TRANSMITTER_HOLDING_REGISTER: u8 = 1 << 5;
+    pub(super) const TRANSMITTER_EMPTY: u8 = 1 << 6; // Send buffer empty (ready to send)
+
+    // Modem Status Register
+    pub(super) const DELTA_CLEAR_TO_SEND: u8 = 1 << 0;
+    pub(super) const DELTA_DATA_SET_READY: u8 = 1 << 1;
+    pub(super) const TRAILING_EDGE_RING_INDICATOR: u8 = 1 << 2;
+    pub(super) const DELTA_DATA_CARRIER_DETECT: u8 = 1 << 3;
+    pub(super) const CLEAR_TO_SEND: u8 = 1 << 4;
+    pub(super) const DATA_SET_READY: u8 = 1 << 5;
+    pub(super) const RING_INDICATOR: u8 = 1 << 6;
+    pub(super) const DATA_CARRIER_DETECT: u8 = 1 << 7;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialError {
+    Busy,
+    OverrunError,
+    ParityError,
+    FramingError,
+}
+
+fn rr(io: &impl PortIo, com: ComPort, index: u8) -> u8 {
+    io.read_u8(com as u16 + index as u16)
+}
+
+fn wr(io: &impl PortIo, com: ComPort, index: u8, value: u8) {
+    io.write_u8(com as u16 + index as u16, value);
+}
+
+pub fn init(com: ComPort, baud: BaudRate) {
+    init_with_io(&X86PortIo, com, baud);
+}
+
+pub fn init_with_io(io: &impl PortIo, com: ComPort, baud: BaudRate) {
+    wr(io, com, ri::LINE_CONTROL_REGISTER, 0b1000_0000); //enable DLAB
+    wr(
+        io,
+        com,
+        ri::DIVISOR_LOW_REGISTER,
+        (baud as u16 & 0xff) as u8,
+    ); //set baud rate
+    wr(io, com, ri::DIVISOR_HIGH_REGISTER, (baud as u16 >> 8) as u8);
+    wr(io, com, ri::LINE_CONTROL_REGISTER, 0b0000_0011); //8N1 + disable DLAB
+    wr(io, com, ri::INTERRUPT_ENABLE_REGISTER, 0); //disable interrupts
+    wr(io, com, ri::MODEM_CONTROL_REGISTER, 0); //disable loopback
+}
+
+// received bytes raise the interrupt of the port (its isa irq), the handler has to read them
+pub fn enable_receive_interrupt(com: ComPort) {
+    enable_receive_interrupt_with_io(&X86PortIo, com);
+}
+
+pub fn enable_receive_interrupt_with_io(io: &impl PortIo, com: ComPort) {
+    wr(
+        io,
+        com,
+        ri::INTERRUPT_ENABLE_REGISTER,
+        RegisterMask::RECEIVED_DATA_AVAILABLE,
+    );
+    wr(io, com, ri::MODEM_CONTROL_REGISTER, RegisterMask::OUT_2);
+}
+
+fn check_line_status(status: u8) -> Result<(), SerialError> {
+    if status & RegisterMask::OVERRUN_ERROR != 0 {
+        return Err(SerialError::OverrunError);
+    }
+    if status & RegisterMask::PARITY_ERROR != 0 {
+        return Err(SerialError::ParityError);
+    }
+    if status & RegisterMask::FRAMING_ERROR != 0 {
+        return Err(SerialError::FramingError);
+    }
+    Ok(())
+}
+
+pub struct ReadPort<P: PortIo = X86PortIo> {
+    com: ComPort,
+    io: P,
+}
+
+pub struct WritePort<P: PortIo = X86PortIo> {
+    com: ComPort,
+    io: P,
+}
+
+impl ReadPort {
+    pub const fn new(com: ComPort) -> Self {
+        Self::with_io(com, X86PortIo)
+    }
+}
+
+impl<P: PortIo> ReadPort<P> {
+    pub const fn with_io(com: ComPort, io: P) -> Self {
+        Self { com, io }
+    }
+
+    pub fn try_read(&self) -> Result<u8, SerialError> {
+        let status = rr(&self.io, self.com, ri::LINE_STATUS_REGISTER);
+        check_line_status(status)?;
+        if status & RegisterMask::DATA_READY != 0 {
+            Ok(rr(&self.io, self.com, ri::RECEIVE_BUFFER_REGISTER))
+        } else {
+            Err(SerialError::Busy)
+        }
+    }
+
+    pub fn read(&self) -> Result<u8, SerialError> {
+        loop {
+            match self.try_read() {
+                Ok(v) => return Ok(v),
+                Err(SerialError::Busy) => {}
+                Err(e) => return Err(e),
+            }
+            hint::spin_loop();
+        }
+    }
+
+    pub fn read_line(&self) -> Result<String, SerialError> {
+        let mut s = String::new();
+        loop {
+            let c = self.read()?;
+            if c == b'\r' {
+                return Ok(s);
+            }
+            if let Ok(c) = core::str::from_utf8(&[c]) {
+                s += c;
+            }
+        }
+    }
+}
+
+impl WritePort {
+    pub const fn new(com: ComPort) -> Self {
+        Self::with_io(com, X86PortIo)
+    }
+}
+
+impl<P: PortIo> WritePort<P> {
+    pub const fn with_io(com: ComPort, io: P) -> Self {
+        Self { com, io }
+    }
+
+    pub fn try_write(&self, value: u8) -> Result<(), SerialError> {
+        let status = rr(&self.io, self.com, ri::LINE_STATUS_REGISTER);
+        check_line_status(status)?;
+        if status & RegisterMask::TRANSMITTER_HOLDING_REGISTER != 0 {
+            wr(&self.io, self.com, ri::TRANSMIT_BUFFER_REGISTER, value);
+            Ok(())
+        } else {
+            Err(SerialError::Busy)
+        }
+    }
+
+    pub fn write(&self, value: u8) {
+        while self.try_write(value).is_err() {
+            hint::spin_loop();
+        }
+    }
+
+    pub fn flush(&self) {
+        while rr(&self.io, self.com, ri::LINE_STATUS_REGISTER) & RegisterMask::TRANSMITTER_EMPTY
+            == 0
+        {
+            hint::spin_loop();
+        }
+    }
+}
+
+impl<P: PortIo> fmt::Write for WritePort<P> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c.is_ascii() {
+                self.write(c as u8);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use port_io::mock::MockPortIo;
+
+    use super::*;
+
+    const COM1: u16 = ComPort::COM1 as u16;
+    const LSR: u16 = COM1 + ri::LINE_STATUS_REGISTER as u16;
+
+    #[test]
+    fn init_programs_divisor_and_line_control() {
+        let io = MockPortIo::new();
+        init_with_io(&io, ComPort::COM1, BaudRate::BAUD_9600);
+
+        assert_eq!(io.writes_to(COM1 + 3), [0b1000_0000, 0b0000_0011]);
+        assert_eq!(io.writes_to(COM1), [12]);
+        assert_eq!(io.writes_to(COM1 + 1), [0, 0]);
+        assert_eq!(io.writes_to(COM1 + 4), [0]);
+    }
+
+    #[test]
+    fn receive_interrupt_sets_out2() {
+        let io = MockPortIo::new();
+        enable_receive_interrupt_with_io(&io, ComPort::COM3);
+
+        let com3 = ComPort::COM3 as u16;
+        assert_eq!(io.writes_to(com3 + 1), [1]);
+        assert_eq!(io.writes_to(com3 + 4), [1 << 3]);
+        assert!(io.writes_to(COM1 + 1).is_empty());
+    }
+
+    #[test]
+    fn read_reports_busy_and_errors() {
+        let io = MockPortIo::new();
+        let port = ReadPort::with_io(ComPort::COM1, &io);
+
+        assert_eq!(port.try_read(), Err(SerialError::Busy));
+
+        io.set(LSR, RegisterMask::PARITY_ERROR as u32);
+        assert_eq!(port.try_read(), Err(SerialError::ParityError));
+
+        io.set(LSR, RegisterMask::DATA_READY as u32);
+        io.queue_reads(COM1, &[b'x' as u32]);
+        assert_eq!(port.try_read(), Ok(b'x'));
+    }
+
+    #[test]
+    fn read_line_stops_at_carriage_return() {
+        let io = MockPortIo::new();
+        io.set(LSR, RegisterMask::DATA_READY as u32);
+        io.queue_reads(COM1, &[b'o' as u32, b'k' as u32, b'\r' as u32]);
+
+        let port = ReadPort::with_io(ComPort::COM1, &io);
+        assert_eq!(port.read_line().unwrap(), "ok");
+    }
+
+    #[test]
+    fn write_waits_for_holding_register_and_skips_non_ascii() {
+        let com2 = ComPort::COM2 as u16;
+        let io = MockPortIo::new();
+        io.queue_reads(com2 + ri::LINE_STATUS_REGISTER as u16, &[0, 0]);
+        io.set(
+            com2 + ri::LINE_STATUS_REGISTER as u16,
+            RegisterMask::TRANSMITTER_HOLDING_REGISTER as u32,
+        );
+
+        let mut port = WritePort::with_io(ComPort::COM2, &io);
+        port.write_str("hä!").unwrap();
+
+        assert_eq!(io.writes_to(com2), [b'h' as u32, b'!' as u32]);
+        assert!(io.writes_to(COM1).is_empty());
+    }
+}
@@ -9,6 +9,7 @@ bits 16
 %define STACK_STRIDE        0x0B10
 %define STACK_BASE_ADDR     0x0B20
 %define ENTRY_FUNCTION_ADDR 0x0B30
+%define STAGE_ADDR          0x0B40
 
 
 real_mode:
@@ -22,6 +23,9 @@ real_mode:
     mov ds, ax
     mov es, ax
 
+    ; report the trampoline as reached (the bsp starts one ap at a time)
+    mov byte [STAGE_ADDR], 1
+
     ; set cr3 to same l4 page table as the bsp
     mov ecx, [L4_TABLE_ADDR]
     mov cr3, ecx
@@ -75,6 +79,9 @@ long_mode:
     mov gs, ax
     mov ss, ax
 
+    ; report long mode as entered
+    mov byte [STAGE_ADDR], 2
+
     ; dont emulate coprocessor (only monitor)
     mov rax, cr0
     and rax, ~(1 << 2)                  
@@ -1,9 +1,19 @@
 use core::ptr::NonNull;
 
-use acpi::{AcpiTables, PhysicalMapping};
-use spin::Mutex;
+use acpi::{
+    address::{AddressSpace, GenericAddress},
+    fadt::Fadt,
+    mcfg::Mcfg,
+    platform::interrupt::{Polarity, TriggerMode},
+    AcpiTable, AcpiTables, PhysicalMapping,
+};
+use alloc::vec::Vec;
 
-use crate::{get_boot_info, memory::physical_memory_offset};
+use crate::{
+    get_boot_info,
+    lock_debug::{self, TrackedMutex},
+    memory::{phys_to_virt, physical_memory_offset},
+};
 
 #[derive(Clone)]
 pub struct AcpiHandler {}
@@ -24,8 +34,72 @@ impl acpi::AcpiHandler for AcpiHandler {
 
 pub struct Acpi {
     pub acpi_tables: AcpiTables<AcpiHandler>,
-    pub local_apic_ptr: *mut (),
+    pub local_apic_phys_addr: u64,
+    // the enabled aps, only the started ones after smp::init_smp
     pub ap_count: u64,
+    // local apic ids of the bsp and the enabled aps (in madt order)
+    pub processor_apic_ids: Vec<u32>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub isa_irq_overrides: Vec<IsaIrqOverride>,
+    // the hpet table is optional
+    pub hpet_phys_addr: Option<u64>,
+    // empty without MCFG (pci configuration space only through ports)
+    pub pci_ecam_regions: Vec<PciEcamRegion>,
+    pub power: PowerInfo,
+}
+
+// an io apic handles the global system interrupts (gsis) starting at gsi_base
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub phys_addr: u64,
+    pub gsi_base: u32,
+}
+
+// The pci express configuration space of the buses start_bus..=end_bus of a segment group is memory mapped,
+// phys_addr is the address of bus 0 (1 MiB per bus)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciEcamRegion {
+    pub phys_addr: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+// fixed hardware registers for shutdown, reset and the power button (see power.rs)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PowerInfo {
+    // isa irq of the system control interrupt
+    pub sci_irq: u8,
+    // the value written to the smi command port hands the fixed hardware over from the firmware (acpi mode)
+    pub smi_command_port: Option<u16>,
+    pub acpi_enable_value: u8,
+    // status registers, the enable registers follow them
+    pub pm1a_event_port: Option<u16>,
+    pub pm1b_event_port: Option<u16>,
+    // of both registers together
+    pub pm1_event_length: u8,
+    pub pm1a_control_port: Option<u16>,
+    pub pm1b_control_port: Option<u16>,
+    // SLP_TYPa and SLP_TYPb of the \_S5 object (soft off)
+    pub s5_sleep_types: Option<(u8, u8)>,
+    pub reset_register: Option<ResetRegister>,
+    pub reset_value: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ResetRegister {
+    Io(u16),
+    Memory(u64),
+}
+
+// isa irqs are identity mapped to gsis (edge triggered, active high) unless overridden
+#[derive(Debug, Clone, Copy)]
+pub struct IsaIrqOverride {
+    pub isa_irq: u8,
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
 }
 
 impl Acpi {
@@ -37,28 +111,59 @@ impl Acpi {
 
         let platform_info = acpi_tables.platform_info().unwrap();
 
-        let local_apic_address =
-            if let acpi::InterruptModel::Apic(ref apic) = platform_info.interrupt_model {
-                apic.local_apic_address
-            } else {
-                panic!("Apic not supported");
-            };
-
-        let ap_count = platform_info
-            .processor_info
-            .as_ref()
-            .unwrap()
+        let acpi::InterruptModel::Apic(ref apic) = platform_info.interrupt_model else {
+            panic!("Apic not supported");
+        };
+        let local_apic_address = apic.local_apic_address;
+        let io_apics = apic
+            .io_apics
+            .iter()
+            .map(|io_apic| IoApicInfo {
+                id: io_apic.id,
+                phys_addr: u64::from(io_apic.address),
+                gsi_base: io_apic.global_system_interrupt_base,
+            })
+            .collect();
+        let isa_irq_overrides = apic
+            .interrupt_source_overrides
+            .iter()
+            .map(|source_override| IsaIrqOverride {
+                isa_irq: source_override.isa_source,
+                gsi: source_override.global_system_interrupt,
+                active_low: matches!(source_override.polarity, Polarity::ActiveLow),
+                level_triggered: matches!(source_override.trigger_mode, TriggerMode::Level),
+            })
+            .collect();
+
+        let processor_info = platform_info.processor_info.as_ref().unwrap();
+        let aps = processor_info
             .application_processors
             .iter()
-            .filter(|e| e.state != acpi::platform::ProcessorState::Disabled && e.is_ap)
-            .count() as u64;
+            .filter(|e| e.state != acpi::platform::ProcessorState::Disabled && e.is_ap);
+        let ap_count = aps.clone().count() as u64;
+        let processor_apic_ids = core::iter::once(&processor_info.boot_processor)
+            .chain(aps)
+            .map(|processor| processor.local_apic_id)
+            .collect();
 
         core::mem::drop(platform_info);
 
+        let hpet_phys_addr = acpi::HpetInfo::new(&acpi_tables)
+            .ok()
+            .map(|hpet| hpet.base_address as u64);
+        let pci_ecam_regions = pci_ecam_regions(&acpi_tables);
+        let power = power_info(&acpi_tables);
+
         let mut s = Self {
             acpi_tables,
-            local_apic_ptr: (physical_memory_offset().as_u64() + local_apic_address) as *mut (),
+            local_apic_phys_addr: local_apic_address,
             ap_count,
+            processor_apic_ids,
+            io_apics,
+            isa_irq_overrides,
+            hpet_phys_addr,
+            pci_ecam_regions,
+            power,
         };
         s.log_proccessor_info(log::Level::Trace);
         s
@@ -73,9 +178,165 @@ impl Acpi {
     }
 }
 
+fn pci_ecam_regions(acpi_tables: &AcpiTables<AcpiHandler>) -> Vec<PciEcamRegion> {
+    let Ok(mcfg) = acpi_tables.find_table::<Mcfg>() else {
+        log::info!("No MCFG, PCI configuration space is accessed through ports");
+        return Vec::new();
+    };
+    let table = unsafe {
+        core::slice::from_raw_parts(
+            mcfg.virtual_start().as_ptr().cast::<u8>(),
+            mcfg.header().length as usize,
+        )
+    };
+    mcfg_entries(table)
+}
+
+// table header and reserved bytes
+const MCFG_ENTRIES_OFFSET: usize = 44;
+const MCFG_ENTRY_SIZE: usize = 16;
+
+// Decodes the configuration space base address allocation structures of the MCFG (including its header)
+pub fn mcfg_entries(table: &[u8]) -> Vec<PciEcamRegion> {
+    table
+        .get(MCFG_ENTRIES_OFFSET..)
+        .unwrap_or_default()
+        .chunks_exact(MCFG_ENTRY_SIZE)
+        .map(|entry| PciEcamRegion {
+            phys_addr: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+            segment_group: u16::from_le_bytes(entry[8..10].try_into().unwrap()),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+        .collect()
+}
+
+fn power_info(acpi_tables: &AcpiTables<AcpiHandler>) -> PowerInfo {
+    let Ok(fadt) = acpi_tables.find_table::<Fadt>() else {
+        log::warn!("No FADT, shutdown and reset are not supported by ACPI");
+        return PowerInfo::default();
+    };
+    let io_port = |register: GenericAddress| {
+        (matches!(register.address_space, AddressSpace::SystemIo) && register.address != 0)
+            .then_some(register.address as u16)
+    };
+    let reset_register = fadt
+        .reset_register()
+        .ok()
+        .filter(|register| register.address != 0)
+        .and_then(|register| match register.address_space {
+            AddressSpace::SystemIo => Some(ResetRegister::Io(register.address as u16)),
+            AddressSpace::SystemMemory => Some(ResetRegister::Memory(register.address)),
+            _ => None,
+        });
+    let s5_sleep_types = acpi_tables.dsdt().ok().and_then(|dsdt| {
+        let aml = unsafe {
+            core::slice::from_raw_parts(
+                phys_to_virt(x86_64::PhysAddr::new(dsdt.address as u64)).as_ptr::<u8>(),
+                dsdt.length as usize,
+            )
+        };
+        s5_sleep_types(aml)
+    });
+
+    PowerInfo {
+        sci_irq: fadt.sci_interrupt as u8,
+        smi_command_port: (fadt.smi_cmd_port != 0).then_some(fadt.smi_cmd_port as u16),
+        acpi_enable_value: fadt.acpi_enable,
+        pm1a_event_port: fadt.pm1a_event_block().ok().and_then(io_port),
+        pm1b_event_port: fadt.pm1b_event_block().ok().flatten().and_then(io_port),
+        pm1_event_length: fadt.pm1_event_length,
+        pm1a_control_port: fadt.pm1a_control_block().ok().and_then(io_port),
+        pm1b_control_port: fadt.pm1b_control_block().ok().flatten().and_then(io_port),
+        s5_sleep_types,
+        reset_register,
+        reset_value: fadt.reset_value,
+    }
+}
+
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ROOT_PREFIX: u8 = b'\\';
+
+// Finds Name(_S5, Package() {SLP_TYPa, SLP_TYPb, ..}) in the aml of the dsdt without interpreting it
+// (firmware defines it statically)
+pub fn s5_sleep_types(aml: &[u8]) -> Option<(u8, u8)> {
+    let mut start = 0;
+    while let Some(position) = aml[start..].windows(4).position(|name| name == b"_S5_") {
+        let name = start + position;
+        start = name + 4;
+        let named = aml[..name].ends_with(&[AML_NAME_OP])
+            || aml[..name].ends_with(&[AML_NAME_OP, AML_ROOT_PREFIX]);
+        if !named || aml.get(start) != Some(&AML_PACKAGE_OP) {
+            continue;
+        }
+        // the upper two bits of the package length count its following bytes, then comes the element count
+        let length_bytes = (*aml.get(start + 1)? >> 6) as usize;
+        let elements = aml.get(start + 3 + length_bytes..)?;
+        let (a, elements) = aml_byte_integer(elements)?;
+        let (b, _) = aml_byte_integer(elements)?;
+        return Some((a, b));
+    }
+    None
+}
+
+fn aml_byte_integer(aml: &[u8]) -> Option<(u8, &[u8])> {
+    match *aml.first()? {
+        AML_ZERO_OP => Some((0, &aml[1..])),
+        AML_ONE_OP => Some((1, &aml[1..])),
+        AML_BYTE_PREFIX => Some((*aml.get(1)?, aml.get(2..)?)),
+        _ => None,
+    }
+}
+
+const SDT_HEADER_LEN: usize = 36;
+const RSDP_REVISION: usize = 15;
+const RSDP_RSDT_ADDRESS: usize = 16;
+const RSDP_XSDT_ADDRESS: usize = 24;
+const RSDP_LEN: usize = 32;
+
+// Finds a table (including its header) through the rsdt or xsdt without the acpi crate,
+// so it can be used before the heap exists
+pub fn find_raw_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let rsdp_addr = *get_boot_info().rsdp_addr.as_ref()?;
+    let rsdp = unsafe {
+        core::slice::from_raw_parts(
+            phys_to_virt(x86_64::PhysAddr::new(rsdp_addr)).as_ptr::<u8>(),
+            RSDP_LEN,
+        )
+    };
+    let xsdt_addr = u64::from_le_bytes(rsdp[RSDP_XSDT_ADDRESS..][..8].try_into().unwrap());
+    let (root_addr, entry_size) = if rsdp[RSDP_REVISION] >= 2 && xsdt_addr != 0 {
+        (xsdt_addr, 8)
+    } else {
+        let rsdt_addr = u32::from_le_bytes(rsdp[RSDP_RSDT_ADDRESS..][..4].try_into().unwrap());
+        (u64::from(rsdt_addr), 4)
+    };
+    raw_table(root_addr)
+        .get(SDT_HEADER_LEN..)?
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let mut addr = [0; 8];
+            addr[..entry_size].copy_from_slice(entry);
+            raw_table(u64::from_le_bytes(addr))
+        })
+        .find(|table| table.starts_with(signature))
+}
+
+fn raw_table(phys_addr: u64) -> &'static [u8] {
+    let start = phys_to_virt(x86_64::PhysAddr::new(phys_addr)).as_ptr::<u8>();
+    unsafe {
+        let length = u32::from_le_bytes(*start.add(4).cast::<[u8; 4]>());
+        core::slice::from_raw_parts(start, length as usize)
+    }
+}
+
 unsafe impl Send for Acpi {}
 unsafe impl Sync for Acpi {}
 
 lazy_static::lazy_static! {
-    pub static ref ACPI: Mutex<Acpi> = Mutex::new(Acpi::new());
+    pub static ref ACPI: TrackedMutex<Acpi> = lock_debug::tracked("ACPI", Acpi::new());
 }
@@ -1,15 +1,12 @@
-use core::alloc::GlobalAlloc;
+use core::{alloc::GlobalAlloc, ptr::NonNull};
 
 use alloc::alloc::Layout;
-use buddy_system_allocator::LockedHeapWithRescue;
+use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+use spin::Mutex;
 
-use x86_64::{
-    align_up,
-    structures::paging::{Page, PageTableFlags},
-    VirtAddr,
-};
+use x86_64::{align_up, structures::paging::PageTableFlags, VirtAddr};
 
-use crate::constants::v;
+use crate::{constants::v, tlb::PendingShootdown};
 
 #[global_allocator]
 pub static ALLOCATOR: KernelAllocatorWrapper = KernelAllocatorWrapper {};
@@ -18,32 +15,29 @@ lazy_static::lazy_static! {
     pub static ref INNER_KERNEL_ALLOC: LockedHeapWithRescue<38> = {
         log::info!("Initializing kernel heap");
         LockedHeapWithRescue::new(|heap, layout|{
+            if restore_trimmed_blocks(heap) && heap.alloc(*layout).map(|ptr| heap.dealloc(ptr, *layout)).is_ok() {
+                return;
+            }
+
             let old_size = heap.stats_total_bytes() as u64;
             let min_size_to_add = (align_up(old_size, layout.align() as u64) - old_size + layout.size() as u64).next_power_of_two();
 
             let new_total_size = (min_size_to_add + old_size).next_power_of_two().max(min_size_to_add * 2);
-            let new_added_size = new_total_size - old_size;
+            let new_added_size = align_up(new_total_size - old_size, 4096);
 
-            let start = v::KERNEL_HEAP_START + heap.stats_total_bytes() as u64;
+            let start = crate::kaslr::layout().heap_start() + heap.stats_total_bytes() as u64;
 
-            let page_range = {
-                let mapping_start = VirtAddr::new(start);
-                let mapping_end = mapping_start + new_added_size - 1u64;
-                let mapping_start_page = Page::containing_address(mapping_start);
-                let mapping_end_page = Page::containing_address(mapping_end);
-                Page::range_inclusive(mapping_start_page, mapping_end_page)
-            };
-
-            let allocation_page_count = page_range.count();
+            // heap growth is a power of two and at least doubles, so larger steps are 2MiB aligned and use huge pages
             log::trace!(
                 "Kernel heap grows by {} pages ({}MB)",
-                 allocation_page_count, allocation_page_count * 4096 / 1024 / 1024);
+                 new_added_size / 4096, new_added_size / 1024 / 1024);
 
             {
                 let mut memory = crate::memory::MEMORY.lock();
-                for page in page_range {
-                    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-                    memory.map_ram_kernel(page, flags);
+                // the allocation fails (returns null), infallible allocations panic in the alloc error handler
+                if let Err(e) = memory.try_map_ram_kernel_region(VirtAddr::new(start), new_added_size, HEAP_FLAGS) {
+                    log::error!("Kernel heap can not grow by {new_added_size:#x} bytes for {layout:?}: {e:?}");
+                    return;
                 }
                 memory.log_memory_utilization(log::Level::Trace);
                 log::trace!("Kernel heap stats: total_bytes: {}, alloc_actual: {}, alloc_user: {}",
@@ -53,68 +47,111 @@ lazy_static::lazy_static! {
                 );
             }
 
-            let start_addr = page_range.start.start_address().as_u64() as usize;
-            let end_addr = page_range.end.start_address().as_u64() as usize + 4096;
+            let start_addr = start as usize;
+            let end_addr = (start + new_added_size) as usize;
             unsafe {heap.add_to_heap(start_addr, end_addr);}
         })
     };
 }
 
-pub fn create_user_heap() -> UserAllocatorWrapper {
-    log::debug!("Initializing user heap");
-    let inner = LockedHeapWithRescue::<38>::new(|heap, layout| {
-        let old_size = heap.stats_total_bytes() as u64;
-        let min_size_to_add = (align_up(old_size, layout.align() as u64) - old_size
-            + layout.size() as u64)
-            .next_power_of_two();
-
-        let new_total_size = (min_size_to_add + old_size)
-            .next_power_of_two()
-            .max(min_size_to_add * 2);
-        let new_added_size = new_total_size - old_size;
-
-        let start = v::USER_HEAP_START + heap.stats_total_bytes() as u64;
-
-        let page_range = {
-            let mapping_start = VirtAddr::new(start);
-            let mapping_end = mapping_start + new_added_size - 1u64;
-            let mapping_start_page = Page::containing_address(mapping_start);
-            let mapping_end_page = Page::containing_address(mapping_end);
-            Page::range_inclusive(mapping_start_page, mapping_end_page)
-        };
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
 
-        let allocation_page_count = page_range.count();
-        log::trace!(
-            "User heap grows by {} pages ({}MB)",
-            allocation_page_count,
-            allocation_page_count * 4096 / 1024 / 1024
-        );
+// Trimmed blocks stay allocated in the buddy allocator (so they are never handed out), but their pages are unmapped.
+// They are mapped and freed again before the heap grows. Only accessed while holding INNER_KERNEL_ALLOC
+const MIN_TRIM_ORDER: usize = 16; // 64KiB
+const MAX_TRIMMED_BLOCKS: usize = 128;
 
-        {
-            let mut memory = crate::memory::MEMORY.lock();
-            for page in page_range {
-                let flags =
-                    PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-                memory.map_ram_user(page, flags);
-            }
-            memory.log_memory_utilization(log::Level::Trace);
-        }
+struct TrimmedBlocks {
+    blocks: [(usize, Layout); MAX_TRIMMED_BLOCKS],
+    len: usize,
+    reclaimed_pages_total: u64,
+}
 
-        let start_addr = page_range.start.start_address().as_u64() as usize;
-        let end_addr = page_range.end.start_address().as_u64() as usize + 4096;
-        unsafe {
-            heap.add_to_heap(start_addr, end_addr);
+impl TrimmedBlocks {
+    fn trimmed_bytes(&self) -> usize {
+        self.blocks[..self.len]
+            .iter()
+            .map(|(_, layout)| layout.size())
+            .sum()
+    }
+}
+
+static TRIMMED: Mutex<TrimmedBlocks> = Mutex::new(TrimmedBlocks {
+    blocks: [(0, Layout::new::<()>()); MAX_TRIMMED_BLOCKS],
+    len: 0,
+    reclaimed_pages_total: 0,
+});
+// held for the whole trim, only by trim
+static TRIMMING: Mutex<()> = Mutex::new(());
+
+// maps the trimmed blocks again and returns them to the heap, returns false if there were none
+fn restore_trimmed_blocks(heap: &mut Heap<38>) -> bool {
+    let mut trimmed = TRIMMED.lock();
+    if trimmed.len == 0 {
+        return false;
+    }
+    let mut memory = crate::memory::MEMORY.lock();
+    while trimmed.len > 0 {
+        let (start, layout) = trimmed.blocks[trimmed.len - 1];
+        let mapped = memory.try_map_ram_kernel_region(
+            VirtAddr::new(start as u64),
+            layout.size() as u64,
+            HEAP_FLAGS,
+        );
+        if mapped.is_err() {
+            break;
         }
+        trimmed.len -= 1;
+        heap.dealloc(NonNull::new(start as *mut u8).unwrap(), layout);
+    }
+    log::trace!("Kernel heap restored trimmed blocks");
+    true
+}
+
+// the whole user heap region is added up front, its pages are mapped on first access (see memory::handle_demand_paging_fault)
+pub fn create_user_heap() -> UserAllocatorWrapper {
+    log::debug!("Initializing user heap");
+    let inner = LockedHeapWithRescue::<38>::new(|heap, layout| {
+        log::error!(
+            "User heap exhausted while allocating {layout:?} (total_bytes: {})",
+            heap.stats_total_bytes()
+        );
+    });
+    crate::user_access::user_access(|| unsafe {
+        inner
+            .lock()
+            .add_to_heap(v::USER_HEAP_START as usize, v::USER_END as usize);
     });
     UserAllocatorWrapper { inner }
 }
 
 pub struct KernelAllocatorWrapper {}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+    pub layout: Layout,
+}
+
 pub struct UserAllocatorWrapper {
     pub inner: LockedHeapWithRescue<38>,
 }
 
+impl UserAllocatorWrapper {
+    // The user heap is demand paged, so the buddy allocator would hand out more memory than there is.
+    // Allocations that can not be backed by free frames fail (null is returned to the application)
+    pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pages = align_up(layout.size() as u64, 4096) / 4096;
+        let free_frames = crate::memory::MEMORY.lock().free_frames();
+        if pages > free_frames {
+            log::warn!("User allocation of {layout:?} failed: only {free_frames} free frames");
+            return core::ptr::null_mut();
+        }
+        self.inner.alloc(layout)
+    }
+}
+
 impl KernelAllocatorWrapper {
     pub fn log_heap_stats(&self, level: log::Level) {
         let _ = self;
@@ -126,27 +163,292 @@ impl KernelAllocatorWrapper {
             inner.stats_alloc_actual(),
             inner.stats_alloc_user(),
         );
+        let trimmed = TRIMMED.lock();
+        log::log!(
+            level,
+            "Kernel heap trim stats: currently trimmed: {} pages in {} blocks, reclaimed in total: {} pages",
+            trimmed.trimmed_bytes() / 4096,
+            trimmed.len,
+            trimmed.reclaimed_pages_total,
+        );
+        #[cfg(feature = "heap-debug")]
+        crate::heap_debug::log_stats(level);
+    }
+
+    // bytes handed out by the buddy allocator (the slabs count as a whole)
+    pub fn heap_used(&self) -> usize {
+        let _ = self;
+        INNER_KERNEL_ALLOC.lock().stats_alloc_actual()
+    }
+
+    // Fallible allocation, for allocations whose size is not under kernel control (collections can use try_reserve)
+    pub fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError { layout })
+    }
+
+    // Returns the ram behind all free heap blocks of at least 64KiB to the frame allocator (the virtual range stays reserved).
+    // Returns the number of reclaimed pages
+    pub fn trim(&self) -> u64 {
+        let _ = self;
+        // one trim at a time, so the free slots of TRIMMED stay free until the blocks are added
+        let _trimming = TRIMMING.lock();
+
+        // unmapped, but only added to TRIMMED after the shootdown (restore_trimmed_blocks could map them again before)
+        let mut unmapped = [(0usize, Layout::new::<()>()); MAX_TRIMMED_BLOCKS];
+        let mut unmapped_len = 0;
+        let mut pending = PendingShootdown::none();
+        {
+            let mut heap = INNER_KERNEL_ALLOC.lock();
+            let free_slots = MAX_TRIMMED_BLOCKS - TRIMMED.lock().len;
+            let mut memory = crate::memory::MEMORY.lock();
+
+            // blocks that can not be unmapped (part of a huge page) are only freed after the search,
+            // otherwise they would be found again
+            let mut skipped = [(0usize, Layout::new::<()>()); MAX_TRIMMED_BLOCKS];
+            let mut skipped_len = 0;
+
+            // starting with the largest order guarantees that no free block gets split
+            'orders: for order in (MIN_TRIM_ORDER..38).rev() {
+                let layout = Layout::from_size_align(1 << order, 1 << order).unwrap();
+                while unmapped_len < free_slots && skipped_len < MAX_TRIMMED_BLOCKS {
+                    let Ok(block) = heap.alloc(layout) else {
+                        continue 'orders;
+                    };
+                    let start = block.as_ptr() as usize;
+                    let region = unsafe {
+                        memory.unmap_ram_kernel_region(
+                            VirtAddr::new(start as u64),
+                            layout.size() as u64,
+                        )
+                    };
+                    if let Some(region) = region {
+                        pending.merge(region);
+                        unmapped[unmapped_len] = (start, layout);
+                        unmapped_len += 1;
+                    } else {
+                        skipped[skipped_len] = (start, layout);
+                        skipped_len += 1;
+                    }
+                }
+                break;
+            }
+
+            for (start, layout) in &skipped[..skipped_len] {
+                heap.dealloc(NonNull::new(*start as *mut u8).unwrap(), *layout);
+            }
+        }
+        // The other cores can't acknowledge the shootdown while they wait for the heap or MEMORY with interrupts
+        // disabled (e.g. a task switch dropping a task), so it waits until both are unlocked
+        pending.flush();
+
+        let reclaimed_pages = {
+            // TRIMMED is only accessed while holding the heap
+            let _heap = INNER_KERNEL_ALLOC.lock();
+            let mut trimmed = TRIMMED.lock();
+            let mut reclaimed_pages = 0;
+            for block in &unmapped[..unmapped_len] {
+                let index = trimmed.len;
+                trimmed.blocks[index] = *block;
+                trimmed.len += 1;
+                reclaimed_pages += block.1.size() as u64 / 4096;
+            }
+            trimmed.reclaimed_pages_total += reclaimed_pages;
+            reclaimed_pages
+        };
+        log::debug!("Kernel heap trimmed: reclaimed {reclaimed_pages} pages");
+        reclaimed_pages
     }
 }
 
-unsafe impl GlobalAlloc for KernelAllocatorWrapper {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+// Slab layer for small allocations: every size class carves 4KiB blocks of the buddy heap into equally sized objects.
+// Slab pages are never returned to the buddy heap, freed objects are reused by the same size class
+const SLAB_SIZE_CLASSES: [usize; 6] = [16, 32, 64, 128, 256, 512];
+const SLAB_PAGE_LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(4096, 4096) };
+
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+struct SlabClass {
+    free: Option<NonNull<FreeObject>>,
+    stats: SlabStats,
+}
+
+unsafe impl Send for SlabClass {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub object_size: usize,
+    pub pages: u64,
+    pub in_use: u64,
+    pub total_allocations: u64,
+}
+
+impl SlabClass {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            free: None,
+            stats: SlabStats {
+                object_size,
+                pages: 0,
+                in_use: 0,
+                total_allocations: 0,
+            },
+        }
+    }
+
+    // null if the class has no free object, see add_page
+    unsafe fn alloc(&mut self) -> *mut u8 {
+        let Some(object) = self.free else {
+            return core::ptr::null_mut();
+        };
+        self.free = unsafe { object.as_ref().next };
+        self.stats.in_use += 1;
+        self.stats.total_allocations += 1;
+        object.as_ptr().cast()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        let object = ptr.cast::<FreeObject>();
+        unsafe { object.write(FreeObject { next: self.free }) };
+        self.free = NonNull::new(object);
+        self.stats.in_use -= 1;
+    }
+
+    // The page comes from the buddy heap, allocated without holding the lock of the class (the heap may grow and
+    // map frames under MEMORY meanwhile, the heap lock must not nest inside a slab lock)
+    unsafe fn add_page(&mut self, page: *mut u8) {
+        let object_size = self.stats.object_size;
+        for offset in (0..SLAB_PAGE_LAYOUT.size()).step_by(object_size).rev() {
+            let object = unsafe { page.add(offset) }.cast::<FreeObject>();
+            unsafe { object.write(FreeObject { next: self.free }) };
+            self.free = NonNull::new(object);
+        }
+        self.stats.pages += 1;
+    }
+}
+
+static SLABS: [Mutex<SlabClass>; SLAB_SIZE_CLASSES.len()] = [
+    Mutex::new(SlabClass::new(SLAB_SIZE_CLASSES[0])),
+    Mutex::new(SlabClass::new(SLAB_SIZE_CLASSES[1])),
+    Mutex::new(SlabClass::new(SLAB_SIZE_CLASSES[2])),
+    Mutex::new(SlabClass::new(SLAB_SIZE_CLASSES[3])),
+    Mutex::new(SlabClass::new(SLAB_SIZE_CLASSES[4])),
+    Mutex::new(SlabClass::new(SLAB_SIZE_CLASSES[5])),
+];
+
+// objects are aligned to their size class, so the class has to cover the alignment as well
+fn slab_class(layout: &Layout) -> Option<&'static Mutex<SlabClass>> {
+    let size = layout.size().max(layout.align());
+    SLAB_SIZE_CLASSES
+        .iter()
+        .position(|&class_size| size <= class_size)
+        .map(|index| &SLABS[index])
+}
+
+impl KernelAllocatorWrapper {
+    pub fn slab_stats(&self) -> [SlabStats; SLAB_SIZE_CLASSES.len()] {
+        let _ = self;
+        core::array::from_fn(|i| SLABS[i].lock().stats)
+    }
+
+    pub fn log_slab_stats(&self, level: log::Level) {
+        for stats in self.slab_stats() {
+            log::log!(
+                level,
+                "Slab {: >3}B: {} objects in use, {} pages, {} allocations",
+                stats.object_size,
+                stats.in_use,
+                stats.pages,
+                stats.total_allocations,
+            );
+        }
+    }
+}
+
+impl KernelAllocatorWrapper {
+    // small allocations are not logged, they are served by the slab layer
+    unsafe fn allocate(&self, layout: Layout) -> *mut u8 {
+        let _ = self;
+        if let Some(slab) = slab_class(&layout) {
+            let object = slab.lock().alloc();
+            if !object.is_null() {
+                return object;
+            }
+            let page = INNER_KERNEL_ALLOC.alloc(SLAB_PAGE_LAYOUT);
+            if page.is_null() {
+                return page;
+            }
+            let mut slab = slab.lock();
+            slab.add_page(page);
+            return slab.alloc();
+        }
         log::trace!("Kernel allocating {:?}", layout);
         INNER_KERNEL_ALLOC.alloc(layout)
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe fn free(&self, ptr: *mut u8, layout: Layout) {
+        let _ = self;
+        if let Some(slab) = slab_class(&layout) {
+            slab.lock().dealloc(ptr);
+            return;
+        }
         log::trace!("Kernel deallocating {:?}", layout);
         INNER_KERNEL_ALLOC.dealloc(ptr, layout);
     }
 
+    unsafe fn realloc_by_copy(&self, ptr: *mut u8, layout: Layout, new_layout: Layout) -> *mut u8 {
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_layout.size()));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocatorWrapper {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.allocate(layout);
+        #[cfg(feature = "heap-debug")]
+        crate::heap_debug::on_alloc(ptr, layout);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-debug")]
+        let Some((ptr, layout)) = crate::heap_debug::on_dealloc(ptr, layout) else {
+            return;
+        };
+        self.free(ptr, layout);
+    }
+
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        log::trace!("Kernel allocating zeroed {:?}", layout);
-        INNER_KERNEL_ALLOC.alloc_zeroed(layout)
+        if slab_class(&layout).is_none() {
+            log::trace!("Kernel allocating zeroed {:?}", layout);
+            let ptr = INNER_KERNEL_ALLOC.alloc_zeroed(layout);
+            #[cfg(feature = "heap-debug")]
+            crate::heap_debug::on_alloc(ptr, layout);
+            return ptr;
+        }
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            ptr.write_bytes(0, layout.size());
+        }
+        ptr
     }
 
+    // with heap-debug every reallocation moves, so the old block is checked and quarantined
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        log::trace!("Kernel reallocating {:?} to {}", layout, new_size);
-        INNER_KERNEL_ALLOC.realloc(ptr, layout, new_size)
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (slab_class(&layout), slab_class(&new_layout)) {
+            _ if cfg!(feature = "heap-debug") => self.realloc_by_copy(ptr, layout, new_layout),
+            (None, None) => {
+                log::trace!("Kernel reallocating {:?} to {}", layout, new_size);
+                INNER_KERNEL_ALLOC.realloc(ptr, layout, new_size)
+            }
+            (Some(old), Some(new)) if core::ptr::eq(old, new) => ptr,
+            _ => self.realloc_by_copy(ptr, layout, new_layout),
+        }
     }
 }
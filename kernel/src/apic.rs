@@ -1,220 +1,304 @@
 use core::{
+    arch::x86_64::__cpuid,
     num::NonZeroU64,
-    ptr::{self},
+    ops::{Deref, DerefMut},
+    sync::atomic::{fence, AtomicBool, Ordering},
 };
 
-use x86_64::instructions::interrupts;
+use local_apic::{set_tsc_deadline, ApicRegisters, LocalApic, X2Apic, XApic};
+use port_io::{VolatileMmio, X86Msr};
+use x86_64::{instructions::interrupts, PhysAddr};
 
-use bitfield::bitfield;
+use crate::{
+    ass,
+    smp::{get_cld, CoreLocalData},
+};
 
-use crate::{ass, smp::get_cld};
+pub use local_apic::{ipi, Offset};
 
-pub struct Apic {
-    local_apic_ptr: *mut u8,
+// time stamp counter and core crystal clock information
+const CPUID_TSC_LEAF: u32 = 0x15;
+// measuring time of the timer calibrations with the hpet
+const CALIBRATION_US: u64 = 10_000;
+
+// x2APIC mode is used if the cpu supports it (msr access, 32 bit ids), otherwise the memory mapped xAPIC
+#[derive(Clone, Copy)]
+enum Mode {
+    XApic(VolatileMmio),
+    X2Apic,
 }
 
-impl Apic {
-    const fn new(local_apic_ptr: *mut u8) -> Self {
-        Self { local_apic_ptr }
-    }
+pub enum Registers {
+    XApic(XApic<VolatileMmio>),
+    X2Apic(X2Apic<X86Msr>),
+}
 
-    fn init(&mut self) {
-        log::debug!("initializing APIC");
-        self.write(Offset::TaskPriority, 0); // set task priority to 0 (accept all interrupts)
-                                             // self.write(Offset::SpuriousInterruptVector, 0xFF); // set spurious interrupt vector to 0xFF
-                                             // self.write(Offset::SpuriousInterruptVector, 0x100); // enable apic
-        self.write(Offset::SpuriousInterruptVector, 0x11FF); // disable focus processor checking
-        self.init_timer();
+impl ApicRegisters for Registers {
+    #[inline]
+    fn read(&self, offset: Offset) -> u32 {
+        match self {
+            Self::XApic(registers) => registers.read(offset),
+            Self::X2Apic(registers) => registers.read(offset),
+        }
     }
 
-    pub fn signal_end_of_interrupt(&mut self) {
-        self.write(Offset::EndOfInterrupt, 0);
+    #[inline]
+    fn write(&self, offset: Offset, value: u32) {
+        match self {
+            Self::XApic(registers) => registers.write(offset, value),
+            Self::X2Apic(registers) => registers.write(offset, value),
+        }
     }
 
-    pub fn id(&mut self) -> u8 {
-        (self.read(Offset::Id) >> 24) as u8
+    #[inline]
+    fn read_interrupt_command(&self) -> u64 {
+        match self {
+            Self::XApic(registers) => registers.read_interrupt_command(),
+            Self::X2Apic(registers) => registers.read_interrupt_command(),
+        }
     }
 
-    pub fn write_interrupt_command(&mut self, cmd: ipi::InterruptCommand) {
-        self.write(Offset::InterruptCommandHigh, cmd.upper() as u32);
-        self.write(Offset::InterruptCommandLow, cmd.lower() as u32); //Order is important writing to low triggers command
+    #[inline]
+    fn write_interrupt_command(&self, value: u64) {
+        match self {
+            Self::XApic(registers) => registers.write_interrupt_command(value),
+            Self::X2Apic(registers) => registers.write_interrupt_command(value),
+        }
     }
 
-    pub fn read_interrupt_command(&mut self) -> ipi::InterruptCommand {
-        let high = self.read(Offset::InterruptCommandHigh);
-        let low = self.read(Offset::InterruptCommandLow);
-        let mut cmd = ipi::InterruptCommand(0);
-        cmd.set_upper(high as u64);
-        cmd.set_lower(low as u64);
-        cmd
+    #[inline]
+    fn id(&self) -> u32 {
+        match self {
+            Self::XApic(registers) => registers.id(),
+            Self::X2Apic(registers) => registers.id(),
+        }
     }
 
-    pub fn read(&mut self, offset: Offset) -> u32 {
-        unsafe { ptr::read_volatile(self.offset(offset)) }
+    #[inline]
+    fn destination(&self, id: u32) -> u64 {
+        match self {
+            Self::XApic(registers) => registers.destination(id),
+            Self::X2Apic(registers) => registers.destination(id),
+        }
     }
+}
 
-    pub fn write(&mut self, offset: Offset, value: u32) {
-        unsafe { ptr::write_volatile(self.offset(offset), value) }
+pub struct Apic {
+    inner: LocalApic<Registers>,
+}
+
+impl Apic {
+    const fn new(mode: Mode) -> Self {
+        let registers = match mode {
+            Mode::XApic(mmio) => Registers::XApic(XApic(mmio)),
+            Mode::X2Apic => Registers::X2Apic(X2Apic(X86Msr)),
+        };
+        Self {
+            inner: LocalApic::new(registers),
+        }
     }
 
-    unsafe fn offset<T>(&mut self, offset: Offset) -> *mut T {
-        self.local_apic_ptr
-            .wrapping_add(offset as usize)
-            .cast::<T>()
+    fn init(&mut self) {
+        log::debug!("initializing APIC");
+        self.enable(0xFF);
+        self.init_timer();
     }
 
     pub fn init_timer(&mut self) {
-        let _lock = AP_TIMER_INIT_LOCK.lock();
-
-        self.write(Offset::TimerDivideConfiguration, 0x3); // divider 16
-        self.write(Offset::TimerInitialCount, 0xFFFF_FFFF);
-
-        crate::pit::delay(50_000).unwrap(); // ~max pit delay
-
-        self.write(Offset::TimerLocalVectorTableEntry, 1 << 16); // stop timer
+        // the time stamp counter runs at the same rate on all cores, it is calibrated once
+        TSC_TICKS_PER_SECOND.call_once(calibrate_tsc);
+        // tsc deadlines do not depend on the bus clock of the apic timer
+        if uses_tsc_deadline() {
+            return;
+        }
 
-        let ticks_in_50ms = 0xFFFF_FFFF - self.read(Offset::TimerCurrentCount);
-        let ticks_per_second = ticks_in_50ms as u64 * 20;
+        // the hpet is read by all cores at once, the pit can only be used by one core at a time
+        let ticks_per_second = if let Some(hpet) = crate::hpet::get() {
+            let ticks = self.measure_timer_ticks(|| hpet.delay_us(CALIBRATION_US));
+            ticks as u64 * 1_000_000 / CALIBRATION_US
+        } else {
+            let _lock = AP_TIMER_INIT_LOCK.lock();
+            let ticks_in_50ms = self.measure_timer_ticks(|| {
+                crate::pit::delay(50_000).unwrap(); // ~max pit delay
+            });
+            ticks_in_50ms as u64 * 20
+        };
         get_cld().apic_timer_ticks_per_second =
             Some(NonZeroU64::new(ticks_per_second).expect("apic timer initialization failed"));
     }
 
-    pub fn start_timer(
-        &mut self,
-        interval_us: u32,
-        periodic: bool,
-        interrupt: fn(),
-    ) -> Result<(), ()> {
-        get_cld().apic_timer_interrupt_function = Some(interrupt);
-        let ticks_per_second = get_cld().apic_timer_ticks_per_second.unwrap().get();
+    // the timer interrupt runs the timer callbacks of the core (see timer_callbacks)
+    pub fn start_timer(&mut self, interval_us: u32, periodic: bool) -> Result<(), ()> {
+        let cld = get_cld();
+        if uses_tsc_deadline() {
+            let ticks_in_interval =
+                (tsc_ticks_per_second() as u128 * interval_us as u128 / 1_000_000) as u64;
+            if periodic {
+                ass!(ticks_in_interval > 0);
+            }
+            cld.tsc_deadline_interval = periodic.then_some(ticks_in_interval);
+            cld.tsc_deadline = rdtsc() + ticks_in_interval;
+            self.start_tsc_deadline_timer(crate::interrupts::TIMER_VECTOR);
+            // the lvt write has to reach the apic before the deadline is armed
+            fence(Ordering::SeqCst);
+            set_tsc_deadline(&X86Msr, cld.tsc_deadline);
+            return Ok(());
+        }
+
+        cld.tsc_deadline_interval = None;
+        let ticks_per_second = cld.apic_timer_ticks_per_second.unwrap().get();
         let ticks_in_interval: u64 = (ticks_per_second * interval_us as u64) / 1_000_000;
         let ticks_in_interval: u32 = ticks_in_interval.try_into().map_err(|_| ())?;
         if periodic {
             ass!(ticks_in_interval > 0);
         }
 
-        self.write(Offset::TimerLocalVectorTableEntry, 1 << 16);
-        // // Start timer as periodic on IRQ 0, divider 16, with the number of ticks we counted
-        self.write(
-            Offset::TimerLocalVectorTableEntry,
-            32 | if periodic { 0x20000 } else { 0x40000 },
-        );
-        self.write(Offset::TimerDivideConfiguration, 0x3);
-        self.write(Offset::TimerInitialCount, ticks_in_interval);
+        self.start_timer_ticks(crate::interrupts::TIMER_VECTOR, ticks_in_interval, periodic);
         Ok(())
     }
 
+    // no timer interrupts until start_timer (a pending one may still arrive)
     pub fn stop_timer(&mut self) {
-        self.write(Offset::TimerLocalVectorTableEntry, 1 << 16);
-    }
-}
-
-pub mod ipi {
-    use super::bitfield;
-
-    pub fn create_send_init_cmd() -> InterruptCommand {
-        let mut ic = InterruptCommand(0);
-        ic.set_interupt_vector(0);
-        ic.set_delivery_mode(5);
-        ic.set_destination_mode_logical(false);
-        ic.set_de_assert(false);
-        ic.set_not_de_assert(true);
-        ic.set_destination_type(3);
-        // ic.set_destination_type(0);
-        // ic.set_apic_id(apic_id as u64);
-        ic
-    }
-
-    pub fn create_startup_cmd(vector: u8) -> InterruptCommand {
-        let mut ic = InterruptCommand(0);
-        ic.set_interupt_vector(vector as u64);
-        ic.set_delivery_mode(6);
-        ic.set_destination_mode_logical(false);
-        ic.set_de_assert(false);
-        ic.set_not_de_assert(true);
-        ic.set_destination_type(3);
-        // ic.set_destination_type(0);
-        // ic.set_apic_id(apic_id as u64);
-        ic
-    }
-
-    bitfield! {
-        #[derive(Clone, Copy)]
-        pub struct InterruptCommand(u64);
-        impl Debug;
-        pub interupt_vector, set_interupt_vector: 7, 0;
-        pub delivery_mode, set_delivery_mode: 10, 8;
-        pub destination_mode_logical, set_destination_mode_logical: 11;
-        pub delivery_status, _: 12;
-        pub not_de_assert, set_not_de_assert: 14;
-        pub de_assert, set_de_assert: 15;
-        pub destination_type, set_destination_type: 19, 18;
-        pub apic_id, set_apic_id: (32+27), (32+24);
-        pub lower, set_lower : 31, 0;
-        pub upper, set_upper : 63, 32;
-    }
-}
-
-#[repr(usize)]
-pub enum Offset {
-    Id = 0x20,
-    Version = 0x30,
-    TaskPriority = 0x80,
-    ArbitrationPriority = 0x90,
-    ProcessorPriority = 0xa0,
-    EndOfInterrupt = 0xb0,
-    RemoteRead = 0xc0,
-    LocalDestination = 0xd0,
-    DestinationFormat = 0xe0,
-    SpuriousInterruptVector = 0xf0,
-    InService = 0x100,
-    TriggerMode = 0x180,
-    InterruptRequest = 0x200,
-    ErrorStatus = 0x280,
-    InterruptCommandLow = 0x300,
-    InterruptCommandHigh = 0x310,
-    TimerLocalVectorTableEntry = 0x320,
-    ThermalLocalVectorTableEntry = 0x330,
-    PerformanceCounterLocalVectorTableEntry = 0x340,
-    LocalInterrupt0VectorTableEntry = 0x350,
-    LocalInterrupt1VectorTableEntry = 0x360,
-    ErrorVectorTableEntry = 0x370,
-    TimerInitialCount = 0x380,
-    TimerCurrentCount = 0x390,
-    TimerDivideConfiguration = 0x3e0,
-    ExtendedApicFeature = 0x400,
-    ExtendedApicControl = 0x410,
-    SpecificEndOfInterrupt = 0x420,
-    InterruptEnable = 0x480,
-    ExtendedInterruptLocalVectorTable = 0x500,
-}
-
-static LOCAL_APIC_PTR: spin::Once<u64> = spin::Once::new();
+        let cld = get_cld();
+        cld.tsc_deadline_interval = None;
+        if uses_tsc_deadline() {
+            set_tsc_deadline(&X86Msr, 0);
+        }
+        self.inner.stop_timer();
+    }
+}
+
+// Called by the timer interrupt: tsc deadlines fire once, periodic timers are armed again with the next deadline.
+// Deadlines missed while interrupts were disabled are skipped instead of firing back to back
+pub fn rearm_timer(cld: &mut CoreLocalData) {
+    let Some(interval) = cld.tsc_deadline_interval else {
+        return;
+    };
+    let now = rdtsc();
+    cld.tsc_deadline += interval;
+    if cld.tsc_deadline <= now {
+        cld.tsc_deadline = now + interval;
+    }
+    set_tsc_deadline(&X86Msr, cld.tsc_deadline);
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+// Uses the frequency the cpu reports (crystal clock and tsc ratio), otherwise the hpet or the pit is the reference
+fn calibrate_tsc() -> NonZeroU64 {
+    if crate::cpuid::features().max_leaf >= CPUID_TSC_LEAF {
+        let tsc = unsafe { __cpuid(CPUID_TSC_LEAF) };
+        let (denominator, numerator, crystal_hz) = (tsc.eax, tsc.ebx, tsc.ecx);
+        if denominator != 0 && numerator != 0 && crystal_hz != 0 {
+            let hz = crystal_hz as u64 * numerator as u64 / denominator as u64;
+            log::info!("TSC frequency reported by the cpu: {hz} Hz");
+            return NonZeroU64::new(hz).unwrap();
+        }
+    }
+    if let Some(hpet) = crate::hpet::get() {
+        let start = rdtsc();
+        hpet.delay_us(CALIBRATION_US);
+        let hz = (rdtsc() - start) * 1_000_000 / CALIBRATION_US;
+        log::info!("TSC frequency measured with the hpet: {hz} Hz");
+        return NonZeroU64::new(hz).expect("tsc calibration failed");
+    }
+    let start = rdtsc();
+    crate::pit::delay(50_000).unwrap(); // ~max pit delay
+    let hz = (rdtsc() - start) * 20;
+    log::info!("TSC frequency measured with the pit: {hz} Hz");
+    NonZeroU64::new(hz).expect("tsc calibration failed")
+}
+
+impl Deref for Apic {
+    type Target = LocalApic<Registers>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for Apic {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+static MODE: spin::Once<Mode> = spin::Once::new();
+// the timers of all cores use tsc deadlines if the cpu supports them
+static TSC_DEADLINE: AtomicBool = AtomicBool::new(false);
 
 #[inline]
 pub fn get_apic() -> Apic {
-    let local_apic_ptr = (*LOCAL_APIC_PTR.get().expect("APIC not initialized")) as *mut u8;
-    Apic::new(local_apic_ptr)
+    try_get_apic().expect("APIC not initialized")
 }
 
 // to be used in exception interrupts (since the underlying ACPI may not be initialized yet)
 #[inline]
 pub fn try_get_apic() -> Option<Apic> {
-    let local_apic_ptr = LOCAL_APIC_PTR.get().map(|p| *p as *mut u8)?;
-    Some(Apic::new(local_apic_ptr))
+    MODE.get().map(|&mode| Apic::new(mode))
+}
+
+pub fn uses_tsc_deadline() -> bool {
+    TSC_DEADLINE.load(Ordering::Relaxed)
+}
+
+// the tsc runs at a constant rate in all power states
+pub fn tsc_is_invariant() -> bool {
+    crate::cpuid::features().invariant_tsc
+}
+
+pub fn is_x2apic() -> bool {
+    matches!(MODE.get(), Some(Mode::X2Apic))
 }
 
 // needs to be called be called once (only bsp) prior to first initialization (requires heap)
 pub fn create() {
+    let tsc_deadline = crate::cpuid::features().tsc_deadline;
+    TSC_DEADLINE.store(tsc_deadline, Ordering::Relaxed);
+    log::info!(
+        "APIC timer mode: {}",
+        if tsc_deadline {
+            "tsc deadline"
+        } else {
+            "bus clock"
+        }
+    );
     interrupts::without_interrupts(|| {
-        LOCAL_APIC_PTR.call_once(|| {
-            log::info!("Creating APIC");
-            let acpi = crate::acpi::ACPI.lock();
-            acpi.local_apic_ptr as u64
+        MODE.call_once(|| {
+            if crate::cpuid::features().x2apic {
+                log::info!("Creating APIC (x2APIC mode)");
+                X2Apic(X86Msr).enable_mode();
+                return Mode::X2Apic;
+            }
+            log::info!("Creating APIC (xAPIC mode)");
+            let local_apic_phys_addr = crate::acpi::ACPI.lock().local_apic_phys_addr;
+            let virt = crate::memory::map_named_mmio(
+                "local apic",
+                PhysAddr::new(local_apic_phys_addr),
+                4096,
+            );
+            Mode::XApic(unsafe { VolatileMmio::new(virt.as_mut_ptr()) })
         });
     });
 }
 
+// needs to be called by every ap before it uses its apic (the bsp switches the mode in create)
+pub fn enable_mode() {
+    if is_x2apic() {
+        X2Apic(X86Msr).enable_mode();
+    }
+}
+
+// io apic and msi destinations are 8 bit wide (higher x2APIC ids would need interrupt remapping)
+pub fn interrupt_destination() -> u8 {
+    get_apic()
+        .id()
+        .try_into()
+        .expect("apic id not addressable by device interrupts")
+}
+
 // needs to be called by every core exactly once to use apic (after gdt is initialized)
 pub fn init() {
     interrupts::without_interrupts(|| {
@@ -223,3 +307,12 @@ pub fn init() {
 }
 
 static AP_TIMER_INIT_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+
+static TSC_TICKS_PER_SECOND: spin::Once<NonZeroU64> = spin::Once::new();
+
+pub fn tsc_ticks_per_second() -> u64 {
+    TSC_TICKS_PER_SECOND
+        .get()
+        .expect("APIC timer not initialized")
+        .get()
+}
@@ -0,0 +1,146 @@
+use core::{arch::asm, fmt};
+
+use x86_64::{
+    structures::{idt::InterruptStackFrame, paging::Translate},
+    VirtAddr,
+};
+
+use crate::{constants::v, memory, symbols};
+
+// Return addresses found by walking the frame pointers (bootimage builds the kernel with -C force-frame-pointers=yes).
+// Every frame starts with the rbp of the caller followed by the return address.
+// The logged addresses are resolved with the symbol table of the ram disk (see symbols.rs),
+// others have to be resolved with the kernel binary (e.g. addr2line) after subtracting the image offset
+#[derive(Clone, Copy)]
+pub struct Backtrace<const N: usize>([u64; N]);
+
+impl<const N: usize> Backtrace<N> {
+    // return addresses of the calling function and its callers
+    #[inline(always)]
+    pub fn capture() -> Self {
+        Self::from_frame(current_frame())
+    }
+
+    pub fn from_frame(mut rbp: u64) -> Self {
+        let mut addresses = [0; N];
+        for address in &mut addresses {
+            if !is_valid_frame(rbp) {
+                break;
+            }
+            let frame = rbp as *const u64;
+            *address = unsafe { frame.add(1).read() };
+            let caller = unsafe { frame.read() };
+            // stacks grow down, anything else is a corrupted (or the last) frame
+            if caller <= rbp {
+                break;
+            }
+            rbp = caller;
+        }
+        Self(addresses)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        self.0.iter().copied().take_while(|&address| address != 0)
+    }
+}
+
+impl<const N: usize> fmt::Display for Backtrace<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for address in self.addresses() {
+            write!(f, "{address:#x} ")?;
+        }
+        Ok(())
+    }
+}
+
+#[inline(always)]
+pub fn current_frame() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+// only mapped kernel stacks are read, so walking a corrupted chain does not fault
+fn is_valid_frame(rbp: u64) -> bool {
+    let page_table = memory::get_active_l4_page_table();
+    rbp % 8 == 0
+        && (v::KERNEL_START..v::KERNEL_END - 16).contains(&rbp)
+        && page_table.translate_addr(VirtAddr::new(rbp)).is_some()
+        && page_table.translate_addr(VirtAddr::new(rbp + 15)).is_some()
+}
+
+const LOG_DEPTH: usize = 32;
+
+pub fn image_offset() -> u64 {
+    crate::get_boot_info().kernel_image_offset
+}
+
+// An address with the symbol it is in, if it can be resolved
+pub struct Frame {
+    pub address: u64,
+    // return addresses point behind the call, which may already be the next function
+    pub is_return_address: bool,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = self.address;
+        let lookup = if self.is_return_address {
+            address.saturating_sub(1)
+        } else {
+            address
+        };
+        match symbols::resolve(lookup) {
+            Some(symbol) => write!(
+                f,
+                "{address:#x} {}+{:#x}",
+                symbol.name,
+                symbol.offset + (address - lookup)
+            ),
+            None => write!(f, "{address:#x}"),
+        }
+    }
+}
+
+fn log_frame(address: u64, is_return_address: bool) {
+    log::error!(
+        "  {}",
+        Frame {
+            address,
+            is_return_address
+        }
+    );
+}
+
+// has to be inlined, so the frame of the caller is the start
+#[inline(always)]
+pub fn log() {
+    log::error!("Backtrace (image offset {:#x}):", image_offset());
+    for address in Backtrace::<LOG_DEPTH>::capture().addresses() {
+        log_frame(address, true);
+    }
+}
+
+// For exception handlers: the prologue of the handler saved the rbp of the interrupted code in its frame
+#[inline(always)]
+pub fn log_interrupted(stack_frame: &InterruptStackFrame) {
+    let handler_frame = current_frame();
+    let rbp = if is_valid_frame(handler_frame) {
+        unsafe { (handler_frame as *const u64).read() }
+    } else {
+        0
+    };
+    log_registers(stack_frame.instruction_pointer.as_u64(), rbp);
+}
+
+// instruction and frame pointer of interrupted code (e.g. saved by an assembly entry, see gdb.rs)
+pub fn log_registers(rip: u64, rbp: u64) {
+    log::error!(
+        "Backtrace of the interrupted code (image offset {:#x}):",
+        image_offset()
+    );
+    log_frame(rip, false);
+    for address in Backtrace::<LOG_DEPTH>::from_frame(rbp).addresses() {
+        log_frame(address, true);
+    }
+}
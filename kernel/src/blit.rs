@@ -0,0 +1,88 @@
+use core::{arch::asm, ptr};
+
+use spin::Once;
+
+// Copies of rows into the front buffer. It is write combining memory the cpu only writes to,
+// so the copies avoid reading it and don't pull it into the caches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlitMethod {
+    // fast strings (ERMS), the microcode copies whole cache lines
+    RepMovsb,
+    // movnti: non-temporal stores from general purpose registers (sse2), the sse registers
+    // only hold application state (see fpu.rs)
+    NonTemporal,
+    Copy,
+}
+
+impl BlitMethod {
+    // in the order of preference
+    pub const ALL: [Self; 3] = [Self::RepMovsb, Self::NonTemporal, Self::Copy];
+
+    pub fn supported(self) -> bool {
+        match self {
+            Self::RepMovsb => crate::cpuid::features().erms,
+            Self::NonTemporal => crate::cpuid::features().sse2,
+            Self::Copy => true,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::RepMovsb => "rep movsb",
+            Self::NonTemporal => "movnti",
+            Self::Copy => "copy",
+        }
+    }
+
+    // Safety: the same as for ptr::copy_nonoverlapping, the method has to be supported
+    pub unsafe fn copy(self, src: *const u8, dst: *mut u8, len: usize) {
+        match self {
+            Self::RepMovsb => asm!(
+                "rep movsb",
+                inout("rcx") len => _,
+                inout("rsi") src => _,
+                inout("rdi") dst => _,
+                options(nostack, preserves_flags)
+            ),
+            Self::NonTemporal => copy_non_temporal(src, dst, len),
+            Self::Copy => ptr::copy_nonoverlapping(src, dst, len),
+        }
+    }
+}
+
+static METHOD: Once<BlitMethod> = Once::new();
+
+// the preferred method of this cpu
+pub fn method() -> BlitMethod {
+    *METHOD.call_once(|| {
+        let method = BlitMethod::ALL
+            .into_iter()
+            .find(|method| method.supported())
+            .unwrap();
+        log::debug!("Frame buffer blit: {}", method.name());
+        method
+    })
+}
+
+#[allow(clippy::cast_ptr_alignment)]
+unsafe fn copy_non_temporal(mut src: *const u8, mut dst: *mut u8, mut len: usize) {
+    // the stores are aligned, the loads may not be
+    let head = dst.align_offset(8).min(len);
+    ptr::copy_nonoverlapping(src, dst, head);
+    (src, dst, len) = (src.add(head), dst.add(head), len - head);
+
+    while len >= 8 {
+        let value = src.cast::<u64>().read_unaligned();
+        asm!(
+            "movnti [{}], {}",
+            in(reg) dst,
+            in(reg) value,
+            options(nostack, preserves_flags)
+        );
+        (src, dst, len) = (src.add(8), dst.add(8), len - 8);
+    }
+    ptr::copy_nonoverlapping(src, dst, len);
+
+    // orders the non-temporal stores before the following ones
+    asm!("sfence", options(nostack, preserves_flags));
+}
@@ -0,0 +1,226 @@
+use core::net::Ipv4Addr;
+
+use log::LevelFilter;
+use spin::Once;
+
+use crate::logging::{LogFormat, ModuleFilters};
+
+// Kernel command line stored by bootimage as the first ram disk region (`cargo run -- -c "loglevel=debug"`)
+// options:
+//   loglevel=<level>           serial and graphics log level (off, error, warn, info, debug, trace)
+//   serial_loglevel=<level>
+//   graphics_loglevel=<level>
+//   logfilter=<module>=<level>,...  levels of modules and their submodules for both outputs (e.g. memory=trace,apic=warn)
+//   logformat=<text|json>      json writes a json object per log record to the serial port (see logging::LogFormat)
+//   graphics=<on|off>          off disables all kernel text output on the screen
+//   test=<filter>              only run tests whose name contains the filter (testing feature)
+//   kaslr=<on|off>             off places the kernel heap and ap stacks at the start of their windows
+//   gdb=<on|off>               gdb remote stub on COM2, the boot waits for the debugger (see gdb.rs)
+//   logport=<1-4>              serial port of the log (COM1 by default, see serial.rs)
+//   shellport=<1-4>            serial port of the shell (COM1 by default)
+//   ip=<dhcp|address/prefix>   address of the first network card (dhcp by default, e.g. ip=10.0.2.15/24)
+//   gateway=<address>          default gateway of a static address
+#[derive(Debug, Clone)]
+pub struct CommandLine {
+    pub raw: &'static str,
+    pub serial_log_level: LevelFilter,
+    pub graphics_log_level: LevelFilter,
+    pub module_filters: ModuleFilters,
+    pub log_format: LogFormat,
+    pub graphics: bool,
+    pub test_filter: Option<&'static str>,
+    pub kaslr: bool,
+    pub gdb: bool,
+    pub log_port: u8,
+    pub shell_port: u8,
+    // None for dhcp
+    pub ip: Option<(Ipv4Addr, u8)>,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        Self {
+            raw: "",
+            serial_log_level: LevelFilter::Trace,
+            graphics_log_level: LevelFilter::Trace,
+            module_filters: ModuleFilters::new(),
+            log_format: LogFormat::Text,
+            graphics: true,
+            test_filter: None,
+            kaslr: true,
+            gdb: false,
+            log_port: 1,
+            shell_port: 1,
+            ip: None,
+            gateway: None,
+        }
+    }
+}
+
+impl CommandLine {
+    pub fn parse(raw: &'static str) -> Self {
+        let mut cmdline = Self {
+            raw,
+            ..Default::default()
+        };
+        for (key, value) in options(raw) {
+            cmdline.apply(key, value);
+        }
+        if !cmdline.graphics {
+            cmdline.graphics_log_level = LevelFilter::Off;
+        }
+        cmdline
+    }
+
+    // returns false if the option is unknown or its value is invalid
+    fn apply(&mut self, key: &'static str, value: Option<&'static str>) -> bool {
+        match (key, value) {
+            ("loglevel" | "serial_loglevel" | "graphics_loglevel", Some(level)) => {
+                let Ok(level) = level.parse::<LevelFilter>() else {
+                    return false;
+                };
+                if key != "graphics_loglevel" {
+                    self.serial_log_level = level;
+                }
+                if key != "serial_loglevel" {
+                    self.graphics_log_level = level;
+                }
+            }
+            ("logfilter", Some(spec)) => {
+                let Ok(filters) = ModuleFilters::parse(spec) else {
+                    return false;
+                };
+                self.module_filters = filters;
+            }
+            ("logformat", Some("text")) => self.log_format = LogFormat::Text,
+            ("logformat", Some("json")) => self.log_format = LogFormat::Json,
+            ("graphics", None | Some("on" | "true" | "1")) => self.graphics = true,
+            ("graphics", Some("off" | "false" | "0")) => self.graphics = false,
+            ("test", Some(filter)) => self.test_filter = Some(filter),
+            ("kaslr", None | Some("on" | "true" | "1")) => self.kaslr = true,
+            ("kaslr", Some("off" | "false" | "0")) => self.kaslr = false,
+            ("gdb", None | Some("on" | "true" | "1")) => self.gdb = true,
+            ("gdb", Some("off" | "false" | "0")) => self.gdb = false,
+            ("logport" | "shellport", Some(number)) => {
+                let Some(number) = number
+                    .parse()
+                    .ok()
+                    .filter(|number| (1..=4).contains(number))
+                else {
+                    return false;
+                };
+                if key == "logport" {
+                    self.log_port = number;
+                } else {
+                    self.shell_port = number;
+                }
+            }
+            ("ip", Some("dhcp")) => self.ip = None,
+            ("ip", Some(cidr)) => {
+                let Some((address, prefix)) = cidr.split_once('/') else {
+                    return false;
+                };
+                let (Ok(address), Some(prefix)) = (
+                    address.parse(),
+                    prefix.parse().ok().filter(|prefix| *prefix <= 32),
+                ) else {
+                    return false;
+                };
+                self.ip = Some((address, prefix));
+            }
+            ("gateway", Some(address)) => {
+                let Ok(address) = address.parse() else {
+                    return false;
+                };
+                self.gateway = Some(address);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn invalid_options(&self) -> impl Iterator<Item = &'static str> {
+        self.raw.split_whitespace().filter(|option| {
+            let (key, value) = split_option(option);
+            !Self::default().apply(key, value)
+        })
+    }
+
+    pub fn log(&self, level: log::Level) {
+        log::log!(level, "Kernel command line: {:?}", self.raw);
+        for option in self.invalid_options() {
+            log::warn!("Ignoring invalid kernel command line option: {option:?}");
+        }
+    }
+}
+
+fn split_option(option: &'static str) -> (&'static str, Option<&'static str>) {
+    match option.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (option, None),
+    }
+}
+
+fn options(raw: &'static str) -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    raw.split_whitespace().map(split_option)
+}
+
+static COMMAND_LINE: Once<CommandLine> = Once::new();
+
+// usable before logging and the heap are initialized (only requires the boot info)
+pub fn get() -> &'static CommandLine {
+    COMMAND_LINE.call_once(|| {
+        let raw = crate::ram_disk::get_file_slice(crate::ram_disk::CMDLINE);
+        CommandLine::parse(core::str::from_utf8(raw).unwrap_or_default())
+    })
+}
+
+crate::test!(command_line_parsing, {
+    use crate::{ass, same};
+
+    let cmdline = CommandLine::parse("  loglevel=info graphics_loglevel=warn test=mem  ");
+    same!(cmdline.serial_log_level, LevelFilter::Info);
+    same!(cmdline.graphics_log_level, LevelFilter::Warn);
+    same!(cmdline.test_filter, Some("mem"));
+    ass!(cmdline.graphics);
+    ass!(cmdline.kaslr);
+    ass!(!cmdline.gdb);
+    same!(cmdline.invalid_options().count(), 0);
+
+    let cmdline = CommandLine::parse("graphics=off loglevel=loud smp_max=2 kaslr=off gdb");
+    ass!(!cmdline.graphics);
+    ass!(!cmdline.kaslr);
+    ass!(cmdline.gdb);
+    same!(cmdline.graphics_log_level, LevelFilter::Off);
+    same!(cmdline.serial_log_level, LevelFilter::Trace);
+    ass!(cmdline.invalid_options().eq(["loglevel=loud", "smp_max=2"]));
+
+    same!(CommandLine::parse("").test_filter, None);
+
+    let cmdline =
+        CommandLine::parse("logfilter=memory=trace,apic=warn logfilter=apic logformat=json");
+    same!(cmdline.log_format, LogFormat::Json);
+    same!(
+        cmdline.module_filters.level("kernel::memory"),
+        Some(LevelFilter::Trace)
+    );
+    same!(
+        cmdline.module_filters.level("kernel::apic"),
+        Some(LevelFilter::Warn)
+    );
+    ass!(cmdline.invalid_options().eq(["logfilter=apic"]));
+
+    let cmdline = CommandLine::parse("logport=3 shellport=5");
+    same!(cmdline.log_port, 3);
+    same!(cmdline.shell_port, 1);
+    ass!(cmdline.invalid_options().eq(["shellport=5"]));
+
+    same!(CommandLine::parse("").ip, None);
+    let cmdline = CommandLine::parse("ip=10.0.2.15/24 gateway=10.0.2.2 ip=10.0.2/24 gateway=x");
+    same!(cmdline.ip, Some((Ipv4Addr::new(10, 0, 2, 15), 24)));
+    same!(cmdline.gateway, Some(Ipv4Addr::new(10, 0, 2, 2)));
+    ass!(cmdline.invalid_options().eq(["ip=10.0.2/24", "gateway=x"]));
+    same!(CommandLine::parse("ip=10.0.2.15/24 ip=dhcp").ip, None);
+    same!(CommandLine::parse("ip=10.0.2.15/33").ip, None);
+});
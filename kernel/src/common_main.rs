@@ -1,17 +1,21 @@
 use core::{
     hint,
-    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use alloc::{boxed::Box, vec::Vec};
-use x86_64::instructions::hlt;
+use x86_64::structures::paging::PhysFrame;
 
 use crate::{
     acpi::ACPI,
-    ass, barrier, get_boot_info,
-    memory::MEMORY,
+    ass, barrier, get_boot_info, image, kthread,
+    memory::{self, MEMORY},
+    same,
+    sched::{self, CoreMask, WaitQueue},
     smp::{cpu_index, get_cld},
-    terminal_out::{self, PlacementInfo, TerminalWriter, WindowInfo, TERM},
+    terminal_out,
+    time::{Duration, Instant},
+    timer_callbacks,
 };
 
 pub fn main() {
@@ -21,9 +25,16 @@ pub fn main() {
     let id = cpu_index();
 
     app_test();
+    frame_allocation_stress_test(ap_count);
 
     crate::smp::sync_cores_barrier();
     if cpu_index() == 0 {
+        // returns the heap peak of the boot and test phase
+        crate::allocator::ALLOCATOR.trim();
+        crate::allocator::ALLOCATOR.log_heap_stats(log::Level::Debug);
+        crate::allocator::ALLOCATOR.log_slab_stats(log::Level::Debug);
+        sched::log_stats(log::Level::Debug);
+        crate::interrupts::log_stats(log::Level::Debug);
         MEMORY.lock().log_memory_utilization(log::Level::Debug);
     }
     crate::smp::sync_cores_barrier();
@@ -39,7 +50,6 @@ pub fn main() {
     crate::smp::sync_cores_barrier();
 
     if id == 0 {
-        static NEW_FRAME_REQUESTED: AtomicBool = AtomicBool::new(true);
         log::info!("Hello from core 0");
 
         let fb = get_boot_info().framebuffer.as_ref().unwrap();
@@ -48,68 +58,73 @@ pub fn main() {
         crate::terminal_out::switch_to_double_buffer();
 
         log::info!("Switched to double buffer");
-        let int = || {
-            NEW_FRAME_REQUESTED.store(true, Ordering::Release);
-        };
-
-        crate::apic::get_apic()
-            .start_timer(30_000, true, int)
-            .unwrap();
-
-        loop {
-            while !NEW_FRAME_REQUESTED.fetch_and(false, Ordering::Acquire) {
-                hlt();
+        crate::status_bar::start();
+
+        // detached, they run until the system stops
+        kthread::spawn_on("compositor", CoreMask::single(0), || {
+            let mut next_frame = Instant::now();
+            loop {
+                crate::terminal_out::push_to_frame_buffer();
+                FRAME_PUSHED.wake_all();
+                // frames which are already late are skipped
+                next_frame = (next_frame + FRAME_INTERVAL).max(Instant::now());
+                sched::sleep_until(next_frame.as_us());
             }
-            REFRESH_COUNTER.fetch_add(1, Ordering::Release);
-            crate::terminal_out::push_to_frame_buffer();
+        });
+        // the shell gets the keys while its window has the focus, clicks move the focus
+        crate::shell::start();
+        kthread::spawn("pointer", pointer);
+        kthread::spawn("floating window", floating_window);
+        kthread::spawn("performance graphs", performance_graphs);
+        for core in 1..=ap_count {
+            kthread::spawn_on("demo writer", CoreMask::single(core as usize), move || {
+                demo_writer(core, ap_count);
+            });
         }
     }
+}
 
-    let mut local_writer =
-        TerminalWriter::new(get_window_info_for_core(id as usize, ap_count as usize));
+// writes into a window of its own (tiled next to stdout), needs to run on a separate core for each id
+fn demo_writer(id: u64, ap_count: u64) {
+    let window = terminal_out::create_window();
 
     let clear_color = terminal_out::Color::new(
         10,
         100 - (100 * id / (ap_count + 1)) as u8,
         (100 * id / (ap_count + 1)) as u8,
     );
-    local_writer.clear_color = clear_color;
+    terminal_out::with_window(window, |writer| {
+        writer.clear_color = clear_color;
+        writer.clear(Some(terminal_out::small_font_size()));
+    });
 
-    local_writer.set_to_double_buffer();
-    local_writer.clear(Some(terminal_out::FontSize::Size16));
-
-    while REFRESH_COUNTER.load(Ordering::Acquire) < 2 {
-        hint::spin_loop();
-    }
-
-    if id == 1 {
-        TERM.lock()
-            .set_window_info(get_window_info_for_core(0, ap_count as usize), None);
-    }
+    FRAME_PUSHED.wait_until(|| terminal_out::frames() >= 2);
 
     barrier!(ap_count);
 
     // // loop{hlt();}
 
-    local_writer.clear(None);
+    terminal_out::with_window(window, |writer| writer.clear(None));
     if id == 1 {
-        let count = ACPI.lock().ap_count;
-
         for i in 0..100_000_000 {
-            let _lock = crate::terminal_out::lock_back_buffer();
-            local_writer.print(format_args!(
-                "{} frame {} {}\n",
-                i,
-                REFRESH_COUNTER.load(Ordering::Relaxed),
-                count
-            ));
+            let lock = crate::terminal_out::lock_back_buffer();
+            terminal_out::with_window(window, |writer| {
+                writer.print(format_args!("[{i}] Hello from core 1\n"));
+            });
+            drop(lock);
+            // lets the other tasks of this core run
+            sched::yield_now();
         }
     } else if id == 2 {
-        local_writer.print(format_args!("Core 2 uses stdout\n"));
+        terminal_out::with_window(window, |writer| {
+            writer.print(format_args!("Core 2 uses stdout\n"));
+        });
         print_logo();
     } else {
         for i in 0..200 {
-            local_writer.print(format_args!("[{i}] Hello from core {id}\n"));
+            terminal_out::with_window(window, |writer| {
+                writer.print(format_args!("[{i}] Hello from core {id}\n"));
+            });
         }
     }
 
@@ -121,49 +136,81 @@ pub fn main() {
     //     }
     // }
 
-    // if cpu_index() == 1 {
-    //     // set main term from core 0
-    //     TERM.lock()
-    //         .set_window_info(get_window_info_for_core(0, ap_count as usize), None)
-    // }
-
-    loop {
-        // let line = { crate::serial::SERIAL.0.lock().read_line().unwrap() };
-        if let Ok(c) = crate::serial::SERIAL.0.lock().read() {
-            local_writer.print(format_args!("{}: {:?}\n", c, core::str::from_utf8(&[c])));
-        }
-    }
-
     // if cpu_index() == 0 {
     //     // ACPI.lock().log_proccessor_info(log::Level::Info);
     //     log::info!("\n\tSerial line echo");
     // }
 
     // MEMORY.lock().log_memory_utilization(log::Level::Debug);
+}
 
-    // loop {
-    //     // let line = { crate::serial::SERIAL.0.lock().read_line().unwrap() };
-    //     if let Ok(c) = crate::serial::SERIAL.0.lock().read() {
-    //         log::info!("{}: {:?}", c, core::str::from_utf8(&[c]));
-    //     }
-    //     hint::spin_loop();
-    // }
+// a window over the ones of the cores that wanders across the screen
+fn floating_window() {
+    let fb_info = get_boot_info().framebuffer.as_ref().unwrap().info();
+    let (width, height) = (fb_info.width / 4, fb_info.height / 8);
+    let window = terminal_out::create_window();
+    terminal_out::resize_window(window, width, height);
+    terminal_out::with_window(window, |writer| {
+        writer.clear_color = terminal_out::Color::new(90, 40, 40);
+        writer.clear(Some(terminal_out::small_font_size()));
+    });
+
+    for step in 0.. {
+        terminal_out::with_window(window, |writer| {
+            writer.print(format_args!("Floating window, step {step}\n"));
+        });
+        terminal_out::move_window(
+            window,
+            step * 16 % (fb_info.width - width),
+            step * 9 % (fb_info.height - height),
+        );
+        sched::sleep_ms(100);
+    }
 }
 
-fn get_window_info_for_core(id: usize, count: usize) -> WindowInfo {
-    let placement = PlacementInfo {
-        x_div: 4,
-        y_div: (count + 4 - 1) / 4,
-        x_index: id % 4,
-        y_index: id / 4,
-        x_size: 1,
-        y_size: 1,
-    };
+// frames per second of the compositor and the size of the kernel heap, in a tiled window
+fn performance_graphs() {
+    const INTERVAL_MS: u64 = 250;
+    let fb_width = get_boot_info().framebuffer.as_ref().unwrap().info().width;
+    let window = terminal_out::create_window();
+    let mut fps =
+        terminal_out::LineGraph::new("fps", terminal_out::Color::new(80, 220, 80), fb_width);
+    let mut heap =
+        terminal_out::LineGraph::new("heap KiB", terminal_out::Color::new(240, 160, 40), fb_width);
+
+    let mut frames = terminal_out::frames();
+    loop {
+        sched::sleep_ms(INTERVAL_MS);
+        let pushed = terminal_out::frames();
+        fps.push((pushed - frames) * 1000 / INTERVAL_MS);
+        frames = pushed;
+        heap.push(crate::allocator::ALLOCATOR.heap_used() as u64 / 1024);
+
+        terminal_out::with_window(window, |writer| {
+            let info = writer.window_info();
+            let (width, half) = (info.width, info.height / 2);
+            fps.draw(writer, image::Rect::new(0, 0, width, half));
+            heap.draw(writer, image::Rect::new(0, half, width, info.height - half));
+        });
+    }
+}
 
-    WindowInfo::from_placement(&placement)
+// the cursor follows the mouse, a left click focuses the window under it
+fn pointer() {
+    let events = crate::mouse::subscribe();
+    loop {
+        let event = events.next();
+        let (x, y) = (event.x as usize, event.y as usize);
+        terminal_out::move_cursor(x, y);
+        if event.changed.left && event.buttons.left {
+            terminal_out::focus_at(x, y);
+        }
+    }
 }
 
-static REFRESH_COUNTER: AtomicU64 = AtomicU64::new(0);
+// the compositor pushes the back buffer this often
+const FRAME_INTERVAL: Duration = Duration::from_millis(30);
+static FRAME_PUSHED: WaitQueue = WaitQueue::new();
 
 fn timer_interrupt() {
     get_cld().stuff.as_mut().unwrap()[0]
@@ -191,9 +238,10 @@ fn timer_test() {
 
     stuff.push(Box::new(AtomicU64::new(0)));
 
-    crate::apic::get_apic()
-        .start_timer(200_000 * (cpu_index() as u32 + 1), true, timer_interrupt)
-        .unwrap();
+    let timer = timer_callbacks::add_periodic(
+        Duration::from_millis(200 * (cpu_index() + 1)),
+        timer_interrupt,
+    );
 
     let mut last_count = 0;
     loop {
@@ -210,32 +258,61 @@ fn timer_test() {
         }
         hint::spin_loop();
     }
+    timer_callbacks::cancel(timer);
 }
 
-fn app_test() {
-    let user_app = crate::ram_disk::get_file_slice(1);
+// compares frame allocation through the global MEMORY lock with the per core frame cache (all cores at once)
+fn frame_allocation_stress_test(ap_count: u64) {
+    const ROUNDS: u64 = 10_000;
+    const FRAMES_PER_ROUND: usize = 8;
+
+    let measure = |allocate: &dyn Fn() -> PhysFrame, deallocate: &dyn Fn(PhysFrame)| {
+        barrier!(ap_count + 1);
+        let start = unsafe { core::arch::x86_64::_rdtsc() };
+        for _ in 0..ROUNDS {
+            let frames: [PhysFrame; FRAMES_PER_ROUND] = core::array::from_fn(|_| allocate());
+            frames.into_iter().for_each(deallocate);
+        }
+        let cycles = unsafe { core::arch::x86_64::_rdtsc() } - start;
+        cycles / (ROUNDS * FRAMES_PER_ROUND as u64)
+    };
 
-    let mut resources_a = crate::loader::prepare_application(user_app);
-    let mut resources_b = crate::loader::prepare_application(user_app);
+    let locked = measure(
+        &|| MEMORY.lock().allocate_frame_uncached().unwrap(),
+        &|frame| unsafe { MEMORY.lock().deallocate_frame_uncached(frame) },
+    );
+    let cached = measure(&|| memory::allocate_frame().unwrap(), &|frame| unsafe {
+        memory::deallocate_frame(frame)
+    });
 
-    ass!(crate::loader::run(&mut resources_a), ==, 0);
-    ass!(crate::loader::run(&mut resources_b), ==, 0);
-    ass!(crate::loader::run(&mut resources_a), ==, 42);
+    log::info!(
+        "Frame allocation on core {}: {locked} cycles locked, {cached} cycles cached (per frame)",
+        cpu_index()
+    );
 }
 
-fn print_logo() {
-    let (width, pixels) = {
-        let image_bytes = crate::ram_disk::get_file_slice(2);
-        let mut decoder = zune_jpeg::JpegDecoder::new(image_bytes);
-
-        let pixels = decoder.decode().unwrap();
-
-        (decoder.dimensions().unwrap().0, pixels)
-    };
-
-    let it = pixels
-        .chunks(3)
-        .map(|pixel| crate::terminal_out::Color::new(pixel[0], pixel[1], pixel[2]));
+fn app_test() {
+    let user_app = crate::ram_disk::get_file_slice(crate::ram_disk::TEST_APP);
+
+    let mut resources_a = crate::loader::prepare_application(user_app).unwrap();
+    let mut resources_b = crate::loader::prepare_application(user_app).unwrap();
+
+    ass!(crate::loader::run(&mut resources_a).success());
+    ass!(crate::loader::run(&mut resources_b).success());
+    let status = crate::loader::run(&mut resources_a);
+    same!(status.cause, crate::loader::ExitCause::Panicked);
+    same!(status.code, 42);
+
+    // runs concurrently on the least loaded cores
+    let apps: Vec<_> = (0..4)
+        .map(|_| crate::loader::spawn("test").unwrap())
+        .collect();
+    for app in apps {
+        ass!(app.join().success());
+    }
+}
 
-    crate::terminal_out::Stdout::acquire().print_pixels(width, it);
+fn print_logo() {
+    let logo = image::decode(crate::ram_disk::get_file_slice(crate::ram_disk::LOGO)).unwrap();
+    crate::terminal_out::Stdout::acquire().print_image(&logo, logo.width);
 }
@@ -1,9 +1,13 @@
 use core::ops::Range;
 
 pub const KERNEL_STACK_SIZE: u64 = 4096 * 1024;
-pub const MAX_CORES: u64 = 256;
+pub const TASK_STACK_SIZE: u64 = 4096 * 64; // includes the privilege stack for entries from ring 3
 pub const USER_STACK_SIZE: u64 = 4096 * 4096; // includes guard page
 
+// the bootloader picks a mode at least this large (bootimage --resolution overrides it)
+pub const MIN_FRAME_BUFFER_WIDTH: u64 = 1280;
+pub const MIN_FRAME_BUFFER_HEIGHT: u64 = 720;
+
 pub const KERNEL_L4_PAGE_TABLE_RANGE: Range<u32> = 100..116;
 #[rustfmt::skip]
 pub mod v {
@@ -18,10 +22,14 @@ pub mod v {
 
     pub const KERNEL_AP_STACKS: u64 =      build_addr(109 ,0  ,0  ,0  ,0);
 
+    pub const KERNEL_MMIO_START: u64 =     build_addr(110 ,0  ,0  ,0  ,0);
+    pub const KERNEL_MMIO_END: u64 =       build_addr(111 ,0  ,0  ,0  ,0); // exclusive
+
     pub const KERNEL_END: u64 =            build_addr(116 ,0  ,0  ,0  ,0); // exclusive
 
     // keep space for bootloader 
     pub const USER_START: u64 =            build_addr(0   ,0  ,0  ,0  ,0);
+    pub const USER_TLS_START: u64 =        build_addr(0   ,3  ,511,0  ,0); // tls block and tcb (see loader::map_tls)
     pub const USER_STACK_START: u64 =      build_addr(0   ,4  ,0  ,0  ,0); //(lowest address of guard page)
     pub const USER_HEAP_START: u64 =       build_addr(0   ,8  ,0  ,0  ,0);
     pub const USER_END: u64 =              build_addr(1   ,0  ,0  ,0  ,0); // exclusive
@@ -0,0 +1,229 @@
+use core::{
+    arch::x86_64::{__cpuid, __cpuid_count, CpuidResult},
+    fmt,
+};
+
+use spin::Once;
+
+use crate::per_core;
+
+// The cpuid leaves of each core, read once when the core starts (init). Decisions for the whole system
+// (timer mode, apic mode, blit method, ...) use the ones of the bsp, the aps only warn if theirs differ
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    pub vendor: [u8; 12],
+    pub brand: [u8; 48],
+    pub max_leaf: u32,
+    pub max_extended_leaf: u32,
+    pub tsc: bool,
+    pub msr: bool,
+    pub apic: bool,
+    pub pat: bool,
+    pub fxsr: bool,
+    pub sse2: bool,
+    pub x2apic: bool,
+    pub tsc_deadline: bool,
+    pub xsave: bool,
+    pub invariant_tsc: bool,
+    pub erms: bool,
+    pub smep: bool,
+    pub smap: bool,
+    pub rdseed: bool,
+    pub no_execute: bool,
+}
+
+// leaf 1
+const EDX_TSC: u32 = 1 << 4;
+const EDX_MSR: u32 = 1 << 5;
+const EDX_APIC: u32 = 1 << 9;
+const EDX_PAT: u32 = 1 << 16;
+const EDX_FXSR: u32 = 1 << 24;
+const EDX_SSE2: u32 = 1 << 26;
+const ECX_X2APIC: u32 = 1 << 21;
+const ECX_TSC_DEADLINE: u32 = 1 << 24;
+const ECX_XSAVE: u32 = 1 << 26;
+// leaf 7 sub leaf 0
+const EBX_SMEP: u32 = 1 << 7;
+const EBX_ERMS: u32 = 1 << 9;
+const EBX_RDSEED: u32 = 1 << 18;
+const EBX_SMAP: u32 = 1 << 20;
+// extended leaves
+const EXTENDED_FEATURES_LEAF: u32 = 0x8000_0001;
+const EXTENDED_EDX_NO_EXECUTE: u32 = 1 << 20;
+const BRAND_LEAF: u32 = 0x8000_0002;
+const POWER_MANAGEMENT_LEAF: u32 = 0x8000_0007;
+const EXTENDED_EDX_INVARIANT_TSC: u32 = 1 << 8;
+
+// indexed by the cpu index (0 is the bsp)
+per_core! {
+    static FEATURES: Once<Features> = Once::new();
+}
+
+impl Features {
+    pub fn read() -> Self {
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+        let leaf = |leaf: u32| {
+            // leaves above the maximum return the data of the highest one on intel
+            if leaf <= max_leaf || (0x8000_0000..=max_extended_leaf).contains(&leaf) {
+                unsafe { __cpuid_count(leaf, 0) }
+            } else {
+                CpuidResult {
+                    eax: 0,
+                    ebx: 0,
+                    ecx: 0,
+                    edx: 0,
+                }
+            }
+        };
+
+        let basic = leaf(0);
+        let mut vendor = [0; 12];
+        for (bytes, register) in vendor.chunks_mut(4).zip([basic.ebx, basic.edx, basic.ecx]) {
+            bytes.copy_from_slice(&register.to_le_bytes());
+        }
+        let mut brand = [0; 48];
+        for (bytes, brand_leaf) in brand.chunks_mut(16).zip(BRAND_LEAF..) {
+            let part = leaf(brand_leaf);
+            for (bytes, register) in bytes
+                .chunks_mut(4)
+                .zip([part.eax, part.ebx, part.ecx, part.edx])
+            {
+                bytes.copy_from_slice(&register.to_le_bytes());
+            }
+        }
+
+        let features = leaf(1);
+        let structured = leaf(7).ebx;
+        let extended = leaf(EXTENDED_FEATURES_LEAF).edx;
+        let power_management = leaf(POWER_MANAGEMENT_LEAF).edx;
+        Self {
+            vendor,
+            brand,
+            max_leaf,
+            max_extended_leaf,
+            tsc: features.edx & EDX_TSC != 0,
+            msr: features.edx & EDX_MSR != 0,
+            apic: features.edx & EDX_APIC != 0,
+            pat: features.edx & EDX_PAT != 0,
+            fxsr: features.edx & EDX_FXSR != 0,
+            sse2: features.edx & EDX_SSE2 != 0,
+            x2apic: features.ecx & ECX_X2APIC != 0,
+            tsc_deadline: features.ecx & ECX_TSC_DEADLINE != 0,
+            xsave: features.ecx & ECX_XSAVE != 0,
+            invariant_tsc: power_management & EXTENDED_EDX_INVARIANT_TSC != 0,
+            erms: structured & EBX_ERMS != 0,
+            smep: structured & EBX_SMEP != 0,
+            smap: structured & EBX_SMAP != 0,
+            rdseed: structured & EBX_RDSEED != 0,
+            no_execute: extended & EXTENDED_EDX_NO_EXECUTE != 0,
+        }
+    }
+
+    // (name, present) of every feature, the required ones first
+    pub const fn list(&self) -> [(&'static str, bool); 15] {
+        [
+            ("tsc", self.tsc),
+            ("msr", self.msr),
+            ("apic", self.apic),
+            ("pat", self.pat),
+            ("fxsr", self.fxsr),
+            ("nx", self.no_execute),
+            ("sse2", self.sse2),
+            ("x2apic", self.x2apic),
+            ("tsc-deadline", self.tsc_deadline),
+            ("invariant-tsc", self.invariant_tsc),
+            ("xsave", self.xsave),
+            ("erms", self.erms),
+            ("smep", self.smep),
+            ("smap", self.smap),
+            ("rdseed", self.rdseed),
+        ]
+    }
+
+    // the kernel can't run without them: the apic timer and the tsc, the pat (write combining frame buffer),
+    // fxsave for the application fpu state and no execute pages
+    pub fn missing_required(&self) -> impl Iterator<Item = &'static str> {
+        self.list()
+            .into_iter()
+            .take(REQUIRED)
+            .filter(|(_, present)| !present)
+            .map(|(name, _)| name)
+    }
+
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    pub fn brand(&self) -> &str {
+        core::str::from_utf8(&self.brand)
+            .unwrap_or("unknown")
+            .trim_end_matches('\0')
+            .trim()
+    }
+}
+
+// the number of required features at the start of Features::list
+const REQUIRED: usize = 6;
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.brand(), self.vendor())?;
+        for (name, present) in self.list() {
+            if present {
+                write!(f, " {name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// the names of the missing required features, separated by commas (formatted without the heap)
+struct Missing<'a>(&'a Features);
+
+impl fmt::Display for Missing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, name) in self.0.missing_required().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{name}")?;
+        }
+        Ok(())
+    }
+}
+
+// Needs to be called by every core before it checks features (the bsp first, before anything depends on them).
+// Panics if a required feature is missing
+pub fn init(cpu_index: u64) -> &'static Features {
+    let features = FEATURES[cpu_index as usize].call_once(Features::read);
+    assert!(
+        features.missing_required().next().is_none(),
+        "Core {cpu_index} lacks required cpu features: {} (cpu: {features})",
+        Missing(features)
+    );
+    if cpu_index == 0 {
+        log::info!("CPU: {features}");
+    } else if let Some(bsp) = FEATURES[0].get() {
+        for ((name, present), (_, bsp_present)) in features.list().into_iter().zip(bsp.list()) {
+            if present != bsp_present {
+                log::warn!(
+                    "Core {cpu_index}: {name} is {} unlike on the bsp (the bsp decides whether it is used)",
+                    if present { "supported" } else { "missing" }
+                );
+            }
+        }
+    }
+    features
+}
+
+// the features of the bsp, for decisions of the whole system
+pub fn features() -> &'static Features {
+    FEATURES[0].get().expect("cpuid::init wasn't called")
+}
+
+// None until the core called init
+pub fn core_features(cpu_index: u64) -> Option<&'static Features> {
+    FEATURES.get(cpu_index as usize)?.get()
+}
@@ -0,0 +1,89 @@
+use core::slice;
+
+use x86_64::{
+    structures::paging::{PageSize, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use crate::memory::{phys_to_virt, MEMORY};
+
+// Physically contiguous, zeroed buffers for device dma (descriptor rings, request and data buffers) from the
+// dma pool of the frame allocator. They are accessed through the physical memory mapping, which is write back:
+// pci devices snoop the caches on x86, so no other memory type is needed. Drivers still have to order their
+// writes to the buffer before the doorbell write (a fence, mmio is uncached).
+// The frames return to the pool when the buffer is dropped, the device must not use them anymore by then
+#[derive(Debug)]
+pub struct DmaBuffer {
+    first: PhysFrame,
+    frames: u64,
+    size: usize,
+}
+
+impl DmaBuffer {
+    pub const fn phys(&self) -> PhysAddr {
+        self.first.start_address()
+    }
+
+    pub fn virt(&self) -> VirtAddr {
+        phys_to_virt(self.phys())
+    }
+
+    // the physical address the device uses for the byte at offset
+    pub fn phys_at(&self, offset: usize) -> PhysAddr {
+        assert!(
+            offset < self.size,
+            "offset {offset} outside of the dma buffer"
+        );
+        self.phys() + offset as u64
+    }
+
+    // requested size in bytes (the buffer is rounded up to whole frames)
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+        self.virt().as_mut_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_mut_ptr(), self.size) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.size) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        unsafe { MEMORY.lock().deallocate_contiguous(self.first, self.frames) };
+    }
+}
+
+// frame aligned buffer of at least size bytes, None if the dma pool has no room
+pub fn alloc(size: usize) -> Option<DmaBuffer> {
+    alloc_aligned(size, Size4KiB::SIZE)
+}
+
+// alignment in bytes (power of two), for devices with stricter requirements than frames
+pub fn alloc_aligned(size: usize, alignment: u64) -> Option<DmaBuffer> {
+    assert!(size > 0, "empty dma buffer");
+    let frames = (size as u64).div_ceil(Size4KiB::SIZE);
+    let first = MEMORY.lock().allocate_contiguous(frames, alignment)?;
+    let buffer = DmaBuffer {
+        first,
+        frames,
+        size,
+    };
+    unsafe {
+        buffer
+            .as_mut_ptr::<u8>()
+            .write_bytes(0, (frames * Size4KiB::SIZE) as usize);
+    }
+    Some(buffer)
+}
+
+pub fn free_frames() -> u64 {
+    MEMORY.lock().dma_pool_free_frames()
+}
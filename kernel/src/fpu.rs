@@ -0,0 +1,65 @@
+use core::arch::asm;
+
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+// The kernel is compiled without sse (soft float), so the x87/sse registers only hold application state.
+// It is saved (fxsave) when an application is switched out and restored before it continues
+// (see sched::schedule and loader::switch_stack_and_execute)
+#[derive(Clone)]
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+// default control words (all exceptions masked, round to nearest)
+const FCW_DEFAULT: u16 = 0x037F;
+const MXCSR_DEFAULT: u32 = 0x1F80;
+
+impl FpuState {
+    // the state after a reset (finit), applications start with it
+    pub const fn new() -> Self {
+        let mut area = [0; 512];
+        let fcw = FCW_DEFAULT.to_le_bytes();
+        area[0] = fcw[0];
+        area[1] = fcw[1];
+        let mxcsr = MXCSR_DEFAULT.to_le_bytes();
+        let mut i = 0;
+        while i < 4 {
+            area[24 + i] = mxcsr[i];
+            i += 1;
+        }
+        Self(area)
+    }
+
+    // stores the registers of this core
+    pub fn save(&mut self) {
+        unsafe {
+            asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack, preserves_flags))
+        };
+    }
+
+    // loads the registers of this core
+    pub fn restore(&self) {
+        unsafe {
+            asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack, preserves_flags))
+        };
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// needs to be called by every core (cr0 and cr4 are per core)
+pub fn init() {
+    unsafe {
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|cr4| {
+            cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+    }
+    FpuState::new().restore();
+}
@@ -0,0 +1,71 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+use spin::Mutex;
+
+use super::FsError;
+
+pub const SECTOR_SIZE: usize = 512;
+
+// Storage addressed in 512 byte sectors (disks, partitions), the buffers are a multiple of SECTOR_SIZE long
+pub trait BlockDevice: Send + Sync {
+    fn sector_count(&self) -> u64;
+
+    fn read_sectors(&self, first: u64, buf: &mut [u8]) -> Result<(), FsError>;
+
+    fn write_sectors(&self, first: u64, data: &[u8]) -> Result<(), FsError>;
+}
+
+fn check_range(device: &dyn BlockDevice, first: u64, len: usize) -> Result<(), FsError> {
+    assert_eq!(len % SECTOR_SIZE, 0, "partial sector");
+    let end = first.checked_add((len / SECTOR_SIZE) as u64);
+    if end.is_some_and(|end| end <= device.sector_count()) {
+        Ok(())
+    } else {
+        Err(FsError::Io)
+    }
+}
+
+// Heap backed device, only the written sectors are stored (the others read as zeros)
+pub struct RamBlockDevice {
+    sector_count: u64,
+    sectors: Mutex<BTreeMap<u64, Box<[u8; SECTOR_SIZE]>>>,
+}
+
+impl RamBlockDevice {
+    pub fn new(sector_count: u64) -> Self {
+        Self {
+            sector_count,
+            sectors: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl BlockDevice for RamBlockDevice {
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_sectors(&self, first: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        check_range(self, first, buf.len())?;
+        let sectors = self.sectors.lock();
+        for (sector, chunk) in (first..).zip(buf.chunks_exact_mut(SECTOR_SIZE)) {
+            match sectors.get(&sector) {
+                Some(data) => chunk.copy_from_slice(&data[..]),
+                None => chunk.fill(0),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, first: u64, data: &[u8]) -> Result<(), FsError> {
+        check_range(self, first, data.len())?;
+        let mut sectors = self.sectors.lock();
+        for (sector, chunk) in (first..).zip(data.chunks_exact(SECTOR_SIZE)) {
+            if chunk.iter().all(|&byte| byte == 0) {
+                sectors.remove(&sector);
+            } else {
+                sectors.insert(sector, Box::new(chunk.try_into().unwrap()));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+use super::{
+    block::{BlockDevice, SECTOR_SIZE},
+    Dir, DirEntry, File, FileSystem, FileType, FsError, Node,
+};
+
+// Read only ext2 (revision 0 and 1) on a block device. Only regular files and directories are
+// visible, symlinks and device nodes are skipped. Volumes with incompatible features that change
+// the layout (extents, 64 bit, journal to recover, ...) are rejected
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+const ROOT_INODE: u32 = 2;
+const GOOD_OLD_INODE_SIZE: u64 = 128;
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+
+// directory entries have a file type byte
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+// the high 32 bits of the size of regular files are stored
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_DIR: u16 = 0x4000;
+const MODE_FILE: u16 = 0x8000;
+const ENTRY_TYPE_FILE: u8 = 1;
+const ENTRY_TYPE_DIR: u8 = 2;
+
+const DIRECT_BLOCKS: usize = 12;
+const SINGLE_INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    block_size: u64,
+    block_count: u64,
+    inode_count: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    // first block of the inode table of every group
+    inode_tables: Vec<u64>,
+    typed_entries: bool,
+    large_files: bool,
+}
+
+struct Inode {
+    mode: u16,
+    size: u64,
+    // 12 direct blocks, then the single, double and triple indirect block
+    blocks: [u32; 15],
+}
+
+impl Inode {
+    fn file_type(&self) -> Option<FileType> {
+        match self.mode & MODE_TYPE_MASK {
+            MODE_FILE => Some(FileType::File),
+            MODE_DIR => Some(FileType::Dir),
+            _ => None,
+        }
+    }
+}
+
+impl Volume {
+    fn parse(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+        device.read_sectors(SUPERBLOCK_OFFSET / SECTOR_SIZE as u64, &mut superblock)?;
+        let inode_count = read_u32(&superblock, 0);
+        let block_count = u64::from(read_u32(&superblock, 4));
+        let first_data_block = u64::from(read_u32(&superblock, 20));
+        let log_block_size = read_u32(&superblock, 24);
+        let blocks_per_group = u64::from(read_u32(&superblock, 32));
+        let inodes_per_group = read_u32(&superblock, 40);
+        let revision = read_u32(&superblock, 76);
+        let (inode_size, incompat, ro_compat) = if revision == 0 {
+            (GOOD_OLD_INODE_SIZE, 0, 0)
+        } else {
+            (
+                u64::from(read_u16(&superblock, 88)),
+                read_u32(&superblock, 96),
+                read_u32(&superblock, 100),
+            )
+        };
+
+        let valid = read_u16(&superblock, 56) == MAGIC
+            && log_block_size <= MAX_LOG_BLOCK_SIZE
+            && incompat & !SUPPORTED_INCOMPAT == 0
+            && blocks_per_group > 0
+            && inodes_per_group > 0
+            && inode_count >= ROOT_INODE
+            && inode_size >= GOOD_OLD_INODE_SIZE
+            && inode_size.is_power_of_two();
+        if !valid {
+            return Err(FsError::InvalidFormat);
+        }
+        let block_size = 1024 << log_block_size;
+        if block_count * (block_size / SECTOR_SIZE as u64) > device.sector_count() {
+            return Err(FsError::InvalidFormat);
+        }
+
+        // the descriptor table follows the block of the superblock
+        let group_count = inode_count.div_ceil(inodes_per_group) as usize;
+        let table_blocks = (group_count * GROUP_DESCRIPTOR_SIZE).div_ceil(block_size as usize);
+        let mut descriptors = vec![0; table_blocks * block_size as usize];
+        device.read_sectors(
+            (first_data_block + 1) * (block_size / SECTOR_SIZE as u64),
+            &mut descriptors,
+        )?;
+        let inode_tables = descriptors
+            .chunks_exact(GROUP_DESCRIPTOR_SIZE)
+            .take(group_count)
+            .map(|descriptor| u64::from(read_u32(descriptor, 8)))
+            .collect();
+
+        let volume = Self {
+            device,
+            block_size,
+            block_count,
+            inode_count,
+            inodes_per_group,
+            inode_size,
+            inode_tables,
+            typed_entries: incompat & INCOMPAT_FILETYPE != 0,
+            large_files: ro_compat & RO_COMPAT_LARGE_FILE != 0,
+        };
+        if volume.inode(ROOT_INODE)?.file_type() != Some(FileType::Dir) {
+            return Err(FsError::InvalidFormat);
+        }
+        Ok(volume)
+    }
+
+    // reads bytes of a block, they must not cross a sector boundary
+    fn read_in_block(&self, block: u64, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if block >= self.block_count {
+            return Err(FsError::InvalidFormat);
+        }
+        let mut sector = [0; SECTOR_SIZE];
+        let position = block * self.block_size + offset;
+        self.device
+            .read_sectors(position / SECTOR_SIZE as u64, &mut sector)?;
+        let start = (position % SECTOR_SIZE as u64) as usize;
+        buf.copy_from_slice(&sector[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if block >= self.block_count {
+            return Err(FsError::InvalidFormat);
+        }
+        self.device
+            .read_sectors(block * (self.block_size / SECTOR_SIZE as u64), buf)
+    }
+
+    fn inode(&self, number: u32) -> Result<Inode, FsError> {
+        if number == 0 || number > self.inode_count {
+            return Err(FsError::InvalidFormat);
+        }
+        let index = number - 1;
+        let table = self.inode_tables[(index / self.inodes_per_group) as usize];
+        let offset = u64::from(index % self.inodes_per_group) * self.inode_size;
+        // inodes are at least 128 bytes and aligned to their size, so the part used here is in one sector
+        let mut bytes = [0; GOOD_OLD_INODE_SIZE as usize];
+        self.read_in_block(
+            table + offset / self.block_size,
+            offset % self.block_size,
+            &mut bytes,
+        )?;
+
+        let mode = read_u16(&bytes, 0);
+        let mut size = u64::from(read_u32(&bytes, 4));
+        if self.large_files && mode & MODE_TYPE_MASK == MODE_FILE {
+            size |= u64::from(read_u32(&bytes, 108)) << 32;
+        }
+        let mut blocks = [0; 15];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = read_u32(&bytes, 40 + i * 4);
+        }
+        Ok(Inode { mode, size, blocks })
+    }
+
+    // the block holding the data at index of the file, 0 for holes
+    fn data_block(&self, inode: &Inode, index: u64) -> Result<u32, FsError> {
+        let per_block = self.block_size / 4;
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok(inode.blocks[index as usize]);
+        }
+        let index = index - DIRECT_BLOCKS as u64;
+        if index < per_block {
+            return self.indirect(inode.blocks[SINGLE_INDIRECT], &[index]);
+        }
+        let index = index - per_block;
+        if index < per_block * per_block {
+            return self.indirect(
+                inode.blocks[DOUBLE_INDIRECT],
+                &[index / per_block, index % per_block],
+            );
+        }
+        let index = index - per_block * per_block;
+        if index < per_block * per_block * per_block {
+            return self.indirect(
+                inode.blocks[TRIPLE_INDIRECT],
+                &[
+                    index / (per_block * per_block),
+                    index / per_block % per_block,
+                    index % per_block,
+                ],
+            );
+        }
+        Err(FsError::InvalidFormat)
+    }
+
+    // follows the indirect blocks, the path has the index in each of them
+    fn indirect(&self, mut block: u32, path: &[u64]) -> Result<u32, FsError> {
+        for &index in path {
+            if block == 0 {
+                break;
+            }
+            let mut entry = [0; 4];
+            self.read_in_block(u64::from(block), index * 4, &mut entry)?;
+            block = u32::from_le_bytes(entry);
+        }
+        Ok(block)
+    }
+
+    fn read_data(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((inode.size - offset) as usize);
+        let mut block = vec![0; self.block_size as usize];
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let in_block = (position % self.block_size) as usize;
+            let count = (self.block_size as usize - in_block).min(len - done);
+            match self.data_block(inode, position / self.block_size)? {
+                0 => block.fill(0),
+                data_block => self.read_block(u64::from(data_block), &mut block)?,
+            }
+            buf[done..done + count].copy_from_slice(&block[in_block..in_block + count]);
+            done += count;
+        }
+        Ok(len)
+    }
+
+    // (name, inode, type) of the files and directories, "." and ".." are skipped
+    fn dir_entries(&self, dir: &Inode) -> Result<Vec<(String, u32, FileType)>, FsError> {
+        let mut data = vec![0; usize::try_from(dir.size).map_err(|_| FsError::InvalidFormat)?];
+        self.read_data(dir, 0, &mut data)?;
+        let mut entries = Vec::new();
+        for block in data.chunks(self.block_size as usize) {
+            let mut offset = 0;
+            while offset + 8 <= block.len() {
+                let inode = read_u32(block, offset);
+                let record_len = usize::from(read_u16(block, offset + 4));
+                let name_len = if self.typed_entries {
+                    usize::from(block[offset + 6])
+                } else {
+                    usize::from(read_u16(block, offset + 6))
+                };
+                if record_len < 8 + name_len || offset + record_len > block.len() {
+                    return Err(FsError::InvalidFormat);
+                }
+                let name = &block[offset + 8..offset + 8 + name_len];
+                offset += record_len;
+                if inode == 0 || name == b"." || name == b".." {
+                    continue;
+                }
+                let file_type = if self.typed_entries {
+                    match block[offset - record_len + 7] {
+                        ENTRY_TYPE_FILE => Some(FileType::File),
+                        ENTRY_TYPE_DIR => Some(FileType::Dir),
+                        _ => None,
+                    }
+                } else {
+                    self.inode(inode)?.file_type()
+                };
+                if let Some(file_type) = file_type {
+                    entries.push((String::from_utf8_lossy(name).into_owned(), inode, file_type));
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+pub struct Ext2 {
+    volume: Arc<Volume>,
+}
+
+impl Ext2 {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        Ok(Self {
+            volume: Arc::new(Volume::parse(device)?),
+        })
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Dir> {
+        // checked by Volume::parse
+        let inode = self.volume.inode(ROOT_INODE).unwrap();
+        Arc::new(Ext2Dir {
+            volume: self.volume.clone(),
+            inode,
+        })
+    }
+}
+
+struct Ext2Dir {
+    volume: Arc<Volume>,
+    inode: Inode,
+}
+
+impl Dir for Ext2Dir {
+    fn lookup(&self, name: &str) -> Result<Node, FsError> {
+        let (_, number, _) = self
+            .volume
+            .dir_entries(&self.inode)?
+            .into_iter()
+            .find(|(entry_name, _, _)| entry_name == name)
+            .ok_or(FsError::NotFound)?;
+        let inode = self.volume.inode(number)?;
+        let volume = self.volume.clone();
+        match inode.file_type() {
+            Some(FileType::File) => Ok(Node::File(Arc::new(Ext2File { volume, inode }))),
+            Some(FileType::Dir) => Ok(Node::Dir(Arc::new(Self { volume, inode }))),
+            None => Err(FsError::InvalidFormat),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .volume
+            .dir_entries(&self.inode)?
+            .iter()
+            .map(|(name, _, file_type)| DirEntry::new(name, *file_type))
+            .collect())
+    }
+}
+
+struct Ext2File {
+    volume: Arc<Volume>,
+    inode: Inode,
+}
+
+impl File for Ext2File {
+    fn size(&self) -> u64 {
+        self.inode.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.volume.read_data(&self.inode, offset, buf)
+    }
+}
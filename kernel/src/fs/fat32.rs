@@ -0,0 +1,924 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use spin::Mutex;
+
+use super::{
+    block::{BlockDevice, SECTOR_SIZE},
+    path::check_name,
+    Dir, DirEntry, File, FileSystem, FileType, FsError, Node,
+};
+
+// FAT32 with long file names (VFAT) on a block device with 512 byte sectors.
+// Every operation locks the whole volume. There is no real time clock, new entries are dated 1980-01-01.
+// The free cluster count of the FSInfo sector is left unknown, so other systems count the free clusters
+// themselves
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const SIGNATURE_OFFSET: usize = 510;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+const ENTRY_SIZE: usize = 32;
+const FREE_ENTRY: u8 = 0x00; // this and all following entries are free
+const DELETED_ENTRY: u8 = 0xE5;
+const LAST_LONG_ENTRY: u8 = 0x40;
+const LONG_ENTRY_CHARS: usize = 13;
+// byte offsets of the utf16 characters in a long name entry
+const LONG_CHAR_OFFSETS: [usize; LONG_ENTRY_CHARS] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_LONG_NAME_LEN: usize = 255;
+// flags of the short entry: base name and extension are lower case (used by windows instead of a long name)
+const LOWER_CASE_BASE: u8 = 0x08;
+const LOWER_CASE_EXTENSION: u8 = 0x10;
+const SHORT_NAME_SPECIAL_CHARS: &[u8] = b"$%'-_@~`!(){}^#&";
+
+const FIRST_CLUSTER: u32 = 2;
+const CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+const MIN_END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const FAT_ENTRIES_PER_SECTOR: u64 = (SECTOR_SIZE / 4) as u64;
+
+const DOS_EPOCH_DATE: u16 = 1 << 5 | 1;
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+// layout of new volumes
+const RESERVED_SECTORS: u64 = 32;
+const FAT_COUNT: u64 = 2;
+const FSINFO_SECTOR: u64 = 1;
+const BACKUP_BOOT_SECTOR: u64 = 6;
+const ROOT_CLUSTER: u32 = FIRST_CLUSTER;
+const ZERO_CHUNK_SECTORS: usize = 64;
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    sectors_per_cluster: u64,
+    fat_start: u64,
+    fat_sectors: u64,
+    fat_count: u64,
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    // held by every operation of the file system
+    lock: Mutex<()>,
+    // where the search for a free cluster starts
+    next_free: AtomicU32,
+}
+
+// An entry of a directory, the offsets are bytes inside the directory
+struct RawEntry {
+    name: String,
+    short_name: [u8; 11],
+    attributes: u8,
+    first_cluster: u32,
+    // first long name entry (the short entry if there is no long name)
+    start: u64,
+    offset: u64,
+}
+
+impl RawEntry {
+    const fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+}
+
+// long name entries seen before a short entry
+struct LongName {
+    checksum: u8,
+    // the ordinal of the next entry, 0 when complete
+    next_ordinal: u8,
+    chars: Vec<u16>,
+    start: u64,
+}
+
+impl Volume {
+    fn parse(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let mut boot = [0; SECTOR_SIZE];
+        device.read_sectors(0, &mut boot)?;
+        let bytes_per_sector = read_u16(&boot, 11);
+        let sectors_per_cluster = u64::from(boot[13]);
+        let reserved = u64::from(read_u16(&boot, 14));
+        let fat_count = u64::from(boot[16]);
+        let root_entry_count = read_u16(&boot, 17);
+        let total_sectors = match read_u16(&boot, 19) {
+            0 => u64::from(read_u32(&boot, 32)),
+            total => u64::from(total),
+        };
+        let fat_size_16 = read_u16(&boot, 22);
+        let fat_sectors = u64::from(read_u32(&boot, 36));
+        let root_cluster = read_u32(&boot, 44);
+
+        // fat12 and fat16 have a fixed root directory and 16 bit fat size
+        let is_fat32 = boot[SIGNATURE_OFFSET..] == SIGNATURE
+            && usize::from(bytes_per_sector) == SECTOR_SIZE
+            && sectors_per_cluster.is_power_of_two()
+            && reserved > 0
+            && fat_count > 0
+            && root_entry_count == 0
+            && fat_size_16 == 0
+            && fat_sectors > 0
+            && total_sectors <= device.sector_count();
+        if !is_fat32 {
+            return Err(FsError::InvalidFormat);
+        }
+        let data_start = reserved + fat_count * fat_sectors;
+        let cluster_count = (total_sectors.saturating_sub(data_start) / sectors_per_cluster)
+            .min(fat_sectors * FAT_ENTRIES_PER_SECTOR - u64::from(FIRST_CLUSTER))
+            .min(u64::from(CLUSTER_MASK - FIRST_CLUSTER)) as u32;
+        let volume = Self {
+            device,
+            sectors_per_cluster,
+            fat_start: reserved,
+            fat_sectors,
+            fat_count,
+            data_start,
+            cluster_count,
+            root_cluster,
+            lock: Mutex::new(()),
+            next_free: AtomicU32::new(FIRST_CLUSTER),
+        };
+        if !volume.is_valid_cluster(root_cluster) {
+            return Err(FsError::InvalidFormat);
+        }
+        Ok(volume)
+    }
+
+    const fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR_SIZE as u64
+    }
+
+    const fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_CLUSTER && cluster - FIRST_CLUSTER < self.cluster_count
+    }
+
+    const fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster
+    }
+
+    fn fat_entry(&self, cluster: u32) -> Result<u32, FsError> {
+        let mut sector = [0; SECTOR_SIZE];
+        let index = u64::from(cluster);
+        self.device
+            .read_sectors(self.fat_start + index / FAT_ENTRIES_PER_SECTOR, &mut sector)?;
+        Ok(read_u32(&sector, (index % FAT_ENTRIES_PER_SECTOR) as usize * 4) & CLUSTER_MASK)
+    }
+
+    // in every copy of the fat, the upper 4 bits are reserved
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        let index = u64::from(cluster);
+        let offset = (index % FAT_ENTRIES_PER_SECTOR) as usize * 4;
+        let mut sector = [0; SECTOR_SIZE];
+        for fat in 0..self.fat_count {
+            let sector_index =
+                self.fat_start + fat * self.fat_sectors + index / FAT_ENTRIES_PER_SECTOR;
+            self.device.read_sectors(sector_index, &mut sector)?;
+            let old = read_u32(&sector, offset);
+            write_u32(
+                &mut sector,
+                offset,
+                old & !CLUSTER_MASK | value & CLUSTER_MASK,
+            );
+            self.device.write_sectors(sector_index, &sector)?;
+        }
+        Ok(())
+    }
+
+    // the clusters of a chain in order, empty for cluster 0 (empty files)
+    fn chain(&self, first: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            // a loop in the chain would be longer than the volume
+            if !self.is_valid_cluster(cluster) || chain.len() >= self.cluster_count as usize {
+                return Err(FsError::InvalidFormat);
+            }
+            chain.push(cluster);
+            cluster = match self.fat_entry(cluster)? {
+                MIN_END_OF_CHAIN.. => 0,
+                next => next,
+            };
+        }
+        Ok(chain)
+    }
+
+    // a zeroed cluster at the end of the chain of previous
+    fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32, FsError> {
+        let start = self.next_free.load(Ordering::Relaxed);
+        let mut sector = [0; SECTOR_SIZE];
+        let mut loaded = None;
+        for i in 0..self.cluster_count {
+            let cluster = FIRST_CLUSTER + (start - FIRST_CLUSTER + i) % self.cluster_count;
+            let index = u64::from(cluster);
+            let sector_index = self.fat_start + index / FAT_ENTRIES_PER_SECTOR;
+            if loaded != Some(sector_index) {
+                self.device.read_sectors(sector_index, &mut sector)?;
+                loaded = Some(sector_index);
+            }
+            if read_u32(&sector, (index % FAT_ENTRIES_PER_SECTOR) as usize * 4) & CLUSTER_MASK != 0
+            {
+                continue;
+            }
+            self.set_fat_entry(cluster, END_OF_CHAIN)?;
+            if let Some(previous) = previous {
+                self.set_fat_entry(previous, cluster)?;
+            }
+            self.zero_sectors(self.cluster_sector(cluster), self.sectors_per_cluster)?;
+            self.next_free.store(cluster + 1, Ordering::Relaxed);
+            return Ok(cluster);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    fn free_chain(&self, first: u32) -> Result<(), FsError> {
+        for cluster in self.chain(first)? {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    fn zero_sectors(&self, first: u64, count: u64) -> Result<(), FsError> {
+        zero_sectors(&*self.device, first, count)
+    }
+
+    // Reads or writes the bytes at offset of the data of the chain (which has to be long enough)
+    fn access_chain(
+        &self,
+        chain: &[u32],
+        offset: u64,
+        len: usize,
+        mut access: impl FnMut(&mut [u8; SECTOR_SIZE], usize, usize) -> bool,
+    ) -> Result<(), FsError> {
+        let mut sector = [0; SECTOR_SIZE];
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let cluster = chain[(position / self.cluster_size()) as usize];
+            let in_cluster = position % self.cluster_size();
+            let sector_index = self.cluster_sector(cluster) + in_cluster / SECTOR_SIZE as u64;
+            let in_sector = (in_cluster % SECTOR_SIZE as u64) as usize;
+            let count = (SECTOR_SIZE - in_sector).min(len - done);
+            self.device.read_sectors(sector_index, &mut sector)?;
+            // returns true if the sector was changed
+            if access(&mut sector, in_sector, done) {
+                self.device.write_sectors(sector_index, &sector)?;
+            }
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn read_chain(&self, chain: &[u32], offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let len = buf.len();
+        self.access_chain(chain, offset, len, |sector, in_sector, done| {
+            let count = (SECTOR_SIZE - in_sector).min(len - done);
+            buf[done..done + count].copy_from_slice(&sector[in_sector..in_sector + count]);
+            false
+        })
+    }
+
+    fn write_chain(&self, chain: &[u32], offset: u64, data: &[u8]) -> Result<(), FsError> {
+        self.access_chain(chain, offset, data.len(), |sector, in_sector, done| {
+            let count = (SECTOR_SIZE - in_sector).min(data.len() - done);
+            sector[in_sector..in_sector + count].copy_from_slice(&data[done..done + count]);
+            true
+        })
+    }
+
+    fn dir_chain(&self, dir_cluster: u32) -> Result<Vec<u32>, FsError> {
+        let chain = self.chain(dir_cluster)?;
+        if chain.is_empty() {
+            return Err(FsError::InvalidFormat);
+        }
+        Ok(chain)
+    }
+
+    fn read_dir(&self, dir_cluster: u32) -> Result<(Vec<RawEntry>, Vec<u8>), FsError> {
+        let chain = self.dir_chain(dir_cluster)?;
+        let mut bytes = vec![0; chain.len() * self.cluster_size() as usize];
+        self.read_chain(&chain, 0, &mut bytes)?;
+        Ok((parse_entries(&bytes), bytes))
+    }
+
+    fn find(&self, dir_cluster: u32, name: &str) -> Result<RawEntry, FsError> {
+        let (entries, _) = self.read_dir(dir_cluster)?;
+        entries
+            .into_iter()
+            .find(|entry| names_match(&entry.name, name))
+            .ok_or(FsError::NotFound)
+    }
+
+    // adds the entries for name (long name and short entry), returns the offset of the short entry
+    fn add_entry(
+        &self,
+        dir_cluster: u32,
+        name: &str,
+        attributes: u8,
+        first_cluster: u32,
+    ) -> Result<u64, FsError> {
+        let (entries, bytes) = self.read_dir(dir_cluster)?;
+        if entries.iter().any(|entry| names_match(&entry.name, name)) {
+            return Err(FsError::AlreadyExists);
+        }
+        let short_names: Vec<[u8; 11]> = entries.iter().map(|entry| entry.short_name).collect();
+        let short_name = short_name(name, &short_names)?;
+        let mut new_entries = long_name_entries(name, &short_name)?;
+        new_entries.extend_from_slice(&short_entry(&short_name, attributes, first_cluster));
+
+        let slots = new_entries.len() / ENTRY_SIZE;
+        let mut chain = self.dir_chain(dir_cluster)?;
+        let mut dir_len = bytes.len();
+        let start = if let Some(start) = free_run(&bytes, slots) {
+            start
+        } else {
+            // the run continues into new clusters
+            let free_at_end = bytes
+                .chunks_exact(ENTRY_SIZE)
+                .rev()
+                .take_while(|entry| matches!(entry[0], FREE_ENTRY | DELETED_ENTRY))
+                .count();
+            let start = dir_len - free_at_end * ENTRY_SIZE;
+            while dir_len < start + new_entries.len() {
+                chain.push(self.allocate_cluster(chain.last().copied())?);
+                dir_len += self.cluster_size() as usize;
+            }
+            start
+        };
+        self.write_chain(&chain, start as u64, &new_entries)?;
+        Ok((start + new_entries.len() - ENTRY_SIZE) as u64)
+    }
+
+    // marks the long name entries and the short entry as deleted
+    fn remove_entry(&self, dir_cluster: u32, entry: &RawEntry) -> Result<(), FsError> {
+        let chain = self.dir_chain(dir_cluster)?;
+        let mut offset = entry.start;
+        while offset <= entry.offset {
+            self.write_chain(&chain, offset, &[DELETED_ENTRY])?;
+            offset += ENTRY_SIZE as u64;
+        }
+        Ok(())
+    }
+
+    fn read_short_entry(&self, dir_cluster: u32, offset: u64) -> Result<[u8; ENTRY_SIZE], FsError> {
+        let chain = self.dir_chain(dir_cluster)?;
+        let mut entry = [0; ENTRY_SIZE];
+        self.read_chain(&chain, offset, &mut entry)?;
+        // the file was removed
+        if matches!(entry[0], FREE_ENTRY | DELETED_ENTRY) {
+            return Err(FsError::NotFound);
+        }
+        Ok(entry)
+    }
+
+    fn write_short_entry(
+        &self,
+        dir_cluster: u32,
+        offset: u64,
+        entry: &[u8; ENTRY_SIZE],
+    ) -> Result<(), FsError> {
+        let chain = self.dir_chain(dir_cluster)?;
+        self.write_chain(&chain, offset, entry)
+    }
+
+    fn free_clusters(&self) -> Result<u32, FsError> {
+        let mut free = 0;
+        let mut sector = [0; SECTOR_SIZE];
+        let last = u64::from(FIRST_CLUSTER + self.cluster_count);
+        for sector_offset in 0..last.div_ceil(FAT_ENTRIES_PER_SECTOR) {
+            self.device
+                .read_sectors(self.fat_start + sector_offset, &mut sector)?;
+            for (i, entry) in sector.chunks_exact(4).enumerate() {
+                let cluster = sector_offset * FAT_ENTRIES_PER_SECTOR + i as u64;
+                if (u64::from(FIRST_CLUSTER)..last).contains(&cluster)
+                    && read_u32(entry, 0) & CLUSTER_MASK == 0
+                {
+                    free += 1;
+                }
+            }
+        }
+        Ok(free)
+    }
+}
+
+fn zero_sectors(device: &dyn BlockDevice, first: u64, count: u64) -> Result<(), FsError> {
+    let zeros = vec![0; SECTOR_SIZE * ZERO_CHUNK_SECTORS];
+    let mut sector = first;
+    while sector < first + count {
+        let chunk = (first + count - sector).min(ZERO_CHUNK_SECTORS as u64);
+        device.write_sectors(sector, &zeros[..chunk as usize * SECTOR_SIZE])?;
+        sector += chunk;
+    }
+    Ok(())
+}
+
+fn names_match(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+fn decode_short_name(entry: &[u8]) -> String {
+    let lower = |bytes: &[u8], flag: u8| -> String {
+        let part = core::str::from_utf8(bytes).unwrap_or("_").trim_end();
+        if entry[12] & flag != 0 {
+            part.to_ascii_lowercase()
+        } else {
+            String::from(part)
+        }
+    };
+    let mut name = lower(&entry[..8], LOWER_CASE_BASE);
+    // 0x05 stands for a leading 0xE5 (which marks deleted entries)
+    if name.starts_with('\u{5}') {
+        name.replace_range(..1, "\u{E5}");
+    }
+    let extension = lower(&entry[8..11], LOWER_CASE_EXTENSION);
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(&extension);
+    }
+    name
+}
+
+// entries of a directory, "." and ".." and the volume label are skipped
+fn parse_entries(bytes: &[u8]) -> Vec<RawEntry> {
+    let mut entries = Vec::new();
+    let mut long_name: Option<LongName> = None;
+    for (index, entry) in bytes.chunks_exact(ENTRY_SIZE).enumerate() {
+        let offset = (index * ENTRY_SIZE) as u64;
+        match entry[0] {
+            FREE_ENTRY => break,
+            DELETED_ENTRY => {
+                long_name = None;
+                continue;
+            }
+            _ => {}
+        }
+        let attributes = entry[11];
+        if attributes & 0x3F == ATTR_LONG_NAME {
+            let ordinal = entry[0] & 0x1F;
+            let checksum = entry[13];
+            if entry[0] & LAST_LONG_ENTRY != 0 && ordinal > 0 {
+                long_name = Some(LongName {
+                    checksum,
+                    next_ordinal: ordinal,
+                    chars: vec![0xFFFF; ordinal as usize * LONG_ENTRY_CHARS],
+                    start: offset,
+                });
+            }
+            match &mut long_name {
+                Some(long)
+                    if long.next_ordinal == ordinal && long.checksum == checksum && ordinal > 0 =>
+                {
+                    let first = (ordinal as usize - 1) * LONG_ENTRY_CHARS;
+                    for (i, &char_offset) in LONG_CHAR_OFFSETS.iter().enumerate() {
+                        long.chars[first + i] = read_u16(entry, char_offset);
+                    }
+                    long.next_ordinal -= 1;
+                }
+                _ => long_name = None,
+            }
+            continue;
+        }
+        let long = long_name.take();
+        if attributes & ATTR_VOLUME_ID != 0 || entry[0] == b'.' {
+            continue;
+        }
+        let short_name: [u8; 11] = entry[..11].try_into().unwrap();
+        let (name, start) = match long {
+            Some(long)
+                if long.next_ordinal == 0 && long.checksum == short_name_checksum(&short_name) =>
+            {
+                let len = long
+                    .chars
+                    .iter()
+                    .position(|&c| c == 0)
+                    .unwrap_or(long.chars.len());
+                (String::from_utf16_lossy(&long.chars[..len]), long.start)
+            }
+            _ => (decode_short_name(entry), offset),
+        };
+        entries.push(RawEntry {
+            name,
+            short_name,
+            attributes,
+            first_cluster: u32::from(read_u16(entry, 20)) << 16 | u32::from(read_u16(entry, 26)),
+            start,
+            offset,
+        });
+    }
+    entries
+}
+
+// start of the first run of free entries of the given length
+fn free_run(bytes: &[u8], slots: usize) -> Option<usize> {
+    let mut run = 0;
+    for (index, entry) in bytes.chunks_exact(ENTRY_SIZE).enumerate() {
+        if matches!(entry[0], FREE_ENTRY | DELETED_ENTRY) {
+            run += 1;
+            if run == slots {
+                return Some((index + 1 - slots) * ENTRY_SIZE);
+            }
+        } else {
+            run = 0;
+        }
+    }
+    None
+}
+
+fn is_short_name_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || SHORT_NAME_SPECIAL_CHARS.contains(&byte)
+}
+
+// the short entry of names that are already valid upper case 8.3 names (they need no long name)
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    let valid = |part: &str, max: usize| part.len() <= max && part.bytes().all(is_short_name_char);
+    if base.is_empty() || !valid(base, 8) || !valid(extension, 3) {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+    Some(short_name)
+}
+
+// the exact 8.3 name or a unique alias like LONGNA~1.TXT
+fn short_name(name: &str, existing: &[[u8; 11]]) -> Result<[u8; 11], FsError> {
+    if let Some(short_name) = exact_short_name(name) {
+        return Ok(short_name);
+    }
+    let (base, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match u8::try_from(c.to_ascii_uppercase()) {
+                Ok(byte) if is_short_name_char(byte) => byte,
+                _ => b'_',
+            })
+            .collect()
+    };
+    let (base, extension) = (clean(base), clean(extension));
+    let mut short_name = [b' '; 11];
+    for (i, &byte) in extension.iter().take(3).enumerate() {
+        short_name[8 + i] = byte;
+    }
+    for number in 1..1_000_000 {
+        let suffix = format!("~{number}");
+        let kept = (8 - suffix.len()).min(base.len());
+        short_name[..8].fill(b' ');
+        short_name[..kept].copy_from_slice(&base[..kept]);
+        short_name[kept..kept + suffix.len()].copy_from_slice(suffix.as_bytes());
+        if !existing.contains(&short_name) {
+            return Ok(short_name);
+        }
+    }
+    Err(FsError::AlreadyExists)
+}
+
+// the long name entries in the order they are stored (last part first), none for exact short names
+fn long_name_entries(name: &str, short_name: &[u8; 11]) -> Result<Vec<u8>, FsError> {
+    if exact_short_name(name).is_some() {
+        return Ok(Vec::new());
+    }
+    let chars: Vec<u16> = name.encode_utf16().collect();
+    if chars.len() > MAX_LONG_NAME_LEN {
+        return Err(FsError::InvalidPath);
+    }
+    let count = chars.len().div_ceil(LONG_ENTRY_CHARS);
+    let checksum = short_name_checksum(short_name);
+    let mut entries = vec![0; count * ENTRY_SIZE];
+    for (entry, ordinal) in entries.chunks_exact_mut(ENTRY_SIZE).zip((1..=count).rev()) {
+        entry[0] = ordinal as u8 | if ordinal == count { LAST_LONG_ENTRY } else { 0 };
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        for (i, &char_offset) in LONG_CHAR_OFFSETS.iter().enumerate() {
+            // terminated with 0 and padded with 0xFFFF
+            let index = (ordinal - 1) * LONG_ENTRY_CHARS + i;
+            let c = match index.cmp(&chars.len()) {
+                core::cmp::Ordering::Less => chars[index],
+                core::cmp::Ordering::Equal => 0,
+                core::cmp::Ordering::Greater => 0xFFFF,
+            };
+            write_u16(entry, char_offset, c);
+        }
+    }
+    Ok(entries)
+}
+
+// the entry of a new (empty) file or directory
+fn short_entry(short_name: &[u8; 11], attributes: u8, first_cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0; ENTRY_SIZE];
+    entry[..11].copy_from_slice(short_name);
+    entry[11] = attributes;
+    // creation, last access and modification date
+    for offset in [16, 18, 24] {
+        write_u16(&mut entry, offset, DOS_EPOCH_DATE);
+    }
+    set_first_cluster(&mut entry, first_cluster);
+    entry
+}
+
+fn set_first_cluster(entry: &mut [u8; ENTRY_SIZE], cluster: u32) {
+    write_u16(entry, 20, (cluster >> 16) as u16);
+    write_u16(entry, 26, cluster as u16);
+}
+
+fn first_cluster(entry: &[u8; ENTRY_SIZE]) -> u32 {
+    u32::from(read_u16(entry, 20)) << 16 | u32::from(read_u16(entry, 26))
+}
+
+pub struct Fat32 {
+    volume: Arc<Volume>,
+}
+
+impl Fat32 {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        Ok(Self {
+            volume: Arc::new(Volume::parse(device)?),
+        })
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        self.volume.cluster_size()
+    }
+
+    pub fn free_clusters(&self) -> Result<u32, FsError> {
+        let _lock = self.volume.lock.lock();
+        self.volume.free_clusters()
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn root(&self) -> Arc<dyn Dir> {
+        Arc::new(FatDir {
+            volume: self.volume.clone(),
+            cluster: self.volume.root_cluster,
+        })
+    }
+}
+
+struct FatDir {
+    volume: Arc<Volume>,
+    cluster: u32,
+}
+
+impl FatDir {
+    fn node(&self, entry: &RawEntry) -> Node {
+        if entry.is_dir() {
+            // ".." entries refer to the root as cluster 0
+            let cluster = match entry.first_cluster {
+                0 => self.volume.root_cluster,
+                cluster => cluster,
+            };
+            Node::Dir(Arc::new(Self {
+                volume: self.volume.clone(),
+                cluster,
+            }))
+        } else {
+            Node::File(Arc::new(FatFile {
+                volume: self.volume.clone(),
+                dir_cluster: self.cluster,
+                offset: entry.offset,
+            }))
+        }
+    }
+}
+
+impl Dir for FatDir {
+    fn lookup(&self, name: &str) -> Result<Node, FsError> {
+        let _lock = self.volume.lock.lock();
+        Ok(self.node(&self.volume.find(self.cluster, name)?))
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        let _lock = self.volume.lock.lock();
+        let (entries, _) = self.volume.read_dir(self.cluster)?;
+        Ok(entries
+            .iter()
+            .map(|entry| {
+                let file_type = if entry.is_dir() {
+                    FileType::Dir
+                } else {
+                    FileType::File
+                };
+                DirEntry::new(&entry.name, file_type)
+            })
+            .collect())
+    }
+
+    fn create_file(&self, name: &str) -> Result<Arc<dyn File>, FsError> {
+        check_name(name)?;
+        let _lock = self.volume.lock.lock();
+        let offset = self.volume.add_entry(self.cluster, name, ATTR_ARCHIVE, 0)?;
+        Ok(Arc::new(FatFile {
+            volume: self.volume.clone(),
+            dir_cluster: self.cluster,
+            offset,
+        }))
+    }
+
+    fn create_dir(&self, name: &str) -> Result<Arc<dyn Dir>, FsError> {
+        check_name(name)?;
+        let _lock = self.volume.lock.lock();
+        let volume = &self.volume;
+        let cluster = volume.allocate_cluster(None)?;
+        let parent = if self.cluster == volume.root_cluster {
+            0
+        } else {
+            self.cluster
+        };
+        let mut dots = [0; 2 * ENTRY_SIZE];
+        dots[..ENTRY_SIZE].copy_from_slice(&short_entry(b".          ", ATTR_DIRECTORY, cluster));
+        dots[ENTRY_SIZE..].copy_from_slice(&short_entry(b"..         ", ATTR_DIRECTORY, parent));
+        let added = volume
+            .write_chain(&[cluster], 0, &dots)
+            .and_then(|()| volume.add_entry(self.cluster, name, ATTR_DIRECTORY, cluster));
+        if let Err(err) = added {
+            volume.free_chain(cluster)?;
+            return Err(err);
+        }
+        Ok(Arc::new(Self {
+            volume: volume.clone(),
+            cluster,
+        }))
+    }
+
+    fn remove(&self, name: &str) -> Result<(), FsError> {
+        let _lock = self.volume.lock.lock();
+        let volume = &self.volume;
+        let entry = volume.find(self.cluster, name)?;
+        if entry.is_dir() && !volume.read_dir(entry.first_cluster)?.0.is_empty() {
+            return Err(FsError::NotEmpty);
+        }
+        volume.remove_entry(self.cluster, &entry)?;
+        volume.free_chain(entry.first_cluster)
+    }
+}
+
+// Refers to its directory entry, which has the first cluster and the size (so all open copies agree)
+struct FatFile {
+    volume: Arc<Volume>,
+    dir_cluster: u32,
+    offset: u64,
+}
+
+impl File for FatFile {
+    fn size(&self) -> u64 {
+        let _lock = self.volume.lock.lock();
+        self.volume
+            .read_short_entry(self.dir_cluster, self.offset)
+            .map_or(0, |entry| u64::from(read_u32(&entry, 28)))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let _lock = self.volume.lock.lock();
+        let volume = &self.volume;
+        let entry = volume.read_short_entry(self.dir_cluster, self.offset)?;
+        let size = u64::from(read_u32(&entry, 28));
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let chain = volume.chain(first_cluster(&entry))?;
+        if (chain.len() as u64) < size.div_ceil(volume.cluster_size()) {
+            return Err(FsError::InvalidFormat);
+        }
+        volume.read_chain(&chain, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        let _lock = self.volume.lock.lock();
+        let volume = &self.volume;
+        let mut entry = volume.read_short_entry(self.dir_cluster, self.offset)?;
+        let size = u64::from(read_u32(&entry, 28));
+        let end = offset + data.len() as u64;
+        if end > MAX_FILE_SIZE {
+            return Err(FsError::NoSpace);
+        }
+        let mut chain = volume.chain(first_cluster(&entry))?;
+        let needed = end.max(size).div_ceil(volume.cluster_size()) as usize;
+        while chain.len() < needed {
+            let cluster = match volume.allocate_cluster(chain.last().copied()) {
+                Ok(cluster) => cluster,
+                // the clusters allocated so far stay in the chain and are used by the next write
+                Err(err) => {
+                    volume.write_short_entry(self.dir_cluster, self.offset, &entry)?;
+                    return Err(err);
+                }
+            };
+            if chain.is_empty() {
+                set_first_cluster(&mut entry, cluster);
+            }
+            chain.push(cluster);
+        }
+        // the tail of the last cluster may hold old data
+        if offset > size {
+            let zeros = vec![0; (offset - size) as usize];
+            volume.write_chain(&chain, size, &zeros)?;
+        }
+        volume.write_chain(&chain, offset, data)?;
+        write_u32(&mut entry, 28, end.max(size) as u32);
+        volume.write_short_entry(self.dir_cluster, self.offset, &entry)?;
+        Ok(data.len())
+    }
+}
+
+// Writes an empty FAT32 file system (two fats, cluster size as recommended by microsoft for the size).
+// Volumes below ~33 MiB have too few clusters for other systems to accept them as FAT32
+pub fn format(device: &dyn BlockDevice, label: &str) -> Result<(), FsError> {
+    let total_sectors = device.sector_count().min(u64::from(u32::MAX));
+    let sectors_per_cluster: u64 = match total_sectors * SECTOR_SIZE as u64 {
+        0..=0x1040_0000 => 1,
+        0x1040_0001..=0x2_0000_0000 => 8,
+        0x2_0000_0001..=0x4_0000_0000 => 16,
+        0x4_0000_0001..=0x8_0000_0000 => 32,
+        _ => 64,
+    };
+    // every fat sector covers 128 clusters, the fats are not part of the data area
+    let fat_sectors = (total_sectors.saturating_sub(RESERVED_SECTORS))
+        .div_ceil(128 * sectors_per_cluster + FAT_COUNT / 2);
+    let data_start = RESERVED_SECTORS + FAT_COUNT * fat_sectors;
+    if data_start + sectors_per_cluster > total_sectors {
+        return Err(FsError::NoSpace);
+    }
+
+    let mut boot = [0; SECTOR_SIZE];
+    boot[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"STEELMND");
+    write_u16(&mut boot, 11, SECTOR_SIZE as u16);
+    boot[13] = sectors_per_cluster as u8;
+    write_u16(&mut boot, 14, RESERVED_SECTORS as u16);
+    boot[16] = FAT_COUNT as u8;
+    boot[21] = 0xF8; // fixed disk
+    write_u16(&mut boot, 24, 63); // sectors per track and heads, only used by the bios
+    write_u16(&mut boot, 26, 255);
+    write_u32(&mut boot, 32, total_sectors as u32);
+    write_u32(&mut boot, 36, fat_sectors as u32);
+    write_u32(&mut boot, 44, ROOT_CLUSTER);
+    write_u16(&mut boot, 48, FSINFO_SECTOR as u16);
+    write_u16(&mut boot, 50, BACKUP_BOOT_SECTOR as u16);
+    boot[64] = 0x80; // drive number
+    boot[66] = 0x29; // the volume id, label and type follow
+    write_u32(&mut boot, 67, crate::random::next_u64() as u32);
+    let mut volume_label = [b' '; 11];
+    for (byte, label_byte) in volume_label.iter_mut().zip(label.bytes()) {
+        *byte = label_byte.to_ascii_uppercase();
+    }
+    boot[71..82].copy_from_slice(&volume_label);
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[SIGNATURE_OFFSET..].copy_from_slice(&SIGNATURE);
+
+    let mut fsinfo = [0; SECTOR_SIZE];
+    write_u32(&mut fsinfo, 0, 0x4161_5252);
+    write_u32(&mut fsinfo, 484, 0x6141_7272);
+    write_u32(&mut fsinfo, 488, u32::MAX); // free clusters unknown
+    write_u32(&mut fsinfo, 492, u32::MAX); // no hint for the next free cluster
+    write_u32(&mut fsinfo, 508, 0xAA55_0000);
+
+    zero_sectors(device, 0, data_start)?;
+    for first in [0, BACKUP_BOOT_SECTOR] {
+        device.write_sectors(first, &boot)?;
+        device.write_sectors(first + FSINFO_SECTOR, &fsinfo)?;
+    }
+    // media type, reserved entry, end of the root directory
+    let mut fat = [0; SECTOR_SIZE];
+    write_u32(&mut fat, 0, 0x0FFF_FF00 | u32::from(boot[21]));
+    write_u32(&mut fat, 4, END_OF_CHAIN);
+    write_u32(&mut fat, ROOT_CLUSTER as usize * 4, END_OF_CHAIN);
+    for copy in 0..FAT_COUNT {
+        device.write_sectors(RESERVED_SECTORS + copy * fat_sectors, &fat)?;
+    }
+    zero_sectors(device, data_start, sectors_per_cluster)
+}
@@ -0,0 +1,359 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use spin::RwLock;
+
+pub use block::{BlockDevice, RamBlockDevice, SECTOR_SIZE};
+pub use ext2::Ext2;
+pub use fat32::{format, Fat32};
+pub use path::Path;
+pub use ram_disk::RamDiskFs;
+pub use tar::TarFs;
+pub use tmpfs::TmpFs;
+
+mod block;
+mod ext2;
+mod fat32;
+mod path;
+mod ram_disk;
+mod tar;
+mod tmpfs;
+
+// Virtual file system: file systems are mounted at absolute paths, a path belongs to the file system with the
+// longest mount path it is inside of. Directories above mount points exist even if no file system provides
+// them (they list the mount points below them).
+// Files and directories are shared (Arc), so the file system objects use interior mutability.
+// No lock of the vfs is held while a file system is called, so file system operations can block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    ReadOnly,
+    InvalidPath,
+    // the directory is a mount point
+    Busy,
+    // the directory has entries
+    NotEmpty,
+    NoSpace,
+    // the device does not hold the expected file system or it is corrupted
+    InvalidFormat,
+    // the device failed or the access was outside of it
+    Io,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub file_type: FileType,
+    // in bytes, 0 for directories
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+}
+
+impl DirEntry {
+    pub fn new(name: &str, file_type: FileType) -> Self {
+        Self {
+            name: name.to_string(),
+            file_type,
+        }
+    }
+}
+
+pub trait File: Send + Sync {
+    fn size(&self) -> u64;
+
+    // returns 0 at or behind the end of the file
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError>;
+
+    // writing behind the end extends the file (the gap reads as zeros)
+    fn write_at(&self, _offset: u64, _data: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+pub trait Dir: Send + Sync {
+    fn lookup(&self, name: &str) -> Result<Node, FsError>;
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError>;
+
+    fn create_file(&self, _name: &str) -> Result<Arc<dyn File>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create_dir(&self, _name: &str) -> Result<Arc<dyn Dir>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    // directories have to be empty
+    fn remove(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Dir>;
+}
+
+#[derive(Clone)]
+pub enum Node {
+    File(Arc<dyn File>),
+    Dir(Arc<dyn Dir>),
+}
+
+impl Node {
+    pub fn metadata(&self) -> Metadata {
+        match self {
+            Self::File(file) => Metadata {
+                file_type: FileType::File,
+                size: file.size(),
+            },
+            Self::Dir(_) => Metadata {
+                file_type: FileType::Dir,
+                size: 0,
+            },
+        }
+    }
+}
+
+struct Mount {
+    path: Path,
+    fs: Arc<dyn FileSystem>,
+}
+
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+// directories above mount points that no file system provides
+struct EmptyDir;
+
+impl Dir for EmptyDir {
+    fn lookup(&self, _name: &str) -> Result<Node, FsError> {
+        Err(FsError::NotFound)
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(Vec::new())
+    }
+}
+
+// needs to be called once (only bsp) after the heap is initialized
+pub fn init() {
+    match crate::ram_disk::tar_archive() {
+        Some(archive) => mount("/boot", Arc::new(TarFs::new(archive).unwrap())),
+        None => mount("/boot", Arc::new(RamDiskFs::new())),
+    }
+    .unwrap();
+    mount("/tmp", Arc::new(TmpFs::new())).unwrap();
+}
+
+// the mount point does not have to exist in the file system above it
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = Path::parse(path)?;
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(FsError::AlreadyExists);
+    }
+    log::info!("Mounted {} at {path}", fs.name());
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+// open files of the file system stay usable
+pub fn unmount(path: &str) -> Result<Arc<dyn FileSystem>, FsError> {
+    let path = Path::parse(path)?;
+    let mut mounts = MOUNTS.write();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(FsError::NotFound)?;
+    Ok(mounts.remove(index).fs)
+}
+
+// (mount path, file system name)
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS
+        .read()
+        .iter()
+        .map(|mount| (mount.path.to_string(), mount.fs.name()))
+        .collect()
+}
+
+fn is_mount_point(path: &Path) -> bool {
+    MOUNTS.read().iter().any(|mount| mount.path == *path)
+}
+
+// names of the mount points directly inside path
+fn mount_points_in(path: &Path) -> Vec<String> {
+    MOUNTS
+        .read()
+        .iter()
+        .filter_map(|mount| match mount.path.strip_prefix(path)? {
+            [name] => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn resolve_path(path: &Path) -> Result<Node, FsError> {
+    let (innermost, above_mount_point) = {
+        let mounts = MOUNTS.read();
+        let innermost = mounts
+            .iter()
+            .filter_map(|mount| Some((mount, path.strip_prefix(&mount.path)?)))
+            .max_by_key(|(mount, _)| mount.path.components().count())
+            .map(|(mount, rest)| (mount.fs.root(), rest.to_vec()));
+        let above_mount_point = mounts.iter().any(|mount| {
+            mount
+                .path
+                .strip_prefix(path)
+                .is_some_and(|rest| !rest.is_empty())
+        });
+        (innermost, above_mount_point)
+    };
+    let walked = match innermost {
+        Some((root, rest)) => walk(root, &rest),
+        None => Err(FsError::NotFound),
+    };
+    match walked {
+        Err(FsError::NotFound) if above_mount_point => Ok(Node::Dir(Arc::new(EmptyDir))),
+        walked => walked,
+    }
+}
+
+fn walk(root: Arc<dyn Dir>, names: &[String]) -> Result<Node, FsError> {
+    let mut node = Node::Dir(root);
+    for name in names {
+        node = match node {
+            Node::Dir(dir) => dir.lookup(name)?,
+            Node::File(_) => return Err(FsError::NotADirectory),
+        };
+    }
+    Ok(node)
+}
+
+fn resolve_dir(path: &Path) -> Result<Arc<dyn Dir>, FsError> {
+    match resolve_path(path)? {
+        Node::Dir(dir) => Ok(dir),
+        Node::File(_) => Err(FsError::NotADirectory),
+    }
+}
+
+// the parent directory and the name of the entry in it
+fn resolve_parent(path: &str) -> Result<(Path, Arc<dyn Dir>, String), FsError> {
+    let path = Path::parse(path)?;
+    let name = path.name().ok_or(FsError::InvalidPath)?.to_string();
+    let parent = resolve_dir(&path.parent().unwrap())?;
+    Ok((path, parent, name))
+}
+
+pub fn lookup(path: &str) -> Result<Node, FsError> {
+    resolve_path(&Path::parse(path)?)
+}
+
+pub fn metadata(path: &str) -> Result<Metadata, FsError> {
+    Ok(lookup(path)?.metadata())
+}
+
+// sorted by name, includes the mount points inside the directory
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    let path = Path::parse(path)?;
+    let mut entries = resolve_dir(&path)?.entries()?;
+    for name in mount_points_in(&path) {
+        if !entries.iter().any(|entry| entry.name == name) {
+            entries.push(DirEntry::new(&name, FileType::Dir));
+        }
+    }
+    entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+pub fn open(path: &str) -> Result<OpenFile, FsError> {
+    match lookup(path)? {
+        Node::File(file) => Ok(OpenFile::new(file)),
+        Node::Dir(_) => Err(FsError::IsADirectory),
+    }
+}
+
+// fails if the file exists
+pub fn create(path: &str) -> Result<OpenFile, FsError> {
+    let (_, parent, name) = resolve_parent(path)?;
+    Ok(OpenFile::new(parent.create_file(&name)?))
+}
+
+pub fn create_dir(path: &str) -> Result<(), FsError> {
+    let (_, parent, name) = resolve_parent(path)?;
+    parent.create_dir(&name).map(|_| ())
+}
+
+// removes a file or an empty directory
+pub fn remove(path: &str) -> Result<(), FsError> {
+    let (path, parent, name) = resolve_parent(path)?;
+    if is_mount_point(&path) {
+        return Err(FsError::Busy);
+    }
+    parent.remove(&name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+// A file opened by the kernel or an application (see handle.rs), reads and writes continue at the current
+// position
+pub struct OpenFile {
+    file: Arc<dyn File>,
+    position: u64,
+}
+
+impl OpenFile {
+    pub fn new(file: Arc<dyn File>) -> Self {
+        Self { file, position: 0 }
+    }
+
+    // returns 0 at the end of the file
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let read = self.file.read_at(self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        let written = self.file.write_at(self.position, data)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    // Returns the new position, None if it would be before the start of the file.
+    // Positions behind the end are allowed (reads return 0)
+    pub fn seek(&mut self, from: SeekFrom) -> Option<u64> {
+        let position = match from {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.file.size().checked_add_signed(offset),
+        }?;
+        self.position = position;
+        Some(position)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+}
@@ -0,0 +1,98 @@
+use core::fmt;
+
+use alloc::{string::String, vec::Vec};
+
+use super::FsError;
+
+pub const MAX_NAME_LEN: usize = 255;
+
+// Normalized absolute path: "." components are dropped and ".." removes the component before it
+// (it stays at the root)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Path {
+    components: Vec<String>,
+}
+
+impl Path {
+    pub const fn root() -> Self {
+        Self {
+            components: Vec::new(),
+        }
+    }
+
+    pub fn parse(path: &str) -> Result<Self, FsError> {
+        let relative = path.strip_prefix('/').ok_or(FsError::InvalidPath)?;
+        let mut components = Vec::new();
+        for component in relative.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                name => {
+                    check_name(name)?;
+                    components.push(String::from(name));
+                }
+            }
+        }
+        Ok(Self { components })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.components.iter().map(String::as_str)
+    }
+
+    // the last component, None for the root
+    pub fn name(&self) -> Option<&str> {
+        self.components.last().map(String::as_str)
+    }
+
+    // None for the root
+    pub fn parent(&self) -> Option<Self> {
+        let (_, parent) = self.components.split_last()?;
+        Some(Self {
+            components: parent.to_vec(),
+        })
+    }
+
+    pub fn join(&self, name: &str) -> Result<Self, FsError> {
+        check_name(name)?;
+        let mut joined = self.clone();
+        joined.components.push(String::from(name));
+        Ok(joined)
+    }
+
+    // the components after prefix, None if the path is not inside of it
+    pub fn strip_prefix(&self, prefix: &Self) -> Option<&[String]> {
+        self.components.strip_prefix(prefix.components.as_slice())
+    }
+}
+
+// names of directory entries, "." and ".." are not allowed
+pub fn check_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\0'])
+    {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str("/");
+        }
+        for component in &self.components {
+            write!(f, "/{component}")?;
+        }
+        Ok(())
+    }
+}
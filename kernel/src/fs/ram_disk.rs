@@ -0,0 +1,66 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use super::{Dir, DirEntry, File, FileSystem, FileType, FsError, Node};
+use crate::ram_disk;
+
+// The files of the ram disk as a flat, read only directory (mounted at /boot)
+pub struct RamDiskFs {
+    root: Arc<RamDiskDir>,
+}
+
+impl RamDiskFs {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(RamDiskDir),
+        }
+    }
+}
+
+impl FileSystem for RamDiskFs {
+    fn name(&self) -> &'static str {
+        "ramdisk"
+    }
+
+    fn root(&self) -> Arc<dyn Dir> {
+        self.root.clone()
+    }
+}
+
+struct RamDiskDir;
+
+impl Dir for RamDiskDir {
+    fn lookup(&self, name: &str) -> Result<Node, FsError> {
+        let data = ram_disk::find_file(name).ok_or(FsError::NotFound)?;
+        Ok(Node::File(Arc::new(RamDiskFile::new(data))))
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(ram_disk::file_names()
+            .map(|name| DirEntry::new(name, FileType::File))
+            .collect())
+    }
+}
+
+// also the files of tar archives (see tar.rs)
+pub(super) struct RamDiskFile {
+    data: &'static [u8],
+}
+
+impl RamDiskFile {
+    pub(super) const fn new(data: &'static [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl File for RamDiskFile {
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let start = (offset.min(self.data.len() as u64)) as usize;
+        let len = buf.len().min(self.data.len() - start);
+        buf[..len].copy_from_slice(&self.data[start..start + len]);
+        Ok(len)
+    }
+}
@@ -0,0 +1,96 @@
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use super::{ram_disk::RamDiskFile, Dir, DirEntry, FileSystem, FileType, FsError, Node};
+use crate::tar::{self, EntryType};
+
+// A ustar archive in memory as a read only tree (the ram disk if bootimage wrote it as tar).
+// Directories without an entry of their own exist if the archive has paths inside them
+pub struct TarFs {
+    root: Arc<TarDir>,
+}
+
+impl TarFs {
+    pub fn new(archive: &'static [u8]) -> Result<Self, FsError> {
+        let mut root = TarDir::default();
+        for entry in tar::entries(archive) {
+            let entry = entry?;
+            let components: Vec<&str> = entry.components().collect();
+            match entry.entry_type {
+                EntryType::File => root.insert(&components, Some(entry.data))?,
+                EntryType::Dir => root.insert(&components, None)?,
+                EntryType::Other => {}
+            }
+        }
+        Ok(Self {
+            root: Arc::new(root),
+        })
+    }
+}
+
+impl FileSystem for TarFs {
+    fn name(&self) -> &'static str {
+        "tar"
+    }
+
+    fn root(&self) -> Arc<dyn Dir> {
+        self.root.clone()
+    }
+}
+
+enum TarNode {
+    File(&'static [u8]),
+    Dir(Arc<TarDir>),
+}
+
+#[derive(Default)]
+struct TarDir {
+    entries: BTreeMap<String, TarNode>,
+}
+
+impl TarDir {
+    // adds a file (with its data) or a directory, later files replace earlier ones with the same path
+    fn insert(&mut self, components: &[&str], data: Option<&'static [u8]>) -> Result<(), FsError> {
+        let Some((&name, rest)) = components.split_first() else {
+            // the root itself
+            return Ok(());
+        };
+        if rest.is_empty() {
+            if let Some(data) = data {
+                self.entries.insert(String::from(name), TarNode::File(data));
+                return Ok(());
+            }
+        }
+        let dir = self
+            .entries
+            .entry(String::from(name))
+            .or_insert_with(|| TarNode::Dir(Arc::default()));
+        match dir {
+            // nothing else references the directories while the tree is built
+            TarNode::Dir(dir) => Arc::get_mut(dir).unwrap().insert(rest, data),
+            TarNode::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+}
+
+impl Dir for TarDir {
+    fn lookup(&self, name: &str) -> Result<Node, FsError> {
+        match self.entries.get(name).ok_or(FsError::NotFound)? {
+            TarNode::File(data) => Ok(Node::File(Arc::new(RamDiskFile::new(data)))),
+            TarNode::Dir(dir) => Ok(Node::Dir(dir.clone())),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .entries
+            .iter()
+            .map(|(name, node)| {
+                let file_type = match node {
+                    TarNode::File(_) => FileType::File,
+                    TarNode::Dir(_) => FileType::Dir,
+                };
+                DirEntry::new(name, file_type)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,156 @@
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use spin::{Mutex, RwLock};
+
+use super::{path::check_name, Dir, DirEntry, File, FileSystem, FileType, FsError, Node};
+
+// Writable file system on the heap (mounted at /tmp), the content is lost on reboot
+pub struct TmpFs {
+    root: Arc<TmpDir>,
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(TmpDir::new()),
+        }
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Dir> {
+        self.root.clone()
+    }
+}
+
+#[derive(Clone)]
+enum TmpNode {
+    File(Arc<TmpFile>),
+    Dir(Arc<TmpDir>),
+}
+
+impl TmpNode {
+    fn node(&self) -> Node {
+        match self {
+            Self::File(file) => Node::File(file.clone()),
+            Self::Dir(dir) => Node::Dir(dir.clone()),
+        }
+    }
+}
+
+struct TmpDir {
+    entries: Mutex<BTreeMap<String, TmpNode>>,
+}
+
+impl TmpDir {
+    const fn new() -> Self {
+        Self {
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // adds the entry created by new if there is none with the name
+    fn insert(&self, name: &str, new: impl FnOnce() -> TmpNode) -> Result<TmpNode, FsError> {
+        check_name(name)?;
+        let mut entries = self.entries.lock();
+        if entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let node = new();
+        entries.insert(String::from(name), node.clone());
+        Ok(node)
+    }
+}
+
+impl Dir for TmpDir {
+    fn lookup(&self, name: &str) -> Result<Node, FsError> {
+        self.entries
+            .lock()
+            .get(name)
+            .map(TmpNode::node)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .entries
+            .lock()
+            .iter()
+            .map(|(name, node)| {
+                let file_type = match node {
+                    TmpNode::File(_) => FileType::File,
+                    TmpNode::Dir(_) => FileType::Dir,
+                };
+                DirEntry::new(name, file_type)
+            })
+            .collect())
+    }
+
+    fn create_file(&self, name: &str) -> Result<Arc<dyn File>, FsError> {
+        match self.insert(name, || TmpNode::File(Arc::new(TmpFile::new())))? {
+            TmpNode::File(file) => Ok(file),
+            TmpNode::Dir(_) => unreachable!(),
+        }
+    }
+
+    fn create_dir(&self, name: &str) -> Result<Arc<dyn Dir>, FsError> {
+        match self.insert(name, || TmpNode::Dir(Arc::new(Self::new())))? {
+            TmpNode::Dir(dir) => Ok(dir),
+            TmpNode::File(_) => unreachable!(),
+        }
+    }
+
+    // open files stay usable, their content is freed with the last reference
+    fn remove(&self, name: &str) -> Result<(), FsError> {
+        let mut entries = self.entries.lock();
+        match entries.get(name).ok_or(FsError::NotFound)? {
+            TmpNode::Dir(dir) if !dir.entries.lock().is_empty() => Err(FsError::NotEmpty),
+            _ => {
+                entries.remove(name);
+                Ok(())
+            }
+        }
+    }
+}
+
+struct TmpFile {
+    data: RwLock<Vec<u8>>,
+}
+
+impl TmpFile {
+    const fn new() -> Self {
+        Self {
+            data: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl File for TmpFile {
+    fn size(&self) -> u64 {
+        self.data.read().len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let data = self.data.read();
+        let start = offset.min(data.len() as u64) as usize;
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, new: &[u8]) -> Result<usize, FsError> {
+        let start = usize::try_from(offset).map_err(|_| FsError::NoSpace)?;
+        let end = start.checked_add(new.len()).ok_or(FsError::NoSpace)?;
+        let mut data = self.data.write();
+        if data.len() < end {
+            let additional = end - data.len();
+            data.try_reserve(additional).map_err(|_| FsError::NoSpace)?;
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(new);
+        Ok(new.len())
+    }
+}
@@ -0,0 +1,249 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use gdb_stub::{Connection, GdbStub, Memory, Registers, Stop};
+use spin::Mutex;
+use x86_64::{
+    registers::segmentation::{Segment, DS, ES, FS, GS},
+    structures::paging::Translate,
+    VirtAddr,
+};
+
+use crate::{backtrace, interrupts, memory, serial::COM2, smp::try_get_cld};
+
+// Gdb remote stub on COM2, enabled with the kernel command line option gdb=on (`cargo run -- --gdb 1234`).
+// int3 and the debug exception enter the stub with all registers saved (see trap_entry), the trapping core
+// serves the debugger with interrupts disabled while the other cores keep running.
+// A core stopped for longer than the watchdog interval is reported by the watchdog once.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STUB: Mutex<GdbStub<Com2>> = Mutex::new(GdbStub::new(Com2));
+
+// polled, the stub runs with interrupts disabled
+struct Com2;
+
+impl Connection for Com2 {
+    // lost bytes fail the packet checksum, the debugger resends the packet
+    fn read(&mut self) -> u8 {
+        loop {
+            if let Ok(byte) = COM2.read() {
+                return byte;
+            }
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        COM2.writer().write(byte);
+    }
+}
+
+// Accesses go through the physical memory mapping with the page table active at the trap, so breakpoints can be
+// placed in read only kernel code and in the running application
+struct PhysicalMemory;
+
+fn mapped(address: u64) -> Option<*mut u8> {
+    let address = VirtAddr::try_new(address).ok()?;
+    let phys = memory::get_active_l4_page_table().translate_addr(address)?;
+    Some(memory::phys_to_virt(phys).as_mut_ptr())
+}
+
+impl Memory for PhysicalMemory {
+    fn read(&mut self, address: u64, data: &mut [u8]) -> bool {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let Some(ptr) = mapped(address.wrapping_add(i as u64)) else {
+                return false;
+            };
+            *byte = unsafe { ptr.read_volatile() };
+        }
+        true
+    }
+
+    fn write(&mut self, address: u64, data: &[u8]) -> bool {
+        for (i, &byte) in data.iter().enumerate() {
+            let Some(ptr) = mapped(address.wrapping_add(i as u64)) else {
+                return false;
+            };
+            unsafe { ptr.write_volatile(byte) };
+        }
+        true
+    }
+}
+
+// Stops the boot until the debugger is attached. The kernel is relocated, gdb needs the image offset:
+// gdb -ex "symbol-file -o <offset> target/x86_64-unknown-none/opt-dev/kernel" -ex "target remote :1234"
+pub fn init() {
+    COM2.init();
+    ENABLED.store(true, Ordering::Release);
+    log::info!(
+        "Waiting for gdb on COM2 (symbol-file -o {:#x} <kernel>)",
+        crate::get_boot_info().kernel_image_offset
+    );
+    x86_64::instructions::interrupts::int3();
+    log::info!("gdb attached");
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+// general purpose registers pushed by trap_entry below the interrupt stack frame (int3 and #DB have no error code)
+#[repr(C)]
+struct TrapFrame {
+    rax: u64,
+    rbx: u64,
+    rcx: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rbp: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+impl TrapFrame {
+    fn registers(&self) -> Registers {
+        Registers {
+            rax: self.rax,
+            rbx: self.rbx,
+            rcx: self.rcx,
+            rdx: self.rdx,
+            rsi: self.rsi,
+            rdi: self.rdi,
+            rbp: self.rbp,
+            rsp: self.rsp,
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rip: self.rip,
+            rflags: self.rflags,
+            cs: self.cs,
+            ss: self.ss,
+            ds: DS::get_reg().0 as u64,
+            es: ES::get_reg().0 as u64,
+            fs: FS::get_reg().0 as u64,
+            gs: GS::get_reg().0 as u64,
+        }
+    }
+
+    // the segment registers can not be changed by the debugger
+    fn set_registers(&mut self, registers: &Registers) {
+        self.rax = registers.rax;
+        self.rbx = registers.rbx;
+        self.rcx = registers.rcx;
+        self.rdx = registers.rdx;
+        self.rsi = registers.rsi;
+        self.rdi = registers.rdi;
+        self.rbp = registers.rbp;
+        self.rsp = registers.rsp;
+        self.r8 = registers.r8;
+        self.r9 = registers.r9;
+        self.r10 = registers.r10;
+        self.r11 = registers.r11;
+        self.r12 = registers.r12;
+        self.r13 = registers.r13;
+        self.r14 = registers.r14;
+        self.r15 = registers.r15;
+        self.rip = registers.rip;
+        self.rflags = registers.rflags;
+    }
+}
+
+// Saves all general purpose registers as a TrapFrame and passes it to the handler, which may change them.
+// The cpu aligned the stack to 16 bytes before pushing the 5 entries of the interrupt stack frame,
+// with the 15 pushed registers it is aligned again for the call
+macro_rules! trap_entry {
+    ($name:ident, $handler:ident) => {
+        #[naked]
+        pub unsafe extern "C" fn $name() {
+            asm!(
+                "push r15",
+                "push r14",
+                "push r13",
+                "push r12",
+                "push r11",
+                "push r10",
+                "push r9",
+                "push r8",
+                "push rbp",
+                "push rdi",
+                "push rsi",
+                "push rdx",
+                "push rcx",
+                "push rbx",
+                "push rax",
+                "mov rdi, rsp",
+                "cld",
+                "call {handler}",
+                "pop rax",
+                "pop rbx",
+                "pop rcx",
+                "pop rdx",
+                "pop rsi",
+                "pop rdi",
+                "pop rbp",
+                "pop r8",
+                "pop r9",
+                "pop r10",
+                "pop r11",
+                "pop r12",
+                "pop r13",
+                "pop r14",
+                "pop r15",
+                "iretq",
+                handler = sym $handler,
+                options(noreturn),
+            )
+        }
+    };
+}
+
+trap_entry!(breakpoint_entry, breakpoint_trap);
+trap_entry!(debug_entry, debug_trap);
+
+extern "C" fn breakpoint_trap(frame: &mut TrapFrame) {
+    interrupts::count_interrupt(3);
+    if !is_enabled() {
+        let _handler = interrupts::enter_handler();
+        log::info!("Breakpoint at {:#x}", frame.rip);
+        return;
+    }
+    stopped(frame, Stop::Breakpoint);
+}
+
+extern "C" fn debug_trap(frame: &mut TrapFrame) {
+    interrupts::count_interrupt(1);
+    if !is_enabled() {
+        backtrace::log_registers(frame.rip, frame.rbp);
+        panic!(
+            "EXCEPTION: debug rip:{:#x} rsp:{:#x}\nCore local data: {:x?}",
+            frame.rip,
+            frame.rsp,
+            try_get_cld()
+        );
+    }
+    stopped(frame, Stop::Step);
+}
+
+fn stopped(frame: &mut TrapFrame, stop: Stop) {
+    let mut registers = frame.registers();
+    STUB.lock()
+        .stopped(&mut PhysicalMemory, &mut registers, stop);
+    frame.set_registers(&registers);
+}
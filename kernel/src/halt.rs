@@ -0,0 +1,68 @@
+use core::{
+    hint,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use x86_64::instructions::{self, interrupts};
+
+use crate::{
+    apic::{ipi, try_get_apic},
+    sched,
+    smp::try_get_cld,
+};
+
+// Stops the other cores when a core panics, so its output is not overwritten by them
+// and the qemu exit code of tests is decided by the panicking core.
+// They are stopped with an nmi, which also arrives if interrupts are disabled (see interrupts::nmi_handler)
+const NO_PANIC: u64 = u64::MAX;
+// the other cores usually halt within microseconds, hung ones are not waited for
+const HALT_WAIT_ITERATIONS: u64 = 10_000_000;
+
+static PANICKING_CORE: AtomicU64 = AtomicU64::new(NO_PANIC);
+static HALTED_CORES: AtomicU64 = AtomicU64::new(0);
+
+// Called by the panic handlers. Returns once the other cores halted,
+// halts this core if another one panicked first
+pub fn stop_other_cores() {
+    interrupts::disable();
+    // only the bsp runs before the core local data exists
+    let core = try_get_cld().map_or(0, |cld| cld.cpu_index);
+    if let Err(panicking) =
+        PANICKING_CORE.compare_exchange(NO_PANIC, core, Ordering::AcqRel, Ordering::Acquire)
+    {
+        if panicking != core {
+            halt_for_panic();
+        }
+        // panic while panicking
+        return;
+    }
+    let Some(mut apic) = try_get_apic() else {
+        return;
+    };
+    let other_cores = sched::online_core_count().saturating_sub(1) as u64;
+    apic.write_interrupt_command(ipi::create_nmi_broadcast_cmd());
+    for _ in 0..HALT_WAIT_ITERATIONS {
+        if HALTED_CORES.load(Ordering::Acquire) >= other_cores {
+            break;
+        }
+        hint::spin_loop();
+    }
+}
+
+pub fn is_panicking() -> bool {
+    PANICKING_CORE.load(Ordering::Acquire) != NO_PANIC
+}
+
+// called by the nmi handler of the other cores (nmis stay blocked since it never returns)
+pub fn halt_for_panic() -> ! {
+    interrupts::disable();
+    HALTED_CORES.fetch_add(1, Ordering::AcqRel);
+    log::error!(
+        "core {} halted due to panic on core {}",
+        try_get_cld().map_or(0, |cld| cld.cpu_index),
+        PANICKING_CORE.load(Ordering::Acquire)
+    );
+    loop {
+        instructions::hlt();
+    }
+}
@@ -0,0 +1,42 @@
+use alloc::{sync::Arc, vec::Vec};
+use spin::Mutex;
+
+use crate::{
+    fs::OpenFile,
+    pipe::{PipeReader, PipeWriter},
+};
+
+// Kernel objects an application uses through syscalls, referred to by their number in its handle table
+#[derive(Clone)]
+pub enum Handle {
+    PipeReader(PipeReader),
+    PipeWriter(PipeWriter),
+    // shared by the copies, so they use the same position
+    File(Arc<Mutex<OpenFile>>),
+}
+
+#[derive(Default)]
+pub struct HandleTable {
+    entries: Vec<Option<Handle>>,
+}
+
+impl HandleTable {
+    // returns the number of the handle, the lowest free number is reused
+    pub fn insert(&mut self, handle: Handle) -> u64 {
+        if let Some(index) = self.entries.iter().position(Option::is_none) {
+            self.entries[index] = Some(handle);
+            index as u64
+        } else {
+            self.entries.push(Some(handle));
+            self.entries.len() as u64 - 1
+        }
+    }
+
+    pub fn get(&self, number: u64) -> Option<&Handle> {
+        self.entries.get(number as usize)?.as_ref()
+    }
+
+    pub fn remove(&mut self, number: u64) -> Option<Handle> {
+        self.entries.get_mut(number as usize)?.take()
+    }
+}
@@ -0,0 +1,251 @@
+use core::{alloc::Layout, fmt};
+
+use spin::Mutex;
+
+use crate::backtrace::Backtrace;
+
+// Kernel heap checks (cargo feature heap-debug, enabled with bootimage --heap-debug):
+// Freed blocks are poisoned and kept in a quarantine, writes to them are detected when they leave it (at a later free).
+// Live allocations are tracked in a fixed size hash table to detect double, invalid and mismatched frees.
+// Callers are found by walking frame pointers (see backtrace.rs)
+const POISON: u8 = 0xDF;
+const LIVE_CAPACITY: usize = 1 << 14;
+const QUARANTINE_CAPACITY: usize = 256;
+const CALLER_DEPTH: usize = 6;
+
+pub type Callers = Backtrace<CALLER_DEPTH>;
+
+#[derive(Clone, Copy)]
+struct Allocation {
+    ptr: usize,
+    layout: Layout,
+    callers: Callers,
+}
+
+pub enum HeapError {
+    DoubleFree {
+        ptr: usize,
+        layout: Layout,
+        freed_by: Callers,
+    },
+    InvalidFree {
+        ptr: usize,
+        layout: Layout,
+    },
+    LayoutMismatch {
+        ptr: usize,
+        layout: Layout,
+        allocated_layout: Layout,
+        allocated_by: Callers,
+    },
+    UseAfterFree {
+        ptr: usize,
+        layout: Layout,
+        offset: usize,
+        freed_by: Callers,
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DoubleFree {
+                ptr,
+                layout,
+                freed_by,
+            } => write!(
+                f,
+                "double free of {ptr:#x} {layout:?} (first freed by: {freed_by})"
+            ),
+            Self::InvalidFree { ptr, layout } => {
+                write!(f, "free of unknown pointer {ptr:#x} {layout:?}")
+            }
+            Self::LayoutMismatch {
+                ptr,
+                layout,
+                allocated_layout,
+                allocated_by,
+            } => write!(
+                f,
+                "free of {ptr:#x} with {layout:?}, but it was allocated with {allocated_layout:?} (by: {allocated_by})"
+            ),
+            Self::UseAfterFree {
+                ptr,
+                layout,
+                offset,
+                freed_by,
+            } => write!(
+                f,
+                "use after free: {ptr:#x} {layout:?} was written at offset {offset} (freed by: {freed_by})"
+            ),
+        }
+    }
+}
+
+struct State {
+    // linear probing without tombstones (backward shift deletion)
+    live: [Option<Allocation>; LIVE_CAPACITY],
+    live_count: usize,
+    // set once an allocation did not fit into the table, unknown pointers are no longer reported from then on
+    overflowed: bool,
+    quarantine: [Option<Allocation>; QUARANTINE_CAPACITY],
+    quarantine_next: usize,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    live: [None; LIVE_CAPACITY],
+    live_count: 0,
+    overflowed: false,
+    quarantine: [None; QUARANTINE_CAPACITY],
+    quarantine_next: 0,
+});
+
+const fn home_slot(ptr: usize) -> usize {
+    ((ptr as u64 >> 4).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - LIVE_CAPACITY.trailing_zeros()))
+        as usize
+}
+
+impl State {
+    fn insert(&mut self, allocation: Allocation) {
+        // keep at least one empty slot, so lookups terminate
+        if self.live_count + 1 >= LIVE_CAPACITY {
+            self.overflowed = true;
+            return;
+        }
+        let mut slot = home_slot(allocation.ptr);
+        while self.live[slot].is_some() {
+            slot = (slot + 1) % LIVE_CAPACITY;
+        }
+        self.live[slot] = Some(allocation);
+        self.live_count += 1;
+    }
+
+    fn remove(&mut self, ptr: usize) -> Option<Allocation> {
+        let mut slot = home_slot(ptr);
+        loop {
+            match self.live[slot] {
+                None => return None,
+                Some(allocation) if allocation.ptr == ptr => break,
+                Some(_) => slot = (slot + 1) % LIVE_CAPACITY,
+            }
+        }
+        let removed = self.live[slot].take();
+        self.live_count -= 1;
+
+        // move following entries of the probe sequence into the hole
+        let mut hole = slot;
+        let mut next = (slot + 1) % LIVE_CAPACITY;
+        while let Some(allocation) = self.live[next] {
+            let home = home_slot(allocation.ptr);
+            let between = if hole <= next {
+                hole < home && home <= next
+            } else {
+                hole < home || home <= next
+            };
+            if !between {
+                self.live[hole] = self.live[next].take();
+                hole = next;
+            }
+            next = (next + 1) % LIVE_CAPACITY;
+        }
+        removed
+    }
+
+    fn quarantined(&self, ptr: usize) -> Option<&Allocation> {
+        self.quarantine
+            .iter()
+            .flatten()
+            .find(|allocation| allocation.ptr == ptr)
+    }
+
+    // returns the oldest block if the quarantine is full
+    fn push_quarantine(&mut self, allocation: Allocation) -> Option<Allocation> {
+        let evicted = self.quarantine[self.quarantine_next].replace(allocation);
+        self.quarantine_next = (self.quarantine_next + 1) % QUARANTINE_CAPACITY;
+        evicted
+    }
+}
+
+#[inline(always)]
+fn callers() -> Callers {
+    Backtrace::capture()
+}
+
+fn report(error: &HeapError) -> ! {
+    panic!("Heap corruption: {error}");
+}
+
+pub fn on_alloc(ptr: *mut u8, layout: Layout) {
+    if ptr.is_null() {
+        return;
+    }
+    let callers = callers();
+    STATE.lock().insert(Allocation {
+        ptr: ptr as usize,
+        layout,
+        callers,
+    });
+}
+
+// Has to be called before the block is freed. Poisons it and returns the block that has to be freed instead
+// (the one leaving the quarantine)
+pub unsafe fn on_dealloc(ptr: *mut u8, layout: Layout) -> Option<(*mut u8, Layout)> {
+    let callers = callers();
+    let mut state = STATE.lock();
+    let error = match state.remove(ptr as usize) {
+        Some(allocation) if allocation.layout != layout => Some(HeapError::LayoutMismatch {
+            ptr: ptr as usize,
+            layout,
+            allocated_layout: allocation.layout,
+            allocated_by: allocation.callers,
+        }),
+        Some(_) => None,
+        None => match state.quarantined(ptr as usize) {
+            Some(freed) => Some(HeapError::DoubleFree {
+                ptr: ptr as usize,
+                layout,
+                freed_by: freed.callers,
+            }),
+            None if !state.overflowed => Some(HeapError::InvalidFree {
+                ptr: ptr as usize,
+                layout,
+            }),
+            None => None,
+        },
+    };
+    if let Some(error) = error {
+        drop(state);
+        report(&error);
+    }
+
+    unsafe { ptr.write_bytes(POISON, layout.size()) };
+    let evicted = state.push_quarantine(Allocation {
+        ptr: ptr as usize,
+        layout,
+        callers,
+    })?;
+    drop(state);
+
+    let bytes =
+        unsafe { core::slice::from_raw_parts(evicted.ptr as *const u8, evicted.layout.size()) };
+    if let Some(offset) = bytes.iter().position(|&byte| byte != POISON) {
+        report(&HeapError::UseAfterFree {
+            ptr: evicted.ptr,
+            layout: evicted.layout,
+            offset,
+            freed_by: evicted.callers,
+        });
+    }
+    Some((evicted.ptr as *mut u8, evicted.layout))
+}
+
+pub fn log_stats(level: log::Level) {
+    let state = STATE.lock();
+    log::log!(
+        level,
+        "Heap debug: {} live allocations tracked (overflowed: {}), {} blocks in quarantine",
+        state.live_count,
+        state.overflowed,
+        state.quarantine.iter().flatten().count(),
+    );
+}
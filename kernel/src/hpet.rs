@@ -0,0 +1,55 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use hpet::Hpet;
+use port_io::VolatileMmio;
+use x86_64::PhysAddr;
+
+use crate::{acpi::ACPI, apic, memory};
+
+// register block of the main counter and 32 comparators
+const REGISTERS_LEN: u64 = 0x400;
+
+// The hpet (if acpi reports one) is the reference for the tsc and apic timer calibration.
+// It is also the clock of time::Instant if the tsc does not tick at a constant rate
+static HPET: spin::Once<Hpet<VolatileMmio>> = spin::Once::new();
+static IS_CLOCK: AtomicBool = AtomicBool::new(false);
+
+// needs to be called once (only bsp) before the apic is initialized (requires heap)
+pub fn init() {
+    let Some(phys_addr) = ACPI.lock().hpet_phys_addr else {
+        log::warn!("No HPET, timers are calibrated with the PIT");
+        return;
+    };
+    let virt = memory::map_named_mmio("hpet", PhysAddr::new(phys_addr), REGISTERS_LEN);
+    let hpet = match Hpet::new(unsafe { VolatileMmio::new(virt.as_mut_ptr()) }) {
+        Ok(hpet) => hpet,
+        Err(err) => {
+            log::warn!("HPET not usable: {err:?}");
+            return;
+        }
+    };
+    hpet.enable();
+
+    // a 32 bit counter wraps around too fast to be a clock
+    let is_clock = hpet.is_64bit() && !apic::tsc_is_invariant();
+    log::info!(
+        "HPET: {} Hz, {} bit counter{}",
+        hpet.frequency(),
+        if hpet.is_64bit() { 64 } else { 32 },
+        if is_clock { ", used as clock" } else { "" }
+    );
+    HPET.call_once(|| hpet);
+    IS_CLOCK.store(is_clock, Ordering::Release);
+}
+
+pub fn get() -> Option<&'static Hpet<VolatileMmio>> {
+    HPET.get()
+}
+
+pub fn clock() -> Option<&'static Hpet<VolatileMmio>> {
+    if IS_CLOCK.load(Ordering::Acquire) {
+        HPET.get()
+    } else {
+        None
+    }
+}
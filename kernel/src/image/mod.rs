@@ -0,0 +1,134 @@
+use crate::terminal_out::Color;
+use alloc::vec::Vec;
+
+mod png;
+mod ppm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    UnknownFormat,
+    // valid, but uses features the decoder doesn't have (e.g. interlaced pngs)
+    Unsupported,
+    Invalid,
+}
+
+// Decoded pixels, row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageBuffer {
+    pub width: usize,
+    pub height: usize,
+    // with their opacity
+    pub pixels: Vec<Color>,
+}
+
+impl ImageBuffer {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        crate::same!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // three bytes per pixel
+    pub fn from_rgb(width: usize, height: usize, rgb: &[u8]) -> Self {
+        let pixels = rgb
+            .chunks_exact(3)
+            .map(|pixel| Color::new(pixel[0], pixel[1], pixel[2]))
+            .collect();
+        Self::new(width, height, pixels)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    // the height of the image scaled to the width, keeping the aspect ratio
+    pub fn height_at(&self, width: usize) -> usize {
+        if self.width == 0 {
+            return 0;
+        }
+        self.height * width / self.width
+    }
+}
+
+const JPEG_SIGNATURE: &[u8] = &[0xFF, 0xD8, 0xFF];
+
+// the format is detected by the signature at the start of the file
+pub fn decode(data: &[u8]) -> Result<ImageBuffer, ImageError> {
+    if data.starts_with(png::SIGNATURE) {
+        png::decode(data)
+    } else if data.starts_with(JPEG_SIGNATURE) {
+        decode_jpeg(data)
+    } else if data.starts_with(ppm::SIGNATURE) {
+        ppm::decode(data)
+    } else {
+        Err(ImageError::UnknownFormat)
+    }
+}
+
+// as a binary ppm, the alpha is dropped
+pub fn encode_ppm(image: &ImageBuffer) -> Vec<u8> {
+    ppm::encode(image)
+}
+
+fn decode_jpeg(data: &[u8]) -> Result<ImageBuffer, ImageError> {
+    let mut decoder = zune_jpeg::JpegDecoder::new(data);
+    let pixels = decoder.decode().map_err(|error| {
+        log::debug!("Invalid jpeg: {error:?}");
+        ImageError::Invalid
+    })?;
+    let (width, height) = decoder.dimensions().ok_or(ImageError::Invalid)?;
+    Ok(ImageBuffer::from_rgb(width, height, &pixels))
+}
+
+// in pixels of a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    // the image keeps its size, it starts at the top left corner and is cut off at the edges
+    None,
+    // the image is stretched to the rectangle, every pixel takes the nearest one of the image
+    Nearest,
+}
+
+impl Scaling {
+    // the pixel of the image at the offset into the rectangle
+    pub const fn source(self, offset: usize, rect_len: usize, image_len: usize) -> usize {
+        match self {
+            Self::None => offset,
+            Self::Nearest => offset * image_len / rect_len,
+        }
+    }
+
+    // the part of the rectangle the image covers
+    pub const fn covered(self, rect_len: usize, image_len: usize) -> usize {
+        match self {
+            Self::None if image_len < rect_len => image_len,
+            Self::None | Self::Nearest => rect_len,
+        }
+    }
+}
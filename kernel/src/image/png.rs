@@ -0,0 +1,273 @@
+use super::{ImageBuffer, ImageError};
+use crate::terminal_out::Color;
+use alloc::vec::Vec;
+use zune_inflate::{DeflateDecoder, DeflateOptions};
+
+// PNG (RFC 2083) without interlacing. Every color type and bit depth is read, 16 bit samples are
+// cut to 8 bits. The chunk crcs aren't checked, the zlib checksum of the pixels is
+pub const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorType {
+    Gray,
+    Rgb,
+    Palette,
+    GrayAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    const fn channels(self) -> usize {
+        match self {
+            Self::Gray | Self::Palette => 1,
+            Self::GrayAlpha => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color_type: ColorType,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self, ImageError> {
+        if data.len() != 13 {
+            return Err(ImageError::Invalid);
+        }
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+        let depth = data[8];
+        let color_type = match (data[9], depth) {
+            (0, 1 | 2 | 4 | 8 | 16) => ColorType::Gray,
+            (2, 8 | 16) => ColorType::Rgb,
+            (3, 1 | 2 | 4 | 8) => ColorType::Palette,
+            (4, 8 | 16) => ColorType::GrayAlpha,
+            (6, 8 | 16) => ColorType::Rgba,
+            _ => return Err(ImageError::Invalid),
+        };
+        // compression and filter method 0 are the only ones
+        if width == 0 || height == 0 || data[10] != 0 || data[11] != 0 {
+            return Err(ImageError::Invalid);
+        }
+        if data[12] != 0 {
+            return Err(ImageError::Unsupported);
+        }
+        Ok(Self {
+            width,
+            height,
+            depth,
+            color_type,
+        })
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * usize::from(self.depth)
+    }
+
+    // without the filter type byte
+    fn row_len(&self) -> Result<usize, ImageError> {
+        self.width
+            .checked_mul(self.bits_per_pixel())
+            .map(|bits| bits.div_ceil(8))
+            .ok_or(ImageError::Invalid)
+    }
+}
+
+// (type, data)
+fn chunks(mut data: &[u8]) -> impl Iterator<Item = Result<(&[u8], &[u8]), ImageError>> {
+    core::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        let Some((len, rest)) = data.split_first_chunk::<4>() else {
+            data = &[];
+            return Some(Err(ImageError::Invalid));
+        };
+        let len = u32::from_be_bytes(*len) as usize;
+        // the type, the data and the crc
+        let Some(chunk) = rest.get(..4 + len + 4) else {
+            data = &[];
+            return Some(Err(ImageError::Invalid));
+        };
+        data = &rest[4 + len + 4..];
+        Some(Ok((&chunk[..4], &chunk[4..4 + len])))
+    })
+}
+
+pub fn decode(data: &[u8]) -> Result<ImageBuffer, ImageError> {
+    let data = data
+        .strip_prefix(SIGNATURE)
+        .ok_or(ImageError::UnknownFormat)?;
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: Option<&[u8]> = None;
+    let mut compressed = Vec::new();
+    for chunk in chunks(data) {
+        match chunk? {
+            (b"IHDR", data) => header = Some(Header::parse(data)?),
+            (b"PLTE", data) => palette = data,
+            (b"tRNS", data) => transparency = Some(data),
+            (b"IDAT", data) => compressed.extend_from_slice(data),
+            (b"IEND", _) => break,
+            // ancillary chunks (the first letter is lowercase) can be skipped
+            (chunk_type, _) if chunk_type[0].is_ascii_lowercase() => {}
+            _ => return Err(ImageError::Unsupported),
+        }
+    }
+    let header = header.ok_or(ImageError::Invalid)?;
+
+    let row_len = header.row_len()?;
+    let len = (row_len + 1)
+        .checked_mul(header.height)
+        .ok_or(ImageError::Invalid)?;
+    let mut filtered = DeflateDecoder::new_with_options(
+        &compressed,
+        DeflateOptions::default().set_limit(len).set_size_hint(len),
+    )
+    .decode_zlib()
+    .map_err(|error| {
+        log::debug!("Invalid png: {error:?}");
+        ImageError::Invalid
+    })?;
+    if filtered.len() < len {
+        return Err(ImageError::Invalid);
+    }
+
+    let bytes_per_pixel = header.bits_per_pixel().div_ceil(8);
+    let mut pixels = Vec::with_capacity(header.width * header.height);
+    let mut previous: &[u8] = &[];
+    for row in filtered[..len].chunks_exact_mut(row_len + 1) {
+        let (filter, row) = row.split_first_mut().unwrap();
+        unfilter(*filter, row, previous, bytes_per_pixel)?;
+        for x in 0..header.width {
+            pixels.push(read_pixel(&header, row, x, palette, transparency)?);
+        }
+        previous = row;
+    }
+
+    Ok(ImageBuffer::new(header.width, header.height, pixels))
+}
+
+// undoes the filter of the row, the previous row is already unfiltered (empty for the first row)
+fn unfilter(
+    filter: u8,
+    row: &mut [u8],
+    previous: &[u8],
+    bytes_per_pixel: usize,
+) -> Result<(), ImageError> {
+    let above = |i: usize| previous.get(i).copied().unwrap_or(0);
+    for i in 0..row.len() {
+        let left = if i >= bytes_per_pixel {
+            row[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        let upper_left = if i >= bytes_per_pixel {
+            above(i - bytes_per_pixel)
+        } else {
+            0
+        };
+        let prediction = match filter {
+            0 => 0,
+            1 => left,
+            2 => above(i),
+            3 => left.midpoint(above(i)),
+            4 => paeth(left, above(i), upper_left),
+            _ => return Err(ImageError::Invalid),
+        };
+        row[i] = row[i].wrapping_add(prediction);
+    }
+    Ok(())
+}
+
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = i16::from(left) + i16::from(above) - i16::from(upper_left);
+    let distance = |value: u8| (estimate - i16::from(value)).abs();
+    if distance(left) <= distance(above) && distance(left) <= distance(upper_left) {
+        left
+    } else if distance(above) <= distance(upper_left) {
+        above
+    } else {
+        upper_left
+    }
+}
+
+// the sample (channel) at the index of the row as it is stored
+fn sample(row: &[u8], index: usize, depth: u8) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => u16::from(row[index]),
+        _ => {
+            let bit = index * usize::from(depth);
+            let shift = 8 - usize::from(depth) - bit % 8;
+            u16::from(row[bit / 8] >> shift) & ((1 << depth) - 1)
+        }
+    }
+}
+
+// scaled to 8 bits
+fn to_byte(sample: u16, depth: u8) -> u8 {
+    match depth {
+        16 => (sample >> 8) as u8,
+        depth => (u32::from(sample) * 255 / ((1 << depth) - 1)) as u8,
+    }
+}
+
+fn read_pixel(
+    header: &Header,
+    row: &[u8],
+    x: usize,
+    palette: &[u8],
+    transparency: Option<&[u8]>,
+) -> Result<Color, ImageError> {
+    let depth = header.depth;
+    let channels = header.color_type.channels();
+    let samples = || (0..channels).map(|channel| sample(row, x * channels + channel, depth));
+    let mut bytes = samples().map(|value| to_byte(value, depth));
+    let mut next = || bytes.next().unwrap();
+    // the transparent color of images without alpha channel, in samples as they are stored
+    let opacity = || {
+        let transparent = transparency.is_some_and(|transparency| {
+            transparency.len() == channels * 2
+                && transparency
+                    .chunks_exact(2)
+                    .map(|value| u16::from_be_bytes([value[0], value[1]]))
+                    .eq(samples())
+        });
+        if transparent {
+            0
+        } else {
+            u8::MAX
+        }
+    };
+
+    Ok(match header.color_type {
+        ColorType::Gray => {
+            let gray = next();
+            Color::rgba(gray, gray, gray, opacity())
+        }
+        ColorType::Rgb => Color::rgba(next(), next(), next(), opacity()),
+        ColorType::Palette => {
+            let index = usize::from(sample(row, x, depth));
+            let entry = palette
+                .get(index * 3..index * 3 + 3)
+                .ok_or(ImageError::Invalid)?;
+            // entries after the end of the transparency chunk are opaque
+            let alpha = transparency
+                .and_then(|transparency| transparency.get(index).copied())
+                .unwrap_or(u8::MAX);
+            Color::rgba(entry[0], entry[1], entry[2], alpha)
+        }
+        ColorType::GrayAlpha => {
+            let gray = next();
+            Color::rgba(gray, gray, gray, next())
+        }
+        ColorType::Rgba => Color::rgba(next(), next(), next(), next()),
+    })
+}
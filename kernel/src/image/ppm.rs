@@ -0,0 +1,67 @@
+use super::{ImageBuffer, ImageError};
+use crate::terminal_out::Color;
+use alloc::{format, vec::Vec};
+
+// Binary PPM (P6), the simplest format to get pixels out of the kernel (see terminal_out::screenshot).
+// It is written with 8 bit samples and without alpha, 16 bit samples aren't read
+pub const SIGNATURE: &[u8] = b"P6";
+
+pub fn encode(image: &ImageBuffer) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+    ppm.reserve(image.pixels.len() * 3);
+    for pixel in &image.pixels {
+        ppm.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+    ppm
+}
+
+pub fn decode(data: &[u8]) -> Result<ImageBuffer, ImageError> {
+    let mut rest = data
+        .strip_prefix(SIGNATURE)
+        .ok_or(ImageError::UnknownFormat)?;
+
+    // width, height and the largest sample value, separated by whitespace and comments
+    let mut fields = [0; 3];
+    for field in &mut fields {
+        loop {
+            match rest.first() {
+                Some(byte) if byte.is_ascii_whitespace() => rest = &rest[1..],
+                Some(b'#') => {
+                    let line = rest.iter().position(|&byte| byte == b'\n');
+                    rest = &rest[line.map_or(rest.len(), |line| line + 1)..];
+                }
+                _ => break,
+            }
+        }
+        let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+        *field = core::str::from_utf8(&rest[..digits])
+            .unwrap()
+            .parse::<usize>()
+            .map_err(|_| ImageError::Invalid)?;
+        rest = &rest[digits..];
+    }
+    let [width, height, max] = fields;
+    // a single whitespace before the pixels
+    let pixels = match rest.split_first() {
+        Some((byte, pixels)) if byte.is_ascii_whitespace() => pixels,
+        _ => return Err(ImageError::Invalid),
+    };
+    match max {
+        0 => return Err(ImageError::Invalid),
+        256.. => return Err(ImageError::Unsupported),
+        _ => {}
+    }
+
+    let len = width
+        .checked_mul(height)
+        .and_then(|len| len.checked_mul(3))
+        .ok_or(ImageError::Invalid)?;
+    let scale = |sample: u8| (usize::from(sample) * 255 / max).min(255) as u8;
+    let pixels = pixels
+        .get(..len)
+        .ok_or(ImageError::Invalid)?
+        .chunks_exact(3)
+        .map(|pixel| Color::new(scale(pixel[0]), scale(pixel[1]), scale(pixel[2])))
+        .collect();
+    Ok(ImageBuffer::new(width, height, pixels))
+}
@@ -0,0 +1,84 @@
+use alloc::{sync::Arc, vec::Vec};
+use spin::Mutex;
+use thingbuf::ThingBuf;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::sched::WaitQueue;
+
+// events of a subscriber that does not keep up are dropped
+const QUEUE_CAPACITY: usize = 128;
+
+struct Queue<T> {
+    events: ThingBuf<Option<T>>,
+    available: WaitQueue,
+}
+
+// Hands the events of an input device (published by its interrupt) to every subscriber,
+// each one has its own lock free queue
+pub struct Subscribers<T: 'static> {
+    // locked with interrupts disabled
+    queues: Mutex<Vec<Arc<Queue<T>>>>,
+}
+
+impl<T: Copy> Subscribers<T> {
+    pub const fn new() -> Self {
+        Self {
+            queues: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn publish(&self, event: T) {
+        without_interrupts(|| {
+            for queue in self.queues.lock().iter() {
+                if queue.events.push(Some(event)).is_ok() {
+                    queue.available.wake_all();
+                }
+            }
+        });
+    }
+
+    // receives every event published from now on (until it is dropped)
+    pub fn subscribe(&'static self) -> Events<T> {
+        let queue = Arc::new(Queue {
+            events: ThingBuf::new(QUEUE_CAPACITY),
+            available: WaitQueue::new(),
+        });
+        without_interrupts(|| self.queues.lock().push(queue.clone()));
+        Events {
+            queue,
+            subscribers: self,
+        }
+    }
+}
+
+pub struct Events<T: 'static> {
+    queue: Arc<Queue<T>>,
+    subscribers: &'static Subscribers<T>,
+}
+
+impl<T: Copy> Events<T> {
+    pub fn try_next(&self) -> Option<T> {
+        self.queue.events.pop().flatten()
+    }
+
+    // blocks until an event arrives
+    pub fn next(&self) -> T {
+        let mut event = None;
+        self.queue.available.wait_until(|| {
+            event = self.try_next();
+            event.is_some()
+        });
+        event.unwrap()
+    }
+}
+
+impl<T> Drop for Events<T> {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            self.subscribers
+                .queues
+                .lock()
+                .retain(|queue| !Arc::ptr_eq(queue, &self.queue));
+        });
+    }
+}
@@ -1,28 +1,46 @@
-use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use alloc::{boxed::Box, vec::Vec};
 use lazy_static::lazy_static;
+use spin::Mutex;
 use x86_64::{
-    instructions::{port::Port, tables::load_tss},
+    instructions::{self, port::Port, tables::load_tss},
     registers::segmentation::{Segment, CS, DS, SS},
     structures::{
         gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
         idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
         tss::TaskStateSegment,
     },
-    VirtAddr,
+    PrivilegeLevel, VirtAddr,
 };
 
 use crate::{
-    apic::get_apic,
-    constants::MAX_CORES,
-    serial_println,
-    smp::{get_cld, try_get_cld},
+    apic::{self, get_apic},
+    backtrace,
+    constants::v,
+    gdb, halt, kernel_stack, loader, memory, per_core,
+    smp::{get_cld, try_get_cld, REMOTE_CALL_VECTOR},
+    timer_callbacks, user_access,
+    watchdog::{self, Nmi},
 };
 
+const IST_STACK_SIZE: usize = 4096 * 5;
+// stack for interrupts and syscalls from ring 3 (tss rsp0), each task has its own (see sched)
+pub const PRIVILEGE_STACK_SIZE: usize = 4096 * 16;
+const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const PAGE_FAULT_IST_INDEX: u16 = 1;
+
+pub const TIMER_VECTOR: u8 = 32;
+// 32..48 is left to the (masked) legacy pic, 0xF0 and above are reserved for ipis and the spurious vector
+const FIRST_DYNAMIC_VECTOR: u8 = 0x30;
+const LAST_DYNAMIC_VECTOR: u8 = 0xEF;
+const SPURIOUS_VECTOR: u8 = 0xFF;
+
 macro_rules! interrupt_handler___ {
-    ($idt:ident, $x:ident) => {{
+    ($idt:ident, $x:ident, $vector:literal) => {{
         extern "x86-interrupt" fn handler(stack_frame: InterruptStackFrame) {
+            count_interrupt($vector);
+            backtrace::log_interrupted(&stack_frame);
             let cld = try_get_cld();
             panic!(
                 "EXCEPTION: {}\n{:#?}\nCore local data: {:x?}",
@@ -37,8 +55,10 @@ macro_rules! interrupt_handler___ {
 }
 
 macro_rules! interrupt_handler_ec {
-    ($idt:ident, $x:ident) => {{
+    ($idt:ident, $x:ident, $vector:literal) => {{
         extern "x86-interrupt" fn handler(stack_frame: InterruptStackFrame, ec: u64) {
+            count_interrupt($vector);
+            backtrace::log_interrupted(&stack_frame);
             let cld = try_get_cld();
             panic!(
                 "EXCEPTION: {} error code:{}\n{:#?}\nCore local data: {:x?}",
@@ -53,80 +73,160 @@ macro_rules! interrupt_handler_ec {
     }};
 }
 
+// installs irq_entry for the vectors high * 16 + 0..16 of every given high nibble
+macro_rules! irq_entries {
+    ($idt:ident, $($high:literal)*) => {$(
+        irq_entries!(@row $idt, $high, 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+    )*};
+    (@row $idt:ident, $high:literal, $($low:literal)*) => {$(
+        $idt[$high * 16 + $low].set_handler_fn(irq_entry::<{ $high * 16 + $low }>);
+    )*};
+}
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
 
-        interrupt_handler_ec!(idt, alignment_check);
-        interrupt_handler___!(idt, bound_range_exceeded);
-        interrupt_handler_ec!(idt, cp_protection_exception);
-        interrupt_handler___!(idt, device_not_available);
-        interrupt_handler___!(idt, divide_error);
-        interrupt_handler_ec!(idt, general_protection_fault);
-        interrupt_handler___!(idt, hv_injection_exception);
-        interrupt_handler___!(idt, invalid_opcode);
-        interrupt_handler_ec!(idt, invalid_tss);
-        interrupt_handler___!(idt, non_maskable_interrupt);
-        interrupt_handler___!(idt, overflow);
-        interrupt_handler_ec!(idt, security_exception);
-        interrupt_handler_ec!(idt, segment_not_present);
-        interrupt_handler___!(idt, simd_floating_point);
-        interrupt_handler_ec!(idt, stack_segment_fault);
-        interrupt_handler___!(idt, virtualization);
-        interrupt_handler_ec!(idt, vmm_communication_exception);
-        interrupt_handler___!(idt, x87_floating_point);
-
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        interrupt_handler_ec!(idt, alignment_check, 17);
+        interrupt_handler___!(idt, bound_range_exceeded, 5);
+        interrupt_handler_ec!(idt, cp_protection_exception, 21);
+        interrupt_handler___!(idt, device_not_available, 7);
+        interrupt_handler___!(idt, divide_error, 0);
+        interrupt_handler___!(idt, hv_injection_exception, 28);
+        interrupt_handler_ec!(idt, invalid_tss, 10);
+        interrupt_handler___!(idt, overflow, 4);
+        interrupt_handler_ec!(idt, security_exception, 30);
+        interrupt_handler_ec!(idt, segment_not_present, 11);
+        interrupt_handler___!(idt, simd_floating_point, 19);
+        interrupt_handler_ec!(idt, stack_segment_fault, 12);
+        interrupt_handler___!(idt, virtualization, 20);
+        interrupt_handler_ec!(idt, vmm_communication_exception, 29);
+        interrupt_handler___!(idt, x87_floating_point, 16);
+
+        // the page fault handler maps demand paged user stacks, so it can not run on the faulting stack
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(PAGE_FAULT_IST_INDEX);
+        }
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
-                .set_stack_index(0);
+                .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        }
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
+        // every other vector is dispatched to the handler registered with register_irq
+        irq_entries!(idt, 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+        idt[SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt);
+        // int3 and single steps are handled by the gdb stub, which needs all registers (see gdb.rs)
+        unsafe {
+            idt.debug
+                .set_handler_addr(VirtAddr::new(gdb::debug_entry as usize as u64));
+            let breakpoint = idt
+                .breakpoint
+                .set_handler_addr(VirtAddr::new(gdb::breakpoint_entry as usize as u64));
+            // breakpoints in the application
+            if crate::cmdline::get().gdb {
+                breakpoint.set_privilege_level(PrivilegeLevel::Ring3);
+            }
         }
-        idt[32].set_handler_fn(timer_interrupt);
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt
     };
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[0] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+            static mut STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
 
             let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            stack_start + STACK_SIZE
+            stack_start + IST_STACK_SIZE
+        };
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            static mut STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+            stack_start + IST_STACK_SIZE
+        };
+        tss.privilege_stack_table[0] = {
+            static mut STACK: [u8; PRIVILEGE_STACK_SIZE] = [0; PRIVILEGE_STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+            stack_start + PRIVILEGE_STACK_SIZE
         };
         tss
     };
-    static ref AP_TSS: [TaskStateSegment; MAX_CORES as usize - 1] = {
-        let mut tss_arr = [TaskStateSegment::new(); MAX_CORES as usize - 1];
+    // for the aps acpi reports, allocated by the bsp before they start (see prepare_ap_gdts)
+    static ref AP_TSS: Vec<TaskStateSegment> = {
+        let ap_count = crate::acpi::ACPI.lock().ap_count as usize;
+        let mut tss_arr = alloc::vec![TaskStateSegment::new(); ap_count];
         for tss in &mut tss_arr {
-            tss.interrupt_stack_table[0] = {
-                const STACK_SIZE: usize = 4096 * 5;
-                let stack = Box::leak(Box::new([0u8; STACK_SIZE]));
+            for index in [DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX] {
+                tss.interrupt_stack_table[index as usize] = {
+                    let stack = Box::leak(Box::new([0u8; IST_STACK_SIZE]));
+
+                    let stack_start = VirtAddr::from_ptr(stack.as_ptr());
+                    stack_start + IST_STACK_SIZE
+                };
+            }
+            tss.privilege_stack_table[0] = {
+                let stack = Box::leak(Box::new([0u8; PRIVILEGE_STACK_SIZE]));
 
                 let stack_start = VirtAddr::from_ptr(stack.as_ptr());
-                stack_start + STACK_SIZE
+                stack_start + PRIVILEGE_STACK_SIZE
             };
         }
         tss_arr
     };
-    static ref GDT: (GlobalDescriptorTable, SegmentSelector, SegmentSelector) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (gdt, code_selector, tss_selector)
-    };
-    static ref AP_GDT: Vec<(GlobalDescriptorTable, SegmentSelector, SegmentSelector)> = {
-        AP_TSS
-            .iter()
-            .map(|tss| {
-                let mut gdt = GlobalDescriptorTable::new();
-                let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-                let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
-                (gdt, code_selector, tss_selector)
-            })
-            .collect()
+    static ref GDT: (GlobalDescriptorTable, Selectors) = create_gdt(&TSS);
+    static ref AP_GDT: Vec<(GlobalDescriptorTable, Selectors)> =
+        AP_TSS.iter().map(create_gdt).collect();
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+// The segment order is the same in all gdts (and the one sysret expects: kernel code, kernel data, user data, user code)
+fn create_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let selectors = Selectors {
+        kernel_code: gdt.add_entry(Descriptor::kernel_code_segment()),
+        kernel_data: gdt.add_entry(Descriptor::kernel_data_segment()),
+        user_data: gdt.add_entry(Descriptor::user_data_segment()),
+        user_code: gdt.add_entry(Descriptor::user_code_segment()),
+        tss: gdt.add_entry(Descriptor::tss_segment(tss)),
     };
+    (gdt, selectors)
+}
+
+// identical on every core
+pub fn selectors() -> Selectors {
+    GDT.1
+}
+
+fn tss(cpu_index: u64) -> &'static TaskStateSegment {
+    match cpu_index {
+        0 => &TSS,
+        _ => &AP_TSS[cpu_index as usize - 1],
+    }
+}
+
+// top of the initial tss rsp0 stack of a core (used by its boot task)
+pub fn privilege_stack_top(cpu_index: u64) -> VirtAddr {
+    tss(cpu_index).privilege_stack_table[0]
+}
+
+// only to be called by the core itself (the cpu reads rsp0 on every entry from ring 3)
+pub fn set_privilege_stack_top(cpu_index: u64, top: VirtAddr) {
+    let rsp0 = core::ptr::addr_of!(tss(cpu_index).privilege_stack_table[0]);
+    unsafe { rsp0.cast_mut().write_volatile(top) };
 }
 
 fn remap_and_disable_pic(offset1: u8, offset2: u8) {
@@ -160,14 +260,19 @@ fn remap_and_disable_pic(offset1: u8, offset2: u8) {
     }
 }
 
+// needs the heap and acpi
+pub fn prepare_ap_gdts() {
+    lazy_static::initialize(&AP_GDT);
+}
+
 pub fn init_gdt_and_exceptions_ap(ap_index: u64) {
     let gdt = &AP_GDT[ap_index as usize];
     gdt.0.load();
     unsafe {
         SS::set_reg(SegmentSelector(0));
         DS::set_reg(SegmentSelector(0));
-        CS::set_reg(gdt.1);
-        load_tss(gdt.2);
+        CS::set_reg(gdt.1.kernel_code);
+        load_tss(gdt.1.tss);
     }
     IDT.load();
 }
@@ -177,22 +282,118 @@ pub fn init_gdt_and_exceptions_bsp() {
     unsafe {
         SS::set_reg(SegmentSelector(0));
         DS::set_reg(SegmentSelector(0));
-        CS::set_reg(GDT.1);
-        load_tss(GDT.2);
+        CS::set_reg(GDT.1.kernel_code);
+        load_tss(GDT.1.tss);
     }
     IDT.load();
     remap_and_disable_pic(32, 32 + 8);
+    register_irq(TIMER_VECTOR, "apic timer", timer_interrupt).unwrap();
+    register_irq(REMOTE_CALL_VECTOR, "remote call", remote_call_interrupt).unwrap();
 }
 
+// Classifies faults which are not resolved by demand paging:
+// faults of a running application (user address or user code) abort only the application,
+// guard page hits are reported as stack overflows and everything else is a kernel bug
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
-    let cld = try_get_cld();
+    count_interrupt(14);
     let cr2 = x86_64::registers::control::Cr2::read();
+    if memory::handle_demand_paging_fault(cr2, error_code) {
+        return;
+    }
+    let area = memory::find_area(cr2);
+    let region = area.as_ref().map_or("none", |area| area.name);
+    let stack_overflow = area
+        .as_ref()
+        .is_some_and(|area| area.backing == memory::Backing::Guard);
+
+    let application_running =
+        try_get_cld().is_some_and(|cld| cld.running_application_data.is_some());
+    // application code, or the kernel accessing a user address for it (see user_access)
+    let user_fault = error_code.contains(PageFaultErrorCode::USER_MODE)
+        || (cr2.as_u64() < v::USER_END && user_access::interrupted_in_scope(stack_frame.cpu_flags));
+
+    if application_running && user_fault {
+        let fault = if stack_overflow {
+            log::error!("Application stack overflow (fault at {cr2:x?})");
+            loader::Fault::StackOverflow
+        } else {
+            log::error!(
+                "Application page fault at {cr2:x?} in region '{region}' ({error_code:?}) ip:{:x?}",
+                stack_frame.instruction_pointer
+            );
+            loader::Fault::PageFault
+        };
+        unsafe { loader::end_running_application(loader::ExitStatus::killed(fault)) };
+    }
+
+    backtrace::log_interrupted(&stack_frame);
+    let cld = try_get_cld();
+    if let Some(overflow) = kernel_stack::diagnose_overflow(cr2.as_u64()) {
+        panic!(
+            "EXCEPTION: kernel stack overflow on core {} (depth {:#x})\n{:#?}\nCore local data: {:x?}",
+            overflow.cpu_index, overflow.depth, stack_frame, cld
+        );
+    }
+    if stack_overflow {
+        panic!(
+            "EXCEPTION: STACK OVERFLOW hit guard page '{}' at {:x?}\n{:#?}\nCore local data: {:x?}",
+            region, cr2, stack_frame, cld
+        );
+    }
     panic!(
-        "EXCEPTION: PAGE FAULT\n{:#?}\n{:?}\n{:x?}\nCore local data: {:x?}",
-        stack_frame, error_code, cr2, cld
+        "EXCEPTION: PAGE FAULT at {:x?} in region '{}'\n{:#?}\n{:?}\nCore local data: {:x?}",
+        cr2, region, stack_frame, error_code, cld
+    );
+}
+
+// Faults of application code (ring 3) abort only the application, returns if the kernel faulted
+fn kill_faulting_application(
+    stack_frame: &InterruptStackFrame,
+    fault: loader::Fault,
+    error_code: Option<u64>,
+) {
+    let application_running =
+        try_get_cld().is_some_and(|cld| cld.running_application_data.is_some());
+    if application_running && stack_frame.code_segment & 3 == 3 {
+        log::error!(
+            "Application {fault:?} ip:{:x?} sp:{:x?} error code:{error_code:x?}",
+            stack_frame.instruction_pointer,
+            stack_frame.stack_pointer
+        );
+        unsafe { loader::end_running_application(loader::ExitStatus::killed(fault)) };
+    }
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    count_interrupt(13);
+    kill_faulting_application(
+        &stack_frame,
+        loader::Fault::GeneralProtectionFault,
+        Some(error_code),
+    );
+    backtrace::log_interrupted(&stack_frame);
+    panic!(
+        "EXCEPTION: general_protection_fault error code:{}\n{:#?}\nCore local data: {:x?}",
+        error_code,
+        stack_frame,
+        try_get_cld()
+    );
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(6);
+    kill_faulting_application(&stack_frame, loader::Fault::InvalidOpcode, None);
+    backtrace::log_interrupted(&stack_frame);
+    panic!(
+        "EXCEPTION: invalid_opcode\n{:#?}\nCore local data: {:x?}",
+        stack_frame,
+        try_get_cld()
     );
 }
 
@@ -200,7 +401,18 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    count_interrupt(8);
+    backtrace::log_interrupted(&stack_frame);
     let cld = try_get_cld();
+    let cr2 = x86_64::registers::control::Cr2::read().as_u64();
+    let overflow = kernel_stack::diagnose_overflow(stack_frame.stack_pointer.as_u64())
+        .or_else(|| kernel_stack::diagnose_overflow(cr2));
+    if let Some(overflow) = overflow {
+        panic!(
+            "EXCEPTION: DOUBLE FAULT: kernel stack overflow on core {} (depth {:#x})\n{:#?}\nCore local data: {:x?}",
+            overflow.cpu_index, overflow.depth, stack_frame, cld
+        );
+    }
     panic!(
         "EXCEPTION: DOUBLE FAULT\n{:#?}\nCore local data: {:x?}",
         stack_frame, cld
@@ -209,14 +421,323 @@ extern "x86-interrupt" fn double_fault_handler(
 
 pub static TIMER_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-extern "x86-interrupt" fn timer_interrupt(_stack_frame: InterruptStackFrame) {
-    if let Some(callback) = get_cld().apic_timer_interrupt_function {
-        callback();
+fn timer_interrupt() -> IrqReturn {
+    let cld = get_cld();
+    apic::rearm_timer(cld);
+    watchdog::heartbeat(cld.cpu_index);
+    crate::smp::account_tick(cld.cpu_index);
+    timer_callbacks::run_due(cld.cpu_index);
+    IrqReturn::Preempt
+}
+
+fn remote_call_interrupt() -> IrqReturn {
+    crate::smp::handle_remote_calls();
+    IrqReturn::Handled
+}
+
+// Nmis are sent by the watchdog (see watchdog.rs) and panicking cores (see halt.rs),
+// they arrive even if interrupts are disabled
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(2);
+    if halt::is_panicking() {
+        halt::halt_for_panic();
+    }
+    match watchdog::handle_nmi() {
+        Nmi::Alive => {}
+        Nmi::Hung => {
+            log::error!(
+                "Watchdog: no progress for {} ms ip:{:x?} sp:{:x?}\nCore local data: {:x?}",
+                watchdog::hang_duration_ms(),
+                stack_frame.instruction_pointer,
+                stack_frame.stack_pointer,
+                try_get_cld()
+            );
+            backtrace::log_interrupted(&stack_frame);
+            if let Some(cld) = try_get_cld() {
+                crate::lock_debug::log_held_by(cld.cpu_index);
+            }
+        }
+        Nmi::Unexpected => {
+            backtrace::log_interrupted(&stack_frame);
+            panic!(
+                "EXCEPTION: non_maskable_interrupt\n{:#?}\nCore local data: {:x?}",
+                stack_frame,
+                try_get_cld()
+            );
+        }
+    }
+}
+
+// the apic does not expect an end of interrupt for spurious interrupts
+extern "x86-interrupt" fn spurious_interrupt(_stack_frame: InterruptStackFrame) {
+    count_interrupt(SPURIOUS_VECTOR);
+}
+
+// Device interrupts are dispatched to handlers registered at runtime (see register_irq).
+// The handler services its device, the end of interrupt is signaled after it returned
+pub type IrqHandler = fn() -> IrqReturn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    // continue with another task if the interrupted one may be preempted (see sched::preempt)
+    Preempt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    NotAnIrqVector,
+    InUse,
+    NotRegistered,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrqStats {
+    pub vector: u8,
+    // None for interrupts which arrived without a registered handler
+    pub name: Option<&'static str>,
+    // indexed by the cpu index, one entry for every core that received any interrupt
+    pub per_core: Vec<u64>,
+}
+
+impl IrqStats {
+    pub fn total(&self) -> u64 {
+        self.per_core.iter().sum()
     }
+}
+
+struct IrqSlot {
+    // the handler as usize, 0 if there is none
+    handler: AtomicUsize,
+    // number of dispatches currently using handler (unregister_irq waits for them)
+    active: AtomicUsize,
+}
+
+impl IrqSlot {
+    const fn new() -> Self {
+        Self {
+            handler: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const IRQ_SLOT_INIT: IrqSlot = IrqSlot::new();
+static IRQ_SLOTS: [IrqSlot; 256] = [IRQ_SLOT_INIT; 256];
+// Names of the allocated or registered vectors, never locked by interrupt handlers.
+// Changes of the handlers are serialized by it
+static IRQ_NAMES: Mutex<[Option<&'static str>; 256]> = Mutex::new([None; 256]);
+
+const fn is_irq_vector(vector: u8) -> bool {
+    vector >= 32 && vector != SPURIOUS_VECTOR
+}
+
+extern "x86-interrupt" fn irq_entry<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
+    dispatch_irq(VECTOR, &stack_frame);
+}
+
+fn dispatch_irq(vector: u8, stack_frame: &InterruptStackFrame) {
+    count_interrupt(vector);
+    let handler_context = enter_handler();
+    crate::smp::end_idle(handler_context.0);
+    crate::random::add_interrupt_timing(vector);
+    let slot = &IRQ_SLOTS[vector as usize];
+    // announced before the handler is read, so unregister_irq either sees it or we see no handler
+    slot.active.fetch_add(1, Ordering::SeqCst);
+    let handler = slot.handler.load(Ordering::SeqCst);
+    let action = if handler == 0 {
+        IrqReturn::Handled
+    } else {
+        let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler()
+    };
+    slot.active.fetch_sub(1, Ordering::SeqCst);
     get_apic().signal_end_of_interrupt();
+    // the next task may run on this core for a long time
+    drop(handler_context);
+
+    if action == IrqReturn::Preempt {
+        let interrupted_user_mode = stack_frame.code_segment & 3 == 3;
+        if interrupted_user_mode {
+            loader::enforce_time_limit();
+        }
+        // continues with the next task of this core, returns when the interrupted task is scheduled again
+        crate::sched::preempt(interrupted_user_mode);
+    }
 }
 
-extern "x86-interrupt" fn breakpoint_handler(_stack_frame: InterruptStackFrame) {
-    let cld = try_get_cld();
-    serial_println!("break_point cld:{:?}", cld); // TODO: lock free
+// Reserves a free vector for register_irq (it stays reserved until unregister_irq)
+pub fn allocate_vector(name: &'static str) -> Option<u8> {
+    instructions::interrupts::without_interrupts(|| {
+        let mut names = IRQ_NAMES.lock();
+        let vector = (FIRST_DYNAMIC_VECTOR..=LAST_DYNAMIC_VECTOR)
+            .find(|&vector| names[vector as usize].is_none())?;
+        names[vector as usize] = Some(name);
+        Some(vector)
+    })
+}
+
+// Takes effect on all cores immediately. The vector has to be free or allocated by allocate_vector
+pub fn register_irq(vector: u8, name: &'static str, handler: IrqHandler) -> Result<(), IrqError> {
+    if !is_irq_vector(vector) {
+        return Err(IrqError::NotAnIrqVector);
+    }
+    instructions::interrupts::without_interrupts(|| {
+        let mut names = IRQ_NAMES.lock();
+        let slot = &IRQ_SLOTS[vector as usize];
+        if slot.handler.load(Ordering::SeqCst) != 0 {
+            return Err(IrqError::InUse);
+        }
+        names[vector as usize] = Some(name);
+        reset_count(vector);
+        slot.handler.store(handler as usize, Ordering::SeqCst);
+        Ok(())
+    })
+}
+
+// Removes the handler (or the allocation) and frees the vector.
+// Returns once the handler is no longer running on any core, so it must not be called from the handler itself
+pub fn unregister_irq(vector: u8) -> Result<(), IrqError> {
+    if !is_irq_vector(vector) {
+        return Err(IrqError::NotAnIrqVector);
+    }
+    instructions::interrupts::without_interrupts(|| {
+        let mut names = IRQ_NAMES.lock();
+        if names[vector as usize].take().is_none() {
+            return Err(IrqError::NotRegistered);
+        }
+        IRQ_SLOTS[vector as usize]
+            .handler
+            .store(0, Ordering::SeqCst);
+        Ok(())
+    })?;
+    let slot = &IRQ_SLOTS[vector as usize];
+    while slot.active.load(Ordering::SeqCst) != 0 {
+        core::hint::spin_loop();
+    }
+    reset_count(vector);
+    Ok(())
+}
+
+// Every interrupt (exceptions, the spurious vector and irqs) is counted per core and vector
+#[allow(clippy::declare_interior_mutable_const)]
+const COUNTER_INIT: AtomicU64 = AtomicU64::new(0);
+per_core! {
+    static COUNTERS: [AtomicU64; 256] = [COUNTER_INIT; 256];
+}
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "divide error",
+    "debug",
+    "non maskable interrupt",
+    "breakpoint",
+    "overflow",
+    "bound range exceeded",
+    "invalid opcode",
+    "device not available",
+    "double fault",
+    "coprocessor segment overrun",
+    "invalid tss",
+    "segment not present",
+    "stack segment fault",
+    "general protection fault",
+    "page fault",
+    "reserved",
+    "x87 floating point",
+    "alignment check",
+    "machine check",
+    "simd floating point",
+    "virtualization",
+    "cp protection",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "hv injection",
+    "vmm communication",
+    "security",
+    "reserved",
+];
+
+// Interrupt handlers running on each core, they must not take locks the interrupted code may hold
+// (e.g. logging defers their records, see logging.rs)
+per_core! {
+    static HANDLER_DEPTH: AtomicUsize = AtomicUsize::new(0);
+}
+
+// the core runs a handler until the guard is dropped (it has to stay on the core, interrupts are disabled)
+pub struct HandlerContext(usize);
+
+pub fn enter_handler() -> HandlerContext {
+    // only the bsp runs before the core local data exists
+    let core = try_get_cld().map_or(0, |cld| cld.cpu_index as usize);
+    HANDLER_DEPTH[core].fetch_add(1, Ordering::Relaxed);
+    HandlerContext(core)
+}
+
+impl Drop for HandlerContext {
+    fn drop(&mut self) {
+        HANDLER_DEPTH[self.0].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn in_handler() -> bool {
+    let core = try_get_cld().map_or(0, |cld| cld.cpu_index as usize);
+    HANDLER_DEPTH[core].load(Ordering::Relaxed) > 0
+}
+
+pub fn count_interrupt(vector: u8) {
+    // only the bsp runs before the core local data exists
+    let core = try_get_cld().map_or(0, |cld| cld.cpu_index as usize);
+    COUNTERS[core][vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+fn reset_count(vector: u8) {
+    for counters in COUNTERS.iter() {
+        counters[vector as usize].store(0, Ordering::Relaxed);
+    }
+}
+
+// every vector that was received by any core or has a registered handler
+pub fn stats() -> Vec<IrqStats> {
+    let names = instructions::interrupts::without_interrupts(|| *IRQ_NAMES.lock());
+    let cores = (0..COUNTERS.len())
+        .rfind(|&core| {
+            COUNTERS[core]
+                .iter()
+                .any(|counter| counter.load(Ordering::Relaxed) != 0)
+        })
+        .map_or(0, |last| last + 1);
+    (0..=u8::MAX)
+        .map(|vector| IrqStats {
+            vector,
+            name: match vector {
+                0..=31 => Some(EXCEPTION_NAMES[vector as usize]),
+                SPURIOUS_VECTOR => Some("spurious"),
+                _ => names[vector as usize],
+            },
+            per_core: COUNTERS
+                .iter()
+                .take(cores)
+                .map(|counters| counters[vector as usize].load(Ordering::Relaxed))
+                .collect(),
+        })
+        .filter(|stats| stats.total() > 0 || (is_irq_vector(stats.vector) && stats.name.is_some()))
+        .collect()
+}
+
+pub fn log_stats(level: log::Level) {
+    for stats in stats() {
+        log::log!(
+            level,
+            "Vector {:#04x} '{}': {} total, per core {:?}",
+            stats.vector,
+            stats.name.unwrap_or("unregistered"),
+            stats.total(),
+            stats.per_core
+        );
+    }
 }
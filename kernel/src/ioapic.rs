@@ -0,0 +1,131 @@
+use alloc::vec::Vec;
+use io_apic::{IoApic, RedirectionEntry};
+use port_io::VolatileMmio;
+use spin::{Mutex, Once};
+use x86_64::{instructions::interrupts, PhysAddr};
+
+use crate::acpi::{IsaIrqOverride, ACPI};
+
+// Routes device interrupts (global system interrupts, gsis) to a vector on a core.
+// Every input starts masked, it is unmasked when it gets routed
+struct RoutedIoApic {
+    gsi_base: u32,
+    entry_count: u32,
+    // locked with interrupts disabled (the registers are accessed through an index register)
+    inner: Mutex<IoApic<VolatileMmio>>,
+}
+
+static IO_APICS: Once<Vec<RoutedIoApic>> = Once::new();
+static ISA_IRQ_OVERRIDES: Once<Vec<IsaIrqOverride>> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoApicError {
+    NotInitialized,
+    NoIoApicForGsi(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GsiConfig {
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+impl GsiConfig {
+    // the default of isa irqs without an override
+    pub const ISA: Self = Self {
+        active_low: false,
+        level_triggered: false,
+    };
+}
+
+// needs to be called once by the bsp (after apic::create)
+pub fn init() {
+    interrupts::without_interrupts(|| {
+        let acpi = ACPI.lock();
+        ISA_IRQ_OVERRIDES.call_once(|| acpi.isa_irq_overrides.clone());
+        IO_APICS.call_once(|| {
+            acpi.io_apics
+                .iter()
+                .map(|info| {
+                    let virt = crate::memory::map_named_mmio(
+                        "io apic",
+                        PhysAddr::new(info.phys_addr),
+                        4096,
+                    );
+                    let mut io_apic = IoApic::new(unsafe { VolatileMmio::new(virt.as_mut_ptr()) });
+                    io_apic.mask_all();
+                    let entry_count = io_apic.entry_count();
+                    log::info!(
+                        "IO APIC {} at {:#x}: gsis {}..{}",
+                        info.id,
+                        info.phys_addr,
+                        info.gsi_base,
+                        info.gsi_base + entry_count
+                    );
+                    RoutedIoApic {
+                        gsi_base: info.gsi_base,
+                        entry_count,
+                        inner: Mutex::new(io_apic),
+                    }
+                })
+                .collect()
+        });
+    });
+}
+
+// Translates a legacy isa irq (keyboard 1, com1 4, ...) with the interrupt source overrides of the madt
+pub fn isa_irq_to_gsi(irq: u8) -> (u32, GsiConfig) {
+    ISA_IRQ_OVERRIDES
+        .get()
+        .and_then(|overrides| overrides.iter().find(|o| o.isa_irq == irq))
+        .map_or_else(
+            || (u32::from(irq), GsiConfig::ISA),
+            |o| {
+                (
+                    o.gsi,
+                    GsiConfig {
+                        active_low: o.active_low,
+                        level_triggered: o.level_triggered,
+                    },
+                )
+            },
+        )
+}
+
+fn with_entry<R>(
+    gsi: u32,
+    f: impl FnOnce(&mut IoApic<VolatileMmio>, u32) -> R,
+) -> Result<R, IoApicError> {
+    let io_apic = IO_APICS
+        .get()
+        .ok_or(IoApicError::NotInitialized)?
+        .iter()
+        .find(|io_apic| (io_apic.gsi_base..io_apic.gsi_base + io_apic.entry_count).contains(&gsi))
+        .ok_or(IoApicError::NoIoApicForGsi(gsi))?;
+    Ok(interrupts::without_interrupts(|| {
+        f(&mut io_apic.inner.lock(), gsi - io_apic.gsi_base)
+    }))
+}
+
+// Delivers the gsi as vector to the core with the apic id and unmasks it
+pub fn route_gsi(gsi: u32, config: GsiConfig, vector: u8, apic_id: u8) -> Result<(), IoApicError> {
+    let mut entry = RedirectionEntry::fixed(vector, apic_id);
+    entry.set_active_low(config.active_low);
+    entry.set_level_triggered(config.level_triggered);
+    with_entry(gsi, |io_apic, index| io_apic.write_entry(index, entry))
+}
+
+// returns the gsi the irq is connected to
+pub fn route_isa_irq(irq: u8, vector: u8, apic_id: u8) -> Result<u32, IoApicError> {
+    let (gsi, config) = isa_irq_to_gsi(irq);
+    route_gsi(gsi, config, vector, apic_id)?;
+    Ok(gsi)
+}
+
+pub fn mask_gsi(gsi: u32) -> Result<(), IoApicError> {
+    with_entry(gsi, |io_apic, index| io_apic.set_masked(index, true))
+}
+
+pub fn redirection_entry(gsi: u32) -> Result<RedirectionEntry, IoApicError> {
+    with_entry(gsi, IoApic::read_entry)
+}
@@ -0,0 +1,79 @@
+use spin::Once;
+
+use crate::{constants::v, random};
+
+// KASLR-lite: the kernel heap and the ap stacks start at a random 2MiB aligned offset inside their windows.
+// The dynamic mappings (frame buffer, ram disk, physical memory) are randomized by the bootloader (see BOOTLOADER_CONFIG).
+// Disabled with the kernel command line option kaslr=off
+const ALIGNMENT: u64 = 2 * 1024 * 1024;
+// only the lower half of each window is used for the start, so there is enough room to grow
+const HEAP_WINDOW: u64 = (v::KERNEL_AP_STACKS - v::KERNEL_HEAP_START) / 2;
+const AP_STACKS_WINDOW: u64 = (v::KERNEL_MMIO_START - v::KERNEL_AP_STACKS) / 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct KernelLayout {
+    pub randomized: bool,
+    pub seed_source: &'static str,
+    pub heap_offset: u64,
+    pub ap_stacks_offset: u64,
+}
+
+impl KernelLayout {
+    fn new(randomize: bool) -> Self {
+        if !randomize {
+            return Self {
+                randomized: false,
+                seed_source: "none",
+                heap_offset: 0,
+                ap_stacks_offset: 0,
+            };
+        }
+        Self {
+            randomized: true,
+            seed_source: random::hardware_source().name(),
+            heap_offset: aligned_offset(HEAP_WINDOW),
+            ap_stacks_offset: aligned_offset(AP_STACKS_WINDOW),
+        }
+    }
+
+    pub const fn heap_start(&self) -> u64 {
+        v::KERNEL_HEAP_START + self.heap_offset
+    }
+
+    pub const fn ap_stacks_start(&self) -> u64 {
+        v::KERNEL_AP_STACKS + self.ap_stacks_offset
+    }
+
+    pub fn log(&self, level: log::Level) {
+        log::log!(
+            level,
+            "Kernel layout (randomized: {}, seed: {}): heap {:#x} (+{:#x}), ap stacks {:#x} (+{:#x})",
+            self.randomized,
+            self.seed_source,
+            self.heap_start(),
+            self.heap_offset,
+            self.ap_stacks_start(),
+            self.ap_stacks_offset,
+        );
+    }
+}
+
+fn aligned_offset(window: u64) -> u64 {
+    random::below(window / ALIGNMENT) * ALIGNMENT
+}
+
+// Random 2MiB aligned offset below window, used for the load base of position independent applications.
+// Always 0 with kaslr=off
+pub fn random_offset(window: u64) -> u64 {
+    if !layout().randomized {
+        return 0;
+    }
+    aligned_offset(window)
+}
+
+static LAYOUT: Once<KernelLayout> = Once::new();
+
+// chosen on first use (before the heap is initialized), only requires the boot info for the command line
+pub fn layout() -> &'static KernelLayout {
+    LAYOUT.call_once(|| KernelLayout::new(crate::cmdline::get().kaslr))
+}
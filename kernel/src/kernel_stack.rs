@@ -0,0 +1,53 @@
+use core::{arch::asm, ops::Range};
+
+use spin::Once;
+
+use crate::{constants::KERNEL_STACK_SIZE, per_core};
+
+// Stack ranges of all cores (indexed by cpu index), used to diagnose stack overflows in exception handlers
+#[derive(Debug, Clone)]
+pub struct KernelStack {
+    pub guard: Range<u64>,
+    pub stack: Range<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StackOverflow {
+    pub cpu_index: u64,
+    // bytes between the top of the stack and the faulting address
+    pub depth: u64,
+}
+
+per_core! {
+    static STACKS: Once<KernelStack> = Once::new();
+}
+
+pub fn register(cpu_index: u64, stack: Range<u64>) {
+    STACKS[cpu_index as usize].call_once(|| KernelStack {
+        guard: stack.start - 4096..stack.start,
+        stack,
+    });
+}
+
+// The bootloader does not report the stack location, so it is derived from the current stack pointer
+// (the bootloader leaves the page below the stack unmapped). Has to be called at the start of kernel_main
+#[inline(always)]
+pub fn register_bsp() {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    let top = x86_64::align_up(rsp, 4096);
+    register(0, top - KERNEL_STACK_SIZE..top);
+}
+
+// returns the core whose guard page contains addr
+pub fn diagnose_overflow(addr: u64) -> Option<StackOverflow> {
+    STACKS
+        .iter()
+        .enumerate()
+        .filter_map(|(cpu_index, stack)| Some((cpu_index, stack.get()?)))
+        .find(|(_, stack)| stack.guard.contains(&addr))
+        .map(|(cpu_index, stack)| StackOverflow {
+            cpu_index: cpu_index as u64,
+            depth: stack.stack.end - addr,
+        })
+}
@@ -0,0 +1,43 @@
+use ps2_8042::{keyboard::Decoder, Ps2Error, Typematic};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{
+    apic,
+    input::{Events, Subscribers},
+    interrupts, ioapic,
+    ps2::{self, CONTROLLER},
+};
+
+pub use ps2_8042::keyboard::{KeyCode, KeyEvent, KeyState, Modifiers};
+
+const KEYBOARD_ISA_IRQ: u8 = 1;
+
+// locked with interrupts disabled
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+static SUBSCRIBERS: Subscribers<KeyEvent> = Subscribers::new();
+
+// needs to be called once by the bsp (after ioapic::init), fails if there is no ps/2 keyboard
+pub fn init() -> Result<(), Ps2Error> {
+    without_interrupts(|| CONTROLLER.lock().init_keyboard(Typematic::DEFAULT))?;
+    let vector = interrupts::allocate_vector("ps/2 keyboard").unwrap();
+    interrupts::register_irq(vector, "ps/2 keyboard", ps2::interrupt).unwrap();
+    let gsi =
+        ioapic::route_isa_irq(KEYBOARD_ISA_IRQ, vector, apic::interrupt_destination()).unwrap();
+    log::info!("PS/2 keyboard initialized (gsi {gsi}, vector {vector:#x})");
+    Ok(())
+}
+
+// decodes one byte of the keyboard (called by the interrupt, the tests inject scancodes with it)
+pub fn handle_scancode(scancode: u8) {
+    let event = without_interrupts(|| DECODER.lock().feed(scancode));
+    if let Some(event) = event {
+        SUBSCRIBERS.publish(event);
+    }
+}
+
+pub type KeyEvents = Events<KeyEvent>;
+
+pub fn subscribe() -> KeyEvents {
+    SUBSCRIBERS.subscribe()
+}
@@ -0,0 +1,90 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use spin::Mutex;
+
+use crate::sched::{self, CoreMask, TaskId};
+
+// Kernel threads are scheduler tasks with a result. They are not preempted (see sched),
+// so long running threads have to call sched::yield_now
+
+struct Packet<T> {
+    result: Mutex<Option<T>>,
+    finished: Arc<AtomicBool>,
+}
+
+// finished flags of the running threads, so exit can mark the joining side
+static RUNNING: Mutex<BTreeMap<TaskId, Arc<AtomicBool>>> = Mutex::new(BTreeMap::new());
+
+pub struct JoinHandle<T> {
+    task: TaskId,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub const fn task(&self) -> TaskId {
+        self.task
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.finished.load(Ordering::Acquire)
+    }
+
+    // Waits until the thread finished (lets other tasks of this core run in the meantime).
+    // Returns None if the thread ended with exit
+    pub fn join(self) -> Option<T> {
+        while !self.is_finished() {
+            sched::yield_now();
+            core::hint::spin_loop();
+        }
+        self.packet.result.lock().take()
+    }
+}
+
+// Runs f in a new kernel thread (with its own stack) on the least loaded core.
+// Dropping the handle detaches the thread
+pub fn spawn<T: Send + 'static>(
+    name: &'static str,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> JoinHandle<T> {
+    spawn_on(name, CoreMask::all(), f)
+}
+
+// Like spawn, the thread only runs on the given cores (see sched::set_affinity to change it later)
+pub fn spawn_on<T: Send + 'static>(
+    name: &'static str,
+    affinity: CoreMask,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> JoinHandle<T> {
+    let finished = Arc::new(AtomicBool::new(false));
+    let packet = Arc::new(Packet {
+        result: Mutex::new(None),
+        finished: finished.clone(),
+    });
+    let thread_packet = packet.clone();
+
+    // locked until the task id is registered, so the thread can not exit before
+    let mut running = RUNNING.lock();
+    let task = sched::spawn_on(name, affinity, move || {
+        let result = f();
+        *thread_packet.result.lock() = Some(result);
+        finish();
+    });
+    running.insert(task, finished);
+    drop(running);
+
+    JoinHandle { task, packet }
+}
+
+fn finish() {
+    let task = sched::current_task_id().expect("kthread without task");
+    if let Some(finished) = RUNNING.lock().remove(&task) {
+        finished.store(true, Ordering::Release);
+    }
+}
+
+// Ends the current thread, join returns None. Values on the stack of the thread are not dropped
+pub fn exit() -> ! {
+    finish();
+    sched::exit();
+}
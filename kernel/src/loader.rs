@@ -2,23 +2,155 @@ use core::arch::asm;
 use core::fmt::Debug;
 use core::ptr;
 
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use elf::endian::LittleEndian;
+use elf::file::Class;
+use elf::parse::ParseError;
+use elf::relocation::RelaIterator;
+use elf::segment::ProgramHeader;
 
 use elf::ElfBytes;
+use spin::{Mutex, Once};
+use x86_64::registers::model_specific::FsBase;
+use x86_64::structures::paging::page::PageRangeInclusive;
 use x86_64::structures::paging::OffsetPageTable;
 use x86_64::structures::paging::Page;
 use x86_64::structures::paging::PageTableFlags;
-use x86_64::structures::paging::Size4KiB;
 use x86_64::VirtAddr;
 
 use crate::allocator::UserAllocatorWrapper;
 use crate::constants::v;
 use crate::constants::USER_STACK_SIZE;
-use crate::memory::MEMORY;
-use crate::println;
+use crate::fpu::FpuState;
+use crate::handle::{Handle, HandleTable};
+use crate::memory::{self, Backing, VirtualMemoryArea, MEMORY};
+use crate::sched::{CoreMask, TaskId};
 use crate::smp::get_cld;
+use crate::tlb::PendingShootdown;
+use crate::user_access::user_access;
+
+// exit codes of applications killed by the kernel
+pub const EXIT_CODE_PAGE_FAULT: u64 = 0xDEAD_0000_0000_000E;
+pub const EXIT_CODE_GENERAL_PROTECTION_FAULT: u64 = 0xDEAD_0000_0000_000D;
+pub const EXIT_CODE_INVALID_OPCODE: u64 = 0xDEAD_0000_0000_0006;
+pub const EXIT_CODE_STACK_OVERFLOW: u64 = 0xDEAD_0000_0000_5F0E;
+pub const EXIT_CODE_BAD_USER_POINTER: u64 = 0xDEAD_0000_0000_0BAD;
+pub const EXIT_CODE_INVALID_SYSCALL: u64 = 0xDEAD_0000_0000_05C0;
+pub const EXIT_CODE_TIMED_OUT: u64 = 0xDEAD_0000_0000_71E0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    PageFault,
+    GeneralProtectionFault,
+    InvalidOpcode,
+    StackOverflow,
+    BadUserPointer,
+    InvalidSyscall,
+}
+
+impl Fault {
+    pub const fn exit_code(self) -> u64 {
+        match self {
+            Self::PageFault => EXIT_CODE_PAGE_FAULT,
+            Self::GeneralProtectionFault => EXIT_CODE_GENERAL_PROTECTION_FAULT,
+            Self::InvalidOpcode => EXIT_CODE_INVALID_OPCODE,
+            Self::StackOverflow => EXIT_CODE_STACK_OVERFLOW,
+            Self::BadUserPointer => EXIT_CODE_BAD_USER_POINTER,
+            Self::InvalidSyscall => EXIT_CODE_INVALID_SYSCALL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCause {
+    // the entry point returned
+    Returned,
+    // the abort syscall
+    Aborted,
+    // the panic handler of the application
+    Panicked,
+    Killed(Fault),
+    // the time limit passed (see run_with_time_limit)
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus {
+    pub code: u64,
+    pub cause: ExitCause,
+}
+
+impl ExitStatus {
+    pub const fn killed(fault: Fault) -> Self {
+        Self {
+            code: fault.exit_code(),
+            cause: ExitCause::Killed(fault),
+        }
+    }
+
+    pub const fn timed_out() -> Self {
+        Self {
+            code: EXIT_CODE_TIMED_OUT,
+            cause: ExitCause::TimedOut,
+        }
+    }
+
+    pub const fn success(&self) -> bool {
+        matches!(self.cause, ExitCause::Returned) && self.code == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoaderError {
+    // the ram disk contains no file with this name
+    NotFound,
+    // not a 64 bit little endian elf file
+    BadMagic,
+    // a header, table or segment reaches past the end of the file
+    TruncatedData,
+    // a segment (at its load address) is not inside the code window of the user address space
+    SegmentOutsideUserRange { start: u64, end: u64 },
+    OverlappingSegments,
+    // the entry point is not inside an executable segment
+    BadEntryPoint,
+    // a relocation or the relocation table lies outside of the loaded segments
+    BadRelocation,
+    // a valid elf file using features the loader does not support
+    Unsupported(&'static str),
+}
 
-fn map_segment(virt_addr: u64, size: u64, flags: PageTableFlags, data: &[u8]) {
+impl From<ParseError> for LoaderError {
+    fn from(error: ParseError) -> Self {
+        match error {
+            ParseError::BadMagic(_)
+            | ParseError::UnsupportedElfClass(_)
+            | ParseError::UnsupportedElfEndianness(_)
+            | ParseError::UnsupportedVersion(_) => Self::BadMagic,
+            _ => Self::TruncatedData,
+        }
+    }
+}
+
+// SMEP is only enabled once this is true, since it prevents the kernel from executing user pages
+pub const APPLICATIONS_RUN_IN_RING_3: bool = true;
+
+// Position independent applications (ET_DYN) are moved by a random 2MiB aligned offset inside this window,
+// the first 2MiB stay unmapped to catch null pointers. The whole image has to fit below the tls area
+const PIE_BASE_MIN: u64 = 2 * 1024 * 1024;
+const PIE_BASE_WINDOW: u64 = v::USER_STACK_START / 2;
+
+// maps the segment writable, its final flags are set by protect_segment (after the relocations are applied)
+fn map_segment(
+    name: &'static str,
+    virt_addr: u64,
+    size: u64,
+    flags: PageTableFlags,
+    data: &[u8],
+) -> PageRangeInclusive {
     let page_range = {
         let region_start = VirtAddr::new(virt_addr);
         let region_end = region_start + size - 1u64;
@@ -38,31 +170,322 @@ fn map_segment(virt_addr: u64, size: u64, flags: PageTableFlags, data: &[u8]) {
     let mapped_segment =
         unsafe { &mut *ptr::slice_from_raw_parts_mut(virt_addr as *mut u8, size as usize) };
 
-    mapped_segment[..].fill(0);
-    mapped_segment[..data.len()].copy_from_slice(data);
+    user_access(|| {
+        mapped_segment[..].fill(0);
+        mapped_segment[..data.len()].copy_from_slice(data);
+    });
+
+    let flags = flags | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT;
+    let area_start = page_range.start.start_address().as_u64();
+    let area_end = page_range.end.start_address().as_u64() + 4096;
+    memory::register_area(VirtualMemoryArea::new(
+        name,
+        area_start..area_end,
+        flags,
+        Backing::Ram,
+    ))
+    .expect("overlapping application segments");
+    page_range
+}
+
+fn protect_segment(page_range: PageRangeInclusive, flags: PageTableFlags) {
+    let flags = flags | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT;
+    let mut pending = PendingShootdown::none();
+    let mut mem = MEMORY.lock();
+    for page in page_range {
+        pending.merge(unsafe { mem.change_flags(page, flags) });
+    }
+    drop(mem);
+    // empty, user pages are only in this core's tlb
+    pending.flush();
+}
+
+// A validated application file, loading it can not fail anymore (except for running out of memory)
+struct Executable<'data> {
+    file: ElfBytes<'data, LittleEndian>,
+    // the load segments sorted by address
+    segments: Vec<ProgramHeader>,
+    tls: Option<ProgramHeader>,
+    // the rela table (see relocate)
+    relocations: &'data [u8],
+    // 0 for applications linked at a fixed address
+    base: u64,
+    // link address of the entry point
+    entry: u64,
+}
+
+// Checks everything load relies on, nothing is mapped yet
+fn parse(raw: &[u8]) -> Result<Executable<'_>, LoaderError> {
+    let file = ElfBytes::<LittleEndian>::minimal_parse(raw)?;
+    if file.ehdr.class != Class::ELF64 {
+        return Err(LoaderError::BadMagic);
+    }
+    if file.ehdr.e_machine != elf::abi::EM_X86_64 {
+        return Err(LoaderError::Unsupported("not an x86_64 executable"));
+    }
+    let headers: Vec<ProgramHeader> = file
+        .segments()
+        .ok_or(LoaderError::Unsupported("no program headers"))?
+        .iter()
+        .collect();
+
+    let mut segments: Vec<ProgramHeader> = headers
+        .iter()
+        .filter(|segment| segment.p_type == elf::abi::PT_LOAD && segment.p_memsz > 0)
+        .copied()
+        .collect();
+    segments.sort_unstable_by_key(|segment| segment.p_vaddr);
+    for segment in &segments {
+        file.segment_data(segment)?;
+        if segment.p_filesz > segment.p_memsz {
+            return Err(LoaderError::TruncatedData);
+        }
+        if segment.p_flags & (elf::abi::PF_R | elf::abi::PF_X) == 0 {
+            return Err(LoaderError::Unsupported(
+                "segment is neither readable nor executable",
+            ));
+        }
+    }
+    let image_start = segments
+        .first()
+        .ok_or(LoaderError::Unsupported("no load segments"))?
+        .p_vaddr;
+    let image_end = segments
+        .iter()
+        .map(|segment| segment.p_vaddr.checked_add(segment.p_memsz))
+        .try_fold(0, |end, segment_end| {
+            segment_end.map(|segment_end| end.max(segment_end))
+        })
+        .ok_or(LoaderError::SegmentOutsideUserRange {
+            start: image_start,
+            end: u64::MAX,
+        })?;
+
+    let base = match file.ehdr.e_type {
+        elf::abi::ET_EXEC => 0,
+        elf::abi::ET_DYN if image_end - image_start <= PIE_BASE_WINDOW => {
+            (PIE_BASE_MIN + crate::kaslr::random_offset(PIE_BASE_WINDOW)).wrapping_sub(image_start)
+        }
+        elf::abi::ET_DYN => {
+            return Err(LoaderError::SegmentOutsideUserRange {
+                start: image_start,
+                end: image_end,
+            })
+        }
+        _ => return Err(LoaderError::Unsupported("not an executable")),
+    };
+
+    check_layout(&segments, base)?;
+
+    let entry = file.ehdr.e_entry;
+    if !segments.iter().any(|segment| {
+        segment.p_flags & elf::abi::PF_X != 0
+            && (segment.p_vaddr..segment.p_vaddr + segment.p_memsz).contains(&entry)
+    }) {
+        return Err(LoaderError::BadEntryPoint);
+    }
+
+    let tls = headers
+        .iter()
+        .find(|segment| segment.p_type == elf::abi::PT_TLS)
+        .copied();
+    if let Some(tls) = tls {
+        check_tls(tls, &segments)?;
+    }
+
+    let relocations = find_relocations(raw, &file, &segments)?;
+    for relocation in RelaIterator::new(LittleEndian, file.ehdr.class, relocations) {
+        match relocation.r_type {
+            elf::abi::R_X86_64_NONE => {}
+            elf::abi::R_X86_64_RELATIVE => {
+                if !segments
+                    .iter()
+                    .any(|segment| contains(segment, relocation.r_offset, 8))
+                {
+                    return Err(LoaderError::BadRelocation);
+                }
+            }
+            _ => return Err(LoaderError::Unsupported("relocation type")),
+        }
+    }
+
+    Ok(Executable {
+        file,
+        segments,
+        tls,
+        relocations,
+        base,
+        entry,
+    })
+}
+
+// the segments (sorted by address) have to be inside the code window of the user address space at their load address
+fn check_layout(segments: &[ProgramHeader], base: u64) -> Result<(), LoaderError> {
+    let window = v::USER_START..=v::USER_TLS_START;
+    let mut previous_end = v::USER_START;
+    for segment in segments {
+        let start = base.wrapping_add(segment.p_vaddr);
+        let end = start.wrapping_add(segment.p_memsz);
+        if end < start || !window.contains(&start) || !window.contains(&end) {
+            return Err(LoaderError::SegmentOutsideUserRange { start, end });
+        }
+        // segments can not share pages
+        if start < previous_end.next_multiple_of(4096) {
+            return Err(LoaderError::OverlappingSegments);
+        }
+        previous_end = end;
+    }
+    Ok(())
+}
 
+fn check_tls(tls: ProgramHeader, segments: &[ProgramHeader]) -> Result<(), LoaderError> {
+    if tls.p_align > 4096 {
+        return Err(LoaderError::Unsupported("tls alignment larger than a page"));
+    }
+    if tls_block_size(Some(tls)) + TCB_SIZE > v::USER_STACK_START - v::USER_TLS_START {
+        return Err(LoaderError::Unsupported("tls segment too large"));
+    }
+    // the template is copied from the loaded segments
+    if !segments
+        .iter()
+        .any(|segment| contains(segment, tls.p_vaddr, tls.p_filesz))
     {
-        let mut mem = MEMORY.lock();
-        for page in page_range {
-            unsafe {
-                mem.change_flags(
-                    page,
-                    flags | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
-                );
-            };
+        return Err(LoaderError::TruncatedData);
+    }
+    Ok(())
+}
+
+// whether the segment contains the len bytes at the (link) address addr in its file data
+fn contains(segment: &ProgramHeader, addr: u64, len: u64) -> bool {
+    addr >= segment.p_vaddr
+        && addr
+            .checked_add(len)
+            .is_some_and(|end| end <= segment.p_vaddr + segment.p_filesz)
+}
+
+// The rela table of the dynamic table, empty for applications without relocations.
+// Only R_X86_64_RELATIVE is supported, which is all a static position independent executable needs
+fn find_relocations<'data>(
+    raw: &'data [u8],
+    file: &ElfBytes<'data, LittleEndian>,
+    segments: &[ProgramHeader],
+) -> Result<&'data [u8], LoaderError> {
+    let Some(dynamic) = file.dynamic()? else {
+        return Ok(&[]);
+    };
+    let (mut rela, mut rela_size) = (None, 0);
+    for entry in dynamic {
+        match entry.d_tag {
+            elf::abi::DT_RELA => rela = Some(entry.d_ptr()),
+            elf::abi::DT_RELASZ => rela_size = entry.d_val(),
+            elf::abi::DT_RELAENT if entry.d_val() != RELA_SIZE => {
+                return Err(LoaderError::Unsupported("relocation entry size"));
+            }
+            elf::abi::DT_REL => return Err(LoaderError::Unsupported("rel relocations")),
+            _ => {}
         }
     }
+    let Some(rela) = rela else {
+        return Ok(&[]);
+    };
+    if rela_size % RELA_SIZE != 0 {
+        return Err(LoaderError::BadRelocation);
+    }
+    let segment = segments
+        .iter()
+        .find(|segment| contains(segment, rela, rela_size))
+        .ok_or(LoaderError::BadRelocation)?;
+    let start = segment.p_offset + rela - segment.p_vaddr;
+    Ok(&raw[start as usize..(start + rela_size) as usize])
 }
 
-fn allocate_stack() {
-    log::trace!("allocate application stack");
-    let mut mem = MEMORY.lock();
-    let base_virt_addr = v::USER_STACK_START + 4096; //add guard page
-    let page_count = USER_STACK_SIZE / 4096 - 1;
-    for i in 0..page_count {
-        let page =
-            Page::<Size4KiB>::from_start_address(VirtAddr::new(base_virt_addr + i * 4096)).unwrap();
-        mem.map_ram_user(page, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+const RELA_SIZE: u64 = 24;
+
+// Applies the (validated) relocations to the image loaded at the base, all loaded pages have to be writable
+fn relocate(executable: &Executable) {
+    let base = executable.base;
+    let relocations = RelaIterator::new(
+        LittleEndian,
+        executable.file.ehdr.class,
+        executable.relocations,
+    );
+    let mut count = 0;
+    for relocation in relocations {
+        if relocation.r_type != elf::abi::R_X86_64_RELATIVE {
+            continue;
+        }
+        let target = base.wrapping_add(relocation.r_offset);
+        let value = base.wrapping_add_signed(relocation.r_addend);
+        user_access(|| unsafe { ptr::write_unaligned(target as *mut u64, value) });
+        count += 1;
+    }
+    log::trace!("Applied {count} relocations");
+}
+
+// Thread local storage (x86_64 variant II): the tls block ends at the thread pointer (fs base),
+// which points to the thread control block. The first word of the tcb points to itself
+const TCB_SIZE: u64 = 64;
+
+fn tls_block_size(tls: Option<ProgramHeader>) -> u64 {
+    tls.map_or(0, |tls| tls.p_memsz.next_multiple_of(tls.p_align.max(1)))
+}
+
+// Sets up the tls block and the tcb of the application from the (relocated) tls segment, returns the thread pointer.
+// Applications without a tls segment still get a tcb
+fn map_tls(executable: &Executable) -> u64 {
+    let block_size = tls_block_size(executable.tls);
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let page_range = map_segment(
+        "user tls",
+        v::USER_TLS_START,
+        block_size + TCB_SIZE,
+        flags,
+        &[],
+    );
+    protect_segment(page_range, flags);
+
+    let thread_pointer = v::USER_TLS_START + block_size;
+    user_access(|| unsafe {
+        if let Some(tls) = executable.tls {
+            // the template is part of a loaded segment
+            ptr::copy_nonoverlapping(
+                executable.base.wrapping_add(tls.p_vaddr) as *const u8,
+                v::USER_TLS_START as *mut u8,
+                tls.p_filesz as usize,
+            );
+        }
+        ptr::write(thread_pointer as *mut u64, thread_pointer);
+    });
+    thread_pointer
+}
+
+fn register_stack_and_heap() {
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::USER_ACCESSIBLE;
+    let areas = [
+        VirtualMemoryArea::new(
+            "user stack guard",
+            v::USER_STACK_START..v::USER_STACK_START + 4096,
+            PageTableFlags::empty(),
+            Backing::Guard,
+        ),
+        VirtualMemoryArea::new(
+            "user stack",
+            v::USER_STACK_START + 4096..v::USER_STACK_START + USER_STACK_SIZE,
+            flags,
+            Backing::DemandPaged,
+        ),
+        VirtualMemoryArea::new(
+            "user heap",
+            v::USER_HEAP_START..v::USER_END,
+            flags,
+            Backing::DemandPaged,
+        ),
+    ];
+    for area in areas {
+        memory::register_area(area).expect("application overlaps the stack or heap region");
     }
 }
 
@@ -70,7 +493,14 @@ fn allocate_stack() {
 pub struct ApplicationResources {
     l4_page_table: OffsetPageTable<'static>,
     entry_point_virt_addr: u64,
+    load_base: u64,
+    // fs base of the application (see map_tls)
+    thread_pointer: u64,
     heap: UserAllocatorWrapper,
+    // saved while the application is switched out
+    fpu: FpuState,
+    // used by the syscalls of the application, dropped (closed) when it ends
+    handles: Mutex<HandleTable>,
 }
 
 impl Drop for ApplicationResources {
@@ -80,13 +510,16 @@ impl Drop for ApplicationResources {
             .switch_to_user_page_table(&mut self.l4_page_table);
 
         log::warn!("TODO free application resources");
+        memory::remove_user_address_space(&mut self.l4_page_table);
 
         MEMORY.lock().switch_to_kernel_page_table();
     }
 }
 
-pub fn prepare_application(file: &[u8]) -> ApplicationResources {
+// Fails without side effects if the file is not a valid application
+pub fn prepare_application(file: &[u8]) -> Result<ApplicationResources, LoaderError> {
     log::debug!("Preparing application");
+    let executable = parse(file)?;
     let l4_page_table = {
         let mut mem = MEMORY.lock();
         let mut user_page_table = mem.create_user_page_table();
@@ -94,16 +527,38 @@ pub fn prepare_application(file: &[u8]) -> ApplicationResources {
         user_page_table
     };
 
-    let entry_point = load(file);
+    let image = load(&executable);
+    log::debug!("Application loaded at {:#x}", image.base);
+    // the stack and the heap are mapped on demand by the page fault handler
+    register_stack_and_heap();
     let heap = crate::allocator::create_user_heap();
-    allocate_stack();
 
     MEMORY.lock().switch_to_kernel_page_table();
 
-    ApplicationResources {
+    Ok(ApplicationResources {
         l4_page_table,
-        entry_point_virt_addr: entry_point,
+        entry_point_virt_addr: image.entry_point,
+        load_base: image.base,
+        thread_pointer: image.thread_pointer,
         heap,
+        fpu: FpuState::new(),
+        handles: Mutex::new(HandleTable::default()),
+    })
+}
+
+impl ApplicationResources {
+    // the offset of the image to its link address, 0 for applications linked at a fixed address
+    pub const fn load_base(&self) -> u64 {
+        self.load_base
+    }
+
+    pub const fn thread_pointer(&self) -> u64 {
+        self.thread_pointer
+    }
+
+    // handles are numbered in order, starting at 0
+    pub fn add_handle(&mut self, handle: Handle) -> u64 {
+        self.handles.get_mut().insert(handle)
     }
 }
 
@@ -111,27 +566,26 @@ fn free_application(resources: ApplicationResources) {
     drop(resources);
 }
 
-fn load(file: &[u8]) -> u64 {
-    log::trace!("Loading application");
-    let file = ElfBytes::<LittleEndian>::minimal_parse(file).unwrap();
+struct LoadedImage {
+    entry_point: u64,
+    // 0 for applications linked at a fixed address
+    base: u64,
+    thread_pointer: u64,
+}
 
-    let entry_point = file.ehdr.e_entry;
+fn load(executable: &Executable) -> LoadedImage {
+    log::trace!("Loading application");
+    let base = executable.base;
+    let entry_point = base.wrapping_add(executable.entry);
     // println!("Entry point: {entry_point:X}");
 
-    for segment in file.segments().unwrap() {
-        if segment.p_type != elf::abi::PT_LOAD {
-            continue;
-        }
-        let virt_addr = segment.p_vaddr;
-        // if virt_addr < crate::constants::v::USER_START {
-        //     continue;
-        // }
+    let mut loaded = Vec::new();
+    for segment in &executable.segments {
+        let virt_addr = base.wrapping_add(segment.p_vaddr);
         let size = segment.p_memsz;
         let raw_flags = segment.p_flags;
         let raw_flag_exec = raw_flags & elf::abi::PF_X != 0;
         let raw_flag_write = raw_flags & elf::abi::PF_W != 0;
-        let raw_flag_read = raw_flags & elf::abi::PF_R != 0;
-        crate::ass!(raw_flag_read || raw_flag_exec);
 
         let mut flags = PageTableFlags::empty();
 
@@ -142,16 +596,25 @@ fn load(file: &[u8]) -> u64 {
             flags |= PageTableFlags::WRITABLE;
         }
 
-        let data = file.segment_data(&segment).unwrap();
+        let data = executable.file.segment_data(segment).unwrap();
 
         log::trace!(
             "Loading application segment [{virt_addr:X}] size:{size:X} flags:{flags:?} data_len:{}",
             data.len()
         );
 
-        map_segment(virt_addr, size, flags, data);
+        loaded.push((
+            map_segment("elf segment", virt_addr, size, flags, data),
+            flags,
+        ));
     }
 
+    relocate(executable);
+    for (page_range, flags) in loaded {
+        protect_segment(page_range, flags);
+    }
+    let thread_pointer = map_tls(executable);
+
     // Relics of a terrible idea with to much UB and double faults
     // let common = file.find_common_data().expect("shdrs should parse");
     // let symtab = common.symtab.unwrap();
@@ -172,101 +635,148 @@ fn load(file: &[u8]) -> u64 {
     //     }
     //     // println!("name:{name} addr:{addr:x} fp:{fp:p}");
     // }
-    entry_point
+    LoadedImage {
+        entry_point,
+        base,
+        thread_pointer,
+    }
 }
 
-mod user_functions {
-    use core::{alloc::GlobalAlloc, slice};
+// the heap of the application running on this core, used by the alloc and dealloc syscalls
+pub unsafe fn running_application_heap() -> &'static UserAllocatorWrapper {
+    &(*get_cld()
+        .running_application_data
+        .as_ref()
+        .expect("no application is running on this core")
+        .application_resources)
+        .heap
+}
 
-    use crate::smp::get_cld;
+// the handles of the application running on this core, used by the handle syscalls
+pub unsafe fn running_application_handles() -> &'static Mutex<HandleTable> {
+    &(*get_cld()
+        .running_application_data
+        .as_ref()
+        .expect("no application is running on this core")
+        .application_resources)
+        .handles
+}
 
-    pub static FUNCTION_POINTERS: FunctionPointers = FunctionPointers {
-        print,
-        abort,
-        alloc,
-        dealloc,
-    };
+// An application running in its own task (see sched)
+pub struct AppHandle {
+    name: String,
+    task: TaskId,
+    status: Arc<Once<ExitStatus>>,
+}
 
-    #[repr(C)]
-    pub struct FunctionPointers {
-        print: extern "C" fn(*const u8, u64),
-        abort: extern "C" fn(u64) -> !,
-        alloc: extern "C" fn(u64, u64) -> *mut u8,
-        dealloc: extern "C" fn(*mut u8, u64, u64),
+impl AppHandle {
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    pub extern "C" fn print(string: *const u8, len: u64) {
-        let slice = unsafe { slice::from_raw_parts(string, len as usize) };
-        crate::print!("{}", core::str::from_utf8(slice).unwrap());
-    }
-    pub extern "C" fn abort(exit_code: u64) -> ! {
-        unsafe {
-            super::abort(exit_code);
-        }
+    pub const fn task(&self) -> TaskId {
+        self.task
     }
-    pub extern "C" fn alloc(size: u64, alignment: u64) -> *mut u8 {
-        unsafe {
-            let layout =
-                core::alloc::Layout::from_size_align_unchecked(size as usize, alignment as usize);
-            let app_res = &mut *get_cld()
-                .running_application_data
-                .as_mut()
-                .unwrap()
-                .application_resources;
-            app_res.heap.inner.alloc(layout)
+
+    // waits until the application exited (lets other tasks of this core run in the meantime)
+    pub fn join(self) -> ExitStatus {
+        loop {
+            if let Some(status) = self.status.get() {
+                return *status;
+            }
+            crate::sched::yield_now();
+            core::hint::spin_loop();
         }
     }
-    pub extern "C" fn dealloc(ptr: *mut u8, size: u64, alignment: u64) {
-        unsafe {
-            let layout =
-                core::alloc::Layout::from_size_align_unchecked(size as usize, alignment as usize);
-            let app_res = &mut *get_cld()
-                .running_application_data
-                .as_mut()
-                .unwrap()
-                .application_resources;
-            app_res.heap.inner.dealloc(ptr, layout);
-        }
+}
+
+// Loads the application on the calling core and starts it in a new task
+pub fn spawn(name: &str) -> Result<AppHandle, LoaderError> {
+    spawn_on(name, CoreMask::all())
+}
+
+// Like spawn, the application only runs on the given cores
+pub fn spawn_on(name: &str, affinity: CoreMask) -> Result<AppHandle, LoaderError> {
+    spawn_with_handles(name, affinity, Vec::new())
+}
+
+// Like spawn_on, the application starts with the given handles (numbered in order, starting at 0)
+pub fn spawn_with_handles(
+    name: &str,
+    affinity: CoreMask,
+    handles: Vec<Handle>,
+) -> Result<AppHandle, LoaderError> {
+    let file = crate::ram_disk::find_file(name).ok_or(LoaderError::NotFound)?;
+    let mut resources = prepare_application(file)?;
+    for handle in handles {
+        resources.add_handle(handle);
     }
+    let status = Arc::new(Once::new());
+    let task_status = status.clone();
+    let app_name = name.to_string();
+    let task = crate::sched::spawn_on("application", affinity, move || {
+        let status = run(&mut resources);
+        log::debug!("Application '{app_name}' exited: {status:x?}");
+        free_application(resources);
+        task_status.call_once(|| status);
+    });
+    Ok(AppHandle {
+        name: name.to_string(),
+        task,
+        status,
+    })
 }
 
-pub fn run(resources: &mut ApplicationResources) -> u64 {
+pub fn run(resources: &mut ApplicationResources) -> ExitStatus {
+    run_until(resources, u64::MAX)
+}
+
+// Like run, the application is aborted once it ran longer than the time limit (including the time
+// other tasks ran in between). Checked by the timer interrupt, so it may run up to one tick longer
+pub fn run_with_time_limit(resources: &mut ApplicationResources, time_limit_ms: u64) -> ExitStatus {
+    let deadline_us = crate::sched::now_us().saturating_add(time_limit_ms.saturating_mul(1000));
+    run_until(resources, deadline_us)
+}
+
+fn run_until(resources: &mut ApplicationResources, deadline_us: u64) -> ExitStatus {
     log::debug!("Running application");
     MEMORY
         .lock()
         .switch_to_user_page_table(&mut resources.l4_page_table);
 
-    let ret = switch_stack_and_execute(resources);
-    get_cld().running_application_data = None;
+    let code = switch_stack_and_execute(resources, deadline_us);
+    let cause = get_cld()
+        .running_application_data
+        .take()
+        .expect("running application data was removed while the application ran")
+        .exit_cause;
+    // the application may have ended during the stack switch of a syscall entry
+    crate::syscall::reset_gs_base();
 
     MEMORY.lock().switch_to_kernel_page_table();
-    ret
-}
-
-#[naked]
-unsafe extern "C" fn save_instruction_pointer() {
-    asm!(
-        // In: rdi (abort_pointer_pointer), rsi (entry_point_pointer), rdx(saved_stack_pointer_pointer), rcx(function_pointers_pointer)
-        // Out: rax (exit_code), rdx (saved_stack_pointer_pointer)
-        "mov rax, [rsp]", // store return address in rax (used for abort)
-        "mov [rdi], rax", // store return address in abort_pointer_pointer
-        "mov r15, rdx",   // store saved_rsp_ptr in a callee save register (r15)
-        "mov rdi, rcx",   // set first argument for call to entry point to function_pointers_pointer
-        "call rsi",       // call entry point (sets rax to the exit code)
-        "mov rdx, r15",   // set rdx to saved_rsp_ptr
-        "ret",            // return
-        options(noreturn),
-    )
+    ExitStatus { code, cause }
 }
 
+// Enters the application in ring 3 with iretq. The kernel is entered again through interrupts and syscalls,
+// the application ends with the exit syscall or an abort, both jump to the label behind iretq,
+// which restores the saved kernel stack
 #[inline(never)]
-extern "C" fn switch_stack_and_execute(resources: &mut ApplicationResources) -> u64 {
+extern "C" fn switch_stack_and_execute(
+    resources: &mut ApplicationResources,
+    deadline_us: u64,
+) -> u64 {
     unsafe {
-        let new_rsp: u64 = v::USER_STACK_START + USER_STACK_SIZE - 1024;
+        // aligned like after a call, the entry point never returns
+        let user_rsp: u64 = v::USER_STACK_START + USER_STACK_SIZE - 1024 - 8;
 
-        println!("new rsp: {:x}", new_rsp);
+        log::trace!("user rsp: {:x}", user_rsp);
 
-        let entry_point_pointer = resources.entry_point_virt_addr;
+        let entry_point = resources.entry_point_virt_addr;
+        // saved and restored by the scheduler from now on
+        FsBase::write(VirtAddr::new(resources.thread_pointer));
+        let mut kernel_fpu = FpuState::new();
+        kernel_fpu.save();
+        resources.fpu.restore();
 
         let resources_ptr = core::ptr::addr_of_mut!(*resources);
         let cld = get_cld();
@@ -274,62 +784,78 @@ extern "C" fn switch_stack_and_execute(resources: &mut ApplicationResources) ->
             application_resources: resources_ptr,
             abort_addr: 0,
             saved_stack_pointer: 0,
+            interrupts_enabled: x86_64::instructions::interrupts::are_enabled(),
+            exit_cause: ExitCause::Returned,
+            deadline_us,
         });
         let app_cld = cld.running_application_data.as_mut().unwrap();
 
         let saved_stack_pointer_pointer = core::ptr::addr_of_mut!(app_cld.saved_stack_pointer);
         let abort_pointer_pointer = core::ptr::addr_of_mut!(app_cld.abort_addr);
 
-        let function_pointers_pointer: *const user_functions::FunctionPointers =
-            &user_functions::FUNCTION_POINTERS;
+        let selectors = crate::interrupts::selectors();
+        // the interrupt flag is kept, alignment checking (the smap flag) is disabled
+        let rflags = x86_64::registers::rflags::read_raw()
+            & !x86_64::registers::rflags::RFlags::ALIGNMENT_CHECK.bits();
         let mut ret: u64;
 
-        // type EntryPoint = unsafe extern "C" fn(*const user_functions::FunctionPointers) -> u64;
-        // let entry_point = core::mem::transmute::<_, EntryPoint>(entry_point_pointer as *const ());
-        // println!("entry point: {:x}", entry_point_pointer);
-        // ret = entry_point(function_pointers_pointer);
-        // println!("done {ret}");
-        // drop(function_pointers_storage);
-        // return ret;
-
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
 
         asm!(
-            "push rbx",         // store everything on the stack which needs to be restored for after this asm block
+            "push rbx",         // callee saved registers are restored after the application ended
             "push rbp",
+            "push r12",
+            "push r13",
             "push r14",
             "push r15",
 
-            "mov [r8], rsp",    // save stack pointer
-            "mov rsp, r9",      // set new stack pointer
-            "mov rbp, rsp",
-            "mov rdi, r10",     // first  arg for save_instruction_pointer
-            "mov rsi, r11",     // second arg for save_instruction_pointer
-            "mov rdx, r8",      // third  arg for save_instruction_pointer
-            "mov rcx, r12",     // fourth arg for save_instruction_pointer
-
-            "call {sip}",       // pushes return address to stack
-                                // since we jump to this point directly from abort all registers (including stack are unusable) (except the return registers)
-
-            "mov r13, rax",     // rax contains the exit code of the application (from return value or abort)
-            "mov rsp, [rdx]",   // rdx contains the saved stack pointer (how it is retried divers between return and abort)
-
-
-            "pop r15",          // restore everything we saved at the beginning
+            "mov [rdi], rsp",   // save stack pointer
+            "lea rax, [rip + 2f]",
+            "mov [rsi], rax",   // the exit and abort paths jump to 2
+
+            "push r8",          // ss
+            "push r9",          // rsp
+            "push r10",         // rflags
+            "push r11",         // cs
+            "push rcx",         // rip
+
+            "xor eax, eax",     // do not leak kernel values into the application
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor edi, edi",
+            "xor esi, esi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+
+            "2:",               // rax: exit code, rdx: saved_stack_pointer_pointer
+            "mov rsp, [rdx]",
+            "pop r15",
             "pop r14",
+            "pop r13",
+            "pop r12",
             "pop rbp",
             "pop rbx",
 
-            // "2: jmp 2b",
-
-            in("r8") saved_stack_pointer_pointer,
-            in("r9") new_rsp,
-            in("r10") abort_pointer_pointer,
-            in("r11") entry_point_pointer,
-            in("r12") function_pointers_pointer,
-            out("r13") ret,
-            sip = sym save_instruction_pointer
+            in("rdi") saved_stack_pointer_pointer,
+            in("rsi") abort_pointer_pointer,
+            in("r8") u64::from(selectors.user_data.0),
+            in("r9") user_rsp,
+            in("r10") rflags,
+            in("r11") u64::from(selectors.user_code.0),
+            in("rcx") entry_point,
+            lateout("rax") ret,
+            clobber_abi("C"),
         );
+        kernel_fpu.restore();
         ret
     }
 }
@@ -359,9 +885,52 @@ unsafe extern "C" fn abort(exit_code: u64) -> ! {
     panic!("should not be reached");
 }
 
+// to be called from syscalls and exception handlers of a running application (same requirements as abort)
+// the kernel stack of the syscall or exception is abandoned, interrupts are restored to their state before
+// the application was started
+pub unsafe fn end_running_application(status: ExitStatus) -> ! {
+    let app_cld = get_cld()
+        .running_application_data
+        .as_mut()
+        .expect("no application is running on this core");
+    app_cld.exit_cause = status.cause;
+    if app_cld.interrupts_enabled {
+        x86_64::instructions::interrupts::enable();
+    }
+    abort(status.code);
+}
+
 #[derive(Debug, Clone)]
 pub struct RunningApplicationCLD {
     application_resources: *mut ApplicationResources,
     abort_addr: u64,
     saved_stack_pointer: u64,
+    // restored when the application ends (syscalls and exceptions disable interrupts)
+    interrupts_enabled: bool,
+    exit_cause: ExitCause,
+    // see run_with_time_limit (u64::MAX without a time limit)
+    deadline_us: u64,
+}
+
+// called by the timer interrupt after it interrupted an application (ring 3), ends it if its time limit passed
+pub fn enforce_time_limit() {
+    let expired = get_cld()
+        .running_application_data
+        .as_ref()
+        .is_some_and(|application| crate::sched::now_us() >= application.deadline_us);
+    if expired {
+        log::error!("Application exceeded its time limit");
+        unsafe { end_running_application(ExitStatus::timed_out()) };
+    }
+}
+
+impl RunningApplicationCLD {
+    // the fpu registers hold the state of the application while its task runs (see sched::schedule)
+    pub fn save_fpu(&self) {
+        unsafe { (*self.application_resources).fpu.save() };
+    }
+
+    pub fn restore_fpu(&self) {
+        unsafe { (*self.application_resources).fpu.restore() };
+    }
 }
@@ -0,0 +1,454 @@
+use core::panic::Location;
+
+use alloc::vec::Vec;
+#[cfg(not(feature = "lock-debug"))]
+use spin::mutex::{TicketMutex, TicketMutexGuard};
+
+// Lock diagnostics (cargo feature lock-debug, enabled with bootimage --lock-debug):
+// The central locks (MEMORY, ACPI and the serial ports) are TrackedMutexes, the terminal writers
+// TrackedRwLocks. With the feature they count acquisitions and contention, measure the hold times (tsc) and
+// remember the core and the code holding them. The watchdog reports locks held for longer than
+// DEADLOCK_REPORT_MS while others wait for them, hung cores report the locks they hold. Without the feature
+// they are plain ticket locks and spin read write locks.
+// Ticket locks are fair, a core waiting for the lock gets it before any core which asks for it later
+#[cfg(feature = "lock-debug")]
+pub use tracked::{TrackedGuard, TrackedMutex, TrackedRwLock, TrackedWriteGuard};
+
+#[cfg(not(feature = "lock-debug"))]
+pub type TrackedMutex<T> = TicketMutex<T>;
+#[cfg(not(feature = "lock-debug"))]
+pub type TrackedGuard<'a, T> = TicketMutexGuard<'a, T>;
+#[cfg(not(feature = "lock-debug"))]
+pub type TrackedRwLock<T> = spin::RwLock<T>;
+#[cfg(not(feature = "lock-debug"))]
+pub type TrackedWriteGuard<'a, T> = spin::RwLockWriteGuard<'a, T>;
+// readers aren't tracked as owners (there can be several)
+pub type TrackedReadGuard<'a, T> = spin::RwLockReadGuard<'a, T>;
+
+pub const DEADLOCK_REPORT_MS: u64 = 1_000;
+
+// A tracked lock has to stay in place once it was locked (a static or behind an Arc)
+#[cfg(feature = "lock-debug")]
+pub const fn tracked<T>(name: &'static str, value: T) -> TrackedMutex<T> {
+    TrackedMutex::new(name, value)
+}
+
+#[cfg(not(feature = "lock-debug"))]
+pub const fn tracked<T>(_name: &'static str, value: T) -> TrackedMutex<T> {
+    TicketMutex::new(value)
+}
+
+// Readers don't block each other, unlike the ticket locks it isn't fair (waiting writers let new readers
+// in), so it is meant for locks which are mostly written and read now and then
+#[cfg(feature = "lock-debug")]
+pub const fn tracked_rw<T>(name: &'static str, value: T) -> TrackedRwLock<T> {
+    TrackedRwLock::new(name, value)
+}
+
+#[cfg(not(feature = "lock-debug"))]
+pub const fn tracked_rw<T>(_name: &'static str, value: T) -> TrackedRwLock<T> {
+    spin::RwLock::new(value)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LockInfo {
+    pub name: &'static str,
+    pub acquisitions: u64,
+    // acquisitions which had to wait
+    pub contended: u64,
+    pub waiters: u64,
+    // cpu index and caller of the holder
+    pub owner: Option<(u64, &'static Location<'static>)>,
+    pub held_us: u64,
+    pub max_hold_us: u64,
+    pub total_hold_us: u64,
+}
+
+// snapshot of the tracked locks (empty without the feature)
+pub fn locks() -> Vec<LockInfo> {
+    #[cfg(feature = "lock-debug")]
+    {
+        tracked::locks()
+    }
+    #[cfg(not(feature = "lock-debug"))]
+    {
+        Vec::new()
+    }
+}
+
+// called by the watchdog on the bsp, lock free
+pub fn check_deadlocks() {
+    #[cfg(feature = "lock-debug")]
+    tracked::check_deadlocks();
+}
+
+// called by the nmi handler of a hung core
+pub fn log_held_by(cpu_index: u64) {
+    #[cfg(feature = "lock-debug")]
+    tracked::log_held_by(cpu_index);
+    #[cfg(not(feature = "lock-debug"))]
+    let _ = cpu_index;
+}
+
+#[cfg(feature = "lock-debug")]
+mod tracked {
+    use core::{
+        ops::{Deref, DerefMut},
+        panic::Location,
+        ptr,
+        sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
+    };
+
+    use alloc::vec::Vec;
+    use spin::mutex::{TicketMutex, TicketMutexGuard};
+    use x86_64::instructions::interrupts::without_interrupts;
+
+    use super::{LockInfo, DEADLOCK_REPORT_MS};
+    use crate::smp::try_get_cld;
+
+    const MAX_TRACKED: usize = 64;
+
+    // addresses of the stats of the locked tracked locks, locked with interrupts disabled
+    static REGISTRY: spin::Mutex<[usize; MAX_TRACKED]> = spin::Mutex::new([0; MAX_TRACKED]);
+
+    struct LockStats {
+        name: &'static str,
+        registered: AtomicBool,
+        acquisitions: AtomicU64,
+        contended: AtomicU64,
+        waiters: AtomicU64,
+        // cpu index + 1 of the holder, 0 while it is free
+        owner: AtomicU64,
+        location: AtomicPtr<Location<'static>>,
+        // tsc
+        locked_since: AtomicU64,
+        hold_ticks: AtomicU64,
+        max_hold_ticks: AtomicU64,
+        // the current hold was reported as a possible deadlock
+        reported: AtomicBool,
+    }
+
+    pub struct TrackedMutex<T> {
+        inner: TicketMutex<T>,
+        stats: LockStats,
+    }
+
+    pub struct TrackedGuard<'a, T> {
+        guard: TicketMutexGuard<'a, T>,
+        stats: &'a LockStats,
+    }
+
+    // the owner and the hold times are those of the writers
+    pub struct TrackedRwLock<T> {
+        inner: spin::RwLock<T>,
+        stats: LockStats,
+    }
+
+    pub struct TrackedWriteGuard<'a, T> {
+        guard: spin::RwLockWriteGuard<'a, T>,
+        stats: &'a LockStats,
+    }
+
+    fn rdtsc() -> u64 {
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+
+    fn ticks_to_us(ticks: u64) -> u64 {
+        ticks / (crate::apic::tsc_ticks_per_second() / 1_000_000).max(1)
+    }
+
+    impl<T> TrackedMutex<T> {
+        pub const fn new(name: &'static str, value: T) -> Self {
+            Self {
+                inner: TicketMutex::new(value),
+                stats: LockStats::new(name),
+            }
+        }
+
+        #[track_caller]
+        pub fn lock(&self) -> TrackedGuard<'_, T> {
+            let location = Location::caller();
+            let guard = self
+                .stats
+                .wait(|| self.inner.try_lock(), || self.inner.lock());
+            self.acquired(guard, location)
+        }
+
+        #[track_caller]
+        pub fn try_lock(&self) -> Option<TrackedGuard<'_, T>> {
+            let guard = self.inner.try_lock()?;
+            Some(self.acquired(guard, Location::caller()))
+        }
+
+        pub fn is_locked(&self) -> bool {
+            self.inner.is_locked()
+        }
+
+        fn acquired<'a>(
+            &'a self,
+            guard: TicketMutexGuard<'a, T>,
+            location: &'static Location<'static>,
+        ) -> TrackedGuard<'a, T> {
+            self.stats.acquired(location);
+            TrackedGuard {
+                guard,
+                stats: &self.stats,
+            }
+        }
+    }
+
+    impl LockStats {
+        const fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                registered: AtomicBool::new(false),
+                acquisitions: AtomicU64::new(0),
+                contended: AtomicU64::new(0),
+                waiters: AtomicU64::new(0),
+                owner: AtomicU64::new(0),
+                location: AtomicPtr::new(ptr::null_mut()),
+                locked_since: AtomicU64::new(0),
+                hold_ticks: AtomicU64::new(0),
+                max_hold_ticks: AtomicU64::new(0),
+                reported: AtomicBool::new(false),
+            }
+        }
+
+        // try_lock first, counts the lock as contended if it fails
+        fn wait<G>(&self, try_lock: impl FnOnce() -> Option<G>, lock: impl FnOnce() -> G) -> G {
+            if let Some(guard) = try_lock() {
+                return guard;
+            }
+            self.contended.fetch_add(1, Ordering::Relaxed);
+            self.waiters.fetch_add(1, Ordering::Relaxed);
+            let guard = lock();
+            self.waiters.fetch_sub(1, Ordering::Relaxed);
+            guard
+        }
+
+        fn counted(&self) {
+            if !self.registered.swap(true, Ordering::AcqRel) {
+                register(self);
+            }
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn acquired(&self, location: &'static Location<'static>) {
+            self.counted();
+            // only the bsp runs before the core local data exists
+            let core = try_get_cld().map_or(0, |cld| cld.cpu_index);
+            self.reported.store(false, Ordering::Relaxed);
+            self.location
+                .store(ptr::from_ref(location).cast_mut(), Ordering::Relaxed);
+            self.locked_since.store(rdtsc(), Ordering::Relaxed);
+            self.owner.store(core + 1, Ordering::Release);
+        }
+
+        fn released(&self) {
+            let held = rdtsc().saturating_sub(self.locked_since.load(Ordering::Relaxed));
+            self.owner.store(0, Ordering::Release);
+            self.hold_ticks.fetch_add(held, Ordering::Relaxed);
+            self.max_hold_ticks.fetch_max(held, Ordering::Relaxed);
+        }
+
+        fn unregister(&self) {
+            if self.registered.load(Ordering::Acquire) {
+                let address = ptr::from_ref(self) as usize;
+                without_interrupts(|| {
+                    for slot in REGISTRY.lock().iter_mut() {
+                        if *slot == address {
+                            *slot = 0;
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    impl<T> Drop for TrackedMutex<T> {
+        fn drop(&mut self) {
+            self.stats.unregister();
+        }
+    }
+
+    // further locks are not tracked once the registry is full (it can't log, the lock may be the logger's)
+    fn register(stats: &LockStats) {
+        without_interrupts(|| {
+            if let Some(slot) = REGISTRY.lock().iter_mut().find(|slot| **slot == 0) {
+                *slot = ptr::from_ref(stats) as usize;
+            }
+        });
+    }
+
+    impl<T> Deref for TrackedGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.guard
+        }
+    }
+
+    impl<T> DerefMut for TrackedGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.guard
+        }
+    }
+
+    // the inner guard unlocks after the stats are updated
+    impl<T> Drop for TrackedGuard<'_, T> {
+        fn drop(&mut self) {
+            self.stats.released();
+        }
+    }
+
+    impl<T> TrackedRwLock<T> {
+        pub const fn new(name: &'static str, value: T) -> Self {
+            Self {
+                inner: spin::RwLock::new(value),
+                stats: LockStats::new(name),
+            }
+        }
+
+        pub fn read(&self) -> spin::RwLockReadGuard<'_, T> {
+            let guard = self
+                .stats
+                .wait(|| self.inner.try_read(), || self.inner.read());
+            self.stats.counted();
+            guard
+        }
+
+        pub fn try_read(&self) -> Option<spin::RwLockReadGuard<'_, T>> {
+            let guard = self.inner.try_read()?;
+            self.stats.counted();
+            Some(guard)
+        }
+
+        #[track_caller]
+        pub fn write(&self) -> TrackedWriteGuard<'_, T> {
+            let location = Location::caller();
+            let guard = self
+                .stats
+                .wait(|| self.inner.try_write(), || self.inner.write());
+            self.stats.acquired(location);
+            TrackedWriteGuard {
+                guard,
+                stats: &self.stats,
+            }
+        }
+
+        #[track_caller]
+        pub fn try_write(&self) -> Option<TrackedWriteGuard<'_, T>> {
+            let guard = self.inner.try_write()?;
+            self.stats.acquired(Location::caller());
+            Some(TrackedWriteGuard {
+                guard,
+                stats: &self.stats,
+            })
+        }
+    }
+
+    impl<T> Drop for TrackedRwLock<T> {
+        fn drop(&mut self) {
+            self.stats.unregister();
+        }
+    }
+
+    impl<T> Deref for TrackedWriteGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.guard
+        }
+    }
+
+    impl<T> DerefMut for TrackedWriteGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.guard
+        }
+    }
+
+    impl<T> Drop for TrackedWriteGuard<'_, T> {
+        fn drop(&mut self) {
+            self.stats.released();
+        }
+    }
+
+    // calls f with the stats of every tracked lock, None if the registry is locked
+    fn for_each(mut f: impl FnMut(&LockStats)) -> Option<()> {
+        without_interrupts(|| {
+            let registry = REGISTRY.try_lock()?;
+            for &address in registry.iter().filter(|&&address| address != 0) {
+                // unregistered before it is dropped
+                f(unsafe { &*(address as *const LockStats) });
+            }
+            Some(())
+        })
+    }
+
+    fn info(stats: &LockStats) -> LockInfo {
+        let owner = stats.owner.load(Ordering::Acquire);
+        let location = stats.location.load(Ordering::Relaxed);
+        let held_us = if owner == 0 {
+            0
+        } else {
+            ticks_to_us(rdtsc().saturating_sub(stats.locked_since.load(Ordering::Relaxed)))
+        };
+        LockInfo {
+            name: stats.name,
+            acquisitions: stats.acquisitions.load(Ordering::Relaxed),
+            contended: stats.contended.load(Ordering::Relaxed),
+            waiters: stats.waiters.load(Ordering::Relaxed),
+            owner: (owner != 0 && !location.is_null()).then(|| (owner - 1, unsafe { &*location })),
+            held_us,
+            max_hold_us: ticks_to_us(stats.max_hold_ticks.load(Ordering::Relaxed)),
+            total_hold_us: ticks_to_us(stats.hold_ticks.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn locks() -> Vec<LockInfo> {
+        let mut stats = Vec::new();
+        // the registry is only locked briefly by other tasks
+        while for_each(|lock| stats.push(info(lock))).is_none() {
+            stats.clear();
+            core::hint::spin_loop();
+        }
+        stats
+    }
+
+    // reported once per hold
+    pub fn check_deadlocks() {
+        let _ = for_each(|stats| {
+            let lock = info(stats);
+            let Some((core, location)) = lock.owner else {
+                return;
+            };
+            if lock.waiters > 0
+                && lock.held_us > DEADLOCK_REPORT_MS * 1000
+                && !stats.reported.swap(true, Ordering::Relaxed)
+            {
+                log::error!(
+                    "Possible deadlock: lock {} held by core {core} for {} ms (locked at {location}), {} waiting",
+                    lock.name,
+                    lock.held_us / 1000,
+                    lock.waiters
+                );
+            }
+        });
+    }
+
+    pub fn log_held_by(cpu_index: u64) {
+        let _ = for_each(|stats| {
+            let lock = info(stats);
+            if let Some((core, location)) = lock.owner {
+                if core == cpu_index {
+                    log::error!(
+                        "Core {core} holds lock {} for {} ms (locked at {location}), {} waiting",
+                        lock.name,
+                        lock.held_us / 1000,
+                        lock.waiters
+                    );
+                }
+            }
+        });
+    }
+}
@@ -1,10 +1,16 @@
-use core::sync::atomic::AtomicU64;
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize},
+    time::Duration,
+};
 
 use log::{Level, LevelFilter, Metadata, Record};
+use x86_64::instructions::interrupts;
 
 use core::sync::atomic::Ordering;
 
-use crate::{smp::try_get_cld, terminal_out::Color};
+use crate::{kthread, per_core, sched::WaitQueue, smp::try_get_cld, terminal_out::Color};
 
 static LOGGER: KernelLogger = KernelLogger;
 struct KernelLogger;
@@ -20,88 +26,170 @@ impl log::Log for KernelLogger {
     fn log(&self, record: &Record) {
         let level = record.metadata().level();
 
-        let serial_level: LevelFilter =
-            unsafe { core::mem::transmute(SERIAL_LOG_LEVEL.load(Ordering::Relaxed)) };
-        let graphics_level: LevelFilter =
-            unsafe { core::mem::transmute(GRAPHICS_LOG_LEVEL.load(Ordering::Relaxed)) };
-
-        if level <= serial_level {
-            let _lock = SERIAL_WRITE_LOCK.lock();
-            crate::serial_print!("[{:<5}", record.level());
+        // the level of the module replaces the ones of both outputs (that aren't off)
+        let module_level = MODULE_FILTERS
+            .try_read()
+            .and_then(|filters| filters.level(record.target()));
+        if module_level.is_some_and(|module_level| level > module_level) {
+            return;
+        }
 
-            if let Some(ref mut cld) = try_get_cld() {
-                crate::serial_print!(" {: >2}", cld.cpu_index);
-            }
-            if let (Some(file), Some(line)) = (record.file(), record.line()) {
-                crate::serial_println!(" {}:{}] {}", file, line, record.args());
-            } else {
-                crate::serial_println!(" {}", record.args());
-            }
+        let stamp = Stamp::now();
+        if !READY.load(Ordering::Acquire) {
+            early(record, stamp);
+            return;
+        }
+        // the interrupted code may hold the locks of the outputs (a panic is written anyway)
+        if crate::interrupts::in_handler() && !crate::halt::is_panicking() {
+            DEFERRED[stamp.core.unwrap_or(0) as usize].push(record, stamp, module_level);
+            DEFERRED_PENDING.store(true, Ordering::Release);
+            DEFERRED_AVAILABLE.wake_all();
+            return;
         }
+        drain_deferred();
+        emit(record, stamp, module_level);
+    }
 
-        if level <= graphics_level {
-            let info_color = match level {
-                Level::Error => Color::new(255, 50, 50),
-                Level::Warn => Color::new(255, 200, 0),
-                Level::Info => Color::new(220, 220, 220),
-                Level::Debug => Color::new(0, 40, 255),
-                Level::Trace => Color::new(130, 130, 130),
-            };
-
-            let mut stdout = crate::terminal_out::Stdout::acquire();
-
-            let foreground = stdout.foreground();
-            let background = stdout.background();
-            let font = stdout.font_weight();
-
-            stdout.set_foreground(foreground);
-            stdout.set_background(background);
-            stdout.set_font_weight(crate::terminal_out::FontWeight::Light);
-            crate::print!(stdout; "[");
-            stdout.set_foreground(info_color);
-            stdout.set_font_weight(crate::terminal_out::FontWeight::Bold);
-            crate::print!(stdout;"{:<5}", record.level());
-            stdout.set_foreground(foreground);
-            if let Some(ref mut cld) = try_get_cld() {
-                crate::print!(stdout;" {: >2}", cld.cpu_index);
-            }
-            stdout.set_font_weight(crate::terminal_out::FontWeight::Light);
+    fn flush(&self) {
+        crate::serial::log_port().writer().flush();
+    }
+}
 
-            if let (Some(file), Some(line)) = (record.file(), record.line()) {
-                if file.len() > 50 {
-                    crate::print!(stdout;" |{}:{}", &file[file.len() - 50..], line);
-                } else {
-                    crate::print!(stdout;" {}:{}", file, line);
-                }
-            }
+// writes the record to the outputs its level passes
+fn emit(record: &Record, stamp: Stamp, module_level: Option<LevelFilter>) {
+    let level = record.metadata().level();
+
+    // the other cores may have been stopped while holding it
+    let recent = if crate::halt::is_panicking() {
+        RECENT.try_lock()
+    } else {
+        Some(RECENT.lock())
+    };
+    if let Some(mut recent) = recent {
+        recent.push(record, stamp);
+    }
 
-            crate::print!(stdout;"] - ");
-            stdout.set_font_weight(crate::terminal_out::FontWeight::Regular);
+    let output_level = |output: &AtomicU64| {
+        let level: LevelFilter = unsafe { core::mem::transmute(output.load(Ordering::Relaxed)) };
+        match module_level {
+            Some(module_level) if level != LevelFilter::Off => module_level,
+            _ => level,
+        }
+    };
+    let serial_level = output_level(&SERIAL_LOG_LEVEL);
+    let graphics_level = output_level(&GRAPHICS_LOG_LEVEL);
 
-            crate::println!(stdout;"{}", record.args());
+    if level <= serial_level && JSON_SERIAL_LOG.load(Ordering::Relaxed) {
+        let _lock = SERIAL_WRITE_LOCK.lock();
+        log_json(record, stamp);
+    } else if level <= serial_level {
+        let _lock = SERIAL_WRITE_LOCK.lock();
+        crate::serial_print!("[{:<5}", record.level());
 
-            stdout.set_foreground(foreground);
-            stdout.set_background(background);
-            stdout.set_font_weight(font);
+        if let Some(uptime) = stamp.uptime {
+            crate::serial_print!(" {:>4}.{:03}", uptime.as_secs(), uptime.subsec_millis());
+        }
+        if let Some(core) = stamp.core {
+            crate::serial_print!(" {core: >2}");
+        }
+        if let (Some(file), Some(line)) = (record.file(), record.line()) {
+            crate::serial_println!(" {}:{}] {}", file, line, record.args());
+        } else {
+            crate::serial_println!(" {}", record.args());
         }
     }
 
-    fn flush(&self) {
-        crate::serial::SERIAL.1.lock().flush();
+    if level <= graphics_level {
+        let info_color = match level {
+            Level::Error => Color::new(255, 50, 50),
+            Level::Warn => Color::new(255, 200, 0),
+            Level::Info => Color::new(220, 220, 220),
+            Level::Debug => Color::new(0, 40, 255),
+            Level::Trace => Color::new(130, 130, 130),
+        };
+
+        let mut stdout = crate::terminal_out::Stdout::acquire();
+
+        let foreground = stdout.foreground();
+        let background = stdout.background();
+        let font = stdout.font_weight();
+
+        stdout.set_foreground(foreground);
+        stdout.set_background(background);
+        stdout.set_font_weight(crate::terminal_out::FontWeight::Light);
+        crate::print!(stdout; "[");
+        stdout.set_foreground(info_color);
+        stdout.set_font_weight(crate::terminal_out::FontWeight::Bold);
+        crate::print!(stdout;"{:<5}", record.level());
+        stdout.set_foreground(foreground);
+        if let Some(uptime) = stamp.uptime {
+            crate::print!(stdout;" {:>4}.{:03}", uptime.as_secs(), uptime.subsec_millis());
+        }
+        if let Some(core) = stamp.core {
+            crate::print!(stdout;" {core: >2}");
+        }
+        stdout.set_font_weight(crate::terminal_out::FontWeight::Light);
+
+        if let (Some(file), Some(line)) = (record.file(), record.line()) {
+            if file.len() > 50 {
+                crate::print!(stdout;" |{}:{}", &file[file.len() - 50..], line);
+            } else {
+                crate::print!(stdout;" {}:{}", file, line);
+            }
+        }
+
+        crate::print!(stdout;"] - ");
+        stdout.set_font_weight(crate::terminal_out::FontWeight::Regular);
+
+        crate::println!(stdout;"{}", record.args());
+
+        stdout.set_foreground(foreground);
+        stdout.set_background(background);
+        stdout.set_font_weight(font);
     }
 }
 
+// The first call of kernel_main, records are kept in the early log until init_logging.
+// Doesn't need the heap, the serial port or anything lazily initialized
+pub fn init_early_logging() {
+    log::set_logger(&LOGGER)
+        .map(|()| log::set_max_level(LevelFilter::Trace))
+        .expect("Logger setup failed");
+}
+
+// writes the early log to the outputs (with the levels and module filters set by now)
 pub fn init_logging(serial_level: LevelFilter, graphics_level: LevelFilter) {
     set_serial_log_level(serial_level);
     set_graphics_log_level(graphics_level);
+    READY.store(true, Ordering::Release);
 
-    log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(LevelFilter::Trace))
-        .expect("Logger setup failed");
+    let dropped = {
+        let mut early = EARLY.lock();
+        let filters = MODULE_FILTERS.read();
+        for record in &mut early.records[..early.count] {
+            let target = core::str::from_utf8(&record.target[..record.target_len]).unwrap_or("?");
+            record.module_level = filters.level(target);
+            if !record
+                .module_level
+                .is_some_and(|module_level| record.level > module_level)
+            {
+                record.emit();
+            }
+        }
+        early.count = 0;
+        core::mem::take(&mut early.dropped)
+    };
+    if dropped > 0 {
+        log::warn!("{dropped} records didn't fit into the early log");
+    }
 
     log::debug!("Logging initialized");
 }
 
+pub fn set_serial_log_format(format: LogFormat) {
+    JSON_SERIAL_LOG.store(format == LogFormat::Json, Ordering::Release);
+}
+
 pub fn set_serial_log_level(level: LevelFilter) {
     SERIAL_LOG_LEVEL.store(level as u64, Ordering::Release);
 }
@@ -110,7 +198,522 @@ pub fn set_graphics_log_level(level: LevelFilter) {
     GRAPHICS_LOG_LEVEL.store(level as u64, Ordering::Release);
 }
 
+// replaces all module filters
+pub fn set_module_filters(filters: ModuleFilters) {
+    *MODULE_FILTERS.write() = filters;
+}
+
+// calls f with the module prefix and level of every filter
+pub fn module_filters(mut f: impl FnMut(&str, LevelFilter)) {
+    let filters = MODULE_FILTERS.read();
+    for filter in &filters.filters[..filters.count] {
+        f(filter.module(), filter.level);
+    }
+}
+
 static SERIAL_LOG_LEVEL: AtomicU64 = AtomicU64::new(0);
 static GRAPHICS_LOG_LEVEL: AtomicU64 = AtomicU64::new(0);
 
 static SERIAL_WRITE_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+// set by init_logging
+static READY: AtomicBool = AtomicBool::new(false);
+static JSON_SERIAL_LOG: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    // A json object per line on the serial port, for programs reading the log (like the test runner
+    // of bootimage): {"level":"INFO","ts":1.234567,"core":0,"target":"kernel::memory","file":"src/memory.rs","line":12,"msg":"..."}
+    // ts (seconds since boot), core, file and line are null while they are unknown
+    Json,
+}
+
+fn log_json(record: &Record, stamp: Stamp) {
+    let mut port = crate::serial::log_port().writer();
+    let _ = write_json(&mut *port, record, stamp);
+}
+
+fn write_json(out: &mut impl Write, record: &Record, stamp: Stamp) -> fmt::Result {
+    write!(out, "{{\"level\":\"{}\",\"ts\":", record.level())?;
+    match stamp.uptime {
+        Some(uptime) => write!(out, "{}.{:06}", uptime.as_secs(), uptime.subsec_micros())?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\"core\":")?;
+    match stamp.core {
+        Some(core) => write!(out, "{core}")?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\"target\":")?;
+    json_string(out, format_args!("{}", record.target()))?;
+    write!(out, ",\"file\":")?;
+    match record.file() {
+        Some(file) => json_string(out, format_args!("{file}"))?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\"line\":")?;
+    match record.line() {
+        Some(line) => write!(out, "{line}")?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\"msg\":")?;
+    json_string(out, *record.args())?;
+    writeln!(out, "}}")
+}
+
+// the arguments formatted as a json string, quoted and escaped
+fn json_string(out: &mut impl Write, args: fmt::Arguments) -> fmt::Result {
+    struct Escaped<'a, W: Write>(&'a mut W);
+
+    impl<W: Write> Write for Escaped<'_, W> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for c in s.chars() {
+                match c {
+                    '"' => self.0.write_str("\\\"")?,
+                    '\\' => self.0.write_str("\\\\")?,
+                    '\n' => self.0.write_str("\\n")?,
+                    '\r' => self.0.write_str("\\r")?,
+                    '\t' => self.0.write_str("\\t")?,
+                    c if c.is_control() => write!(self.0, "\\u{:04x}", c as u32)?,
+                    c => self.0.write_char(c)?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    out.write_char('"')?;
+    Escaped(out).write_fmt(args)?;
+    out.write_char('"')
+}
+
+// Levels of modules (e.g. `memory=trace,apic=warn`), stored inline since they are set before the heap
+// exists (see cmdline.rs). The logger only tries the lock, a record logged while the filters are
+// replaced goes through the levels of the outputs
+const MAX_MODULE_FILTERS: usize = 16;
+const MAX_MODULE_LENGTH: usize = 48;
+
+static MODULE_FILTERS: spin::RwLock<ModuleFilters> = spin::RwLock::new(ModuleFilters::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleFilterError {
+    // an entry without `=<level>` or with an empty module
+    Malformed,
+    InvalidLevel,
+    TooManyModules,
+    ModuleTooLong,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModuleFilter {
+    module: [u8; MAX_MODULE_LENGTH],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl ModuleFilter {
+    fn module(&self) -> &str {
+        core::str::from_utf8(&self.module[..self.len]).unwrap()
+    }
+
+    // the module itself and its submodules, paths are relative to the kernel crate
+    fn matches(&self, target: &str) -> bool {
+        let target = target.strip_prefix("kernel::").unwrap_or(target);
+        target
+            .strip_prefix(self.module())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ModuleFilters {
+    filters: [ModuleFilter; MAX_MODULE_FILTERS],
+    count: usize,
+}
+
+impl ModuleFilters {
+    pub const fn new() -> Self {
+        Self {
+            filters: [ModuleFilter {
+                module: [0; MAX_MODULE_LENGTH],
+                len: 0,
+                level: LevelFilter::Off,
+            }; MAX_MODULE_FILTERS],
+            count: 0,
+        }
+    }
+
+    // comma separated `<module>=<level>` entries, e.g. `memory=trace,sched::timer=warn`
+    pub fn parse(spec: &str) -> Result<Self, ModuleFilterError> {
+        let mut filters = Self::new();
+        for entry in spec.split(',').filter(|entry| !entry.is_empty()) {
+            let (module, level) = entry.split_once('=').ok_or(ModuleFilterError::Malformed)?;
+            if module.is_empty() {
+                return Err(ModuleFilterError::Malformed);
+            }
+            let level = level
+                .parse::<LevelFilter>()
+                .map_err(|_| ModuleFilterError::InvalidLevel)?;
+            if module.len() > MAX_MODULE_LENGTH {
+                return Err(ModuleFilterError::ModuleTooLong);
+            }
+            let filter = filters
+                .filters
+                .get_mut(filters.count)
+                .ok_or(ModuleFilterError::TooManyModules)?;
+            filter.module[..module.len()].copy_from_slice(module.as_bytes());
+            filter.len = module.len();
+            filter.level = level;
+            filters.count += 1;
+        }
+        Ok(filters)
+    }
+
+    // the level of the longest matching module prefix
+    pub fn level(&self, target: &str) -> Option<LevelFilter> {
+        self.filters[..self.count]
+            .iter()
+            .filter(|filter| filter.matches(target))
+            .max_by_key(|filter| filter.len)
+            .map(|filter| filter.level)
+    }
+}
+
+impl Default for ModuleFilters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The last log lines of every level, for the panic screen (see panic_screen.rs) and the remote shell.
+// Lines are cut after LINE_LENGTH bytes, so formatting into them doesn't allocate
+pub const RECENT_LINES: usize = 12;
+const LINE_LENGTH: usize = 160;
+
+static RECENT: spin::Mutex<Recent> = spin::Mutex::new(Recent::new());
+
+struct Recent {
+    lines: [[u8; LINE_LENGTH]; RECENT_LINES],
+    lengths: [usize; RECENT_LINES],
+    // the slot of the next line
+    next: usize,
+    count: usize,
+    // lines pushed since the boot, the number of the next line
+    total: u64,
+}
+
+impl Recent {
+    const fn new() -> Self {
+        Self {
+            lines: [[0; LINE_LENGTH]; RECENT_LINES],
+            lengths: [0; RECENT_LINES],
+            next: 0,
+            count: 0,
+            total: 0,
+        }
+    }
+
+    fn push(&mut self, record: &Record, stamp: Stamp) {
+        let mut line = Line {
+            bytes: &mut self.lines[self.next],
+            len: 0,
+        };
+        let _ = write!(line, "[{:<5}", record.level());
+        if let Some(uptime) = stamp.uptime {
+            let _ = write!(
+                line,
+                " {:>4}.{:03}",
+                uptime.as_secs(),
+                uptime.subsec_millis()
+            );
+        }
+        if let Some(core) = stamp.core {
+            let _ = write!(line, " {core: >2}");
+        }
+        let _ = write!(line, "] {}", record.args());
+        self.lengths[self.next] = line.len;
+        self.next = (self.next + 1) % RECENT_LINES;
+        self.count = (self.count + 1).min(RECENT_LINES);
+        self.total += 1;
+    }
+
+    fn line(&self, index: usize) -> &str {
+        let slot = (self.next + RECENT_LINES - self.count + index) % RECENT_LINES;
+        core::str::from_utf8(&self.lines[slot][..self.lengths[slot]]).unwrap_or("?")
+    }
+}
+
+// cut at the end of the bytes
+struct Line<'a> {
+    bytes: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Line<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            // multi line messages are kept on one line
+            let c = if c.is_control() { ' ' } else { c };
+            if self.len + c.len_utf8() > self.bytes.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.bytes[self.len..]);
+            self.len += c.len_utf8();
+        }
+        Ok(())
+    }
+}
+
+// Calls f with the last lines, the oldest first. Nothing is returned if another core logs at the
+// moment, so it can be called while panicking
+pub fn recent_lines(mut f: impl FnMut(&str)) {
+    let Some(recent) = RECENT.try_lock() else {
+        return;
+    };
+    for index in 0..recent.count {
+        f(recent.line(index));
+    }
+}
+
+// Calls f with the lines from line number next on (the older ones of them are gone if more than RECENT_LINES
+// were logged since), returns the number of the next line. f must not log
+pub fn lines_since(next: u64, mut f: impl FnMut(&str)) -> u64 {
+    let recent = RECENT.lock();
+    let first = recent.total - recent.count as u64;
+    for index in next.max(first)..recent.total {
+        f(recent.line((index - first) as usize));
+    }
+    recent.total
+}
+
+// Records logged by interrupt handlers, copied into the queue of their core (the code they interrupted
+// may hold the locks of the outputs). They are written by the next record logged outside of a handler
+// or by the log drain task (see start_drain). The text is cut after LINE_LENGTH bytes
+const DEFERRED_RECORDS: usize = 8;
+const TARGET_LENGTH: usize = 48;
+
+per_core! {
+    static DEFERRED: DeferredQueue = DeferredQueue::new();
+}
+static DEFERRED_PENDING: AtomicBool = AtomicBool::new(false);
+static DEFERRED_AVAILABLE: WaitQueue = WaitQueue::new();
+
+// when and where the record was logged, not written
+#[derive(Clone, Copy)]
+struct Stamp {
+    uptime: Option<Duration>,
+    core: Option<u64>,
+}
+
+impl Stamp {
+    fn now() -> Self {
+        Self {
+            uptime: crate::time::try_uptime(),
+            core: try_get_cld().map(|cld| cld.cpu_index),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Deferred {
+    level: Level,
+    target: [u8; TARGET_LENGTH],
+    target_len: usize,
+    file: Option<&'static str>,
+    line: Option<u32>,
+    stamp: Stamp,
+    module_level: Option<LevelFilter>,
+    text: [u8; LINE_LENGTH],
+    text_len: usize,
+}
+
+impl Deferred {
+    const EMPTY: Self = Self {
+        level: Level::Error,
+        target: [0; TARGET_LENGTH],
+        target_len: 0,
+        file: None,
+        line: None,
+        stamp: Stamp {
+            uptime: None,
+            core: None,
+        },
+        module_level: None,
+        text: [0; LINE_LENGTH],
+        text_len: 0,
+    };
+
+    fn write(&mut self, record: &Record, stamp: Stamp, module_level: Option<LevelFilter>) {
+        self.level = record.level();
+        self.file = record.file_static();
+        self.line = record.line();
+        self.stamp = stamp;
+        self.module_level = module_level;
+        let mut target = Line {
+            bytes: &mut self.target,
+            len: 0,
+        };
+        let _ = target.write_str(record.target());
+        self.target_len = target.len;
+        let mut text = Line {
+            bytes: &mut self.text,
+            len: 0,
+        };
+        let _ = write!(text, "{}", record.args());
+        self.text_len = text.len;
+    }
+
+    fn emit(&self) {
+        let target = core::str::from_utf8(&self.target[..self.target_len]).unwrap_or("?");
+        let text = core::str::from_utf8(&self.text[..self.text_len]).unwrap_or("?");
+        emit(
+            &Record::builder()
+                .args(format_args!("{text}"))
+                .level(self.level)
+                .target(target)
+                .file_static(self.file)
+                .line(self.line)
+                .build(),
+            self.stamp,
+            self.module_level,
+        );
+    }
+
+    // without a lock, e.g. when the kernel panics before logging is initialized
+    fn print_raw(&self) {
+        let text = core::str::from_utf8(&self.text[..self.text_len]).unwrap_or("?");
+        crate::serial::print_raw(format_args!("[{:<5} early", self.level));
+        if let (Some(file), Some(line)) = (self.file, self.line) {
+            crate::serial::print_raw(format_args!(" {file}:{line}"));
+        }
+        crate::serial::print_raw(format_args!("] {text}\n"));
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const DEFERRED_SLOT_INIT: UnsafeCell<Deferred> = UnsafeCell::new(Deferred::EMPTY);
+
+// Written only by the handlers of its core and read by one drainer at a time (the records between
+// tail and head are written, both only grow)
+struct DeferredQueue {
+    records: [UnsafeCell<Deferred>; DEFERRED_RECORDS],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    draining: spin::Mutex<()>,
+    // records that didn't fit, since the last drain
+    dropped: AtomicU64,
+}
+
+unsafe impl Sync for DeferredQueue {}
+
+impl DeferredQueue {
+    const fn new() -> Self {
+        Self {
+            records: [DEFERRED_SLOT_INIT; DEFERRED_RECORDS],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            draining: spin::Mutex::new(()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    // a nested handler (an exception or nmi in a handler) may overwrite the record of the interrupted one
+    fn push(&self, record: &Record, stamp: Stamp, module_level: Option<LevelFilter>) {
+        interrupts::without_interrupts(|| {
+            let head = self.head.load(Ordering::Relaxed);
+            if head - self.tail.load(Ordering::Acquire) == DEFERRED_RECORDS {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            let slot = unsafe { &mut *self.records[head % DEFERRED_RECORDS].get() };
+            slot.write(record, stamp, module_level);
+            self.head.store(head + 1, Ordering::Release);
+        });
+    }
+
+    fn drain(&self) {
+        if self.tail.load(Ordering::Relaxed) == self.head.load(Ordering::Acquire) {
+            return;
+        }
+        // another core writes them
+        let Some(_draining) = self.draining.try_lock() else {
+            return;
+        };
+        loop {
+            let tail = self.tail.load(Ordering::Relaxed);
+            if tail == self.head.load(Ordering::Acquire) {
+                break;
+            }
+            // the slot is free again once it is copied
+            let deferred = unsafe { *self.records[tail % DEFERRED_RECORDS].get() };
+            self.tail.store(tail + 1, Ordering::Release);
+            deferred.emit();
+        }
+    }
+}
+
+// writes the deferred records of every core, the ones of a core in order
+fn drain_deferred() {
+    for queue in DEFERRED.iter() {
+        queue.drain();
+        let dropped = queue.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!("{dropped} log records of interrupt handlers didn't fit into the queue");
+        }
+    }
+}
+
+// writes the deferred records while nothing else is logged
+pub fn start_drain() {
+    kthread::spawn("log drain", drain_task);
+}
+
+fn drain_task() {
+    loop {
+        DEFERRED_AVAILABLE.wait_until(|| DEFERRED_PENDING.swap(false, Ordering::Acquire));
+        drain_deferred();
+    }
+}
+
+// Records logged before init_logging (the levels and the outputs aren't known yet), the first ones
+// are kept. Nothing is written unless the kernel panics before logging is initialized
+const EARLY_RECORDS: usize = 32;
+
+static EARLY: spin::Mutex<EarlyLog> = spin::Mutex::new(EarlyLog {
+    records: [Deferred::EMPTY; EARLY_RECORDS],
+    count: 0,
+    dropped: 0,
+});
+
+struct EarlyLog {
+    records: [Deferred; EARLY_RECORDS],
+    count: usize,
+    dropped: u64,
+}
+
+fn early(record: &Record, stamp: Stamp) {
+    let early = EARLY.try_lock();
+    // nothing else would show them (or an exception happened while the early log was written)
+    if crate::halt::is_panicking() || early.is_none() {
+        if let Some(mut early) = early {
+            for record in &early.records[..early.count] {
+                record.print_raw();
+            }
+            early.count = 0;
+        }
+        let mut raw = Deferred::EMPTY;
+        raw.write(record, stamp, None);
+        raw.print_raw();
+        return;
+    }
+    let Some(mut early) = early else {
+        return;
+    };
+    if early.count == EARLY_RECORDS {
+        early.dropped += 1;
+        return;
+    }
+    let count = early.count;
+    early.records[count].write(record, stamp, None);
+    early.count += 1;
+}
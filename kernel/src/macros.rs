@@ -28,12 +28,13 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+// a barrier of its own for every call site (see sync::barrier for named ones)
 #[macro_export]
 macro_rules! barrier {
     ($count:expr) => {{
-        static BARRIER: spin::once::Once<spin::Barrier> = spin::once::Once::new();
+        static BARRIER: spin::once::Once<$crate::sync::Barrier> = spin::once::Once::new();
         BARRIER
-            .call_once(|| spin::Barrier::new($count as usize))
+            .call_once(|| $crate::sync::Barrier::new($count as usize))
             .wait();
     }};
 }
@@ -20,82 +20,183 @@
 mod acpi;
 mod allocator;
 mod apic;
+mod backtrace;
+mod blit;
+mod cmdline;
 mod common_main;
 mod constants;
+mod cpuid;
+mod dma;
+mod fpu;
+mod fs;
+mod gdb;
+mod halt;
+mod handle;
+#[cfg(feature = "heap-debug")]
+mod heap_debug;
+mod hpet;
+mod image;
+mod input;
 mod interrupts;
+mod ioapic;
+mod kaslr;
+mod kernel_stack;
+mod keyboard;
+mod kthread;
 mod loader;
+mod lock_debug;
 mod logging;
 mod macros;
 mod memory;
+mod mouse;
+mod msi;
+mod net;
+mod numa;
+mod panic_screen;
+mod pci;
+mod per_core;
+mod pipe;
 mod pit;
+mod power;
+mod ps2;
 mod ram_disk;
+mod random;
+mod sched;
 mod serial;
+mod shell;
 mod smp;
+mod status_bar;
+mod symbols;
+mod sync;
+mod syscall;
+mod tar;
 mod terminal_out;
 mod tester;
 mod tests;
+mod time;
+mod timer_callbacks;
+mod tlb;
+mod user_access;
+mod watchdog;
 
 extern crate alloc;
 
 use bootloader_api::{BootInfo, BootloaderConfig};
 use spin::Once;
-use x86_64::instructions::hlt;
 
+// the frame buffer config is deprecated in favor of the boot config of the disk image, the bootloader
+// only uses it if the image doesn't set a resolution (see bootimage --resolution)
+#[allow(deprecated)]
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
+    config.frame_buffer.minimum_framebuffer_width = Some(constants::MIN_FRAME_BUFFER_WIDTH);
+    config.frame_buffer.minimum_framebuffer_height = Some(constants::MIN_FRAME_BUFFER_HEIGHT);
     config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
     config.kernel_stack_size = constants::KERNEL_STACK_SIZE;
     config.mappings.dynamic_range_start = Some(constants::v::KERNEL_DYNAMIC_START);
     config.mappings.dynamic_range_end = Some(constants::v::KERNEL_DYNAMIC_END);
+    config.mappings.aslr = true; // randomizes the dynamic mappings (see kaslr.rs)
     config
 };
 
 bootloader_api::entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
 // initialization order:
+// early logging (records are kept until logging is initialized, written raw to the serial port on a panic)
+// register the bsp stack (for stack overflow diagnosis)
 // Set global boot info
 // gdt_and_exceptions_bsp: to be able to handle exceptions (which shouldn't happen at this point)
-// initialize logging (includes serial port)
+// cpu features (per core, panics if a required one is missing, the bsp's decide for the whole system)
+// parse kernel command line (only needs the ram disk from the boot info)
+// initialize logging (includes serial port, writes the early records)
+// kernel layout (kaslr offsets, lazily chosen, needs the command line)
+// smap/smep (per core)
 // change pat so write_through + cache_disabled is write combining (workaround it would be better to use the pat bit in huge pages)
 // set frame buffer to write combining (way faster than default on real hardware)
 // (optional clear screen)
 // (optional assert stuff we can print nice error messages)
 // heap (lazily initialized) a lot of stuff needs a heap (could be optimized but the acpi currently needs a heap, and by extension the core local storage)
+// virtual memory areas of the boot mappings (needs heap)
 // acpi (needs heap, lazily initialized)
+// hpet (needs acpi, reference for the timer calibration in apic init)
 // apic creation (needs acpi, required for local interrupts)
 // core local storage (needs apic (use try_get_cli in exception handlers since this initialization is so late))
+// syscalls (per core, needs the gdt and core local storage)
 // apic init (needed for apic to function)
+// time (needs the clock calibrated by apic init, log messages get timestamps from here on)
+// file systems (needs heap, mounts the ram disk at /boot and a tmpfs at /tmp)
 // smp (needs apic, multi core support (initializes aps))
+// remote call receiver (needs apic and core local storage, before interrupts are enabled)
+// scheduler (per core, needs heap, apic and core local storage, before interrupts are enabled)
 // enable interrupts
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    logging::init_early_logging();
+    kernel_stack::register_bsp();
     BOOT_INFO.call_once(|| boot_info as *mut _ as u64);
 
     interrupts::init_gdt_and_exceptions_bsp();
-
-    logging::init_logging(log::LevelFilter::Trace, log::LevelFilter::Trace);
+    cpuid::init(0);
+
+    let cmdline = cmdline::get();
+    serial::set_log_port(cmdline.log_port);
+    logging::set_module_filters(cmdline.module_filters);
+    logging::set_serial_log_format(cmdline.log_format);
+    logging::init_logging(cmdline.serial_log_level, cmdline.graphics_log_level);
+    cmdline.log(log::Level::Info);
+    kaslr::layout().log(log::Level::Debug);
+    user_access::init(cpuid::features());
+    fpu::init();
 
     memory::change_pat_so_write_through_plus_cache_disabled_is_write_combining();
     memory::set_frame_buffer_cache_to_write_combining();
 
-    terminal_out::Stdout::acquire().clear(Some(terminal_out::FontSize::Size20));
+    if cmdline.graphics {
+        terminal_out::Stdout::acquire().clear(Some(terminal_out::default_font_size()));
+    }
     assert_boot_info();
+    if cmdline.gdb {
+        gdb::init();
+    }
 
+    memory::register_boot_areas();
+    hpet::init();
     apic::create();
     smp::initialize_own_core_local_data(smp::CoreLocalData::default());
+    syscall::init();
     apic::init();
+    time::init();
+    ioapic::init();
+    pci::init();
+    fs::init();
+    if let Err(err) = keyboard::init() {
+        log::warn!("No PS/2 keyboard: {err:?}");
+    }
+    if let Err(err) = mouse::init() {
+        log::warn!("No PS/2 mouse: {err:?}");
+    }
 
+    smp::init_topology();
     smp::init_smp();
 
+    smp::init_remote_calls();
+    sched::init();
     x86_64::instructions::interrupts::enable();
 
     smp::sync_cores_barrier();
 
-    memory::MEMORY
-        .lock()
-        .log_memory_utilization(log::Level::Info);
+    {
+        let memory = memory::MEMORY.lock();
+        memory.log_memory_utilization(log::Level::Info);
+        memory.log_memory_map(log::Level::Debug);
+    }
+    numa::layout().log(log::Level::Info);
 
     log::info!("Booted successfully");
+    watchdog::start();
+    logging::start_drain();
+    power::init_events();
+    net::init();
 
     smp::sync_cores_barrier();
 
@@ -105,9 +206,8 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     #[cfg(not(feature = "testing"))]
     common_main::main();
 
-    loop {
-        hlt();
-    }
+    // the idle task of this core takes over (and runs or steals the spawned tasks)
+    sched::exit()
 }
 
 fn assert_boot_info() {
@@ -135,14 +235,10 @@ fn assert_boot_info() {
 #[cfg(not(feature = "testing"))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    use core::fmt::Write;
-
-    terminal_out::panic_print(|term| {
-        term.foreground = terminal_out::Color::new(0xFF, 0x00, 0x00);
-        term.background = terminal_out::Color::new(0x70, 0x70, 0x00);
-        term.write_fmt(format_args!("\n{info}\n")).unwrap();
-    });
+    halt::stop_other_cores();
+    panic_screen::show(info);
     log::error!("\n\t{info}");
+    backtrace::log();
 
     #[allow(clippy::empty_loop)]
     loop {}
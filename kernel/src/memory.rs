@@ -1,22 +1,31 @@
 // everything is UNSAFE! unsafe functions are only extra unsafe
 
-use core::ptr::addr_of;
+use core::{fmt, ops::Range, ptr::addr_of};
 
+use alloc::{collections::BTreeMap, vec::Vec};
 use lazy_static::lazy_static;
-use spin::Mutex;
 use x86_64::{
     align_down, align_up,
     registers::control::Cr3Flags,
+    structures::idt::PageFaultErrorCode,
     structures::paging::{
-        page::PageRangeInclusive, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page,
-        PageTable, PageTableFlags, PhysFrame, Size4KiB,
+        mapper::{MapToError, MappedFrame, TranslateResult},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableEntry, PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
 
 use bootloader_api::info::MemoryRegionKind;
 
-use crate::{ass, constants::v, println};
+use crate::{
+    ass,
+    constants::v,
+    lock_debug::{self, TrackedMutex},
+    numa::{self, MAX_NODES},
+    println,
+    tlb::PendingShootdown,
+};
 
 #[inline]
 pub fn active_level_4_table() -> &'static mut PageTable {
@@ -94,10 +103,188 @@ pub fn switch_l4_page_table(l4_table: &mut OffsetPageTable) {
     }
 }
 
+// Free frames are kept in a list per numa node, allocations prefer the nearest node with free frames
 pub struct BootInfoFrameAllocator {
-    free_memory_head: Option<*mut FreeNode>,
+    free_lists: [Option<*mut FreeNode>; MAX_NODES],
+    node_free_frames: [u64; MAX_NODES],
     total_frames: u64,
     free_frames: u64,
+    // reserved physically contiguous pool (not part of the free list)
+    contiguous: ContiguousFrameAllocator,
+    regions: RegionTable,
+}
+
+// more usable regions than this are not tracked (their frames are still allocated normally)
+const MAX_TRACKED_REGIONS: usize = 64;
+
+// Frame counters of one usable memory region (frames of the dma pool and the ramdisk are not counted)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegionStats {
+    pub start: u64,
+    pub end: u64, // exclusive
+    pub total_frames: u64,
+    pub free_frames: u64,
+}
+
+impl RegionStats {
+    pub const fn used_frames(&self) -> u64 {
+        self.total_frames - self.free_frames
+    }
+}
+
+// sorted by start address (the bootloader reports the regions sorted)
+struct RegionTable {
+    regions: [RegionStats; MAX_TRACKED_REGIONS],
+    len: usize,
+}
+
+impl RegionTable {
+    fn new() -> Self {
+        let mut table = Self {
+            regions: [RegionStats::default(); MAX_TRACKED_REGIONS],
+            len: 0,
+        };
+        for r in usable_regions() {
+            if table.len == MAX_TRACKED_REGIONS {
+                log::warn!("Too many memory regions, {r:?} is not tracked");
+                continue;
+            }
+            table.regions[table.len] = RegionStats {
+                start: r.start,
+                end: r.end,
+                ..Default::default()
+            };
+            table.len += 1;
+        }
+        table.regions[..table.len].sort_unstable_by_key(|r| r.start);
+        table
+    }
+
+    fn find(&mut self, addr: PhysAddr) -> Option<&mut RegionStats> {
+        let addr = addr.as_u64();
+        let index = self.regions[..self.len].partition_point(|r| r.end <= addr);
+        self.regions[..self.len]
+            .get_mut(index)
+            .filter(|r| r.start <= addr)
+    }
+
+    fn frame_added(&mut self, addr: PhysAddr) {
+        if let Some(region) = self.find(addr) {
+            region.total_frames += 1;
+            region.free_frames += 1;
+        }
+    }
+
+    fn frame_allocated(&mut self, addr: PhysAddr) {
+        if let Some(region) = self.find(addr) {
+            region.free_frames -= 1;
+        }
+    }
+
+    fn frame_freed(&mut self, addr: PhysAddr) {
+        if let Some(region) = self.find(addr) {
+            region.free_frames += 1;
+        }
+    }
+
+    fn get(&self) -> &[RegionStats] {
+        &self.regions[..self.len]
+    }
+}
+
+// the table is printed while holding MEMORY, so formatting must not allocate
+fn region_kind_name(kind: MemoryRegionKind) -> &'static str {
+    match kind {
+        MemoryRegionKind::Usable => "usable",
+        MemoryRegionKind::Bootloader => "bootloader",
+        MemoryRegionKind::UnknownUefi(_) => "uefi reserved",
+        MemoryRegionKind::UnknownBios(_) => "bios reserved",
+        _ => "unknown",
+    }
+}
+
+fn usable_regions() -> impl Iterator<Item = &'static bootloader_api::info::MemoryRegion> {
+    crate::get_boot_info()
+        .memory_regions
+        .iter()
+        .filter(|r| r.kind == MemoryRegionKind::Usable && r.start > 0)
+}
+
+const DMA_POOL_SIZE: u64 = 16 * 1024 * 1024;
+const DMA_POOL_FRAMES: usize = (DMA_POOL_SIZE / Size4KiB::SIZE) as usize;
+
+// Bitmap allocator for physically contiguous multi frame buffers (dma rings and buffers)
+pub struct ContiguousFrameAllocator {
+    base: PhysAddr,
+    frame_count: u64,
+    used: [u64; DMA_POOL_FRAMES / 64],
+}
+
+impl ContiguousFrameAllocator {
+    pub fn new(base: PhysAddr, frame_count: u64) -> Self {
+        ass!(base.is_aligned(Size4KiB::SIZE));
+        ass!(frame_count, <=, DMA_POOL_FRAMES as u64);
+        Self {
+            base,
+            frame_count,
+            used: [0; DMA_POOL_FRAMES / 64],
+        }
+    }
+
+    fn is_used(&self, index: u64) -> bool {
+        self.used[index as usize / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set_used(&mut self, indices: Range<u64>, used: bool) {
+        for index in indices {
+            if used {
+                self.used[index as usize / 64] |= 1 << (index % 64);
+            } else {
+                self.used[index as usize / 64] &= !(1 << (index % 64));
+            }
+        }
+    }
+
+    // first fit search for frames consecutive free frames, the first one aligned to alignment (power of two in bytes)
+    pub fn allocate_contiguous(&mut self, frames: u64, alignment: u64) -> Option<PhysFrame> {
+        ass!(frames > 0 && alignment.is_power_of_two());
+        let alignment = alignment.max(Size4KiB::SIZE);
+
+        let mut start = 0;
+        while start + frames <= self.frame_count {
+            let addr = self.base + start * Size4KiB::SIZE;
+            if !addr.is_aligned(alignment) {
+                start += (addr.align_up(alignment) - addr) / Size4KiB::SIZE;
+                continue;
+            }
+            match (start..start + frames).rev().find(|&i| self.is_used(i)) {
+                Some(used) => start = used + 1,
+                None => {
+                    self.set_used(start..start + frames, true);
+                    return Some(PhysFrame::from_start_address(addr).unwrap());
+                }
+            }
+        }
+        None
+    }
+
+    pub unsafe fn deallocate_contiguous(&mut self, first: PhysFrame, frames: u64) {
+        let start = (first.start_address() - self.base) / Size4KiB::SIZE;
+        ass!(start + frames <= self.frame_count);
+        ass!(
+            (start..start + frames).all(|i| self.is_used(i)),
+            "double free of dma frames"
+        );
+        self.set_used(start..start + frames, false);
+    }
+
+    pub fn free_frames(&self) -> u64 {
+        (0..self.frame_count).filter(|&i| !self.is_used(i)).count() as u64
+    }
+
+    pub const fn total_frames(&self) -> u64 {
+        self.frame_count
+    }
 }
 
 struct FreeNode {
@@ -126,33 +313,81 @@ pub fn set_frame_buffer_cache_to_write_combining() {
         .unwrap()
         .buffer();
 
-    let page_range: PageRangeInclusive = {
-        let region_start = VirtAddr::new(addr_of!(frame_buffer[0]) as u64);
-        let region_end = region_start + frame_buffer.len() as u64 - 1u64;
-        let region_start_page = Page::containing_address(region_start);
-        let region_end_page = Page::containing_address(region_end);
-        Page::range_inclusive(region_start_page, region_end_page)
-    };
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_CACHE;
+
+    let region_start = VirtAddr::new(addr_of!(frame_buffer[0]) as u64);
+    let region_end = region_start + frame_buffer.len() as u64;
 
-    for page in page_range {
+    let mut table = get_active_l4_page_table();
+    let mut addr = region_start.align_down(Size4KiB::SIZE);
+    let mut huge_page_count = 0;
+    while addr < region_end {
+        if addr.is_aligned(Size2MiB::SIZE)
+            && region_end - addr >= Size2MiB::SIZE
+            && promote_to_huge_page(&table, Page::from_start_address(addr).unwrap(), flags)
+        {
+            huge_page_count += 1;
+            addr += Size2MiB::SIZE;
+            continue;
+        }
         unsafe {
-            get_active_l4_page_table()
-                .update_flags(
-                    page,
-                    PageTableFlags::PRESENT
-                        | PageTableFlags::WRITABLE
-                        | PageTableFlags::NO_EXECUTE
-                        | PageTableFlags::WRITE_THROUGH
-                        | PageTableFlags::NO_CACHE,
-                )
+            table
+                .update_flags(Page::<Size4KiB>::from_start_address(addr).unwrap(), flags)
                 .unwrap()
                 .flush();
         }
+        addr += Size4KiB::SIZE;
+    }
+    x86_64::instructions::tlb::flush_all();
+    log::debug!("Frame buffer mapped with {huge_page_count} huge pages");
+}
+
+// Replaces the 512 small pages mapping page with a single huge page,
+// if they map one physically contiguous and 2MiB aligned region.
+// The level 1 table is leaked (it belongs to the bootloader), the caller has to flush the tlb.
+fn promote_to_huge_page(
+    table: &OffsetPageTable<'static>,
+    page: Page<Size2MiB>,
+    flags: PageTableFlags,
+) -> bool {
+    let translate = |i: u64| {
+        let small_page = Page::<Size4KiB>::from_start_address(page.start_address() + i * 4096);
+        table.translate_page(small_page.unwrap()).ok()
+    };
+
+    let Some(first_frame) = translate(0) else {
+        return false;
+    };
+    let phys_start = first_frame.start_address();
+    if !phys_start.is_aligned(Size2MiB::SIZE) {
+        return false;
     }
+    let contiguous = (1..Size2MiB::SIZE / Size4KiB::SIZE)
+        .all(|i| translate(i).is_some_and(|f| f.start_address() == phys_start + i * 4096));
+    if !contiguous {
+        return false;
+    }
+
+    let Some(entry) = level_2_entry(page) else {
+        return false;
+    };
+    entry.set_addr(phys_start, flags | PageTableFlags::HUGE_PAGE);
+    true
+}
+
+fn level_2_entry(page: Page<Size2MiB>) -> Option<&'static mut PageTableEntry> {
+    let l4 = active_level_4_table();
+    let l3 = page_table_from_frame(l4[page.p4_index()].frame().ok()?);
+    let l2 = page_table_from_frame(l3[page.p3_index()].frame().ok()?);
+    Some(&mut l2[page.p2_index()])
 }
 
 impl BootInfoFrameAllocator {
-    fn initialize_free_memory() -> (Option<*mut FreeNode>, u64) {
+    fn initialize_free_memory() -> (FreeLists, u64, Range<u64>, RegionTable) {
         log::info!("Initializing physical memory allocator");
         // bootloader bug mitigation
         let l4 = get_active_l4_page_table();
@@ -179,17 +414,32 @@ impl BootInfoFrameAllocator {
             last_phys_addr.unwrap()
         );
 
+        // the dma pool is the first 2MiB aligned usable range which is large enough
+        let dma_pool_range = usable_regions()
+            .map(|r| (align_up(r.start, Size2MiB::SIZE), r.end))
+            .map(|(start, region_end)| (start..start + DMA_POOL_SIZE, region_end))
+            .find(|(pool, region_end)| {
+                pool.end <= *region_end
+                    && (pool.end <= *already_mapped_range.start()
+                        || *already_mapped_range.end() < pool.start)
+            })
+            .map_or(0..0, |(pool, _)| pool);
+
+        log::debug!(
+            "Dma pool: physical range:{:x} - {:x}",
+            dma_pool_range.start,
+            dma_pool_range.end
+        );
+
         let mut removed_page_count = 0;
 
-        let mut raw_usable_frame_addresses = crate::get_boot_info()
-            .memory_regions
-            .iter()
-            .filter(|r| r.kind == MemoryRegionKind::Usable && r.start > 0)
+        let raw_usable_frame_addresses = usable_regions()
             .map(|r| {
                 log::trace!("Memory region: {r:?}");
                 align_up(r.start, 4096)..align_down(r.end, 4096)
             })
             .flat_map(|r| r.step_by(4096))
+            .filter(|r| !dma_pool_range.contains(r))
             .filter(|r| {
                 if already_mapped_range.contains(r) {
                     removed_page_count += 1;
@@ -197,44 +447,80 @@ impl BootInfoFrameAllocator {
                 } else {
                     true
                 }
-            })
-            .peekable();
-
-        let free_memory_head = raw_usable_frame_addresses.peek().map(phys_addr_to_node_ref);
+            });
 
+        // the lists stay sorted by address
+        let numa = numa::layout();
+        let mut free_lists = FreeLists {
+            heads: [None; MAX_NODES],
+            frames: [0; MAX_NODES],
+        };
+        let mut tails: [Option<*mut FreeNode>; MAX_NODES] = [None; MAX_NODES];
         let mut frame_count = 0;
+        let mut regions = RegionTable::new();
 
-        while let Some(phys_addr) = raw_usable_frame_addresses.next() {
+        for phys_addr in raw_usable_frame_addresses {
             let node_ref = phys_addr_to_node_ref(&phys_addr);
-            let node_next_ref = raw_usable_frame_addresses.peek().map(phys_addr_to_node_ref);
-
             unsafe {
                 *node_ref = FreeNode {
-                    next: node_next_ref,
+                    next: None,
                     frame: PhysFrame::from_start_address(PhysAddr::new(phys_addr)).unwrap(),
                 };
             }
+            let node = numa.node_of_addr(phys_addr);
+            match tails[node] {
+                Some(tail) => unsafe { (*tail).next = Some(node_ref) },
+                None => free_lists.heads[node] = Some(node_ref),
+            }
+            tails[node] = Some(node_ref);
+            free_lists.frames[node] += 1;
             frame_count += 1;
+            regions.frame_added(PhysAddr::new(phys_addr));
         }
 
         log::debug!("Bootloader ram disk corruption mitigation: removed {removed_page_count} pages of ramdisk from free list");
 
-        (free_memory_head, frame_count)
+        (free_lists, frame_count, dma_pool_range, regions)
     }
 
     pub fn new() -> Self {
         println!("Initializing memory");
 
-        let (free_memory_head, frame_count) = Self::initialize_free_memory();
+        let (free_lists, frame_count, dma_pool_range, regions) = Self::initialize_free_memory();
 
         println!("{}MB available", frame_count * 4096 / 1024 / 1024);
 
         Self {
-            free_memory_head,
+            free_lists: free_lists.heads,
+            node_free_frames: free_lists.frames,
             total_frames: frame_count,
             free_frames: frame_count,
+            contiguous: ContiguousFrameAllocator::new(
+                PhysAddr::new(dma_pool_range.start),
+                (dma_pool_range.end - dma_pool_range.start) / Size4KiB::SIZE,
+            ),
+            regions,
         }
     }
+
+    // from the free list of the node, otherwise from the nearest node with free frames
+    fn allocate_frame_near(&mut self, node: usize) -> Option<PhysFrame> {
+        let node = numa::layout()
+            .nodes_by_distance(node)
+            .find(|&node| self.free_lists[node].is_some())?;
+        let head = self.free_lists[node]?;
+        self.free_lists[node] = unsafe { (*head).next };
+        self.node_free_frames[node] -= 1;
+        self.free_frames -= 1;
+        let frame = unsafe { (*head).frame };
+        self.regions.frame_allocated(frame.start_address());
+        Some(frame)
+    }
+}
+
+struct FreeLists {
+    heads: [Option<*mut FreeNode>; MAX_NODES],
+    frames: [u64; MAX_NODES],
 }
 
 unsafe impl Send for BootInfoFrameAllocator {}
@@ -242,30 +528,106 @@ unsafe impl Sync for BootInfoFrameAllocator {}
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        self.free_memory_head.map(|node| {
-            self.free_frames -= 1;
-            self.free_memory_head = unsafe { (*node).next };
-            unsafe { (*node).frame }
-        })
+        self.allocate_frame_near(numa::current_node())
+    }
+}
+
+// Contiguous mode: searches the free lists (the nearest node first) for 512 physically consecutive and 2MiB aligned
+// frames. The free lists start out sorted, so this is fast early on, but degrades with fragmentation
+// (returns None if nothing is found)
+unsafe impl FrameAllocator<Size2MiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        numa::layout()
+            .nodes_by_distance(numa::current_node())
+            .find_map(|node| self.allocate_huge_frame_on(node))
+    }
+}
+
+impl BootInfoFrameAllocator {
+    fn allocate_huge_frame_on(&mut self, node: usize) -> Option<PhysFrame<Size2MiB>> {
+        const FRAMES_PER_HUGE_PAGE: u64 = Size2MiB::SIZE / Size4KiB::SIZE;
+
+        let mut previous: Option<*mut FreeNode> = None;
+        let mut current = self.free_lists[node];
+
+        while let Some(node) = current {
+            let start = unsafe { (*node).frame.start_address() };
+            if !start.is_aligned(Size2MiB::SIZE) {
+                previous = current;
+                current = unsafe { (*node).next };
+                continue;
+            }
+
+            let mut run_end = node;
+            let mut run_length = 1;
+            while run_length < FRAMES_PER_HUGE_PAGE {
+                match unsafe { (*run_end).next } {
+                    Some(next)
+                        if unsafe { (*next).frame.start_address() }
+                            == start + run_length * Size4KiB::SIZE =>
+                    {
+                        run_end = next;
+                        run_length += 1;
+                    }
+                    _ => break,
+                }
+            }
+
+            let after_run = unsafe { (*run_end).next };
+            if run_length == FRAMES_PER_HUGE_PAGE {
+                match previous {
+                    Some(previous) => unsafe { (*previous).next = after_run },
+                    None => self.free_lists[node] = after_run,
+                }
+                self.node_free_frames[node] -= FRAMES_PER_HUGE_PAGE;
+                self.free_frames -= FRAMES_PER_HUGE_PAGE;
+                for i in 0..FRAMES_PER_HUGE_PAGE {
+                    self.regions.frame_allocated(start + i * Size4KiB::SIZE);
+                }
+                return Some(PhysFrame::from_start_address(start).unwrap());
+            }
+
+            // no frame in a too short run can start a valid run
+            previous = Some(run_end);
+            current = after_run;
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        // pushed in reverse so the small frames stay sorted at the head of the list (all are on the same node)
+        for i in (0..Size2MiB::SIZE / Size4KiB::SIZE).rev() {
+            let small_frame = PhysFrame::<Size4KiB>::from_start_address(
+                frame.start_address() + i * Size4KiB::SIZE,
+            )
+            .unwrap();
+            FrameDeallocator::<Size4KiB>::deallocate_frame(self, small_frame);
+        }
     }
 }
 
 impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
     unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let numa_node = numa::layout().node_of_addr(frame.start_address().as_u64());
         let node = phys_addr_to_node_ref(&frame.start_address().as_u64());
         unsafe {
             *node = FreeNode {
-                next: self.free_memory_head,
+                next: self.free_lists[numa_node],
                 frame,
             };
         }
-        self.free_memory_head = Some(node);
+        self.free_lists[numa_node] = Some(node);
+        self.node_free_frames[numa_node] += 1;
         self.free_frames += 1;
+        self.regions.frame_freed(frame.start_address());
     }
 }
 
 pub struct Memory {
     kernel_page_table: OffsetPageTable<'static>,
+    next_mmio_addr: u64,
     frame_allocator: BootInfoFrameAllocator,
 }
 
@@ -276,10 +638,21 @@ impl Memory {
         let frame_allocator = BootInfoFrameAllocator::new();
         Self {
             kernel_page_table,
+            next_mmio_addr: v::KERNEL_MMIO_START,
             frame_allocator,
         }
     }
 
+    // frames in the per core caches are counted as used
+    pub const fn free_frames(&self) -> u64 {
+        self.frame_allocator.free_frames
+    }
+
+    // indexed by numa node
+    pub fn node_free_frames(&self) -> &[u64] {
+        &self.frame_allocator.node_free_frames[..numa::layout().node_count()]
+    }
+
     pub const fn get_memory_utilization(&self) -> (u64, u64) {
         (
             (self.frame_allocator.total_frames - self.frame_allocator.free_frames),
@@ -297,6 +670,124 @@ impl Memory {
             (util.1 - util.0) * 4096 / 1024 / 1024,
             util.1 * 4096 / 1024 / 1024,
         );
+        if self.node_free_frames().len() > 1 {
+            for (node, free_frames) in self.node_free_frames().iter().enumerate() {
+                log::log!(
+                    level,
+                    "  node {node}: {}MB free",
+                    free_frames * 4096 / 1024 / 1024
+                );
+            }
+        }
+        log::log!(
+            level,
+            "Dma pool {}/{} frames free",
+            self.dma_pool_free_frames(),
+            self.frame_allocator.contiguous.total_frames()
+        );
+    }
+
+    // table of all memory regions reported by the bootloader, with the utilization of the usable ones
+    pub fn log_memory_map(&self, level: log::Level) {
+        log::log!(level, "Memory map:");
+        let tracked = self.frame_allocator.regions.get();
+        for r in crate::get_boot_info().memory_regions.iter() {
+            let size_kib = (r.end - r.start) / 1024;
+            let kind = region_kind_name(r.kind);
+            match tracked.iter().find(|t| t.start == r.start) {
+                Some(stats) => log::log!(
+                    level,
+                    "  {:#014x} - {:#014x} {:>10}KiB {:<16} {:>8}/{:<8} frames used ({}%)",
+                    r.start,
+                    r.end,
+                    size_kib,
+                    kind,
+                    stats.used_frames(),
+                    stats.total_frames,
+                    (stats.used_frames() * 100)
+                        .checked_div(stats.total_frames)
+                        .unwrap_or(0),
+                ),
+                None => log::log!(
+                    level,
+                    "  {:#014x} - {:#014x} {:>10}KiB {:<16}",
+                    r.start,
+                    r.end,
+                    size_kib,
+                    kind,
+                ),
+            }
+        }
+    }
+
+    // physically contiguous frames from the dma pool (not mapped, use phys_to_virt to access them), see dma::alloc
+    pub fn allocate_contiguous(&mut self, frames: u64, alignment: u64) -> Option<PhysFrame> {
+        self.frame_allocator
+            .contiguous
+            .allocate_contiguous(frames, alignment)
+    }
+
+    pub unsafe fn deallocate_contiguous(&mut self, first: PhysFrame, frames: u64) {
+        self.frame_allocator
+            .contiguous
+            .deallocate_contiguous(first, frames);
+    }
+
+    pub fn dma_pool_free_frames(&self) -> u64 {
+        self.frame_allocator.contiguous.free_frames()
+    }
+
+    // takes the frame from the per core cache (refilled from the global allocator) if the core local data is initialized
+    pub fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        with_frame_cache(|cache| match cache {
+            Some(cache) => cache.pop().or_else(|| {
+                cache.refill(&mut self.frame_allocator);
+                cache.pop()
+            }),
+            None => FrameAllocator::<Size4KiB>::allocate_frame(&mut self.frame_allocator),
+        })
+    }
+
+    pub unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        with_frame_cache(|cache| match cache {
+            Some(cache) => {
+                if cache.is_full() {
+                    cache.flush(&mut self.frame_allocator);
+                }
+                cache.push(frame);
+            }
+            None => {
+                FrameDeallocator::<Size4KiB>::deallocate_frame(&mut self.frame_allocator, frame)
+            }
+        });
+    }
+
+    fn refill_frame_cache(&mut self, cache: &mut FrameCache) {
+        cache.refill(&mut self.frame_allocator);
+    }
+
+    fn flush_frame_cache(&mut self, cache: &mut FrameCache) {
+        cache.flush(&mut self.frame_allocator);
+    }
+
+    // bypasses the per core cache (for comparison in the frame allocation stress test)
+    pub fn allocate_frame_uncached(&mut self) -> Option<PhysFrame> {
+        FrameAllocator::<Size4KiB>::allocate_frame(&mut self.frame_allocator)
+    }
+
+    pub unsafe fn deallocate_frame_uncached(&mut self, frame: PhysFrame) {
+        FrameDeallocator::<Size4KiB>::deallocate_frame(&mut self.frame_allocator, frame);
+    }
+
+    // a frame of unmapped ram, once its PendingShootdown is flushed
+    pub unsafe fn deallocate_unmapped_frame(&mut self, start: PhysAddr, size: u64) {
+        if size == Size2MiB::SIZE {
+            let frame = PhysFrame::<Size2MiB>::from_start_address(start).unwrap();
+            FrameDeallocator::<Size2MiB>::deallocate_frame(&mut self.frame_allocator, frame);
+        } else {
+            let frame = PhysFrame::<Size4KiB>::from_start_address(start).unwrap();
+            FrameDeallocator::<Size4KiB>::deallocate_frame(&mut self.frame_allocator, frame);
+        }
     }
 
     pub fn create_user_page_table(&mut self) -> OffsetPageTable<'static> {
@@ -312,80 +803,275 @@ impl Memory {
         switch_l4_page_table(&mut self.kernel_page_table);
     }
 
-    pub fn map_ram_kernel(&mut self, page: Page, flags: PageTableFlags) -> PhysFrame {
+    pub fn kernel_page_table_frame(&mut self) -> PhysFrame {
+        frame_from_page_table(self.kernel_page_table.level_4_table())
+    }
+
+    pub fn map_ram_kernel<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> PhysFrame<S>
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+        BootInfoFrameAllocator: FrameAllocator<S> + FrameDeallocator<S>,
+    {
+        self.try_map_ram_kernel(page, flags).unwrap_or_else(|e| {
+            log::error!("{e:?}: while allocating {page:?} with flags({flags:?}) for kernel");
+            self.log_memory_utilization(log::Level::Error);
+            panic!("Out of memory");
+        })
+    }
+
+    pub fn try_map_ram_kernel<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> Result<PhysFrame<S>, MapError>
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+        BootInfoFrameAllocator: FrameAllocator<S> + FrameDeallocator<S>,
+    {
         ass!((v::KERNEL_START..v::KERNEL_END).contains(&page.start_address().as_u64()));
-        let frame = self
-            .frame_allocator
-            .allocate_frame()
-            .or_else(|| {
-                log::error!(
-                    "Out of memory: while allocating {page:?} with flags({flags:?}) for kernel"
-                );
+        let frame = if S::SIZE == Size4KiB::SIZE {
+            self.allocate_frame()
+                .map(|frame| PhysFrame::<S>::from_start_address(frame.start_address()).unwrap())
+        } else {
+            FrameAllocator::<S>::allocate_frame(&mut self.frame_allocator)
+        };
+        let frame = frame.ok_or(MapError::OutOfMemory)?;
+        if let Err(e) = unsafe { self.try_map_frame(page, flags | PageTableFlags::PRESENT, frame) }
+        {
+            unsafe { FrameDeallocator::<S>::deallocate_frame(&mut self.frame_allocator, frame) };
+            return Err(e);
+        }
+        Ok(frame)
+    }
+
+    // maps [start, start + size) (page aligned) with fresh ram,
+    // using huge pages for every fully covered 2MiB chunk if enough contiguous memory is available
+    pub fn map_ram_kernel_region(&mut self, start: VirtAddr, size: u64, flags: PageTableFlags) {
+        self.try_map_ram_kernel_region(start, size, flags)
+            .unwrap_or_else(|e| {
+                log::error!("{e:?}: while mapping {size:#x} bytes at {start:p} for kernel");
                 self.log_memory_utilization(log::Level::Error);
                 panic!("Out of memory");
-            })
-            .unwrap();
-        unsafe { self.map_frame(page, flags | PageTableFlags::PRESENT, frame) };
-        frame
+            });
+    }
+
+    // on failure the already mapped part of the region is freed again
+    pub fn try_map_ram_kernel_region(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapError> {
+        ass!(start.is_aligned(Size4KiB::SIZE) && size % Size4KiB::SIZE == 0);
+        if size / Size4KiB::SIZE > self.frame_allocator.free_frames {
+            return Err(MapError::OutOfMemory);
+        }
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            let result = if addr.is_aligned(Size2MiB::SIZE) && end - addr >= Size2MiB::SIZE {
+                let page = Page::<Size2MiB>::from_start_address(addr).unwrap();
+                self.try_map_ram_kernel(page, flags).map(|_| Size2MiB::SIZE)
+            } else {
+                Err(MapError::OutOfMemory)
+            };
+            let result = result.or_else(|_| {
+                let page = Page::<Size4KiB>::from_start_address(addr).unwrap();
+                self.try_map_ram_kernel(page, flags).map(|_| Size4KiB::SIZE)
+            });
+            match result {
+                Ok(step) => addr += step,
+                Err(e) => {
+                    // the pages were never handed out, no other core used them
+                    if let Some(pending) =
+                        unsafe { self.unmap_ram_kernel_region(start, addr - start) }
+                    {
+                        unsafe { pending.discard(self) };
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Counterpart of map_ram_kernel_region, frees the backing ram of [start, start + size).
+    // Returns None without unmapping anything if the range would split a huge page
+    pub unsafe fn unmap_ram_kernel_region(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+    ) -> Option<PendingShootdown> {
+        ass!(start.is_aligned(Size4KiB::SIZE) && size % Size4KiB::SIZE == 0);
+        let end = start + size;
+        let is_huge = |addr: VirtAddr| {
+            matches!(
+                get_active_l4_page_table().translate(addr),
+                TranslateResult::Mapped {
+                    frame: MappedFrame::Size2MiB(_),
+                    ..
+                }
+            )
+        };
+        let mut addr = start;
+        while addr < end {
+            let step = if is_huge(addr) {
+                Size2MiB::SIZE
+            } else {
+                Size4KiB::SIZE
+            };
+            if !addr.is_aligned(step) || end - addr < step {
+                return None;
+            }
+            addr += step;
+        }
+
+        let mut pending = PendingShootdown::none();
+        let mut addr = start;
+        while addr < end {
+            if is_huge(addr) {
+                pending.merge(self.unmap_ram(Page::<Size2MiB>::from_start_address(addr).unwrap()));
+                addr += Size2MiB::SIZE;
+            } else {
+                pending.merge(self.unmap_ram(Page::<Size4KiB>::from_start_address(addr).unwrap()));
+                addr += Size4KiB::SIZE;
+            }
+        }
+        Some(pending)
     }
 
     pub fn map_ram_user(&mut self, page: Page, flags: PageTableFlags) -> PhysFrame {
+        self.try_map_ram_user(page, flags).unwrap_or_else(|e| {
+            log::error!("{e:?}: while allocating {page:?} with flags({flags:?}) for user");
+            self.log_memory_utilization(log::Level::Error);
+            panic!("Out of memory");
+        })
+    }
+
+    pub fn try_map_ram_user(
+        &mut self,
+        page: Page,
+        flags: PageTableFlags,
+    ) -> Result<PhysFrame, MapError> {
         ass!((v::USER_START..v::USER_END).contains(&page.start_address().as_u64()));
-        let frame = self
-            .frame_allocator
-            .allocate_frame()
-            .or_else(|| {
-                log::error!(
-                    "Out of memory: while allocating {page:?} with flags({flags:?}) for user"
-                );
-                self.log_memory_utilization(log::Level::Error);
-                panic!("Out of memory");
-            })
-            .unwrap();
-        unsafe {
-            self.map_frame(
-                page,
-                flags | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
-                frame,
-            );
-        };
-        frame
+        let frame = self.allocate_frame().ok_or(MapError::OutOfMemory)?;
+        let flags = flags | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT;
+        if let Err(e) = unsafe { self.try_map_frame(page, flags, frame) } {
+            unsafe { self.deallocate_frame(frame) };
+            return Err(e);
+        }
+        Ok(frame)
+    }
+
+    // Maps the physical range uncached into the kernel mmio window (never unmapped, each mapping is followed by a guard page).
+    // Use map_named_mmio to also register the mapping as a virtual memory area
+    pub fn map_mmio(&mut self, phys: PhysAddr, len: u64) -> VirtAddr {
+        let phys_start = phys.align_down(Size4KiB::SIZE);
+        let offset = phys - phys_start;
+        let size = align_up(offset + len, Size4KiB::SIZE);
+
+        let virt_start = self.next_mmio_addr;
+        ass!(
+            virt_start + size <= v::KERNEL_MMIO_END,
+            "mmio window exhausted"
+        );
+        self.next_mmio_addr += size + Size4KiB::SIZE;
+
+        for i in (0..size).step_by(Size4KiB::SIZE as usize) {
+            let page = Page::<Size4KiB>::from_start_address(VirtAddr::new(virt_start + i)).unwrap();
+            let frame = PhysFrame::<Size4KiB>::from_start_address(phys_start + i).unwrap();
+            unsafe { self.map_frame(page, MMIO_FLAGS, frame) };
+        }
+        log::debug!("Mapped mmio {phys:p} ({len:#x} bytes) to {virt_start:#x}");
+        VirtAddr::new(virt_start + offset)
+    }
+
+    pub unsafe fn map_frame<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+        frame: PhysFrame<S>,
+    ) where
+        OffsetPageTable<'static>: Mapper<S>,
+    {
+        unsafe { self.try_map_frame(page, flags, frame) }.unwrap_or_else(|e| {
+            panic!(
+                "Unable to map page:{:?} to frame:{:?}:\n{:?}",
+                page, frame, e
+            )
+        });
     }
 
-    pub unsafe fn map_frame(&mut self, page: Page, flags: PageTableFlags, frame: PhysFrame) {
+    pub unsafe fn try_map_frame<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+        frame: PhysFrame<S>,
+    ) -> Result<(), MapError>
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+    {
         unsafe {
-            get_active_l4_page_table()
-                .map_to(page, frame, flags, &mut self.frame_allocator)
-                .unwrap_or_else(|e| {
-                    panic!(
-                        "Unable to map page:{:?} to frame:{:?}:\n{:?}",
-                        page, frame, e
-                    )
-                })
-                .flush();
+            Mapper::<S>::map_to(
+                &mut get_active_l4_page_table(),
+                page,
+                frame,
+                flags,
+                &mut self.frame_allocator,
+            )?
+            .flush();
         };
+        Ok(())
     }
 
-    pub unsafe fn change_flags(&mut self, page: Page, flags: PageTableFlags) {
+    // the pending shootdown has to be flushed after MEMORY is unlocked (as for unmap)
+    pub unsafe fn change_flags<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> PendingShootdown
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+    {
         let _ = self;
-        get_active_l4_page_table()
-            .update_flags(page, flags)
+        Mapper::<S>::update_flags(&mut get_active_l4_page_table(), page, flags)
             .unwrap_or_else(|e| panic!("Unable to change flags of page:{:?}:\n{:?}", page, e))
             .flush();
+        shootdown_if_shared(page)
     }
 
-    pub unsafe fn unmap_ram(&mut self, page: Page) {
-        let frame = self.unmap(page);
-        self.frame_allocator.deallocate_frame(frame);
+    // The frame of a kernel page is freed by the flush, once no other core has the old translation anymore
+    // (nothing may access the page anymore, and it can't be mapped again before the flush by the caller).
+    // The frame of a user page is free right away
+    pub unsafe fn unmap_ram<S: PageSize>(&mut self, page: Page<S>) -> PendingShootdown
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+        BootInfoFrameAllocator: FrameDeallocator<S>,
+    {
+        let (frame, mut pending) = self.unmap(page);
+        if pending.is_empty() {
+            FrameDeallocator::<S>::deallocate_frame(&mut self.frame_allocator, frame);
+        } else {
+            pending.add_frame(frame);
+        }
+        pending
     }
 
-    pub unsafe fn unmap(&mut self, page: Page) -> PhysFrame {
+    // Only flushes the local tlb. The other cores wait for MEMORY with interrupts disabled (e.g. page faults),
+    // so they could not acknowledge a shootdown while it is locked: the caller flushes the returned one afterwards
+    pub unsafe fn unmap<S: PageSize>(&mut self, page: Page<S>) -> (PhysFrame<S>, PendingShootdown)
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+    {
         let _ = self;
-        let (frame, flusher) = get_active_l4_page_table()
-            .unmap(page)
+        let (frame, flusher) = Mapper::<S>::unmap(&mut get_active_l4_page_table(), page)
             .unwrap_or_else(|e| panic!("Unable to unmap page:{:?}:\n{:?}", page, e));
         flusher.flush();
-        frame
+        (frame, shootdown_if_shared(page))
     }
 
     pub fn log_page_table_info(&mut self, level: log::Level) {
@@ -410,9 +1096,373 @@ impl Memory {
     }
 }
 
+// only the kernel part of the address space is shared between cores,
+// user page tables are only active on one core and get flushed with every cr3 switch
+fn shootdown_if_shared<S: PageSize>(page: Page<S>) -> PendingShootdown {
+    let mut pending = PendingShootdown::none();
+    if (v::KERNEL_START..v::KERNEL_END).contains(&page.start_address().as_u64()) {
+        pending.add(page.start_address()..page.start_address() + page.size());
+    }
+    pending
+}
+
 unsafe impl Send for Memory {}
 unsafe impl Sync for Memory {}
 
 lazy_static! {
-    pub static ref MEMORY: Mutex<Memory> = Mutex::new(Memory::new());
+    pub static ref MEMORY: TrackedMutex<Memory> = lock_debug::tracked("MEMORY", Memory::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    // mapped when registered
+    Ram,
+    // zeroed ram mapped on first access by the page fault handler
+    DemandPaged,
+    // fixed physical range (framebuffer, mmio, ...)
+    Physical(PhysAddr),
+    // never mapped, every access is a bug
+    Guard,
+}
+
+#[derive(Debug, Clone)]
+pub struct VirtualMemoryArea {
+    pub name: &'static str,
+    pub range: Range<u64>,
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl VirtualMemoryArea {
+    pub fn new(
+        name: &'static str,
+        range: Range<u64>,
+        flags: PageTableFlags,
+        backing: Backing,
+    ) -> Self {
+        Self {
+            name,
+            range,
+            flags,
+            backing,
+        }
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.range.start < other.range.end && other.range.start < self.range.end
+    }
+}
+
+impl fmt::Display for VirtualMemoryArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:#x}..{:#x}) {: <16} {:?} {:?}",
+            self.range.start, self.range.end, self.name, self.backing, self.flags
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    OutOfMemory,
+    AlreadyMapped,
+    ParentEntryHugePage,
+}
+
+impl<S: PageSize> From<MapToError<S>> for MapError {
+    fn from(error: MapToError<S>) -> Self {
+        match error {
+            MapToError::FrameAllocationFailed => Self::OutOfMemory,
+            MapToError::PageAlreadyMapped(_) => Self::AlreadyMapped,
+            MapToError::ParentEntryHugePage => Self::ParentEntryHugePage,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OverlapError {
+    pub new: VirtualMemoryArea,
+    pub existing: VirtualMemoryArea,
+}
+
+// regions of one address space, sorted by start address and without overlaps
+#[derive(Debug, Default)]
+pub struct AddressSpace {
+    areas: Vec<VirtualMemoryArea>,
+}
+
+impl AddressSpace {
+    pub const fn new() -> Self {
+        Self { areas: Vec::new() }
+    }
+
+    pub fn register(&mut self, area: VirtualMemoryArea) -> Result<(), OverlapError> {
+        ass!(area.range.start < area.range.end);
+        let index = self
+            .areas
+            .partition_point(|a| a.range.start < area.range.start);
+        let neighbours = index.saturating_sub(1)..(index + 1).min(self.areas.len());
+        if let Some(existing) = self.areas[neighbours].iter().find(|a| a.overlaps(&area)) {
+            return Err(OverlapError {
+                new: area,
+                existing: existing.clone(),
+            });
+        }
+        self.areas.insert(index, area);
+        Ok(())
+    }
+
+    pub fn find(&self, addr: u64) -> Option<&VirtualMemoryArea> {
+        let index = self.areas.partition_point(|a| a.range.start <= addr);
+        let area = self.areas.get(index.checked_sub(1)?)?;
+        area.range.contains(&addr).then_some(area)
+    }
+
+    pub fn areas(&self) -> &[VirtualMemoryArea] {
+        &self.areas
+    }
+
+    pub fn log(&self, level: log::Level) {
+        for area in &self.areas {
+            log::log!(level, "  {area}");
+        }
+    }
+}
+
+struct AddressSpaces {
+    kernel: AddressSpace,
+    // indexed by the physical address of the l4 page table
+    user: BTreeMap<u64, AddressSpace>,
+}
+
+// separate from MEMORY, since registering allocates and growing the kernel heap locks MEMORY
+static ADDRESS_SPACES: TrackedMutex<AddressSpaces> = lock_debug::tracked(
+    "address spaces",
+    AddressSpaces {
+        kernel: AddressSpace::new(),
+        user: BTreeMap::new(),
+    },
+);
+
+fn is_kernel_addr(addr: u64) -> bool {
+    (v::KERNEL_START..v::KERNEL_END).contains(&addr)
+}
+
+// kernel areas are shared, user areas are registered in the address space of the active page table
+pub fn register_area(area: VirtualMemoryArea) -> Result<(), OverlapError> {
+    let mut spaces = ADDRESS_SPACES.lock();
+    if is_kernel_addr(area.range.start) {
+        ass!(area.range.end <= v::KERNEL_END);
+        spaces.kernel.register(area)
+    } else {
+        ass!(area.range.end <= v::USER_END);
+        spaces
+            .user
+            .entry(active_level_4_table_phys_addr())
+            .or_default()
+            .register(area)
+    }
+}
+
+pub fn remove_user_address_space(l4_table: &mut OffsetPageTable) {
+    let l4_table_phys_addr = frame_from_page_table(l4_table.level_4_table()).start_address();
+    ADDRESS_SPACES
+        .lock()
+        .user
+        .remove(&l4_table_phys_addr.as_u64());
+}
+
+// usable in the page fault handler
+pub fn find_area(addr: VirtAddr) -> Option<VirtualMemoryArea> {
+    let spaces = crate::tlb::lock_servicing_shootdowns(&ADDRESS_SPACES);
+    let addr = addr.as_u64();
+    if is_kernel_addr(addr) {
+        spaces.kernel.find(addr).cloned()
+    } else {
+        let user = spaces.user.get(&active_level_4_table_phys_addr())?;
+        user.find(addr).cloned()
+    }
+}
+
+pub fn log_address_space(level: log::Level) {
+    let spaces = ADDRESS_SPACES.lock();
+    log::log!(level, "Kernel address space:");
+    spaces.kernel.log(level);
+    if let Some(user) = spaces.user.get(&active_level_4_table_phys_addr()) {
+        log::log!(level, "User address space:");
+        user.log(level);
+    }
+}
+
+// needs the heap, called once by the bsp
+pub fn register_boot_areas() {
+    let boot_info = crate::get_boot_info();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    if let Some(frame_buffer) = boot_info.framebuffer.as_ref() {
+        let start = frame_buffer.buffer().as_ptr() as u64;
+        let phys = get_active_l4_page_table().translate_addr(VirtAddr::new(start));
+        register_area(VirtualMemoryArea::new(
+            "frame buffer",
+            start..align_up(start + frame_buffer.buffer().len() as u64, 4096),
+            flags | PageTableFlags::WRITE_THROUGH | PageTableFlags::NO_CACHE,
+            Backing::Physical(phys.unwrap()),
+        ))
+        .unwrap();
+    }
+    if let Some(ram_disk_addr) = boot_info.ramdisk_addr.as_ref() {
+        let phys = get_active_l4_page_table().translate_addr(VirtAddr::new(*ram_disk_addr));
+        register_area(VirtualMemoryArea::new(
+            "ram disk",
+            *ram_disk_addr..align_up(*ram_disk_addr + boot_info.ramdisk_len, 4096),
+            PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE,
+            Backing::Physical(phys.unwrap()),
+        ))
+        .unwrap();
+    }
+    register_area(VirtualMemoryArea::new(
+        "kernel heap",
+        crate::kaslr::layout().heap_start()..v::KERNEL_AP_STACKS,
+        flags,
+        Backing::Ram,
+    ))
+    .unwrap();
+}
+
+const FRAME_CACHE_CAPACITY: usize = 32;
+const FRAME_CACHE_BATCH: usize = FRAME_CACHE_CAPACITY / 2;
+
+// Per core stack of free frames (in CoreLocalData), refilled and flushed in batches from the global allocator,
+// so most frame allocations don't need the MEMORY lock. Cached frames count as used in the utilization
+#[derive(Default)]
+pub struct FrameCache {
+    frames: [u64; FRAME_CACHE_CAPACITY],
+    len: usize,
+}
+
+impl fmt::Debug for FrameCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameCache")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl FrameCache {
+    fn pop(&mut self) -> Option<PhysFrame> {
+        self.len = self.len.checked_sub(1)?;
+        Some(PhysFrame::from_start_address(PhysAddr::new(self.frames[self.len])).unwrap())
+    }
+
+    fn push(&mut self, frame: PhysFrame) {
+        ass!(!self.is_full());
+        self.frames[self.len] = frame.start_address().as_u64();
+        self.len += 1;
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == FRAME_CACHE_CAPACITY
+    }
+
+    // with frames of the node of the current core
+    fn refill(&mut self, allocator: &mut BootInfoFrameAllocator) {
+        let node = numa::current_node();
+        while self.len < FRAME_CACHE_BATCH {
+            let Some(frame) = allocator.allocate_frame_near(node) else {
+                break;
+            };
+            self.push(frame);
+        }
+    }
+
+    fn flush(&mut self, allocator: &mut BootInfoFrameAllocator) {
+        while self.len > FRAME_CACHE_BATCH {
+            let frame = self.pop().unwrap();
+            unsafe { FrameDeallocator::<Size4KiB>::deallocate_frame(allocator, frame) };
+        }
+    }
+}
+
+// interrupts are disabled while the cache is used, so interrupt handlers can allocate frames as well
+fn with_frame_cache<R>(f: impl FnOnce(Option<&mut FrameCache>) -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        f(crate::smp::try_get_cld().map(|cld| &mut cld.frame_cache))
+    })
+}
+
+// only locks MEMORY if the cache of the current core is empty
+pub fn allocate_frame() -> Option<PhysFrame> {
+    with_frame_cache(|cache| match cache {
+        Some(cache) => cache.pop().or_else(|| {
+            crate::tlb::lock_servicing_shootdowns(&MEMORY).refill_frame_cache(cache);
+            cache.pop()
+        }),
+        None => MEMORY.lock().allocate_frame_uncached(),
+    })
+}
+
+// only locks MEMORY if the cache of the current core is full
+pub unsafe fn deallocate_frame(frame: PhysFrame) {
+    with_frame_cache(|cache| match cache {
+        Some(cache) => {
+            if cache.is_full() {
+                crate::tlb::lock_servicing_shootdowns(&MEMORY).flush_frame_cache(cache);
+            }
+            cache.push(frame);
+        }
+        None => MEMORY.lock().deallocate_frame_uncached(frame),
+    });
+}
+
+// with the modified PAT NO_CACHE alone selects uncached (WRITE_THROUGH | NO_CACHE would be write combining)
+const MMIO_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+pub fn map_named_mmio(name: &'static str, phys: PhysAddr, len: u64) -> VirtAddr {
+    let virt = MEMORY.lock().map_mmio(phys, len);
+    let virt_start = virt.align_down(Size4KiB::SIZE).as_u64();
+    register_area(VirtualMemoryArea::new(
+        name,
+        virt_start..align_up(virt.as_u64() + len, Size4KiB::SIZE),
+        MMIO_FLAGS,
+        Backing::Physical(phys.align_down(Size4KiB::SIZE)),
+    ))
+    .unwrap();
+    virt
+}
+
+// Maps a zeroed frame if addr lies in a demand paged area of the active address space.
+// Returns false if the fault has to be handled otherwise
+pub fn handle_demand_paging_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if error_code.intersects(
+        PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::INSTRUCTION_FETCH,
+    ) {
+        return false;
+    }
+    let Some(area) = find_area(addr) else {
+        return false;
+    };
+    if area.backing != Backing::DemandPaged {
+        return false;
+    }
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let mut memory = crate::tlb::lock_servicing_shootdowns(&MEMORY);
+    let result = if is_kernel_addr(addr.as_u64()) {
+        memory.try_map_ram_kernel(page, area.flags)
+    } else {
+        memory.try_map_ram_user(page, area.flags)
+    };
+    // the fault is then handled like an access to unmapped memory (aborts the application)
+    if let Err(e) = result {
+        log::error!("{e:?}: while demand paging {page:?} of {}", area.name);
+        return false;
+    }
+    crate::user_access::user_access(|| unsafe {
+        page.start_address().as_mut_ptr::<u8>().write_bytes(0, 4096);
+    });
+    true
 }
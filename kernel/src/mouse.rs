@@ -0,0 +1,102 @@
+use ps2_8042::{mouse::Decoder, Ps2Error};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{
+    apic, get_boot_info,
+    input::{Events, Subscribers},
+    interrupts, ioapic,
+    ps2::{self, CONTROLLER},
+};
+
+pub use ps2_8042::mouse::{MouseButtons, MouseEvent};
+
+const MOUSE_ISA_IRQ: u8 = 12;
+
+// The mouse movement applied to a pointer on the screen (clamped to the frame buffer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerEvent {
+    pub x: u32,
+    pub y: u32,
+    pub dx: i16,
+    pub dy: i16,
+    pub buttons: MouseButtons,
+    // the buttons whose state changed with this event
+    pub changed: MouseButtons,
+}
+
+struct Pointer {
+    decoder: Decoder,
+    x: u32,
+    y: u32,
+    buttons: MouseButtons,
+}
+
+// locked with interrupts disabled
+static POINTER: Mutex<Pointer> = Mutex::new(Pointer {
+    decoder: Decoder::new(),
+    x: 0,
+    y: 0,
+    buttons: MouseButtons {
+        left: false,
+        right: false,
+        middle: false,
+    },
+});
+static SUBSCRIBERS: Subscribers<PointerEvent> = Subscribers::new();
+
+// needs to be called once by the bsp (after ioapic::init), fails if there is no ps/2 mouse
+pub fn init() -> Result<(), Ps2Error> {
+    without_interrupts(|| CONTROLLER.lock().init_mouse())?;
+    let vector = interrupts::allocate_vector("ps/2 mouse").unwrap();
+    interrupts::register_irq(vector, "ps/2 mouse", ps2::interrupt).unwrap();
+    let gsi = ioapic::route_isa_irq(MOUSE_ISA_IRQ, vector, apic::interrupt_destination()).unwrap();
+    log::info!("PS/2 mouse initialized (gsi {gsi}, vector {vector:#x})");
+    Ok(())
+}
+
+fn screen_size() -> (u32, u32) {
+    get_boot_info().framebuffer.as_ref().map_or((1, 1), |fb| {
+        let info = fb.info();
+        (info.width as u32, info.height as u32)
+    })
+}
+
+fn move_by(position: u32, delta: i16, size: u32) -> u32 {
+    position
+        .saturating_add_signed(i32::from(delta))
+        .min(size.saturating_sub(1))
+}
+
+// decodes one byte of the mouse (called by the interrupt, the tests inject packets with it)
+pub fn handle_byte(byte: u8) {
+    let event = without_interrupts(|| {
+        let mut pointer = POINTER.lock();
+        let event = pointer.decoder.feed(byte)?;
+        let (width, height) = screen_size();
+        pointer.x = move_by(pointer.x, event.dx, width);
+        pointer.y = move_by(pointer.y, event.dy, height);
+        let previous = core::mem::replace(&mut pointer.buttons, event.buttons);
+        Some(PointerEvent {
+            x: pointer.x,
+            y: pointer.y,
+            dx: event.dx,
+            dy: event.dy,
+            buttons: event.buttons,
+            changed: MouseButtons {
+                left: previous.left != event.buttons.left,
+                right: previous.right != event.buttons.right,
+                middle: previous.middle != event.buttons.middle,
+            },
+        })
+    });
+    if let Some(event) = event {
+        SUBSCRIBERS.publish(event);
+    }
+}
+
+pub type PointerEvents = Events<PointerEvent>;
+
+pub fn subscribe() -> PointerEvents {
+    SUBSCRIBERS.subscribe()
+}
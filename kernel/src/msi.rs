@@ -0,0 +1,132 @@
+use alloc::vec::Vec;
+use pci_config::{
+    msi::{self as pci_msi, MsiMessage, MsixInfo, CAPABILITY_MSI, CAPABILITY_MSIX},
+    Bar, PciAddress,
+};
+use port_io::VolatileMmio;
+use spin::Mutex;
+use x86_64::{instructions::interrupts::without_interrupts, PhysAddr};
+
+use crate::{
+    interrupts::{self, IrqError, IrqHandler},
+    pci,
+};
+
+// Message signaled interrupts: the device writes the message to the local apic of the destination core
+// instead of raising a (shared) intx line, which would have to be routed through the io apic
+const MSI_ADDRESS_BASE: u64 = 0xFEE0_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+    NoVectorAvailable,
+    Irq(IrqError),
+    NoCapability,
+    NoSuchEntry(u16),
+    TableNotInMemory,
+}
+
+impl From<IrqError> for MsiError {
+    fn from(err: IrqError) -> Self {
+        Self::Irq(err)
+    }
+}
+
+// fixed delivery of vector to the core with apic_id, edge triggered
+pub const fn message(vector: u8, apic_id: u8) -> MsiMessage {
+    MsiMessage {
+        address: MSI_ADDRESS_BASE | (apic_id as u64) << 12,
+        data: vector as u32,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiVector {
+    pub vector: u8,
+    pub message: MsiMessage,
+}
+
+// Allocates a vector with handler (see interrupts::register_irq) and the message which triggers it
+pub fn allocate(
+    name: &'static str,
+    handler: IrqHandler,
+    apic_id: u8,
+) -> Result<MsiVector, MsiError> {
+    let vector = interrupts::allocate_vector(name).ok_or(MsiError::NoVectorAvailable)?;
+    if let Err(err) = interrupts::register_irq(vector, name, handler) {
+        interrupts::unregister_irq(vector).unwrap();
+        return Err(err.into());
+    }
+    Ok(MsiVector {
+        vector,
+        message: message(vector, apic_id),
+    })
+}
+
+// the device has to be disabled first (see disable)
+pub fn free(msi: MsiVector) -> Result<(), MsiError> {
+    Ok(interrupts::unregister_irq(msi.vector)?)
+}
+
+// Uses msi (a single message) instead of intx for the device
+pub fn enable_msi(address: PciAddress, msi: &MsiVector) -> Result<(), MsiError> {
+    let device = pci::device(address);
+    let offset = device
+        .find_capability(CAPABILITY_MSI)
+        .ok_or(MsiError::NoCapability)?;
+    device.disable_intx();
+    pci_msi::enable_msi(&device, offset, msi.message);
+    Ok(())
+}
+
+// msi-x tables that are already mapped
+static MSIX_TABLES: Mutex<Vec<(PciAddress, VolatileMmio)>> = Mutex::new(Vec::new());
+
+fn msix_table(address: PciAddress, info: MsixInfo) -> Result<VolatileMmio, MsiError> {
+    without_interrupts(|| {
+        let mut tables = MSIX_TABLES.lock();
+        if let Some(&(_, table)) = tables.iter().find(|(a, _)| *a == address) {
+            return Ok(table);
+        }
+        let Some(Bar::Memory { address: bar, .. }) = pci::device(address).bar(info.table_bar)
+        else {
+            return Err(MsiError::TableNotInMemory);
+        };
+        let virt = crate::memory::map_named_mmio(
+            "msi-x table",
+            PhysAddr::new(bar + u64::from(info.table_offset)),
+            u64::from(info.table_size) * 16,
+        );
+        let table = unsafe { VolatileMmio::new(virt.as_mut_ptr()) };
+        tables.push((address, table));
+        Ok(table)
+    })
+}
+
+// Programs entry of the msi-x table with the message and enables msi-x for the device
+// (can be called once for every entry the driver uses)
+pub fn enable_msix(address: PciAddress, entry: u16, msi: &MsiVector) -> Result<(), MsiError> {
+    let device = pci::device(address);
+    let offset = device
+        .find_capability(CAPABILITY_MSIX)
+        .ok_or(MsiError::NoCapability)?;
+    let info = MsixInfo::read(&device, offset);
+    if entry >= info.table_size {
+        return Err(MsiError::NoSuchEntry(entry));
+    }
+    let table = msix_table(address, info)?;
+    pci_msi::write_msix_entry(&table, entry, msi.message, false);
+    device.disable_intx();
+    pci_msi::enable_msix(&device, offset);
+    Ok(())
+}
+
+// stops msi and msi-x of the device
+pub fn disable(address: PciAddress) {
+    let device = pci::device(address);
+    if let Some(offset) = device.find_capability(CAPABILITY_MSI) {
+        pci_msi::disable_msi(&device, offset);
+    }
+    if let Some(offset) = device.find_capability(CAPABILITY_MSIX) {
+        pci_msi::disable_msix(&device, offset);
+    }
+}
@@ -0,0 +1,337 @@
+use core::{
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+
+use alloc::{format, sync::Arc, vec::Vec};
+use pci_config::{Bar, PciAddress};
+use port_io::{Mmio32, VolatileMmio};
+use smoltcp::wire::EthernetAddress;
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+use super::NetDevice;
+use crate::{
+    dma::{self, DmaBuffer},
+    memory, pci, sched,
+};
+
+// Intel 8254x gigabit ethernet (the default network card of qemu). Polled by the network stack, its interrupts
+// stay masked. Every descriptor has a buffer of its own, frames are copied in and out of them
+const VENDOR_INTEL: u16 = 0x8086;
+// 82540EM (qemu e1000) and 82545EM (qemu e1000-82545em)
+const DEVICE_IDS: [u16; 2] = [0x100E, 0x100F];
+const REGISTERS_SIZE: u64 = 0x2_0000;
+
+const CTRL: usize = 0x0000;
+const ICR: usize = 0x00C0;
+const IMC: usize = 0x00D8;
+const RCTL: usize = 0x0100;
+const TCTL: usize = 0x0400;
+const TIPG: usize = 0x0410;
+const RDBAL: usize = 0x2800;
+const RDBAH: usize = 0x2804;
+const RDLEN: usize = 0x2808;
+const RDH: usize = 0x2810;
+const RDT: usize = 0x2818;
+const TDBAL: usize = 0x3800;
+const TDBAH: usize = 0x3804;
+const TDLEN: usize = 0x3808;
+const TDH: usize = 0x3810;
+const TDT: usize = 0x3818;
+// multicast table array, 128 entries
+const MTA: usize = 0x5200;
+// receive address 0 (the mac address loaded from the eeprom)
+const RAL: usize = 0x5400;
+const RAH: usize = 0x5404;
+
+const CTRL_AUTO_SPEED: u32 = 1 << 5;
+const CTRL_SET_LINK_UP: u32 = 1 << 6;
+const CTRL_RESET: u32 = 1 << 26;
+const RCTL_ENABLE: u32 = 1 << 1;
+const RCTL_BROADCAST: u32 = 1 << 15;
+const RCTL_STRIP_CRC: u32 = 1 << 26;
+const TCTL_ENABLE: u32 = 1 << 1;
+const TCTL_PAD_SHORT: u32 = 1 << 3;
+const TCTL_COLLISION_THRESHOLD: u32 = 0x10 << 4;
+const TCTL_COLLISION_DISTANCE: u32 = 0x40 << 12;
+// inter packet gap of the 82540EM (copper)
+const TIPG_VALUE: u32 = 0xA | 0x8 << 10 | 0x6 << 20;
+const RAH_VALID: u32 = 1 << 31;
+
+const DESCRIPTOR_DONE: u8 = 1 << 0;
+const RX_END_OF_PACKET: u8 = 1 << 1;
+const TX_END_OF_PACKET: u8 = 1 << 0;
+const TX_INSERT_CRC: u8 = 1 << 1;
+const TX_REPORT_STATUS: u8 = 1 << 3;
+
+// the ring lengths in bytes have to be multiples of 128
+const DESCRIPTORS: usize = 32;
+// the default receive buffer size of RCTL
+const BUFFER_SIZE: usize = 2048;
+// ethernet header and payload, without the crc
+const MTU: usize = 1514;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct RxDescriptor {
+    buffer: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TxDescriptor {
+    buffer: u64,
+    length: u16,
+    checksum_offset: u8,
+    command: u8,
+    status: u8,
+    checksum_start: u8,
+    special: u16,
+}
+
+// a descriptor ring and the buffers of its descriptors
+struct Ring {
+    descriptors: DmaBuffer,
+    buffers: DmaBuffer,
+    // the next descriptor the driver looks at
+    next: usize,
+}
+
+impl Ring {
+    fn new() -> Self {
+        Self {
+            descriptors: dma::alloc(DESCRIPTORS * 16).expect("no dma memory for the e1000 ring"),
+            buffers: dma::alloc(DESCRIPTORS * BUFFER_SIZE)
+                .expect("no dma memory for the e1000 buffers"),
+            next: 0,
+        }
+    }
+
+    fn buffer_phys(&self, index: usize) -> u64 {
+        self.buffers.phys_at(index * BUFFER_SIZE).as_u64()
+    }
+
+    fn buffer(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers.as_mut_slice()[index * BUFFER_SIZE..][..BUFFER_SIZE]
+    }
+
+    // the device writes the descriptors, so they are only accessed with volatile reads and writes
+    fn read<T>(&self, index: usize) -> T {
+        unsafe { ptr::read_volatile(self.descriptors.as_mut_ptr::<T>().add(index)) }
+    }
+
+    fn write<T>(&self, index: usize, descriptor: T) {
+        unsafe { ptr::write_volatile(self.descriptors.as_mut_ptr::<T>().add(index), descriptor) }
+    }
+}
+
+struct Rings {
+    rx: Ring,
+    tx: Ring,
+}
+
+pub struct E1000 {
+    registers: VolatileMmio,
+    mac: [u8; 6],
+    // the device keeps using them until it is reset, so they are never freed
+    rings: Mutex<Rings>,
+}
+
+impl E1000 {
+    fn new(address: PciAddress) -> Option<Self> {
+        let location = format!(
+            "{:02x}:{:02x}.{}",
+            address.bus, address.device, address.function
+        );
+        let device = pci::device(address);
+        let Some(Bar::Memory { address: bar, .. }) = device.bar(0) else {
+            log::warn!("e1000 {location} without memory bar");
+            return None;
+        };
+        device.enable_bus_master();
+        let virt = memory::map_named_mmio("e1000", PhysAddr::new(bar), REGISTERS_SIZE);
+        let registers = unsafe { VolatileMmio::new(virt.as_mut_ptr()) };
+
+        registers.write(IMC, u32::MAX);
+        registers.write(CTRL, registers.read(CTRL) | CTRL_RESET);
+        // the reset takes about a microsecond
+        while registers.read(CTRL) & CTRL_RESET != 0 {
+            sched::sleep_ms(1);
+        }
+        registers.write(IMC, u32::MAX);
+        registers.read(ICR);
+        registers.write(
+            CTRL,
+            registers.read(CTRL) | CTRL_SET_LINK_UP | CTRL_AUTO_SPEED,
+        );
+
+        let (low, high) = (registers.read(RAL), registers.read(RAH));
+        if high & RAH_VALID == 0 {
+            log::warn!("e1000 {location} without mac address");
+            return None;
+        }
+        let mut mac = [0; 6];
+        mac[..4].copy_from_slice(&low.to_le_bytes());
+        mac[4..].copy_from_slice(&high.to_le_bytes()[..2]);
+        for entry in 0..128 {
+            registers.write(MTA + entry * 4, 0);
+        }
+
+        log::info!("e1000 {location} with mac {}", EthernetAddress(mac));
+        let e1000 = Self {
+            registers,
+            mac,
+            rings: Mutex::new(Rings {
+                rx: Ring::new(),
+                tx: Ring::new(),
+            }),
+        };
+        e1000.init_rings();
+        Some(e1000)
+    }
+
+    fn init_rings(&self) {
+        let rings = self.rings.lock();
+        for index in 0..DESCRIPTORS {
+            rings.rx.write(
+                index,
+                RxDescriptor {
+                    buffer: rings.rx.buffer_phys(index),
+                    length: 0,
+                    checksum: 0,
+                    status: 0,
+                    errors: 0,
+                    special: 0,
+                },
+            );
+            // free until the first round
+            rings.tx.write(
+                index,
+                TxDescriptor {
+                    buffer: rings.tx.buffer_phys(index),
+                    length: 0,
+                    checksum_offset: 0,
+                    command: 0,
+                    status: DESCRIPTOR_DONE,
+                    checksum_start: 0,
+                    special: 0,
+                },
+            );
+        }
+
+        let ring_length = (DESCRIPTORS * 16) as u32;
+        let rx = rings.rx.descriptors.phys().as_u64();
+        self.registers.write(RDBAL, rx as u32);
+        self.registers.write(RDBAH, (rx >> 32) as u32);
+        self.registers.write(RDLEN, ring_length);
+        self.registers.write(RDH, 0);
+        // all descriptors belong to the device
+        self.registers.write(RDT, DESCRIPTORS as u32 - 1);
+        self.registers
+            .write(RCTL, RCTL_ENABLE | RCTL_BROADCAST | RCTL_STRIP_CRC);
+
+        let tx = rings.tx.descriptors.phys().as_u64();
+        self.registers.write(TDBAL, tx as u32);
+        self.registers.write(TDBAH, (tx >> 32) as u32);
+        self.registers.write(TDLEN, ring_length);
+        self.registers.write(TDH, 0);
+        self.registers.write(TDT, 0);
+        self.registers.write(TIPG, TIPG_VALUE);
+        self.registers.write(
+            TCTL,
+            TCTL_ENABLE | TCTL_PAD_SHORT | TCTL_COLLISION_THRESHOLD | TCTL_COLLISION_DISTANCE,
+        );
+    }
+}
+
+impl NetDevice for E1000 {
+    fn name(&self) -> &'static str {
+        "e1000"
+    }
+
+    fn mac_address(&self) -> Option<[u8; 6]> {
+        Some(self.mac)
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    // skips frames with errors and frames which span several buffers (longer than the mtu)
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut rings = self.rings.lock();
+        loop {
+            let index = rings.rx.next;
+            let descriptor: RxDescriptor = rings.rx.read(index);
+            if descriptor.status & DESCRIPTOR_DONE == 0 {
+                return None;
+            }
+            let frame = (descriptor.status & RX_END_OF_PACKET != 0 && descriptor.errors == 0)
+                .then(|| rings.rx.buffer(index)[..descriptor.length.into()].to_vec());
+
+            rings.rx.write(
+                index,
+                RxDescriptor {
+                    status: 0,
+                    ..descriptor
+                },
+            );
+            // the buffer is read before the device may reuse it
+            fence(Ordering::SeqCst);
+            self.registers.write(RDT, index as u32);
+            rings.rx.next = (index + 1) % DESCRIPTORS;
+            if frame.is_some() {
+                return frame;
+            }
+        }
+    }
+
+    fn can_transmit(&self) -> bool {
+        let rings = self.rings.lock();
+        rings.tx.read::<TxDescriptor>(rings.tx.next).status & DESCRIPTOR_DONE != 0
+    }
+
+    fn transmit(&self, frame: Vec<u8>) {
+        let mut rings = self.rings.lock();
+        let index = rings.tx.next;
+        let length = frame.len().min(MTU);
+        rings.tx.buffer(index)[..length].copy_from_slice(&frame[..length]);
+        let buffer = rings.tx.buffer_phys(index);
+        rings.tx.write(
+            index,
+            TxDescriptor {
+                buffer,
+                length: length as u16,
+                checksum_offset: 0,
+                command: TX_END_OF_PACKET | TX_INSERT_CRC | TX_REPORT_STATUS,
+                status: 0,
+                checksum_start: 0,
+                special: 0,
+            },
+        );
+        rings.tx.next = (index + 1) % DESCRIPTORS;
+        // the descriptor and the buffer are written before the device sees the new tail
+        fence(Ordering::SeqCst);
+        self.registers.write(TDT, rings.tx.next as u32);
+    }
+}
+
+// Initializes all supported cards found on the pci bus
+pub fn probe() -> Vec<Arc<E1000>> {
+    pci::devices()
+        .iter()
+        .copied()
+        .filter(|&address| {
+            let device = pci::device(address);
+            device.vendor_id() == VENDOR_INTEL && DEVICE_IDS.contains(&device.device_id())
+        })
+        .filter_map(E1000::new)
+        .map(Arc::new)
+        .collect()
+}
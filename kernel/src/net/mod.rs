@@ -0,0 +1,386 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::{collections::BTreeSet, sync::Arc, vec, vec::Vec};
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet},
+    phy::{self, DeviceCapabilities, Medium},
+    socket::{dhcpv4, tcp},
+    time::Instant,
+    wire::{EthernetAddress, HardwareAddress},
+};
+use spin::Mutex;
+
+use crate::{
+    cmdline, kthread, random,
+    sched::{self, WaitQueue},
+    time::Duration,
+    timer_callbacks,
+};
+
+pub use loopback::LoopbackDevice;
+pub use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv4Cidr};
+pub use socket::{TcpListener, TcpStream, UdpSocket};
+
+mod e1000;
+mod loopback;
+mod socket;
+
+// Network stack: smoltcp on top of the network devices, every device is an interface with sockets of its own
+// (a packet leaves through the interface its socket belongs to). The socket functions (see socket.rs) block
+// the task until the poll thread made progress, it polls the interfaces after every socket call that queued
+// data and at least every POLL_INTERVAL_MS (the devices are not interrupt driven)
+const POLL_INTERVAL_MS: u64 = 10;
+// local ports of connections and of sockets bound to port 0
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+// Network hardware (or the loopback) below the stack, it sends and receives whole frames.
+// Called by the poll thread with the stack locked, so the functions must not block
+pub trait NetDevice: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // None for devices which carry ip packets without a link layer (loopback)
+    fn mac_address(&self) -> Option<[u8; 6]>;
+
+    // largest frame in bytes (including the ethernet header)
+    fn mtu(&self) -> usize;
+
+    fn receive(&self) -> Option<Vec<u8>>;
+
+    // whether a frame can be queued right now
+    fn can_transmit(&self) -> bool;
+
+    fn transmit(&self, frame: Vec<u8>);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    // no interface reaches the address
+    Unreachable,
+    // another socket is bound to the port
+    AddressInUse,
+    // the peer refused or reset the connection
+    ConnectionRefused,
+    TimedOut,
+    // the connection is closed for sending
+    Closed,
+    // the datagram does not fit into the socket buffer
+    TooLarge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpConfig {
+    Static {
+        address: Ipv4Cidr,
+        gateway: Option<Ipv4Address>,
+    },
+    // the interface has no address until the first lease
+    Dhcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub name: &'static str,
+    pub mac_address: Option<[u8; 6]>,
+    pub address: Option<IpCidr>,
+    pub gateway: Option<Ipv4Address>,
+}
+
+// a socket of one interface
+#[derive(Debug, Clone, Copy)]
+struct SocketRef {
+    interface: usize,
+    handle: SocketHandle,
+}
+
+struct NetInterface {
+    device: Arc<dyn NetDevice>,
+    iface: Interface,
+    sockets: SocketSet<'static>,
+    gateway: Option<Ipv4Address>,
+    dhcp: Option<SocketHandle>,
+}
+
+impl NetInterface {
+    fn configure(&mut self, address: Option<Ipv4Cidr>, gateway: Option<Ipv4Address>) {
+        self.iface.update_ip_addrs(|addresses| {
+            addresses.clear();
+            if let Some(address) = address {
+                addresses.push(address.into()).unwrap();
+            }
+        });
+        match gateway {
+            Some(gateway) => {
+                self.iface
+                    .routes_mut()
+                    .add_default_ipv4_route(gateway)
+                    .unwrap();
+            }
+            None => {
+                self.iface.routes_mut().remove_default_ipv4_route();
+            }
+        }
+        self.gateway = gateway;
+    }
+
+    // applies the new lease (or its loss)
+    fn poll_dhcp(&mut self) {
+        let Some(handle) = self.dhcp else {
+            return;
+        };
+        let name = self.device.name();
+        match self.sockets.get_mut::<dhcpv4::Socket>(handle).poll() {
+            None => {}
+            Some(dhcpv4::Event::Configured(config)) => {
+                log::info!(
+                    "DHCP lease for {name}: {} via {:?} (server {}, dns {:?})",
+                    config.address,
+                    config.router,
+                    config.server.address,
+                    config.dns_servers
+                );
+                let (address, router) = (config.address, config.router);
+                self.configure(Some(address), router);
+            }
+            Some(dhcpv4::Event::Deconfigured) => {
+                log::warn!("DHCP lease for {name} lost");
+                self.configure(None, None);
+            }
+        }
+    }
+}
+
+struct Stack {
+    // never removed, sockets refer to them by index
+    interfaces: Vec<NetInterface>,
+    // connections whose stream was dropped, removed once they are closed
+    closing: Vec<SocketRef>,
+    tcp_ports: BTreeSet<u16>,
+    udp_ports: BTreeSet<u16>,
+    next_port: u16,
+}
+
+// Not locked in interrupt handlers (kernel code is not preempted, see sched)
+static STACK: Mutex<Stack> = Mutex::new(Stack {
+    interfaces: Vec::new(),
+    closing: Vec::new(),
+    tcp_ports: BTreeSet::new(),
+    udp_ports: BTreeSet::new(),
+    next_port: *EPHEMERAL_PORTS.start(),
+});
+// woken after every poll, the blocked socket calls check their sockets again
+static ACTIVITY: WaitQueue = WaitQueue::new();
+static POLL: WaitQueue = WaitQueue::new();
+static POLL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn now() -> Instant {
+    Instant::from_micros(sched::now_us() as i64)
+}
+
+impl Stack {
+    // Polls all interfaces and removes the closed connections, returns when the next poll is due at the latest
+    fn poll(&mut self) -> u64 {
+        let timestamp = now();
+        let mut delay = POLL_INTERVAL_MS * 1000;
+        for interface in &mut self.interfaces {
+            let mut link = Link(&*interface.device);
+            interface
+                .iface
+                .poll(timestamp, &mut link, &mut interface.sockets);
+            interface.poll_dhcp();
+            if let Some(next) = interface.iface.poll_delay(timestamp, &interface.sockets) {
+                delay = delay.min(next.total_micros());
+            }
+        }
+
+        let interfaces = &mut self.interfaces;
+        self.closing.retain(|socket| {
+            let sockets = &mut interfaces[socket.interface].sockets;
+            let closed = sockets.get::<tcp::Socket>(socket.handle).state() == tcp::State::Closed;
+            if closed {
+                sockets.remove(socket.handle);
+            }
+            !closed
+        });
+        delay
+    }
+
+    // the interface with the address in its subnet, otherwise the first one with a default gateway
+    fn route(&self, addr: IpAddress) -> Option<usize> {
+        self.interfaces
+            .iter()
+            .position(|interface| {
+                interface
+                    .iface
+                    .ip_addrs()
+                    .iter()
+                    .any(|cidr| cidr.contains_addr(&addr))
+            })
+            .or_else(|| {
+                self.interfaces
+                    .iter()
+                    .position(|interface| interface.gateway.is_some())
+            })
+    }
+
+    // next port of the ephemeral range which no socket is bound to
+    fn ephemeral_port(&mut self, in_use: impl Fn(&Self, u16) -> bool) -> u16 {
+        loop {
+            let port = self.next_port;
+            self.next_port = if port == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                port + 1
+            };
+            if !in_use(self, port) {
+                return port;
+            }
+        }
+    }
+}
+
+// smoltcp device of a NetDevice
+struct Link<'a>(&'a dyn NetDevice);
+
+struct RxFrame(Vec<u8>);
+
+struct TxFrame<'a>(&'a dyn NetDevice);
+
+impl phy::Device for Link<'_> {
+    type RxToken<'b>
+        = RxFrame
+    where
+        Self: 'b;
+    type TxToken<'b>
+        = TxFrame<'b>
+    where
+        Self: 'b;
+
+    fn receive(&mut self, _: Instant) -> Option<(RxFrame, TxFrame<'_>)> {
+        let frame = self.0.receive()?;
+        Some((RxFrame(frame), TxFrame(self.0)))
+    }
+
+    fn transmit(&mut self, _: Instant) -> Option<TxFrame<'_>> {
+        self.0.can_transmit().then_some(TxFrame(self.0))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = if self.0.mac_address().is_some() {
+            Medium::Ethernet
+        } else {
+            Medium::Ip
+        };
+        capabilities.max_transmission_unit = self.0.mtu();
+        capabilities
+    }
+}
+
+impl phy::RxToken for RxFrame {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(mut self, f: F) -> R {
+        f(&mut self.0)
+    }
+}
+
+impl phy::TxToken for TxFrame<'_> {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame);
+        self.0.transmit(frame);
+        result
+    }
+}
+
+// Adds an interface for the device, returns its index
+pub fn add_interface(device: Arc<dyn NetDevice>, config: IpConfig) -> usize {
+    let hardware_address = match device.mac_address() {
+        Some(mac) => HardwareAddress::Ethernet(EthernetAddress(mac)),
+        None => HardwareAddress::Ip,
+    };
+    let mut interface_config = Config::new(hardware_address);
+    interface_config.random_seed = random::next_u64();
+    let iface = Interface::new(interface_config, &mut Link(&*device), now());
+    let mut interface = NetInterface {
+        device,
+        iface,
+        sockets: SocketSet::new(Vec::new()),
+        gateway: None,
+        dhcp: None,
+    };
+    match config {
+        IpConfig::Static { address, gateway } => {
+            interface.configure(Some(address), gateway);
+            log::info!(
+                "Network interface {} with {address} via {gateway:?}",
+                interface.device.name()
+            );
+        }
+        IpConfig::Dhcp => {
+            interface.dhcp = Some(interface.sockets.add(dhcpv4::Socket::new()));
+            log::info!(
+                "Network interface {} waits for a DHCP lease",
+                interface.device.name()
+            );
+        }
+    }
+
+    let mut stack = STACK.lock();
+    stack.interfaces.push(interface);
+    stack.interfaces.len() - 1
+}
+
+pub fn interfaces() -> Vec<InterfaceInfo> {
+    STACK
+        .lock()
+        .interfaces
+        .iter()
+        .map(|interface| InterfaceInfo {
+            name: interface.device.name(),
+            mac_address: interface.device.mac_address(),
+            address: interface.iface.ip_addrs().first().copied(),
+            gateway: interface.gateway,
+        })
+        .collect()
+}
+
+// the poll thread runs soon (after data was queued)
+fn request_poll() {
+    POLL_REQUESTED.store(true, Ordering::Release);
+    POLL.wake_one();
+}
+
+// Needs to be called once by the bsp after the scheduler is initialized, adds the loopback interface and one
+// for every network card. The kernel command line configures the first card, the others use dhcp
+pub fn init() {
+    add_interface(
+        Arc::new(LoopbackDevice::new()),
+        IpConfig::Static {
+            address: Ipv4Cidr::new(Ipv4Address::new(127, 0, 0, 1), 8),
+            gateway: None,
+        },
+    );
+    let cmdline = cmdline::get();
+    for (index, card) in e1000::probe().into_iter().enumerate() {
+        let config = match cmdline.ip {
+            Some((address, prefix)) if index == 0 => IpConfig::Static {
+                address: Ipv4Cidr::new(Ipv4Address(address.octets()), prefix),
+                gateway: cmdline.gateway.map(|gateway| Ipv4Address(gateway.octets())),
+            },
+            _ => IpConfig::Dhcp,
+        };
+        add_interface(card, config);
+    }
+    // the bsp is never parked, its timer keeps running
+    timer_callbacks::add_periodic(Duration::from_millis(POLL_INTERVAL_MS), || {
+        POLL.wake_one();
+    });
+    // detached, it runs until the system stops
+    kthread::spawn("net poll", || loop {
+        let delay = STACK.lock().poll();
+        ACTIVITY.wake_all();
+        let deadline = sched::now_us() + delay;
+        POLL.wait_until(|| {
+            POLL_REQUESTED.swap(false, Ordering::AcqRel) || sched::now_us() >= deadline
+        });
+    });
+}
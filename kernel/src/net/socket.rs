@@ -0,0 +1,322 @@
+use alloc::{vec, vec::Vec};
+use smoltcp::{
+    socket::{tcp, udp, Socket},
+    wire::{IpEndpoint, IpListenEndpoint},
+};
+
+use super::{request_poll, NetError, SocketRef, Stack, ACTIVITY, STACK};
+use crate::time::{Duration, Instant};
+
+// Blocking sockets for kernel threads. TCP connections and UDP sockets are bound to one interface
+// (the one which reaches the peer), listeners and bound UDP sockets have a socket on every interface
+// that exists when they are bound
+const TCP_BUFFER_SIZE: usize = 16 * 1024;
+const UDP_BUFFER_SIZE: usize = 16 * 1024;
+const UDP_PACKETS: usize = 16;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn tcp_socket() -> tcp::Socket<'static> {
+    tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+    )
+}
+
+fn udp_socket() -> udp::Socket<'static> {
+    let buffer = || {
+        udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; UDP_PACKETS],
+            vec![0; UDP_BUFFER_SIZE],
+        )
+    };
+    udp::Socket::new(buffer(), buffer())
+}
+
+fn with_tcp<T>(socket: SocketRef, f: impl FnOnce(&mut tcp::Socket<'static>) -> T) -> T {
+    let mut stack = STACK.lock();
+    f(stack.interfaces[socket.interface]
+        .sockets
+        .get_mut::<tcp::Socket>(socket.handle))
+}
+
+// Calls f until it returns Some, waits for the next poll in between
+fn wait_for<T>(mut f: impl FnMut(&mut Stack) -> Option<T>) -> T {
+    let mut result = None;
+    ACTIVITY.wait_until(|| {
+        result = f(&mut STACK.lock());
+        result.is_some()
+    });
+    result.unwrap()
+}
+
+fn tcp_port_in_use(stack: &Stack, port: u16) -> bool {
+    stack.tcp_ports.contains(&port)
+        || stack.interfaces.iter().any(|interface| {
+            interface.sockets.iter().any(|(_, socket)| {
+                matches!(socket, Socket::Tcp(socket)
+                    if socket.local_endpoint().is_some_and(|local| local.port == port))
+            })
+        })
+}
+
+// Accepts connections on a port of every interface
+pub struct TcpListener {
+    port: u16,
+    // the listening socket of each interface
+    sockets: Vec<SocketRef>,
+}
+
+impl TcpListener {
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut stack = STACK.lock();
+        if tcp_port_in_use(&stack, port) {
+            return Err(NetError::AddressInUse);
+        }
+        stack.tcp_ports.insert(port);
+        let sockets = (0..stack.interfaces.len())
+            .map(|interface| listen(&mut stack, interface, port))
+            .collect();
+        Ok(Self { port, sockets })
+    }
+
+    pub const fn port(&self) -> u16 {
+        self.port
+    }
+
+    // Waits for the next connection of any interface
+    pub fn accept(&mut self) -> TcpStream {
+        let port = self.port;
+        let sockets = &mut self.sockets;
+        let stream = wait_for(|stack| {
+            let index = sockets.iter().position(|socket| {
+                let sockets = &mut stack.interfaces[socket.interface].sockets;
+                sockets.get_mut::<tcp::Socket>(socket.handle).may_send()
+            })?;
+            // the connection keeps the socket, the interface gets a new listening one
+            let connected = sockets[index];
+            sockets[index] = listen(stack, connected.interface, port);
+            Some(TcpStream(connected))
+        });
+        request_poll();
+        stream
+    }
+}
+
+fn listen(stack: &mut Stack, interface: usize, port: u16) -> SocketRef {
+    let mut socket = tcp_socket();
+    socket.listen(port).unwrap();
+    SocketRef {
+        interface,
+        handle: stack.interfaces[interface].sockets.add(socket),
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut stack = STACK.lock();
+        stack.tcp_ports.remove(&self.port);
+        for socket in &self.sockets {
+            stack.interfaces[socket.interface]
+                .sockets
+                .remove(socket.handle);
+        }
+    }
+}
+
+// A connection, closed when it is dropped
+pub struct TcpStream(SocketRef);
+
+impl TcpStream {
+    // Waits until the connection is established
+    pub fn connect(remote: IpEndpoint) -> Result<Self, NetError> {
+        let mut stack = STACK.lock();
+        let interface = stack.route(remote.addr).ok_or(NetError::Unreachable)?;
+        let port = stack.ephemeral_port(tcp_port_in_use);
+        let mut socket = tcp_socket();
+        let interface_data = &mut stack.interfaces[interface];
+        socket
+            .connect(interface_data.iface.context(), remote, port)
+            .map_err(|_| NetError::Unreachable)?;
+        let stream = Self(SocketRef {
+            interface,
+            handle: interface_data.sockets.add(socket),
+        });
+        drop(stack);
+        request_poll();
+
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        wait_for(|stack| {
+            let socket = stack.interfaces[interface]
+                .sockets
+                .get_mut::<tcp::Socket>(stream.0.handle);
+            match socket.state() {
+                tcp::State::SynSent | tcp::State::SynReceived if Instant::now() >= deadline => {
+                    socket.abort();
+                    Some(Err(NetError::TimedOut))
+                }
+                tcp::State::SynSent | tcp::State::SynReceived => None,
+                tcp::State::Closed => Some(Err(NetError::ConnectionRefused)),
+                _ => Some(Ok(())),
+            }
+        })
+        .map(|()| stream)
+    }
+
+    pub fn remote_endpoint(&self) -> Option<IpEndpoint> {
+        with_tcp(self.0, |socket| socket.remote_endpoint())
+    }
+
+    // Waits until there is space and sends as much of data as fits
+    pub fn send(&self, data: &[u8]) -> Result<usize, NetError> {
+        let sent = wait_for(|stack| {
+            let socket = stack.interfaces[self.0.interface]
+                .sockets
+                .get_mut::<tcp::Socket>(self.0.handle);
+            if !socket.may_send() {
+                Some(Err(NetError::Closed))
+            } else if socket.can_send() {
+                Some(socket.send_slice(data).map_err(|_| NetError::Closed))
+            } else {
+                None
+            }
+        });
+        request_poll();
+        sent
+    }
+
+    pub fn send_all(&self, mut data: &[u8]) -> Result<(), NetError> {
+        while !data.is_empty() {
+            let sent = self.send(data)?;
+            data = &data[sent..];
+        }
+        Ok(())
+    }
+
+    // Waits until data arrived and reads as much of it as fits into buf, returns 0 once the peer closed
+    // the connection
+    pub fn recv(&self, buf: &mut [u8]) -> usize {
+        let received = wait_for(|stack| {
+            let socket = stack.interfaces[self.0.interface]
+                .sockets
+                .get_mut::<tcp::Socket>(self.0.handle);
+            read_available(socket, buf)
+        });
+        // the window may have opened
+        request_poll();
+        received
+    }
+
+    // Like recv without waiting, None if no data arrived yet
+    pub fn try_recv(&self, buf: &mut [u8]) -> Option<usize> {
+        let received = with_tcp(self.0, |socket| read_available(socket, buf));
+        if received.is_some_and(|received| received > 0) {
+            request_poll();
+        }
+        received
+    }
+}
+
+// None if the connection is open and no data arrived
+fn read_available(socket: &mut tcp::Socket, buf: &mut [u8]) -> Option<usize> {
+    if socket.can_recv() {
+        Some(socket.recv_slice(buf).unwrap_or(0))
+    } else if !socket.may_recv() {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        with_tcp(self.0, tcp::Socket::close);
+        // the poll thread removes it once the close handshake is done
+        STACK.lock().closing.push(self.0);
+        request_poll();
+    }
+}
+
+// Receives datagrams on a port of every interface, sends them through the interface which reaches the peer
+pub struct UdpSocket {
+    port: u16,
+    sockets: Vec<SocketRef>,
+}
+
+impl UdpSocket {
+    // port 0 binds to an unused port
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut stack = STACK.lock();
+        let port = match port {
+            0 => stack.ephemeral_port(|stack, port| stack.udp_ports.contains(&port)),
+            port if stack.udp_ports.contains(&port) => return Err(NetError::AddressInUse),
+            port => port,
+        };
+        stack.udp_ports.insert(port);
+        let sockets = (0..stack.interfaces.len())
+            .map(|interface| {
+                let mut socket = udp_socket();
+                socket.bind(IpListenEndpoint::from(port)).unwrap();
+                SocketRef {
+                    interface,
+                    handle: stack.interfaces[interface].sockets.add(socket),
+                }
+            })
+            .collect();
+        Ok(Self { port, sockets })
+    }
+
+    pub const fn port(&self) -> u16 {
+        self.port
+    }
+
+    // Waits until there is space in the socket buffer
+    pub fn send_to(&self, data: &[u8], remote: IpEndpoint) -> Result<(), NetError> {
+        if data.len() > UDP_BUFFER_SIZE {
+            return Err(NetError::TooLarge);
+        }
+        let interface = STACK
+            .lock()
+            .route(remote.addr)
+            .ok_or(NetError::Unreachable)?;
+        let socket = self.sockets[interface];
+        let sent = wait_for(|stack| {
+            let socket = stack.interfaces[interface]
+                .sockets
+                .get_mut::<udp::Socket>(socket.handle);
+            socket.can_send().then(|| {
+                socket
+                    .send_slice(data, remote)
+                    .map_err(|_| NetError::TooLarge)
+            })
+        });
+        request_poll();
+        sent
+    }
+
+    // Waits for a datagram of any interface, the part which does not fit into buf is dropped
+    pub fn recv_from(&self, buf: &mut [u8]) -> (usize, IpEndpoint) {
+        wait_for(|stack| {
+            self.sockets.iter().find_map(|socket| {
+                let socket = stack.interfaces[socket.interface]
+                    .sockets
+                    .get_mut::<udp::Socket>(socket.handle);
+                let (data, meta) = socket.recv().ok()?;
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Some((len, meta.endpoint))
+            })
+        })
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let mut stack = STACK.lock();
+        stack.udp_ports.remove(&self.port);
+        for socket in &self.sockets {
+            stack.interfaces[socket.interface]
+                .sockets
+                .remove(socket.handle);
+        }
+    }
+}
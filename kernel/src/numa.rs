@@ -0,0 +1,277 @@
+use spin::Once;
+
+use crate::{acpi, apic};
+
+pub const MAX_NODES: usize = 8;
+const MAX_MEMORY_RANGES: usize = 32;
+
+// distances of the SLIT, used if there is none
+const LOCAL_DISTANCE: u8 = 10;
+const REMOTE_DISTANCE: u8 = 20;
+
+// table header and reserved fields
+const SRAT_ENTRIES_OFFSET: usize = 48;
+const SLIT_LOCALITY_COUNT_OFFSET: usize = 36;
+const SLIT_DISTANCES_OFFSET: usize = 44;
+
+const SRAT_PROCESSOR_AFFINITY: u8 = 0;
+const SRAT_MEMORY_AFFINITY: u8 = 1;
+const SRAT_X2APIC_AFFINITY: u8 = 2;
+const SRAT_ENABLED: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryAffinity {
+    pub start: u64,
+    pub end: u64, // exclusive
+    pub node: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProcessorAffinity {
+    pub apic_id: u32,
+    pub node: usize,
+}
+
+// Memory and processor affinity of the numa nodes (SRAT) and the distances between them (SLIT).
+// Proximity domains are numbered as nodes in the order they appear, domains past MAX_NODES belong to node 0.
+// Fixed size, the frame allocator needs it before the heap exists. The processors are read from the SRAT when
+// needed (there is an entry for every core). Without SRAT everything is on node 0
+pub struct NumaLayout<'a> {
+    domains: [u32; MAX_NODES],
+    node_count: usize,
+    memory: [MemoryAffinity; MAX_MEMORY_RANGES],
+    memory_len: usize,
+    // the entries of the srat
+    srat_entries: &'a [u8],
+    distances: [[u8; MAX_NODES]; MAX_NODES],
+}
+
+impl<'a> NumaLayout<'a> {
+    // srat and slit include their table headers
+    pub fn parse(srat: Option<&'a [u8]>, slit: Option<&[u8]>) -> Self {
+        let mut layout = Self {
+            domains: [0; MAX_NODES],
+            node_count: 1,
+            memory: [MemoryAffinity::default(); MAX_MEMORY_RANGES],
+            memory_len: 0,
+            srat_entries: &[],
+            distances: [[REMOTE_DISTANCE; MAX_NODES]; MAX_NODES],
+        };
+        for node in 0..MAX_NODES {
+            layout.distances[node][node] = LOCAL_DISTANCE;
+        }
+        let Some(srat) = srat else {
+            return layout;
+        };
+        layout.node_count = 0;
+        layout.srat_entries = srat.get(SRAT_ENTRIES_OFFSET..).unwrap_or_default();
+
+        for entry in srat_entries(layout.srat_entries) {
+            if let Some((_, domain)) = processor_entry(entry) {
+                layout.node_of_domain(domain);
+            } else if entry[0] == SRAT_MEMORY_AFFINITY
+                && entry.len() >= 40
+                && read_u32(entry, 28) & SRAT_ENABLED != 0
+            {
+                let node = layout.node_of_domain(read_u32(entry, 2));
+                let start = read_u64(entry, 8);
+                layout.add_memory(start, start.saturating_add(read_u64(entry, 16)), node);
+            }
+        }
+        layout.node_count = layout.node_count.max(1);
+        layout.memory[..layout.memory_len].sort_unstable_by_key(|range| range.start);
+
+        if let Some(slit) = slit {
+            layout.parse_distances(slit);
+        }
+        layout
+    }
+
+    // the slit is indexed by proximity domain
+    fn parse_distances(&mut self, slit: &[u8]) {
+        let Some(count) = slit
+            .get(SLIT_LOCALITY_COUNT_OFFSET..SLIT_DISTANCES_OFFSET)
+            .map(|count| u64::from_le_bytes(count.try_into().unwrap()) as usize)
+        else {
+            return;
+        };
+        for from in 0..self.node_count {
+            for to in 0..self.node_count {
+                let (from_domain, to_domain) =
+                    (self.domains[from] as usize, self.domains[to] as usize);
+                if from_domain >= count || to_domain >= count {
+                    continue;
+                }
+                if let Some(&distance) =
+                    slit.get(SLIT_DISTANCES_OFFSET + from_domain * count + to_domain)
+                {
+                    self.distances[from][to] = distance;
+                }
+            }
+        }
+    }
+
+    fn node_of_domain(&mut self, domain: u32) -> usize {
+        if let Some(node) = self.domains[..self.node_count]
+            .iter()
+            .position(|&known| known == domain)
+        {
+            return node;
+        }
+        if self.node_count == MAX_NODES {
+            log::warn!(
+                "More than {MAX_NODES} NUMA nodes, proximity domain {domain} is treated as node 0"
+            );
+            return 0;
+        }
+        self.domains[self.node_count] = domain;
+        self.node_count += 1;
+        self.node_count - 1
+    }
+
+    // after parse, the domains past MAX_NODES belong to node 0
+    fn node_of_known_domain(&self, domain: u32) -> usize {
+        self.domains[..self.node_count]
+            .iter()
+            .position(|&known| known == domain)
+            .unwrap_or(0)
+    }
+
+    fn add_memory(&mut self, start: u64, end: u64, node: usize) {
+        match self.memory.get_mut(self.memory_len) {
+            Some(slot) => {
+                *slot = MemoryAffinity { start, end, node };
+                self.memory_len += 1;
+            }
+            None => log::warn!(
+                "Too many NUMA memory ranges, {start:#x} - {end:#x} is treated as node 0"
+            ),
+        }
+    }
+
+    pub const fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    pub fn memory_ranges(&self) -> &[MemoryAffinity] {
+        &self.memory[..self.memory_len]
+    }
+
+    // the enabled processors in the order of the srat
+    pub fn processors(&self) -> impl Iterator<Item = ProcessorAffinity> + '_ {
+        srat_entries(self.srat_entries)
+            .filter_map(processor_entry)
+            .map(|(apic_id, domain)| ProcessorAffinity {
+                apic_id,
+                node: self.node_of_known_domain(domain),
+            })
+    }
+
+    // node 0 for memory outside of the ranges
+    pub fn node_of_addr(&self, addr: u64) -> usize {
+        let ranges = self.memory_ranges();
+        let index = ranges.partition_point(|range| range.end <= addr);
+        ranges
+            .get(index)
+            .filter(|range| range.start <= addr)
+            .map_or(0, |range| range.node)
+    }
+
+    pub fn node_of_apic_id(&self, apic_id: u32) -> usize {
+        self.processors()
+            .find(|processor| processor.apic_id == apic_id)
+            .map_or(0, |processor| processor.node)
+    }
+
+    // relative memory latency, 10 for the node itself
+    pub fn distance(&self, from: usize, to: usize) -> u8 {
+        self.distances[from][to]
+    }
+
+    // all nodes, the nearest first (starting with node itself)
+    pub fn nodes_by_distance(&self, node: usize) -> impl Iterator<Item = usize> {
+        let mut order = [0; MAX_NODES];
+        for (index, entry) in order.iter_mut().enumerate() {
+            *entry = index;
+        }
+        order[..self.node_count].sort_unstable_by_key(|&other| (self.distance(node, other), other));
+        order.into_iter().take(self.node_count)
+    }
+
+    pub fn log(&self, level: log::Level) {
+        log::log!(level, "NUMA nodes: {}", self.node_count);
+        for node in 0..self.node_count {
+            log::log!(
+                level,
+                "  node {node} (proximity domain {}): distances {:?}",
+                self.domains[node],
+                &self.distances[node][..self.node_count]
+            );
+            for range in self
+                .memory_ranges()
+                .iter()
+                .filter(|range| range.node == node)
+            {
+                log::log!(
+                    level,
+                    "    memory {:#014x} - {:#014x} {:>8}MiB",
+                    range.start,
+                    range.end,
+                    (range.end - range.start) / 1024 / 1024
+                );
+            }
+            for processor in self.processors().filter(|processor| processor.node == node) {
+                log::log!(level, "    processor with apic id {}", processor.apic_id);
+            }
+        }
+    }
+}
+
+// entries are a type and a length byte followed by the data
+fn srat_entries(mut entries: &[u8]) -> impl Iterator<Item = &[u8]> {
+    core::iter::from_fn(move || {
+        let [_, length, ..] = *entries else {
+            return None;
+        };
+        let entry = entries.get(..length.max(2) as usize)?;
+        entries = &entries[entry.len()..];
+        Some(entry)
+    })
+}
+
+// apic id and proximity domain of an enabled (x2)apic affinity entry
+fn processor_entry(entry: &[u8]) -> Option<(u32, u32)> {
+    match (entry[0], entry.len()) {
+        (SRAT_PROCESSOR_AFFINITY, 16..) if read_u32(entry, 4) & SRAT_ENABLED != 0 => {
+            let domain =
+                u32::from(entry[2]) | u32::from_le_bytes([0, entry[9], entry[10], entry[11]]);
+            Some((u32::from(entry[3]), domain))
+        }
+        (SRAT_X2APIC_AFFINITY, 24..) if read_u32(entry, 12) & SRAT_ENABLED != 0 => {
+            Some((read_u32(entry, 8), read_u32(entry, 4)))
+        }
+        _ => None,
+    }
+}
+
+fn read_u32(entry: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(entry[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(entry: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(entry[offset..offset + 8].try_into().unwrap())
+}
+
+static LAYOUT: Once<NumaLayout<'static>> = Once::new();
+
+// parsed on first use
+pub fn layout() -> &'static NumaLayout<'static> {
+    LAYOUT.call_once(|| {
+        NumaLayout::parse(acpi::find_raw_table(b"SRAT"), acpi::find_raw_table(b"SLIT"))
+    })
+}
+
+// node of the current core (0 before the apic is initialized)
+pub fn current_node() -> usize {
+    apic::try_get_apic().map_or(0, |mut apic| layout().node_of_apic_id(apic.id()))
+}
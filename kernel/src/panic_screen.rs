@@ -0,0 +1,133 @@
+use core::{arch::asm, fmt::Write, panic::PanicInfo};
+
+use x86_64::registers::{
+    control::{Cr0, Cr2, Cr3, Cr4},
+    rflags,
+};
+
+use crate::{
+    backtrace::{self, Backtrace, Frame},
+    logging,
+    memory::MEMORY,
+    smp::try_get_cld,
+    terminal_out::{self, Color, FontWeight, TerminalWriter},
+};
+
+// The panic handlers draw everything needed to debug a panic over the whole screen,
+// so a photo of it is enough on real hardware (where the serial log is usually missing)
+const BACKGROUND: Color = Color::new(0x50, 0x00, 0x00);
+const FOREGROUND: Color = Color::white();
+const HEADING: Color = Color::new(0xFF, 0xC8, 0x00);
+const BACKTRACE_DEPTH: usize = 12;
+
+// of the panicking code, read before anything is drawn
+struct Registers {
+    rsp: u64,
+    rbp: u64,
+    rflags: u64,
+    cr0: u64,
+    cr2: u64,
+    cr3: u64,
+    cr4: u64,
+}
+
+impl Registers {
+    #[inline(always)]
+    fn read() -> Self {
+        let rsp: u64;
+        unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+        Self {
+            rsp,
+            rbp: backtrace::current_frame(),
+            rflags: rflags::read_raw(),
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read().as_u64(),
+            cr3: Cr3::read().0.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+        }
+    }
+}
+
+// has to be inlined, so the backtrace starts at the panic handler
+#[inline(always)]
+pub fn show(info: &PanicInfo) {
+    let registers = Registers::read();
+    let backtrace = Backtrace::<BACKTRACE_DEPTH>::capture();
+    terminal_out::panic_print(|term| {
+        let _ = draw(term, info, &registers, &backtrace);
+    });
+}
+
+fn draw(
+    term: &mut TerminalWriter,
+    info: &PanicInfo,
+    registers: &Registers,
+    backtrace: &Backtrace<BACKTRACE_DEPTH>,
+) -> core::fmt::Result {
+    term.clear_color = BACKGROUND;
+    term.background = BACKGROUND;
+    term.foreground = FOREGROUND;
+    // the report has to fit on the screen without scrolling (about 35 lines)
+    term.clear(Some(terminal_out::small_font_size()));
+
+    heading(term, "KERNEL PANIC")?;
+    // only the bsp runs before the core local data exists
+    let core = try_get_cld().map_or(0, |cld| cld.cpu_index);
+    writeln!(term, " on core {core}")?;
+    writeln!(term, "{info}")?;
+
+    heading(term, "Registers")?;
+    writeln!(
+        term,
+        "\n  rsp {:#018x}  rbp {:#018x}  rflags {:#010x}",
+        registers.rsp, registers.rbp, registers.rflags
+    )?;
+    writeln!(
+        term,
+        "  cr0 {:#018x}  cr2 {:#018x}  cr3 {:#018x}  cr4 {:#010x}",
+        registers.cr0, registers.cr2, registers.cr3, registers.cr4
+    )?;
+
+    heading(term, "Memory")?;
+    // the panic may have happened while allocating
+    match MEMORY.try_lock() {
+        Some(memory) => {
+            let (used, total) = memory.get_memory_utilization();
+            writeln!(
+                term,
+                " {used}/{total} pages used; {}MB free of {}MB",
+                (total - used) * 4096 / 1024 / 1024,
+                total * 4096 / 1024 / 1024
+            )?;
+        }
+        None => writeln!(term, " locked")?,
+    }
+
+    heading(term, "Backtrace")?;
+    writeln!(term, " (image offset {:#x})", backtrace::image_offset())?;
+    for address in backtrace.addresses() {
+        let frame = Frame {
+            address,
+            is_return_address: true,
+        };
+        writeln!(term, "  {frame}")?;
+    }
+
+    heading(term, "Log")?;
+    writeln!(term, " (last {} lines)", logging::RECENT_LINES)?;
+    term.font_weight = FontWeight::Light;
+    logging::recent_lines(|line| {
+        let _ = writeln!(term, "  {line}");
+    });
+    Ok(())
+}
+
+fn heading(term: &mut TerminalWriter, title: &str) -> core::fmt::Result {
+    let foreground = term.foreground;
+    term.foreground = HEADING;
+    term.font_weight = FontWeight::Bold;
+    write!(term, "{title}")?;
+    term.foreground = foreground;
+    term.font_weight = FontWeight::Regular;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use alloc::vec::Vec;
+use pci_config::{ConfigSpace, Device, EcamConfigSpace, PciAddress, PortConfigSpace};
+use port_io::VolatileMmio;
+use spin::{Mutex, Once};
+use x86_64::{instructions::interrupts::without_interrupts, PhysAddr};
+
+use crate::{acpi::ACPI, memory};
+
+// The address and data ports are used as a pair, so every access holds the lock (with interrupts disabled)
+static PORT_CONFIG: Mutex<PortConfigSpace> = Mutex::new(PortConfigSpace::new());
+// memory mapped configuration space of segment group 0 (if acpi reports one)
+static ECAM: Once<EcamConfigSpace<VolatileMmio>> = Once::new();
+
+// Configuration space of segment group 0: memory mapped (including the extended configuration space) after init,
+// through the ports without MCFG
+pub struct KernelConfigSpace;
+
+impl ConfigSpace for KernelConfigSpace {
+    fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        match ECAM.get() {
+            Some(ecam) => ecam.read(address, offset),
+            None => without_interrupts(|| PORT_CONFIG.lock().read(address, offset)),
+        }
+    }
+
+    fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        match ECAM.get() {
+            Some(ecam) => ecam.write(address, offset, value),
+            None => without_interrupts(|| PORT_CONFIG.lock().write(address, offset, value)),
+        }
+    }
+
+    fn size(&self) -> u16 {
+        match ECAM.get() {
+            Some(ecam) => ecam.size(),
+            None => pci_config::CONFIG_SPACE_SIZE,
+        }
+    }
+}
+
+pub static CONFIG: KernelConfigSpace = KernelConfigSpace;
+static DEVICES: Once<Vec<PciAddress>> = Once::new();
+
+fn init_ecam() {
+    let Some(region) = ACPI
+        .lock()
+        .pci_ecam_regions
+        .iter()
+        .copied()
+        .find(|region| region.segment_group == 0 && region.start_bus <= region.end_bus)
+    else {
+        return;
+    };
+    let buses = u64::from(region.end_bus - region.start_bus) + 1;
+    let phys = PhysAddr::new(region.phys_addr + (u64::from(region.start_bus) << 20));
+    let virt = memory::map_named_mmio("pci ecam", phys, buses << 20);
+    log::info!(
+        "PCI ECAM at {phys:?} for buses {:02x}..={:02x}",
+        region.start_bus,
+        region.end_bus
+    );
+    ECAM.call_once(|| {
+        EcamConfigSpace::new(
+            unsafe { VolatileMmio::new(virt.as_mut_ptr()) },
+            region.start_bus,
+            region.end_bus,
+        )
+    });
+}
+
+// needs to be called once by the bsp (requires heap)
+pub fn init() {
+    init_ecam();
+    DEVICES.call_once(|| {
+        pci_config::enumerate(&CONFIG)
+            .inspect(|&address| {
+                let device = device(address);
+                let (class, subclass, _) = device.class();
+                log::info!(
+                    "PCI {:02x}:{:02x}.{} {:04x}:{:04x} class {class:02x}:{subclass:02x}",
+                    address.bus,
+                    address.device,
+                    address.function,
+                    device.vendor_id(),
+                    device.device_id()
+                );
+            })
+            .collect()
+    });
+}
+
+// all functions found by init
+pub fn devices() -> &'static [PciAddress] {
+    DEVICES.get().map_or(&[], Vec::as_slice)
+}
+
+pub fn device(address: PciAddress) -> Device<'static, KernelConfigSpace> {
+    Device::new(&CONFIG, address)
+}
@@ -0,0 +1,91 @@
+use core::ops::Index;
+
+use alloc::boxed::Box;
+use spin::Once;
+
+// A value for every core, indexed by cpu index. The entry of the bsp is part of the static (some are used before
+// the heap exists), the entries of the aps are allocated once by the bsp before it starts them.
+// Declared with per_core!, which registers the static for allocate_aps
+pub struct PerCore<T: 'static> {
+    bsp: T,
+    aps: Once<&'static [T]>,
+    init: fn() -> T,
+}
+
+impl<T> PerCore<T> {
+    pub const fn new(bsp: T, init: fn() -> T) -> Self {
+        Self {
+            bsp,
+            aps: Once::new(),
+            init,
+        }
+    }
+
+    // None for cores beyond the ones acpi reported (and for all aps before allocate_aps)
+    pub fn get(&self, cpu_index: usize) -> Option<&T> {
+        match cpu_index {
+            0 => Some(&self.bsp),
+            _ => self.aps.get()?.get(cpu_index - 1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        1 + self.aps.get().map_or(0, |aps| aps.len())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let aps: &[T] = self.aps.get().map_or(&[], |aps| aps);
+        core::iter::once(&self.bsp).chain(aps)
+    }
+}
+
+impl<T> Index<usize> for PerCore<T> {
+    type Output = T;
+
+    fn index(&self, cpu_index: usize) -> &T {
+        self.get(cpu_index)
+            .unwrap_or_else(|| panic!("no per core entry for cpu index {cpu_index}"))
+    }
+}
+
+pub trait AllocateAps {
+    fn allocate_aps(&self, ap_count: usize);
+}
+
+impl<T> AllocateAps for PerCore<T> {
+    fn allocate_aps(&self, ap_count: usize) {
+        self.aps
+            .call_once(|| Box::leak((0..ap_count).map(|_| (self.init)()).collect()));
+    }
+}
+
+#[linkme::distributed_slice]
+pub static PER_CORE_STATICS: [&'static (dyn AllocateAps + Sync)];
+
+// Needs to be called once (only bsp) before the aps are started. Not done on first use, the aps use some of
+// the entries in interrupt handlers (allocating there could deadlock on the heap)
+pub fn allocate_aps(ap_count: usize) {
+    for per_core in PER_CORE_STATICS {
+        per_core.allocate_aps(ap_count);
+    }
+    log::debug!(
+        "Allocated {} per core statics for {ap_count} aps",
+        PER_CORE_STATICS.len()
+    );
+}
+
+// per_core! { static NAME: Type = initial value; ... }, the initial value is evaluated once for every core
+#[macro_export]
+macro_rules! per_core {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::per_core::PerCore<$ty> =
+                $crate::per_core::PerCore::new($init, || $init);
+            const _: () = {
+                #[linkme::distributed_slice($crate::per_core::PER_CORE_STATICS)]
+                static REGISTER: &'static (dyn $crate::per_core::AllocateAps + Sync) = &$name;
+            };
+        )*
+    };
+}
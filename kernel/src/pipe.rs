@@ -0,0 +1,127 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use spin::Mutex;
+
+use crate::sched::WaitQueue;
+
+// Byte stream between tasks (kernel threads or applications, see handle.rs).
+// Reads block while the pipe is empty, writes while it is full
+struct Pipe {
+    buffer: Mutex<PipeBuffer>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+struct PipeBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+    readers: usize,
+    writers: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipeClosed;
+
+pub struct PipeReader(Arc<Pipe>);
+
+pub struct PipeWriter(Arc<Pipe>);
+
+pub fn pipe(capacity: usize) -> (PipeWriter, PipeReader) {
+    crate::ass!(capacity > 0);
+    let pipe = Arc::new(Pipe {
+        buffer: Mutex::new(PipeBuffer {
+            data: VecDeque::with_capacity(capacity),
+            capacity,
+            readers: 1,
+            writers: 1,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeWriter(pipe.clone()), PipeReader(pipe))
+}
+
+impl PipeReader {
+    // Waits until data is available and reads as much of it as fits into buf.
+    // Returns 0 once the pipe is empty and all writers are gone
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        let pipe = &self.0;
+        let mut read = 0;
+        pipe.readable.wait_until(|| {
+            let mut buffer = pipe.buffer.lock();
+            let len = buf.len().min(buffer.data.len());
+            for (byte, value) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+                *byte = value;
+            }
+            read = len;
+            read > 0 || buffer.writers == 0
+        });
+        pipe.writable.wake_all();
+        read
+    }
+}
+
+impl PipeWriter {
+    // Waits until there is space and writes as much of data as fits, fails if all readers are gone
+    pub fn write(&self, data: &[u8]) -> Result<usize, PipeClosed> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        let mut written = Ok(0);
+        pipe.writable.wait_until(|| {
+            let mut buffer = pipe.buffer.lock();
+            if buffer.readers == 0 {
+                written = Err(PipeClosed);
+                return true;
+            }
+            let len = data.len().min(buffer.capacity - buffer.data.len());
+            buffer.data.extend(&data[..len]);
+            written = Ok(len);
+            len > 0
+        });
+        pipe.readable.wake_all();
+        written
+    }
+
+    // writes all of data, blocking as often as necessary
+    pub fn write_all(&self, mut data: &[u8]) -> Result<(), PipeClosed> {
+        while !data.is_empty() {
+            let written = self.write(data)?;
+            data = &data[written..];
+        }
+        Ok(())
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.0.buffer.lock().readers += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.0.buffer.lock().writers += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.buffer.lock().readers -= 1;
+        // blocked writers fail
+        self.0.writable.wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.buffer.lock().writers -= 1;
+        // blocked readers see the end of the stream
+        self.0.readable.wake_all();
+    }
+}
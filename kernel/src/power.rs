@@ -0,0 +1,218 @@
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use ps2_8042::Ps2Controller;
+use spin::Once;
+use x86_64::{
+    instructions::{interrupts, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+    PhysAddr, VirtAddr,
+};
+
+use crate::{
+    acpi::{PowerInfo, ResetRegister, ACPI},
+    apic,
+    interrupts::IrqReturn,
+    ioapic::{self, GsiConfig},
+    kthread,
+    memory::phys_to_virt,
+    sched::{self, WaitQueue},
+};
+
+// PM1 control register
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_EN: u16 = 1 << 13;
+// PM1 status and enable registers
+const PWRBTN_STS: u16 = 1 << 8;
+const PWRBTN_EN: u16 = 1 << 8;
+
+const ACPI_ENABLE_POLLS: u32 = 300;
+
+// the sci is level triggered and active low unless an override says otherwise
+const SCI_CONFIG: GsiConfig = GsiConfig {
+    active_low: true,
+    level_triggered: true,
+};
+
+// (status port, enable port) of the pm1a and pm1b event blocks, read by the sci handler
+static PM1_EVENT_PORTS: Once<[Option<(u16, u16)>; 2]> = Once::new();
+static POWER_BUTTON_PRESSED: AtomicBool = AtomicBool::new(false);
+static POWER_BUTTON: WaitQueue = WaitQueue::new();
+
+// exit codes of the isa-debug-exit device qemu is started with (qemu exits with code << 1 | 1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+#[allow(dead_code)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+// returns if the kernel does not run in qemu
+#[allow(dead_code)]
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    unsafe {
+        let mut port = Port::new(0xf4);
+        port.write(exit_code as u32);
+    }
+}
+
+// Enters the soft off state (S5) through the pm1 control registers of the FADT.
+// Falls back to exiting qemu, otherwise the cores halt
+#[allow(dead_code)]
+pub fn shutdown() -> ! {
+    log::info!("Shutting down");
+    interrupts::disable();
+    let power = ACPI.lock().power;
+    if let (Some(port), Some((sleep_type_a, sleep_type_b))) =
+        (power.pm1a_control_port, power.s5_sleep_types)
+    {
+        unsafe {
+            let mut pm1a = Port::<u16>::new(port);
+            let value = pm1a.read();
+            pm1a.write(value | (sleep_type_a as u16 & 7) << SLP_TYP_SHIFT | SLP_EN);
+            if let Some(port) = power.pm1b_control_port {
+                let mut pm1b = Port::<u16>::new(port);
+                let value = pm1b.read();
+                pm1b.write(value | (sleep_type_b as u16 & 7) << SLP_TYP_SHIFT | SLP_EN);
+            }
+        }
+        // the transition takes a moment
+        for _ in 0..1_000_000 {
+            hint::spin_loop();
+        }
+        log::warn!("ACPI shutdown failed");
+    } else {
+        log::warn!("ACPI shutdown not supported (FADT: {power:x?})");
+    }
+    exit_qemu(QemuExitCode::Success);
+    log::error!("Shutdown failed, halting");
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+// Resets the machine with the reset register of the FADT, the reset line of the ps/2 controller
+// or a triple fault as the last resort
+#[allow(dead_code)]
+pub fn reboot() -> ! {
+    log::info!("Rebooting");
+    interrupts::disable();
+    let power = ACPI.lock().power;
+    match power.reset_register {
+        Some(ResetRegister::Io(port)) => unsafe { Port::<u8>::new(port).write(power.reset_value) },
+        Some(ResetRegister::Memory(address)) => unsafe {
+            phys_to_virt(PhysAddr::new(address))
+                .as_mut_ptr::<u8>()
+                .write_volatile(power.reset_value)
+        },
+        None => {}
+    }
+
+    // not locked, the other users are stopped by the reset anyway
+    if let Err(err) = Ps2Controller::new().pulse_reset_line() {
+        log::warn!("PS/2 controller reset failed: {err:?}");
+    }
+    for _ in 0..1_000_000 {
+        hint::spin_loop();
+    }
+
+    log::warn!("Reset failed, triple faulting");
+    let empty_idt = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    unsafe { lidt(&empty_idt) };
+    interrupts::int3();
+    unreachable!("triple fault did not reset")
+}
+
+// Enables the power button (a fixed event) and routes the system control interrupt, pressing the button shuts
+// down. Needs to be called once by the bsp after the scheduler is initialized
+pub fn init_events() {
+    let power = ACPI.lock().power;
+    let Some(pm1a_event_port) = power.pm1a_event_port else {
+        log::warn!("No PM1 event block, the power button is ignored");
+        return;
+    };
+    if !enable_acpi_mode(&power) {
+        log::warn!("ACPI mode not enabled, the power button is ignored");
+        return;
+    }
+
+    let enable_offset = u16::from(power.pm1_event_length / 2);
+    let ports = [Some(pm1a_event_port), power.pm1b_event_port]
+        .map(|port| port.map(|status_port| (status_port, status_port + enable_offset)));
+    for (status_port, enable_port) in ports.into_iter().flatten() {
+        unsafe {
+            // presses from before the boot
+            Port::<u16>::new(status_port).write(PWRBTN_STS);
+            let mut enable = Port::<u16>::new(enable_port);
+            let value = enable.read();
+            enable.write(value | PWRBTN_EN);
+        }
+    }
+    PM1_EVENT_PORTS.call_once(|| ports);
+
+    // the shutdown can not run in the interrupt handler
+    kthread::spawn("power button", || {
+        POWER_BUTTON.wait_until(|| POWER_BUTTON_PRESSED.load(Ordering::Acquire));
+        log::info!("Power button pressed");
+        shutdown()
+    });
+
+    let vector = crate::interrupts::allocate_vector("acpi sci").unwrap();
+    crate::interrupts::register_irq(vector, "acpi sci", sci_interrupt).unwrap();
+    // an override conforming to the bus also means the sci default
+    let (gsi, config) = match ioapic::isa_irq_to_gsi(power.sci_irq) {
+        (gsi, GsiConfig::ISA) => (gsi, SCI_CONFIG),
+        routing => routing,
+    };
+    ioapic::route_gsi(gsi, config, vector, apic::interrupt_destination()).unwrap();
+    log::info!("ACPI power button enabled (sci gsi {gsi}, vector {vector:#x})");
+}
+
+// The firmware hands the fixed hardware over by setting SCI_EN, hardware without smi command port is always
+// in acpi mode
+fn enable_acpi_mode(power: &PowerInfo) -> bool {
+    let Some(control_port) = power.pm1a_control_port else {
+        return false;
+    };
+    let mut control = Port::<u16>::new(control_port);
+    let is_enabled = |control: &mut Port<u16>| unsafe { control.read() } & SCI_EN != 0;
+    if is_enabled(&mut control) {
+        return true;
+    }
+    let (Some(smi_command_port), 1..) = (power.smi_command_port, power.acpi_enable_value) else {
+        return false;
+    };
+    unsafe { Port::<u8>::new(smi_command_port).write(power.acpi_enable_value) };
+    for _ in 0..ACPI_ENABLE_POLLS {
+        if is_enabled(&mut control) {
+            return true;
+        }
+        sched::sleep_ms(1);
+    }
+    false
+}
+
+fn sci_interrupt() -> IrqReturn {
+    let mut power_button = false;
+    for &(status_port, enable_port) in PM1_EVENT_PORTS.get().into_iter().flatten().flatten() {
+        unsafe {
+            let mut status = Port::<u16>::new(status_port);
+            let pending = status.read() & Port::<u16>::new(enable_port).read();
+            // write one to clear, the level triggered sci stays asserted otherwise
+            status.write(pending);
+            power_button |= pending & PWRBTN_STS != 0;
+        }
+    }
+    if power_button {
+        POWER_BUTTON_PRESSED.store(true, Ordering::Release);
+        POWER_BUTTON.wake_all();
+    }
+    IrqReturn::Handled
+}
@@ -0,0 +1,20 @@
+use ps2_8042::{Ps2Controller, Ps2Port};
+use spin::Mutex;
+
+use crate::{interrupts::IrqReturn, keyboard, mouse};
+
+// Shared by the keyboard and the mouse driver, locked with interrupts disabled
+pub static CONTROLLER: Mutex<Ps2Controller> = Mutex::new(Ps2Controller::new());
+
+// Registered for the keyboard and the mouse interrupt.
+// Both devices share the data port, so each interrupt hands every pending byte to the device that sent it
+pub fn interrupt() -> IrqReturn {
+    loop {
+        let data = CONTROLLER.lock().try_read_data_from();
+        match data {
+            Some((Ps2Port::First, scancode)) => keyboard::handle_scancode(scancode),
+            Some((Ps2Port::Second, byte)) => mouse::handle_byte(byte),
+            None => return IrqReturn::Handled,
+        }
+    }
+}
@@ -1,8 +1,52 @@
 use pruefung::Hasher;
 
-use crate::{ass, get_boot_info};
+use crate::{ass, get_boot_info, tar};
+
+// Bootimage writes the ram disk as regions (length, checksum, region count and ends, data) or, with
+// --tar-ram-disk, as a ustar archive
+// indices of the regions written by bootimage (see create_ram_disk)
+pub const CMDLINE: usize = 0;
+pub const MAIN_APP: usize = 1;
+pub const TEST_APP: usize = 2;
+pub const LOGO: usize = 3;
+pub const PIPE_APP: usize = 4;
+pub const FAULT_APP: usize = 5;
+pub const KERNEL_SYMBOLS: usize = 6;
+
+// the region format has no names, these follow the indices above (tar archives use them as paths)
+const FILE_NAMES: [&str; 7] = [
+    "cmdline", "main", "test", "logo", "pipe", "fault", "symbols",
+];
+
+// None if the ram disk does not contain the file (it is older than the kernel)
+pub fn find_file(name: &str) -> Option<&'static [u8]> {
+    let index = file_names().position(|file_name| file_name == name)?;
+    Some(get_file_slice(index))
+}
+
+// names of the files the ram disk contains
+pub fn file_names() -> impl Iterator<Item = &'static str> {
+    FILE_NAMES.into_iter().take(get_file_count())
+}
+
+fn data() -> &'static [u8] {
+    let bootinfo = get_boot_info();
+    let ramdisk_ptr = (*bootinfo.ramdisk_addr.as_ref().unwrap()) as *const u8;
+    unsafe { core::slice::from_raw_parts(ramdisk_ptr, bootinfo.ramdisk_len as usize) }
+}
+
+// the ram disk if it is a tar archive (see fs::TarFs)
+pub fn tar_archive() -> Option<&'static [u8]> {
+    let data = data();
+    tar::is_archive(data).then_some(data)
+}
 
 pub fn get_file_slice(index: usize) -> &'static [u8] {
+    if let Some(archive) = tar_archive() {
+        let name = FILE_NAMES[index];
+        return tar::find(archive, name)
+            .unwrap_or_else(|| panic!("The ram disk has no file {name}"));
+    }
     let bootinfo = get_boot_info();
 
     let ramdisk_ptr = (*bootinfo.ramdisk_addr.as_ref().unwrap()) as *mut u8;
@@ -35,6 +79,12 @@ pub fn get_file_slice(index: usize) -> &'static [u8] {
 }
 
 pub fn get_file_count() -> usize {
+    if let Some(archive) = tar_archive() {
+        return FILE_NAMES
+            .iter()
+            .take_while(|name| tar::find(archive, name).is_some())
+            .count();
+    }
     let bootinfo = get_boot_info();
 
     let ramdisk_ptr = (*bootinfo.ramdisk_addr.as_ref().unwrap()) as *mut u8;
@@ -54,6 +104,13 @@ pub fn assert_soundness() {
     ass!(bootinfo.ramdisk_addr.as_ref().is_some());
     ass!(bootinfo.ramdisk_len, >=, 8+8+8+8+1);
 
+    if let Some(archive) = tar_archive() {
+        ass!(tar::entries(archive).all(|entry| entry.is_ok()));
+        ass!(get_file_count(), >, CMDLINE);
+        log::debug!("Ramdisk ok (tar archive)");
+        return;
+    }
+
     let ramdisk_ptr = (*bootinfo.ramdisk_addr.as_ref().unwrap()) as *mut u8;
 
     #[allow(clippy::cast_ptr_alignment)]
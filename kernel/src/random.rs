@@ -0,0 +1,322 @@
+use core::{
+    arch::x86_64::{_rdseed64_step, _rdtsc},
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::{Mutex, Once};
+use x86_64::instructions::{interrupts, random::RdRand};
+
+use crate::per_core;
+
+// Kernel random numbers: a ChaCha20 (RFC 8439) generator keyed from the entropy sources, which are rdseed or
+// rdrand (if the cpu has them), the jitter of the tsc and the arrival times of interrupts.
+// The key is replaced with generator output after every fill (so earlier output can not be reconstructed)
+// and fresh entropy is mixed in every RESEED_INTERVAL bytes.
+// Works without heap and is interrupt safe, so kaslr can use it before the heap exists
+const RESEED_INTERVAL: u64 = 1024 * 1024;
+// bytes generated per lock of the generator
+const FILL_CHUNK: usize = 4096;
+
+const JITTER_ROUNDS: u32 = 64;
+const JITTER_WORK: u64 = 32;
+const RDSEED_RETRIES: u32 = 16;
+
+// odd, so the mixing is a bijection and no sample can cancel the pool
+const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareSource {
+    RdSeed,
+    RdRand,
+    // only tsc jitter and interrupt timings
+    None,
+}
+
+impl HardwareSource {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::RdSeed => "rdseed",
+            Self::RdRand => "rdrand",
+            Self::None => "tsc",
+        }
+    }
+}
+
+static HARDWARE_SOURCE: Once<HardwareSource> = Once::new();
+
+pub fn hardware_source() -> HardwareSource {
+    *HARDWARE_SOURCE.call_once(|| {
+        if crate::cpuid::features().rdseed {
+            HardwareSource::RdSeed
+        } else if RdRand::new().is_some() {
+            HardwareSource::RdRand
+        } else {
+            HardwareSource::None
+        }
+    })
+}
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed() -> Option<u64> {
+    let mut value = 0;
+    // rdseed fails while the conditioner has no fresh entropy
+    (0..RDSEED_RETRIES).find_map(|_| (_rdseed64_step(&mut value) == 1).then_some(value))
+}
+
+fn hardware_word() -> Option<u64> {
+    match hardware_source() {
+        HardwareSource::RdSeed => unsafe { rdseed() }.or_else(|| RdRand::new()?.get_u64()),
+        HardwareSource::RdRand => RdRand::new()?.get_u64(),
+        HardwareSource::None => None,
+    }
+}
+
+// The duration of the same work varies with caches, the pipeline and interrupts, the low bits of the deltas
+// are folded together
+fn tsc_jitter() -> u64 {
+    let mut sample = 0;
+    for _ in 0..JITTER_ROUNDS {
+        let start = unsafe { _rdtsc() };
+        for i in 0..JITTER_WORK {
+            black_box(i);
+        }
+        let delta = unsafe { _rdtsc() }.wrapping_sub(start);
+        sample = mix(sample, delta);
+    }
+    sample
+}
+
+const fn mix(pool: u64, sample: u64) -> u64 {
+    (pool.rotate_left(7) ^ sample).wrapping_mul(MIX)
+}
+
+// Written by the interrupts of the core, taken by reseeds on any core
+struct CorePool {
+    pool: AtomicU64,
+    samples: AtomicU64,
+}
+
+per_core! {
+    static CORE_POOLS: CorePool = CorePool {
+        pool: AtomicU64::new(0),
+        samples: AtomicU64::new(0),
+    };
+}
+
+// called by the irq dispatch of every interrupt
+pub fn add_interrupt_timing(vector: u8) {
+    let core = &CORE_POOLS[crate::smp::cpu_index() as usize];
+    let sample = unsafe { _rdtsc() } ^ u64::from(vector) << 56;
+    // only this core writes its pool (with interrupts disabled), a reseed racing with it may lose a sample
+    core.pool.store(
+        mix(core.pool.load(Ordering::Relaxed), sample),
+        Ordering::Relaxed,
+    );
+    core.samples.fetch_add(1, Ordering::Relaxed);
+}
+
+// number of interrupt timings added on all cores since the boot
+pub fn interrupt_samples() -> u64 {
+    CORE_POOLS
+        .iter()
+        .map(|core| core.samples.load(Ordering::Relaxed))
+        .sum()
+}
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+// the ChaCha20 block function of RFC 8439 (section 2.3)
+pub fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u8; 64] {
+    let mut initial = [0; 16];
+    initial[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    initial[4..12].copy_from_slice(key);
+    initial[12] = counter;
+    initial[13..].copy_from_slice(nonce);
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut block = [0; 64];
+    for (i, bytes) in block.chunks_exact_mut(4).enumerate() {
+        bytes.copy_from_slice(&state[i].wrapping_add(initial[i]).to_le_bytes());
+    }
+    block
+}
+
+struct Generator {
+    key: [u32; 8],
+    seeded: bool,
+    since_reseed: u64,
+    reseeds: u64,
+}
+
+impl Generator {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            seeded: false,
+            since_reseed: 0,
+            reseeds: 0,
+        }
+    }
+
+    // the entropy is xored into the key, which is then replaced by a block of the mixed key
+    fn reseed(&mut self) {
+        let mut entropy = [0u64; 4];
+        for word in &mut entropy {
+            *word = hardware_word().unwrap_or(0);
+        }
+        entropy[0] ^= tsc_jitter();
+        entropy[1] ^= unsafe { _rdtsc() };
+        for (i, core) in CORE_POOLS.iter().enumerate() {
+            entropy[2 + i % 2] ^= core.pool.swap(0, Ordering::Relaxed);
+        }
+        for (i, word) in entropy.into_iter().enumerate() {
+            self.key[2 * i] ^= word as u32;
+            self.key[2 * i + 1] ^= (word >> 32) as u32;
+        }
+        self.rekey();
+        self.seeded = true;
+        self.since_reseed = 0;
+        self.reseeds += 1;
+    }
+
+    // block 0 of the current key becomes the next key, the output uses the blocks after it
+    fn rekey(&mut self) {
+        let block = chacha20_block(&self.key, 0, &[0; 3]);
+        for (word, bytes) in self.key.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+    }
+
+    // at most FILL_CHUNK bytes
+    fn fill(&mut self, buf: &mut [u8]) {
+        if !self.seeded || self.since_reseed >= RESEED_INTERVAL {
+            self.reseed();
+        }
+        for (counter, chunk) in buf.chunks_mut(64).enumerate() {
+            let block = chacha20_block(&self.key, counter as u32 + 1, &[0; 3]);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        self.rekey();
+        self.since_reseed += buf.len() as u64;
+    }
+}
+
+// Locked with interrupts disabled
+static GENERATOR: Mutex<Generator> = Mutex::new(Generator::new());
+
+// fills buf with random bytes, can be used before the heap is initialized and in interrupt handlers
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(FILL_CHUNK) {
+        interrupts::without_interrupts(|| GENERATOR.lock().fill(chunk));
+    }
+}
+
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+// uniform below bound (which must not be 0)
+pub fn below(bound: u64) -> u64 {
+    scale(next_u64(), bound)
+}
+
+// mixes fresh entropy into the generator, the next fill uses the new key
+pub fn reseed() {
+    interrupts::without_interrupts(|| GENERATOR.lock().reseed());
+}
+
+pub fn reseed_count() -> u64 {
+    interrupts::without_interrupts(|| GENERATOR.lock().reseeds)
+}
+
+// the bias of the multiplication is below bound / 2^64
+fn scale(random: u64, bound: u64) -> u64 {
+    assert!(bound != 0, "random number below 0");
+    ((u128::from(random) * u128::from(bound)) >> 64) as u64
+}
+
+// Fast, seedable generator (xoshiro256**) for numbers that need to be reproducible (test orderings)
+// or cheap, it is not suitable for anything an attacker should not predict
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    // the seed is expanded with splitmix64, so similar seeds give unrelated sequences
+    pub fn seed_from_u64(mut seed: u64) -> Self {
+        let mut state = [0; 4];
+        for word in &mut state {
+            *word = splitmix64(&mut seed);
+        }
+        Self { state }
+    }
+
+    // seeded by the kernel generator
+    pub fn from_entropy() -> Self {
+        Self::seed_from_u64(next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+        result
+    }
+
+    // uniform below bound (which must not be 0)
+    pub fn below(&mut self, bound: u64) -> u64 {
+        scale(self.next_u64(), bound)
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    // Fisher-Yates
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            slice.swap(i, self.below(i as u64 + 1) as usize);
+        }
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(MIX);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
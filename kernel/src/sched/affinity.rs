@@ -0,0 +1,54 @@
+use alloc::vec::Vec;
+
+// Set of cores (cpu indices) a task may run on. A bit per core up to the highest one in the set
+// (all is a flag, it contains cores regardless of how many there are)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreMask {
+    all: bool,
+    words: Vec<u64>,
+}
+
+impl CoreMask {
+    pub const fn all() -> Self {
+        Self {
+            all: true,
+            words: Vec::new(),
+        }
+    }
+
+    pub const fn empty() -> Self {
+        Self {
+            all: false,
+            words: Vec::new(),
+        }
+    }
+
+    pub fn single(core: usize) -> Self {
+        Self::empty().with(core)
+    }
+
+    #[must_use]
+    pub fn with(mut self, core: usize) -> Self {
+        if !self.all {
+            if self.words.len() <= core / 64 {
+                self.words.resize(core / 64 + 1, 0);
+            }
+            self.words[core / 64] |= 1 << (core % 64);
+        }
+        self
+    }
+
+    pub fn contains(&self, core: usize) -> bool {
+        self.all
+            || self
+                .words
+                .get(core / 64)
+                .is_some_and(|word| word & (1 << (core % 64)) != 0)
+    }
+}
+
+impl FromIterator<usize> for CoreMask {
+    fn from_iter<I: IntoIterator<Item = usize>>(cores: I) -> Self {
+        cores.into_iter().fold(Self::empty(), Self::with)
+    }
+}
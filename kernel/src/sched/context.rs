@@ -0,0 +1,56 @@
+use core::arch::asm;
+
+// callee saved registers pushed by switch_context (rbp, rbx, r12-r15)
+const SAVED_REGISTERS: u64 = 6;
+
+// Saves the callee saved registers on the current stack, stores the stack pointer in current_rsp
+// and continues on the stack of the next task (where its own switch_context call returns)
+#[naked]
+pub unsafe extern "C" fn switch_context(current_rsp: *mut u64, next_rsp: u64) {
+    asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+        options(noreturn),
+    )
+}
+
+// first instruction of a new task (switch_context returns here), the stack is 16 byte aligned
+#[naked]
+unsafe extern "C" fn task_entry_trampoline() {
+    asm!(
+        "call {entry}",
+        "ud2",
+        entry = sym super::task_entry,
+        options(noreturn),
+    )
+}
+
+// Prepares a new stack, so switch_context continues with task_entry on it. Returns the initial stack pointer
+pub unsafe fn init_stack(stack_top: u64) -> u64 {
+    let stack_top = stack_top & !0xF;
+    // after popping the registers and the return address the stack pointer is aligned again
+    let rsp = stack_top - (SAVED_REGISTERS + 3) * 8;
+    let frame = rsp as *mut u64;
+    for i in 0..SAVED_REGISTERS as usize {
+        frame.add(i).write(0);
+    }
+    frame
+        .add(SAVED_REGISTERS as usize)
+        .write(task_entry_trampoline as usize as u64);
+    frame.add(SAVED_REGISTERS as usize + 1).write(0);
+    frame.add(SAVED_REGISTERS as usize + 2).write(0);
+    rsp
+}
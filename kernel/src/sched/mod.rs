@@ -0,0 +1,487 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::{boxed::Box, vec::Vec};
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    registers::{control::Cr3, model_specific::FsBase},
+};
+
+use crate::{
+    per_core,
+    smp::{get_cld, try_get_cld},
+};
+
+use task::{Task, TaskKind, TaskQueue};
+
+pub use affinity::CoreMask;
+pub use task::TaskId;
+pub use timer::{now_us, sleep, sleep_ms, sleep_until, sleep_us};
+pub use wait::WaitQueue;
+
+mod affinity;
+mod context;
+mod task;
+mod timer;
+mod wait;
+
+// Interval of the apic timer, every timer interrupt switches to the next ready task of the core
+// (and runs the due timer callbacks).
+// Only applications (ring 3) and the idle task are preempted. Kernel code holds spin locks with interrupts
+// enabled, which exception handlers of a preempted application on the same core might need,
+// so kernel tasks switch with yield_now and exit
+const TIME_SLICE_US: u32 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Switch {
+    Preempt,
+    // only switches if the idle task is running
+    PreemptIdle,
+    Yield,
+    Exit,
+    // blocks until the wait queue is woken up (after the given wake up count)
+    Wait { queue: *const WaitQueue, epoch: u64 },
+    // blocks until the deadline (see now_us)
+    Sleep(u64),
+    // moves to another core of the affinity of the task
+    Migrate,
+}
+
+// the wait queue outlives the switch, the waiting task borrows it until it is woken up
+unsafe impl Send for Switch {}
+
+impl Switch {
+    const fn blocks(self) -> bool {
+        matches!(
+            self,
+            Self::Exit | Self::Wait { .. } | Self::Sleep(_) | Self::Migrate
+        )
+    }
+}
+
+struct RunQueue {
+    current: Option<Box<Task>>,
+    ready: TaskQueue,
+    // runs when no task is ready, never in the ready queue
+    idle: Option<Box<Task>>,
+    // the task switched away from, it is still on its stack until the switch is done (see finish_switch)
+    previous: Option<(Box<Task>, Switch)>,
+    switches: u64,
+    // tasks taken from the ready queues of other cores
+    steals: u64,
+}
+
+impl RunQueue {
+    const fn new() -> Self {
+        Self {
+            current: None,
+            ready: TaskQueue::new(),
+            idle: None,
+            previous: None,
+            switches: 0,
+            steals: 0,
+        }
+    }
+
+    // tasks which want to run on this core (without idle)
+    fn load(&self) -> usize {
+        let current_busy = self
+            .current
+            .as_ref()
+            .is_some_and(|task| task.kind == TaskKind::Spawned);
+        self.ready.len() + usize::from(current_busy)
+    }
+}
+
+// Indexed by cpu index. Locked with interrupts disabled, since the timer interrupt uses them
+per_core! {
+    static RUN_QUEUES: Mutex<RunQueue> = Mutex::new(RunQueue::new());
+}
+
+per_core! {
+    static ONLINE: AtomicBool = AtomicBool::new(false);
+}
+
+// number of cores running the scheduler (lock free, used by panicking cores)
+pub fn online_core_count() -> usize {
+    ONLINE
+        .iter()
+        .filter(|online| online.load(Ordering::Acquire))
+        .count()
+}
+
+// Needs to be called by every core (after the apic and the core local data are initialized, before interrupts
+// are enabled). The current flow of the core becomes its boot task
+pub fn init() {
+    let cpu_index = crate::smp::cpu_index();
+    let boot = Box::new(Task::boot(cpu_index));
+    let idle = Box::new(Task::new("idle", TaskKind::Idle, Box::new(idle_loop)));
+    {
+        let mut queue = RUN_QUEUES[cpu_index as usize].lock();
+        queue.current = Some(boot);
+        queue.idle = Some(idle);
+    }
+    ONLINE[cpu_index as usize].store(true, Ordering::Release);
+
+    crate::apic::get_apic()
+        .start_timer(TIME_SLICE_US, true)
+        .unwrap();
+}
+
+// the interrupt ending the hlt adds the idle time (see smp::end_idle)
+fn idle_loop() {
+    loop {
+        interrupts::disable();
+        crate::smp::enter_idle();
+        interrupts::enable_and_hlt();
+    }
+}
+
+// Runs f in a new task on the core with the least load. The task ends when f returns
+pub fn spawn(name: &'static str, f: impl FnOnce() + Send + 'static) -> TaskId {
+    spawn_on(name, CoreMask::all(), f)
+}
+
+// Like spawn, the task only runs on the given cores
+pub fn spawn_on(
+    name: &'static str,
+    affinity: CoreMask,
+    f: impl FnOnce() + Send + 'static,
+) -> TaskId {
+    let mut task = Box::new(Task::new(name, TaskKind::Spawned, Box::new(f)));
+    task.affinity = affinity;
+    let id = task.id;
+    interrupts::without_interrupts(|| {
+        task.core = least_loaded_core(&task.affinity);
+        make_ready(task);
+    });
+    id
+}
+
+fn least_loaded_core(affinity: &CoreMask) -> usize {
+    try_least_loaded_core(affinity).expect("no core of the affinity runs the scheduler")
+}
+
+// On equal load a core whose hyperthread siblings are idle is preferred (it does not share execution units),
+// then the one which was busy for the shortest time recently
+fn try_least_loaded_core(affinity: &CoreMask) -> Option<usize> {
+    let load = |core: usize| RUN_QUEUES[core].lock().load();
+    (0..ONLINE.len())
+        .filter(|&core| affinity.contains(core) && ONLINE[core].load(Ordering::Acquire))
+        .min_by_key(|&core| {
+            let sibling_load: usize = crate::smp::siblings(core as u64)
+                .map(|sibling| load(sibling as usize))
+                .sum();
+            (load(core), sibling_load, crate::smp::busy_permille(core))
+        })
+}
+
+// Takes the current core out of the scheduler (see smp::set_online_cores): no tasks are placed on it anymore,
+// its timer is stopped and its ready and sleeping tasks move to other cores. Has to be called with interrupts
+// disabled by the task that parks the core, it has to keep running until go_online
+pub fn go_offline() {
+    let cpu_index = get_cld().cpu_index as usize;
+    ONLINE[cpu_index].store(false, Ordering::Release);
+    crate::apic::get_apic().stop_timer();
+    let mut sleepers = timer::take_sleepers(cpu_index);
+    while let Some(task) = sleepers.pop_front() {
+        move_away(task);
+    }
+    move_ready_tasks_away();
+}
+
+// Moves the ready tasks of the offline current core to the online cores of their affinity, tasks without one
+// stay until the core is online again. Has to be called with interrupts disabled
+pub fn move_ready_tasks_away() {
+    let cpu_index = get_cld().cpu_index as usize;
+    let mut ready = core::mem::take(&mut RUN_QUEUES[cpu_index].lock().ready);
+    while let Some(task) = ready.pop_front() {
+        move_away(task);
+    }
+}
+
+// sleeping tasks continue to sleep on the new core, without one they wake up early (and sleep again)
+fn move_away(mut task: Box<Task>) {
+    match try_least_loaded_core(&task.affinity) {
+        Some(core) => {
+            task.core = core;
+            if task.wake_at > now_us() {
+                if let Some(task) = timer::park(core as u64, task) {
+                    make_ready(task);
+                }
+            } else {
+                make_ready(task);
+            }
+        }
+        None => {
+            let cpu_index = task.core;
+            RUN_QUEUES[cpu_index].lock().ready.push_back(task);
+        }
+    }
+}
+
+// Lets the current core run tasks again (after go_offline), has to be called with interrupts disabled
+pub fn go_online() {
+    let cpu_index = get_cld().cpu_index as usize;
+    crate::apic::get_apic()
+        .start_timer(TIME_SLICE_US, true)
+        .unwrap();
+    ONLINE[cpu_index].store(true, Ordering::Release);
+}
+
+// Changes the cores the current task may run on, it moves to one of them if necessary.
+// Boot tasks stay on their core
+pub fn set_affinity(affinity: CoreMask) {
+    interrupts::without_interrupts(|| {
+        let cpu_index = get_cld().cpu_index as usize;
+        let mut queue = RUN_QUEUES[cpu_index].lock();
+        let current = queue
+            .current
+            .as_mut()
+            .expect("the scheduler is not initialized");
+        assert!(
+            current.kind == TaskKind::Spawned,
+            "only spawned tasks can change their affinity"
+        );
+        let stays = affinity.contains(cpu_index);
+        // the previous mask is freed after the run queue is unlocked
+        let previous = core::mem::replace(&mut current.affinity, affinity);
+        drop(queue);
+        drop(previous);
+        if !stays {
+            schedule(Switch::Migrate);
+        }
+    });
+}
+
+// Lets the other ready tasks of this core run, returns immediately if there are none
+pub fn yield_now() {
+    interrupts::without_interrupts(|| {
+        timer::wake_sleepers();
+        schedule(Switch::Yield);
+    });
+}
+
+// called by the timer interrupt (after the end of interrupt was signaled), returns once the task runs again
+pub fn preempt(interrupted_user_mode: bool) {
+    timer::wake_sleepers();
+    schedule(if interrupted_user_mode {
+        Switch::Preempt
+    } else {
+        Switch::PreemptIdle
+    });
+}
+
+// ends the current task
+pub fn exit() -> ! {
+    interrupts::disable();
+    schedule(Switch::Exit);
+    unreachable!("exited task was scheduled again");
+}
+
+pub fn current_task_id() -> Option<TaskId> {
+    let cpu_index = try_get_cld()?.cpu_index;
+    interrupts::without_interrupts(|| {
+        RUN_QUEUES[cpu_index as usize]
+            .lock()
+            .current
+            .as_ref()
+            .map(|task| task.id)
+    })
+}
+
+// whether the current flow can block (otherwise waiting has to spin)
+fn is_running() -> bool {
+    interrupts::are_enabled()
+        && try_get_cld().is_some_and(|cld| ONLINE[cld.cpu_index as usize].load(Ordering::Acquire))
+}
+
+// Puts a woken up task back into the run queue of its core (another one of its affinity if the core was parked
+// in the meantime). Does not allocate, has to be called with interrupts disabled
+fn make_ready(mut task: Box<Task>) {
+    if !ONLINE[task.core].load(Ordering::Acquire) {
+        if let Some(core) = try_least_loaded_core(&task.affinity) {
+            task.core = core;
+        }
+    }
+    RUN_QUEUES[task.core].lock().ready.push_back(task);
+}
+
+// has to be called with interrupts disabled
+fn schedule(switch: Switch) {
+    let Some(cld) = try_get_cld() else {
+        return;
+    };
+    let cpu_index = cld.cpu_index;
+    let mut queue = RUN_QUEUES[cpu_index as usize].lock();
+    let Some(current) = queue.current.as_ref() else {
+        return; // not initialized on this core
+    };
+    let idle_running = current.kind == TaskKind::Idle;
+    if switch == Switch::PreemptIdle && !idle_running {
+        return;
+    }
+    // instead of running the idle task, work of other cores is taken over
+    let may_steal = switch.blocks() || idle_running;
+    let mut next = if let Some(next) = queue.ready.pop_front() {
+        next
+    } else if let Some(stolen) = may_steal.then(|| steal(cpu_index as usize)).flatten() {
+        queue.steals += 1;
+        stolen
+    } else if switch.blocks() {
+        queue.idle.take().expect("idle task is missing")
+    } else {
+        return;
+    };
+    let mut previous = queue.current.take().unwrap();
+
+    // core state of the tasks
+    previous.page_table = Cr3::read();
+    if let Some(application) = &cld.running_application_data {
+        application.save_fpu();
+    }
+    previous.application = cld.running_application_data.take();
+    if Cr3::read() != next.page_table {
+        unsafe { Cr3::write(next.page_table.0, next.page_table.1) };
+    }
+    cld.running_application_data = next.application.take();
+    if let Some(application) = &cld.running_application_data {
+        application.restore_fpu();
+    }
+    previous.fs_base = FsBase::read();
+    FsBase::write(next.fs_base);
+    next.core = cpu_index as usize;
+    crate::interrupts::set_privilege_stack_top(cpu_index, next.privilege_stack_top);
+    crate::syscall::set_kernel_stack(cpu_index, next.privilege_stack_top);
+
+    let previous_rsp = core::ptr::addr_of_mut!(previous.rsp);
+    let next_rsp = next.rsp;
+    queue.current = Some(next);
+    queue.previous = Some((previous, switch));
+    queue.switches += 1;
+    drop(queue);
+
+    unsafe { context::switch_context(previous_rsp, next_rsp) };
+    finish_switch();
+}
+
+// Takes a ready task (which may run on this core) from the core with the most ready tasks.
+// Other run queues are only try-locked, so cores stealing from each other can not deadlock
+fn steal(cpu_index: usize) -> Option<Box<Task>> {
+    let (victim, _) = (0..ONLINE.len())
+        .filter(|&core| core != cpu_index && ONLINE[core].load(Ordering::Acquire))
+        .filter_map(|core| Some((core, RUN_QUEUES[core].try_lock()?.ready.len())))
+        .filter(|&(_, ready)| ready > 0)
+        .max_by_key(|&(_, ready)| ready)?;
+    let mut task = RUN_QUEUES[victim]
+        .try_lock()?
+        .ready
+        .remove_first(|task| task.affinity.contains(cpu_index))?;
+    task.core = cpu_index;
+    Some(task)
+}
+
+// Puts the previous task back into the queue (or where it blocks) once its stack is no longer in use
+fn finish_switch() {
+    let cpu_index = get_cld().cpu_index;
+    let mut queue = RUN_QUEUES[cpu_index as usize].lock();
+    let (mut previous, switch) = queue.previous.take().expect("no task switch in progress");
+    match switch {
+        Switch::Exit => {
+            drop(queue);
+            // frees the stack of the exited task
+            drop(previous);
+        }
+        _ if previous.kind == TaskKind::Idle => queue.idle = Some(previous),
+        Switch::Preempt | Switch::PreemptIdle | Switch::Yield => queue.ready.push_back(previous),
+        // the run queue is locked after the wait queue and the timer wheel
+        Switch::Wait {
+            queue: wait_queue,
+            epoch,
+        } => {
+            drop(queue);
+            // the waiting task still borrows the wait queue
+            if let Some(task) = unsafe { &*wait_queue }.park(previous, epoch) {
+                make_ready(task);
+            }
+        }
+        Switch::Sleep(deadline) => {
+            drop(queue);
+            previous.wake_at = deadline;
+            if let Some(task) = timer::park(cpu_index, previous) {
+                make_ready(task);
+            }
+        }
+        Switch::Migrate => {
+            drop(queue);
+            previous.core = least_loaded_core(&previous.affinity);
+            make_ready(previous);
+        }
+    }
+}
+
+// first function of every new task (called by context::task_entry_trampoline)
+extern "C" fn task_entry() -> ! {
+    finish_switch();
+    let cpu_index = get_cld().cpu_index;
+    let entry = RUN_QUEUES[cpu_index as usize]
+        .lock()
+        .current
+        .as_mut()
+        .and_then(|task| task.entry.take())
+        .expect("new task without entry");
+    interrupts::enable();
+    entry();
+    exit();
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CoreStats {
+    pub core: usize,
+    pub topology: Option<crate::smp::CpuTopology>,
+    pub current: &'static str,
+    pub ready: usize,
+    // tasks which want to run on the core (the ready ones and the current one, without idle)
+    pub load: usize,
+    pub switches: u64,
+    pub steals: u64,
+}
+
+// snapshot of the run queues of all cores running the scheduler
+pub fn core_stats() -> Vec<CoreStats> {
+    (0..ONLINE.len())
+        .filter(|&core| ONLINE[core].load(Ordering::Acquire))
+        .map(|core| {
+            interrupts::without_interrupts(|| {
+                let queue = RUN_QUEUES[core].lock();
+                CoreStats {
+                    core,
+                    topology: crate::smp::topology(core as u64),
+                    current: queue.current.as_ref().map_or("none", |task| task.name),
+                    ready: queue.ready.len(),
+                    load: queue.load(),
+                    switches: queue.switches,
+                    steals: queue.steals,
+                }
+            })
+        })
+        .collect()
+}
+
+pub fn log_stats(level: log::Level) {
+    for stats in core_stats() {
+        let (package, physical_core, thread) = stats.topology.map_or((0, 0, 0), |topology| {
+            (topology.package_id, topology.core_id, topology.thread_id)
+        });
+        log::log!(
+            level,
+            "Core {} (package {package} core {physical_core} thread {thread}): running '{}', {} ready tasks, \
+             {} task switches, {} stolen tasks",
+            stats.core,
+            stats.current,
+            stats.ready,
+            stats.switches,
+            stats.steals
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::{boxed::Box, vec};
+use x86_64::{
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::PhysFrame,
+    VirtAddr,
+};
+
+use crate::{constants::TASK_STACK_SIZE, interrupts::PRIVILEGE_STACK_SIZE, loader};
+
+use super::CoreMask;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(pub u64);
+
+impl TaskId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    // the initial flow of a core (kernel_main or ap_entry_fn) on the core stack
+    Boot,
+    Idle,
+    Spawned,
+}
+
+pub struct Task {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub kind: TaskKind,
+    // saved by switch_context while the task is not running
+    pub rsp: u64,
+    // the top of the stack is reserved for entries from ring 3 (tss rsp0 and syscalls),
+    // so they do not overwrite the frames of loader::run below
+    pub privilege_stack_top: VirtAddr,
+    pub entry: Option<Box<dyn FnOnce() + Send>>,
+    // state of the core, swapped on every switch
+    pub page_table: (PhysFrame, Cr3Flags),
+    pub application: Option<loader::RunningApplicationCLD>,
+    // thread pointer of applications (see loader::map_tls), unused by the kernel
+    pub fs_base: VirtAddr,
+    // core the task ran on last, woken up tasks return to it
+    pub core: usize,
+    pub affinity: CoreMask,
+    // deadline (see timer::now_us) while the task sleeps
+    pub wake_at: u64,
+    // link of the TaskQueue the task is in
+    next: Option<Box<Self>>,
+    // None for boot tasks
+    _stack: Option<Box<[u8]>>,
+}
+
+// the raw pointer in the running application data points to resources owned by the task itself
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl Send for Task {}
+
+impl Task {
+    pub fn boot(cpu_index: u64) -> Self {
+        Self {
+            id: TaskId::next(),
+            name: "boot",
+            kind: TaskKind::Boot,
+            rsp: 0,
+            privilege_stack_top: crate::interrupts::privilege_stack_top(cpu_index),
+            entry: None,
+            page_table: Cr3::read(),
+            application: None,
+            fs_base: VirtAddr::zero(),
+            core: cpu_index as usize,
+            affinity: CoreMask::single(cpu_index as usize),
+            wake_at: 0,
+            next: None,
+            _stack: None,
+        }
+    }
+
+    pub fn new(name: &'static str, kind: TaskKind, entry: Box<dyn FnOnce() + Send>) -> Self {
+        let stack = vec![0u8; TASK_STACK_SIZE as usize].into_boxed_slice();
+        let stack_top = stack.as_ptr() as u64 + TASK_STACK_SIZE;
+        let privilege_stack_top = VirtAddr::new(stack_top).align_down(16u64);
+        let rsp = unsafe { super::context::init_stack(stack_top - PRIVILEGE_STACK_SIZE as u64) };
+        Self {
+            id: TaskId::next(),
+            name,
+            kind,
+            rsp,
+            privilege_stack_top,
+            entry: Some(entry),
+            // tasks start in the kernel address space
+            page_table: (
+                crate::memory::MEMORY.lock().kernel_page_table_frame(),
+                Cr3Flags::empty(),
+            ),
+            application: None,
+            fs_base: VirtAddr::zero(),
+            core: 0,
+            affinity: CoreMask::all(),
+            wake_at: 0,
+            next: None,
+            _stack: Some(stack),
+        }
+    }
+}
+
+// Intrusive fifo of tasks. Pushing never allocates, so tasks can be woken up by interrupt handlers
+// (which might have interrupted the heap)
+pub struct TaskQueue {
+    head: Option<Box<Task>>,
+    tail: *mut Task,
+    len: usize,
+}
+
+// the tail pointer points into the owned list
+unsafe impl Send for TaskQueue {}
+
+impl TaskQueue {
+    pub const fn new() -> Self {
+        Self {
+            head: None,
+            tail: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    pub fn push_back(&mut self, mut task: Box<Task>) {
+        debug_assert!(task.next.is_none());
+        let task_ptr = ptr::addr_of_mut!(*task);
+        match self.head {
+            None => self.head = Some(task),
+            // the heap allocation of the tail does not move while it is in the list
+            Some(_) => unsafe { (*self.tail).next = Some(task) },
+        }
+        self.tail = task_ptr;
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<Box<Task>> {
+        let mut task = self.head.take()?;
+        self.head = task.next.take();
+        if self.head.is_none() {
+            self.tail = ptr::null_mut();
+        }
+        self.len -= 1;
+        Some(task)
+    }
+
+    // removes the first task f returns true for, the order of the others stays the same
+    pub fn remove_first(&mut self, mut f: impl FnMut(&Task) -> bool) -> Option<Box<Task>> {
+        let mut found = None;
+        for _ in 0..self.len {
+            let task = self.pop_front().unwrap();
+            if found.is_none() && f(&task) {
+                found = Some(task);
+            } else {
+                self.push_back(task);
+            }
+        }
+        found
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,136 @@
+use core::hint;
+
+use alloc::boxed::Box;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    per_core,
+    smp::try_get_cld,
+    time::{Duration, Instant},
+};
+
+use super::{
+    task::{Task, TaskQueue},
+    Switch,
+};
+
+// Sleeping tasks of a core are kept in a hashed timer wheel, it is advanced by the timer interrupt
+// (and yield_now), so sleeps end with the first tick after the deadline.
+// Deadlines further away than one turn of the wheel stay in their slot for the following turns
+const WHEEL_SLOTS: usize = 64;
+const WHEEL_TICK_US: u64 = 1_000;
+
+struct TimerWheel {
+    slots: [TaskQueue; WHEEL_SLOTS],
+    // all earlier ticks are processed
+    next_tick: u64,
+    sleepers: usize,
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        const EMPTY_SLOT: TaskQueue = TaskQueue::new();
+        Self {
+            slots: [EMPTY_SLOT; WHEEL_SLOTS],
+            next_tick: 0,
+            sleepers: 0,
+        }
+    }
+
+    // returns the task if its deadline already passed
+    fn insert(&mut self, task: Box<Task>) -> Option<Box<Task>> {
+        let tick = task.wake_at.div_ceil(WHEEL_TICK_US);
+        if tick < self.next_tick {
+            return Some(task);
+        }
+        self.slots[tick as usize % WHEEL_SLOTS].push_back(task);
+        self.sleepers += 1;
+        None
+    }
+
+    fn advance(&mut self, now_us: u64, mut wake: impl FnMut(Box<Task>)) {
+        let now_tick = now_us / WHEEL_TICK_US;
+        if self.sleepers > 0 {
+            // one turn visits every slot
+            let first = self
+                .next_tick
+                .max((now_tick + 1).saturating_sub(WHEEL_SLOTS as u64));
+            for tick in first..=now_tick {
+                let slot = &mut self.slots[tick as usize % WHEEL_SLOTS];
+                for _ in 0..slot.len() {
+                    let task = slot.pop_front().unwrap();
+                    if task.wake_at.div_ceil(WHEEL_TICK_US) <= now_tick {
+                        self.sleepers -= 1;
+                        wake(task);
+                    } else {
+                        slot.push_back(task);
+                    }
+                }
+            }
+        }
+        self.next_tick = self.next_tick.max(now_tick + 1);
+    }
+}
+
+// Indexed by cpu index. Locked with interrupts disabled (before the run queues)
+per_core! {
+    static WHEELS: Mutex<TimerWheel> = Mutex::new(TimerWheel::new());
+}
+
+// Deadlines of sleeping tasks are microseconds of time::Instant
+pub fn now_us() -> u64 {
+    Instant::now().as_us()
+}
+
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now().saturating_add(duration).as_us());
+}
+
+pub fn sleep_ms(ms: u64) {
+    sleep_us(ms.saturating_mul(1000));
+}
+
+pub fn sleep_us(us: u64) {
+    sleep_until(now_us().saturating_add(us));
+}
+
+// Blocks the current task until the deadline (see now_us), other tasks of the core run in the meantime.
+// Spins if the task can not block (interrupts disabled or the scheduler is not running yet)
+pub fn sleep_until(deadline_us: u64) {
+    while now_us() < deadline_us {
+        if super::is_running() {
+            interrupts::without_interrupts(|| super::schedule(Switch::Sleep(deadline_us)));
+        } else {
+            hint::spin_loop();
+        }
+    }
+}
+
+// called by finish_switch with the task that went to sleep, returns it if its deadline already passed
+pub(super) fn park(cpu_index: u64, task: Box<Task>) -> Option<Box<Task>> {
+    WHEELS[cpu_index as usize].lock().insert(task)
+}
+
+// the sleeping tasks of a core going offline, has to be called with interrupts disabled
+pub(super) fn take_sleepers(cpu_index: usize) -> TaskQueue {
+    let mut wheel = WHEELS[cpu_index].lock();
+    let mut sleepers = TaskQueue::new();
+    for slot in &mut wheel.slots {
+        while let Some(task) = slot.pop_front() {
+            sleepers.push_back(task);
+        }
+    }
+    wheel.sleepers = 0;
+    sleepers
+}
+
+// has to be called with interrupts disabled
+pub(super) fn wake_sleepers() {
+    let Some(cld) = try_get_cld() else {
+        return;
+    };
+    WHEELS[cld.cpu_index as usize]
+        .lock()
+        .advance(now_us(), super::make_ready);
+}
@@ -0,0 +1,88 @@
+use core::hint;
+
+use alloc::boxed::Box;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{
+    task::{Task, TaskQueue},
+    Switch,
+};
+
+// Tasks blocked until another task or an interrupt handler wakes them up.
+// Waking up does not allocate, so interrupt handlers can use it
+pub struct WaitQueue {
+    // locked with interrupts disabled (before the run queues)
+    inner: Mutex<Waiters>,
+}
+
+struct Waiters {
+    tasks: TaskQueue,
+    // incremented by every wake up, so a wake up between checking the condition and blocking is not lost
+    epoch: u64,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Waiters {
+                tasks: TaskQueue::new(),
+                epoch: 0,
+            }),
+        }
+    }
+
+    // Blocks the current task until condition returns true, it is checked again after every wake up.
+    // Spins if the task can not block (interrupts disabled or the scheduler is not running yet)
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let epoch = interrupts::without_interrupts(|| self.inner.lock().epoch);
+            if condition() {
+                return;
+            }
+            if super::is_running() {
+                interrupts::without_interrupts(|| {
+                    super::schedule(Switch::Wait { queue: self, epoch });
+                });
+            } else {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    // returns whether a task was woken up
+    pub fn wake_one(&self) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            inner.epoch += 1;
+            let task = inner.tasks.pop_front();
+            drop(inner);
+            task.map(super::make_ready).is_some()
+        })
+    }
+
+    // returns the number of woken up tasks
+    pub fn wake_all(&self) -> usize {
+        interrupts::without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            inner.epoch += 1;
+            let mut tasks = core::mem::take(&mut inner.tasks);
+            drop(inner);
+            let woken = tasks.len();
+            while let Some(task) = tasks.pop_front() {
+                super::make_ready(task);
+            }
+            woken
+        })
+    }
+
+    // called by finish_switch with the task that started waiting, returns it if it was woken up in the meantime
+    pub(super) fn park(&self, task: Box<Task>, epoch: u64) -> Option<Box<Task>> {
+        let mut inner = self.inner.lock();
+        if inner.epoch != epoch {
+            return Some(task);
+        }
+        inner.tasks.push_back(task);
+        None
+    }
+}
@@ -1,284 +1,221 @@
-#![allow(non_camel_case_types)]
-#![allow(non_snake_case)]
-
 use core::{
     fmt::{self, Write},
     hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
-use alloc::string::String;
-use spin::Mutex;
-use x86_64::instructions::port::Port;
-
-#[repr(u16)]
-#[derive(Clone, Copy)]
-pub enum ComPort {
-    COM1 = 0x3f8,
-    COM2 = 0x2f8,
-    COM3 = 0x3e8,
-    COM4 = 0x2e8,
-}
-
-#[repr(u16)]
-#[derive(Clone, Copy)]
-pub enum BaudRate {
-    BAUD_300 = 384,
-    BAUD_600 = 192,
-    BAUD_1200 = 96,
-    BAUD_2400 = 48,
-    BAUD_4800 = 24,
-    BAUD_9600 = 12,
-    BAUD_19200 = 6,
-    BAUD_38400 = 3,
-    BAUD_57600 = 2,
-    BAUD_115200 = 1,
-}
-
-#[repr(u8)]
-#[derive(Clone, Copy)]
-enum DataBits {
-    DATA_5BIT = 0,
-    DATA_6BIT = 1,
-    DATA_7BIT = 2,
-    DATA_8BIT = 3,
-}
-
-mod StopBits {
-    pub(super) const STOP_1BIT: u8 = 0;
-    pub(super) const STOP_1_5BIT: u8 = 4;
-    pub(super) const STOP_2BIT: u8 = 4;
-}
-
-#[repr(u8)]
-#[derive(Clone, Copy)]
-enum Parity {
-    PARITY_NONE = 0,
-    PARITY_ODD = 8,
-    PARITY_EVEN = 24,
-    PARITY_MARK = 40,
-    PARITY_SPACE = 56,
-}
-
-mod ri {
-    // if Divisor Latch Access Bit [DLAB] = 0
-    pub(super) const RECEIVE_BUFFER_REGISTER: u8 = 0;
-    ///< read only
-    pub(super) const TRANSMIT_BUFFER_REGISTER: u8 = 0;
-    ///< write only
-    pub(super) const INTERRUPT_ENABLE_REGISTER: u8 = 1;
-
-    // if Divisor Latch Access Bit [DLAB] = 1
-    pub(super) const DIVISOR_LOW_REGISTER: u8 = 0;
-    pub(super) const DIVISOR_HIGH_REGISTER: u8 = 1;
-
-    // (irrespective from DLAB)
-    pub(super) const INTERRUPT_IDENT_REGISTER: u8 = 2;
-    ///< read only
-    pub(super) const FIFO_CONTROL_REGISTER: u8 = 2;
-    ///< write only -- 16550 and newer (esp. not 8250a)
-    pub(super) const LINE_CONTROL_REGISTER: u8 = 3;
-    ///< highest-order bit is DLAB (see above)
-    pub(super) const MODEM_CONTROL_REGISTER: u8 = 4;
-    pub(super) const LINE_STATUS_REGISTER: u8 = 5;
-    pub(super) const MODEM_STATUS_REGISTER: u8 = 6;
-}
-
-mod RegisterMask {
-    // Interrupt Enable Register
-    pub(super) const RECEIVED_DATA_AVAILABLE: u8 = 1 << 0;
-    pub(super) const TRANSMITTER_HOLDING_REGISTER_EMPTY: u8 = 1 << 1;
-    pub(super) const RECEIVER_LINE_STATUS: u8 = 1 << 2;
-    pub(super) const MODEM_STATUS: u8 = 1 << 3;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts::{self, without_interrupts};
 
-    // Interrupt Ident Register
-    pub(super) const INTERRUPT_PENDING: u8 = 1 << 0;
-    ///< 0 means interrupt pending
-    pub(super) const INTERRUPT_ID_0: u8 = 1 << 1;
-    pub(super) const INTERRUPT_ID_1: u8 = 1 << 2;
-
-    // FIFO Control Register
-    pub(super) const ENABLE_FIFO: u8 = 1 << 0;
-    ///< 0 means disabled ^= conforming to 8250a
-    pub(super) const CLEAR_RECEIVE_FIFO: u8 = 1 << 1;
-    pub(super) const CLEAR_TRANSMIT_FIFO: u8 = 1 << 2;
-    pub(super) const DMA_MODE_SELECT: u8 = 1 << 3;
-    pub(super) const TRIGGER_RECEIVE: u8 = 1 << 6;
-
-    // Line Control Register
-    //  bits per character:  5   6   7   8
-    pub(super) const WORD_LENGTH_SELECT_0: u8 = 1 << 0; //  Setting Select0:     0   1   0   1
-    pub(super) const WORD_LENGTH_SELECT_1: u8 = 1 << 1; //  Setting Select1:     0   0   1   1
-    pub(super) const NUMBER_OF_STOP_BITS: u8 = 1 << 2; //  0 ≙ one stop bit, 1 ≙ 1.5/2 stop bits
-    pub(super) const PARITY_ENABLE: u8 = 1 << 3;
-    pub(super) const EVEN_PARITY_SELECT: u8 = 1 << 4;
-    pub(super) const STICK_PARITY: u8 = 1 << 5;
-    pub(super) const SET_BREAK: u8 = 1 << 6;
-    pub(super) const DIVISOR_LATCH_ACCESS_BIT: u8 = 1 << 7; // DLAB
-
-    // Modem Control Register
-    pub(super) const DATA_TERMINAL_READY: u8 = 1 << 0;
-    pub(super) const REQUEST_TO_SEND: u8 = 1 << 1;
-    pub(super) const OUT_1: u8 = 1 << 2;
-    pub(super) const OUT_2: u8 = 1 << 3; // must be set for interrupts!
-    pub(super) const LOOP: u8 = 1 << 4;
-
-    // Line Status Register
-    pub(super) const DATA_READY: u8 = 1 << 0; // Set when there is a value in the receive buffer
-    pub(super) const OVERRUN_ERROR: u8 = 1 << 1;
-    pub(super) const PARITY_ERROR: u8 = 1 << 2;
-    pub(super) const FRAMING_ERROR: u8 = 1 << 3;
-    pub(super) const BREAK_INTERRUPT: u8 = 1 << 4;
-    pub(super) const TRANSMITTER_HOLDING_REGISTER: u8 = 1 << 5;
-    pub(super) const TRANSMITTER_EMPTY: u8 = 1 << 6; // Send buffer empty (ready to send)
-
-    // Modem Status Register
-    pub(super) const DELTA_CLEAR_TO_SEND: u8 = 1 << 0;
-    pub(super) const DELTA_DATA_SET_READY: u8 = 1 << 1;
-    pub(super) const TRAILING_EDGE_RING_INDICATOR: u8 = 1 << 2;
-    pub(super) const DELTA_DATA_CARRIER_DETECT: u8 = 1 << 3;
-    pub(super) const CLEAR_TO_SEND: u8 = 1 << 4;
-    pub(super) const DATA_SET_READY: u8 = 1 << 5;
-    pub(super) const RING_INDICATOR: u8 = 1 << 6;
-    pub(super) const DATA_CARRIER_DETECT: u8 = 1 << 7;
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum SerialError {
-    Busy,
-    OverrunError,
-    ParityError,
-    FramingError,
-}
-
-fn rr(com: ComPort, index: u8) -> u8 {
-    unsafe { Port::new(com as u16 + index as u16).read() }
-}
-
-fn wr(com: ComPort, index: u8, value: u8) {
-    unsafe { Port::new(com as u16 + index as u16).write(value) }
-}
-
-pub fn init(com: ComPort, baud: BaudRate) {
-    wr(com, ri::LINE_CONTROL_REGISTER, 0b1000_0000); //enable DLAB
-    wr(com, ri::DIVISOR_LOW_REGISTER, (baud as u16 & 0xff) as u8); //set baud rate
-    wr(com, ri::DIVISOR_HIGH_REGISTER, (baud as u16 >> 8) as u8);
-    wr(com, ri::LINE_CONTROL_REGISTER, 0b0000_0011); //8N1 + disable DLAB
-    wr(com, ri::INTERRUPT_ENABLE_REGISTER, 0); //disable interrupts
-    wr(com, ri::MODEM_CONTROL_REGISTER, 0); //disable loopback
-}
+use crate::{
+    apic,
+    interrupts::{IrqHandler, IrqReturn},
+    ioapic::{self, IoApicError},
+    lock_debug::{self, TrackedGuard, TrackedMutex},
+    sched::WaitQueue,
+};
 
-pub struct ReadPort {
+pub use serial_16550::{init, BaudRate, ComPort, ReadPort, SerialError, WritePort};
+
+// The four legacy uarts, each one initialized on its first use with locks of its own. The log (serial_print)
+// and the shell use COM1 unless the command line picks another port (logport=, shellport=),
+// the gdb stub owns COM2. COM3 shares the irq of COM1 and COM4 the one of COM2
+pub static COM1: SerialPort =
+    SerialPort::new(["COM1", "COM1 read", "COM1 write"], ComPort::COM1, 4);
+pub static COM2: SerialPort =
+    SerialPort::new(["COM2", "COM2 read", "COM2 write"], ComPort::COM2, 3);
+pub static COM3: SerialPort =
+    SerialPort::new(["COM3", "COM3 read", "COM3 write"], ComPort::COM3, 4);
+pub static COM4: SerialPort =
+    SerialPort::new(["COM4", "COM4 read", "COM4 write"], ComPort::COM4, 3);
+
+static PORTS: [&SerialPort; 4] = [&COM1, &COM2, &COM3, &COM4];
+const HANDLERS: [IrqHandler; 4] = [
+    interrupt::<0>,
+    interrupt::<1>,
+    interrupt::<2>,
+    interrupt::<3>,
+];
+
+// index into PORTS
+static LOG_PORT: AtomicUsize = AtomicUsize::new(0);
+
+const RECEIVE_BUFFER: usize = 64;
+
+pub struct SerialPort {
+    name: &'static str,
     com: ComPort,
-}
+    isa_irq: u8,
+    initialized: Once,
+    // locked with interrupts disabled (the receive interrupt reads the port)
+    read: TrackedMutex<ReadPort>,
+    write: TrackedMutex<WritePort>,
+    // filled by the receive interrupt once it is enabled, locked with interrupts disabled
+    received: Mutex<Received>,
+    available: WaitQueue,
+    interrupt: AtomicBool,
+}
+
+impl SerialPort {
+    // the names of the port and its locks
+    const fn new([name, read, write]: [&'static str; 3], com: ComPort, isa_irq: u8) -> Self {
+        Self {
+            name,
+            com,
+            isa_irq,
+            initialized: Once::new(),
+            read: lock_debug::tracked(read, ReadPort::new(com)),
+            write: lock_debug::tracked(write, WritePort::new(com)),
+            received: Mutex::new(Received::new()),
+            available: WaitQueue::new(),
+            interrupt: AtomicBool::new(false),
+        }
+    }
 
-pub struct WritePort {
-    com: ComPort,
-}
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn init(&self) {
+        self.initialized
+            .call_once(|| init(self.com, BaudRate::BAUD_115200));
+    }
 
-impl ReadPort {
-    pub const fn new(com: ComPort) -> Self {
-        Self { com }
+    pub fn writer(&self) -> TrackedGuard<'_, WritePort> {
+        self.init();
+        self.write.lock()
     }
 
+    // the bytes received by the interrupt first
     pub fn try_read(&self) -> Result<u8, SerialError> {
-        let status = rr(self.com, ri::LINE_STATUS_REGISTER);
-        if status & RegisterMask::OVERRUN_ERROR != 0 {
-            return Err(SerialError::OverrunError);
-        }
-        if status & RegisterMask::PARITY_ERROR != 0 {
-            return Err(SerialError::ParityError);
-        }
-        if status & RegisterMask::FRAMING_ERROR != 0 {
-            return Err(SerialError::FramingError);
-        }
-        if status & RegisterMask::DATA_READY != 0 {
-            Ok(rr(self.com, ri::RECEIVE_BUFFER_REGISTER))
-        } else {
-            Err(SerialError::Busy)
-        }
+        self.init();
+        without_interrupts(|| {
+            if let Some(byte) = self.received.lock().pop() {
+                return Ok(byte);
+            }
+            self.read.lock().try_read()
+        })
     }
 
+    // blocks the task until a byte is received if the interrupt is enabled, otherwise it spins
     pub fn read(&self) -> Result<u8, SerialError> {
+        if self.has_interrupt() && interrupts::are_enabled() {
+            let mut byte = None;
+            self.available.wait_until(|| {
+                byte = without_interrupts(|| self.received.lock().pop());
+                byte.is_some()
+            });
+            return Ok(byte.unwrap());
+        }
         loop {
             match self.try_read() {
-                Ok(v) => return Ok(v),
-                Err(SerialError::Busy) => {}
-                Err(e) => return Err(e),
+                Err(SerialError::Busy) => hint::spin_loop(),
+                result => return result,
             }
-            hint::spin_loop();
         }
     }
 
-    pub fn read_line(&self) -> Result<String, SerialError> {
-        let mut s = String::new();
-        loop {
-            let c = self.read()?;
-            if c == b'\r' {
-                return Ok(s);
-            }
-            if let Ok(c) = core::str::from_utf8(&[c]) {
-                s += c;
+    pub fn has_interrupt(&self) -> bool {
+        self.interrupt.load(Ordering::Acquire)
+    }
+
+    // needs to be called once (after ioapic::init), only one port of a shared irq can use it
+    pub fn enable_interrupt(&'static self) -> Result<u32, IoApicError> {
+        self.init();
+        let index = PORTS
+            .iter()
+            .position(|port| core::ptr::eq(*port, self))
+            .unwrap();
+        let vector = crate::interrupts::allocate_vector(self.name).unwrap();
+        crate::interrupts::register_irq(vector, self.name, HANDLERS[index]).unwrap();
+        self.interrupt.store(true, Ordering::Release);
+        let gsi = ioapic::route_isa_irq(self.isa_irq, vector, apic::interrupt_destination())?;
+        serial_16550::enable_receive_interrupt(self.com);
+        log::info!(
+            "{} receive interrupt enabled (gsi {gsi}, vector {vector:#x})",
+            self.name
+        );
+        Ok(gsi)
+    }
+
+    fn receive(&self) {
+        let mut received = self.received.lock();
+        // the fifo holds at most 16 bytes
+        for _ in 0..16 {
+            match self.read.lock().try_read() {
+                Ok(byte) => received.push(byte),
+                Err(SerialError::Busy) => break,
+                Err(error) => log::warn!("{}: {error:?}", self.name),
             }
         }
+        drop(received);
+        self.available.wake_all();
     }
 }
 
-impl WritePort {
-    pub const fn new(com: ComPort) -> Self {
-        Self { com }
-    }
+fn interrupt<const INDEX: usize>() -> IrqReturn {
+    PORTS[INDEX].receive();
+    IrqReturn::Handled
+}
 
-    pub fn try_write(&self, value: u8) -> Result<(), SerialError> {
-        let status = rr(self.com, ri::LINE_STATUS_REGISTER);
-        if status & RegisterMask::OVERRUN_ERROR != 0 {
-            return Err(SerialError::OverrunError);
-        }
-        if status & RegisterMask::PARITY_ERROR != 0 {
-            return Err(SerialError::ParityError);
-        }
-        if status & RegisterMask::FRAMING_ERROR != 0 {
-            return Err(SerialError::FramingError);
-        }
-        if status & RegisterMask::TRANSMITTER_HOLDING_REGISTER != 0 {
-            wr(self.com, ri::TRANSMIT_BUFFER_REGISTER, value);
-            Ok(())
-        } else {
-            Err(SerialError::Busy)
+// the newest bytes are dropped if nobody reads them
+struct Received {
+    bytes: [u8; RECEIVE_BUFFER],
+    start: usize,
+    len: usize,
+}
+
+impl Received {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; RECEIVE_BUFFER],
+            start: 0,
+            len: 0,
         }
     }
 
-    pub fn write(&self, value: u8) {
-        while self.try_write(value).is_err() {
-            hint::spin_loop();
+    fn push(&mut self, byte: u8) {
+        if self.len < RECEIVE_BUFFER {
+            self.bytes[(self.start + self.len) % RECEIVE_BUFFER] = byte;
+            self.len += 1;
         }
     }
 
-    pub fn flush(&self) {
-        while rr(self.com, ri::LINE_STATUS_REGISTER) & RegisterMask::TRANSMITTER_EMPTY == 0 {
-            hint::spin_loop();
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
         }
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % RECEIVE_BUFFER;
+        self.len -= 1;
+        Some(byte)
     }
 }
 
-impl fmt::Write for WritePort {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            if c.is_ascii() {
-                self.write(c as u8);
-            }
-        }
-        Ok(())
+// COM1 to COM4
+pub fn port(number: u8) -> Option<&'static SerialPort> {
+    PORTS.get(usize::from(number).checked_sub(1)?).copied()
+}
+
+pub fn log_port() -> &'static SerialPort {
+    PORTS[LOG_PORT.load(Ordering::Relaxed)]
+}
+
+// the port of serial_print and the log
+pub fn set_log_port(number: u8) {
+    if port(number).is_some() {
+        LOG_PORT.store(usize::from(number) - 1, Ordering::Relaxed);
     }
 }
 
-lazy_static::lazy_static! {
-    pub static ref SERIAL: (Mutex<ReadPort>, Mutex<WritePort>) = {
-        init(ComPort::COM1, BaudRate::BAUD_115200);
-        (Mutex::new(ReadPort::new(ComPort::COM1)), Mutex::new(WritePort::new(ComPort::COM1)))
-    };
+// Without the lock, for panics before logging is initialized (the output of other cores may end up in between)
+pub fn print_raw(args: fmt::Arguments) {
+    let port = log_port();
+    port.init();
+    let _ = WritePort::new(port.com).write_fmt(args);
 }
 
 #[doc(hidden)]
 pub fn _print_serial(args: fmt::Arguments) {
-    let _ = SERIAL.1.lock().write_fmt(args);
+    let _ = log_port().writer().write_fmt(args);
 }
@@ -0,0 +1,439 @@
+use core::fmt::{self, Write};
+
+use alloc::{string::String, vec::Vec};
+use log::LevelFilter;
+
+use crate::{
+    acpi,
+    allocator::ALLOCATOR,
+    keyboard, kthread, loader, lock_debug,
+    logging::{self, ModuleFilters},
+    memory::MEMORY,
+    net::{self, TcpStream},
+    power, ram_disk, sched,
+    serial::{self, SerialError, SerialPort},
+    smp,
+    terminal_out::{self, Color, WindowId},
+};
+
+// A line based command interpreter. Lines typed on the keyboard (while its window has the focus) are
+// answered in its window, lines sent over the serial port (shellport= on the command line) are answered there.
+// Tcp connections to REMOTE_SHELL_PORT get the log and can send commands (`nc <address> 2323`)
+const PROMPT: &str = "> ";
+const BACKSPACE: char = '\x08';
+const DELETE: char = '\x7f';
+// without the receive interrupt the serial port is polled
+const SERIAL_POLL_MS: u64 = 20;
+pub const REMOTE_SHELL_PORT: u16 = 2323;
+// remote sessions check for new log lines and commands this often
+const REMOTE_POLL_MS: u64 = 20;
+
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "lists the commands"),
+    ("mem", "memory and heap utilization"),
+    ("ps", "the tasks of each core"),
+    ("top", "the busy and idle time of each core"),
+    (
+        "cores [<count>]",
+        "keeps count cores online and parks the others (shows the online cores without one)",
+    ),
+    (
+        "run <app>",
+        "runs an application of the ram disk and waits for it (lists the files without one)",
+    ),
+    (
+        "loglevel [serial|graphics] <level>",
+        "sets the log level of both outputs or of one",
+    ),
+    (
+        "logfilter [<module>=<level>,...|clear]",
+        "replaces the levels of modules (lists them without filters)",
+    ),
+    ("windows", "lists the windows"),
+    (
+        "locks",
+        "hold times and contention of the tracked locks (needs the lock-debug feature)",
+    ),
+    ("poweroff", "switches the machine off"),
+    ("reboot", "restarts the machine"),
+];
+
+// in a window of its own, which gets the focus
+pub fn start() {
+    let window = terminal_out::create_window();
+    terminal_out::with_window(window, |writer| {
+        writer.clear_color = Color::new(20, 20, 30);
+        writer.clear(None);
+    });
+    terminal_out::focus(window);
+
+    let port = serial::port(crate::cmdline::get().shell_port).unwrap();
+    if let Err(error) = port.enable_interrupt() {
+        log::warn!("{} is polled by the shell: {error:?}", port.name());
+    }
+
+    // detached, they run until the system stops
+    kthread::spawn("shell", move || keyboard_shell(window));
+    kthread::spawn("serial shell", move || serial_shell(port));
+    kthread::spawn("remote shell", remote_shell);
+}
+
+fn keyboard_shell(window: WindowId) {
+    let mut out = WindowOutput(window);
+    let events = keyboard::subscribe();
+    let mut line = String::new();
+    let _ = write!(out, "Steelmind shell, type help for the commands\n{PROMPT}");
+    loop {
+        let Some(c) = events.next().char() else {
+            continue;
+        };
+        if terminal_out::focused() != window {
+            continue;
+        }
+        if let Some(line) = edit(&mut line, c, &mut out) {
+            let _ = execute(&line, &mut out);
+            let _ = write!(out, "{PROMPT}");
+        }
+    }
+}
+
+fn serial_shell(port: &'static SerialPort) {
+    let mut out = SerialOutput(port);
+    let mut line = String::new();
+    loop {
+        let byte = if port.has_interrupt() {
+            port.read()
+        } else {
+            port.try_read()
+        };
+        match byte {
+            Ok(byte) if byte.is_ascii() => {
+                // terminals send a carriage return for enter
+                let c = if byte == b'\r' {
+                    '\n'
+                } else {
+                    char::from(byte)
+                };
+                if let Some(line) = edit(&mut line, c, &mut out) {
+                    let _ = execute(&line, &mut out);
+                    let _ = write!(out, "{PROMPT}");
+                }
+            }
+            Ok(_) => {}
+            Err(SerialError::Busy) => sched::sleep_ms(SERIAL_POLL_MS),
+            Err(error) => log::warn!("Serial shell input: {error:?}"),
+        }
+    }
+}
+
+fn remote_shell() {
+    let mut listener = match net::TcpListener::bind(REMOTE_SHELL_PORT) {
+        Ok(listener) => listener,
+        Err(error) => {
+            log::warn!("No remote shell on tcp port {REMOTE_SHELL_PORT}: {error:?}");
+            return;
+        }
+    };
+    log::info!("Remote shell on tcp port {REMOTE_SHELL_PORT}");
+    loop {
+        let stream = listener.accept();
+        kthread::spawn("remote shell session", move || remote_session(&stream));
+    }
+}
+
+// Sends the kept log lines and then every new one, runs the lines received (the client echoes them itself).
+// Returns when the client closes the connection
+pub fn remote_session(stream: &TcpStream) {
+    // None if the client already reset the connection
+    let Some(peer) = stream.remote_endpoint() else {
+        return;
+    };
+    log::info!("Remote shell session of {peer}");
+    let mut out = String::from("Steelmind shell, type help for the commands\n");
+    let mut line = String::new();
+    let mut next_line = 0;
+    let mut buffer = [0; 256];
+    loop {
+        // the log first, so the answer to a command follows the lines it logged
+        next_line = logging::lines_since(next_line, |log_line| {
+            out.push_str(log_line);
+            out.push('\n');
+        });
+        if !out.is_empty() && stream.send_all(out.as_bytes()).is_err() {
+            break;
+        }
+        out.clear();
+
+        match stream.try_recv(&mut buffer) {
+            None => sched::sleep_ms(REMOTE_POLL_MS),
+            Some(0) => break,
+            Some(len) => {
+                for &byte in &buffer[..len] {
+                    match byte {
+                        b'\n' => {
+                            let _ = execute(&core::mem::take(&mut line), &mut out);
+                        }
+                        // terminals end lines with a carriage return and a line feed
+                        byte if byte.is_ascii_control() && byte != b'\t' => {}
+                        byte if byte.is_ascii() => line.push(char::from(byte)),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    log::info!("Remote shell session of {peer} closed");
+}
+
+// Adds the character to the line and echoes it. Returns the line once it is complete
+fn edit(line: &mut String, c: char, out: &mut impl Output) -> Option<String> {
+    match c {
+        '\n' => {
+            let _ = writeln!(out);
+            return Some(core::mem::take(line));
+        }
+        BACKSPACE | DELETE => {
+            if line.pop().is_some() {
+                out.erase_char();
+            }
+        }
+        c if c.is_control() && c != '\t' => {}
+        c => {
+            line.push(c);
+            let _ = out.write_char(c);
+        }
+    }
+    None
+}
+
+// where the echo and the answers go
+trait Output: Write {
+    fn erase_char(&mut self);
+}
+
+struct WindowOutput(WindowId);
+
+impl Write for WindowOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        terminal_out::with_window(self.0, |writer| writer.print(format_args!("{s}")));
+        Ok(())
+    }
+}
+
+impl Output for WindowOutput {
+    fn erase_char(&mut self) {
+        terminal_out::with_window(self.0, terminal_out::TerminalWriter::erase_char);
+    }
+}
+
+struct SerialOutput(&'static SerialPort);
+
+impl Write for SerialOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut port = self.0.writer();
+        // terminals expect a carriage return before a line feed
+        for part in s.split_inclusive('\n') {
+            port.write_str(part.trim_end_matches('\n'))?;
+            if part.ends_with('\n') {
+                port.write_str("\r\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Output for SerialOutput {
+    fn erase_char(&mut self) {
+        let _ = self.0.writer().write_str("\x08 \x08");
+    }
+}
+
+// runs the command of the line (empty lines do nothing)
+pub fn execute(line: &str, out: &mut impl Write) -> fmt::Result {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [] => Ok(()),
+        ["help"] => {
+            for (command, description) in COMMANDS {
+                writeln!(out, "  {command:<40} {description}")?;
+            }
+            Ok(())
+        }
+        ["mem"] => mem(out),
+        ["ps"] => ps(out),
+        ["top"] => top(out),
+        ["cores"] => {
+            let core_count = acpi::ACPI.lock().ap_count + 1;
+            let online = (0..core_count)
+                .filter(|&core| !smp::is_parked(core))
+                .count();
+            writeln!(out, "{online} of {core_count} cores online")
+        }
+        ["cores", count] => match count.parse() {
+            Ok(count) => writeln!(out, "{} cores online", smp::set_online_cores(count)),
+            Err(_) => writeln!(out, "invalid core count '{count}'"),
+        },
+        ["run"] => {
+            for name in ram_disk::file_names() {
+                writeln!(out, "  {name}")?;
+            }
+            Ok(())
+        }
+        ["run", name] => run(name, out),
+        ["loglevel", level] => set_log_level(None, level, out),
+        ["loglevel", output @ ("serial" | "graphics"), level] => {
+            set_log_level(Some(*output), level, out)
+        }
+        ["logfilter"] => {
+            let mut result = Ok(());
+            logging::module_filters(|module, level| {
+                result = result.and_then(|()| writeln!(out, "  {module}={level}"));
+            });
+            result
+        }
+        ["logfilter", "clear"] => {
+            logging::set_module_filters(ModuleFilters::new());
+            Ok(())
+        }
+        ["logfilter", spec] => match ModuleFilters::parse(spec) {
+            Ok(filters) => {
+                logging::set_module_filters(filters);
+                Ok(())
+            }
+            Err(error) => writeln!(out, "invalid filters '{spec}': {error:?}"),
+        },
+        ["windows"] => windows(out),
+        ["locks"] => locks(out),
+        ["poweroff"] => power::shutdown(),
+        ["reboot"] => power::reboot(),
+        _ => writeln!(out, "unknown command '{line}', type help for the commands"),
+    }
+}
+
+fn mem(out: &mut impl Write) -> fmt::Result {
+    let ((used, total), dma_free) = {
+        let memory = MEMORY.lock();
+        (
+            memory.get_memory_utilization(),
+            memory.dma_pool_free_frames(),
+        )
+    };
+    writeln!(
+        out,
+        "frames: {used}/{total} used, {}MB free of {}MB",
+        (total - used) * 4096 / 1024 / 1024,
+        total * 4096 / 1024 / 1024
+    )?;
+    writeln!(out, "dma pool: {dma_free} frames free")?;
+    writeln!(out, "heap: {}KiB used", ALLOCATOR.heap_used() / 1024)
+}
+
+fn ps(out: &mut impl Write) -> fmt::Result {
+    for stats in sched::core_stats() {
+        writeln!(
+            out,
+            "core {:>2}: running '{}', {} ready, {} switches, {} stolen",
+            stats.core, stats.current, stats.ready, stats.switches, stats.steals
+        )?;
+    }
+    Ok(())
+}
+
+fn top(out: &mut impl Write) -> fmt::Result {
+    for stats in smp::load_stats() {
+        if stats.parked {
+            writeln!(out, "core {:>2}: parked", stats.core)?;
+            continue;
+        }
+        writeln!(
+            out,
+            "core {:>2}: {:>3}.{}% busy, {}.{:03}s busy, {}.{:03}s idle",
+            stats.core,
+            stats.busy_permille / 10,
+            stats.busy_permille % 10,
+            stats.busy.as_secs(),
+            stats.busy.subsec_millis(),
+            stats.idle.as_secs(),
+            stats.idle.subsec_millis()
+        )?;
+    }
+    Ok(())
+}
+
+fn run(name: &str, out: &mut impl Write) -> fmt::Result {
+    match loader::spawn(name) {
+        Ok(app) => {
+            let status = app.join();
+            writeln!(
+                out,
+                "'{name}' exited with {} ({:?})",
+                status.code, status.cause
+            )
+        }
+        Err(error) => writeln!(out, "'{name}' could not be started: {error:?}"),
+    }
+}
+
+fn set_log_level(output: Option<&str>, level: &str, out: &mut impl Write) -> fmt::Result {
+    let Ok(level) = level.parse::<LevelFilter>() else {
+        return writeln!(
+            out,
+            "unknown level '{level}' (off, error, warn, info, debug or trace)"
+        );
+    };
+    if output != Some("graphics") {
+        logging::set_serial_log_level(level);
+    }
+    if output != Some("serial") {
+        logging::set_graphics_log_level(level);
+    }
+    Ok(())
+}
+
+fn locks(out: &mut impl Write) -> fmt::Result {
+    let locks = lock_debug::locks();
+    if locks.is_empty() {
+        return writeln!(
+            out,
+            "no tracked locks (the kernel is built without lock-debug)"
+        );
+    }
+    for lock in locks {
+        write!(
+            out,
+            "{:<16} {} locked, {} contended, hold max {}us total {}us",
+            lock.name, lock.acquisitions, lock.contended, lock.max_hold_us, lock.total_hold_us
+        )?;
+        match lock.owner {
+            Some((core, location)) => writeln!(
+                out,
+                ", held by core {core} for {}us at {location}, {} waiting",
+                lock.held_us, lock.waiters
+            )?,
+            None => writeln!(out)?,
+        }
+    }
+    Ok(())
+}
+
+fn windows(out: &mut impl Write) -> fmt::Result {
+    let focused = terminal_out::focused();
+    for window in terminal_out::window_stats() {
+        writeln!(
+            out,
+            "{:?}: {}x{} at {},{}{}{}",
+            window.id,
+            window.size.0,
+            window.size.1,
+            window.position.0,
+            window.position.1,
+            if window.tiled { "" } else { ", floating" },
+            if window.id == focused {
+                ", focused"
+            } else {
+                ""
+            },
+        )?;
+    }
+    Ok(())
+}
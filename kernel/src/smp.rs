@@ -1,34 +1,54 @@
 use core::{
     any::Any,
+    arch::x86_64::{__cpuid, __cpuid_count},
     cell::OnceCell,
     num::NonZeroU64,
     ptr,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
 use alloc::{boxed::Box, vec::Vec};
-use spin::{Barrier, Once};
+use spin::Once;
 use x86_64::{
     align_up,
-    instructions::hlt,
+    instructions::interrupts::without_interrupts,
     structures::paging::{Page, PageTableFlags, PhysFrame, Size4KiB},
     PhysAddr, VirtAddr,
 };
 
 use crate::{
     acpi::ACPI,
-    apic::{
-        get_apic,
-        ipi::{create_send_init_cmd, create_startup_cmd},
-    },
+    apic::{get_apic, ipi::create_broadcast_cmd},
     ass,
-    constants::{v, KERNEL_STACK_SIZE, MAX_CORES},
+    constants::KERNEL_STACK_SIZE,
     interrupts,
-    memory::MEMORY,
+    memory::{register_area, Backing, VirtualMemoryArea, MEMORY},
+    per_core,
+    sched::CoreMask,
+    time::Duration,
 };
 
 static AP_CORE_COUNTER: AtomicU64 = AtomicU64::new(0);
-static AP_STARTUP_DONE_COUNTER: AtomicU64 = AtomicU64::new(0);
+// The startup attempt of the bsp (see attempt), the ap swaps it for AP_ENTERED in ap_entry_fn and waits for
+// AP_RELEASE afterwards (the core count is known then). An ap of an attempt that timed out (or one that got
+// another index, because such an ap took one) finds a different value and halts
+static AP_ATTEMPT: AtomicU64 = AtomicU64::new(NO_ATTEMPT);
+const NO_ATTEMPT: u64 = u64::MAX;
+const AP_ENTERED: u64 = u64::MAX - 1;
+static AP_RELEASE: AtomicBool = AtomicBool::new(false);
+
+// written by the trampoline (ApStage), the ap is started after the bsp cleared it
+const STAGE_ADDR: usize = 0x0B40;
+// after the startup ipis
+const AP_ENTRY_TIMEOUT_US: u16 = 50_000;
+
+// how far an ap got before it timed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ApStage {
+    NotStarted,
+    Trampoline,
+    LongMode,
+}
 
 const CODE: &[u8] = include_bytes!("../smp_trampoline/ap.bin");
 pub fn init_smp() {
@@ -38,7 +58,12 @@ pub fn init_smp() {
     // ; 0x0B10 u64 stride of the stack
     // ; 0x0B20 u64 base address of the stack
     // ; 0x0B30 u64 address of the entry point for rust kernel ap function
+    // ; 0x0B40 u8  stage reached by the ap
+    let ap_core_count = ACPI.lock().ap_count;
+    per_core::allocate_aps(ap_core_count as usize);
+    allocate_mailboxes();
     allocate_stacks();
+    interrupts::prepare_ap_gdts();
 
     let l4_page_table_phys_addr = crate::memory::active_level_4_table_phys_addr();
     ass!(l4_page_table_phys_addr, <, 0xffff_ffff, "page table is not addressable with 32bits");
@@ -47,7 +72,7 @@ pub fn init_smp() {
 
     let stack_stride = calc_stack_stride();
 
-    let stack_base = v::KERNEL_AP_STACKS + stack_stride - 1;
+    let stack_base = crate::kaslr::layout().ap_stacks_start() + stack_stride - 1;
 
     let entry_function_addr = (ap_entry_fn as *const ()) as u64;
 
@@ -75,36 +100,53 @@ pub fn init_smp() {
         *(0x0B30 as *mut u64) = entry_function_addr;
     }
 
-    startup_aps();
-
-    let ap_core_count = ACPI.lock().ap_count;
-    let mut time_out = 1_000_000;
+    let started = startup_aps();
 
-    while AP_STARTUP_DONE_COUNTER.load(Ordering::Acquire) < ap_core_count && time_out > 0 {
-        core::hint::spin_loop();
-        time_out -= 1;
-    }
+    let (_, pending) = unsafe {
+        MEMORY
+            .lock()
+            .unmap(Page::<Size4KiB>::from_start_address(VirtAddr::new(0)).unwrap())
+    };
+    pending.flush();
 
-    {
-        let mut mem = MEMORY.lock();
-        unsafe { mem.unmap(Page::<Size4KiB>::from_start_address(VirtAddr::new(0)).unwrap()) };
-    }
-
-    if time_out == 0 {
-        panic!("AP startup timed out");
-    } else {
+    // the kernel continues with the started cores (they got the first cpu indices)
+    ACPI.lock().ap_count = started;
+    AP_RELEASE.store(true, Ordering::Release);
+    if started == ap_core_count {
         log::info!("All aps started");
+    } else {
+        log::error!("{started} of {ap_core_count} aps started");
     }
 }
 
 fn allocate_stacks() {
     log::trace!("Allocating stacks for APs");
     let ap_core_count = ACPI.lock().ap_count;
-    let mut mem = MEMORY.lock();
 
     let pages_per_core = align_up(KERNEL_STACK_SIZE, 4096) / 4096;
 
-    let mut virt_addr = v::KERNEL_AP_STACKS;
+    // registered before locking MEMORY, since registering might grow the kernel heap
+    for i in 0..ap_core_count {
+        let guard_page = crate::kaslr::layout().ap_stacks_start() + i * calc_stack_stride();
+        register_area(VirtualMemoryArea::new(
+            "ap stack guard",
+            guard_page..guard_page + 4096,
+            PageTableFlags::empty(),
+            Backing::Guard,
+        ))
+        .unwrap();
+        crate::kernel_stack::register(i + 1, guard_page + 4096..guard_page + calc_stack_stride());
+        register_area(VirtualMemoryArea::new(
+            "ap stack",
+            guard_page + 4096..guard_page + calc_stack_stride(),
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Ram,
+        ))
+        .unwrap();
+    }
+
+    let mut mem = MEMORY.lock();
+    let mut virt_addr = crate::kaslr::layout().ap_stacks_start();
 
     for _ in 0..ap_core_count {
         virt_addr += 4096; // add guard page
@@ -122,27 +164,100 @@ const fn calc_stack_stride() -> u64 {
     (pages_per_core + 1) * 4096
 }
 
-fn startup_aps() {
-    log::debug!("Starting APs: sending init and startup commands");
+// the apic is not set up when the ap enters, cpuid knows the id as well (the x2apic one with the topology leaf)
+fn initial_apic_id() -> u32 {
+    let topology = (unsafe { __cpuid(0) }.eax >= CPUID_TOPOLOGY_LEAF)
+        .then(|| unsafe { __cpuid_count(CPUID_TOPOLOGY_LEAF, 0) })
+        .filter(|topology| topology.ebx != 0);
+    if let Some(topology) = topology {
+        topology.edx
+    } else {
+        unsafe { __cpuid(1) }.ebx >> 24
+    }
+}
 
-    let mut apic = get_apic();
+// One at a time, so the stage the trampoline reports belongs to a single ap. Returns the number of started aps
+fn startup_aps() -> u64 {
+    let apic_ids = ACPI.lock().processor_apic_ids.clone();
+    let mut started = 0;
+    // the first one is the bsp
+    for &apic_id in &apic_ids[1..] {
+        log::debug!("Starting AP with apic id {apic_id} (ap index {started})");
+        match startup_ap(apic_id, started) {
+            Ok(()) => started += 1,
+            Err(stage) => {
+                log::error!("AP with apic id {apic_id} did not start (reached {stage:?})");
+            }
+        }
+    }
+    started
+}
 
-    let init_cmd = create_send_init_cmd();
-    let startup_cmd = create_startup_cmd(0);
-    // println!("init_cmd: {:#x} {:#?}", init_cmd.0, init_cmd);
-    // println!("startup_cmd: {:#x} {:#?}", startup_cmd.0, startup_cmd);
+const fn attempt(apic_id: u32, ap_index: u64) -> u64 {
+    ((apic_id as u64) << 32) | ap_index
+}
 
-    apic.write_interrupt_command(init_cmd);
+fn startup_ap(apic_id: u32, ap_index: u64) -> Result<(), ApStage> {
+    let mut apic = get_apic();
+    unsafe { (STAGE_ADDR as *mut u8).write_volatile(0) };
+    // the trampoline increments it, an ap which failed after that doesn't leave a gap
+    AP_CORE_COUNTER.store(ap_index, Ordering::Release);
+    let attempt = attempt(apic_id, ap_index);
+    AP_ATTEMPT.store(attempt, Ordering::Release);
+    let entered = || AP_ATTEMPT.load(Ordering::Acquire) == AP_ENTERED;
+
+    apic.send_init(apic_id);
     crate::pit::delay(10_000).unwrap();
-    apic.write_interrupt_command(startup_cmd);
-    crate::pit::delay(200).unwrap();
-    apic.write_interrupt_command(startup_cmd);
+    for _ in 0..2 {
+        apic.send_startup(0, apic_id);
+        crate::pit::delay(200).unwrap();
+        if entered() {
+            return Ok(());
+        }
+    }
+    for _ in 0..AP_ENTRY_TIMEOUT_US / 1000 {
+        if entered() {
+            return Ok(());
+        }
+        crate::pit::delay(1000).unwrap();
+    }
+    // fails if the ap entered just now, otherwise it can't enter anymore
+    if AP_ATTEMPT
+        .compare_exchange(attempt, NO_ATTEMPT, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return Ok(());
+    }
 
-    log::debug!("Starting APs: commands sent");
+    // back to waiting for a startup ipi, so it doesn't enter the kernel with the index of the next one
+    apic.send_init(apic_id);
+    Err(match unsafe { (STAGE_ADDR as *const u8).read_volatile() } {
+        0 => ApStage::NotStarted,
+        1 => ApStage::Trampoline,
+        _ => ApStage::LongMode,
+    })
 }
 
 unsafe extern "C" fn ap_entry_fn(ap_index: u64) -> ! {
-    AP_STARTUP_DONE_COUNTER.fetch_add(1, core::sync::atomic::Ordering::AcqRel);
+    if AP_ATTEMPT
+        .compare_exchange(
+            attempt(initial_apic_id(), ap_index),
+            AP_ENTERED,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        )
+        .is_err()
+    {
+        // not the attempt the bsp waits for, it stays halted
+        loop {
+            x86_64::instructions::interrupts::disable();
+            x86_64::instructions::hlt();
+        }
+    }
+    while !AP_RELEASE.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    crate::apic::enable_mode();
 
     log::info!(
         "Core started: index({}) apic_id({})",
@@ -151,6 +266,9 @@ unsafe extern "C" fn ap_entry_fn(ap_index: u64) -> ! {
     );
 
     interrupts::init_gdt_and_exceptions_ap(ap_index);
+    let features = crate::cpuid::init(ap_index + 1);
+    crate::user_access::init(features);
+    crate::fpu::init();
 
     log::debug!(
         "Exceptions setup: index({}) apic_id({})",
@@ -162,6 +280,7 @@ unsafe extern "C" fn ap_entry_fn(ap_index: u64) -> ! {
         cpu_index: ap_index + 1,
         ..Default::default()
     });
+    crate::syscall::init();
 
     log::debug!(
         "Core local data initialized: index({}) apic_id({})",
@@ -171,6 +290,8 @@ unsafe extern "C" fn ap_entry_fn(ap_index: u64) -> ! {
 
     crate::apic::init();
 
+    init_remote_calls();
+    crate::sched::init();
     x86_64::instructions::interrupts::enable();
 
     log::info!(
@@ -188,9 +309,8 @@ unsafe extern "C" fn ap_entry_fn(ap_index: u64) -> ! {
     #[cfg(feature = "testing")]
     crate::tester::ap_test_main();
 
-    loop {
-        hlt();
-    }
+    // the idle task of this core takes over (and runs or steals the spawned tasks)
+    crate::sched::exit()
 }
 
 pub fn sync_cores_barrier() {
@@ -198,22 +318,226 @@ pub fn sync_cores_barrier() {
         "syncing all aps and bsp (core with apic id {} arrived)",
         get_apic().id()
     );
-    let barrier =
-        SYNC_STARTUP_BARRIER.call_once(|| Barrier::new(ACPI.lock().ap_count as usize + 1));
-
-    if barrier.wait().is_leader() {
+    let barrier = crate::sync::barrier("cores", ACPI.lock().ap_count as usize + 1);
+    if barrier.wait() {
         log::info!("All cores synced");
     }
 }
 
-static SYNC_STARTUP_BARRIER: Once<Barrier> = Once::new();
+// Parked aps halt outside of the scheduler (without timer interrupts) until they are woken up again
+// with a remote call. They still handle remote calls and nmis in the meantime.
+// Both indexed by cpu index: the requested state and the one the core reached
+per_core! {
+    static PARK_REQUESTED: AtomicBool = AtomicBool::new(false);
+    static PARKED: AtomicBool = AtomicBool::new(false);
+}
+
+// Keeps the cores with a cpu index below count running and parks the others (the bsp is never parked), e.g. to
+// measure how something scales without rebooting with another core count. Their tasks move to the online cores
+// (tasks bound to parked cores and their timer callbacks wait for them).
+// Returns the number of online cores once all cores reached their state
+pub fn set_online_cores(count: usize) -> usize {
+    let core_count = ACPI.lock().ap_count as usize + 1;
+    let count = count.clamp(1, core_count);
+    for core in 1..core_count {
+        let park = core >= count;
+        if PARK_REQUESTED[core].swap(park, Ordering::AcqRel) == park {
+            continue;
+        }
+        if park {
+            crate::sched::spawn_on("park", CoreMask::single(core), move || park(core));
+        } else {
+            // the interrupt ends the hlt of the parked core
+            call_on(core as u64, || {}).unwrap();
+        }
+    }
+    while (1..core_count).any(|core| {
+        PARKED[core].load(Ordering::Acquire) != PARK_REQUESTED[core].load(Ordering::Acquire)
+    }) {
+        crate::sched::yield_now();
+        core::hint::spin_loop();
+    }
+    log::info!("{count} of {core_count} cores online");
+    count
+}
+
+pub fn is_parked(cpu_index: u64) -> bool {
+    PARKED
+        .get(cpu_index as usize)
+        .is_some_and(|parked| parked.load(Ordering::Acquire))
+}
 
-// must be called by each core
+// runs in a task bound to the core until it is woken up
+fn park(cpu_index: usize) {
+    without_interrupts(|| {
+        // woken up before the task ran
+        if !PARK_REQUESTED[cpu_index].load(Ordering::Acquire) {
+            return;
+        }
+        crate::sched::go_offline();
+        crate::watchdog::set_parked(cpu_index as u64, true);
+        PARKED[cpu_index].store(true, Ordering::Release);
+        log::debug!("Core {cpu_index} parked");
+        while PARK_REQUESTED[cpu_index].load(Ordering::Acquire) {
+            // tasks placed on the core while it went offline
+            crate::sched::move_ready_tasks_away();
+            enter_idle();
+            x86_64::instructions::interrupts::enable_and_hlt();
+            x86_64::instructions::interrupts::disable();
+        }
+        crate::watchdog::set_parked(cpu_index as u64, false);
+        crate::sched::go_online();
+        PARKED[cpu_index].store(false, Ordering::Release);
+        log::debug!("Core {cpu_index} online");
+    });
+}
+
+// Time stamp counter ticks each core spent halted in its idle task (or parked). The idle task marks when it
+// halts and the interrupt waking it up adds the time (see end_idle), the timer interrupt computes the share
+// of busy time over the last window
+const LOAD_WINDOW_MS: u64 = 500;
+
+struct CoreUsage {
+    // tsc when the core halted, 0 while it runs
+    idle_since: AtomicU64,
+    idle_ticks: AtomicU64,
+    // tsc of the first timer interrupt
+    started: AtomicU64,
+    window_start: AtomicU64,
+    window_idle_ticks: AtomicU64,
+    busy_permille: AtomicU64,
+}
+
+impl CoreUsage {
+    const fn new() -> Self {
+        Self {
+            idle_since: AtomicU64::new(0),
+            idle_ticks: AtomicU64::new(0),
+            started: AtomicU64::new(0),
+            window_start: AtomicU64::new(0),
+            window_idle_ticks: AtomicU64::new(0),
+            busy_permille: AtomicU64::new(0),
+        }
+    }
+}
+
+per_core! {
+    static CORE_USAGE: CoreUsage = CoreUsage::new();
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+// called with interrupts disabled right before the core halts without work
+pub fn enter_idle() {
+    let usage = &CORE_USAGE[cpu_index() as usize];
+    // an nmi doesn't end the idle time
+    if usage.idle_since.load(Ordering::Relaxed) == 0 {
+        usage.idle_since.store(rdtsc(), Ordering::Relaxed);
+    }
+}
+
+// called by every interrupt (before its handler runs)
+pub fn end_idle(cpu_index: usize) {
+    let usage = &CORE_USAGE[cpu_index];
+    let since = usage.idle_since.swap(0, Ordering::Relaxed);
+    if since != 0 {
+        usage
+            .idle_ticks
+            .fetch_add(rdtsc().saturating_sub(since), Ordering::Relaxed);
+    }
+}
+
+// called by the timer interrupt of every core
+pub fn account_tick(cpu_index: u64) {
+    let usage = &CORE_USAGE[cpu_index as usize];
+    let now = rdtsc();
+    let window_start = usage.window_start.load(Ordering::Relaxed);
+    if window_start == 0 {
+        usage.started.store(now, Ordering::Relaxed);
+        usage.window_start.store(now, Ordering::Relaxed);
+        return;
+    }
+    let window = now - window_start;
+    if window < crate::apic::tsc_ticks_per_second() * LOAD_WINDOW_MS / 1000 {
+        return;
+    }
+    let idle_ticks = usage.idle_ticks.load(Ordering::Relaxed);
+    let idle = idle_ticks - usage.window_idle_ticks.swap(idle_ticks, Ordering::Relaxed);
+    usage.busy_permille.store(
+        window.saturating_sub(idle) * 1000 / window,
+        Ordering::Relaxed,
+    );
+    usage.window_start.store(now, Ordering::Relaxed);
+}
+
+// busy share of the last window (0 to 1000), lock free
+pub fn busy_permille(cpu_index: usize) -> u64 {
+    CORE_USAGE[cpu_index].busy_permille.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoadStats {
+    pub core: u64,
+    // busy share of the last window (0 to 1000)
+    pub busy_permille: u64,
+    // since the first timer interrupt of the core
+    pub busy: Duration,
+    pub idle: Duration,
+    pub parked: bool,
+}
+
+// of every started core
+pub fn load_stats() -> Vec<LoadStats> {
+    let core_count = ACPI.lock().ap_count + 1;
+    (0..core_count).map(core_load).collect()
+}
+
+pub fn core_load(cpu_index: u64) -> LoadStats {
+    let usage = &CORE_USAGE[cpu_index as usize];
+    let ticks_per_us = (crate::apic::tsc_ticks_per_second() / 1_000_000).max(1);
+    let started = usage.started.load(Ordering::Relaxed);
+    let total = if started == 0 { 0 } else { rdtsc() - started };
+    // the current idle time is not added yet
+    let idle_since = usage.idle_since.load(Ordering::Relaxed);
+    let idle = usage.idle_ticks.load(Ordering::Relaxed)
+        + if idle_since == 0 {
+            0
+        } else {
+            rdtsc().saturating_sub(idle_since)
+        };
+    let parked = is_parked(cpu_index);
+    LoadStats {
+        core: cpu_index,
+        busy_permille: if parked {
+            0
+        } else {
+            busy_permille(cpu_index as usize)
+        },
+        busy: Duration::from_micros(total.saturating_sub(idle) / ticks_per_us),
+        idle: Duration::from_micros(idle.min(total) / ticks_per_us),
+        parked,
+    }
+}
+
+// must be called by each core (the bsp allocates the table, so it needs the heap and acpi)
 pub fn initialize_own_core_local_data(core_local_data: CoreLocalData) {
     let apic_id = get_apic().id();
-    unsafe {
-        CORE_LOCAL[apic_id as usize].get_or_init(|| core_local_data);
-    }
+    let table = CORE_LOCAL.call_once(|| {
+        // apic ids may have gaps
+        let len = ACPI
+            .lock()
+            .processor_apic_ids
+            .iter()
+            .max()
+            .map_or(1, |&max| max as usize + 1);
+        let entries: Box<[OnceCell<CoreLocalData>]> =
+            core::iter::repeat_with(OnceCell::new).take(len).collect();
+        CoreLocalTable(Box::leak(entries).as_mut_ptr(), len)
+    });
+    unsafe { &mut *table.entry(apic_id).expect("apic id not reported by acpi") }
+        .get_or_init(|| core_local_data);
 }
 
 // undefined behavior id initialize_own_core_local_data was not called by the calling core
@@ -221,7 +545,7 @@ pub fn initialize_own_core_local_data(core_local_data: CoreLocalData) {
 pub fn get_cld() -> &'static mut CoreLocalData {
     let apic_id = get_apic().id();
     unsafe {
-        CORE_LOCAL[apic_id as usize]
+        (*CORE_LOCAL.get_unchecked().entry(apic_id).unwrap_unchecked())
             .get_mut()
             .expect("core local data not initialized")
     }
@@ -236,20 +560,341 @@ pub fn cpu_index() -> u64 {
 #[inline]
 pub fn try_get_cld() -> Option<&'static mut CoreLocalData> {
     let apic_id = crate::apic::try_get_apic()?.id();
-    unsafe { CORE_LOCAL[apic_id as usize].get_mut() }
+    unsafe { (*CORE_LOCAL.get()?.entry(apic_id)?).get_mut() }
 }
 
 #[derive(Debug, Default)]
-// Needs to stay small since it is in static memory for each potential core
 pub struct CoreLocalData {
     pub running_application_data: Option<crate::loader::RunningApplicationCLD>,
     pub cpu_index: u64, //None for bsp
     pub apic_timer_ticks_per_second: Option<NonZeroU64>,
-    pub apic_timer_interrupt_function: Option<fn()>,
+    // tsc ticks between the deadlines of a periodic tsc deadline timer and the armed deadline (see apic::rearm_timer)
+    pub tsc_deadline_interval: Option<u64>,
+    pub tsc_deadline: u64,
     pub stuff: Option<Vec<Box<dyn Any>>>,
+    pub frame_cache: crate::memory::FrameCache,
+}
+
+// Indexed by apic id, allocated for the processors acpi reports. Every entry is only accessed by its own core
+static CORE_LOCAL: Once<CoreLocalTable> = Once::new();
+
+// the leaked entries
+struct CoreLocalTable(*mut OnceCell<CoreLocalData>, usize);
+
+unsafe impl Send for CoreLocalTable {}
+unsafe impl Sync for CoreLocalTable {}
+
+impl CoreLocalTable {
+    fn entry(&self, apic_id: u32) -> Option<*mut OnceCell<CoreLocalData>> {
+        let index = apic_id as usize;
+        (index < self.1).then(|| unsafe { self.0.add(index) })
+    }
 }
 
-#[allow(clippy::declare_interior_mutable_const)]
-const CORE_LOCAL_ENTRY_INIT: OnceCell<CoreLocalData> = OnceCell::new();
-static mut CORE_LOCAL: [OnceCell<CoreLocalData>; MAX_CORES as usize] =
-    [CORE_LOCAL_ENTRY_INIT; MAX_CORES as usize];
+// Remote calls run a function on other cores in their REMOTE_CALL_VECTOR interrupt and return once all of them
+// ran it. The call stays on the stack of the caller, each core has a mailbox with a bit per calling core.
+// A waiting caller keeps running the calls to itself, so two cores can call each other (with interrupts disabled).
+// The functions run in interrupt handlers: they must not allocate (the heap lock is not interrupt safe),
+// block, or make remote calls themselves
+pub const REMOTE_CALL_VECTOR: u8 = 0xF0;
+
+const NOT_RECEIVING: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteCallError {
+    // the core is not started yet (see init_remote_calls)
+    NotReceiving,
+}
+
+struct RemoteCall<'a> {
+    function: &'a (dyn Fn() + Sync),
+    // cores which did not run the function yet
+    pending: AtomicUsize,
+}
+
+// all indexed by cpu index
+per_core! {
+    static APIC_IDS: AtomicU32 = AtomicU32::new(NOT_RECEIVING);
+    static OUTGOING: AtomicPtr<RemoteCall<'static>> = AtomicPtr::new(ptr::null_mut());
+    // a bit for every core, allocated before the aps start (there are no remote calls before)
+    static MAILBOXES: Once<Box<[AtomicU64]>> = Once::new();
+}
+
+fn allocate_mailboxes() {
+    let words = APIC_IDS.len().div_ceil(64);
+    for mailbox in MAILBOXES.iter() {
+        mailbox.call_once(|| (0..words).map(|_| AtomicU64::new(0)).collect());
+    }
+}
+
+// needs to be called by every core before enabling interrupts, from then on it has to handle every remote call
+pub fn init_remote_calls() {
+    APIC_IDS[cpu_index() as usize].store(get_apic().id(), Ordering::Release);
+}
+
+fn is_receiving(cpu_index: usize) -> bool {
+    APIC_IDS
+        .get(cpu_index)
+        .is_some_and(|apic_id| apic_id.load(Ordering::Acquire) != NOT_RECEIVING)
+}
+
+// Runs the function on the core (directly if it is the current one)
+pub fn call_on(cpu_index: u64, function: impl Fn() + Sync) -> Result<(), RemoteCallError> {
+    let target = cpu_index as usize;
+    if !is_receiving(target) {
+        return Err(RemoteCallError::NotReceiving);
+    }
+    without_interrupts(|| {
+        if target as u64 == self::cpu_index() {
+            function();
+        } else {
+            call_remote(&function, |cpu_index| cpu_index == target);
+        }
+    });
+    Ok(())
+}
+
+// runs the function on all started cores (including the current one)
+pub fn call_all(function: impl Fn() + Sync) {
+    without_interrupts(|| {
+        call_remote(&function, |_| true);
+        function();
+    });
+}
+
+// Runs the function on all other started cores. Can be used before the core local data of the current core is
+// initialized, there are no other cores running then
+pub fn call_others(function: impl Fn() + Sync) {
+    let Some(cld) = try_get_cld() else {
+        return;
+    };
+    let own = cld.cpu_index as usize;
+    without_interrupts(|| call_remote(&function, |cpu_index| cpu_index != own));
+}
+
+// Has to be called with interrupts disabled. Cores may start receiving while the targets are notified,
+// so pending counts them one by one (the caller holds one until the last one is notified)
+fn call_remote(function: &(dyn Fn() + Sync), is_target: impl Fn(usize) -> bool) {
+    let own = cpu_index() as usize;
+    let mut targets = (0..APIC_IDS.len())
+        .filter(|&cpu_index| cpu_index != own && is_receiving(cpu_index) && is_target(cpu_index))
+        .peekable();
+    if targets.peek().is_none() {
+        return;
+    }
+
+    let call = RemoteCall {
+        function,
+        pending: AtomicUsize::new(1),
+    };
+    let previous = OUTGOING[own].swap(
+        &call as *const RemoteCall as *mut RemoteCall<'static>,
+        Ordering::AcqRel,
+    );
+    ass!(previous.is_null(), "remote call from a remote call");
+
+    let mut apic = get_apic();
+    let mut count = 0;
+    let mut last_target = 0;
+    for cpu_index in targets {
+        call.pending.fetch_add(1, Ordering::AcqRel);
+        let mailbox = MAILBOXES[cpu_index]
+            .get()
+            .expect("mailboxes are not allocated");
+        mailbox[own / 64].fetch_or(1 << (own % 64), Ordering::AcqRel);
+        count += 1;
+        last_target = cpu_index;
+    }
+    call.pending.fetch_sub(1, Ordering::AcqRel);
+    if count == 1 {
+        apic.send_ipi(
+            REMOTE_CALL_VECTOR,
+            APIC_IDS[last_target].load(Ordering::Acquire),
+        );
+    } else {
+        // cores which are not targeted find their mailbox empty
+        apic.write_interrupt_command(create_broadcast_cmd(REMOTE_CALL_VECTOR));
+    }
+
+    while call.pending.load(Ordering::Acquire) > 0 {
+        handle_remote_calls();
+        core::hint::spin_loop();
+    }
+    OUTGOING[own].store(ptr::null_mut(), Ordering::Release);
+}
+
+// called from the remote call interrupt (or while spinning with interrupts disabled)
+pub fn handle_remote_calls() {
+    let Some(cld) = try_get_cld() else {
+        return;
+    };
+    let Some(mailbox) = MAILBOXES[cld.cpu_index as usize].get() else {
+        return;
+    };
+    for (word_index, word) in mailbox.iter().enumerate() {
+        let mut callers = word.swap(0, Ordering::AcqRel);
+        while callers != 0 {
+            let caller = word_index * 64 + callers.trailing_zeros() as usize;
+            callers &= callers - 1;
+            // the caller waits until pending is decremented, the call stays valid until then
+            let call = unsafe { &*OUTGOING[caller].load(Ordering::Acquire) };
+            (call.function)();
+            call.pending.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+const CPUID_HTT: u32 = 1 << 28;
+const CPUID_CACHE_LEAF: u32 = 0x4;
+const CPUID_TOPOLOGY_LEAF: u32 = 0xB;
+const TOPOLOGY_LEVEL_THREAD: u32 = 1;
+const TOPOLOGY_LEVEL_CORE: u32 = 2;
+
+// Position of a logical processor: the package (socket), the physical core in the package and the hardware
+// thread of the core. Hyperthreads of a core share its execution units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuTopology {
+    pub apic_id: u32,
+    pub package_id: u32,
+    pub core_id: u32,
+    pub thread_id: u32,
+}
+
+// Widths of the thread and core fields at the bottom of the apic ids, the package id is the rest.
+// The same for all processors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApicIdLayout {
+    pub thread_bits: u32,
+    pub core_bits: u32,
+}
+
+impl ApicIdLayout {
+    // From the extended topology leaf, otherwise from the logical processor and core counts of the legacy leaves
+    pub fn detect() -> Self {
+        let max_leaf = unsafe { __cpuid(0) }.eax;
+        if max_leaf >= CPUID_TOPOLOGY_LEAF
+            && unsafe { __cpuid_count(CPUID_TOPOLOGY_LEAF, 0) }.ebx != 0
+        {
+            let mut layout = Self {
+                thread_bits: 0,
+                core_bits: 0,
+            };
+            // the shifts are cumulative, levels end with an invalid (zero) level type
+            for sub_leaf in 0.. {
+                let level = unsafe { __cpuid_count(CPUID_TOPOLOGY_LEAF, sub_leaf) };
+                let shift = level.eax & 0x1F;
+                match (level.ecx >> 8) & 0xFF {
+                    TOPOLOGY_LEVEL_THREAD => layout.thread_bits = shift,
+                    TOPOLOGY_LEVEL_CORE => {
+                        layout.core_bits = shift.saturating_sub(layout.thread_bits)
+                    }
+                    0 => break,
+                    _ => {}
+                }
+            }
+            return layout;
+        }
+
+        let features = unsafe { __cpuid(1) };
+        if features.edx & CPUID_HTT == 0 {
+            return Self {
+                thread_bits: 0,
+                core_bits: 0,
+            };
+        }
+        let logical_per_package = ((features.ebx >> 16) & 0xFF).max(1);
+        let cores_per_package = if max_leaf >= CPUID_CACHE_LEAF {
+            (unsafe { __cpuid_count(CPUID_CACHE_LEAF, 0) }.eax >> 26) + 1
+        } else {
+            1
+        };
+        Self::from_counts(
+            (logical_per_package / cores_per_package).max(1),
+            cores_per_package,
+        )
+    }
+
+    // each field holds the count rounded up to a power of two
+    pub fn from_counts(threads_per_core: u32, cores_per_package: u32) -> Self {
+        Self {
+            thread_bits: threads_per_core.next_power_of_two().trailing_zeros(),
+            core_bits: cores_per_package.next_power_of_two().trailing_zeros(),
+        }
+    }
+
+    pub fn decode(self, apic_id: u32) -> CpuTopology {
+        let field = |shift: u32, bits: u32| {
+            apic_id.checked_shr(shift).unwrap_or(0) & ((1u64 << bits) - 1) as u32
+        };
+        CpuTopology {
+            apic_id,
+            package_id: apic_id
+                .checked_shr(self.thread_bits + self.core_bits)
+                .unwrap_or(0),
+            core_id: field(self.thread_bits, self.core_bits),
+            thread_id: field(0, self.thread_bits),
+        }
+    }
+}
+
+static APIC_ID_LAYOUT: Once<ApicIdLayout> = Once::new();
+
+// needs to be called once (only bsp) before the aps are started
+pub fn init_topology() {
+    let layout = *APIC_ID_LAYOUT.call_once(ApicIdLayout::detect);
+    let processors: Vec<CpuTopology> = ACPI
+        .lock()
+        .processor_apic_ids
+        .iter()
+        .map(|&apic_id| layout.decode(apic_id))
+        .collect();
+    let mut packages: Vec<u32> = processors.iter().map(|cpu| cpu.package_id).collect();
+    packages.sort_unstable();
+    packages.dedup();
+    log::info!(
+        "CPU topology: {} packages, {} physical cores, {} threads ({layout:?})",
+        packages.len(),
+        physical_core_count(),
+        processors.len()
+    );
+    for processor in processors {
+        log::debug!("{processor:?}");
+    }
+}
+
+// None if the core is not started yet
+pub fn topology(cpu_index: u64) -> Option<CpuTopology> {
+    let layout = APIC_ID_LAYOUT.get()?;
+    let apic_id = APIC_IDS.get(cpu_index as usize)?.load(Ordering::Acquire);
+    (apic_id != NOT_RECEIVING).then(|| layout.decode(apic_id))
+}
+
+// started cores which are other hardware threads of the same physical core
+pub fn siblings(cpu_index: u64) -> impl Iterator<Item = u64> {
+    let own = topology(cpu_index);
+    (0..APIC_IDS.len() as u64).filter(move |&other| {
+        other != cpu_index
+            && own.is_some_and(|own| {
+                topology(other).is_some_and(|other| {
+                    (other.package_id, other.core_id) == (own.package_id, own.core_id)
+                })
+            })
+    })
+}
+
+// of all processors reported by acpi (started or not)
+pub fn physical_core_count() -> usize {
+    let Some(layout) = APIC_ID_LAYOUT.get() else {
+        return 0;
+    };
+    let mut cores: Vec<(u32, u32)> = ACPI
+        .lock()
+        .processor_apic_ids
+        .iter()
+        .map(|&apic_id| layout.decode(apic_id))
+        .map(|cpu| (cpu.package_id, cpu.core_id))
+        .collect();
+    cores.sort_unstable();
+    cores.dedup();
+    cores.len()
+}
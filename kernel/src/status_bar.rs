@@ -0,0 +1,67 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    kthread,
+    memory::MEMORY,
+    sched::{self, WaitQueue},
+    smp,
+    terminal_out::{self, Color, TerminalWriter},
+    time::{self, Duration},
+    timer_callbacks,
+};
+
+// A bar over the top of the screen with the uptime, the busy share and the load of every core (the tasks
+// which want to run on it), the free memory and the frames pushed by the compositor. A timer callback wakes the thread
+// drawing it, the callback itself must not block
+const REFRESH: Duration = Duration::from_secs(1);
+
+static REFRESH_REQUESTED: AtomicBool = AtomicBool::new(true);
+static REFRESH_WAIT: WaitQueue = WaitQueue::new();
+
+// needs the double buffer (see terminal_out::switch_to_double_buffer)
+pub fn start() {
+    // the font follows the resolution
+    let font = terminal_out::small_font_size();
+    let mut bar = terminal_out::reserve_top(font.val());
+    bar.clear_color = Color::new(30, 30, 50);
+    bar.background = bar.clear_color;
+    bar.clear(Some(font));
+
+    timer_callbacks::add_periodic(REFRESH, || {
+        REFRESH_REQUESTED.store(true, Ordering::Release);
+        REFRESH_WAIT.wake_all();
+    });
+    // detached, it runs until the system stops
+    kthread::spawn("status bar", move || loop {
+        REFRESH_WAIT.wait_until(|| REFRESH_REQUESTED.fetch_and(false, Ordering::Acquire));
+        draw(&mut bar);
+    });
+}
+
+fn draw(bar: &mut TerminalWriter) {
+    let uptime = time::uptime().as_secs();
+    let free_mib = MEMORY.lock().free_frames() * 4096 / 1024 / 1024;
+    let cores = sched::core_stats();
+    let usage = smp::load_stats();
+
+    // the compositor doesn't push the bar while it is cleared
+    let _lock = terminal_out::lock_back_buffer();
+    bar.clear(None);
+    bar.print(format_args!(
+        "up {}:{:02}:{:02} | cpu",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60
+    ));
+    for core in usage.iter().filter(|core| !core.parked) {
+        bar.print(format_args!(" {}%", core.busy_permille / 10));
+    }
+    bar.print(format_args!(" | load"));
+    for core in cores {
+        bar.print(format_args!(" {}", core.load));
+    }
+    bar.print(format_args!(
+        " | {free_mib} MiB free | frame {}",
+        terminal_out::frames()
+    ));
+}
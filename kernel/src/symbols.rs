@@ -0,0 +1,98 @@
+use crate::{get_boot_info, ram_disk};
+
+// Function symbols of the kernel, written into the ram disk by bootimage (see Image::add_kernel_symbols):
+// entry count (u64), entries sorted by address (address u64, size u32, name start u32), names (utf-8).
+// Used in the panic path, so lookups neither lock nor allocate and a broken table only yields no symbol
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: &'static str,
+    // bytes from the start of the function
+    pub offset: u64,
+}
+
+struct Entry {
+    address: u64,
+    size: u64,
+    name_start: usize,
+}
+
+struct Table {
+    entries: &'static [u8],
+    names: &'static [u8],
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+impl Table {
+    fn get() -> Option<Self> {
+        if ram_disk::get_file_count() <= ram_disk::KERNEL_SYMBOLS {
+            return None;
+        }
+        let data = ram_disk::get_file_slice(ram_disk::KERNEL_SYMBOLS);
+        let count = usize::try_from(read_u64(data, 0)?).ok()?;
+        let names_start = count.checked_mul(ENTRY_SIZE)?.checked_add(8)?;
+        Some(Self {
+            entries: data.get(8..names_start)?,
+            names: data.get(names_start..)?,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    fn entry(&self, index: usize) -> Option<Entry> {
+        let offset = index * ENTRY_SIZE;
+        Some(Entry {
+            address: read_u64(self.entries, offset)?,
+            size: u64::from(read_u32(self.entries, offset + 8)?),
+            name_start: read_u32(self.entries, offset + 12)? as usize,
+        })
+    }
+
+    fn name(&self, index: usize) -> Option<&'static str> {
+        let start = self.entry(index)?.name_start;
+        let end = self
+            .entry(index + 1)
+            .map_or(self.names.len(), |next| next.name_start);
+        core::str::from_utf8(self.names.get(start..end)?).ok()
+    }
+}
+
+// Resolves an address of the running kernel (the offset the kernel was loaded at is subtracted)
+pub fn resolve(address: u64) -> Option<Symbol> {
+    let table = Table::get()?;
+    let address = address.checked_sub(get_boot_info().kernel_image_offset)?;
+    // first entry behind the address
+    let (mut low, mut high) = (0, table.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        if table.entry(middle)?.address <= address {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    let index = low.checked_sub(1)?;
+    let entry = table.entry(index)?;
+    let offset = address - entry.address;
+    if offset >= entry.size.max(1) {
+        return None;
+    }
+    Some(Symbol {
+        name: table.name(index)?,
+        offset,
+    })
+}
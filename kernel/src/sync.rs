@@ -0,0 +1,144 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use spin::Mutex;
+
+use crate::sched::WaitQueue;
+
+// Barriers and count down latches shared by name (created by the first user, see barrier and latch).
+// Waiting blocks the task, before the scheduler runs or with interrupts disabled it spins
+// (see WaitQueue::wait_until), so they also work during the bring up of the cores
+
+// Releases the waiting tasks once count of them arrived, afterwards it can be used again
+pub struct Barrier {
+    count: usize,
+    arrived: AtomicUsize,
+    // incremented by the last task of each round
+    generation: AtomicU64,
+    released: WaitQueue,
+}
+
+impl Barrier {
+    pub const fn new(count: usize) -> Self {
+        assert!(count > 0);
+        Self {
+            count,
+            arrived: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            released: WaitQueue::new(),
+        }
+    }
+
+    pub const fn count(&self) -> usize {
+        self.count
+    }
+
+    // Returns true for the last task of the round (only one per round, the leader)
+    pub fn wait(&self) -> bool {
+        // the round can't end before this task arrived
+        let generation = self.generation.load(Ordering::Acquire);
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 == self.count {
+            self.arrived.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+            self.released.wake_all();
+            return true;
+        }
+        self.released
+            .wait_until(|| self.generation.load(Ordering::Acquire) != generation);
+        false
+    }
+
+    // the tasks waiting in the current round
+    pub fn waiting(&self) -> usize {
+        self.arrived.load(Ordering::Relaxed)
+    }
+}
+
+// Releases the waiting tasks once count_down was called count times, stays open afterwards
+pub struct Latch {
+    remaining: AtomicUsize,
+    open: WaitQueue,
+}
+
+impl Latch {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            remaining: AtomicUsize::new(count),
+            open: WaitQueue::new(),
+        }
+    }
+
+    // calls on an open latch do nothing
+    pub fn count_down(&self) {
+        let previous =
+            self.remaining
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |remaining| {
+                    remaining.checked_sub(1)
+                });
+        if previous == Ok(1) {
+            self.open.wake_all();
+        }
+    }
+
+    pub fn wait(&self) {
+        self.open.wait_until(|| self.is_open());
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining.load(Ordering::Acquire)
+    }
+}
+
+// only locked by tasks (the lookups allocate)
+static BARRIERS: Mutex<BTreeMap<&'static str, Arc<Barrier>>> = Mutex::new(BTreeMap::new());
+static LATCHES: Mutex<BTreeMap<&'static str, Arc<Latch>>> = Mutex::new(BTreeMap::new());
+
+// The barrier with the name, created for count tasks by the first call. All users have to agree on the count
+pub fn barrier(name: &'static str, count: usize) -> Arc<Barrier> {
+    let barrier = BARRIERS
+        .lock()
+        .entry(name)
+        .or_insert_with(|| Arc::new(Barrier::new(count)))
+        .clone();
+    assert_eq!(
+        barrier.count(),
+        count,
+        "barrier '{name}' exists for another number of tasks"
+    );
+    barrier
+}
+
+// the latch with the name, created with count by the first call (later calls get it as it is)
+pub fn latch(name: &'static str, count: usize) -> Arc<Latch> {
+    LATCHES
+        .lock()
+        .entry(name)
+        .or_insert_with(|| Arc::new(Latch::new(count)))
+        .clone()
+}
+
+// The next call of barrier or latch with the name creates a new one, the current users keep theirs.
+// Returns whether one existed
+pub fn remove(name: &str) -> bool {
+    let barrier = BARRIERS.lock().remove(name).is_some();
+    let latch = LATCHES.lock().remove(name).is_some();
+    barrier || latch
+}
+
+// (name, count, waiting tasks) of the named barriers
+pub fn barriers(mut f: impl FnMut(&str, usize, usize)) {
+    for (name, barrier) in BARRIERS.lock().iter() {
+        f(name, barrier.count(), barrier.waiting());
+    }
+}
+
+// (name, remaining count downs) of the named latches
+pub fn latches(mut f: impl FnMut(&str, usize)) {
+    for (name, latch) in LATCHES.lock().iter() {
+        f(name, latch.remaining());
+    }
+}
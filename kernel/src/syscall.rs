@@ -0,0 +1,426 @@
+use core::{
+    alloc::GlobalAlloc,
+    arch::asm,
+    ptr, slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::{string::String, sync::Arc, vec};
+use spin::Mutex;
+
+use x86_64::{
+    registers::{
+        model_specific::{Efer, EferFlags, GsBase, KernelGsBase, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    VirtAddr,
+};
+
+use crate::{
+    constants::v,
+    fs::{self, FsError, SeekFrom},
+    handle::Handle,
+    loader::{self, ExitCause, ExitStatus, Fault},
+    per_core,
+    user_access::user_access,
+};
+
+// User ABI: syscall with the number in rax, arguments in rdi, rsi, rdx and the result in rax
+// (rcx and r11 are clobbered by the instruction). Keep in sync with user_app/src/os_functions.rs
+const SYS_EXIT: u64 = 0;
+const SYS_PRINT: u64 = 1;
+const SYS_ABORT: u64 = 2;
+const SYS_ALLOC: u64 = 3;
+const SYS_DEALLOC: u64 = 4;
+const SYS_PANIC: u64 = 5;
+const SYS_YIELD: u64 = 6;
+const SYS_SLEEP: u64 = 7;
+const SYS_READ: u64 = 8;
+const SYS_WRITE: u64 = 9;
+const SYS_CLOSE: u64 = 10;
+const SYS_OPEN: u64 = 11;
+const SYS_SEEK: u64 = 12;
+const SYS_RANDOM: u64 = 13;
+const SYS_REMOVE: u64 = 14;
+const SYSCALL_COUNT: usize = 15;
+
+// error results of the handle syscalls
+const ERROR_BAD_HANDLE: u64 = u64::MAX;
+const ERROR_CLOSED: u64 = u64::MAX - 1;
+const ERROR_NOT_FOUND: u64 = u64::MAX - 2;
+const ERROR_INVALID_ARGUMENT: u64 = u64::MAX - 3;
+const ERROR_READ_ONLY: u64 = u64::MAX - 4;
+
+// whence of the seek syscall
+const SEEK_START: u64 = 0;
+const SEEK_CURRENT: u64 = 1;
+const SEEK_END: u64 = 2;
+
+// flags of the open syscall
+const OPEN_CREATE: u64 = 1;
+
+const MAX_PATH_LEN: u64 = 4096;
+
+// Reads and writes go through a kernel buffer (a task must not block inside user_access),
+// they transfer at most this many bytes per syscall
+const MAX_TRANSFER: usize = 4096;
+
+type Syscall = fn(u64, u64, u64) -> u64;
+
+static SYSCALLS: [Syscall; SYSCALL_COUNT] = {
+    let mut table: [Syscall; SYSCALL_COUNT] = [sys_unknown; SYSCALL_COUNT];
+    table[SYS_EXIT as usize] = sys_exit;
+    table[SYS_PRINT as usize] = sys_print;
+    table[SYS_ABORT as usize] = sys_abort;
+    table[SYS_ALLOC as usize] = sys_alloc;
+    table[SYS_DEALLOC as usize] = sys_dealloc;
+    table[SYS_PANIC as usize] = sys_panic;
+    table[SYS_YIELD as usize] = sys_yield;
+    table[SYS_SLEEP as usize] = sys_sleep;
+    table[SYS_READ as usize] = sys_read;
+    table[SYS_WRITE as usize] = sys_write;
+    table[SYS_CLOSE as usize] = sys_close;
+    table[SYS_OPEN as usize] = sys_open;
+    table[SYS_SEEK as usize] = sys_seek;
+    table[SYS_RANDOM as usize] = sys_random;
+    table[SYS_REMOVE as usize] = sys_remove;
+    table
+};
+
+// gs points to the data of the current core during the stack switch of the syscall entry (swapgs),
+// the entry writes the user stack pointer
+#[repr(C)]
+struct SyscallCpuData {
+    kernel_stack_pointer: AtomicU64,
+    user_stack_pointer: AtomicU64,
+}
+
+per_core! {
+    static CPU_DATA: SyscallCpuData = SyscallCpuData {
+        kernel_stack_pointer: AtomicU64::new(0),
+        user_stack_pointer: AtomicU64::new(0),
+    };
+}
+
+// needs to be called by every core (after its gdt is loaded)
+pub fn init() {
+    let cpu_index = crate::smp::cpu_index();
+    let selectors = crate::interrupts::selectors();
+    set_kernel_stack(cpu_index, crate::interrupts::privilege_stack_top(cpu_index));
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+    Star::write(
+        selectors.user_code,
+        selectors.user_data,
+        selectors.kernel_code,
+        selectors.kernel_data,
+    )
+    .unwrap();
+    LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+    // syscalls start with interrupts disabled, so the stack switch can not be interrupted
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    reset_gs_base();
+}
+
+// Syscalls use the same stack as interrupts from ring 3 (the tss rsp0 stack), changed on every task switch
+pub fn set_kernel_stack(cpu_index: u64, top: VirtAddr) {
+    // unlike interrupts syscall does not align the stack
+    let top = top.align_down(16u64).as_u64();
+    CPU_DATA[cpu_index as usize]
+        .kernel_stack_pointer
+        .store(top, Ordering::Relaxed);
+}
+
+// The caller saved registers (except rax, rcx, r11) are restored, so the user side only has to clobber those.
+// The handlers run with interrupts enabled and can block (the task switches on its own kernel stack),
+// so gs is swapped back right after the stack switch
+#[naked]
+unsafe extern "C" fn syscall_entry() {
+    asm!(
+        "swapgs",
+        "mov gs:[8], rsp",  // user stack pointer
+        "mov rsp, gs:[0]",  // kernel stack pointer
+        "push qword ptr gs:[8]",
+        "swapgs",
+        "push r11",         // user rflags
+        "push rcx",         // user rip
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "sub rsp, 8",       // align the stack to 16 bytes (9 pushed)
+        "mov rcx, rdx",     // dispatch(number, arg0, arg1, arg2)
+        "mov rdx, rsi",
+        "mov rsi, rdi",
+        "mov rdi, rax",
+        "sti",
+        "call {dispatch}",
+        "cli",              // until sysret, the user stack can not take interrupts
+        "add rsp, 8",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop r11",
+        "pop rsp",
+        "sysretq",
+        dispatch = sym dispatch,
+        options(noreturn),
+    )
+}
+
+extern "C" fn dispatch(number: u64, arg0: u64, arg1: u64, arg2: u64) -> u64 {
+    match SYSCALLS.get(number as usize) {
+        Some(syscall) => syscall(arg0, arg1, arg2),
+        None => sys_unknown(number, 0, 0),
+    }
+}
+
+fn is_user_range(addr: u64, len: u64) -> bool {
+    addr.checked_add(len).is_some_and(|end| end <= v::USER_END)
+}
+
+// ends the application from inside a syscall (jumps back into loader::switch_stack_and_execute)
+fn exit(status: ExitStatus) -> ! {
+    unsafe { loader::end_running_application(status) }
+}
+
+fn kill(fault: Fault) -> ! {
+    exit(ExitStatus::killed(fault))
+}
+
+// The kernel does not use gs, so the swapgs state only matters for the syscall entry.
+// Restores it after an application ended (in case it ended during the stack switch of a syscall entry)
+pub fn reset_gs_base() {
+    let cpu_index = crate::smp::cpu_index();
+    GsBase::write(VirtAddr::zero());
+    KernelGsBase::write(VirtAddr::from_ptr(&CPU_DATA[cpu_index as usize]));
+}
+
+fn sys_unknown(number: u64, _: u64, _: u64) -> u64 {
+    log::error!("Application used unknown syscall {number}");
+    kill(Fault::InvalidSyscall)
+}
+
+fn sys_exit(code: u64, _: u64, _: u64) -> u64 {
+    exit(ExitStatus {
+        code,
+        cause: ExitCause::Returned,
+    })
+}
+
+fn sys_abort(code: u64, _: u64, _: u64) -> u64 {
+    log::debug!("Application aborted with {code:#x}");
+    exit(ExitStatus {
+        code,
+        cause: ExitCause::Aborted,
+    })
+}
+
+// the application prints the panic message itself
+fn sys_panic(code: u64, _: u64, _: u64) -> u64 {
+    log::debug!("Application panicked");
+    exit(ExitStatus {
+        code,
+        cause: ExitCause::Panicked,
+    })
+}
+
+fn sys_yield(_: u64, _: u64, _: u64) -> u64 {
+    crate::sched::yield_now();
+    0
+}
+
+// other tasks of the core run while the application sleeps
+fn sys_sleep(ms: u64, _: u64, _: u64) -> u64 {
+    crate::sched::sleep_ms(ms);
+    0
+}
+
+// a copy, so the handle table is not locked while the syscall blocks
+fn running_handle(number: u64) -> Option<Handle> {
+    unsafe { loader::running_application_handles() }
+        .lock()
+        .get(number)
+        .cloned()
+}
+
+// blocks until data is available (pipes), returns 0 at the end of the stream or file
+fn sys_read(handle: u64, buf: u64, len: u64) -> u64 {
+    if !is_user_range(buf, len) {
+        kill(Fault::BadUserPointer);
+    }
+    let Some(source) = running_handle(handle) else {
+        return ERROR_BAD_HANDLE;
+    };
+    let mut buffer = vec![0u8; (len as usize).min(MAX_TRANSFER)];
+    let read = match source {
+        Handle::PipeReader(reader) => reader.read(&mut buffer),
+        Handle::File(file) => match file.lock().read(&mut buffer) {
+            Ok(read) => read,
+            Err(err) => return fs_error(err),
+        },
+        Handle::PipeWriter(_) => return ERROR_BAD_HANDLE,
+    };
+    user_access(|| unsafe { ptr::copy_nonoverlapping(buffer.as_ptr(), buf as *mut u8, read) });
+    read as u64
+}
+
+// blocks until some of the data is written, returns how much
+fn sys_write(handle: u64, buf: u64, len: u64) -> u64 {
+    if !is_user_range(buf, len) {
+        kill(Fault::BadUserPointer);
+    }
+    let Some(destination) = running_handle(handle) else {
+        return ERROR_BAD_HANDLE;
+    };
+    let len = (len as usize).min(MAX_TRANSFER);
+    let mut buffer = vec![0u8; len];
+    user_access(|| unsafe { ptr::copy_nonoverlapping(buf as *const u8, buffer.as_mut_ptr(), len) });
+    match destination {
+        Handle::PipeWriter(writer) => writer
+            .write(&buffer)
+            .map_or(ERROR_CLOSED, |written| written as u64),
+        Handle::File(file) => file
+            .lock()
+            .write(&buffer)
+            .map_or_else(fs_error, |written| written as u64),
+        Handle::PipeReader(_) => ERROR_BAD_HANDLE,
+    }
+}
+
+fn fs_error(err: FsError) -> u64 {
+    match err {
+        FsError::NotFound => ERROR_NOT_FOUND,
+        FsError::ReadOnly => ERROR_READ_ONLY,
+        _ => ERROR_INVALID_ARGUMENT,
+    }
+}
+
+// None if the path is too long or not utf8
+fn user_path(path: u64, len: u64) -> Option<String> {
+    if !is_user_range(path, len) {
+        kill(Fault::BadUserPointer);
+    }
+    if len > MAX_PATH_LEN {
+        return None;
+    }
+    user_access(|| {
+        let slice = unsafe { slice::from_raw_parts(path as *const u8, len as usize) };
+        core::str::from_utf8(slice).map(String::from).ok()
+    })
+}
+
+// opens a file by its absolute path (see fs), returns its handle.
+// With OPEN_CREATE a missing file is created (empty)
+fn sys_open(path: u64, len: u64, flags: u64) -> u64 {
+    let Some(path) = user_path(path, len) else {
+        return ERROR_INVALID_ARGUMENT;
+    };
+    if flags & !OPEN_CREATE != 0 {
+        return ERROR_INVALID_ARGUMENT;
+    }
+    let file = match fs::open(&path) {
+        Err(FsError::NotFound) if flags & OPEN_CREATE != 0 => fs::create(&path),
+        opened => opened,
+    };
+    let file = match file {
+        Ok(file) => file,
+        Err(err) => return fs_error(err),
+    };
+    unsafe { loader::running_application_handles() }
+        .lock()
+        .insert(Handle::File(Arc::new(Mutex::new(file))))
+}
+
+// removes a file or an empty directory
+fn sys_remove(path: u64, len: u64, _: u64) -> u64 {
+    let Some(path) = user_path(path, len) else {
+        return ERROR_INVALID_ARGUMENT;
+    };
+    fs::remove(&path).map_or_else(fs_error, |()| 0)
+}
+
+// returns the new position
+fn sys_seek(handle: u64, offset: u64, whence: u64) -> u64 {
+    let Some(Handle::File(file)) = running_handle(handle) else {
+        return ERROR_BAD_HANDLE;
+    };
+    let from = match whence {
+        SEEK_START => SeekFrom::Start(offset),
+        SEEK_CURRENT => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return ERROR_INVALID_ARGUMENT,
+    };
+    let position = file.lock().seek(from);
+    position.unwrap_or(ERROR_INVALID_ARGUMENT)
+}
+
+fn sys_close(handle: u64, _: u64, _: u64) -> u64 {
+    let removed = unsafe { loader::running_application_handles() }
+        .lock()
+        .remove(handle);
+    removed.map_or(ERROR_BAD_HANDLE, |_| 0)
+}
+
+// fills the buffer with bytes of the kernel generator, returns how many
+fn sys_random(buf: u64, len: u64, _: u64) -> u64 {
+    if !is_user_range(buf, len) {
+        kill(Fault::BadUserPointer);
+    }
+    let mut buffer = vec![0u8; (len as usize).min(MAX_TRANSFER)];
+    crate::random::fill(&mut buffer);
+    user_access(|| unsafe {
+        ptr::copy_nonoverlapping(buffer.as_ptr(), buf as *mut u8, buffer.len())
+    });
+    buffer.len() as u64
+}
+
+fn sys_print(string: u64, len: u64, _: u64) -> u64 {
+    if !is_user_range(string, len) {
+        kill(Fault::BadUserPointer);
+    }
+    user_access(|| {
+        let slice = unsafe { slice::from_raw_parts(string as *const u8, len as usize) };
+        crate::print!(
+            "{}",
+            core::str::from_utf8(slice).unwrap_or("<invalid utf8>")
+        );
+    });
+    0
+}
+
+// The free lists of the user heap are stored in user memory. A corrupted user heap can still make the kernel
+// write to arbitrary user addresses, but returned allocations are checked to stay inside the user heap
+fn sys_alloc(size: u64, alignment: u64, _: u64) -> u64 {
+    let Ok(layout) = core::alloc::Layout::from_size_align(size as usize, alignment as usize) else {
+        return 0;
+    };
+    let heap = unsafe { loader::running_application_heap() };
+    let ptr = user_access(|| unsafe { heap.alloc(layout) }) as u64;
+    if ptr != 0 && !(v::USER_HEAP_START..v::USER_END).contains(&ptr) {
+        kill(Fault::BadUserPointer);
+    }
+    ptr
+}
+
+fn sys_dealloc(ptr: u64, size: u64, alignment: u64) -> u64 {
+    let Ok(layout) = core::alloc::Layout::from_size_align(size as usize, alignment as usize) else {
+        kill(Fault::BadUserPointer);
+    };
+    if !(v::USER_HEAP_START..v::USER_END).contains(&ptr) || !is_user_range(ptr, size) {
+        kill(Fault::BadUserPointer);
+    }
+    let heap = unsafe { loader::running_application_heap() };
+    user_access(|| unsafe { heap.inner.dealloc(ptr as *mut u8, layout) });
+    0
+}
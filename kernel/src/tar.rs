@@ -0,0 +1,165 @@
+use crate::fs::FsError;
+
+// ustar archives (POSIX.1-1988, long paths through the prefix field), read in place.
+// Needs no heap, the ram disk can be a tar archive and the command line is read before the heap exists
+pub const BLOCK_SIZE: usize = 512;
+
+const NAME: (usize, usize) = (0, 100);
+const SIZE: (usize, usize) = (124, 12);
+const CHECKSUM: (usize, usize) = (148, 8);
+const TYPE_OFFSET: usize = 156;
+const MAGIC_OFFSET: usize = 257;
+// "ustar\0" (posix) or "ustar " (gnu)
+const MAGIC: &[u8] = b"ustar";
+const PREFIX: (usize, usize) = (345, 155);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Dir,
+    // links, devices, pax headers, ...
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    prefix: &'a str,
+    name: &'a str,
+    pub entry_type: EntryType,
+    pub data: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    // the path is relative to the archive, empty and "." components are skipped
+    pub fn components(&self) -> impl Iterator<Item = &'a str> {
+        self.prefix
+            .split('/')
+            .chain(self.name.split('/'))
+            .filter(|component| !component.is_empty() && *component != ".")
+    }
+
+    pub fn is(&self, path: &str) -> bool {
+        self.components().eq(path
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != "."))
+    }
+}
+
+pub fn is_archive(data: &[u8]) -> bool {
+    data.get(MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()) == Some(MAGIC)
+}
+
+// stops after the first error
+pub fn entries(archive: &[u8]) -> Entries<'_> {
+    Entries {
+        archive,
+        offset: 0,
+        failed: false,
+    }
+}
+
+// the data of the last file with this path
+pub fn find<'a>(archive: &'a [u8], path: &str) -> Option<&'a [u8]> {
+    entries(archive)
+        .map_while(Result::ok)
+        .filter(|entry| entry.entry_type == EntryType::File && entry.is(path))
+        .last()
+        .map(|entry| entry.data)
+}
+
+pub struct Entries<'a> {
+    archive: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let entry = self.parse();
+        self.failed = matches!(entry, Some(Err(_)));
+        entry
+    }
+}
+
+impl<'a> Entries<'a> {
+    // None at the end of the archive (a zero block or the end of the data)
+    fn parse(&mut self) -> Option<Result<Entry<'a>, FsError>> {
+        let header = self.archive.get(self.offset..self.offset + BLOCK_SIZE)?;
+        if header.iter().all(|&byte| byte == 0) {
+            return None;
+        }
+        Some(self.parse_header(header))
+    }
+
+    fn parse_header(&mut self, header: &'a [u8]) -> Result<Entry<'a>, FsError> {
+        // the checksum field counts as spaces
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| {
+                if (CHECKSUM.0..CHECKSUM.0 + CHECKSUM.1).contains(&i) {
+                    u64::from(b' ')
+                } else {
+                    u64::from(byte)
+                }
+            })
+            .sum();
+        if !is_archive(header) || octal(field(header, CHECKSUM))? != sum {
+            return Err(FsError::InvalidFormat);
+        }
+
+        let size =
+            usize::try_from(octal(field(header, SIZE))?).map_err(|_| FsError::InvalidFormat)?;
+        let start = self.offset + BLOCK_SIZE;
+        let data = self
+            .archive
+            .get(start..start + size)
+            .ok_or(FsError::InvalidFormat)?;
+        let name = text(field(header, NAME))?;
+        let entry_type = match header[TYPE_OFFSET] {
+            // old archives mark directories with a trailing slash
+            b'0' | b'\0' | b'7' if name.ends_with('/') => EntryType::Dir,
+            b'0' | b'\0' | b'7' => EntryType::File,
+            b'5' => EntryType::Dir,
+            _ => EntryType::Other,
+        };
+        self.offset = start + size.next_multiple_of(BLOCK_SIZE);
+        Ok(Entry {
+            prefix: text(field(header, PREFIX))?,
+            name,
+            entry_type,
+            data,
+        })
+    }
+}
+
+// up to the first nul byte
+fn field(header: &[u8], (offset, len): (usize, usize)) -> &[u8] {
+    let field = &header[offset..offset + len];
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(len);
+    &field[..end]
+}
+
+fn text(field: &[u8]) -> Result<&str, FsError> {
+    core::str::from_utf8(field).map_err(|_| FsError::InvalidFormat)
+}
+
+// surrounded by spaces in some archives
+fn octal(field: &[u8]) -> Result<u64, FsError> {
+    let digits = field.trim_ascii();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    digits.iter().try_fold(0u64, |value, &digit| match digit {
+        b'0'..=b'7' => value
+            .checked_mul(8)
+            .map(|value| value + u64::from(digit - b'0'))
+            .ok_or(FsError::InvalidFormat),
+        _ => Err(FsError::InvalidFormat),
+    })
+}
@@ -0,0 +1,122 @@
+use super::WindowInfo;
+use core::ops::Range;
+
+// The mouse pointer, drawn by the compositor over the composed rows of the back buffer. The pixels
+// under it are saved, so moving it only restores and redraws its rows instead of composing them again
+const WIDTH: usize = 11;
+const HEIGHT: usize = 16;
+// 'X' is the black outline, '.' the white inside, the rest is transparent
+const SHAPE: [&[u8]; HEIGHT] = [
+    b"X",
+    b"XX",
+    b"X.X",
+    b"X..X",
+    b"X...X",
+    b"X....X",
+    b"X.....X",
+    b"X......X",
+    b"X.......X",
+    b"X........X",
+    b"X.....XXXXX",
+    b"X..X..X",
+    b"X.X X..X",
+    b"XX  X..X",
+    b"X    X..X",
+    b"     XXXX",
+];
+// up to 4 bytes per pixel
+const MAX_BYTES_PER_PIXEL: usize = 4;
+
+pub struct Cursor {
+    // hidden until it is moved
+    position: Option<(usize, usize)>,
+    // where it is drawn, the pixels under it are saved row by row
+    drawn: Option<(usize, usize)>,
+    saved: [u8; WIDTH * HEIGHT * MAX_BYTES_PER_PIXEL],
+}
+
+impl Cursor {
+    pub const fn new() -> Self {
+        Self {
+            position: None,
+            drawn: None,
+            saved: [0; WIDTH * HEIGHT * MAX_BYTES_PER_PIXEL],
+        }
+    }
+
+    // the tip of the arrow
+    pub fn move_to(&mut self, x: usize, y: usize) {
+        self.position = Some((x, y));
+    }
+
+    pub const fn position(&self) -> Option<(usize, usize)> {
+        self.position
+    }
+
+    // it has to be drawn again
+    pub fn moved(&self) -> bool {
+        self.position != self.drawn
+    }
+
+    pub fn drawn_rows(&self, screen: &WindowInfo) -> Range<usize> {
+        self.drawn.map_or(0..0, |(x, y)| Self::clip(x, y, screen).1)
+    }
+
+    // the columns and rows of the screen the cursor covers at (x, y)
+    fn clip(x: usize, y: usize, screen: &WindowInfo) -> (Range<usize>, Range<usize>) {
+        (
+            x.min(screen.width)..(x + WIDTH).min(screen.width),
+            y.min(screen.height)..(y + HEIGHT).min(screen.height),
+        )
+    }
+
+    // puts the saved pixels back, returns the rows of the screen they are in
+    pub fn restore(&mut self, screen: &mut [u8], info: &WindowInfo) -> Range<usize> {
+        let Some((x, y)) = self.drawn.take() else {
+            return 0..0;
+        };
+        let (columns, rows) = Self::clip(x, y, info);
+        let len = columns.len() * info.bytes_per_pixel;
+        for (index, row) in rows.clone().enumerate() {
+            let start = (row * info.stride + columns.start) * info.bytes_per_pixel;
+            let saved = index * WIDTH * info.bytes_per_pixel;
+            screen[start..start + len].copy_from_slice(&self.saved[saved..saved + len]);
+        }
+        rows
+    }
+
+    // Draws the cursor at its position (after restore), returns the rows of the screen it is in.
+    // Black and white have the same bytes in every pixel format
+    pub fn draw(&mut self, screen: &mut [u8], info: &WindowInfo) -> Range<usize> {
+        crate::ass!(info.bytes_per_pixel, <=, MAX_BYTES_PER_PIXEL);
+        let Some((x, y)) = self.position else {
+            return 0..0;
+        };
+        let (columns, rows) = Self::clip(x, y, info);
+        let bytes_per_pixel = info.bytes_per_pixel;
+        for (index, row) in rows.clone().enumerate() {
+            let start = (row * info.stride + columns.start) * bytes_per_pixel;
+            let len = columns.len() * bytes_per_pixel;
+            let saved = index * WIDTH * bytes_per_pixel;
+            self.saved[saved..saved + len].copy_from_slice(&screen[start..start + len]);
+
+            for column in columns.clone() {
+                let value = match SHAPE[index].get(column - x) {
+                    Some(b'X') => 0,
+                    Some(b'.') => u8::MAX,
+                    _ => continue,
+                };
+                let pixel = (row * info.stride + column) * bytes_per_pixel;
+                screen[pixel..pixel + bytes_per_pixel.min(3)].fill(value);
+            }
+        }
+        self.drawn = Some((x, y));
+        rows
+    }
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,115 @@
+use super::{Color, TerminalWriter};
+use crate::image::Rect;
+use alloc::collections::VecDeque;
+use core::fmt::{self, Write};
+
+// A line graph of the last samples, one per column. New samples push the old ones out to the left,
+// the vertical scale goes from 0 to the largest shown sample
+pub struct LineGraph {
+    label: &'static str,
+    color: Color,
+    samples: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl LineGraph {
+    // keeps up to capacity samples (the widest area it is drawn into)
+    pub fn new(label: &'static str, color: Color, capacity: usize) -> Self {
+        Self {
+            label,
+            color,
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, value: u64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(value);
+    }
+
+    pub fn latest(&self) -> Option<u64> {
+        self.samples.back().copied()
+    }
+
+    // Clears the area (in pixels of the window) with the clear color and draws the graph into it,
+    // the label and the latest sample are written in its top left corner
+    pub fn draw(&self, writer: &mut TerminalWriter, area: Rect) {
+        let right = (area.x + area.width).min(writer.info.width);
+        let bottom = (area.y + area.height).min(writer.info.height);
+        if area.x >= right || area.y >= bottom {
+            return;
+        }
+        writer.hide_caret();
+        for y in area.y..bottom {
+            for x in area.x..right {
+                writer.write_pixel(x, y, writer.clear_color);
+            }
+        }
+
+        let shown = self.samples.len().min(area.width);
+        let samples = self.samples.iter().skip(self.samples.len() - shown);
+        let max = samples.clone().max().copied().unwrap_or(0).max(1);
+        // the largest sample is on the top row, 0 on the bottom one
+        let row = |value: u64| {
+            let height = (area.height - 1) as u128;
+            area.y + area.height - 1 - (u128::from(value) * height / u128::from(max)) as usize
+        };
+        let mut previous = None;
+        for (column, &value) in samples.enumerate() {
+            let x = area.x + area.width - shown + column;
+            let y = row(value);
+            // connected to the previous sample by a vertical line
+            let (top, end) =
+                previous.map_or((y, y), |previous: usize| (previous.min(y), previous.max(y)));
+            if x < right {
+                for y in top..=end.min(bottom - 1) {
+                    writer.write_pixel(x, y, self.color);
+                }
+            }
+            previous = Some(y);
+        }
+
+        if area.y + writer.font_height.val() <= bottom {
+            let background = writer.background;
+            writer.background = writer.clear_color;
+            let mut label = Label {
+                writer: &mut *writer,
+                x: area.x,
+                y: area.y,
+                right,
+            };
+            let _ = write!(label, "{} {}", self.label, self.latest().unwrap_or(0));
+            writer.background = background;
+        }
+        writer.touched(area.y..bottom);
+        writer.show_caret();
+    }
+}
+
+// text at a position of the window without moving the cursor, cut off at the right
+struct Label<'a> {
+    writer: &'a mut TerminalWriter,
+    x: usize,
+    y: usize,
+    right: usize,
+}
+
+impl Write for Label<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let (rasterized_char, char_width) = self.writer.get_rasterized_char(c);
+            if self.x + char_width > self.right {
+                break;
+            }
+            let cursor = (self.writer.x_pos, self.writer.y_pos);
+            (self.writer.x_pos, self.writer.y_pos) = (self.x, self.y);
+            self.writer.print_rasterized_char(&rasterized_char);
+            (self.writer.x_pos, self.writer.y_pos) = cursor;
+            self.x += char_width + super::LETTER_SPACING;
+        }
+        Ok(())
+    }
+}
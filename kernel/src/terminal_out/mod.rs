@@ -0,0 +1,1406 @@
+use crate::{
+    blit, get_boot_info,
+    image::{ImageBuffer, Rect, Scaling},
+    lock_debug::{self, TrackedRwLock, TrackedWriteGuard},
+    serial_mark,
+    time::Duration,
+    timer_callbacks,
+};
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use bootloader_api::info::PixelFormat;
+use core::hint;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use core::{
+    fmt::{self, Write},
+    ops::Range,
+    ptr, slice,
+};
+use lazy_static::lazy_static;
+use noto_sans_mono_bitmap::{get_raster, get_raster_width, RasterizedChar};
+use spin::{
+    mutex::{TicketMutex, TicketMutexGuard},
+    Mutex, Once,
+};
+use x86_64::instructions::interrupts;
+
+pub use cursor::Cursor;
+pub use graph::LineGraph;
+pub use noto_sans_mono_bitmap::FontWeight;
+pub use noto_sans_mono_bitmap::RasterHeight as FontSize;
+pub use screenshot::{save_screenshot, screenshot, send_screenshot};
+
+mod cursor;
+mod graph;
+mod screenshot;
+
+const LINE_SPACING: usize = 0;
+const LETTER_SPACING: usize = 0;
+const SIDE_PADDING: usize = 0;
+
+const TAB_SIZE: usize = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    // the opacity (255 is opaque), drawing a color blends it with the pixels below
+    pub a: u8,
+}
+
+#[allow(dead_code)]
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self::rgba(r, g, b, u8::MAX)
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn black() -> Self {
+        Self::new(0, 0, 0)
+    }
+
+    pub const fn white() -> Self {
+        Self::new(255, 255, 255)
+    }
+
+    pub const fn transparent() -> Self {
+        Self::rgba(0, 0, 0, 0)
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    pub const fn lerp(self, other: Self, alpha_1024: u32) -> Self {
+        let r =
+            (self.r as i32 + ((other.r as i32 - self.r as i32) * alpha_1024 as i32) / 1024) as u8;
+        let g =
+            (self.g as i32 + ((other.g as i32 - self.g as i32) * alpha_1024 as i32) / 1024) as u8;
+        let b =
+            (self.b as i32 + ((other.b as i32 - self.b as i32) * alpha_1024 as i32) / 1024) as u8;
+        let a =
+            (self.a as i32 + ((other.a as i32 - self.a as i32) * alpha_1024 as i32) / 1024) as u8;
+        Self { r, g, b, a }
+    }
+
+    // the color drawn over this one, it keeps the opacity of this one
+    pub const fn blend(self, over: Self) -> Self {
+        let blended = self.lerp(over, over.a as u32 * 1024 / 255);
+        Self::rgba(blended.r, blended.g, blended.b, self.a)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlacementInfo {
+    pub x_div: usize,
+    pub y_div: usize,
+    pub x_index: usize,
+    pub y_index: usize,
+    pub x_size: usize,
+    pub y_size: usize,
+}
+
+const FULL_SCREEN_PLACEMENT: PlacementInfo = PlacementInfo {
+    x_div: 1,
+    y_div: 1,
+    x_index: 0,
+    y_index: 0,
+    x_size: 1,
+    y_size: 1,
+};
+
+#[derive(Debug, Clone)]
+pub struct WindowInfo {
+    pub x: usize,
+    pub y: usize,
+    pub byte_offset: usize,
+    pub width: usize,
+    pub height: usize,
+    pub pixel_format: PixelFormat,
+    pub bytes_per_pixel: usize,
+    pub stride: usize,
+}
+
+impl WindowInfo {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        serial_mark!("new window info x:{x:?} y:{y:?} width:{width:?} height:{height:?}");
+        let fb_info = crate::get_boot_info().framebuffer.as_mut().unwrap().info();
+        crate::ass!(x + width, <=, fb_info.width);
+        crate::ass!(y + height, <=, fb_info.height);
+
+        let pixel_offset = y * fb_info.stride + x;
+        let byte_offset = pixel_offset * fb_info.bytes_per_pixel;
+
+        Self {
+            x,
+            y,
+            byte_offset,
+            width,
+            height,
+            pixel_format: fb_info.pixel_format,
+            bytes_per_pixel: fb_info.bytes_per_pixel,
+            stride: fb_info.stride,
+        }
+    }
+
+    pub fn from_placement(placement: &PlacementInfo) -> Self {
+        serial_mark!("from_placement: {placement:?}");
+        let fb_info = crate::get_boot_info().framebuffer.as_mut().unwrap().info();
+
+        let start_pixel_x = placement.x_index * fb_info.width / placement.x_div;
+        let stop_pixel_x = (placement.x_index + placement.x_size) * fb_info.width / placement.x_div;
+
+        let start_pixel_y = placement.y_index * fb_info.height / placement.y_div;
+        let stop_pixel_y =
+            (placement.y_index + placement.y_size) * fb_info.height / placement.y_div;
+
+        serial_mark!("start_pixel_x:{start_pixel_x} stop_pixel_x:{stop_pixel_x} start_pixel_y:{start_pixel_y} stop_pixel_y:{stop_pixel_y}");
+
+        Self::new(
+            start_pixel_x,
+            start_pixel_y,
+            stop_pixel_x - start_pixel_x,
+            stop_pixel_y - start_pixel_y,
+        )
+    }
+
+    // the whole frame buffer, in the resolution the bootloader selected (see BOOTLOADER_CONFIG)
+    pub fn screen() -> Self {
+        let fb_info = crate::get_boot_info().framebuffer.as_ref().unwrap().info();
+        Self {
+            x: 0,
+            y: 0,
+            byte_offset: 0,
+            width: fb_info.width,
+            height: fb_info.height,
+            pixel_format: fb_info.pixel_format,
+            bytes_per_pixel: fb_info.bytes_per_pixel,
+            stride: fb_info.stride,
+        }
+    }
+}
+
+// the color of the pixel at the start of the bytes
+fn pixel_color(bytes: &[u8], pixel_format: PixelFormat) -> Color {
+    match pixel_format {
+        PixelFormat::Rgb => Color::new(bytes[0], bytes[1], bytes[2]),
+        PixelFormat::Bgr => Color::new(bytes[2], bytes[1], bytes[0]),
+        _ => Color::new(bytes[0], bytes[0], bytes[0]),
+    }
+}
+
+// larger screens get larger fonts, so about as many lines fit on them
+pub const fn font_size_for(screen_height: usize) -> FontSize {
+    match screen_height {
+        0..=600 => FontSize::Size16,
+        601..=900 => FontSize::Size20,
+        901..=1440 => FontSize::Size24,
+        _ => FontSize::Size32,
+    }
+}
+
+// the font of new writers
+pub fn default_font_size() -> FontSize {
+    font_size_for(WindowInfo::screen().height)
+}
+
+// for small windows and bars, a size below the default one
+pub fn small_font_size() -> FontSize {
+    match default_font_size() {
+        FontSize::Size16 | FontSize::Size20 => FontSize::Size16,
+        FontSize::Size24 => FontSize::Size20,
+        FontSize::Size32 => FontSize::Size24,
+    }
+}
+
+// A surface on the heap in the pixel format of the frame buffer. Once shown, the compositor draws it
+// at its position over the windows with a lower z (see push_to_frame_buffer)
+pub struct Window {
+    pixels: *mut u8,
+    width: usize,
+    height: usize,
+    pixel_format: PixelFormat,
+    bytes_per_pixel: usize,
+    // changed while WINDOWS is locked
+    x: AtomicUsize,
+    y: AtomicUsize,
+    z: AtomicI32,
+    visible: AtomicBool,
+}
+
+unsafe impl Send for Window {}
+unsafe impl Sync for Window {}
+
+impl Window {
+    // the position and size of info, the pixels are black
+    pub fn new(info: &WindowInfo) -> Arc<Self> {
+        let pixels = vec![0u8; info.width * info.height * info.bytes_per_pixel];
+        Arc::new(Self {
+            pixels: Box::into_raw(pixels.into_boxed_slice()).cast(),
+            width: info.width,
+            height: info.height,
+            pixel_format: info.pixel_format,
+            bytes_per_pixel: info.bytes_per_pixel,
+            x: AtomicUsize::new(info.x),
+            y: AtomicUsize::new(info.y),
+            z: AtomicI32::new(0),
+            visible: AtomicBool::new(true),
+        })
+    }
+
+    // the surface as the buffer of a terminal writer
+    fn info(&self) -> WindowInfo {
+        let (x, y) = self.position();
+        WindowInfo {
+            x,
+            y,
+            byte_offset: 0,
+            width: self.width,
+            height: self.height,
+            pixel_format: self.pixel_format,
+            bytes_per_pixel: self.bytes_per_pixel,
+            stride: self.width,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.width * self.height * self.bytes_per_pixel
+    }
+
+    // writers and the compositor access the pixels at the same time (they may tear, see lock_back_buffer)
+    #[allow(clippy::mut_from_ref)]
+    fn surface(&self) -> &'static mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.pixels, self.len()) }
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (
+            self.x.load(Ordering::Relaxed),
+            self.y.load(Ordering::Relaxed),
+        )
+    }
+
+    pub const fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    // whether the pixel of the screen is in the window
+    fn contains(&self, x: usize, y: usize) -> bool {
+        let (left, top) = self.position();
+        (left..left + self.width).contains(&x) && (top..top + self.height).contains(&y)
+    }
+
+    pub fn move_to(&self, x: usize, y: usize) {
+        let _windows = WINDOWS.lock();
+        self.damage();
+        self.x.store(x, Ordering::Relaxed);
+        self.y.store(y, Ordering::Relaxed);
+        self.damage();
+    }
+
+    // windows with a higher z are drawn over the others, the last shown one is on top of equal ones
+    pub fn set_z(&self, z: i32) {
+        let mut windows = WINDOWS.lock();
+        self.z.store(z, Ordering::Relaxed);
+        windows.sort_by_key(|window| window.z.load(Ordering::Relaxed));
+        self.damage();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_visible(&self, visible: bool) {
+        let _windows = WINDOWS.lock();
+        self.visible.store(visible, Ordering::Relaxed);
+        self.damage();
+    }
+
+    // marks rows of the window for the next push_to_frame_buffer
+    fn touched(&self, rows: Range<usize>) {
+        let y = self.y.load(Ordering::Relaxed);
+        mark_dirty(y + rows.start..y + rows.end.min(self.height));
+    }
+
+    fn damage(&self) {
+        self.touched(0..self.height);
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.pixels, self.len())) });
+    }
+}
+
+// the shown windows ordered by z, the compositor draws them from the first to the last
+static WINDOWS: Mutex<Vec<Arc<Window>>> = Mutex::new(Vec::new());
+
+pub fn show(window: Arc<Window>) {
+    let mut windows = WINDOWS.lock();
+    window.damage();
+    windows.push(window);
+    windows.sort_by_key(|window| window.z.load(Ordering::Relaxed));
+}
+
+#[allow(dead_code)]
+pub fn hide(window: &Arc<Window>) {
+    WINDOWS.lock().retain(|shown| !Arc::ptr_eq(shown, window));
+    window.damage();
+}
+
+// new takes the place of old (its z), old is hidden
+fn replace(old: &Arc<Window>, new: Arc<Window>) {
+    let mut windows = WINDOWS.lock();
+    new.z
+        .store(old.z.load(Ordering::Relaxed), Ordering::Relaxed);
+    new.damage();
+    old.damage();
+    match windows.iter().position(|shown| Arc::ptr_eq(shown, old)) {
+        Some(index) => windows[index] = new,
+        None => windows.push(new),
+    }
+    windows.sort_by_key(|window| window.z.load(Ordering::Relaxed));
+}
+
+// Draws row y of the screen (the rest is black), the windows are drawn over each other in their order
+pub fn compose_row(windows: &[Arc<Window>], y: usize, row: &mut [u8], bytes_per_pixel: usize) {
+    row.fill(0);
+    for window in windows {
+        let (x, top) = window.position();
+        let start = x * bytes_per_pixel;
+        if !window.visible.load(Ordering::Relaxed)
+            || !(top..top + window.height).contains(&y)
+            || start >= row.len()
+        {
+            continue;
+        }
+        let row_len = window.width * bytes_per_pixel;
+        let len = row_len.min(row.len() - start);
+        unsafe {
+            ptr::copy_nonoverlapping(
+                window.pixels.add((y - top) * row_len),
+                row[start..].as_mut_ptr(),
+                len,
+            );
+        }
+    }
+}
+
+pub struct TerminalWriter {
+    buffer_base_ptr: *mut u8,
+    buffer: &'static mut [u8],
+    info: WindowInfo,
+    // the writer draws into the frame buffer without one
+    window: Option<Arc<Window>>,
+    x_pos: usize,
+    y_pos: usize,
+    // where the word at the end of the current line starts (it moves to the next line if it doesn't fit)
+    word_start: Option<usize>,
+    font_height: FontSize,
+    line_height: usize,
+    pub font_weight: FontWeight,
+    pub foreground: Color,
+    pub background: Color,
+    pub clear_color: Color,
+    // a blinking caret at the write position, it is hidden while the writer draws
+    caret: bool,
+    caret_on: bool,
+    // the inverted columns and rows
+    caret_drawn: Option<(Range<usize>, Range<usize>)>,
+}
+
+fn construct_buffer(buffer_base_ptr: *mut u8, info: &WindowInfo) -> &'static mut [u8] {
+    unsafe {
+        slice::from_raw_parts_mut(
+            buffer_base_ptr.add(info.byte_offset),
+            info.stride * info.height * info.bytes_per_pixel,
+        )
+    }
+}
+
+impl TerminalWriter {
+    // draws into this part of the frame buffer
+    pub fn new(info: WindowInfo) -> Self {
+        let buffer_base_ptr = crate::get_boot_info()
+            .framebuffer
+            .as_mut()
+            .unwrap()
+            .buffer_mut()
+            .as_mut_ptr();
+        Self::with_buffer(
+            buffer_base_ptr,
+            construct_buffer(buffer_base_ptr, &info),
+            info,
+            None,
+        )
+    }
+
+    // draws into the window, it has to be shown to be on the screen
+    pub fn in_window(window: Arc<Window>) -> Self {
+        Self::with_buffer(
+            ptr::null_mut(),
+            window.surface(),
+            window.info(),
+            Some(window),
+        )
+    }
+
+    fn with_buffer(
+        buffer_base_ptr: *mut u8,
+        buffer: &'static mut [u8],
+        info: WindowInfo,
+        window: Option<Arc<Window>>,
+    ) -> Self {
+        let font_height = default_font_size();
+        Self {
+            buffer_base_ptr,
+            buffer,
+            info,
+            window,
+            x_pos: 0,
+            y_pos: 0,
+            word_start: None,
+            font_height,
+            line_height: font_height.val() + LINE_SPACING,
+            font_weight: FontWeight::Regular,
+            foreground: Color::white(),
+            background: Color::black(),
+            clear_color: Color::new(70, 60, 60),
+            caret: false,
+            caret_on: false,
+            caret_drawn: None,
+        }
+    }
+
+    // waits for the compositor and moves what the writer drew so far into a window of its own
+    pub fn set_to_double_buffer(&mut self) {
+        while DOUBLE_BUFFER.get().is_none() {
+            hint::spin_loop();
+        }
+        if self.window.is_some() {
+            return;
+        }
+        let window = Window::new(&self.info);
+        let surface = window.surface();
+        let row_len = self.info.width * self.info.bytes_per_pixel;
+        for (y, row) in surface.chunks_exact_mut(row_len).enumerate() {
+            let offset = y * self.info.stride * self.info.bytes_per_pixel;
+            row.copy_from_slice(&self.buffer[offset..offset + row_len]);
+        }
+        self.buffer = surface;
+        self.info = window.info();
+        show(window.clone());
+        self.window = Some(window);
+    }
+
+    pub fn window_info(&self) -> &WindowInfo {
+        &self.info
+    }
+
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        self.window.as_ref()
+    }
+
+    fn move_to(&mut self, x: usize, y: usize) {
+        if let Some(window) = &self.window {
+            window.move_to(x, y);
+            (self.info.x, self.info.y) = (x, y);
+        }
+    }
+
+    // a writer with a window gets a new one (with the z of the old one)
+    pub fn set_window_info(&mut self, info: WindowInfo, font_height: Option<FontSize>) {
+        serial_mark!("Setting window info to {:?}", &info);
+        self.hide_caret();
+        if let Some(old) = self.window.take() {
+            let window = Window::new(&info);
+            replace(&old, window.clone());
+            self.buffer = window.surface();
+            self.info = window.info();
+            self.window = Some(window);
+        } else {
+            self.info = info;
+            self.buffer = construct_buffer(self.buffer_base_ptr, &self.info);
+        }
+        self.clear(font_height);
+    }
+
+    pub fn clear(&mut self, font_height: Option<FontSize>) {
+        self.hide_caret();
+        self.carriage_return();
+        self.y_pos = 0;
+
+        self.fill_rows(0..self.info.height, self.clear_color);
+
+        if let Some(font_height) = font_height {
+            self.font_height = font_height;
+            self.line_height = self.font_height.val() + LINE_SPACING;
+        }
+        self.show_caret();
+    }
+
+    pub fn set_caret(&mut self, enabled: bool) {
+        self.hide_caret();
+        self.caret = enabled;
+        self.show_caret();
+    }
+
+    pub const fn has_caret(&self) -> bool {
+        self.caret
+    }
+
+    // the blink phase of the caret (see blink_carets)
+    pub fn blink(&mut self, on: bool) {
+        self.caret_on = on;
+        if on {
+            self.show_caret();
+        } else {
+            self.hide_caret();
+        }
+    }
+
+    fn show_caret(&mut self) {
+        if !self.caret || !self.caret_on || self.caret_drawn.is_some() {
+            return;
+        }
+        let char_width = get_raster_width(self.font_weight, self.font_height);
+        // where write_raw_char puts the next character
+        let start = if self.x_pos == 0 {
+            SIDE_PADDING
+        } else {
+            self.x_pos
+        }
+        .min(self.info.width);
+        let columns = start..(start + char_width).min(self.info.width);
+        let top = self.y_pos.min(self.info.height);
+        let rows = top..(top + self.line_height).min(self.info.height);
+        self.invert(columns.clone(), rows.clone());
+        self.caret_drawn = Some((columns, rows));
+    }
+
+    fn hide_caret(&mut self) {
+        if let Some((columns, rows)) = self.caret_drawn.take() {
+            self.invert(columns, rows);
+        }
+    }
+
+    // inverting twice restores the pixels
+    fn invert(&mut self, columns: Range<usize>, rows: Range<usize>) {
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        for y in rows.clone() {
+            let row = y * self.info.stride * bytes_per_pixel;
+            let pixels = row + columns.start * bytes_per_pixel..row + columns.end * bytes_per_pixel;
+            for byte in &mut self.buffer[pixels] {
+                *byte = !*byte;
+            }
+        }
+        self.touched(rows);
+    }
+
+    fn newline(&mut self) {
+        self.y_pos += self.line_height;
+        self.carriage_return();
+
+        // the window scrolls up until the new line fits
+        let overflow = (self.y_pos + self.line_height).saturating_sub(self.info.height);
+        if overflow > 0 {
+            self.scroll_up(overflow.min(self.y_pos));
+        }
+        let end = (self.y_pos + self.line_height).min(self.info.height);
+        self.fill_rows(self.y_pos..end, self.clear_color);
+    }
+
+    // moves the content of the window up, the rows at the bottom keep their old content
+    fn scroll_up(&mut self, rows: usize) {
+        let row_len = self.info.width * self.info.bytes_per_pixel;
+        let row_stride = self.info.stride * self.info.bytes_per_pixel;
+        for y in 0..self.info.height.saturating_sub(rows) {
+            let src = (y + rows) * row_stride;
+            self.buffer.copy_within(src..src + row_len, y * row_stride);
+        }
+        self.touched(0..self.info.height);
+        self.y_pos -= rows;
+    }
+
+    fn fill_rows(&mut self, rows: Range<usize>, color: Color) {
+        for y in rows.clone() {
+            for x in 0..self.info.width {
+                self.write_pixel(x, y, color);
+            }
+        }
+        self.touched(rows);
+    }
+
+    // writers without a window draw into the front buffer directly
+    fn touched(&self, rows: Range<usize>) {
+        if let Some(window) = &self.window {
+            window.touched(rows);
+        }
+    }
+
+    fn carriage_return(&mut self) {
+        self.x_pos = 0;
+        self.word_start = None;
+    }
+
+    // The tab stops are TAB_SIZE characters of the current font apart, counted in pixels from the left
+    // edge of the window. So they line up even if the position is not on the character grid (e.g. after
+    // a font change). A tab past the last stop of the line goes to the next line
+    fn tab(&mut self) {
+        let char_width = get_raster_width(self.font_weight, self.font_height) + LETTER_SPACING;
+        let tab_width = TAB_SIZE * char_width;
+        let x = if self.x_pos == 0 {
+            SIDE_PADDING
+        } else {
+            self.x_pos
+        };
+        let stop = SIDE_PADDING + ((x - SIDE_PADDING) / tab_width + 1) * tab_width;
+        if stop + char_width >= self.info.width.saturating_sub(SIDE_PADDING) {
+            self.newline();
+            return;
+        }
+
+        let bottom = (self.y_pos + self.line_height).min(self.info.height);
+        for y in self.y_pos..bottom {
+            for column in x..stop {
+                self.write_pixel(column, y, self.background);
+            }
+        }
+        self.touched(self.y_pos..bottom);
+        self.x_pos = stop;
+        self.word_start = None;
+    }
+
+    pub fn write_char(&mut self, c: char) {
+        self.hide_caret();
+        self.put_char(c);
+        self.show_caret();
+    }
+
+    // Removes the character before the write position (line editing, see shell.rs). It stays on the
+    // line, characters that were wrapped or scrolled away are not erased
+    pub fn erase_char(&mut self) {
+        let char_width = get_raster_width(self.font_weight, self.font_height) + LETTER_SPACING;
+        if self.x_pos < SIDE_PADDING + char_width {
+            return;
+        }
+        self.hide_caret();
+        self.x_pos -= char_width;
+        let bottom = (self.y_pos + self.line_height).min(self.info.height);
+        for y in self.y_pos..bottom {
+            for column in self.x_pos..self.x_pos + char_width {
+                self.write_pixel(column, y, self.background);
+            }
+        }
+        self.touched(self.y_pos..bottom);
+        self.word_start = self.word_start.filter(|&start| start < self.x_pos);
+        self.show_caret();
+    }
+
+    fn put_char(&mut self, c: char) {
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.carriage_return(),
+            '\t' => self.tab(),
+            c => self.write_raw_char(c),
+        }
+    }
+
+    #[inline]
+    fn get_rasterized_char(&self, c: char) -> (RasterizedChar, usize) {
+        (
+            get_raster(c, self.font_weight, self.font_height)
+                .unwrap_or_else(|| get_raster('�', self.font_weight, self.font_height).unwrap()),
+            get_raster_width(self.font_weight, self.font_height),
+        )
+    }
+
+    // lines are wrapped between words, a space that doesn't fit on the line is dropped
+    fn write_raw_char(&mut self, c: char) {
+        let (rasterized_char, char_width) = self.get_rasterized_char(c);
+        if self.x_pos == 0 {
+            self.x_pos = SIDE_PADDING;
+        }
+        let space = c.is_whitespace();
+        if space {
+            self.word_start = None;
+        }
+        if self.x_pos + char_width >= self.info.width - SIDE_PADDING {
+            if space {
+                self.newline();
+                return;
+            }
+            self.wrap();
+        }
+        if !space && self.word_start.is_none() {
+            self.word_start = Some(self.x_pos);
+        }
+
+        self.print_rasterized_char(&rasterized_char);
+
+        self.x_pos += char_width + LETTER_SPACING;
+    }
+
+    // Starts a new line and moves the pixels of the word at the end of the old one there. Words that
+    // start the line are broken, as are all of them if only one line fits into the window
+    fn wrap(&mut self) {
+        let end = self.x_pos;
+        let word = self
+            .word_start
+            .filter(|&start| start > SIDE_PADDING && self.info.height >= 2 * self.line_height);
+        self.newline();
+        let Some(start) = word else {
+            return;
+        };
+
+        // the old line is right above the new one (after scrolling)
+        let old_y = self.y_pos - self.line_height;
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let row_stride = self.info.stride * bytes_per_pixel;
+        let len = (end - start) * bytes_per_pixel;
+        for row in 0..self.line_height {
+            let src = (old_y + row) * row_stride + start * bytes_per_pixel;
+            let dst = (self.y_pos + row) * row_stride + SIDE_PADDING * bytes_per_pixel;
+            self.buffer.copy_within(src..src + len, dst);
+        }
+        for y in old_y..self.y_pos {
+            for x in start..end {
+                self.write_pixel(x, y, self.clear_color);
+            }
+        }
+        self.touched(old_y..self.y_pos + self.line_height);
+        self.x_pos = SIDE_PADDING + end - start;
+        self.word_start = Some(SIDE_PADDING);
+    }
+
+    fn print_rasterized_char(&mut self, rasterized_char: &RasterizedChar) {
+        for (y, row) in rasterized_char.raster().iter().enumerate() {
+            for (x, byte) in row.iter().enumerate() {
+                let color = self
+                    .background
+                    .lerp(self.foreground, *byte as u32 * 1024 / 255);
+                self.write_pixel(self.x_pos + x, self.y_pos + y, color);
+            }
+        }
+        self.touched(self.y_pos..self.y_pos + self.line_height);
+    }
+
+    // Draws the image into the rectangle (in pixels of the window), the parts outside of the window
+    // are clipped. The cursor stays where it is
+    pub fn blit(&mut self, image: &ImageBuffer, dst: Rect, scaling: Scaling) {
+        if image.is_empty() {
+            return;
+        }
+        let right = dst.x + scaling.covered(dst.width, image.width);
+        let bottom = dst.y + scaling.covered(dst.height, image.height);
+        let columns = dst.x..right.min(self.info.width);
+        let rows = dst.y..bottom.min(self.info.height);
+        if columns.is_empty() || rows.is_empty() {
+            return;
+        }
+
+        self.hide_caret();
+        for y in rows.clone() {
+            let image_y = scaling.source(y - dst.y, dst.height, image.height);
+            for x in columns.clone() {
+                let image_x = scaling.source(x - dst.x, dst.width, image.width);
+                self.write_pixel(x, y, image.pixel(image_x, image_y));
+            }
+        }
+        self.touched(rows);
+        self.show_caret();
+    }
+
+    // draws the image scaled to the width (at most the one of the window) on new lines below the cursor,
+    // the text continues under it
+    pub fn print_image(&mut self, image: &ImageBuffer, width: usize) {
+        let width = width.min(self.info.width);
+        let height = image.height_at(width);
+        self.hide_caret();
+        if self.x_pos != 0 {
+            self.newline();
+        }
+        // scrolls until the image fits above the cursor (or the window is full)
+        let lines = height.div_ceil(self.line_height);
+        for _ in 0..lines {
+            self.newline();
+        }
+        let top = self.y_pos.saturating_sub(lines * self.line_height);
+        self.blit(image, Rect::new(0, top, width, height), Scaling::Nearest);
+        self.show_caret();
+    }
+
+    #[inline]
+    #[allow(clippy::cast_ptr_alignment)]
+    fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let color = match color.a {
+            0 => return,
+            u8::MAX => color,
+            _ => self.read_pixel(x, y).blend(color),
+        };
+        let pixel_offset = y * self.info.stride + x;
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let byte_offset = pixel_offset * bytes_per_pixel;
+
+        match self.info.pixel_format {
+            PixelFormat::Rgb => {
+                self.buffer[byte_offset] = color.r;
+                self.buffer[byte_offset + 1] = color.g;
+                self.buffer[byte_offset + 2] = color.b;
+            }
+            PixelFormat::Bgr => {
+                self.buffer[byte_offset] = color.b;
+                self.buffer[byte_offset + 1] = color.g;
+                self.buffer[byte_offset + 2] = color.r;
+            }
+            _ => {
+                self.buffer[byte_offset] =
+                    ((color.r as u16 + color.g as u16 + color.b as u16) / 3) as u8;
+            }
+        };
+    }
+
+    fn read_pixel(&self, x: usize, y: usize) -> Color {
+        let byte_offset = (y * self.info.stride + x) * self.info.bytes_per_pixel;
+        pixel_color(&self.buffer[byte_offset..], self.info.pixel_format)
+    }
+
+    pub fn print(&mut self, args: fmt::Arguments) {
+        self.write_fmt(args).unwrap();
+    }
+}
+
+impl fmt::Write for TerminalWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.hide_caret();
+        for c in s.chars() {
+            self.put_char(c);
+        }
+        self.show_caret();
+        Ok(())
+    }
+}
+
+unsafe impl Send for TerminalWriter {}
+unsafe impl Sync for TerminalWriter {}
+
+lazy_static! {
+    // the window of stdout (STDOUT_WINDOW) once the double buffer is on
+    pub static ref TERM: Arc<TrackedRwLock<TerminalWriter>> = Arc::new(lock_debug::tracked_rw(
+        "TERM",
+        TerminalWriter::new(WindowInfo::from_placement(&FULL_SCREEN_PLACEMENT))
+    ));
+    static ref EMERGENCY_PANIC_TERM: Mutex<TerminalWriter> = Mutex::new(TerminalWriter::new(
+        WindowInfo::from_placement(&FULL_SCREEN_PLACEMENT)
+    ));
+}
+
+static SWAP_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+static PANICKED_STOP_PRINTING: AtomicBool = AtomicBool::new(false);
+static DOUBLE_BUFFER: Once<DoubleBuffer> = Once::new();
+// fair, the cores printing in a loop get it in turns with the compositor
+static BACK_BUFFER_LOCK: TicketMutex<()> = TicketMutex::new(());
+
+// Rows of the back buffer changed since the last push, a bit per band of rows (up to 8192 rows).
+// Writers mark rows after writing them, so a band marked during a push is copied again by the next one
+const DIRTY_BAND_ROWS: usize = 8;
+#[allow(clippy::declare_interior_mutable_const)]
+const CLEAN: AtomicU64 = AtomicU64::new(0);
+static DIRTY_BANDS: [AtomicU64; 16] = [CLEAN; 16];
+
+fn mark_dirty(rows: Range<usize>) {
+    if rows.is_empty() {
+        return;
+    }
+    let last_band = ((rows.end - 1) / DIRTY_BAND_ROWS).min(DIRTY_BANDS.len() * 64 - 1);
+    for band in rows.start / DIRTY_BAND_ROWS..=last_band {
+        DIRTY_BANDS[band / 64].fetch_or(1 << (band % 64), Ordering::Release);
+    }
+}
+
+// the dirty bands since the last call
+fn take_dirty_bands() -> [u64; DIRTY_BANDS.len()] {
+    core::array::from_fn(|word| DIRTY_BANDS[word].swap(0, Ordering::Acquire))
+}
+
+fn is_dirty(bands: &[u64; DIRTY_BANDS.len()], y: usize) -> bool {
+    let band = (y / DIRTY_BAND_ROWS).min(DIRTY_BANDS.len() * 64 - 1);
+    bands[band / 64] & (1 << (band % 64)) != 0
+}
+
+struct DoubleBuffer {
+    back_buffer: *mut u8,
+    front_buffer: *mut u8,
+    length: usize,
+}
+
+unsafe impl Send for DoubleBuffer {}
+unsafe impl Sync for DoubleBuffer {}
+
+pub fn switch_to_double_buffer() {
+    DOUBLE_BUFFER.call_once(|| {
+        let frame_buffer = get_boot_info().framebuffer.as_mut().unwrap();
+        let info = frame_buffer.info();
+        // bootloader bug mitigation (the size of the framebuffer is the whole vram)
+        let frame_buffer_size = info
+            .byte_len
+            .min(info.stride * info.bytes_per_pixel * info.height);
+
+        log::trace!("Initializing double buffer (size{})", frame_buffer_size);
+
+        // the compositor draws the rows into it before they are copied to the frame buffer
+        let new_back_buffer = vec![0; frame_buffer_size].leak();
+        DoubleBuffer {
+            back_buffer: new_back_buffer.as_mut_ptr(),
+            front_buffer: frame_buffer.buffer_mut().as_mut_ptr(),
+            length: frame_buffer_size,
+        }
+    });
+    TERM.write().set_to_double_buffer();
+    MANAGED.lock().push(Managed {
+        id: STDOUT_WINDOW,
+        writer: TERM.clone(),
+        tiled: true,
+    });
+    timer_callbacks::add_periodic(CARET_BLINK, blink_carets);
+    // the first push draws the whole screen
+    mark_dirty(0..usize::MAX);
+}
+
+pub fn push_to_frame_buffer() {
+    unsafe {
+        if PANICKED_STOP_PRINTING.load(core::sync::atomic::Ordering::Acquire) {
+            return;
+        }
+        let fb_info = get_boot_info().framebuffer.as_ref().unwrap().info();
+        let db = DOUBLE_BUFFER.get().unwrap(); // Required for panic handling
+        let _term = TERM.read(); // Lock terminal to prevent tearing from main out (other readers don't wait)
+        let _back_buffer = BACK_BUFFER_LOCK.lock(); // Lock back buffer to prevent tearing from other cores (opt in)
+        interrupts::without_interrupts(|| {
+            let windows = WINDOWS.lock();
+            let mut cursor = CURSOR.lock();
+            let _swap = SWAP_LOCK.lock();
+            let blit = blit::method();
+            let row_len = fb_info.width * fb_info.bytes_per_pixel;
+            let screen = WindowInfo::screen();
+            let back_buffer = slice::from_raw_parts_mut(db.back_buffer, db.length);
+            let row_offset = |y: usize| y * fb_info.stride * fb_info.bytes_per_pixel;
+
+            // The cursor is taken off the back buffer before the rows are composed and drawn over them
+            // afterwards, if it moved or is in a dirty row. Otherwise it stays where it is
+            let dirty = take_dirty_bands();
+            let dirty_rows = || {
+                (0..fb_info.height.div_ceil(DIRTY_BAND_ROWS))
+                    .filter(|&band| is_dirty(&dirty, band * DIRTY_BAND_ROWS))
+                    .flat_map(|band| band * DIRTY_BAND_ROWS..(band + 1) * DIRTY_BAND_ROWS)
+                    .take_while(|&y| y < fb_info.height)
+            };
+            let redraw = cursor.moved() || cursor.drawn_rows(&screen).any(|y| is_dirty(&dirty, y));
+            let restored = if redraw {
+                cursor.restore(back_buffer, &screen)
+            } else {
+                0..0
+            };
+            for y in dirty_rows() {
+                let row = &mut back_buffer[row_offset(y)..row_offset(y) + row_len];
+                compose_row(&windows, y, row, fb_info.bytes_per_pixel);
+            }
+            let drawn = if redraw {
+                cursor.draw(back_buffer, &screen)
+            } else {
+                0..0
+            };
+            let cursor_rows = restored
+                .clone()
+                .chain(drawn.filter(|y| !restored.contains(y)))
+                .filter(|&y| !is_dirty(&dirty, y));
+            for y in dirty_rows().chain(cursor_rows) {
+                // more efficient if stride is big (may be in the thousands)
+                blit.copy(
+                    db.back_buffer.add(row_offset(y)),
+                    db.front_buffer.add(row_offset(y)),
+                    row_len,
+                );
+            }
+        });
+        FRAMES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+static FRAMES: AtomicU64 = AtomicU64::new(0);
+static CURSOR: Mutex<Cursor> = Mutex::new(Cursor::new());
+
+// the mouse pointer is drawn with the next frame, it is hidden until it is moved the first time
+pub fn move_cursor(x: usize, y: usize) {
+    CURSOR.lock().move_to(x, y);
+}
+
+// pushed to the frame buffer since the switch to the double buffer
+pub fn frames() -> u64 {
+    FRAMES.load(Ordering::Relaxed)
+}
+
+// Windows created at runtime, referred to by their id. They are tiled over the screen in the order of
+// creation until they are moved or resized, then they float over the tiled ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WindowId(u64);
+
+pub const STDOUT_WINDOW: WindowId = WindowId(0);
+
+struct Managed {
+    id: WindowId,
+    // written by the printing tasks, read by the compositor and the queries
+    writer: Arc<TrackedRwLock<TerminalWriter>>,
+    tiled: bool,
+}
+
+static MANAGED: Mutex<Vec<Managed>> = Mutex::new(Vec::new());
+static NEXT_WINDOW_ID: AtomicU64 = AtomicU64::new(1);
+// the window the keyboard input goes to, it shows its caret
+static FOCUSED: AtomicU64 = AtomicU64::new(STDOUT_WINDOW.0);
+// rows at the top of the screen the tiled windows leave free (see reserve_top)
+static RESERVED_TOP: AtomicUsize = AtomicUsize::new(0);
+
+// the windows don't have to fit on the screen, the compositor only draws the visible part
+fn surface_info(x: usize, y: usize, width: usize, height: usize) -> WindowInfo {
+    let fb_info = get_boot_info().framebuffer.as_ref().unwrap().info();
+    WindowInfo {
+        x,
+        y,
+        byte_offset: 0,
+        width: width.max(1),
+        height: height.max(1),
+        pixel_format: fb_info.pixel_format,
+        bytes_per_pixel: fb_info.bytes_per_pixel,
+        stride: width.max(1),
+    }
+}
+
+// A grid with about as many columns as rows below the reserved rows, the longer side of the screen
+// gets the extra ones. Windows that change their size are cleared
+fn relayout(managed: &[Managed]) {
+    let fb_info = get_boot_info().framebuffer.as_ref().unwrap().info();
+    let top = RESERVED_TOP.load(Ordering::Relaxed).min(fb_info.height);
+    let tiled: Vec<&Managed> = managed.iter().filter(|window| window.tiled).collect();
+    let longer = (1..=tiled.len().max(1))
+        .find(|longer| longer * longer >= tiled.len())
+        .unwrap();
+    let shorter = tiled.len().div_ceil(longer).max(1);
+    let (columns, rows) = if fb_info.width >= fb_info.height - top {
+        (longer, shorter)
+    } else {
+        (shorter, longer)
+    };
+    // the start of column or row i
+    let x = |i: usize| i * fb_info.width / columns;
+    let y = |i: usize| top + i * (fb_info.height - top) / rows;
+    for (index, window) in tiled.iter().enumerate() {
+        let (column, row) = (index % columns, index / columns);
+        let info = WindowInfo::new(
+            x(column),
+            y(row),
+            x(column + 1) - x(column),
+            y(row + 1) - y(row),
+        );
+        let mut writer = window.writer.write();
+        if writer.window().unwrap().size() == (info.width, info.height) {
+            writer.move_to(info.x, info.y);
+        } else {
+            writer.set_window_info(info, None);
+        }
+    }
+}
+
+// A window over the width of the screen at its top, drawn over all others. The tiled windows are laid
+// out below it. It has no id, the window manager doesn't move it
+pub fn reserve_top(height: usize) -> TerminalWriter {
+    let fb_info = get_boot_info().framebuffer.as_ref().unwrap().info();
+    let window = Window::new(&surface_info(0, 0, fb_info.width, height));
+    window.set_z(i32::MAX);
+    show(window.clone());
+    RESERVED_TOP.store(height, Ordering::Relaxed);
+    relayout(&MANAGED.lock());
+    TerminalWriter::in_window(window)
+}
+
+// a tiled window, the others make room for it
+pub fn create_window() -> WindowId {
+    let id = WindowId(NEXT_WINDOW_ID.fetch_add(1, Ordering::Relaxed));
+    let window = Window::new(&surface_info(0, 0, 1, 1));
+    show(window.clone());
+    let mut managed = MANAGED.lock();
+    managed.push(Managed {
+        id,
+        writer: Arc::new(lock_debug::tracked_rw(
+            "window",
+            TerminalWriter::in_window(window),
+        )),
+        tiled: true,
+    });
+    relayout(&managed);
+    id
+}
+
+// false if there is no such window, stdout can not be destroyed
+pub fn destroy_window(id: WindowId) -> bool {
+    let mut managed = MANAGED.lock();
+    let Some(index) = managed
+        .iter()
+        .position(|window| window.id == id && id != STDOUT_WINDOW)
+    else {
+        return false;
+    };
+    let window = managed.remove(index);
+    hide(window.writer.read().window().unwrap());
+    if focused() == id {
+        set_focus(&managed, STDOUT_WINDOW);
+    }
+    relayout(&managed);
+    true
+}
+
+// the window floats afterwards
+pub fn move_window(id: WindowId, x: usize, y: usize) -> bool {
+    float(id, |writer| writer.move_to(x, y))
+}
+
+// the window floats afterwards and is cleared
+pub fn resize_window(id: WindowId, width: usize, height: usize) -> bool {
+    float(id, |writer| {
+        let (x, y) = writer.window().unwrap().position();
+        writer.set_window_info(surface_info(x, y, width, height), None);
+    })
+}
+
+fn float(id: WindowId, change: impl FnOnce(&mut TerminalWriter)) -> bool {
+    let mut managed = MANAGED.lock();
+    let Some(window) = managed.iter_mut().find(|window| window.id == id) else {
+        return false;
+    };
+    window.tiled = false;
+    {
+        let mut writer = window.writer.write();
+        writer.window().unwrap().set_z(1);
+        change(&mut writer);
+    }
+    relayout(&managed);
+    true
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WindowStats {
+    pub id: WindowId,
+    pub position: (usize, usize),
+    pub size: (usize, usize),
+    pub tiled: bool,
+}
+
+// snapshot of the managed windows, in the order they were created
+pub fn window_stats() -> Vec<WindowStats> {
+    MANAGED
+        .lock()
+        .iter()
+        .map(|window| {
+            let writer = window.writer.read();
+            let surface = writer.window().unwrap();
+            WindowStats {
+                id: window.id,
+                position: surface.position(),
+                size: surface.size(),
+                tiled: window.tiled,
+            }
+        })
+        .collect()
+}
+
+pub fn focused() -> WindowId {
+    WindowId(FOCUSED.load(Ordering::Relaxed))
+}
+
+// false if there is no such window
+pub fn focus(id: WindowId) -> bool {
+    let managed = MANAGED.lock();
+    if !managed.iter().any(|window| window.id == id) {
+        return false;
+    }
+    set_focus(&managed, id);
+    true
+}
+
+// The topmost window at the pixel of the screen gets the focus (a click). None if there is no window
+// or it is not managed (like the reserved one at the top), the focus stays then
+pub fn focus_at(x: usize, y: usize) -> Option<WindowId> {
+    let managed = MANAGED.lock();
+    let surfaces: Vec<(WindowId, Arc<Window>)> = managed
+        .iter()
+        .map(|window| (window.id, window.writer.read().window().unwrap().clone()))
+        .collect();
+    let top = WINDOWS
+        .lock()
+        .iter()
+        .rev()
+        .find(|window| window.visible.load(Ordering::Relaxed) && window.contains(x, y))
+        .cloned()?;
+    let (id, _) = surfaces
+        .iter()
+        .find(|(_, surface)| Arc::ptr_eq(surface, &top))?;
+    set_focus(&managed, *id);
+    Some(*id)
+}
+
+// the caret moves to the focused window
+fn set_focus(managed: &[Managed], id: WindowId) {
+    let previous = WindowId(FOCUSED.swap(id.0, Ordering::Relaxed));
+    for window in managed {
+        if window.id == previous || window.id == id {
+            window.writer.write().set_caret(window.id == id);
+        }
+    }
+}
+
+const CARET_BLINK: Duration = Duration::from_millis(500);
+
+// Runs in the timer interrupt of core 0, so it only tries the locks. Writers that are drawing keep their
+// caret hidden until they are done
+fn blink_carets() {
+    static ON: AtomicBool = AtomicBool::new(false);
+    let on = !ON.fetch_xor(true, Ordering::Relaxed);
+    let Some(managed) = MANAGED.try_lock() else {
+        return;
+    };
+    for window in managed.iter() {
+        if let Some(mut writer) = window.writer.try_write() {
+            writer.blink(on);
+        }
+    }
+}
+
+// None if there is no such window
+pub fn with_window<R>(id: WindowId, f: impl FnOnce(&mut TerminalWriter) -> R) -> Option<R> {
+    let writer = MANAGED
+        .lock()
+        .iter()
+        .find(|window| window.id == id)?
+        .writer
+        .clone();
+    let result = f(&mut writer.write());
+    Some(result)
+}
+
+// like with_window, but other readers (the compositor, queries) don't wait for each other
+pub fn read_window<R>(id: WindowId, f: impl FnOnce(&TerminalWriter) -> R) -> Option<R> {
+    let writer = MANAGED
+        .lock()
+        .iter()
+        .find(|window| window.id == id)?
+        .writer
+        .clone();
+    let result = f(&writer.read());
+    Some(result)
+}
+
+// lock back buffer to write to it without tearing (The buffer is multi write single read)
+pub fn lock_back_buffer() -> TicketMutexGuard<'static, ()> {
+    BACK_BUFFER_LOCK.lock()
+}
+
+pub fn panic_print(callback: impl FnOnce(&mut TerminalWriter)) {
+    static PANIC_PRINT_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+
+    PANICKED_STOP_PRINTING.store(true, core::sync::atomic::Ordering::Release);
+    let _lock = PANIC_PRINT_LOCK.lock();
+
+    if let Some(db) = DOUBLE_BUFFER.get() {
+        let mut time_out = 1_000_000;
+        let _swap = loop {
+            let x = SWAP_LOCK.try_lock();
+            if x.is_some() {
+                break x;
+            }
+            if time_out == 0 {
+                break None;
+            }
+            time_out -= 1;
+            hint::spin_loop();
+        };
+        let term = &mut EMERGENCY_PANIC_TERM.lock();
+        term.buffer = unsafe { slice::from_raw_parts_mut(db.front_buffer, db.length) };
+        callback(term);
+    } else if let Some(ref mut term) = TERM.try_write() {
+        callback(term);
+    } else {
+        let term = &mut EMERGENCY_PANIC_TERM.lock();
+        callback(term);
+    }
+}
+
+// stdout is silenced after a panic and when graphics are turned off on the kernel command line
+fn output_disabled() -> bool {
+    PANICKED_STOP_PRINTING.load(core::sync::atomic::Ordering::Relaxed)
+        || !crate::cmdline::get().graphics
+}
+
+pub struct Stdout {
+    inner: TrackedWriteGuard<'static, TerminalWriter>,
+}
+
+impl Stdout {
+    #[inline]
+    pub fn acquire() -> Self {
+        Self {
+            inner: TERM.write(),
+        }
+    }
+
+    pub fn print(&mut self, args: fmt::Arguments) {
+        if output_disabled() {
+            return;
+        }
+        self.inner.write_fmt(args).unwrap();
+    }
+
+    pub fn print_image(&mut self, image: &ImageBuffer, width: usize) {
+        if output_disabled() {
+            return;
+        }
+        self.inner.print_image(image, width);
+    }
+
+    pub fn clear(&mut self, font_height: Option<FontSize>) {
+        if output_disabled() {
+            return;
+        }
+        self.inner.clear(font_height);
+    }
+
+    pub fn set_font_weight(&mut self, weight: FontWeight) {
+        self.inner.font_weight = weight;
+    }
+
+    pub fn font_weight(&self) -> FontWeight {
+        self.inner.font_weight
+    }
+
+    pub fn set_foreground(&mut self, color: Color) {
+        self.inner.foreground = color;
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.inner.foreground
+    }
+
+    pub fn set_background(&mut self, color: Color) {
+        self.inner.background = color;
+    }
+
+    pub fn background(&self) -> Color {
+        self.inner.background
+    }
+
+    pub fn set_clear_color(&mut self, color: Color) {
+        self.inner.clear_color = color;
+    }
+
+    pub fn clear_color(&self) -> Color {
+        self.inner.clear_color
+    }
+}
@@ -0,0 +1,85 @@
+use super::{pixel_color, WindowInfo, DOUBLE_BUFFER, SWAP_LOCK};
+use crate::{
+    fs::{self, FsError},
+    image::{self, ImageBuffer},
+    serial,
+};
+use alloc::vec::Vec;
+use core::{fmt::Write, slice};
+use x86_64::instructions::interrupts;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+// bytes per line on the serial port (76 characters encoded)
+const BASE64_LINE: usize = 57;
+
+// A copy of the screen, to look at visual bugs outside of the qemu window. The composed frame in the
+// back buffer (the frame buffer before the switch to the double buffer), taken between two pushes
+pub fn screenshot() -> ImageBuffer {
+    let screen = WindowInfo::screen();
+    let pixels = interrupts::without_interrupts(|| {
+        let _swap = SWAP_LOCK.lock();
+        let buffer = match DOUBLE_BUFFER.get() {
+            Some(double_buffer) => unsafe {
+                slice::from_raw_parts(double_buffer.back_buffer, double_buffer.length)
+            },
+            None => crate::get_boot_info()
+                .framebuffer
+                .as_ref()
+                .unwrap()
+                .buffer(),
+        };
+        (0..screen.height)
+            .flat_map(|y| (0..screen.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let offset = (y * screen.stride + x) * screen.bytes_per_pixel;
+                pixel_color(&buffer[offset..], screen.pixel_format)
+            })
+            .collect()
+    });
+    ImageBuffer::new(screen.width, screen.height, pixels)
+}
+
+// as a ppm file (e.g. in /tmp)
+pub fn save_screenshot(path: &str) -> Result<(), FsError> {
+    let ppm = image::encode_ppm(&screenshot());
+    let mut file = fs::create(path)?;
+    if file.write(&ppm)? < ppm.len() {
+        return Err(FsError::NoSpace);
+    }
+    Ok(())
+}
+
+// As a base64 encoded ppm file between two marker lines on the serial port, the lines between them
+// can be decoded with `base64 -d > screenshot.ppm`
+pub fn send_screenshot() {
+    let ppm = image::encode_ppm(&screenshot());
+    // other output would end up in the middle of the image
+    let mut port = serial::log_port().writer();
+    let _ = writeln!(port, "-----BEGIN SCREENSHOT-----");
+    for chunk in ppm.chunks(BASE64_LINE) {
+        let line = base64(chunk);
+        let _ = writeln!(port, "{}", core::str::from_utf8(&line).unwrap());
+    }
+    let _ = writeln!(port, "-----END SCREENSHOT-----");
+}
+
+fn base64(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            // the last group is padded
+            if index <= chunk.len() {
+                encoded.push(BASE64[((bits >> (18 - 6 * index)) & 0x3F) as usize]);
+            } else {
+                encoded.push(b'=');
+            }
+        }
+    }
+    encoded
+}
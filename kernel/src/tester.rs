@@ -1,9 +1,29 @@
 #[cfg(feature = "testing")]
+use core::{fmt, panic::PanicInfo};
+
+#[cfg(feature = "testing")]
+use spin::Mutex;
+
+#[cfg(feature = "testing")]
+use crate::power::{exit_qemu, QemuExitCode};
+#[cfg(feature = "testing")]
+use crate::sched::{self, CoreMask, TaskId};
+#[cfg(feature = "testing")]
 use crate::{ass, different, same};
 
 #[cfg(feature = "testing")]
 #[linkme::distributed_slice]
-pub static TESTS: [fn(&mut Tester)];
+pub static TESTS: [TestDescriptor];
+
+#[cfg(feature = "testing")]
+pub struct TestDescriptor {
+    pub name: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+    pub function: fn(&mut Tester),
+    // the test passes if it panics with a message containing the text (any panic for "")
+    pub should_panic: Option<&'static str>,
+}
 
 #[cfg(feature = "testing")]
 pub struct Tester {
@@ -13,38 +33,99 @@ pub struct Tester {
 
 #[cfg(feature = "testing")]
 impl Tester {
-    pub fn start_test(&mut self, args: core::fmt::Arguments) {
+    // The records of the test runner have targets below `test::` (for the json log of bootimage's runner):
+    // test::start (the message starts with the name of the test), test::failed and test::passed
+    pub fn start_test(&mut self, test: &TestDescriptor) {
         self.counter += 1;
 
         log::info!(
-            "\nStarting test ({}/{}): {args}",
+            target: "test::start",
+            "{} ({}/{}) \t({}:{})",
+            test.name,
             self.counter,
-            self.number_of_tests
+            self.number_of_tests,
+            test.file,
+            test.line
+        );
+    }
+
+    // Runs the test in a kernel thread of its own on this core, the panic ends the thread instead of the run.
+    // Locks the test holds when it panics stay locked
+    fn run_expecting_panic(&mut self, test: &TestDescriptor, expected: &'static str) {
+        let function = test.function;
+        let mut tester = Tester {
+            number_of_tests: self.number_of_tests,
+            counter: self.counter,
+        };
+        let core = CoreMask::single(crate::smp::cpu_index() as usize);
+        let thread = crate::kthread::spawn_on("should_panic test", core, move || {
+            *EXPECTED_PANIC.lock() = Some(ExpectedPanic {
+                task: sched::current_task_id().expect("kthread without task"),
+                message: expected,
+            });
+            function(&mut tester);
+        });
+        let returned = thread.join().is_some();
+        *EXPECTED_PANIC.lock() = None;
+        let caught = CAUGHT_PANIC.lock().take();
+
+        assert!(!returned, "{} did not panic", test.name);
+        assert!(
+            caught == Some(true),
+            "{} did not panic with a message containing {expected:?}",
+            test.name
         );
     }
 }
 
+#[cfg(feature = "testing")]
+#[derive(Clone, Copy)]
+struct ExpectedPanic {
+    task: TaskId,
+    message: &'static str,
+}
+
+// set by the thread of the running should_panic test
+#[cfg(feature = "testing")]
+static EXPECTED_PANIC: Mutex<Option<ExpectedPanic>> = Mutex::new(None);
+// whether the message of the caught panic contained the expected text
+#[cfg(feature = "testing")]
+static CAUGHT_PANIC: Mutex<Option<bool>> = Mutex::new(None);
+
+// test!(name, { ... }), tests which have to panic: test!(should_panic, name, { ... }) or
+// test!(should_panic = "part of the message", name, { ... })
 #[cfg(feature = "testing")]
 #[macro_export]
 macro_rules! test {
-    ($name:ident, $block:block) => {
+    (@descriptor $name:ident, $should_panic:expr, $block:block) => {
         #[linkme::distributed_slice($crate::tester::TESTS)]
-        fn $name(__tester: &mut $crate::tester::Tester) {
-            __tester.start_test(format_args!(
-                "{} \t({}:{})",
-                stringify!($name),
-                file!(),
-                line!()
-            ));
-            $block
-        }
+        #[allow(non_upper_case_globals)]
+        static $name: $crate::tester::TestDescriptor = $crate::tester::TestDescriptor {
+            name: stringify!($name),
+            file: file!(),
+            line: line!(),
+            function: {
+                fn test_function(_tester: &mut $crate::tester::Tester) $block
+                test_function
+            },
+            should_panic: $should_panic,
+        };
+    };
+    (should_panic = $expected:literal, $name:ident, $block:block) => {
+        $crate::test!(@descriptor $name, Some($expected), $block);
+    };
+    (should_panic, $name:ident, $block:block) => {
+        $crate::test!(@descriptor $name, Some(""), $block);
+    };
+    ($name:ident, $block:block) => {
+        $crate::test!(@descriptor $name, None, $block);
     };
 }
 
 #[cfg(not(feature = "testing"))]
 #[macro_export]
 macro_rules! test {
-    ($name:ident, $block:block) => {};
+    ($($test:tt)*) => {};
 }
 
 #[cfg(feature = "testing")]
@@ -55,21 +136,41 @@ pub fn bsp_test_main() {
     run_tests();
 }
 
+#[cfg(feature = "testing")]
+fn selected_tests() -> impl DoubleEndedIterator<Item = &'static TestDescriptor> {
+    let filter = crate::cmdline::get().test_filter;
+    TESTS
+        .iter()
+        .filter(move |test| filter.map_or(true, |filter| test.name.contains(filter)))
+}
+
 #[cfg(feature = "testing")]
 pub fn run_tests() {
-    let number_of_tests = TESTS.len() as u32;
+    let number_of_tests = selected_tests().count() as u32;
 
     if number_of_tests == 0 {
         log::error!("No tests found!");
         return;
     }
 
+    if let Some(filter) = crate::cmdline::get().test_filter {
+        log::info!(
+            "Running {number_of_tests} of {} tests (filter: {filter:?})",
+            TESTS.len()
+        );
+    }
+
     let mut tester = Tester {
         number_of_tests,
         counter: 0,
     };
-    for test in TESTS.iter().rev() {
-        test(&mut tester);
+    for test in selected_tests().rev() {
+        tester.start_test(test);
+        if let Some(expected) = test.should_panic {
+            tester.run_expecting_panic(test, expected);
+        } else {
+            (test.function)(&mut tester);
+        }
     }
 
     crate::println!();
@@ -77,45 +178,69 @@ pub fn run_tests() {
         crate::print!("\rshutdown countdown: {i}");
     }
 
-    log::info!("\nAll({number_of_tests}) tests passed!");
+    log::info!(target: "test::passed", "All({number_of_tests}) tests passed!");
 
+    // the exit code tells the test runner that all tests passed, real hardware is switched off instead
     exit_qemu(QemuExitCode::Success);
+    crate::power::shutdown();
 }
 
+// the start of the panic message, formatted without the heap (the panic may come from the allocator)
 #[cfg(feature = "testing")]
-#[panic_handler]
-fn panic(info: &core::panic::PanicInfo) -> ! {
-    use core::fmt::Write;
-
-    crate::terminal_out::panic_print(|term| {
-        term.foreground = crate::terminal_out::Color::new(0xFF, 0x00, 0x00);
-        term.background = crate::terminal_out::Color::new(0x70, 0x70, 0x00);
-        term.write_fmt(format_args!("\n{info}\n")).unwrap();
-    });
-    log::error!("\n\t{info}");
-
-    exit_qemu(QemuExitCode::Failed);
+struct MessageStart {
+    bytes: [u8; 256],
+    len: usize,
+}
 
-    #[allow(clippy::empty_loop)]
-    loop {}
+#[cfg(feature = "testing")]
+impl fmt::Write for MessageStart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(self.bytes.len() - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u32)]
-#[allow(dead_code)]
-pub enum QemuExitCode {
-    Success = 0x10,
-    Failed = 0x11,
+// Ends the thread of the running should_panic test if it panicked (not an interrupt handler in the meantime)
+#[cfg(feature = "testing")]
+fn catch_expected_panic(info: &PanicInfo) {
+    if crate::interrupts::in_handler() {
+        return;
+    }
+    let Some(expected) = EXPECTED_PANIC.try_lock().and_then(|expected| *expected) else {
+        return;
+    };
+    if sched::current_task_id() != Some(expected.task) {
+        return;
+    }
+    let mut message = MessageStart {
+        bytes: [0; 256],
+        len: 0,
+    };
+    let _ = fmt::Write::write_fmt(&mut message, format_args!("{}", info.message()));
+    let message = core::str::from_utf8(&message.bytes[..message.len]).unwrap_or_default();
+    log::info!("Panicked as expected: {info}");
+    *CAUGHT_PANIC.lock() = Some(message.contains(expected.message));
+    crate::kthread::exit();
 }
 
-#[allow(dead_code)]
-pub fn exit_qemu(exit_code: QemuExitCode) {
-    use x86_64::instructions::port::Port;
+#[cfg(feature = "testing")]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    catch_expected_panic(info);
+    crate::halt::stop_other_cores();
+    crate::panic_screen::show(info);
+    log::error!(target: "test::failed", "{info}");
+    crate::backtrace::log();
 
-    unsafe {
-        let mut port = Port::new(0xf4);
-        port.write(exit_code as u32);
-    }
+    exit_qemu(QemuExitCode::Failed);
+
+    #[allow(clippy::empty_loop)]
+    loop {}
 }
 
 test!(assertion_test, {
@@ -127,3 +252,15 @@ test!(assertion_test, {
     ass!([0, 1, 2, 3].iter().any(|e| *e > 2));
     different!("a", "b");
 });
+
+test!(should_panic, failed_assertions_panic, {
+    ass!(1, ==, 2);
+});
+
+test!(
+    should_panic = "Assertion failed",
+    panic_messages_are_matched,
+    {
+        same!("a", "b");
+    }
+);
@@ -0,0 +1,38 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(backtraces_follow_frame_pointers, {
+    use backtrace::Backtrace;
+
+    #[inline(never)]
+    fn inner() -> Backtrace<8> {
+        Backtrace::capture()
+    }
+
+    #[inline(never)]
+    fn outer() -> Backtrace<8> {
+        let backtrace = inner();
+        core::hint::black_box(backtrace)
+    }
+
+    let backtrace = outer();
+    let addresses: alloc::vec::Vec<u64> = backtrace.addresses().collect();
+    ass!(addresses.len() >= 2);
+    // inner returns into outer
+    let outer_start = outer as usize as u64;
+    ass!((outer_start..outer_start + 0x1000).contains(&addresses[0]));
+    ass!(addresses
+        .iter()
+        .all(|&address| address > get_boot_info().kernel_image_offset));
+
+    // corrupted chains end without faulting
+    same!(Backtrace::<8>::from_frame(0).addresses().count(), 0);
+    same!(
+        Backtrace::<8>::from_frame(0x1234_5671).addresses().count(),
+        0
+    );
+});
@@ -0,0 +1,62 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::{vec, vec::Vec};
+#[cfg(feature = "testing")]
+use blit::BlitMethod;
+#[cfg(feature = "testing")]
+use time::Instant;
+
+#[cfg(feature = "testing")]
+fn supported_methods() -> impl Iterator<Item = BlitMethod> {
+    BlitMethod::ALL
+        .into_iter()
+        .filter(|method| method.supported())
+}
+
+test!(blit_methods_copy_unaligned_rows, {
+    ass!(blit::method().supported());
+    let src: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+    for method in supported_methods() {
+        for (start, len) in [(0, 256), (1, 7), (3, 200), (5, 0), (8, 64)] {
+            let mut dst = vec![0xEEu8; 300];
+            unsafe { method.copy(src[7..].as_ptr(), dst[start..].as_mut_ptr(), len) };
+            same!(&dst[start..start + len], &src[7..7 + len]);
+            // nothing around the row is written
+            ass!(dst[..start].iter().all(|&byte| byte == 0xEE));
+            ass!(dst[start + len..].iter().all(|&byte| byte == 0xEE));
+        }
+    }
+});
+
+// frames of 1920x1080 pixels (4 bytes each) copied row by row like push_to_frame_buffer
+test!(blit_methods_frame_push_times, {
+    const WIDTH: usize = 1920 * 4;
+    const HEIGHT: usize = 1080;
+    const FRAMES: u32 = 10;
+    let back = vec![0x5Au8; WIDTH * HEIGHT];
+    let mut front = vec![0u8; WIDTH * HEIGHT];
+    for method in supported_methods() {
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            for y in 0..HEIGHT {
+                let offset = y * WIDTH;
+                unsafe {
+                    method.copy(
+                        back.as_ptr().add(offset),
+                        front.as_mut_ptr().add(offset),
+                        WIDTH,
+                    );
+                }
+            }
+        }
+        let frame_time = start.elapsed() / FRAMES;
+        log::info!("Frame push with {}: {frame_time:?}", method.name());
+        same!(front, back);
+        front.fill(0);
+    }
+});
@@ -0,0 +1,394 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::{sync::Arc, vec, vec::Vec};
+#[cfg(feature = "testing")]
+use bootloader_api::info::PixelFormat;
+#[cfg(feature = "testing")]
+use image::{ImageBuffer, Rect, Scaling};
+#[cfg(feature = "testing")]
+use terminal_out::{
+    compose_row, Color, Cursor, FontSize, FontWeight, LineGraph, TerminalWriter, Window, WindowId,
+    WindowInfo,
+};
+
+// a window filled with a gray, it is not shown
+#[cfg(feature = "testing")]
+fn gray_window(x: usize, y: usize, width: usize, height: usize, gray: u8) -> Arc<Window> {
+    let window = Window::new(&WindowInfo {
+        x,
+        y,
+        byte_offset: 0,
+        width,
+        height,
+        pixel_format: PixelFormat::Rgb,
+        bytes_per_pixel: 4,
+        stride: width,
+    });
+    let mut writer = TerminalWriter::in_window(window.clone());
+    writer.clear_color = Color::new(gray, gray, gray);
+    writer.clear(None);
+    window
+}
+
+// the red value of every pixel of row y of a 5 pixel wide screen
+#[cfg(feature = "testing")]
+fn screen_row(windows: &[Arc<Window>], y: usize) -> Vec<u8> {
+    let mut row = [0xFF; 5 * 4];
+    compose_row(windows, y, &mut row, 4);
+    row.chunks_exact(4).map(|pixel| pixel[0]).collect()
+}
+
+test!(windows_are_composed_over_each_other, {
+    let bottom = gray_window(0, 0, 4, 2, 10);
+    // reaches past the right edge of the screen
+    let top = gray_window(2, 1, 4, 2, 20);
+    let windows = [bottom.clone(), top.clone()];
+    same!(screen_row(&windows, 0), [10, 10, 10, 10, 0]);
+    same!(screen_row(&windows, 1), [10, 10, 20, 20, 20]);
+    same!(screen_row(&windows, 2), [0, 0, 20, 20, 20]);
+    same!(screen_row(&windows, 3), [0; 5]);
+    same!(
+        screen_row(&[top.clone(), bottom.clone()], 1),
+        [10, 10, 10, 10, 20]
+    );
+
+    top.set_visible(false);
+    same!(screen_row(&windows, 1), [10, 10, 10, 10, 0]);
+    bottom.move_to(3, 1);
+    same!(screen_row(&windows, 0), [0; 5]);
+    same!(screen_row(&windows, 1), [0, 0, 0, 10, 10]);
+    same!(bottom.position(), (3, 1));
+});
+
+test!(the_caret_blinks_at_the_write_position, {
+    let window = gray_window(0, 0, 64, 20, 10);
+    let pixel = |x: usize, y: usize| {
+        let mut row = [0; 64 * 4];
+        compose_row(&[window.clone()], y, &mut row, 4);
+        row[x * 4]
+    };
+    let mut writer = TerminalWriter::in_window(window.clone());
+    writer.clear_color = Color::new(10, 10, 10);
+    writer.clear(Some(FontSize::Size16));
+    writer.set_caret(true);
+    same!(pixel(0, 0), 10);
+    writer.blink(true);
+    same!(pixel(0, 0), 245);
+    same!(pixel(0, 19), 10);
+    same!(pixel(63, 0), 10);
+    writer.blink(false);
+    same!(pixel(0, 0), 10);
+
+    // shown again after writing, behind the new character (a space in the black background)
+    writer.blink(true);
+    writer.print(format_args!(" "));
+    same!(pixel(0, 0), 0);
+    let caret = (0..64).find(|&x| pixel(x, 0) == 245);
+    ass!(caret.is_some());
+    writer.set_caret(false);
+    same!((0..64).find(|&x| pixel(x, 0) == 245), None);
+});
+
+// the characters of a line of ten (with 16 pixel high lines) something is drawn into
+#[cfg(feature = "testing")]
+fn drawn_cells(window: &Arc<Window>, char_width: usize, line: usize) -> Vec<usize> {
+    let mut rows = vec![vec![0; window.size().0 * 4]; 16];
+    for (y, row) in rows.iter_mut().enumerate() {
+        compose_row(&[window.clone()], line * 16 + y, row, 4);
+    }
+    (0..10)
+        .filter(|cell| {
+            rows.iter()
+                .any(|row| (cell * char_width..(cell + 1) * char_width).any(|x| row[x * 4] > 128))
+        })
+        .collect()
+}
+
+test!(lines_wrap_between_words_and_tabs_line_up, {
+    let char_width = noto_sans_mono_bitmap::get_raster_width(FontWeight::Regular, FontSize::Size16);
+    // four lines of ten characters
+    let window = gray_window(0, 0, 10 * char_width + 1, 64, 10);
+    let mut writer = TerminalWriter::in_window(window.clone());
+    let drawn = |line: usize| drawn_cells(&window, char_width, line);
+
+    writer.clear(Some(FontSize::Size16));
+    writer.print(format_args!("aaa bbbbbbb\nccccccccccccc"));
+    same!(drawn(0), [0, 1, 2]);
+    same!(drawn(1), Vec::from_iter(0..7));
+    // a word as long as the line is broken
+    same!(drawn(2), Vec::from_iter(0..10));
+    same!(drawn(3), [0, 1, 2]);
+
+    writer.clear(None);
+    writer.print(format_args!("ab\tc\td\nabcd\te\nabcdefghi\tx"));
+    same!(drawn(0), [0, 1, 4, 8]);
+    same!(drawn(1), [0, 1, 2, 3, 8]);
+    // past the last stop
+    same!(drawn(2), Vec::from_iter(0..9));
+    same!(drawn(3), [0]);
+});
+
+test!(characters_are_erased_on_their_line, {
+    let char_width = noto_sans_mono_bitmap::get_raster_width(FontWeight::Regular, FontSize::Size16);
+    let window = gray_window(0, 0, 10 * char_width + 1, 32, 10);
+    let mut writer = TerminalWriter::in_window(window.clone());
+    let drawn = |line: usize| drawn_cells(&window, char_width, line);
+
+    writer.clear(Some(FontSize::Size16));
+    writer.print(format_args!("abc\nde"));
+    writer.erase_char();
+    same!(drawn(1), [0]);
+    // the previous line stays
+    writer.erase_char();
+    writer.erase_char();
+    same!(drawn(0), [0, 1, 2]);
+    same!(drawn(1), Vec::<usize>::new());
+    writer.print(format_args!("x"));
+    same!(drawn(1), [0]);
+});
+
+test!(images_are_clipped_and_scaled, {
+    let window = gray_window(0, 0, 5, 4, 10);
+    let mut writer = TerminalWriter::in_window(window.clone());
+    // 2x2 pixels with the red values 1, 2 (top) and 3, 4 (bottom)
+    let image = ImageBuffer::new(2, 2, (1..=4).map(|red| Color::new(red, 0, 0)).collect());
+
+    // cut off at the right edge of the window
+    writer.blit(&image, Rect::new(4, 0, 2, 2), Scaling::None);
+    same!(screen_row(&[window.clone()], 0), [10, 10, 10, 10, 1]);
+    same!(screen_row(&[window.clone()], 1), [10, 10, 10, 10, 3]);
+    // the rectangle cuts the image off
+    writer.blit(&image, Rect::new(0, 0, 1, 1), Scaling::None);
+    same!(screen_row(&[window.clone()], 0), [1, 10, 10, 10, 1]);
+    same!(screen_row(&[window.clone()], 1), [10, 10, 10, 10, 3]);
+
+    // every pixel doubled, the bottom row is outside of the window
+    writer.blit(&image, Rect::new(0, 2, 4, 4), Scaling::Nearest);
+    same!(screen_row(&[window.clone()], 2), [1, 1, 2, 2, 10]);
+    same!(screen_row(&[window.clone()], 3), [1, 1, 2, 2, 10]);
+    writer.blit(&image, Rect::new(0, 2, 3, 1), Scaling::Nearest);
+    same!(screen_row(&[window.clone()], 2), [1, 1, 2, 2, 10]);
+    writer.blit(&image, Rect::new(9, 9, 3, 3), Scaling::Nearest);
+
+    // transparent pixels keep the window below, translucent ones are blended with it
+    let image = ImageBuffer::new(
+        2,
+        1,
+        vec![Color::rgba(101, 0, 0, 0), Color::rgba(101, 0, 0, 128)],
+    );
+    writer.blit(&image, Rect::new(0, 3, 2, 1), Scaling::None);
+    same!(screen_row(&[window.clone()], 3), [1, 51, 2, 2, 10]);
+});
+
+test!(translucent_colors_are_blended, {
+    same!(
+        Color::new(0, 100, 200).blend(Color::rgba(200, 0, 100, 255)),
+        Color::new(200, 0, 100)
+    );
+    same!(
+        Color::new(0, 100, 200).blend(Color::transparent()),
+        Color::new(0, 100, 200)
+    );
+    same!(
+        Color::new(0, 100, 200).blend(Color::rgba(200, 0, 100, 128)),
+        Color::new(100, 50, 150)
+    );
+
+    // text on a translucent background over the window (as high as the largest default font)
+    let window = gray_window(0, 0, 64, 32, 100);
+    let mut writer = TerminalWriter::in_window(window.clone());
+    writer.background = Color::rgba(0, 0, 0, 128);
+    writer.print(format_args!(" "));
+    let mut row = [0; 64 * 4];
+    compose_row(&[window], 0, &mut row, 4);
+    same!(row[0], 50);
+    same!(row[63 * 4], 100);
+});
+
+test!(fonts_follow_the_resolution, {
+    same!(terminal_out::font_size_for(480).val(), 16);
+    same!(terminal_out::font_size_for(720).val(), 20);
+    same!(terminal_out::font_size_for(1080).val(), 24);
+    same!(terminal_out::font_size_for(2160).val(), 32);
+    let screen = WindowInfo::screen();
+    same!(
+        terminal_out::default_font_size().val(),
+        terminal_out::font_size_for(screen.height).val()
+    );
+    ass!(terminal_out::small_font_size().val(), <=, terminal_out::default_font_size().val());
+});
+
+test!(screenshots_capture_the_screen, {
+    let screen = WindowInfo::screen();
+    let screenshot = terminal_out::screenshot();
+    same!(
+        (screenshot.width, screenshot.height),
+        (screen.width, screen.height)
+    );
+
+    terminal_out::save_screenshot("/tmp/screenshot.ppm").unwrap();
+    let mut file = fs::open("/tmp/screenshot.ppm").unwrap();
+    let mut ppm = vec![0; file.size() as usize];
+    same!(file.read(&mut ppm), Ok(ppm.len()));
+    let saved = image::decode(&ppm).unwrap();
+    same!((saved.width, saved.height), (screen.width, screen.height));
+    fs::remove("/tmp/screenshot.ppm").unwrap();
+});
+
+test!(line_graphs_scroll_and_scale, {
+    let window = gray_window(0, 0, 5, 5, 10);
+    let mut writer = TerminalWriter::in_window(window.clone());
+    writer.clear_color = Color::new(10, 10, 10);
+    let mut graph = LineGraph::new("test", Color::new(200, 0, 0), 4);
+    let area = Rect::new(0, 0, 5, 5);
+
+    // the newest sample is in the last column, the largest one on the top row
+    graph.push(0);
+    graph.push(4);
+    graph.draw(&mut writer, area);
+    same!(screen_row(&[window.clone()], 0), [10, 10, 10, 10, 200]);
+    same!(screen_row(&[window.clone()], 2), [10, 10, 10, 10, 200]);
+    same!(screen_row(&[window.clone()], 4), [10, 10, 10, 200, 200]);
+
+    // the first sample was pushed out
+    for _ in 0..3 {
+        graph.push(2);
+    }
+    same!(graph.latest(), Some(2));
+    graph.draw(&mut writer, area);
+    same!(screen_row(&[window.clone()], 0), [10, 200, 200, 10, 10]);
+    same!(screen_row(&[window.clone()], 2), [10, 10, 200, 200, 200]);
+    same!(screen_row(&[window.clone()], 4), [10; 5]);
+    // clipped at the edges of the window
+    graph.draw(&mut writer, Rect::new(3, 3, 5, 5));
+});
+
+#[cfg(feature = "testing")]
+fn geometry(id: WindowId) -> Option<(usize, usize, usize, usize)> {
+    terminal_out::with_window(id, |writer| {
+        let info = writer.window_info();
+        (info.x, info.y, info.width, info.height)
+    })
+}
+
+test!(window_manager_tiles_and_floats_windows, {
+    let first = terminal_out::create_window();
+    let second = terminal_out::create_window();
+    ass!(first, !=, second);
+    let (x, y, width, height) = geometry(first).unwrap();
+    let (other_x, other_y, other_width, other_height) = geometry(second).unwrap();
+    same!((width, height), (other_width, other_height));
+    ass!(x + width <= other_x || y + height <= other_y);
+
+    // the first one takes the room of the one that floats now
+    terminal_out::move_window(second, 10, 20);
+    let (_, _, tiled_width, tiled_height) = geometry(first).unwrap();
+    ass!(tiled_width * tiled_height, >, width * height);
+    same!(geometry(second).unwrap(), (10, 20, width, height));
+    terminal_out::resize_window(second, 30, 40);
+    same!(geometry(second).unwrap(), (10, 20, 30, 40));
+    same!(
+        terminal_out::with_window(second, |writer| writer.print(format_args!("floating"))),
+        Some(())
+    );
+
+    ass!(terminal_out::destroy_window(second));
+    ass!(!terminal_out::destroy_window(second));
+    same!(geometry(second), None);
+    ass!(!terminal_out::move_window(second, 0, 0));
+    ass!(!terminal_out::destroy_window(terminal_out::STDOUT_WINDOW));
+    ass!(terminal_out::destroy_window(first));
+});
+
+test!(the_cursor_keeps_the_pixels_under_it, {
+    // a 20x20 screen of gray 10 with 3 bytes per pixel
+    let screen = WindowInfo {
+        x: 0,
+        y: 0,
+        byte_offset: 0,
+        width: 20,
+        height: 20,
+        pixel_format: PixelFormat::Bgr,
+        bytes_per_pixel: 3,
+        stride: 20,
+    };
+    let mut pixels = vec![10; 20 * 20 * 3];
+    let pixel = |pixels: &[u8], x: usize, y: usize| pixels[(y * 20 + x) * 3];
+    let mut cursor = Cursor::new();
+
+    // hidden until it is moved
+    same!(cursor.draw(&mut pixels, &screen), 0..0);
+    ass!(pixels.iter().all(|&byte| byte == 10));
+
+    // cut off at the right and bottom edges, the outline is black and the inside white
+    cursor.move_to(15, 8);
+    ass!(cursor.moved());
+    same!(cursor.draw(&mut pixels, &screen), 8..20);
+    ass!(!cursor.moved());
+    same!(cursor.drawn_rows(&screen), 8..20);
+    same!(pixel(&pixels, 15, 8), 0);
+    same!(pixel(&pixels, 16, 8), 10);
+    same!(pixel(&pixels, 16, 10), 255);
+    same!(pixel(&pixels, 17, 10), 0);
+    same!(pixel(&pixels, 14, 10), 10);
+
+    cursor.move_to(0, 0);
+    ass!(cursor.moved());
+    same!(cursor.restore(&mut pixels, &screen), 8..20);
+    ass!(pixels.iter().all(|&byte| byte == 10));
+    same!(cursor.restore(&mut pixels, &screen), 0..0);
+    same!(cursor.draw(&mut pixels, &screen), 0..16);
+    same!(pixel(&pixels, 0, 0), 0);
+});
+
+test!(clicks_focus_the_topmost_window, {
+    let first = terminal_out::create_window();
+    let second = terminal_out::create_window();
+    let (x, y, width, height) = geometry(first).unwrap();
+    let caret = |id: WindowId| terminal_out::with_window(id, |writer| writer.has_caret()).unwrap();
+
+    ass!(terminal_out::focus(first));
+    same!(terminal_out::focused(), first);
+    ass!(caret(first));
+    same!(
+        terminal_out::focus_at(x + width / 2, y + height / 2),
+        Some(first)
+    );
+
+    // floats over the first one
+    terminal_out::move_window(second, x, y);
+    terminal_out::resize_window(second, 10, 10);
+    same!(terminal_out::focus_at(x + 5, y + 5), Some(second));
+    same!(terminal_out::focused(), second);
+    ass!(caret(second) && !caret(first));
+    same!(terminal_out::focus_at(x + 15, y + 15), Some(first));
+
+    // nothing there, the focus stays
+    same!(terminal_out::focus_at(usize::MAX, usize::MAX), None);
+    same!(terminal_out::focused(), first);
+
+    // destroying the focused window gives the focus back to stdout
+    ass!(terminal_out::destroy_window(second));
+    ass!(terminal_out::destroy_window(first));
+    same!(terminal_out::focused(), terminal_out::STDOUT_WINDOW);
+    ass!(!terminal_out::focus(first));
+});
+
+test!(terminal_readers_share_the_writer, {
+    let window = terminal_out::create_window();
+    terminal_out::with_window(window, |writer| writer.font_weight = FontWeight::Bold);
+    let bold = |writer: &TerminalWriter| matches!(writer.font_weight, FontWeight::Bold);
+    let weight = terminal_out::read_window(window, |writer| {
+        // a second reader doesn't wait for the first, a writer does
+        same!(terminal_out::read_window(window, bold), Some(true));
+        bold(writer)
+    });
+    same!(weight, Some(true));
+    ass!(terminal_out::destroy_window(window));
+    same!(terminal_out::read_window(window, bold), None);
+});
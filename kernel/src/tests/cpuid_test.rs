@@ -0,0 +1,29 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::format;
+
+test!(every_started_core_read_its_features, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    for cpu_index in 0..=ap_count {
+        let features = cpuid::core_features(cpu_index).unwrap();
+        same!(features.missing_required().count(), 0);
+    }
+    same!(cpuid::core_features(ap_count + 1), None);
+    same!(cpuid::core_features(0), Some(cpuid::features()));
+});
+
+test!(features_match_the_ones_in_use, {
+    let features = cpuid::features();
+    same!(features.x2apic, apic::is_x2apic());
+    same!(features.tsc_deadline, apic::uses_tsc_deadline());
+    ass!(features.max_leaf, >=, 1);
+    ass!(features.max_extended_leaf, >=, 0x8000_0001);
+    // qemu reports GenuineIntel or AuthenticAMD (tcg and kvm)
+    same!(features.vendor().len(), 12);
+    ass!(format!("{features}").contains(features.vendor()));
+});
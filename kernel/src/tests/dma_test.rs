@@ -0,0 +1,43 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(dma_buffers_are_contiguous_and_zeroed, {
+    let free = dma::free_frames();
+    let mut buffer = dma::alloc(3 * 4096 + 1).unwrap();
+    same!(dma::free_frames(), free - 4);
+    same!(buffer.size(), 3 * 4096 + 1);
+    same!(buffer.virt(), memory::phys_to_virt(buffer.phys()));
+    same!(buffer.phys_at(4096), buffer.phys() + 4096u64);
+    ass!(buffer.as_slice().iter().all(|&byte| byte == 0));
+
+    // the device sees the writes at the physical address
+    buffer.as_mut_slice()[3 * 4096] = 0xab;
+    let last = memory::phys_to_virt(buffer.phys_at(3 * 4096));
+    same!(unsafe { last.as_ptr::<u8>().read_volatile() }, 0xab);
+
+    drop(buffer);
+    same!(dma::free_frames(), free);
+});
+
+test!(dma_buffers_can_be_aligned, {
+    let small = dma::alloc(64).unwrap();
+    let aligned = dma::alloc_aligned(4096, 0x10000).unwrap();
+    ass!(aligned.phys().is_aligned(0x10000u64));
+    ass!(aligned.phys(), !=, small.phys());
+    // freed and zeroed again for the next user
+    let phys = small.phys();
+    drop(small);
+    let mut reused = dma::alloc(64).unwrap();
+    same!(reused.phys(), phys);
+    reused.as_mut_slice().fill(0xff);
+    drop(reused);
+    ass!(dma::alloc(64)
+        .unwrap()
+        .as_slice()
+        .iter()
+        .all(|&byte| byte == 0));
+});
@@ -0,0 +1,163 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::{sync::Arc, vec, vec::Vec};
+#[cfg(feature = "testing")]
+use fs::{BlockDevice, Ext2, FileType, FsError, RamBlockDevice};
+
+#[cfg(feature = "testing")]
+const BLOCK_SIZE: usize = 1024;
+#[cfg(feature = "testing")]
+const INODE_TABLE: usize = 5;
+
+// Builds a 64 KiB ext2 volume (1 KiB blocks, 16 inodes) with
+// /hello, /big (its last blocks are behind an indirect block), /link (a symlink) and /dir/inner
+#[cfg(feature = "testing")]
+fn ext2_device() -> Arc<RamBlockDevice> {
+    let mut image = vec![0u8; 64 * BLOCK_SIZE];
+    let put = |image: &mut [u8], offset: usize, bytes: &[u8]| {
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+
+    let superblock = BLOCK_SIZE;
+    put(&mut image, superblock, &16u32.to_le_bytes()); // inodes
+    put(&mut image, superblock + 4, &64u32.to_le_bytes()); // blocks
+    put(&mut image, superblock + 20, &1u32.to_le_bytes()); // first data block
+    put(&mut image, superblock + 32, &8192u32.to_le_bytes()); // blocks per group
+    put(&mut image, superblock + 40, &16u32.to_le_bytes()); // inodes per group
+    put(&mut image, superblock + 56, &0xEF53u16.to_le_bytes());
+    put(&mut image, superblock + 76, &1u32.to_le_bytes()); // revision
+    put(&mut image, superblock + 84, &11u32.to_le_bytes()); // first normal inode
+    put(&mut image, superblock + 88, &128u16.to_le_bytes()); // inode size
+    put(&mut image, superblock + 96, &2u32.to_le_bytes()); // typed directory entries
+
+    // the group descriptor: block bitmap, inode bitmap, inode table
+    put(&mut image, 2 * BLOCK_SIZE, &3u32.to_le_bytes());
+    put(&mut image, 2 * BLOCK_SIZE + 4, &4u32.to_le_bytes());
+    put(
+        &mut image,
+        2 * BLOCK_SIZE + 8,
+        &(INODE_TABLE as u32).to_le_bytes(),
+    );
+
+    // (inode, mode, size, blocks)
+    let inode = |image: &mut [u8], number: usize, mode: u16, size: u32, blocks: &[u32]| {
+        let offset = INODE_TABLE * BLOCK_SIZE + (number - 1) * 128;
+        put(image, offset, &mode.to_le_bytes());
+        put(image, offset + 4, &size.to_le_bytes());
+        for (i, block) in blocks.iter().enumerate() {
+            put(image, offset + 40 + i * 4, &block.to_le_bytes());
+        }
+    };
+    // (inode, type, name), the last entry takes the rest of the block
+    let dir = |image: &mut [u8], block: usize, entries: &[(u32, u8, &str)]| {
+        let mut offset = block * BLOCK_SIZE;
+        for (i, (inode, file_type, name)) in entries.iter().enumerate() {
+            let record_len = if i == entries.len() - 1 {
+                (block + 1) * BLOCK_SIZE - offset
+            } else {
+                (8 + name.len()).next_multiple_of(4)
+            };
+            put(image, offset, &inode.to_le_bytes());
+            put(image, offset + 4, &(record_len as u16).to_le_bytes());
+            image[offset + 6] = name.len() as u8;
+            image[offset + 7] = *file_type;
+            put(image, offset + 8, name.as_bytes());
+            offset += record_len;
+        }
+    };
+
+    inode(&mut image, 2, 0x41ED, BLOCK_SIZE as u32, &[7]);
+    dir(
+        &mut image,
+        7,
+        &[
+            (2, 2, "."),
+            (2, 2, ".."),
+            (11, 1, "hello"),
+            (12, 1, "big"),
+            (13, 2, "dir"),
+            (15, 7, "link"),
+        ],
+    );
+    inode(&mut image, 11, 0x81A4, 5, &[8]);
+    put(&mut image, 8 * BLOCK_SIZE, b"hello");
+    // 14 blocks: 12 direct ones (9..=20), the indirect block 21 points to 22 and 23
+    let mut big_blocks: Vec<u32> = (9..=20).collect();
+    big_blocks.push(21);
+    inode(&mut image, 12, 0x81A4, 14 * BLOCK_SIZE as u32, &big_blocks);
+    put(&mut image, 21 * BLOCK_SIZE, &22u32.to_le_bytes());
+    put(&mut image, 21 * BLOCK_SIZE + 4, &23u32.to_le_bytes());
+    for (index, block) in (9..=20).chain(22..=23).enumerate() {
+        image[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].fill(index as u8 + 1);
+    }
+    inode(&mut image, 13, 0x41ED, BLOCK_SIZE as u32, &[24]);
+    dir(
+        &mut image,
+        24,
+        &[(13, 2, "."), (2, 2, ".."), (14, 1, "inner")],
+    );
+    inode(&mut image, 14, 0x81A4, 3, &[25]);
+    put(&mut image, 25 * BLOCK_SIZE, b"abc");
+    // fast symlink, the target is stored in the block pointers
+    inode(&mut image, 15, 0xA1FF, 5, &[u32::from_le_bytes(*b"hell")]);
+
+    let device = Arc::new(RamBlockDevice::new((image.len() / 512) as u64));
+    device.write_sectors(0, &image).unwrap();
+    device
+}
+
+test!(ext2_reads_files_and_directories, {
+    fs::mount("/test/ext2", Arc::new(Ext2::new(ext2_device()).unwrap())).unwrap();
+
+    let entries = fs::read_dir("/test/ext2").unwrap();
+    let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+    same!(names, ["big", "dir", "hello"]);
+    same!(entries[1].file_type, FileType::Dir);
+    same!(
+        fs::metadata("/test/ext2/link").err(),
+        Some(FsError::NotFound)
+    );
+
+    let mut buffer = [0; 16];
+    let mut hello = fs::open("/test/ext2/hello").unwrap();
+    same!(hello.read(&mut buffer), Ok(5));
+    same!(&buffer[..5], b"hello");
+    same!(hello.write(b"x"), Err(FsError::ReadOnly));
+    let mut inner = fs::open("/test/ext2/dir/inner").unwrap();
+    same!(inner.read(&mut buffer), Ok(3));
+    same!(&buffer[..3], b"abc");
+
+    // every block is filled with its index (from 1)
+    let mut big = fs::open("/test/ext2/big").unwrap();
+    let mut data = vec![0; 16 * BLOCK_SIZE];
+    same!(big.read(&mut data), Ok(14 * BLOCK_SIZE));
+    for (index, block) in data[..14 * BLOCK_SIZE].chunks_exact(BLOCK_SIZE).enumerate() {
+        ass!(block.iter().all(|&byte| byte == index as u8 + 1));
+    }
+
+    same!(fs::create("/test/ext2/new").err(), Some(FsError::ReadOnly));
+    fs::unmount("/test/ext2").unwrap();
+});
+
+test!(ext2_rejects_other_volumes, {
+    same!(
+        Ext2::new(Arc::new(RamBlockDevice::new(128))).err(),
+        Some(FsError::InvalidFormat)
+    );
+    let fat = Arc::new(RamBlockDevice::new(8 * 2048));
+    fs::format(&*fat, "fat").unwrap();
+    same!(Ext2::new(fat).err(), Some(FsError::InvalidFormat));
+
+    // extents (ext4) change the meaning of the block pointers
+    let device = ext2_device();
+    let mut superblock = [0; 1024];
+    device.read_sectors(2, &mut superblock).unwrap();
+    superblock[96] |= 0x40;
+    device.write_sectors(2, &superblock).unwrap();
+    same!(Ext2::new(device).err(), Some(FsError::InvalidFormat));
+});
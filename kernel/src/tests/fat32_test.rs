@@ -0,0 +1,109 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+#[cfg(feature = "testing")]
+use fs::{BlockDevice, Fat32, FileSystem, FileType, FsError, RamBlockDevice, SeekFrom};
+
+// 8 MiB, the sectors are only stored once written
+#[cfg(feature = "testing")]
+fn formatted_device() -> Arc<RamBlockDevice> {
+    let device = Arc::new(RamBlockDevice::new(8 * 2048));
+    fs::format(&*device, "steelmind").unwrap();
+    device
+}
+
+test!(fat32_files_keep_long_names_across_mounts, {
+    let device = formatted_device();
+    fs::mount("/test/fat", Arc::new(Fat32::new(device.clone()).unwrap())).unwrap();
+    same!(fs::read_dir("/test/fat").unwrap().len(), 0);
+
+    // spans several clusters
+    let data: Vec<u8> = (0..5000u32).map(|i| (i * 7) as u8).collect();
+    let mut file = fs::create("/test/fat/A long log file name.txt").unwrap();
+    same!(file.write(&data), Ok(data.len()));
+    fs::create_dir("/test/fat/Screenshots").unwrap();
+    fs::create("/test/fat/Screenshots/first.bmp").unwrap();
+    same!(
+        fs::create("/test/fat/a LONG log file name.TXT").err(),
+        Some(FsError::AlreadyExists)
+    );
+    fs::unmount("/test/fat").unwrap();
+
+    fs::mount("/test/fat", Arc::new(Fat32::new(device).unwrap())).unwrap();
+    let entries = fs::read_dir("/test/fat").unwrap();
+    let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+    same!(names, ["A long log file name.txt", "Screenshots"]);
+    same!(entries[1].file_type, FileType::Dir);
+    same!(fs::read_dir("/test/fat/screenshots").unwrap().len(), 1);
+
+    // names are case insensitive
+    let mut file = fs::open("/test/fat/a long log file name.TXT").unwrap();
+    same!(file.size(), data.len() as u64);
+    let mut buffer = vec![0; data.len() + 10];
+    same!(file.read(&mut buffer), Ok(data.len()));
+    same!(&buffer[..data.len()], &data[..]);
+    same!(file.seek(SeekFrom::Start(1000)), Some(1000));
+    same!(file.read(&mut buffer[..600]), Ok(600));
+    same!(&buffer[..600], &data[1000..1600]);
+    fs::unmount("/test/fat").unwrap();
+});
+
+test!(fat32_writes_behind_the_end_read_zeros, {
+    let fat = Fat32::new(formatted_device()).unwrap();
+    let file = fat.root().create_file("x").unwrap();
+    same!(file.write_at(0, b"start"), Ok(5));
+    same!(file.write_at(3000, b"end"), Ok(3));
+    same!(file.size(), 3003);
+    let mut buffer = [1u8; 3003];
+    same!(file.read_at(0, &mut buffer), Ok(3003));
+    same!(&buffer[..5], b"start");
+    ass!(buffer[5..3000].iter().all(|&byte| byte == 0));
+    same!(&buffer[3000..], b"end");
+});
+
+test!(fat32_removing_frees_the_clusters, {
+    let device = formatted_device();
+    let fat = Fat32::new(device.clone()).unwrap();
+    let root = fat.root();
+    let free = fat.free_clusters().unwrap();
+
+    let dir = root.create_dir("logs").unwrap();
+    for i in 0..20 {
+        let file = dir.create_file(&format!("boot log number {i}")).unwrap();
+        file.write_at(0, &[0xAB; 2000]).unwrap();
+    }
+    ass!(fat.free_clusters().unwrap() < free);
+    same!(root.remove("logs"), Err(FsError::NotEmpty));
+
+    let names: Vec<String> = dir
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    same!(names.len(), 20);
+    for name in &names {
+        dir.remove(name).unwrap();
+    }
+    same!(
+        dir.lookup("boot log number 3").err(),
+        Some(FsError::NotFound)
+    );
+    root.remove("logs").unwrap();
+    same!(fat.free_clusters().unwrap(), free);
+    same!(root.entries().unwrap().len(), 0);
+
+    same!(
+        Fat32::new(Arc::new(RamBlockDevice::new(64))).err(),
+        Some(FsError::InvalidFormat)
+    );
+    same!(
+        device.read_sectors(device.sector_count(), &mut [0; 512]),
+        Err(FsError::Io)
+    );
+});
@@ -0,0 +1,54 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(faulting_applications_are_killed, {
+    use loader::{ExitStatus, Fault};
+
+    // the fault application triggers the fault selected by the first byte of its input
+    for (selection, fault) in [
+        (b'p', Fault::PageFault),
+        (b'g', Fault::GeneralProtectionFault),
+        (b'u', Fault::InvalidOpcode),
+        // a page fault of the kernel inside a syscall
+        (b'k', Fault::PageFault),
+    ] {
+        let (input, app_input) = pipe::pipe(2);
+        let app = loader::spawn_with_handles(
+            "fault",
+            sched::CoreMask::all(),
+            alloc::vec![handle::Handle::PipeReader(app_input)],
+        )
+        .unwrap();
+        input.write_all(&[selection, 0]).unwrap();
+
+        let status = app.join();
+        same!(status, ExitStatus::killed(fault));
+    }
+
+    // the kernel keeps running applications
+    ass!(loader::spawn("test").unwrap().join().success());
+});
+
+test!(looping_applications_time_out, {
+    let (input, app_input) = pipe::pipe(1);
+    input.write_all(b"l").unwrap();
+    let file = ram_disk::get_file_slice(ram_disk::FAULT_APP);
+    let mut resources = loader::prepare_application(file).unwrap();
+    resources.add_handle(handle::Handle::PipeReader(app_input));
+
+    let start = time::Instant::now();
+    same!(
+        loader::run_with_time_limit(&mut resources, 50),
+        loader::ExitStatus::timed_out()
+    );
+    ass!(start.elapsed(), >=, time::Duration::from_millis(50));
+
+    // applications ending in time are not affected
+    let file = ram_disk::get_file_slice(ram_disk::TEST_APP);
+    let mut resources = loader::prepare_application(file).unwrap();
+    ass!(loader::run_with_time_limit(&mut resources, 60_000).success());
+});
@@ -0,0 +1,110 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use fs::{FileType, FsError, Path, SeekFrom};
+
+test!(open_files_read_and_seek, {
+    let logo = ram_disk::get_file_slice(ram_disk::LOGO);
+    let mut file = fs::open("/boot/logo").unwrap();
+    same!(file.size(), logo.len() as u64);
+    same!(
+        fs::open("/boot/does not exist").err(),
+        Some(FsError::NotFound)
+    );
+
+    let mut buffer = [0u8; 16];
+    same!(file.read(&mut buffer), Ok(16));
+    same!(&buffer[..], &logo[..16]);
+
+    same!(file.seek(SeekFrom::Current(-8)), Some(8));
+    same!(file.read(&mut buffer[..4]), Ok(4));
+    same!(&buffer[..4], &logo[8..12]);
+
+    same!(file.seek(SeekFrom::End(-2)), Some(logo.len() as u64 - 2));
+    same!(file.read(&mut buffer), Ok(2));
+    same!(file.read(&mut buffer), Ok(0));
+    same!(file.write(&buffer), Err(FsError::ReadOnly));
+
+    same!(
+        file.seek(SeekFrom::Start(logo.len() as u64 + 10)),
+        Some(logo.len() as u64 + 10)
+    );
+    same!(file.read(&mut buffer), Ok(0));
+    same!(
+        file.seek(SeekFrom::Current(-(logo.len() as i64) - 11)),
+        None
+    );
+});
+
+test!(applications_load_their_own_files, {
+    // the main application checks the logo and a file in /tmp through the file syscalls
+    let status = loader::spawn("main").unwrap().join();
+    ass!(status.success());
+});
+
+test!(paths_are_normalized, {
+    let path = Path::parse("/boot/./apps/../logo/").unwrap();
+    same!(path.to_string(), "/boot/logo");
+    same!(path.name(), Some("logo"));
+    same!(path.parent(), Some(Path::parse("/boot").unwrap()));
+    ass!(Path::parse("/..").unwrap().is_root());
+    same!(Path::parse("boot"), Err(FsError::InvalidPath));
+    same!(Path::root().join("a/b"), Err(FsError::InvalidPath));
+    let rest = [alloc::string::String::from("logo")];
+    same!(
+        path.strip_prefix(&Path::parse("/boot").unwrap()),
+        Some(&rest[..])
+    );
+});
+
+test!(ram_disk_is_mounted_at_boot, {
+    let root = fs::read_dir("/").unwrap();
+    ass!(root
+        .iter()
+        .any(|entry| entry.name == "boot" && entry.file_type == FileType::Dir));
+
+    let boot = fs::read_dir("/boot").unwrap();
+    ass!(boot.iter().any(|entry| entry.name == "logo"));
+    same!(
+        fs::metadata("/boot/logo").unwrap().size,
+        ram_disk::get_file_slice(ram_disk::LOGO).len() as u64
+    );
+    same!(fs::open("/boot").err(), Some(FsError::IsADirectory));
+    same!(
+        fs::read_dir("/boot/logo").err(),
+        Some(FsError::NotADirectory)
+    );
+    same!(fs::create("/boot/new").err(), Some(FsError::ReadOnly));
+    same!(fs::remove("/boot"), Err(FsError::Busy));
+    same!(fs::metadata("/nothing").err(), Some(FsError::NotFound));
+});
+
+test!(file_systems_can_be_mounted_inside_others, {
+    fs::mount(
+        "/test/nested/boot",
+        alloc::sync::Arc::new(fs::RamDiskFs::new()),
+    )
+    .unwrap();
+    same!(
+        fs::mount(
+            "/test/nested/boot",
+            alloc::sync::Arc::new(fs::RamDiskFs::new())
+        ),
+        Err(FsError::AlreadyExists)
+    );
+    // the directories above the mount point exist
+    same!(fs::metadata("/test").unwrap().file_type, FileType::Dir);
+    same!(fs::read_dir("/test/nested").unwrap().len(), 1);
+    ass!(fs::open("/test/nested/boot/logo").is_ok());
+
+    fs::unmount("/test/nested/boot").unwrap();
+    same!(fs::metadata("/test").err(), Some(FsError::NotFound));
+    same!(
+        fs::unmount("/test/nested/boot").err(),
+        Some(FsError::NotFound)
+    );
+});
@@ -0,0 +1,29 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::same;
+
+test!(fpu_state_is_saved_and_restored, {
+    use core::arch::asm;
+
+    // the kernel itself does not use the sse registers
+    let write_xmm0 = |value: u64| unsafe { asm!("movq xmm0, {}", in(reg) value) };
+    let read_xmm0 = || {
+        let value: u64;
+        unsafe { asm!("movq {}, xmm0", out(reg) value) };
+        value
+    };
+
+    let mut state = fpu::FpuState::new();
+    write_xmm0(0x1234_5678_9ABC_DEF0);
+    state.save();
+    write_xmm0(0);
+    state.restore();
+    same!(read_xmm0(), 0x1234_5678_9ABC_DEF0);
+
+    // applications start with cleared registers
+    fpu::FpuState::new().restore();
+    same!(read_xmm0(), 0);
+});
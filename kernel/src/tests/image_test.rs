@@ -0,0 +1,164 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::vec::Vec;
+#[cfg(feature = "testing")]
+use image::{ImageBuffer, ImageError};
+#[cfg(feature = "testing")]
+use terminal_out::Color;
+
+// A png with the header fields, the chunks after the header (type, data) and the filtered rows.
+// The crcs are zero, the decoder doesn't check them
+#[cfg(feature = "testing")]
+fn png(
+    width: u32,
+    height: u32,
+    depth_and_type: [u8; 2],
+    chunks: &[(&[u8], &[u8])],
+    rows: &[u8],
+) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&depth_and_type);
+    header.extend_from_slice(&[0, 0, 0]);
+    let pixels = zune_inflate::DeflateEncoder::new(rows).encode_zlib();
+
+    let mut png = Vec::from(b"\x89PNG\r\n\x1a\n");
+    let chunks = [(&b"IHDR"[..], &header[..])]
+        .into_iter()
+        .chain(chunks.iter().copied())
+        .chain([(&b"IDAT"[..], &pixels[..]), (&b"IEND"[..], &[][..])]);
+    for (chunk_type, data) in chunks {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        png.extend_from_slice(chunk_type);
+        png.extend_from_slice(data);
+        png.extend_from_slice(&[0; 4]);
+    }
+    png
+}
+
+test!(png_images_are_decoded, {
+    // rgba, 8 bits: the rows use the sub, up and paeth filters
+    let rows = [
+        1, 10, 20, 30, 255, 10, 20, 30, 129, //
+        2, 0, 0, 0, 0, 10, 0, 246, 128, //
+        4, 246, 236, 226, 1, 231, 241, 231, 5,
+    ];
+    let image = image::decode(&png(2, 3, [8, 6], &[], &rows)).unwrap();
+    same!((image.width, image.height), (2, 3));
+    same!(
+        image.pixels,
+        [
+            Color::rgba(10, 20, 30, 255),
+            Color::rgba(20, 40, 60, 128),
+            Color::rgba(10, 20, 30, 255),
+            Color::rgba(30, 40, 50, 0),
+            Color::rgba(0, 0, 0, 0),
+            Color::rgba(5, 5, 5, 5),
+        ]
+    );
+
+    // 2 bit palette indices (2, 1, 0), the first entry is transparent
+    let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+    let image = image::decode(&png(
+        3,
+        1,
+        [2, 3],
+        &[(b"PLTE", &palette), (b"tRNS", &[0])],
+        &[0, 0b1001_0000],
+    ))
+    .unwrap();
+    same!(
+        image.pixels,
+        [
+            Color::new(0, 0, 255),
+            Color::new(0, 255, 0),
+            Color::rgba(255, 0, 0, 0),
+        ]
+    );
+
+    // 16 bit gray is cut to 8 bits, the image is opaque
+    let image = image::decode(&png(1, 1, [16, 0], &[], &[0, 0x12, 0x34])).unwrap();
+    same!(image.pixels, [Color::new(0x12, 0x12, 0x12)]);
+
+    // the logo is a jpeg
+    let logo = image::decode(ram_disk::get_file_slice(ram_disk::LOGO)).unwrap();
+    ass!(!logo.is_empty());
+});
+
+test!(invalid_images_are_rejected, {
+    same!(image::decode(b"GIF89a"), Err(ImageError::UnknownFormat));
+    same!(image::decode(&[]), Err(ImageError::UnknownFormat));
+
+    let valid = png(1, 1, [8, 0], &[], &[0, 7]);
+    ass!(image::decode(&valid).is_ok());
+    same!(
+        image::decode(&valid[..valid.len() - 20]),
+        Err(ImageError::Invalid)
+    );
+    // a row is missing
+    same!(
+        image::decode(&png(1, 2, [8, 0], &[], &[0, 7])),
+        Err(ImageError::Invalid)
+    );
+    // an unknown filter type
+    same!(
+        image::decode(&png(1, 1, [8, 0], &[], &[5, 7])),
+        Err(ImageError::Invalid)
+    );
+    // palette indices past the end of the palette
+    same!(
+        image::decode(&png(1, 1, [8, 3], &[(b"PLTE", &[0, 0, 0])], &[0, 1])),
+        Err(ImageError::Invalid)
+    );
+    // 16 bit palettes don't exist
+    same!(
+        image::decode(&png(1, 1, [16, 3], &[], &[0, 0, 0])),
+        Err(ImageError::Invalid)
+    );
+
+    let mut interlaced = valid;
+    interlaced[8 + 8 + 12] = 1;
+    same!(image::decode(&interlaced), Err(ImageError::Unsupported));
+});
+
+test!(ppm_images_round_trip, {
+    let image = ImageBuffer::new(
+        2,
+        1,
+        Vec::from([Color::new(1, 2, 3), Color::rgba(250, 251, 252, 0)]),
+    );
+    let ppm = image::encode_ppm(&image);
+    ass!(ppm.starts_with(b"P6\n2 1\n255\n"));
+    // the alpha is dropped
+    same!(
+        image::decode(&ppm).unwrap().pixels,
+        [Color::new(1, 2, 3), Color::new(250, 251, 252)]
+    );
+
+    // comments between the fields, the samples are scaled to 8 bits
+    let image = image::decode(b"P6 # comment\n1 # another\n1 15\n\x0f\x00\x05").unwrap();
+    same!(image.pixels, [Color::new(255, 0, 85)]);
+
+    same!(
+        image::decode(b"P6\n1 1\n255\n\x00"),
+        Err(ImageError::Invalid)
+    );
+    same!(
+        image::decode(b"P6\n1\n255\n\x00\x00\x00"),
+        Err(ImageError::Invalid)
+    );
+    same!(
+        image::decode(b"P6\n1 1 0\n\x00\x00\x00"),
+        Err(ImageError::Invalid)
+    );
+    same!(
+        image::decode(b"P6\n1 1 65535\n\x00\x00\x00\x00\x00\x00"),
+        Err(ImageError::Unsupported)
+    );
+});
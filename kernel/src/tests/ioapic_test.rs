@@ -0,0 +1,32 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(gsis_are_routed_to_vectors, {
+    use ioapic::{GsiConfig, IoApicError};
+
+    // qemu connects the pit (isa irq 0) to gsi 2
+    same!(ioapic::isa_irq_to_gsi(0).0, 2);
+    same!(ioapic::isa_irq_to_gsi(5), (5, GsiConfig::ISA));
+
+    // isa irq 5 is not used by any device of the test machine
+    let apic_id = apic::interrupt_destination();
+    let vector = interrupts::allocate_vector("ioapic test").unwrap();
+    same!(ioapic::route_isa_irq(5, vector, apic_id), Ok(5));
+    let entry = ioapic::redirection_entry(5).unwrap();
+    same!(entry.vector(), u64::from(vector));
+    same!(entry.destination(), u64::from(apic_id));
+    ass!(!entry.masked());
+    ass!(!entry.level_triggered());
+
+    same!(ioapic::mask_gsi(5), Ok(()));
+    ass!(ioapic::redirection_entry(5).unwrap().masked());
+    same!(
+        ioapic::mask_gsi(u32::MAX),
+        Err(IoApicError::NoIoApicForGsi(u32::MAX))
+    );
+    same!(interrupts::unregister_irq(vector), Ok(()));
+});
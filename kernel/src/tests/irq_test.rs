@@ -0,0 +1,120 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(registered_irq_handlers_are_called, {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    use apic::{get_apic, ipi::create_self_cmd};
+    use interrupts::{IrqError, IrqReturn};
+
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    fn handler() -> IrqReturn {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    }
+    let stats = |vector| {
+        interrupts::stats()
+            .into_iter()
+            .find(|stats| stats.vector == vector)
+    };
+
+    let vector = interrupts::allocate_vector("irq test").unwrap();
+    same!(
+        interrupts::register_irq(vector, "irq test", handler),
+        Ok(())
+    );
+    same!(
+        interrupts::register_irq(vector, "irq test", handler),
+        Err(IrqError::InUse)
+    );
+    same!(
+        interrupts::register_irq(interrupts::TIMER_VECTOR, "irq test", handler),
+        Err(IrqError::InUse)
+    );
+    same!(
+        interrupts::register_irq(14, "irq test", handler),
+        Err(IrqError::NotAnIrqVector)
+    );
+
+    for calls in 1..=3 {
+        get_apic().write_interrupt_command(create_self_cmd(vector));
+        while CALLS.load(Ordering::Relaxed) < calls {
+            core::hint::spin_loop();
+        }
+    }
+    let test_stats = stats(vector).unwrap();
+    same!(test_stats.name, Some("irq test"));
+    same!(test_stats.total(), 3);
+    ass!(stats(interrupts::TIMER_VECTOR).unwrap().total() > 0);
+
+    same!(interrupts::unregister_irq(vector), Ok(()));
+    same!(
+        interrupts::unregister_irq(vector),
+        Err(IrqError::NotRegistered)
+    );
+    same!(stats(vector), None);
+    // the vector can be handed out again
+    same!(interrupts::allocate_vector("irq test"), Some(vector));
+    same!(interrupts::unregister_irq(vector), Ok(()));
+});
+
+test!(interrupts_are_counted_per_core, {
+    use apic::{get_apic, ipi::create_self_cmd};
+    use interrupts::IrqReturn;
+
+    fn handler() -> IrqReturn {
+        IrqReturn::Handled
+    }
+    let count_here = |vector| {
+        let core = smp::cpu_index() as usize;
+        interrupts::stats()
+            .into_iter()
+            .find(|stats| stats.vector == vector)
+            .map_or(0, |stats| stats.per_core.get(core).copied().unwrap_or(0))
+    };
+
+    // the task stays on this core while interrupts are disabled
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let breakpoints = count_here(3);
+        x86_64::instructions::interrupts::int3();
+        same!(count_here(3), breakpoints + 1);
+    });
+    let breakpoint = interrupts::stats()
+        .into_iter()
+        .find(|stats| stats.vector == 3)
+        .unwrap();
+    same!(breakpoint.name, Some("breakpoint"));
+    ass!(breakpoint.total() > 0);
+
+    let vector = interrupts::allocate_vector("stats test").unwrap();
+    same!(
+        interrupts::register_irq(vector, "stats test", handler),
+        Ok(())
+    );
+    same!(count_here(vector), 0);
+    let core = x86_64::instructions::interrupts::without_interrupts(|| {
+        get_apic().write_interrupt_command(create_self_cmd(vector));
+        // delivered to this core once interrupts are enabled again
+        same!(count_here(vector), 0);
+        smp::cpu_index() as usize
+    });
+    let stats = interrupts::stats()
+        .into_iter()
+        .find(|stats| stats.vector == vector)
+        .unwrap();
+    same!(stats.name, Some("stats test"));
+    same!(stats.total(), 1);
+    same!(stats.per_core[core], 1);
+    ass!(interrupts::stats()
+        .iter()
+        .any(|stats| stats.vector == interrupts::TIMER_VECTOR && stats.total() > 0));
+
+    same!(interrupts::unregister_irq(vector), Ok(()));
+    ass!(!interrupts::stats()
+        .iter()
+        .any(|stats| stats.vector == vector));
+});
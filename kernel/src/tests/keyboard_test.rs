@@ -0,0 +1,38 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(key_events_reach_every_subscriber, {
+    use keyboard::{KeyCode, KeyState};
+
+    let first = keyboard::subscribe();
+    let second = keyboard::subscribe();
+
+    // shift, a (held down), a released, shift released
+    for scancode in [0x2A, 0x1E, 0x1E, 0x9E, 0xAA] {
+        keyboard::handle_scancode(scancode);
+    }
+
+    for events in [&first, &second] {
+        same!(events.next().key, KeyCode::LeftShift);
+        let pressed = events.next();
+        same!(pressed.state, KeyState::Pressed);
+        same!(pressed.char(), Some('A'));
+        same!(events.next().state, KeyState::Repeated);
+        let released = events.next();
+        same!(released.state, KeyState::Released);
+        same!(released.char(), None);
+        ass!(!events.next().modifiers.shift);
+        same!(events.try_next(), None);
+    }
+
+    // a dropped subscriber no longer receives events, the others still do
+    drop(first);
+    keyboard::handle_scancode(0x39);
+    same!(second.next().key, KeyCode::Space);
+    keyboard::handle_scancode(0xB9);
+    same!(second.next().state, KeyState::Released);
+});
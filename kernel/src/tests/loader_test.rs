@@ -0,0 +1,81 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(malformed_applications_are_rejected, {
+    use loader::LoaderError;
+
+    // offsets into the elf header and the program headers (64 bit)
+    const E_ENTRY: usize = 0x18;
+    const E_PHOFF: usize = 0x20;
+    const E_PHNUM: usize = 0x38;
+    const PHDR_SIZE: usize = 56;
+    const P_VADDR: usize = 16;
+    const P_FILESZ: usize = 32;
+
+    let file = ram_disk::get_file_slice(ram_disk::TEST_APP).to_vec();
+    let read = |offset: usize, len: usize| {
+        let mut bytes = [0u8; 8];
+        bytes[..len].copy_from_slice(&file[offset..offset + len]);
+        u64::from_le_bytes(bytes)
+    };
+    let write = |file: &mut [u8], offset: usize, value: u64| {
+        file[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    };
+    let load_error = |file: &[u8]| loader::prepare_application(file).err();
+
+    // the program headers of the load segments
+    let (phoff, phnum) = (read(E_PHOFF, 8) as usize, read(E_PHNUM, 2) as usize);
+    let loads: alloc::vec::Vec<usize> = (0..phnum)
+        .map(|index| phoff + index * PHDR_SIZE)
+        .filter(|&header| read(header, 4) == u64::from(elf::abi::PT_LOAD))
+        .collect();
+    ass!(loads.len(), >=, 2);
+
+    let mut bad_magic = file.clone();
+    bad_magic[1] = b'X';
+    same!(load_error(&bad_magic), Some(LoaderError::BadMagic));
+
+    same!(
+        load_error(&file[..file.len() / 2]),
+        Some(LoaderError::TruncatedData)
+    );
+    same!(load_error(&[]), Some(LoaderError::TruncatedData));
+
+    let mut past_the_end = file.clone();
+    write(&mut past_the_end, loads[0] + P_FILESZ, 1 << 40);
+    same!(load_error(&past_the_end), Some(LoaderError::TruncatedData));
+
+    let mut kernel_address = file.clone();
+    write(
+        &mut kernel_address,
+        loads[1] + P_VADDR,
+        constants::v::KERNEL_START,
+    );
+    ass!(matches!(
+        load_error(&kernel_address),
+        Some(LoaderError::SegmentOutsideUserRange { .. })
+    ));
+
+    let mut overlapping = file.clone();
+    write(
+        &mut overlapping,
+        loads[1] + P_VADDR,
+        read(loads[0] + P_VADDR, 8),
+    );
+    same!(
+        load_error(&overlapping),
+        Some(LoaderError::OverlappingSegments)
+    );
+
+    let mut bad_entry = file.clone();
+    write(&mut bad_entry, E_ENTRY, u64::MAX - 16);
+    same!(load_error(&bad_entry), Some(LoaderError::BadEntryPoint));
+
+    // nothing is left behind by rejected files, the original still loads and runs
+    let mut resources = loader::prepare_application(&file).unwrap();
+    ass!(loader::run(&mut resources).success());
+});
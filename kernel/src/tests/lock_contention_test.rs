@@ -0,0 +1,110 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::ass;
+#[cfg(feature = "testing")]
+use alloc::{sync::Arc, vec::Vec};
+#[cfg(feature = "testing")]
+use core::time::Duration;
+#[cfg(feature = "testing")]
+use spin::mutex::{SpinMutex, TicketMutex};
+#[cfg(feature = "testing")]
+use time::Instant;
+
+#[cfg(feature = "testing")]
+const RUN: Duration = Duration::from_millis(200);
+
+#[cfg(feature = "testing")]
+static SPIN: SpinMutex<u64> = SpinMutex::new(0);
+#[cfg(feature = "testing")]
+static TICKET: TicketMutex<u64> = TicketMutex::new(0);
+
+// a short critical section, like printing a character
+#[cfg(feature = "testing")]
+fn hold() {
+    for _ in 0..200 {
+        core::hint::spin_loop();
+    }
+}
+
+// Every core locks in a loop for RUN, returns (acquisitions, longest wait in us) of each core
+#[cfg(feature = "testing")]
+fn contend(cores: usize, lock_once: fn()) -> Vec<(u64, u64)> {
+    let start = Arc::new(sync::Barrier::new(cores));
+    let threads: Vec<_> = (0..cores)
+        .map(|core| {
+            let start = start.clone();
+            kthread::spawn_on(
+                "lock contention",
+                sched::CoreMask::single(core),
+                move || {
+                    start.wait();
+                    let end = Instant::now() + RUN;
+                    let (mut acquisitions, mut longest_wait) = (0, 0);
+                    loop {
+                        let before = Instant::now();
+                        if before >= end {
+                            break (acquisitions, longest_wait);
+                        }
+                        lock_once();
+                        longest_wait = longest_wait.max(before.elapsed().as_micros() as u64);
+                        acquisitions += 1;
+                    }
+                },
+            )
+        })
+        .collect();
+    threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect()
+}
+
+#[cfg(feature = "testing")]
+fn log_results(name: &str, results: &[(u64, u64)]) {
+    let total: u64 = results.iter().map(|(acquisitions, _)| acquisitions).sum();
+    for (core, (acquisitions, longest_wait)) in results.iter().enumerate() {
+        log::info!(
+            "{name} lock, core {core}: {acquisitions} acquisitions ({}%), longest wait {longest_wait}us",
+            acquisitions * 100 / total.max(1)
+        );
+    }
+}
+
+// The ticket lock hands the lock to the cores in turns, the spin lock to the one which happens to win
+test!(ticket_lock_shares_the_lock_fairly_between_the_cores, {
+    let cores = sched::online_core_count();
+    if cores == 1 {
+        log::warn!("Skipped, needs a second core");
+        return;
+    }
+    let spin = contend(cores, || {
+        let mut value = SPIN.lock();
+        *value += 1;
+        hold();
+    });
+    log_results("spin", &spin);
+    let ticket = contend(cores, || {
+        let mut value = TICKET.lock();
+        *value += 1;
+        hold();
+    });
+    log_results("ticket", &ticket);
+
+    let total = |results: &[(u64, u64)]| -> u64 {
+        results.iter().map(|(acquisitions, _)| acquisitions).sum()
+    };
+    ass!(*SPIN.lock(), ==, total(&spin));
+    ass!(*TICKET.lock(), ==, total(&ticket));
+    // every core gets its turn, apart from the time other tasks run on it
+    let most = ticket
+        .iter()
+        .map(|(acquisitions, _)| *acquisitions)
+        .max()
+        .unwrap();
+    for (core, (acquisitions, _)) in ticket.iter().enumerate() {
+        ass!(*acquisitions * 2, >=, most, "core {core} was starved");
+    }
+});
@@ -0,0 +1,34 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::ass;
+
+#[cfg(feature = "testing")]
+static TEST_LOCK: lock_debug::TrackedMutex<u64> = lock_debug::tracked("test lock", 0);
+
+test!(tracked_locks_report_their_holder, {
+    {
+        let mut value = TEST_LOCK.lock();
+        *value += 1;
+        ass!(TEST_LOCK.try_lock().is_none());
+        let held = lock_debug::locks()
+            .into_iter()
+            .find(|lock| lock.name == "test lock");
+        // only tracked with the lock-debug feature
+        ass!(held.is_some(), ==, cfg!(feature = "lock-debug"));
+        if let Some(held) = held {
+            ass!(held.owner.map(|(core, _)| core), ==, Some(0));
+            ass!(held.acquisitions, >=, 1);
+        }
+    }
+    ass!(*TEST_LOCK.lock(), ==, 1);
+    if let Some(free) = lock_debug::locks()
+        .into_iter()
+        .find(|lock| lock.name == "test lock")
+    {
+        ass!(free.owner.is_none());
+        ass!(free.acquisitions, >=, 2);
+    }
+});
@@ -0,0 +1,111 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "testing")]
+use log::LevelFilter;
+
+test!(recent_log_lines_are_kept, {
+    let recent = || {
+        let mut lines = Vec::new();
+        logging::recent_lines(|line| lines.push(String::from(line)));
+        lines
+    };
+
+    log::trace!("first\nsecond");
+    let lines = recent();
+    ass!(lines.last().unwrap().starts_with("[TRACE"));
+    ass!(lines.last().unwrap().ends_with("] first second"));
+
+    // the oldest lines are dropped, long ones are cut
+    for i in 0..logging::RECENT_LINES + 2 {
+        log::trace!("{i} {}", "x".repeat(500));
+    }
+    let lines = recent();
+    same!(lines.len(), logging::RECENT_LINES);
+    ass!(lines[0].contains("] 2 x"));
+    ass!(lines.iter().all(|line| line.len() < 500));
+});
+
+test!(module_filters_drop_records, {
+    use logging::ModuleFilters;
+
+    let last = || {
+        let mut last = String::new();
+        logging::recent_lines(|line| last = String::from(line));
+        last
+    };
+
+    logging::set_module_filters(
+        ModuleFilters::parse("tests=trace,tests::logging_test=warn").unwrap(),
+    );
+    log::warn!("kept");
+    log::info!("dropped");
+    ass!(last().ends_with("] kept"));
+    logging::set_module_filters(ModuleFilters::new());
+    log::trace!("kept again");
+    ass!(last().ends_with("] kept again"));
+
+    // the longest prefix wins, only whole module names match
+    let filters = ModuleFilters::parse("mem=off,memory=info,memory::dma=error").unwrap();
+    same!(filters.level("kernel::memory"), Some(LevelFilter::Info));
+    same!(
+        filters.level("kernel::memory::dma"),
+        Some(LevelFilter::Error)
+    );
+    same!(filters.level("kernel::memory_map"), None);
+    same!(filters.level("kernel::apic"), None);
+    same!(
+        ModuleFilters::parse("memory").unwrap_err(),
+        logging::ModuleFilterError::Malformed
+    );
+});
+
+test!(handler_records_are_deferred, {
+    let recent = || {
+        let mut lines = Vec::new();
+        logging::recent_lines(|line| lines.push(String::from(line)));
+        lines
+    };
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let handler = interrupts::enter_handler();
+        ass!(interrupts::in_handler());
+        log::warn!("deferred");
+        drop(handler);
+        ass!(!interrupts::in_handler());
+        // the record of the handler is written before this one at the latest
+        log::trace!("direct");
+    });
+    let lines = recent();
+    ass!(lines.iter().any(|line| line.ends_with("] deferred")));
+    ass!(lines.last().unwrap().ends_with("] direct"));
+});
+
+test!(log_lines_are_numbered, {
+    let since = |next| {
+        let mut lines = Vec::new();
+        let next = logging::lines_since(next, |line| lines.push(String::from(line)));
+        (next, lines)
+    };
+
+    let (next, _) = since(u64::MAX);
+    log::trace!("numbered");
+    let (after, lines) = since(next);
+    same!(after, next + 1);
+    same!(lines.len(), 1);
+    ass!(lines[0].ends_with("] numbered"));
+
+    // the lines that are gone are skipped
+    for i in 0..logging::RECENT_LINES + 2 {
+        log::trace!("{i}");
+    }
+    let (last, lines) = since(after);
+    same!(last, after + logging::RECENT_LINES as u64 + 2);
+    same!(lines.len(), logging::RECENT_LINES);
+    ass!(lines[0].ends_with("] 2"));
+});
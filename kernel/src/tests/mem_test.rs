@@ -3,9 +3,11 @@ use crate::test;
 #[cfg(feature = "testing")]
 use super::*;
 #[cfg(feature = "testing")]
-use crate::ass;
+use crate::{ass, same};
 #[cfg(feature = "testing")]
 use constants::v;
+#[cfg(feature = "testing")]
+use user_access::user_access;
 
 #[cfg(feature = "testing")]
 use x86_64::{
@@ -27,8 +29,10 @@ test!(simple_page_table_swap, {
 
         let user_ref: &mut u64 = unsafe { &mut *page.start_address().as_mut_ptr() };
 
-        log::info!("Garbage: {user_ref}");
-        *user_ref = 42;
+        user_access(|| {
+            log::info!("Garbage: {user_ref}");
+            *user_ref = 42;
+        });
     }
     mem.log_page_table_info(log::Level::Info);
 
@@ -41,8 +45,10 @@ test!(simple_page_table_swap, {
 
         let user_ref: &mut u64 = unsafe { &mut *page.start_address().as_mut_ptr() };
 
-        log::info!("Garbage: {user_ref}");
-        *user_ref = 666;
+        user_access(|| {
+            log::info!("Garbage: {user_ref}");
+            *user_ref = 666;
+        });
     }
     mem.log_page_table_info(log::Level::Info);
 
@@ -55,7 +61,9 @@ test!(simple_page_table_swap, {
     {
         let user_ref: &mut u64 = unsafe { &mut *(v::USER_STACK_START as *mut u64) };
 
-        ass!(*user_ref, ==, 42);
+        user_access(|| {
+            ass!(*user_ref, ==, 42);
+        });
     }
 
     log::info!("Switch to user page table B");
@@ -63,17 +71,185 @@ test!(simple_page_table_swap, {
     {
         let user_ref: &mut u64 = unsafe { &mut *(v::USER_STACK_START as *mut u64) };
 
-        ass!(*user_ref, ==, 666);
+        user_access(|| {
+            ass!(*user_ref, ==, 666);
+        });
     }
 });
 
 test!(simple_user_application, {
-    let user_app = crate::ram_disk::get_file_slice(1);
+    let user_app = crate::ram_disk::get_file_slice(crate::ram_disk::TEST_APP);
+
+    let mut resources_a = crate::loader::prepare_application(user_app).unwrap();
+    let mut resources_b = crate::loader::prepare_application(user_app).unwrap();
+
+    ass!(crate::loader::run(&mut resources_a).success());
+    ass!(crate::loader::run(&mut resources_b).success());
+    let status = crate::loader::run(&mut resources_a);
+    same!(status.cause, crate::loader::ExitCause::Panicked);
+    same!(status.code, 42);
+});
+
+test!(spawn_user_application, {
+    let app = crate::loader::spawn("test").unwrap();
+    same!(app.name(), "test");
+    ass!(app.join().success());
+
+    ass!(matches!(
+        crate::loader::spawn("does not exist"),
+        Err(crate::loader::LoaderError::NotFound)
+    ));
+});
+
+// the applications are position independent, so several copies are loaded at different bases (with kaslr)
+test!(position_independent_applications, {
+    let user_app = crate::ram_disk::get_file_slice(crate::ram_disk::TEST_APP);
+
+    let mut copies: alloc::vec::Vec<_> = (0..4)
+        .map(|_| crate::loader::prepare_application(user_app).unwrap())
+        .collect();
+    let base = copies[0].load_base();
+    ass!(copies.iter().all(|copy| copy.load_base() != 0));
+    if kaslr::layout().randomized {
+        ass!(copies.iter().any(|copy| copy.load_base() != base));
+    }
+    for copy in &mut copies {
+        ass!(crate::loader::run(copy).success());
+    }
+});
+
+test!(applications_get_thread_local_storage, {
+    let user_app = crate::ram_disk::get_file_slice(crate::ram_disk::TEST_APP);
+
+    let mut resources = crate::loader::prepare_application(user_app).unwrap();
+    let thread_pointer = resources.thread_pointer();
+    ass!((v::USER_TLS_START..v::USER_STACK_START).contains(&thread_pointer));
+    ass!(thread_pointer % 8, ==, 0);
+    // the test application checks its thread local counter
+    ass!(crate::loader::run(&mut resources).success());
+});
 
-    let mut resources_a = crate::loader::prepare_application(user_app);
-    let mut resources_b = crate::loader::prepare_application(user_app);
+test!(address_space_overlap_detection, {
+    use memory::{AddressSpace, Backing, VirtualMemoryArea};
+
+    let area =
+        |name, range| VirtualMemoryArea::new(name, range, PageTableFlags::empty(), Backing::Ram);
+
+    let mut space = AddressSpace::new();
+    ass!(space.register(area("b", 0x3000..0x5000)).is_ok());
+    ass!(space.register(area("a", 0x1000..0x2000)).is_ok());
+    ass!(space.register(area("c", 0x5000..0x6000)).is_ok());
+
+    let error = space.register(area("d", 0x1800..0x3800)).unwrap_err();
+    ass!(error.existing.name, ==, "a");
+    ass!(space.register(area("e", 0x0..0x8000)).is_err());
+    ass!(space.register(area("f", 0x2000..0x3000)).is_ok());
+
+    ass!(space.find(0x4fff).unwrap().name, ==, "b");
+    ass!(space.find(0x5000).unwrap().name, ==, "c");
+    ass!(space.find(0x6000).is_none());
+    ass!(space.find(0x0).is_none());
+    ass!(space.areas().len(), ==, 4);
+});
+
+test!(contiguous_frame_allocation, {
+    use memory::ContiguousFrameAllocator;
+    use x86_64::PhysAddr;
+
+    // only the bitmap is touched, so the base does not have to be real memory
+    let mut pool = ContiguousFrameAllocator::new(PhysAddr::new(0x20_0000), 4096);
+    let a = pool.allocate_contiguous(3, 4096).unwrap();
+    ass!(a.start_address().as_u64(), ==, 0x20_0000);
+    let b = pool.allocate_contiguous(2, 0x4000).unwrap();
+    ass!(b.start_address().as_u64(), ==, 0x20_4000);
+    let c = pool.allocate_contiguous(1, 4096).unwrap();
+    ass!(c.start_address().as_u64(), ==, 0x20_3000);
+
+    unsafe { pool.deallocate_contiguous(a, 3) };
+    let d = pool.allocate_contiguous(4, 4096).unwrap();
+    ass!(d.start_address().as_u64(), ==, 0x20_6000);
+
+    ass!(pool.allocate_contiguous(5000, 4096).is_none());
+    let huge = pool.allocate_contiguous(512, 0x20_0000).unwrap();
+    ass!(huge.start_address().as_u64(), ==, 0x40_0000);
+    ass!(pool.free_frames(), ==, 4096 - 7 - 512);
+});
+
+test!(kernel_heap_trim, {
+    let size = 8 * 1024 * 1024;
+    drop(alloc::vec![1u8; size]);
+
+    let (used_before, _) = memory::MEMORY.lock().get_memory_utilization();
+    let reclaimed = allocator::ALLOCATOR.trim();
+    let (used_after, _) = memory::MEMORY.lock().get_memory_utilization();
+    ass!(reclaimed, >=, size as u64 / 4096);
+    ass!(used_after + reclaimed, <=, used_before);
+
+    // trimmed blocks are mapped again when the heap needs them
+    let v = alloc::vec![2u8; size];
+    ass!(v.iter().map(|&b| b as u64).sum::<u64>(), ==, 2 * size as u64);
+});
+
+test!(slab_allocation, {
+    use alloc::boxed::Box;
+    use core::alloc::Layout;
+
+    let allocations_before = allocator::ALLOCATOR.slab_stats()[1].total_allocations;
+    let boxes: alloc::vec::Vec<Box<[u8; 24]>> =
+        (0..1000).map(|i| Box::new([i as u8; 24])).collect();
+    ass!(allocator::ALLOCATOR.slab_stats()[1].total_allocations, >=, allocations_before + 1000);
+    ass!(boxes
+        .iter()
+        .enumerate()
+        .all(|(i, b)| b.iter().all(|&x| x == i as u8)));
+    drop(boxes);
+
+    // the size class covers the alignment
+    let layout = Layout::from_size_align(8, 64).unwrap();
+    let ptr = unsafe { alloc::alloc::alloc(layout) };
+    ass!(ptr as usize % 64, ==, 0);
+    unsafe { alloc::alloc::dealloc(ptr, layout) };
+
+    // growing out of the slab layer keeps the content
+    let mut v = alloc::vec::Vec::<u8>::with_capacity(8);
+    v.extend(0..=255);
+    v.extend(0..=255);
+    v.extend(0..=255);
+    ass!(v.iter().enumerate().all(|(i, &x)| x == (i % 256) as u8));
+});
 
-    ass!(crate::loader::run(&mut resources_a), ==, 0);
-    ass!(crate::loader::run(&mut resources_b), ==, 0);
-    ass!(crate::loader::run(&mut resources_a), ==, 42);
+// like common_main::allocator_fail_test, but the failing allocation is fallible and the kernel keeps running
+test!(fallible_kernel_allocation, {
+    use core::alloc::Layout;
+
+    let (_, total_frames) = memory::MEMORY.lock().get_memory_utilization();
+    let too_large = (total_frames * 4096 * 2) as usize;
+
+    let mut v = alloc::vec::Vec::<u8>::new();
+    ass!(v.try_reserve(too_large).is_err());
+    ass!(v.try_reserve(1024 * 1024).is_ok());
+
+    let layout = Layout::from_size_align(too_large, 4096).unwrap();
+    ass!(allocator::ALLOCATOR.try_alloc(layout).is_err());
+
+    let layout = Layout::from_size_align(64 * 1024, 4096).unwrap();
+    let ptr = allocator::ALLOCATOR.try_alloc(layout).unwrap();
+    unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) };
+
+    let mut memory = memory::MEMORY.lock();
+    let mut user_page_table = memory.create_user_page_table();
+    memory.switch_to_user_page_table(&mut user_page_table);
+    let page = Page::containing_address(VirtAddr::new(v::USER_HEAP_START));
+    ass!(memory
+        .try_map_ram_user(page, PageTableFlags::WRITABLE)
+        .is_ok());
+    ass!(
+        memory.try_map_ram_user(page, PageTableFlags::WRITABLE)
+            == Err(memory::MapError::AlreadyMapped)
+    );
+    let pending = unsafe { memory.unmap_ram(page) };
+    // a user page, only this core's tlb had it
+    ass!(pending.is_empty());
+    pending.flush();
+    memory.switch_to_kernel_page_table();
 });
@@ -1,4 +1,40 @@
 #[allow(unused_imports)]
 use super::*;
 
+mod backtrace_test;
+mod blit_test;
+mod compositor_test;
+mod cpuid_test;
+mod dma_test;
+mod ext2_test;
+mod fat32_test;
+mod fault_test;
+mod file_test;
+mod fpu_test;
+mod image_test;
+mod ioapic_test;
+mod irq_test;
+mod keyboard_test;
+mod loader_test;
+mod lock_contention_test;
+mod lock_debug_test;
+mod logging_test;
 mod mem_test;
+mod mouse_test;
+mod msi_test;
+mod net_test;
+mod numa_test;
+mod pci_test;
+mod pipe_test;
+mod power_test;
+mod random_test;
+mod sched_test;
+mod shell_test;
+mod smp_test;
+mod symbols_test;
+mod sync_test;
+mod tar_test;
+mod time_test;
+mod timer_callbacks_test;
+mod tmpfs_test;
+mod watchdog_test;
@@ -0,0 +1,37 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(pointer_events_follow_mouse_packets, {
+    let events = mouse::subscribe();
+    let packet = |flags: u8, x: u8, y: u8| {
+        for byte in [flags | 1 << 3, x, y] {
+            mouse::handle_byte(byte);
+        }
+    };
+
+    // far to the top left (negative x, positive y is up), the pointer stops at the edge
+    for _ in 0..100 {
+        packet(1 << 4, 0x80, 0x7F);
+    }
+    while let Some(event) = events.try_next() {
+        same!((event.dx, event.dy), (-128, -127));
+    }
+
+    // left button down while moving right and down
+    packet(1 | 1 << 5, 5, 0xFD);
+    let event = events.next();
+    same!((event.x, event.y), (5, 3));
+    same!((event.dx, event.dy), (5, 3));
+    ass!(event.buttons.left && event.changed.left);
+    ass!(!event.changed.right);
+
+    packet(0, 0, 0);
+    let event = events.next();
+    ass!(!event.buttons.left && event.changed.left);
+    same!((event.x, event.y), (5, 3));
+    same!(events.try_next(), None);
+});
@@ -0,0 +1,49 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(msi_vectors_are_registered_irqs, {
+    use interrupts::IrqReturn;
+    use msi::MsiError;
+    use pci_config::PciAddress;
+
+    fn handler() -> IrqReturn {
+        IrqReturn::Handled
+    }
+
+    // the host bridge of qemu (without any capabilities)
+    let host_bridge = PciAddress {
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+    ass!(pci::devices().contains(&host_bridge));
+    same!(pci::device(host_bridge).vendor_id(), 0x8086);
+
+    let apic_id = apic::interrupt_destination();
+    let msi = msi::allocate("msi test", handler, apic_id).unwrap();
+    same!(msi.message, msi::message(msi.vector, apic_id));
+    same!(msi.message.address, 0xFEE0_0000 | u64::from(apic_id) << 12);
+    same!(msi.message.data, u32::from(msi.vector));
+    ass!(interrupts::stats()
+        .iter()
+        .any(|stats| stats.vector == msi.vector && stats.name == Some("msi test")));
+
+    same!(
+        msi::enable_msi(host_bridge, &msi),
+        Err(MsiError::NoCapability)
+    );
+    same!(
+        msi::enable_msix(host_bridge, 0, &msi),
+        Err(MsiError::NoCapability)
+    );
+
+    let vector = msi.vector;
+    same!(msi::free(msi), Ok(()));
+    ass!(!interrupts::stats()
+        .iter()
+        .any(|stats| stats.vector == vector));
+});
@@ -0,0 +1,116 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+#[cfg(feature = "testing")]
+fn localhost(port: u16) -> net::IpEndpoint {
+    net::IpEndpoint::new(net::Ipv4Address::new(127, 0, 0, 1).into(), port)
+}
+
+test!(udp_loopback_round_trip, {
+    let server = net::UdpSocket::bind(7070).unwrap();
+    let client = net::UdpSocket::bind(0).unwrap();
+    same!(
+        net::UdpSocket::bind(7070).err(),
+        Some(net::NetError::AddressInUse)
+    );
+
+    client.send_to(b"ping", localhost(7070)).unwrap();
+    let mut buffer = [0; 16];
+    let (len, from) = server.recv_from(&mut buffer);
+    same!(&buffer[..len], b"ping");
+    same!(from, localhost(client.port()));
+
+    server.send_to(b"pong", from).unwrap();
+    let (len, _) = client.recv_from(&mut buffer);
+    same!(&buffer[..len], b"pong");
+});
+
+test!(tcp_loopback_connection, {
+    let mut listener = net::TcpListener::bind(7071).unwrap();
+    // larger than the socket buffers, so both sides have to wait for the other one
+    let data: alloc::vec::Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = data.clone();
+    let client = kthread::spawn("test client", move || {
+        let stream = net::TcpStream::connect(localhost(7071)).unwrap();
+        stream.send_all(&data).unwrap();
+    });
+
+    let stream = listener.accept();
+    let mut received = alloc::vec::Vec::new();
+    let mut buffer = [0; 1000];
+    loop {
+        let read = stream.recv(&mut buffer);
+        if read == 0 {
+            break;
+        }
+        received.extend_from_slice(&buffer[..read]);
+    }
+    same!(client.join(), Some(()));
+    same!(received, expected);
+});
+
+test!(tcp_connect_to_closed_port_is_refused, {
+    same!(
+        net::TcpStream::connect(localhost(7072)).err(),
+        Some(net::NetError::ConnectionRefused)
+    );
+});
+
+// qemu adds an e1000 with user networking (10.0.2.0/24) unless told otherwise.
+// None without the card, waits for the dhcp lease (or the static address of the command line)
+#[cfg(feature = "testing")]
+fn e1000_with_address() -> Option<net::InterfaceInfo> {
+    let index = net::interfaces()
+        .iter()
+        .position(|interface| interface.name == "e1000")?;
+    let start = time::Instant::now();
+    loop {
+        let interface = net::interfaces()[index];
+        if interface.address.is_some() {
+            return Some(interface);
+        }
+        ass!(start.elapsed(), <, time::Duration::from_secs(10), "no dhcp lease");
+        sched::sleep_ms(50);
+    }
+}
+
+test!(e1000_gets_a_dhcp_lease, {
+    if cmdline::get().ip.is_some() {
+        return;
+    }
+    let Some(lease) = e1000_with_address() else {
+        return;
+    };
+    ass!(lease.mac_address.is_some());
+    let subnet = net::IpCidr::new(net::Ipv4Address::new(10, 0, 2, 0).into(), 24);
+    ass!(subnet.contains_addr(&lease.address.unwrap().address()));
+    same!(lease.gateway, Some(net::Ipv4Address::new(10, 0, 2, 2)));
+});
+
+// The runner (bootimage) forwards a udp port of the host to UDP_TEST_PORT and repeats its greeting until it got
+// the echo and the greeting of the kernel (it fails the run without them), then it says bye
+test!(udp_echo_with_the_host, {
+    const UDP_TEST_PORT: u16 = 5555;
+
+    if e1000_with_address().is_none() {
+        return;
+    }
+    let socket = net::UdpSocket::bind(UDP_TEST_PORT).unwrap();
+    let mut buffer = [0; 64];
+    // the answers are sent before the socket is dropped
+    loop {
+        let (len, host) = socket.recv_from(&mut buffer);
+        // the address of the host behind the user networking of qemu
+        same!(host.addr, net::Ipv4Address::new(10, 0, 2, 2).into());
+        if &buffer[..len] == b"bye" {
+            break;
+        }
+        same!(&buffer[..len], b"hello from the host");
+        socket.send_to(&buffer[..len], host).unwrap();
+        socket.send_to(b"hello from steelmind", host).unwrap();
+    }
+});
@@ -0,0 +1,93 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(srat_and_slit_are_decoded, {
+    use alloc::vec::Vec;
+    use numa::{MemoryAffinity, NumaLayout, ProcessorAffinity};
+
+    let mut srat = Vec::from([0u8; 48]);
+    // processor affinity: apic id 1 in proximity domain 5
+    srat.extend_from_slice(&[0, 16, 5, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    // x2apic affinity: apic id 0x100 in proximity domain 7
+    let mut x2apic = [0u8; 24];
+    x2apic[..2].copy_from_slice(&[2, 24]);
+    x2apic[4..8].copy_from_slice(&7u32.to_le_bytes());
+    x2apic[8..12].copy_from_slice(&0x100u32.to_le_bytes());
+    x2apic[12] = 1;
+    srat.extend_from_slice(&x2apic);
+    // memory affinity: 1 GiB at 1 GiB in domain 7, then a disabled range
+    for (base, enabled) in [(1u64 << 30, 1), (4 << 30, 0)] {
+        let mut memory = [0u8; 40];
+        memory[..2].copy_from_slice(&[1, 40]);
+        memory[2..6].copy_from_slice(&7u32.to_le_bytes());
+        memory[8..16].copy_from_slice(&base.to_le_bytes());
+        memory[16..24].copy_from_slice(&(1u64 << 30).to_le_bytes());
+        memory[28] = enabled;
+        srat.extend_from_slice(&memory);
+    }
+
+    // 8 localities, domain 5 and 7 are 21 apart
+    let mut slit = Vec::from([0u8; 36]);
+    slit.extend_from_slice(&8u64.to_le_bytes());
+    let mut distances = [20u8; 64];
+    for domain in 0..8 {
+        distances[domain * 9] = 10;
+    }
+    distances[5 * 8 + 7] = 21;
+    distances[7 * 8 + 5] = 21;
+    slit.extend_from_slice(&distances);
+
+    let layout = NumaLayout::parse(Some(&srat), Some(&slit));
+    same!(layout.node_count(), 2);
+    same!(
+        layout.processors().collect::<Vec<_>>(),
+        [
+            ProcessorAffinity {
+                apic_id: 1,
+                node: 0
+            },
+            ProcessorAffinity {
+                apic_id: 0x100,
+                node: 1
+            }
+        ]
+    );
+    same!(
+        layout.memory_ranges(),
+        [MemoryAffinity {
+            start: 1 << 30,
+            end: 2 << 30,
+            node: 1
+        }]
+    );
+    same!(layout.node_of_addr(3 << 29), 1);
+    same!(layout.node_of_addr(2 << 30), 0);
+    same!(layout.node_of_apic_id(0x100), 1);
+    same!(layout.node_of_apic_id(3), 0);
+    same!(layout.distance(0, 1), 21);
+    same!(layout.distance(1, 1), 10);
+    same!(layout.nodes_by_distance(1).collect::<Vec<_>>(), [1, 0]);
+
+    // without srat there is a single node
+    let layout = NumaLayout::parse(None, Some(&slit));
+    same!(layout.node_count(), 1);
+    same!(layout.node_of_addr(1 << 30), 0);
+    same!(layout.nodes_by_distance(0).collect::<Vec<_>>(), [0]);
+});
+
+test!(frames_are_tracked_per_node, {
+    let layout = numa::layout();
+    ass!(layout.node_of_apic_id(apic::get_apic().id()) < layout.node_count());
+    ass!(numa::current_node() < layout.node_count());
+
+    let memory = memory::MEMORY.lock();
+    same!(memory.node_free_frames().len(), layout.node_count());
+    same!(
+        memory.node_free_frames().iter().sum::<u64>(),
+        memory.free_frames()
+    );
+});
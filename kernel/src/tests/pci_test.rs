@@ -0,0 +1,64 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(mcfg_entries_are_decoded, {
+    use crate::acpi::{mcfg_entries, PciEcamRegion};
+
+    let mut table = [0u8; 44 + 2 * 16];
+    table[44..52].copy_from_slice(&0xB000_0000u64.to_le_bytes());
+    table[54] = 0x00;
+    table[55] = 0xFF;
+    table[60..68].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+    table[68..70].copy_from_slice(&1u16.to_le_bytes());
+    table[70] = 0x10;
+    table[71] = 0x1F;
+    same!(
+        mcfg_entries(&table),
+        [
+            PciEcamRegion {
+                phys_addr: 0xB000_0000,
+                segment_group: 0,
+                start_bus: 0x00,
+                end_bus: 0xFF,
+            },
+            PciEcamRegion {
+                phys_addr: 0x1_0000_0000,
+                segment_group: 1,
+                start_bus: 0x10,
+                end_bus: 0x1F,
+            },
+        ]
+    );
+
+    // truncated tables
+    ass!(mcfg_entries(&table[..44 + 15]).is_empty());
+    ass!(mcfg_entries(&table[..20]).is_empty());
+});
+
+test!(extended_config_space_is_reachable_with_ecam, {
+    use pci_config::PciAddress;
+
+    let host_bridge = PciAddress {
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+    let device = pci::device(host_bridge);
+    let has_ecam = acpi::ACPI
+        .lock()
+        .pci_ecam_regions
+        .iter()
+        .any(|region| region.segment_group == 0);
+    if has_ecam {
+        // the first 256 bytes are the same through both mechanisms
+        same!(device.read_extended(0), Some(device.read(0)));
+        same!(device.read_extended(0x1000), None);
+    } else {
+        same!(device.read_extended(0x100), None);
+    }
+    same!(device.vendor_id(), 0x8086);
+});
@@ -0,0 +1,81 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::same;
+
+test!(pipe_transfers_between_threads, {
+    let (writer, reader) = pipe::pipe(16);
+    // larger than the pipe, so the writer blocks until the reader made room
+    let data: alloc::vec::Vec<u8> = (0..=255).collect();
+    let expected = data.clone();
+    let producer = kthread::spawn("test producer", move || {
+        writer.write_all(&data).unwrap();
+    });
+
+    let mut received = alloc::vec::Vec::new();
+    let mut buffer = [0u8; 7];
+    loop {
+        let read = reader.read(&mut buffer);
+        if read == 0 {
+            break;
+        }
+        received.extend_from_slice(&buffer[..read]);
+    }
+    same!(producer.join(), Some(()));
+    same!(received, expected);
+});
+
+test!(pipe_write_fails_without_readers, {
+    let (writer, reader) = pipe::pipe(16);
+    drop(reader);
+    same!(writer.write(b"lost"), Err(pipe::PipeClosed));
+});
+
+test!(applications_communicate_through_pipes, {
+    // kernel -> first app -> second app -> kernel
+    let (input, first_input) = pipe::pipe(64);
+    let (first_output, second_input) = pipe::pipe(8);
+    let (second_output, output) = pipe::pipe(64);
+    let any = sched::CoreMask::all();
+    let first = loader::spawn_with_handles(
+        "pipe",
+        any.clone(),
+        alloc::vec![
+            handle::Handle::PipeReader(first_input),
+            handle::Handle::PipeWriter(first_output),
+        ],
+    )
+    .unwrap();
+    let second = loader::spawn_with_handles(
+        "pipe",
+        any,
+        alloc::vec![
+            handle::Handle::PipeReader(second_input),
+            handle::Handle::PipeWriter(second_output),
+        ],
+    )
+    .unwrap();
+
+    let message = b"hello from the kernel through two applications";
+    input.write_all(message).unwrap();
+    drop(input);
+
+    let mut received = alloc::vec::Vec::new();
+    let mut buffer = [0u8; 32];
+    loop {
+        let read = output.read(&mut buffer);
+        if read == 0 {
+            break;
+        }
+        received.extend_from_slice(&buffer[..read]);
+    }
+    same!(received, message.to_ascii_uppercase());
+
+    for app in [first, second] {
+        let status = app.join();
+        same!(status.cause, loader::ExitCause::Returned);
+        same!(status.code, message.len() as u64);
+    }
+});
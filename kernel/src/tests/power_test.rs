@@ -0,0 +1,52 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(s5_sleep_types_are_found_in_the_aml, {
+    use crate::acpi::s5_sleep_types;
+
+    // Name(_S5, Package(4) {0x05, 0x05, Zero, Zero}) behind other objects
+    let aml = [
+        0x08, b'_', b'S', b'4', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00, //
+        0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0A, 0x05, 0x0A, 0x05, 0x00, 0x00,
+    ];
+    same!(s5_sleep_types(&aml), Some((5, 5)));
+
+    // root prefix, a two byte package length and Zero/One
+    let aml = [
+        0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x40, 0x07, 0x04, 0x00, 0x01, 0x00, 0x00,
+    ];
+    same!(s5_sleep_types(&aml), Some((0, 1)));
+
+    // references to the name are not its definition
+    same!(
+        s5_sleep_types(b"\x70_S5_\x12\x06\x04\x00\x00\x00\x00"),
+        None
+    );
+    same!(s5_sleep_types(&[0x08, b'_', b'S', b'5', b'_', 0x12]), None);
+});
+
+test!(qemu_supports_acpi_shutdown, {
+    let power = acpi::ACPI.lock().power;
+    ass!(power.pm1a_control_port.is_some(), "{power:x?}");
+    ass!(power.s5_sleep_types.is_some(), "{power:x?}");
+});
+
+test!(power_button_raises_the_sci, {
+    use x86_64::instructions::port::Port;
+
+    let power = acpi::ACPI.lock().power;
+    let event_port = power.pm1a_event_port.unwrap();
+    let enable =
+        unsafe { Port::<u16>::new(event_port + u16::from(power.pm1_event_length / 2)).read() };
+    // PWRBTN_EN and SCI_EN
+    ass!(enable & 1 << 8 != 0, "{enable:#x}");
+    let control = unsafe { Port::<u16>::new(power.pm1a_control_port.unwrap()).read() };
+    ass!(control & 1 != 0, "{control:#x}");
+    ass!(interrupts::stats()
+        .iter()
+        .any(|stats| stats.name == Some("acpi sci")));
+});
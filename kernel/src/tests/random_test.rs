@@ -0,0 +1,67 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use random::Rng;
+
+// RFC 8439 section 2.3.2
+test!(chacha20_block_matches_the_rfc, {
+    let key = core::array::from_fn(|i| {
+        let byte = 4 * i as u8;
+        u32::from_le_bytes([byte, byte + 1, byte + 2, byte + 3])
+    });
+    let block = random::chacha20_block(&key, 1, &[0x0900_0000, 0x4a00_0000, 0]);
+    same!(
+        &block[..16],
+        &[
+            0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20,
+            0x71, 0xc4
+        ]
+    );
+    same!(&block[60..], &[0xa2, 0x50, 0x3c, 0x4e]);
+});
+
+test!(kernel_random_bytes_do_not_repeat, {
+    let (mut first, mut second) = ([0u8; 100], [0u8; 100]);
+    random::fill(&mut first);
+    random::fill(&mut second);
+    ass!(first, !=, second);
+    // 800 bits, about half of them set
+    let ones: u32 = first.iter().map(|byte| byte.count_ones()).sum();
+    ass!(ones, >, 300);
+    ass!(ones, <, 500);
+    ass!(random::below(10), <, 10);
+
+    let reseeds = random::reseed_count();
+    random::reseed();
+    same!(random::reseed_count(), reseeds + 1);
+});
+
+test!(interrupt_timings_are_collected, {
+    let samples = random::interrupt_samples();
+    // the timer interrupt arrives every time slice
+    sched::sleep_ms(20);
+    ass!(random::interrupt_samples(), >, samples);
+});
+
+test!(seeded_rng_is_reproducible, {
+    let (mut a, mut b) = (Rng::seed_from_u64(42), Rng::seed_from_u64(42));
+    for _ in 0..10 {
+        same!(a.next_u64(), b.next_u64());
+    }
+    ass!(Rng::seed_from_u64(1).next_u64(), !=, Rng::seed_from_u64(2).next_u64());
+
+    let mut rng = Rng::seed_from_u64(7);
+    for bound in 1..100 {
+        ass!(rng.below(bound), <, bound);
+    }
+    let sorted: [u32; 32] = core::array::from_fn(|i| i as u32);
+    let mut values = sorted;
+    rng.shuffle(&mut values);
+    ass!(values, !=, sorted);
+    values.sort_unstable();
+    same!(values, sorted);
+});
@@ -0,0 +1,147 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "testing")]
+use time::{Duration, Instant};
+
+test!(spawned_tasks_run_to_completion, {
+    static FINISHED: AtomicU64 = AtomicU64::new(0);
+    const TASKS: u64 = 16;
+
+    for _ in 0..TASKS {
+        sched::spawn("test task", || {
+            for _ in 0..10 {
+                sched::yield_now();
+            }
+            FINISHED.fetch_add(1, Ordering::AcqRel);
+        });
+    }
+
+    while FINISHED.load(Ordering::Acquire) < TASKS {
+        sched::yield_now();
+        core::hint::spin_loop();
+    }
+    same!(FINISHED.load(Ordering::Acquire), TASKS);
+});
+
+test!(applications_run_concurrently, {
+    let apps: alloc::vec::Vec<_> = (0..8).map(|_| loader::spawn("test").unwrap()).collect();
+    for app in apps {
+        ass!(app.join().success());
+    }
+});
+
+test!(kernel_threads_return_results, {
+    let threads: alloc::vec::Vec<_> = (0..8u64)
+        .map(|i| {
+            kthread::spawn("test thread", move || {
+                sched::yield_now();
+                i * i
+            })
+        })
+        .collect();
+    for (i, thread) in threads.into_iter().enumerate() {
+        same!(thread.join(), Some(i as u64 * i as u64));
+    }
+
+    let exited = kthread::spawn("test thread", || -> u64 { kthread::exit() });
+    same!(exited.join(), None);
+});
+
+test!(sleeping_tasks_wake_up_after_their_deadline, {
+    let start = Instant::now();
+    sched::sleep(Duration::from_millis(20));
+    ass!(start.elapsed(), >=, Duration::from_millis(20));
+
+    // the last sleepers sleep longer than a turn of the timer wheel
+    let sleepers: alloc::vec::Vec<_> = (0..40u64)
+        .map(|i| {
+            kthread::spawn("test sleeper", move || {
+                let deadline = Instant::now() + Duration::from_millis(i * 2);
+                sched::sleep_until(deadline.as_us());
+                Instant::now() >= deadline
+            })
+        })
+        .collect();
+    for sleeper in sleepers {
+        same!(sleeper.join(), Some(true));
+    }
+});
+
+test!(wait_queues_wake_up_all_waiters, {
+    static QUEUE: sched::WaitQueue = sched::WaitQueue::new();
+    static READY: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+    let waiters: alloc::vec::Vec<_> = (0..8)
+        .map(|_| {
+            kthread::spawn("test waiter", || {
+                QUEUE.wait_until(|| READY.load(Ordering::Acquire));
+            })
+        })
+        .collect();
+    sched::sleep_ms(20);
+    ass!(waiters.iter().all(|waiter| !waiter.is_finished()));
+
+    READY.store(true, Ordering::Release);
+    QUEUE.wake_all();
+    for waiter in waiters {
+        same!(waiter.join(), Some(()));
+    }
+});
+
+test!(pinned_threads_run_on_their_core, {
+    let ap_count = crate::acpi::ACPI.lock().ap_count as usize;
+    let threads: alloc::vec::Vec<_> = (0..=ap_count)
+        .map(|core| {
+            let thread = kthread::spawn_on("test pinned", sched::CoreMask::single(core), || {
+                sched::yield_now();
+                crate::smp::cpu_index() as usize
+            });
+            (core, thread)
+        })
+        .collect();
+    for (core, thread) in threads {
+        same!(thread.join(), Some(core));
+    }
+
+    let moved = kthread::spawn_on("test migrate", sched::CoreMask::single(0), move || {
+        let before = crate::smp::cpu_index() as usize;
+        sched::set_affinity(sched::CoreMask::single(ap_count));
+        (before, crate::smp::cpu_index() as usize)
+    });
+    same!(moved.join(), Some((0, ap_count)));
+});
+
+test!(idle_cores_steal_ready_tasks, {
+    let ap_count = crate::acpi::ACPI.lock().ap_count;
+    let steals_before: u64 = sched::core_stats().iter().map(|stats| stats.steals).sum();
+
+    // all start on core 0, which keeps them ready by yielding, so idle cores take them over
+    let threads: alloc::vec::Vec<_> = (0..8)
+        .map(|_| {
+            kthread::spawn_on("test busy", sched::CoreMask::single(0), || {
+                sched::set_affinity(sched::CoreMask::all());
+                let end = Instant::now() + Duration::from_millis(50);
+                while Instant::now() < end {
+                    sched::yield_now();
+                }
+                crate::smp::cpu_index()
+            })
+        })
+        .collect();
+    let cores: alloc::vec::Vec<_> = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect();
+
+    if ap_count > 0 {
+        ass!(cores.iter().any(|&core| core != 0));
+        let steals: u64 = sched::core_stats().iter().map(|stats| stats.steals).sum();
+        ass!(steals, >, steals_before);
+    }
+});
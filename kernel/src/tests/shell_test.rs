@@ -0,0 +1,66 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::ass;
+#[cfg(feature = "testing")]
+use alloc::string::String;
+
+test!(shell_commands_answer, {
+    let execute = |line: &str| {
+        let mut out = String::new();
+        shell::execute(line, &mut out).unwrap();
+        out
+    };
+
+    ass!(execute("").is_empty());
+    let help = execute("help");
+    for command in ["mem", "ps", "run <app>", "loglevel", "windows", "poweroff"] {
+        ass!(help.contains(command));
+    }
+    ass!(execute("  mem ").contains("MB free of"));
+    ass!(execute("cores").contains("cores online"));
+    ass!(execute("top").contains("core  0:"));
+    ass!(!execute("locks").is_empty());
+    ass!(execute("cores all").starts_with("invalid core count"));
+    ass!(execute("run").contains("test"));
+    ass!(execute("run missing").contains("could not be started"));
+    ass!(execute("loglevel loud").starts_with("unknown level"));
+    ass!(execute("logfilter shell=").starts_with("invalid filters"));
+    ass!(execute("logfilter shell=trace").is_empty());
+    ass!(execute("logfilter").contains("shell=TRACE"));
+    ass!(execute("logfilter clear").is_empty());
+    ass!(execute("logfilter").is_empty());
+    ass!(execute("frobnicate now").starts_with("unknown command 'frobnicate now'"));
+});
+
+test!(remote_shell_session_over_loopback, {
+    let mut listener = net::TcpListener::bind(7073).unwrap();
+    let session = kthread::spawn("test session", move || {
+        shell::remote_session(&listener.accept());
+    });
+    let client = net::TcpStream::connect(net::IpEndpoint::new(
+        net::Ipv4Address::new(127, 0, 0, 1).into(),
+        7073,
+    ))
+    .unwrap();
+
+    log::info!("remote shell test marker");
+    client.send_all(b"help\r\n").unwrap();
+    let mut received = alloc::vec::Vec::new();
+    let mut buffer = [0; 512];
+    loop {
+        let text = String::from_utf8_lossy(&received);
+        if text.contains("remote shell test marker") && text.contains("poweroff") {
+            break;
+        }
+        let read = client.recv(&mut buffer);
+        ass!(read > 0, "session closed");
+        received.extend_from_slice(&buffer[..read]);
+    }
+
+    // the session ends with the connection
+    drop(client);
+    ass!(session.join().is_some());
+});
@@ -0,0 +1,166 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+test!(remote_calls_run_on_the_target_core, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    for target in 0..=ap_count {
+        let ran_on = AtomicU64::new(u64::MAX);
+        smp::call_on(target, || {
+            ran_on.store(smp::cpu_index(), Ordering::Relaxed);
+        })
+        .unwrap();
+        same!(ran_on.load(Ordering::Relaxed), target);
+    }
+    same!(
+        smp::call_on(ap_count + 1, || {}),
+        Err(smp::RemoteCallError::NotReceiving)
+    );
+});
+
+test!(remote_calls_to_all_cores_wait_for_every_core, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    let calls = AtomicU64::new(0);
+    let cores = AtomicU64::new(0);
+    smp::call_all(|| {
+        calls.fetch_add(1, Ordering::Relaxed);
+        cores.fetch_or(1 << smp::cpu_index(), Ordering::Relaxed);
+    });
+    same!(calls.load(Ordering::Relaxed), ap_count + 1);
+    // qemu runs with less than 64 cores
+    same!(cores.load(Ordering::Relaxed), (1 << (ap_count + 1)) - 1);
+
+    let others = AtomicU64::new(0);
+    smp::call_others(|| {
+        others.fetch_add(1, Ordering::Relaxed);
+    });
+    same!(others.load(Ordering::Relaxed), ap_count);
+});
+
+test!(cores_calling_each_other_do_not_deadlock, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    if ap_count == 0 {
+        log::warn!("Skipped, needs a second core");
+        return;
+    }
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    // every core calls all others at the same time
+    let threads: alloc::vec::Vec<_> = (0..=ap_count)
+        .map(|cpu_index| {
+            kthread::spawn_on(
+                "test caller",
+                sched::CoreMask::single(cpu_index as usize),
+                || {
+                    for _ in 0..100 {
+                        smp::call_others(|| {
+                            CALLS.fetch_add(1, Ordering::Relaxed);
+                        });
+                    }
+                },
+            )
+        })
+        .collect();
+    for thread in threads {
+        same!(thread.join(), Some(()));
+    }
+    same!(
+        CALLS.load(Ordering::Relaxed),
+        100 * (ap_count + 1) * ap_count
+    );
+});
+
+test!(parked_cores_run_no_tasks_until_they_are_online_again, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    if ap_count == 0 {
+        log::warn!("Skipped, needs a second core");
+        return;
+    }
+    same!(smp::set_online_cores(1), 1);
+    for core in 1..=ap_count {
+        ass!(smp::is_parked(core));
+    }
+    same!(sched::online_core_count(), 1);
+    let threads: alloc::vec::Vec<_> = (0..8)
+        .map(|_| {
+            kthread::spawn("test parked", || {
+                sched::sleep_ms(5);
+                smp::cpu_index()
+            })
+        })
+        .collect();
+    for thread in threads {
+        same!(thread.join(), Some(0));
+    }
+    // parked cores still answer remote calls
+    let calls = AtomicU64::new(0);
+    smp::call_all(|| {
+        calls.fetch_add(1, Ordering::Relaxed);
+    });
+    same!(calls.load(Ordering::Relaxed), ap_count + 1);
+
+    same!(smp::set_online_cores(usize::MAX), ap_count as usize + 1);
+    same!(sched::online_core_count(), ap_count as usize + 1);
+    let last = ap_count as usize;
+    let thread = kthread::spawn_on("test online", sched::CoreMask::single(last), smp::cpu_index);
+    same!(thread.join(), Some(ap_count));
+});
+
+test!(busy_and_idle_time_is_accounted_per_core, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    let before = smp::core_load(0);
+    // the windows end with the timer interrupts
+    sched::sleep_ms(600);
+    let stats = smp::load_stats();
+    same!(stats.len() as u64, ap_count + 1);
+    for core in &stats {
+        ass!(core.busy_permille, <=, 1000);
+        ass!(!core.parked);
+    }
+    let after = smp::core_load(0);
+    ass!(after.idle, >, before.idle);
+    ass!(after.busy + after.idle, >=, before.busy + before.idle + time::Duration::from_millis(500));
+});
+
+test!(apic_ids_are_decoded_into_the_topology, {
+    use smp::{ApicIdLayout, CpuTopology};
+
+    // 2 threads per core, 3 cores per package (rounded up to 4)
+    let layout = ApicIdLayout::from_counts(2, 3);
+    same!(
+        layout,
+        ApicIdLayout {
+            thread_bits: 1,
+            core_bits: 2,
+        }
+    );
+    same!(
+        layout.decode(0b1_10_1),
+        CpuTopology {
+            apic_id: 0b1_10_1,
+            package_id: 1,
+            core_id: 2,
+            thread_id: 1,
+        }
+    );
+    same!(ApicIdLayout::from_counts(1, 1).decode(7).package_id, 7);
+});
+
+test!(every_started_core_has_a_topology, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    same!(
+        smp::topology(smp::cpu_index()).map(|topology| topology.apic_id),
+        Some(apic::get_apic().id())
+    );
+    for cpu_index in 0..=ap_count {
+        ass!(smp::topology(cpu_index).is_some());
+    }
+    same!(smp::topology(ap_count + 1), None);
+    // qemu runs with a single thread per core
+    same!(smp::physical_core_count() as u64, ap_count + 1);
+    same!(smp::siblings(0).count(), 0);
+});
@@ -0,0 +1,24 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+
+test!(kernel_addresses_are_symbolized, {
+    #[inline(never)]
+    fn symbolized_function(value: u64) -> u64 {
+        core::hint::black_box(value).wrapping_mul(3)
+    }
+    same!(symbolized_function(2), 6);
+
+    let address = symbolized_function as usize as u64;
+    let symbol = symbols::resolve(address).unwrap();
+    ass!(symbol.name.ends_with("symbolized_function"));
+    same!(symbol.offset, 0);
+    same!(symbols::resolve(address + 1).unwrap().name, symbol.name);
+    same!(symbols::resolve(address + 1).unwrap().offset, 1);
+
+    same!(symbols::resolve(0), None);
+    same!(symbols::resolve(u64::MAX), None);
+});
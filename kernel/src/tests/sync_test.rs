@@ -0,0 +1,92 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::{sync::Arc, vec::Vec};
+#[cfg(feature = "testing")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+test!(named_barriers_are_reused_for_several_rounds, {
+    const TASKS: usize = 4;
+    const ROUNDS: u64 = 5;
+    let counter = Arc::new(AtomicU64::new(0));
+    let threads: Vec<_> = (0..TASKS)
+        .map(|_| {
+            let counter = counter.clone();
+            kthread::spawn("barrier test", move || {
+                let barrier = sync::barrier("test rounds", TASKS);
+                let mut leader = 0;
+                for round in 0..ROUNDS {
+                    counter.fetch_add(1, Ordering::AcqRel);
+                    if barrier.wait() {
+                        leader += 1;
+                    }
+                    // every task of the round arrived before any of them continued
+                    ass!(counter.load(Ordering::Acquire), >=, (round + 1) * TASKS as u64);
+                    barrier.wait();
+                }
+                leader
+            })
+        })
+        .collect();
+    let leaders: u64 = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .sum();
+    same!(leaders, ROUNDS);
+    same!(counter.load(Ordering::Acquire), ROUNDS * TASKS as u64);
+
+    ass!(Arc::ptr_eq(
+        &sync::barrier("test rounds", TASKS),
+        &sync::barrier("test rounds", TASKS)
+    ));
+    let mut found = false;
+    sync::barriers(|name, count, waiting| {
+        if name == "test rounds" {
+            found = true;
+            same!((count, waiting), (TASKS, 0));
+        }
+    });
+    ass!(found);
+    ass!(sync::remove("test rounds"));
+    ass!(!sync::remove("test rounds"));
+});
+
+test!(latches_open_after_the_last_count_down, {
+    const TASKS: usize = 6;
+    let latch = sync::latch("test latch", TASKS);
+    same!(latch.remaining(), TASKS);
+    let waiter = kthread::spawn("latch waiter", || {
+        sync::latch("test latch", TASKS).wait();
+    });
+    for _ in 0..TASKS {
+        ass!(!latch.is_open());
+        kthread::spawn("latch test", || {
+            sync::latch("test latch", TASKS).count_down()
+        })
+        .join()
+        .unwrap();
+    }
+    waiter.join().unwrap();
+    ass!(latch.is_open());
+    // stays open
+    latch.count_down();
+    same!(latch.remaining(), 0);
+    latch.wait();
+
+    sync::remove("test latch");
+    same!(sync::latch("test latch", 1).remaining(), 1);
+    sync::remove("test latch");
+});
+
+test!(
+    should_panic = "exists for another number of tasks",
+    barriers_are_shared_only_with_the_same_count,
+    {
+        sync::barrier("test count", 2);
+        sync::barrier("test count", 3);
+    }
+);
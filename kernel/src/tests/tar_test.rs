@@ -0,0 +1,100 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use alloc::{format, sync::Arc, vec, vec::Vec};
+#[cfg(feature = "testing")]
+use fs::{FileType, FsError, TarFs};
+#[cfg(feature = "testing")]
+use tar::{EntryType, BLOCK_SIZE};
+
+// (path, type, data), written like bootimage writes the ram disk
+#[cfg(feature = "testing")]
+fn archive(entries: &[(&str, u8, &[u8])]) -> &'static [u8] {
+    let mut archive = Vec::new();
+    for &(path, entry_type, data) in entries {
+        let mut header = [0u8; BLOCK_SIZE];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[156] = entry_type;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        header[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(BLOCK_SIZE), 0);
+    }
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+    archive.leak()
+}
+
+test!(tar_entries_are_read_in_place, {
+    let big = vec![0x5A; 1000];
+    let archive = archive(&[
+        ("cmdline", b'0', b"loglevel=info"),
+        ("apps/", b'5', b""),
+        ("apps/shell", b'0', &big),
+        ("link", b'2', b""),
+        ("cmdline", b'0', b"loglevel=debug"),
+    ]);
+    ass!(tar::is_archive(archive));
+    ass!(!tar::is_archive(&[0; BLOCK_SIZE]));
+
+    let entries: Vec<_> = tar::entries(archive).map(Result::unwrap).collect();
+    same!(entries.len(), 5);
+    same!(entries[1].entry_type, EntryType::Dir);
+    same!(entries[3].entry_type, EntryType::Other);
+    ass!(entries[2].is("/apps/shell"));
+
+    // the last file with a path wins
+    same!(tar::find(archive, "cmdline"), Some(&b"loglevel=debug"[..]));
+    same!(tar::find(archive, "./apps/shell"), Some(&big[..]));
+    same!(tar::find(archive, "apps"), None);
+    same!(tar::find(archive, "link"), None);
+});
+
+test!(tar_archives_can_be_mounted, {
+    let archive = archive(&[("top", b'0', b"hello"), ("apps/deep/inner", b'0', b"abc")]);
+    fs::mount("/test/tar", Arc::new(TarFs::new(archive).unwrap())).unwrap();
+
+    let entries = fs::read_dir("/test/tar").unwrap();
+    let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+    same!(names, ["apps", "top"]);
+    // created for the paths inside them
+    same!(entries[0].file_type, FileType::Dir);
+    same!(fs::read_dir("/test/tar/apps/deep").unwrap().len(), 1);
+
+    let mut buffer = [0; 16];
+    let mut inner = fs::open("/test/tar/apps/deep/inner").unwrap();
+    same!(inner.read(&mut buffer), Ok(3));
+    same!(&buffer[..3], b"abc");
+    same!(inner.write(b"x"), Err(FsError::ReadOnly));
+    same!(fs::create("/test/tar/new").err(), Some(FsError::ReadOnly));
+    fs::unmount("/test/tar").unwrap();
+});
+
+test!(tar_rejects_broken_headers, {
+    let archive = archive(&[("top", b'0', b"hello"), ("second", b'0', b"")]);
+    let mut broken = archive.to_vec();
+    broken[2 * BLOCK_SIZE] ^= 1;
+    let broken = broken.leak();
+    same!(
+        tar::entries(broken)
+            .map(|entry| entry.err())
+            .collect::<Vec<_>>(),
+        [None, Some(FsError::InvalidFormat)]
+    );
+    same!(TarFs::new(broken).err(), Some(FsError::InvalidFormat));
+
+    // the data of the last entry is cut off
+    same!(
+        TarFs::new(&archive[..BLOCK_SIZE + 2]).err(),
+        Some(FsError::InvalidFormat)
+    );
+});
@@ -0,0 +1,56 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use time::{Duration, Instant};
+
+test!(instants_are_monotonic_across_cores, {
+    let ap_count = acpi::ACPI.lock().ap_count;
+    let mut last = Instant::now();
+    // the instant taken on another core is compared with the ones before and after on this core
+    for cpu_index in 0..=ap_count {
+        let there = kthread::spawn_on(
+            "test clock",
+            sched::CoreMask::single(cpu_index as usize),
+            Instant::now,
+        )
+        .join()
+        .unwrap();
+        let here = Instant::now();
+        ass!(last, <=, there);
+        ass!(there, <=, here);
+        last = here;
+    }
+});
+
+test!(instant_arithmetic, {
+    let instant = Instant::from_us(1_500_000);
+    same!(
+        instant + Duration::from_millis(500),
+        Instant::from_us(2_000_000)
+    );
+    same!(instant - Duration::from_secs(1), Instant::from_us(500_000));
+    same!(instant - Duration::from_secs(2), Instant::from_us(0));
+    same!(
+        Instant::from_us(2_000_000) - instant,
+        Duration::from_millis(500)
+    );
+    // durations since later instants are empty
+    same!(instant - Instant::from_us(2_000_000), Duration::ZERO);
+    same!(instant.checked_add(Duration::from_secs(u64::MAX)), None);
+    same!(
+        instant.saturating_add(Duration::from_secs(u64::MAX)),
+        Instant::from_us(u64::MAX)
+    );
+});
+
+test!(uptime_advances_with_sleeps, {
+    let before = time::uptime();
+    let start = Instant::now();
+    sched::sleep(Duration::from_millis(10));
+    ass!(start.elapsed(), >=, Duration::from_millis(10));
+    ass!(time::uptime() - before, >=, Duration::from_millis(10));
+});
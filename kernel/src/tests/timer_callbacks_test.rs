@@ -0,0 +1,62 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "testing")]
+use time::{Duration, Instant};
+
+test!(one_shot_timer_callbacks_run_once, {
+    static RUNS: AtomicU64 = AtomicU64::new(0);
+    let start = Instant::now();
+    let timer = timer_callbacks::add_one_shot(Duration::from_millis(20), || {
+        RUNS.fetch_add(1, Ordering::AcqRel);
+    });
+
+    while RUNS.load(Ordering::Acquire) == 0 {
+        sched::yield_now();
+    }
+    ass!(start.elapsed(), >=, Duration::from_millis(20));
+    sched::sleep(Duration::from_millis(50));
+    same!(RUNS.load(Ordering::Acquire), 1);
+    // fired timers can not be cancelled
+    ass!(!timer_callbacks::cancel(timer));
+});
+
+test!(periodic_timer_callbacks_share_the_timer, {
+    static FAST: AtomicU64 = AtomicU64::new(0);
+    static SLOW: AtomicU64 = AtomicU64::new(0);
+    // the callbacks are counted on the core they were added on
+    let counted = kthread::spawn_on("test timers", sched::CoreMask::single(0), || {
+        let cpu_index = 0;
+        let active = timer_callbacks::active_count(cpu_index);
+
+        let fast = timer_callbacks::add_periodic(Duration::from_millis(10), || {
+            FAST.fetch_add(1, Ordering::Relaxed);
+        });
+        let slow = timer_callbacks::add_periodic(Duration::from_millis(40), || {
+            SLOW.fetch_add(1, Ordering::Relaxed);
+        });
+        same!(timer_callbacks::active_count(cpu_index), active + 2);
+
+        while SLOW.load(Ordering::Relaxed) < 3 {
+            sched::yield_now();
+        }
+        ass!(FAST.load(Ordering::Relaxed), >, SLOW.load(Ordering::Relaxed));
+
+        ass!(timer_callbacks::cancel(fast));
+        ass!(timer_callbacks::cancel(slow));
+        ass!(!timer_callbacks::cancel(slow));
+        same!(timer_callbacks::active_count(cpu_index), active);
+
+        // cancelled callbacks do not run anymore
+        let fast_runs = FAST.load(Ordering::Relaxed);
+        sched::sleep(Duration::from_millis(30));
+        same!(FAST.load(Ordering::Relaxed), fast_runs);
+        true
+    });
+    same!(counted.join(), Some(true));
+});
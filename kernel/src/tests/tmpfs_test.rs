@@ -0,0 +1,62 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::{ass, same};
+#[cfg(feature = "testing")]
+use fs::{FileType, FsError, SeekFrom};
+
+test!(tmp_files_can_be_written_and_removed, {
+    let mut file = fs::create("/tmp/tmpfs test").unwrap();
+    same!(file.write(b"hello"), Ok(5));
+    same!(
+        fs::create("/tmp/tmpfs test").err(),
+        Some(FsError::AlreadyExists)
+    );
+
+    // the gap reads as zeros
+    same!(file.seek(SeekFrom::Start(8)), Some(8));
+    same!(file.write(b"world"), Ok(5));
+    let mut other = fs::open("/tmp/tmpfs test").unwrap();
+    same!(other.size(), 13);
+    let mut buffer = [0xFF; 16];
+    same!(other.read(&mut buffer), Ok(13));
+    same!(&buffer[..13], b"hello\0\0\0world");
+
+    fs::remove("/tmp/tmpfs test").unwrap();
+    same!(fs::open("/tmp/tmpfs test").err(), Some(FsError::NotFound));
+    // open files keep their content
+    same!(other.seek(SeekFrom::Start(0)), Some(0));
+    same!(other.read(&mut buffer[..5]), Ok(5));
+    same!(&buffer[..5], b"hello");
+});
+
+test!(tmp_directories_can_be_nested, {
+    fs::create_dir("/tmp/tmpfs dir").unwrap();
+    fs::create_dir("/tmp/tmpfs dir/inner").unwrap();
+    fs::create("/tmp/tmpfs dir/inner/file").unwrap();
+    same!(
+        fs::create("/tmp/tmpfs dir/missing/file").err(),
+        Some(FsError::NotFound)
+    );
+
+    let entries = fs::read_dir("/tmp/tmpfs dir").unwrap();
+    same!(entries.len(), 1);
+    same!(entries[0].name, "inner");
+    same!(entries[0].file_type, FileType::Dir);
+    same!(
+        fs::metadata("/tmp/tmpfs dir/inner/file").unwrap().file_type,
+        FileType::File
+    );
+
+    same!(fs::remove("/tmp/tmpfs dir"), Err(FsError::NotEmpty));
+    fs::remove("/tmp/tmpfs dir/inner/file").unwrap();
+    fs::remove("/tmp/tmpfs dir/inner").unwrap();
+    fs::remove("/tmp/tmpfs dir").unwrap();
+    ass!(!fs::read_dir("/tmp")
+        .unwrap()
+        .iter()
+        .any(|entry| entry.name == "tmpfs dir"));
+    same!(fs::remove("/tmp"), Err(FsError::Busy));
+});
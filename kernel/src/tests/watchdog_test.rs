@@ -0,0 +1,28 @@
+use crate::test;
+
+#[cfg(feature = "testing")]
+use super::*;
+#[cfg(feature = "testing")]
+use crate::same;
+
+test!(hung_cores_are_reported_by_the_watchdog, {
+    use sched::CoreMask;
+
+    if acpi::ACPI.lock().ap_count == 0 {
+        log::warn!("Skipped, the watchdog needs a second core");
+        return;
+    }
+    let reports = watchdog::reports(1);
+    let hung = kthread::spawn_on("hung core", CoreMask::single(1), move || {
+        // spins with interrupts disabled like a deadlock on a spin lock, until the watchdog reports it
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let deadline = time::Instant::now()
+                + time::Duration::from_millis(4 * watchdog::hang_duration_ms());
+            while watchdog::reports(1) == reports && time::Instant::now() < deadline {
+                core::hint::spin_loop();
+            }
+        });
+        watchdog::reports(1)
+    });
+    same!(hung.join(), Some(reports + 1));
+});
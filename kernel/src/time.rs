@@ -0,0 +1,113 @@
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Sub},
+};
+
+pub use core::time::Duration;
+
+// Monotonic point in time with microsecond resolution, comparable between cores.
+// Counts from the reset of the time stamp counter (about the start of the machine), the hpet counter is used
+// instead if the tsc rate is not constant (see hpet::clock). Both are calibrated during apic init
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    us: u64,
+}
+
+static BOOT: spin::Once<Instant> = spin::Once::new();
+
+impl Instant {
+    pub fn now() -> Self {
+        if let Some(hpet) = crate::hpet::clock() {
+            return Self::from_us(hpet.us_from_ticks(hpet.counter()));
+        }
+        let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+        Self::from_us(
+            (u128::from(tsc) * 1_000_000 / u128::from(crate::apic::tsc_ticks_per_second())) as u64,
+        )
+    }
+
+    pub const fn from_us(us: u64) -> Self {
+        Self { us }
+    }
+
+    pub const fn as_us(self) -> u64 {
+        self.us
+    }
+
+    // zero if earlier is later than self
+    pub fn duration_since(self, earlier: Self) -> Duration {
+        Duration::from_micros(self.us.saturating_sub(earlier.us))
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+
+    pub fn checked_add(self, duration: Duration) -> Option<Self> {
+        let us = u64::try_from(duration.as_micros()).ok()?;
+        Some(Self::from_us(self.us.checked_add(us)?))
+    }
+
+    // clamps at the largest instant
+    pub fn saturating_add(self, duration: Duration) -> Self {
+        self.checked_add(duration)
+            .unwrap_or(Self::from_us(u64::MAX))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Self;
+
+    fn add(self, duration: Duration) -> Self {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Self;
+
+    fn sub(self, duration: Duration) -> Self {
+        let us = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        Self::from_us(self.us.saturating_sub(us))
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Self) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+impl fmt::Debug for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Instant({}.{:06}s)",
+            self.us / 1_000_000,
+            self.us % 1_000_000
+        )
+    }
+}
+
+// needs to be called once (only bsp) after the clock is calibrated (apic init)
+pub fn init() {
+    BOOT.call_once(Instant::now);
+}
+
+pub fn uptime() -> Duration {
+    try_uptime().expect("time not initialized")
+}
+
+// to be used by logging (which starts before the clock is calibrated)
+pub fn try_uptime() -> Option<Duration> {
+    BOOT.get().map(|boot| boot.elapsed())
+}
@@ -0,0 +1,137 @@
+use alloc::{boxed::Box, vec::Vec};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    per_core,
+    smp::cpu_index,
+    time::{Duration, Instant},
+};
+
+// Callbacks run by the apic timer interrupt of the core they were added on, the timer of every core ticks with
+// the scheduler time slice (see sched::init), so deadlines are met with the first tick after them.
+// Callbacks run in the interrupt handler: they must not allocate (the heap lock is not interrupt safe), block
+// or add and cancel timers. Fired one shot callbacks are dropped by the next add or cancel on their core
+type Callback = Box<dyn FnMut() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    cpu_index: u64,
+    id: u64,
+}
+
+struct Timer {
+    id: u64,
+    deadline: Instant,
+    // None for one shot timers
+    interval: Option<Duration>,
+    fired: bool,
+    callback: Callback,
+}
+
+struct CoreTimers {
+    timers: Vec<Timer>,
+    next_id: u64,
+}
+
+impl CoreTimers {
+    const fn new() -> Self {
+        Self {
+            timers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    fn add(&mut self, deadline: Instant, interval: Option<Duration>, callback: Callback) -> u64 {
+        self.timers.retain(|timer| !timer.fired);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id,
+            deadline,
+            interval,
+            fired: false,
+            callback,
+        });
+        id
+    }
+
+    // missed deadlines of periodic timers are skipped instead of running the callback back to back
+    fn run_due(&mut self, now: Instant) {
+        for timer in self.timers.iter_mut().filter(|timer| !timer.fired) {
+            if timer.deadline > now {
+                continue;
+            }
+            (timer.callback)();
+            match timer.interval {
+                Some(interval) => {
+                    timer.deadline += interval;
+                    if timer.deadline <= now {
+                        timer.deadline = now + interval;
+                    }
+                }
+                None => timer.fired = true,
+            }
+        }
+    }
+}
+
+// Indexed by cpu index. Locked with interrupts disabled
+per_core! {
+    static CORE_TIMERS: Mutex<CoreTimers> = Mutex::new(CoreTimers::new());
+}
+
+fn add(delay: Duration, interval: Option<Duration>, callback: Callback) -> TimerId {
+    interrupts::without_interrupts(|| {
+        let cpu_index = cpu_index();
+        let deadline = Instant::now() + delay;
+        let id = CORE_TIMERS[cpu_index as usize]
+            .lock()
+            .add(deadline, interval, callback);
+        TimerId { cpu_index, id }
+    })
+}
+
+// the callback runs once on the current core after the delay
+pub fn add_one_shot(delay: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    add(delay, None, Box::new(callback))
+}
+
+// the callback runs on the current core every interval (starting one interval from now)
+pub fn add_periodic(interval: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    assert!(!interval.is_zero(), "periodic timer without interval");
+    add(interval, Some(interval), Box::new(callback))
+}
+
+// Can be called from any core, returns false if the timer already fired (one shot) or was cancelled
+pub fn cancel(timer: TimerId) -> bool {
+    // dropped after the lock is released, the timer interrupt of the other core may wait for the lock
+    // while the code it interrupted holds the heap lock
+    let removed = interrupts::without_interrupts(|| {
+        let mut core_timers = CORE_TIMERS[timer.cpu_index as usize].lock();
+        let index = core_timers
+            .timers
+            .iter()
+            .position(|entry| entry.id == timer.id)?;
+        Some(core_timers.timers.swap_remove(index))
+    });
+    removed.is_some_and(|entry| !entry.fired)
+}
+
+pub fn active_count(cpu_index: u64) -> usize {
+    interrupts::without_interrupts(|| {
+        CORE_TIMERS[cpu_index as usize]
+            .lock()
+            .timers
+            .iter()
+            .filter(|timer| !timer.fired)
+            .count()
+    })
+}
+
+// called by the timer interrupt
+pub fn run_due(cpu_index: u64) {
+    CORE_TIMERS[cpu_index as usize]
+        .lock()
+        .run_due(Instant::now());
+}
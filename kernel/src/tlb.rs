@@ -0,0 +1,147 @@
+use core::ops::Range;
+
+use x86_64::{
+    instructions::tlb,
+    structures::paging::{PageSize, PhysFrame},
+    PhysAddr, VirtAddr,
+};
+
+use crate::{
+    lock_debug::{TrackedGuard, TrackedMutex},
+    memory::{phys_to_virt, Memory, MEMORY},
+    smp,
+};
+
+// ranges with more pages are flushed completely
+const MAX_PAGES_TO_INVALIDATE: u64 = 64;
+
+fn flush_range(range: Range<VirtAddr>) {
+    let page_count = (range.end.as_u64() - range.start.as_u64()).div_ceil(4096);
+    if page_count > MAX_PAGES_TO_INVALIDATE {
+        tlb::flush_all();
+        return;
+    }
+    let mut addr = range.start.align_down(4096u64);
+    while addr < range.end {
+        tlb::flush(addr);
+        addr += 4096u64;
+    }
+}
+
+// Invalidates the range on all other (started) cores and returns after every one of them flushed it.
+// The local tlb has to be flushed by the caller. Must not be called while holding a lock another core may
+// wait for with interrupts disabled (it can't acknowledge then), see PendingShootdown
+pub fn shootdown(range: Range<VirtAddr>) {
+    smp::call_others(|| flush_range(range.start..range.end));
+}
+
+// Kernel pages whose mapping was changed or removed (flushed locally), the other cores may still use the old
+// one. Returned by the Memory methods instead of shooting down right away, so the caller can release MEMORY
+// (and its own locks) first and flush the whole batch at once. The frames of unmapped ram are only freed
+// after the shootdown, before it another core could still write them through its old mapping
+#[must_use = "the other cores keep the old mapping until it is flushed (and the unmapped frames stay allocated)"]
+pub struct PendingShootdown {
+    range: Option<Range<VirtAddr>>,
+    // the first and the last of the frames to free, they are linked through a FreedFrame at their start
+    frames: Option<(PhysAddr, PhysAddr)>,
+}
+
+#[derive(Clone, Copy)]
+struct FreedFrame {
+    size: u64,
+    next: Option<PhysAddr>,
+}
+
+fn freed_frame(frame: PhysAddr) -> *mut FreedFrame {
+    phys_to_virt(frame).as_mut_ptr()
+}
+
+impl PendingShootdown {
+    pub const fn none() -> Self {
+        Self {
+            range: None,
+            frames: None,
+        }
+    }
+
+    // the pending range grows to cover it (ranges of more than MAX_PAGES_TO_INVALIDATE pages flush everything)
+    pub fn add(&mut self, range: Range<VirtAddr>) {
+        self.range = Some(match self.range.take() {
+            Some(pending) => pending.start.min(range.start)..pending.end.max(range.end),
+            None => range,
+        });
+    }
+
+    // Freed by flush. Nothing else may use the frame anymore, the list is written into it
+    pub unsafe fn add_frame<S: PageSize>(&mut self, frame: PhysFrame<S>) {
+        let start = frame.start_address();
+        unsafe {
+            freed_frame(start).write(FreedFrame {
+                size: S::SIZE,
+                next: None,
+            });
+        }
+        self.append((start, start));
+    }
+
+    fn append(&mut self, (first, last): (PhysAddr, PhysAddr)) {
+        self.frames = Some(match self.frames {
+            Some((head, tail)) => {
+                unsafe { (*freed_frame(tail)).next = Some(first) };
+                (head, last)
+            }
+            None => (first, last),
+        });
+    }
+
+    pub fn merge(&mut self, mut other: Self) {
+        if let Some(range) = other.range.take() {
+            self.add(range);
+        }
+        if let Some(frames) = other.frames.take() {
+            self.append(frames);
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.range.is_none()
+    }
+
+    // after the locks are released (it locks MEMORY to free the frames)
+    pub fn flush(mut self) {
+        if let Some(range) = self.range.take() {
+            shootdown(range);
+        }
+        // every core acknowledged, the old mappings are gone
+        if self.frames.is_some() {
+            unsafe { self.free_frames(&mut MEMORY.lock()) };
+        }
+    }
+
+    // Frees the frames without shooting down, for mappings that no other core used
+    pub unsafe fn discard(mut self, memory: &mut Memory) {
+        unsafe { self.free_frames(memory) };
+    }
+
+    unsafe fn free_frames(&mut self, memory: &mut Memory) {
+        let mut next = self.frames.take().map(|(first, _)| first);
+        while let Some(frame) = next {
+            let freed = unsafe { freed_frame(frame).read() };
+            next = freed.next;
+            unsafe { memory.deallocate_unmapped_frame(frame, freed.size) };
+        }
+    }
+}
+
+// Locks the mutex, but keeps acknowledging shootdowns (remote calls) while waiting.
+// For locks taken with interrupts disabled, in case a shootdown is initiated while holding them anyway
+// (the Memory methods don't, they return a PendingShootdown)
+pub fn lock_servicing_shootdowns<T>(mutex: &TrackedMutex<T>) -> TrackedGuard<T> {
+    loop {
+        if let Some(guard) = mutex.try_lock() {
+            return guard;
+        }
+        smp::handle_remote_calls();
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,68 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use x86_64::registers::{
+    control::{Cr4, Cr4Flags},
+    rflags::{self, RFlags},
+};
+
+use crate::cpuid::Features;
+
+// SMAP: the kernel faults on accesses to user pages outside of user_access scopes
+// SMEP: the kernel faults on executing user pages (only possible once applications run in ring 3)
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// needs to be called by every core with its features (cr4 is per core)
+pub fn init(features: &Features) {
+    let mut flags = Cr4Flags::empty();
+    if features.smap {
+        flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+    if features.smep && crate::loader::APPLICATIONS_RUN_IN_RING_3 {
+        flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    unsafe { Cr4::update(|cr4| cr4.insert(flags)) };
+
+    let smap = flags.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION);
+    SMAP_ENABLED.store(smap, Ordering::Relaxed);
+    log::debug!(
+        "SMAP: {smap}, SMEP: {}",
+        flags.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION)
+    );
+}
+
+// Allows the kernel to access user pages inside f (stac/clac), nested scopes keep the outer state.
+// The AC flag also marks the scope for the page fault handler (a fault on a user address inside it is a fault
+// of the application), so it is set without SMAP as well (it only checks alignment in ring 3)
+pub fn user_access<R>(f: impl FnOnce() -> R) -> R {
+    let already_allowed = rflags::read().contains(RFlags::ALIGNMENT_CHECK);
+    if !already_allowed {
+        set_allowed(true);
+    }
+    let result = f();
+    if !already_allowed {
+        set_allowed(false);
+    }
+    result
+}
+
+fn set_allowed(allowed: bool) {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        if allowed {
+            unsafe { asm!("stac", options(nostack)) };
+        } else {
+            unsafe { asm!("clac", options(nostack)) };
+        }
+    } else {
+        let mut flags = rflags::read();
+        flags.set(RFlags::ALIGNMENT_CHECK, allowed);
+        unsafe { rflags::write(flags) };
+    }
+}
+
+// whether the interrupted code was inside a user_access scope
+pub fn interrupted_in_scope(cpu_flags: u64) -> bool {
+    RFlags::from_bits_truncate(cpu_flags).contains(RFlags::ALIGNMENT_CHECK)
+}
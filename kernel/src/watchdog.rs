@@ -0,0 +1,128 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::{
+    acpi::ACPI,
+    apic::{get_apic, ipi},
+    per_core, sched,
+    smp::try_get_cld,
+};
+
+// NMI watchdog for hung cores: the timer interrupt of the bsp sends an nmi to all other cores every
+// CHECK_INTERVAL_US and each core compares its heartbeat (the timer interrupts it handled) with the previous check.
+// A core spinning with interrupts disabled (e.g. a deadlock on a spin lock) has none and reports itself
+// from its nmi handler (through the logger, which deadlocks if the hung core holds it)
+const CHECK_INTERVAL_US: u64 = 1_000_000;
+// timer interrupts of single cores may be delayed for a while (interrupts disabled)
+pub const MISSED_CHECKS: u64 = 3;
+
+struct CoreWatch {
+    heartbeat: AtomicU64,
+    // heartbeat at the previous check
+    checked_heartbeat: AtomicU64,
+    missed: AtomicU64,
+    // set before the nmi is sent, other nmis are not from the watchdog
+    check_pending: AtomicBool,
+    reports: AtomicU64,
+    // parked cores have no timer interrupts (see smp::set_online_cores)
+    parked: AtomicBool,
+}
+
+impl CoreWatch {
+    const fn new() -> Self {
+        Self {
+            heartbeat: AtomicU64::new(0),
+            checked_heartbeat: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+            check_pending: AtomicBool::new(false),
+            reports: AtomicU64::new(0),
+            parked: AtomicBool::new(false),
+        }
+    }
+}
+
+per_core! {
+    static CORES: CoreWatch = CoreWatch::new();
+}
+// 0 until start
+static CORE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static NEXT_CHECK_US: AtomicU64 = AtomicU64::new(0);
+
+// needs to be called by the bsp once the timers of all cores are running
+pub fn start() {
+    let core_count = ACPI.lock().ap_count as usize + 1;
+    NEXT_CHECK_US.store(sched::now_us() + CHECK_INTERVAL_US, Ordering::Relaxed);
+    CORE_COUNT.store(core_count, Ordering::Release);
+    log::info!("Watchdog started for {} cores", core_count - 1);
+}
+
+// called by the timer interrupt of every core, the bsp sends the checks
+pub fn heartbeat(cpu_index: u64) {
+    CORES[cpu_index as usize]
+        .heartbeat
+        .fetch_add(1, Ordering::Relaxed);
+    let core_count = CORE_COUNT.load(Ordering::Acquire);
+    if cpu_index != 0 || core_count < 2 {
+        return;
+    }
+    let now = sched::now_us();
+    if now < NEXT_CHECK_US.load(Ordering::Relaxed) {
+        return;
+    }
+    NEXT_CHECK_US.store(now + CHECK_INTERVAL_US, Ordering::Relaxed);
+    // locks held for long while others wait for them (with the lock-debug feature)
+    crate::lock_debug::check_deadlocks();
+    for watch in CORES.iter().take(core_count).skip(1) {
+        watch.check_pending.store(true, Ordering::Release);
+    }
+    get_apic().write_interrupt_command(ipi::create_nmi_broadcast_cmd());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nmi {
+    // not sent by the watchdog (hardware error)
+    Unexpected,
+    Alive,
+    // reported once per hang
+    Hung,
+}
+
+// called by the nmi handler
+pub fn handle_nmi() -> Nmi {
+    let Some(cld) = try_get_cld() else {
+        return Nmi::Unexpected;
+    };
+    let watch = &CORES[cld.cpu_index as usize];
+    if !watch.check_pending.swap(false, Ordering::AcqRel) {
+        return Nmi::Unexpected;
+    }
+    if watch.parked.load(Ordering::Acquire) {
+        return Nmi::Alive;
+    }
+    let heartbeat = watch.heartbeat.load(Ordering::Relaxed);
+    if watch.checked_heartbeat.swap(heartbeat, Ordering::Relaxed) != heartbeat {
+        watch.missed.store(0, Ordering::Relaxed);
+        return Nmi::Alive;
+    }
+    if watch.missed.fetch_add(1, Ordering::Relaxed) + 1 == MISSED_CHECKS {
+        watch.reports.fetch_add(1, Ordering::Relaxed);
+        Nmi::Hung
+    } else {
+        Nmi::Alive
+    }
+}
+
+// called by parked cores, they are not checked until they run again
+pub fn set_parked(cpu_index: u64, parked: bool) {
+    let watch = &CORES[cpu_index as usize];
+    watch.missed.store(0, Ordering::Relaxed);
+    watch.parked.store(parked, Ordering::Release);
+}
+
+// number of hangs reported by the core
+pub fn reports(cpu_index: u64) -> u64 {
+    CORES[cpu_index as usize].reports.load(Ordering::Relaxed)
+}
+
+pub const fn hang_duration_ms() -> u64 {
+    MISSED_CHECKS * CHECK_INTERVAL_US / 1000
+}
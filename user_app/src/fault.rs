@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+mod os_functions;
+
+use core::arch::asm;
+
+extern crate alloc;
+
+// handle set up by the kernel
+const INPUT: u64 = 0;
+
+entry_point!(main);
+
+// Misbehaves as selected by the first byte of the input, the kernel kills the application
+fn main() -> u64 {
+    let mut selection = [0u8];
+    os_functions::read(INPUT, &mut selection).unwrap();
+    match selection[0] {
+        b'p' => unsafe { core::ptr::write_volatile(8 as *mut u64, 1) },
+        // privileged instruction
+        b'g' => unsafe { asm!("hlt") },
+        b'u' => unsafe { asm!("ud2") },
+        // the kernel faults copying the next input byte into the unmapped buffer
+        b'k' => {
+            let buffer = unsafe { core::slice::from_raw_parts_mut(8 as *mut u8, 1) };
+            let _ = os_functions::read(INPUT, buffer);
+        }
+        // never ends (see the time limit of the kernel)
+        b'l' => loop {
+            core::hint::spin_loop();
+        },
+        _ => return 1,
+    }
+    0
+}
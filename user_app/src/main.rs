@@ -11,5 +11,67 @@ entry_point!(main);
 
 fn main() -> u64 {
     println!("Fmt {}", HELLO);
-    666
+
+    match check_logo() {
+        Ok(size) => println!("Loaded the logo ({size} bytes)"),
+        Err(error) => {
+            println!("Loading the logo failed: {error:?}");
+            return 1;
+        }
+    }
+    match check_tmp_file() {
+        Ok(()) => 0,
+        Err(error) => {
+            println!("Writing a file to /tmp failed: {error:?}");
+            1
+        }
+    }
+}
+
+// reads the jpeg from the ram disk and checks its start and end markers
+fn check_logo() -> Result<usize, os_functions::Error> {
+    let logo = os_functions::open("/boot/logo")?;
+    let mut image = alloc::vec::Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = os_functions::read(logo, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        image.extend_from_slice(&buffer[..read]);
+    }
+
+    let mut end_marker = [0u8; 2];
+    os_functions::seek(logo, os_functions::SeekFrom::End(-2))?;
+    os_functions::read(logo, &mut end_marker)?;
+    let write = os_functions::write(logo, &end_marker);
+    os_functions::close(logo)?;
+
+    // the ram disk is read only
+    if !image.starts_with(&[0xFF, 0xD8])
+        || end_marker != [0xFF, 0xD9]
+        || write != Err(os_functions::Error::ReadOnly)
+    {
+        return Err(os_functions::Error::InvalidArgument);
+    }
+    Ok(image.len())
+}
+
+// writes a file to the tmpfs, reads it back and removes it
+fn check_tmp_file() -> Result<(), os_functions::Error> {
+    const PATH: &str = "/tmp/main.txt";
+    let file = os_functions::create(PATH)?;
+    os_functions::write_all(file, HELLO.as_bytes())?;
+    os_functions::seek(file, os_functions::SeekFrom::Start(0))?;
+    let mut buffer = [0u8; 64];
+    let read = os_functions::read(file, &mut buffer)?;
+    os_functions::close(file)?;
+    os_functions::remove(PATH)?;
+
+    if &buffer[..read] != HELLO.as_bytes()
+        || os_functions::open(PATH) != Err(os_functions::Error::NotFound)
+    {
+        return Err(os_functions::Error::InvalidArgument);
+    }
+    Ok(())
 }
@@ -1,7 +1,7 @@
-use core::alloc::GlobalAlloc;
+use core::{alloc::GlobalAlloc, arch::asm};
 
 use alloc::string::String;
-use spin::{Mutex, Once};
+use spin::Mutex;
 
 extern crate alloc;
 
@@ -10,7 +10,8 @@ pub use crate::{print, println};
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     println!("User PANIC: {}", info);
-    abort(42);
+    unsafe { syscall(SYS_PANIC, 42, 0, 0) };
+    unreachable!()
 }
 
 #[macro_export]
@@ -60,14 +61,184 @@ static OUT_CONTIGUOUS: Mutex<OutContiguous> = Mutex::new(OutContiguous {
     buffer: String::new(),
 });
 
+// syscall numbers, keep in sync with kernel/src/syscall.rs
+const SYS_EXIT: u64 = 0;
+const SYS_PRINT: u64 = 1;
+const SYS_ABORT: u64 = 2;
+const SYS_ALLOC: u64 = 3;
+const SYS_DEALLOC: u64 = 4;
+const SYS_PANIC: u64 = 5;
+const SYS_YIELD: u64 = 6;
+const SYS_SLEEP: u64 = 7;
+const SYS_READ: u64 = 8;
+const SYS_WRITE: u64 = 9;
+const SYS_CLOSE: u64 = 10;
+const SYS_OPEN: u64 = 11;
+const SYS_SEEK: u64 = 12;
+const SYS_RANDOM: u64 = 13;
+const SYS_REMOVE: u64 = 14;
+
+const ERROR_BAD_HANDLE: u64 = u64::MAX;
+const ERROR_CLOSED: u64 = u64::MAX - 1;
+const ERROR_NOT_FOUND: u64 = u64::MAX - 2;
+const ERROR_INVALID_ARGUMENT: u64 = u64::MAX - 3;
+const ERROR_READ_ONLY: u64 = u64::MAX - 4;
+
+const OPEN_CREATE: u64 = 1;
+
+const SEEK_START: u64 = 0;
+const SEEK_CURRENT: u64 = 1;
+const SEEK_END: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // the handle does not exist or does not support the operation
+    BadHandle,
+    // the other end of the pipe is closed
+    Closed,
+    // there is no file with this path
+    NotFound,
+    InvalidArgument,
+    // the file system can not be written
+    ReadOnly,
+}
+
+fn result(ret: u64) -> Result<u64, Error> {
+    match ret {
+        ERROR_BAD_HANDLE => Err(Error::BadHandle),
+        ERROR_CLOSED => Err(Error::Closed),
+        ERROR_NOT_FOUND => Err(Error::NotFound),
+        ERROR_INVALID_ARGUMENT => Err(Error::InvalidArgument),
+        ERROR_READ_ONLY => Err(Error::ReadOnly),
+        ret => Ok(ret),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+// number in rax, arguments in rdi, rsi, rdx, result in rax (the syscall instruction clobbers rcx and r11)
+#[inline(always)]
+unsafe fn syscall(number: u64, arg0: u64, arg1: u64, arg2: u64) -> u64 {
+    let ret;
+    asm!(
+        "syscall",
+        inlateout("rax") number => ret,
+        in("rdi") arg0,
+        in("rsi") arg1,
+        in("rdx") arg2,
+        out("rcx") _,
+        out("r11") _,
+        options(nostack),
+    );
+    ret
+}
+
 #[inline(always)]
 pub fn _print(string: &str) {
-    unsafe { (_FP.get().unwrap_unchecked().print)(string.as_ptr(), string.len() as u64) };
+    unsafe { syscall(SYS_PRINT, string.as_ptr() as u64, string.len() as u64, 0) };
 }
 
 #[inline]
 pub fn abort(exit_code: u64) -> ! {
-    unsafe { (_FP.get().unwrap_unchecked().abort)(exit_code) };
+    unsafe { syscall(SYS_ABORT, exit_code, 0, 0) };
+    unreachable!()
+}
+
+// lets other tasks run (the application is also preempted regularly)
+#[inline]
+pub fn yield_now() {
+    unsafe { syscall(SYS_YIELD, 0, 0, 0) };
+}
+
+#[inline]
+pub fn sleep(ms: u64) {
+    unsafe { syscall(SYS_SLEEP, ms, 0, 0) };
+}
+
+// fills buf with random bytes of the kernel generator
+pub fn fill_random(mut buf: &mut [u8]) {
+    while !buf.is_empty() {
+        let filled = unsafe { syscall(SYS_RANDOM, buf.as_mut_ptr() as u64, buf.len() as u64, 0) };
+        buf = &mut buf[filled as usize..];
+    }
+}
+
+// Handles are set up by the kernel when it starts the application (numbered from 0) or opened
+
+// opens a file by its absolute path (the ram disk is mounted at /boot, /tmp is writable)
+pub fn open(path: &str) -> Result<u64, Error> {
+    result(unsafe { syscall(SYS_OPEN, path.as_ptr() as u64, path.len() as u64, 0) })
+}
+
+// opens the file and creates it (empty) if it does not exist
+pub fn create(path: &str) -> Result<u64, Error> {
+    result(unsafe {
+        syscall(
+            SYS_OPEN,
+            path.as_ptr() as u64,
+            path.len() as u64,
+            OPEN_CREATE,
+        )
+    })
+}
+
+// removes a file or an empty directory
+pub fn remove(path: &str) -> Result<(), Error> {
+    result(unsafe { syscall(SYS_REMOVE, path.as_ptr() as u64, path.len() as u64, 0) }).map(|_| ())
+}
+
+// returns the new position in the file
+pub fn seek(handle: u64, from: SeekFrom) -> Result<u64, Error> {
+    let (offset, whence) = match from {
+        SeekFrom::Start(offset) => (offset, SEEK_START),
+        SeekFrom::Current(offset) => (offset as u64, SEEK_CURRENT),
+        SeekFrom::End(offset) => (offset as u64, SEEK_END),
+    };
+    result(unsafe { syscall(SYS_SEEK, handle, offset, whence) })
+}
+
+// blocks until data is available (pipes), returns 0 at the end of the stream or file
+pub fn read(handle: u64, buf: &mut [u8]) -> Result<usize, Error> {
+    let ret = unsafe { syscall(SYS_READ, handle, buf.as_mut_ptr() as u64, buf.len() as u64) };
+    result(ret).map(|read| read as usize)
+}
+
+// blocks until some of the data is written (pipes), returns how much
+pub fn write(handle: u64, data: &[u8]) -> Result<usize, Error> {
+    let ret = unsafe { syscall(SYS_WRITE, handle, data.as_ptr() as u64, data.len() as u64) };
+    result(ret).map(|written| written as usize)
+}
+
+pub fn write_all(handle: u64, mut data: &[u8]) -> Result<(), Error> {
+    while !data.is_empty() {
+        let written = write(handle, data)?;
+        data = &data[written..];
+    }
+    Ok(())
+}
+
+pub fn close(handle: u64) -> Result<(), Error> {
+    result(unsafe { syscall(SYS_CLOSE, handle, 0, 0) }).map(|_| ())
+}
+
+// The kernel points fs to the thread control block, its first word is the address of the tcb itself.
+// #[thread_local] statics are stored right below it
+#[inline]
+pub fn thread_pointer() -> *const u8 {
+    let tcb: u64;
+    unsafe { asm!("mov {}, fs:0", out(reg) tcb, options(nostack, readonly, preserves_flags)) };
+    tcb as *const u8
+}
+
+#[doc(hidden)]
+pub fn _exit(exit_code: u64) -> ! {
+    unsafe { syscall(SYS_EXIT, exit_code, 0, 0) };
+    unreachable!()
 }
 
 #[global_allocator]
@@ -77,33 +248,27 @@ struct Allocator {}
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        (_FP.get().unwrap_unchecked().alloc)(layout.size() as u64, layout.align() as u64)
+        syscall(SYS_ALLOC, layout.size() as u64, layout.align() as u64, 0) as *mut u8
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        (_FP.get().unwrap_unchecked().dealloc)(ptr, layout.size() as u64, layout.align() as u64)
+        syscall(
+            SYS_DEALLOC,
+            ptr as u64,
+            layout.size() as u64,
+            layout.align() as u64,
+        );
     }
 }
 
-#[repr(C)]
-pub struct FunctionPointers {
-    print: extern "C" fn(*const u8, u64),
-    abort: extern "C" fn(u64) -> !,
-    alloc: extern "C" fn(u64, u64) -> *mut u8,
-    dealloc: extern "C" fn(*mut u8, u64, u64),
-}
-
-pub static _FP: Once<&'static FunctionPointers> = Once::new();
-
 #[macro_export]
 macro_rules! entry_point {
     ($path:path) => {
         #[doc(hidden)]
         #[export_name = "_start"]
-        pub unsafe extern "C" fn __impl_start(fns: *const os_functions::FunctionPointers) -> u64 {
-            $crate::os_functions::_FP.call_once(|| unsafe { &*fns });
+        pub extern "C" fn __impl_start() -> ! {
             let f: fn() -> u64 = $path;
-            f()
+            $crate::os_functions::_exit(f())
         }
     };
 }
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+mod os_functions;
+
+extern crate alloc;
+
+// handles set up by the kernel
+const INPUT: u64 = 0;
+const OUTPUT: u64 = 1;
+
+entry_point!(main);
+
+// Copies the input to the output in upper case until the input ends, returns the number of copied bytes
+fn main() -> u64 {
+    let mut buffer = [0u8; 64];
+    let mut copied = 0;
+    loop {
+        let read = os_functions::read(INPUT, &mut buffer).unwrap();
+        if read == 0 {
+            break;
+        }
+        buffer[..read].make_ascii_uppercase();
+        os_functions::write_all(OUTPUT, &buffer[..read]).unwrap();
+        copied += read as u64;
+    }
+    // the reader of the output sees the end of the stream
+    os_functions::close(OUTPUT).unwrap();
+    copied
+}
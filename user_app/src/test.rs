@@ -1,9 +1,10 @@
 #![no_std]
 #![no_main]
+#![feature(thread_local)]
 
 mod os_functions;
 
-use core::{panic, sync::atomic::AtomicU64};
+use core::{cell::Cell, panic, sync::atomic::AtomicU64};
 
 extern crate alloc;
 
@@ -11,6 +12,10 @@ static HELLO: &str = "Hello from Steelmind OS Program!\n";
 
 static mut COUNTER: AtomicU64 = AtomicU64::new(0);
 
+// initialized from the tls template, so the start value checks the copy of the loader
+#[thread_local]
+static CALLS: Cell<u64> = Cell::new(100);
+
 entry_point!(main);
 
 fn main() -> u64 {
@@ -20,6 +25,13 @@ fn main() -> u64 {
         let ret = COUNTER.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
         if ret == 0 {
             rec(10);
+            assert!(!os_functions::thread_pointer().is_null());
+            assert_eq!(CALLS.get(), 111);
+            assert_eq!(harmonic(100, true), harmonic(100, false));
+            let (mut first, mut second) = ([0u8; 64], [0u8; 64]);
+            os_functions::fill_random(&mut first);
+            os_functions::fill_random(&mut second);
+            assert_ne!(first, second);
         } else {
             panic!("PANIC");
         }
@@ -29,10 +41,12 @@ fn main() -> u64 {
 
 #[inline(never)]
 fn rec(count: u64) {
+    CALLS.set(CALLS.get() + 1);
     println!("Count: {}", count);
     if count == 0 {
         let mut prime_iter = (2..u64::MAX)
             .filter(|n| !(2..*n).any(|i| n % i == 0))
+            .inspect(|_| os_functions::yield_now())
             .enumerate();
         let v: alloc::vec::Vec<_> = prime_iter.by_ref().take(10).collect();
         print_vec(v);
@@ -41,6 +55,20 @@ fn rec(count: u64) {
     rec(count - 1);
 }
 
+// The partial sum stays in an sse register across the yields, other applications running in between
+// must not change it (the kernel saves the fpu state of switched out applications)
+#[inline(never)]
+fn harmonic(terms: u64, yielding: bool) -> f64 {
+    let mut sum = 0.0;
+    for i in 1..=terms {
+        sum += 1.0 / core::hint::black_box(i as f64);
+        if yielding {
+            os_functions::yield_now();
+        }
+    }
+    sum
+}
+
 #[inline(never)]
 fn print_vec<T: core::fmt::Debug>(v: alloc::vec::Vec<T>) {
     for e in v {